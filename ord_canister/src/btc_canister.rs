@@ -33,11 +33,20 @@ pub struct GetBlockHeadersResponse {
   pub block_headers: Vec<Vec<u8>>,
 }
 
+// signet has no bitcoin canister of its own, a regtest one is fed by a signet node
+fn btc_network() -> BitcoinNetwork {
+  match crate::chain() {
+    Some(crate::Chain::Mainnet) => BitcoinNetwork::Mainnet,
+    Some(crate::Chain::Testnet | crate::Chain::Testnet4) => BitcoinNetwork::Testnet,
+    Some(crate::Chain::Signet | crate::Chain::Regtest) | None => BitcoinNetwork::Regtest,
+  }
+}
+
 pub async fn get_block_hash(height: u32) -> crate::Result<BlockHash> {
   let req = GetBlockHeadersRequest {
     start_height: height,
     end_height: None,
-    network: BitcoinNetwork::Regtest,
+    network: btc_network(),
   };
   let res: (GetBlockHeadersResponse,) = ic_cdk::call(*BTC, "bitcoin_get_block_headers", (req,))
     .await
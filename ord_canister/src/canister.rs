@@ -1,10 +1,17 @@
-use crate::{index::entry::Entry, OutPoint, Txid};
+use crate::{
+  index::{
+    entry::{Entry, ScriptHashValue},
+    event::{Event, EventPointer},
+  },
+  OutPoint, Transaction, Txid,
+};
 use crate::{rune_id_to_rune_entry, RuneEntry};
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
 use rune_indexer_interface::*;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::str::FromStr;
 
@@ -28,6 +35,203 @@ pub fn get_height() -> Result<(u32, String), OrdError> {
   Ok((height, hash.to_string()))
 }
 
+#[derive(CandidType)]
+pub struct OutputRunes {
+  pub vout: u32,
+  pub runes: Vec<RuneBalance>,
+}
+
+#[derive(CandidType)]
+pub struct RuneTransferValidation {
+  pub outputs: Vec<OutputRunes>,
+  pub burned: Vec<RuneBalance>,
+  pub burn_reason: Option<String>,
+}
+
+fn sorted_balances(runes: impl Iterator<Item = (ordinals::RuneId, u128)>) -> Vec<RuneBalance> {
+  let mut runes = runes.collect::<Vec<_>>();
+  runes.sort();
+  runes
+    .into_iter()
+    .map(|(id, balance)| crate::RuneBalance { id, balance }.into())
+    .collect()
+}
+
+#[derive(CandidType)]
+pub struct OutpointRunes {
+  pub txid: String,
+  pub vout: u32,
+  pub runes: Vec<RuneBalance>,
+}
+
+#[derive(CandidType)]
+pub struct AddressRunes {
+  // summed over `outpoints`
+  pub balances: Vec<RuneBalance>,
+  pub outpoints: Vec<OutpointRunes>,
+}
+
+fn address_outpoints(address: &str) -> Result<Vec<OutPoint>, OrdError> {
+  let script = crate::Address::<crate::NetworkUnchecked>::from_str(address)
+    .map_err(|e| OrdError::Params(e.to_string()))?
+    .assume_checked()
+    .script_pubkey();
+  Ok(
+    crate::script_to_outpoints(|s| {
+      s.get(&ScriptHashValue::of(&script)).map(|outpoints| {
+        outpoints
+          .iter()
+          .map(|o| OutPoint::load(o.clone()))
+          .collect()
+      })
+    })
+    .unwrap_or_default(),
+  )
+}
+
+fn outpoint_runes(outpoint: OutPoint) -> Vec<(ordinals::RuneId, u128)> {
+  crate::outpoint_to_rune_balances(|b| {
+    b.get(&outpoint.store())
+      .map(|v| v.iter().map(|i| (i.id, i.balance)).collect())
+  })
+  .unwrap_or_default()
+}
+
+/*
+ * unspent runic outputs locked to the address's script. the address isn't checked
+ * against a network since only its script matters. outputs created before the
+ * address index existed aren't covered
+ */
+#[query]
+pub fn get_rune_balances_for_address(address: String) -> Result<AddressRunes, OrdError> {
+  let mut total: HashMap<ordinals::RuneId, u128> = HashMap::new();
+  let outpoints = address_outpoints(&address)?
+    .into_iter()
+    .map(|outpoint| {
+      let runes = outpoint_runes(outpoint);
+      for (id, balance) in runes.iter() {
+        *total.entry(*id).or_default() += balance;
+      }
+      OutpointRunes {
+        txid: outpoint.txid.to_string(),
+        vout: outpoint.vout,
+        runes: sorted_balances(runes.into_iter()),
+      }
+    })
+    .collect();
+  Ok(AddressRunes {
+    balances: sorted_balances(total.into_iter()),
+    outpoints,
+  })
+}
+
+const OUTPOINTS_PER_PAGE: usize = 500;
+
+#[derive(CandidType)]
+pub struct OutpointPage {
+  pub outpoints: Vec<OutpointRunes>,
+  // passed back for the following page, `None` on the last one
+  pub next_cursor: Option<u64>,
+}
+
+/*
+ * the address's unspent runic outputs a page at a time, `cursor` counts the outputs
+ * already returned. spending an output moves the address's last one into its place,
+ * so outputs spent between two pages can make a later page skip one
+ */
+#[query]
+pub fn get_outpoints_for_address(
+  address: String,
+  cursor: Option<u64>,
+) -> Result<OutpointPage, OrdError> {
+  let outpoints = address_outpoints(&address)?;
+  let start = cursor.unwrap_or(0) as usize;
+  let end = outpoints
+    .len()
+    .min(start.saturating_add(OUTPOINTS_PER_PAGE));
+  Ok(OutpointPage {
+    outpoints: outpoints
+      .get(start..end)
+      .unwrap_or_default()
+      .iter()
+      .map(|outpoint| OutpointRunes {
+        txid: outpoint.txid.to_string(),
+        vout: outpoint.vout,
+        runes: sorted_balances(outpoint_runes(*outpoint).into_iter()),
+      })
+      .collect(),
+    next_cursor: (end < outpoints.len()).then_some(end as u64),
+  })
+}
+
+#[derive(CandidType)]
+pub enum OutpointStatus {
+  Unspent { runes: Vec<RuneBalance> },
+  Spent { height: u32 },
+  // never indexed as holding runes, plain bitcoin or not mined as of the indexed tip
+  Unknown,
+}
+
+/*
+ * whether a runic output is still unspent as of the indexed tip, along with what it
+ * holds. the indexer only follows outputs holding runes, the bitcoin canister has
+ * the last word on plain ones
+ */
+#[query]
+pub fn is_outpoint_spent(txid: String, vout: u32) -> Result<OutpointStatus, OrdError> {
+  let outpoint = OutPoint {
+    txid: Txid::from_str(&txid).map_err(|e| OrdError::Params(e.to_string()))?,
+    vout,
+  };
+  let runes = crate::outpoint_to_rune_balances(|b| {
+    b.get(&outpoint.store())
+      .map(|v| v.iter().map(|i| (i.id, i.balance)).collect::<Vec<_>>())
+  });
+  if let Some(runes) = runes {
+    return Ok(OutpointStatus::Unspent {
+      runes: sorted_balances(runes.into_iter()),
+    });
+  }
+  Ok(
+    match crate::outpoint_to_spent_height(|s| s.get(&outpoint.store()).map(|h| *h)) {
+      Some(height) => OutpointStatus::Spent { height },
+      None => OutpointStatus::Unknown,
+    },
+  )
+}
+
+// where the runes of an unsigned transaction would end up if it got mined on top of the indexed tip
+#[query]
+pub fn validate_rune_transfer(
+  raw_unsigned_tx: Vec<u8>,
+) -> Result<RuneTransferValidation, OrdError> {
+  let tx: Transaction =
+    crate::consensus::deserialize(&raw_unsigned_tx).map_err(|e| OrdError::Params(e.to_string()))?;
+  let simulation = crate::index::simulate_transfer(&tx)?;
+  let outputs = simulation
+    .allocation
+    .allocated
+    .into_iter()
+    .enumerate()
+    .filter(|(_, runes)| !runes.is_empty())
+    .map(|(vout, runes)| OutputRunes {
+      vout: vout as u32,
+      runes: sorted_balances(runes.into_iter().map(|(id, lot)| (id, lot.0))),
+    })
+    .collect();
+  Ok(RuneTransferValidation {
+    outputs,
+    burned: sorted_balances(
+      simulation
+        .allocation
+        .burned
+        .into_iter()
+        .map(|(id, lot)| (id, lot.0)),
+    ),
+    burn_reason: simulation.burn_reason,
+  })
+}
+
 #[query(hidden = true)]
 pub fn rpc_transform(args: TransformArgs) -> HttpResponse {
   let headers = args
@@ -53,6 +257,72 @@ pub fn admin_set_url(url: String) -> Result<(), String> {
   Ok(())
 }
 
+// fallbacks for the main url, tried in order whenever the ones before them fail
+#[update]
+pub fn admin_set_backup_urls(urls: Vec<String>) -> Result<(), String> {
+  let caller = ic_cdk::api::caller();
+  if !ic_cdk::api::is_controller(&caller) {
+    return Err("Not authorized".to_string());
+  }
+  if urls.iter().any(|url| url.is_empty() || url.contains('\n')) {
+    return Err("urls must be non-empty single lines".to_string());
+  }
+  crate::set_backup_urls(urls);
+  Ok(())
+}
+
+// lets blocks through unchecked while none of the backups answers, off by default
+#[update]
+pub fn admin_set_cross_check_fail_open(enabled: bool) -> Result<(), String> {
+  let caller = ic_cdk::api::caller();
+  if !ic_cdk::api::is_controller(&caller) {
+    return Err("Not authorized".to_string());
+  }
+  crate::set_cross_check_fail_open(enabled);
+  Ok(())
+}
+
+// controllers only, the urls tend to carry api keys
+#[query]
+pub fn get_rpc_health() -> Result<Vec<crate::rpc_pool::RpcEndpointHealth>, String> {
+  let caller = ic_cdk::api::caller();
+  if !ic_cdk::api::is_controller(&caller) {
+    return Err("Not authorized".to_string());
+  }
+  Ok(crate::rpc_pool::health())
+}
+
+/*
+ * `canister_id` gets a one-way `on_new_block(nat32, text, nat32)` call with the height,
+ * hash and number of rune events of every block once it is indexed
+ */
+#[update]
+pub fn subscribe_new_blocks(canister_id: Principal) -> Result<(), String> {
+  let caller = ic_cdk::api::caller();
+  if !ic_cdk::api::is_controller(&caller) {
+    return Err("Not authorized".to_string());
+  }
+  crate::subscriber::subscribe(canister_id)
+}
+
+#[update]
+pub fn unsubscribe_new_blocks(canister_id: Principal) -> Result<(), String> {
+  let caller = ic_cdk::api::caller();
+  if !ic_cdk::api::is_controller(&caller) {
+    return Err("Not authorized".to_string());
+  }
+  if crate::subscriber::unsubscribe(canister_id) {
+    Ok(())
+  } else {
+    Err("not subscribed".to_string())
+  }
+}
+
+#[query]
+pub fn get_block_subscribers() -> Vec<Principal> {
+  crate::block_subscribers()
+}
+
 #[derive(CandidType)]
 pub struct CandidRuneEntry {
   pub runeid: CandidRuneId,
@@ -122,6 +392,245 @@ pub fn get_50_rune_entries() -> Vec<CandidRuneEntry> {
   })
 }
 
+#[derive(CandidType, Deserialize)]
+pub struct CandidTerms {
+  pub amount: Option<u128>,
+  pub cap: Option<u128>,
+  pub height: (Option<u64>, Option<u64>),
+  pub offset: (Option<u64>, Option<u64>),
+}
+
+// everything the index holds on a rune, along with the supply and mint window derived from it
+#[derive(CandidType)]
+pub struct RuneDetails {
+  pub runeid: CandidRuneId,
+  pub runename: String,
+  pub etching: String,
+  pub block: u64,
+  pub timestamp: u64,
+  pub divisibility: u8,
+  pub symbol: Option<u32>,
+  pub premine: u128,
+  pub mints: u128,
+  pub burned: u128,
+  pub supply: u128,
+  pub max_supply: u128,
+  pub terms: Option<CandidTerms>,
+  pub mint_start: Option<u64>,
+  pub mint_end: Option<u64>,
+  pub turbo: bool,
+}
+
+impl From<(ordinals::RuneId, RuneEntry)> for RuneDetails {
+  fn from((ordinals::RuneId { block, tx }, entry): (ordinals::RuneId, RuneEntry)) -> Self {
+    Self {
+      runeid: CandidRuneId { block, tx },
+      runename: entry.spaced_rune.to_string(),
+      etching: entry.etching.to_string(),
+      block: entry.block,
+      timestamp: entry.timestamp,
+      divisibility: entry.divisibility,
+      symbol: entry.symbol.map(|symbol| symbol as u32),
+      premine: entry.premine,
+      mints: entry.mints,
+      burned: entry.burned,
+      supply: entry.supply(),
+      max_supply: entry.max_supply(),
+      terms: entry.terms.map(|terms| CandidTerms {
+        amount: terms.amount,
+        cap: terms.cap,
+        height: terms.height,
+        offset: terms.offset,
+      }),
+      mint_start: entry.start(),
+      mint_end: entry.end(),
+      turbo: entry.turbo,
+    }
+  }
+}
+
+pub const MAX_RUNES_PER_PAGE: u64 = 100;
+
+#[query]
+pub fn get_rune_by_id(runeid: CandidRuneId) -> Option<RuneDetails> {
+  let runeid = ordinals::RuneId {
+    block: runeid.block,
+    tx: runeid.tx,
+  };
+  rune_id_to_rune_entry(|entries| entries.get(&runeid).map(|entry| (runeid, *entry).into()))
+}
+
+// accepts the name with or without spacers
+#[query]
+pub fn get_rune_by_name(name: String) -> Result<Option<RuneDetails>, OrdError> {
+  let spaced_rune =
+    ordinals::SpacedRune::from_str(&name).map_err(|e| OrdError::Params(e.to_string()))?;
+  let Some(runeid) = crate::rune_to_rune_id(|r| r.get(&spaced_rune.rune.0).map(|id| *id)) else {
+    return Ok(None);
+  };
+  Ok(get_rune_by_id(CandidRuneId {
+    block: runeid.block,
+    tx: runeid.tx,
+  }))
+}
+
+// indexed runes in etching order, at most `MAX_RUNES_PER_PAGE` per call
+#[query]
+pub fn list_runes(offset: u64, limit: u64) -> Vec<RuneDetails> {
+  let ids = crate::rune_ids(|ids| {
+    ids
+      .iter()
+      .skip(offset.try_into().unwrap_or(usize::MAX))
+      .take(limit.min(MAX_RUNES_PER_PAGE) as usize)
+      .map(|id| *id)
+      .collect::<Vec<_>>()
+  });
+  rune_id_to_rune_entry(|entries| {
+    ids
+      .iter()
+      .filter_map(|id| entries.get(id).map(|entry| (*id, *entry).into()))
+      .collect()
+  })
+}
+
+#[derive(CandidType)]
+pub enum RuneEvent {
+  Etched {
+    txid: String,
+    runeid: CandidRuneId,
+  },
+  Minted {
+    txid: String,
+    runeid: CandidRuneId,
+    amount: u128,
+  },
+  Transferred {
+    txid: String,
+    vout: u32,
+    runeid: CandidRuneId,
+    amount: u128,
+  },
+  Burned {
+    txid: String,
+    runeid: CandidRuneId,
+    amount: u128,
+  },
+}
+
+#[derive(CandidType)]
+pub struct BlockEvent {
+  pub block_height: u32,
+  pub event: RuneEvent,
+}
+
+impl From<Event> for BlockEvent {
+  fn from(event: Event) -> Self {
+    let runeid = |ordinals::RuneId { block, tx }| CandidRuneId { block, tx };
+    match event {
+      Event::RuneEtched {
+        block_height,
+        rune_id,
+        txid,
+      } => Self {
+        block_height,
+        event: RuneEvent::Etched {
+          txid: txid.to_string(),
+          runeid: runeid(rune_id),
+        },
+      },
+      Event::RuneMinted {
+        amount,
+        block_height,
+        rune_id,
+        txid,
+      } => Self {
+        block_height,
+        event: RuneEvent::Minted {
+          txid: txid.to_string(),
+          runeid: runeid(rune_id),
+          amount,
+        },
+      },
+      Event::RuneTransferred {
+        amount,
+        block_height,
+        outpoint,
+        rune_id,
+        txid,
+      } => Self {
+        block_height,
+        event: RuneEvent::Transferred {
+          txid: txid.to_string(),
+          vout: outpoint.vout,
+          runeid: runeid(rune_id),
+          amount,
+        },
+      },
+      Event::RuneBurned {
+        amount,
+        block_height,
+        rune_id,
+        txid,
+      } => Self {
+        block_height,
+        event: RuneEvent::Burned {
+          txid: txid.to_string(),
+          runeid: runeid(rune_id),
+          amount,
+        },
+      },
+    }
+  }
+}
+
+pub const MAX_EVENTS_PER_PAGE: u64 = 100;
+
+// rune activity of the block in the order it was indexed
+#[query]
+pub fn get_events_in_block(height: u32) -> Vec<RuneEvent> {
+  crate::block_events(|b| {
+    b.get(&height)
+      .map(|events| {
+        events
+          .iter()
+          .map(|event| BlockEvent::from(Event::load(*event)).event)
+          .collect()
+      })
+      .unwrap_or_default()
+  })
+}
+
+// the rune's activity oldest first, at most `MAX_EVENTS_PER_PAGE` per call
+#[query]
+pub fn get_events_for_rune(runeid: CandidRuneId, offset: u64, limit: u64) -> Vec<BlockEvent> {
+  let runeid = ordinals::RuneId {
+    block: runeid.block,
+    tx: runeid.tx,
+  };
+  let pointers: Vec<EventPointer> = crate::rune_events(|r| {
+    r.get(&runeid)
+      .map(|pointers| {
+        pointers
+          .iter()
+          .skip(offset.try_into().unwrap_or(usize::MAX))
+          .take(limit.min(MAX_EVENTS_PER_PAGE) as usize)
+          .map(|pointer| *pointer)
+          .collect()
+      })
+      .unwrap_or_default()
+  });
+  crate::block_events(|b| {
+    pointers
+      .into_iter()
+      .filter_map(|pointer| {
+        let events = b.get(&pointer.height)?;
+        let event = *events.get(pointer.index as usize)?;
+        Some(Event::load(event).into())
+      })
+      .collect()
+  })
+}
+
 #[query(hidden = true)]
 fn http_request(
   req: ic_canisters_http_types::HttpRequest,
@@ -132,17 +641,170 @@ fn http_request(
   if req.path() == "/logs" {
     crate::ic_log::do_reply(req)
   } else {
-    ic_canisters_http_types::HttpResponseBuilder::not_found().build()
+    crate::http::do_reply(req)
   }
 }
 
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+  // height of the block passed as `first_block_hash`, `FIRST_HEIGHT` without one
+  pub start_height: Option<u32>,
+  pub stop_height: Option<u32>,
+  // waits for `import_state_chunk` instead of indexing from the start block
+  pub from_checkpoint: bool,
+  // chain of the node behind the rpc url
+  pub chain: Option<crate::Chain>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ImportedRuneEntry {
+  pub runeid: CandidRuneId,
+  pub block: u64,
+  pub burned: u128,
+  pub divisibility: u8,
+  pub etching: String,
+  pub mints: u128,
+  pub premine: u128,
+  pub rune: u128,
+  pub spacers: u32,
+  pub symbol: Option<u32>,
+  pub terms: Option<CandidTerms>,
+  pub timestamp: u64,
+  pub turbo: bool,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ImportedOutput {
+  pub txid: String,
+  pub vout: u32,
+  // feeds the address index
+  pub script_pubkey: Option<Vec<u8>>,
+  pub runes: Vec<(CandidRuneId, u128)>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct StateChunk {
+  pub rune_entries: Vec<ImportedRuneEntry>,
+  pub outputs: Vec<ImportedOutput>,
+  // height and hash of the block the snapshot was taken at, set on the last chunk only
+  pub tip: Option<(u32, String)>,
+}
+
+fn rune_entry(entry: ImportedRuneEntry) -> Result<(ordinals::RuneId, RuneEntry), String> {
+  let symbol = match entry.symbol {
+    None => None,
+    Some(symbol) => Some(char::from_u32(symbol).ok_or("invalid rune symbol".to_string())?),
+  };
+  Ok((
+    ordinals::RuneId {
+      block: entry.runeid.block,
+      tx: entry.runeid.tx,
+    },
+    RuneEntry {
+      block: entry.block,
+      burned: entry.burned,
+      divisibility: entry.divisibility,
+      etching: Txid::from_str(&entry.etching).map_err(|e| e.to_string())?,
+      mints: entry.mints,
+      premine: entry.premine,
+      spaced_rune: ordinals::SpacedRune {
+        rune: ordinals::Rune(entry.rune),
+        spacers: entry.spacers,
+      },
+      symbol,
+      terms: entry.terms.map(|terms| ordinals::Terms {
+        amount: terms.amount,
+        cap: terms.cap,
+        height: terms.height,
+        offset: terms.offset,
+      }),
+      timestamp: entry.timestamp,
+      turbo: entry.turbo,
+    },
+  ))
+}
+
+/*
+ * bootstraps a canister initialized `from_checkpoint` with a trusted state snapshot.
+ * a chunk is parsed whole before anything is written, a rejected chunk changes nothing
+ * and one sent again after a failure replaces what it wrote the first time
+ */
+#[update]
+pub fn import_state_chunk(chunk: StateChunk) -> Result<(), String> {
+  let caller = ic_cdk::api::caller();
+  if !ic_cdk::api::is_controller(&caller) {
+    return Err("Not authorized".to_string());
+  }
+  if !crate::is_bootstrapping() {
+    return Err("the index is already past its start block".to_string());
+  }
+  let tip = match chunk.tip {
+    None => None,
+    Some((height, hash)) => Some((
+      height,
+      crate::BlockHash::from_str(&hash).map_err(|e| e.to_string())?,
+    )),
+  };
+  let entries = chunk
+    .rune_entries
+    .into_iter()
+    .map(rune_entry)
+    .collect::<Result<Vec<_>, _>>()?;
+  let outputs = chunk
+    .outputs
+    .into_iter()
+    .map(|output| {
+      let outpoint = OutPoint {
+        txid: Txid::from_str(&output.txid).map_err(|e| e.to_string())?,
+        vout: output.vout,
+      };
+      let balances = output
+        .runes
+        .into_iter()
+        .map(|(id, balance)| crate::RuneBalance {
+          id: ordinals::RuneId {
+            block: id.block,
+            tx: id.tx,
+          },
+          balance,
+        })
+        .collect::<Vec<_>>();
+      let script = output.script_pubkey.map(crate::ScriptBuf::from_bytes);
+      Ok((outpoint, balances, script))
+    })
+    .collect::<Result<Vec<_>, String>>()?;
+  for (id, entry) in entries {
+    crate::index::checkpoint::import_rune_entry(id, entry);
+  }
+  for (outpoint, balances, script) in outputs {
+    crate::index::checkpoint::import_output(outpoint, balances, script.as_deref());
+  }
+  if let Some((height, hash)) = tip {
+    crate::index::checkpoint::finish(height, hash);
+  }
+  Ok(())
+}
+
 #[init]
-pub fn init(url: String, first_block_hash: String) {
+pub fn init(url: String, first_block_hash: String, args: Option<InitArgs>) {
   crate::init_storage();
   crate::set_url(url);
-  crate::index::init_rune(&first_block_hash);
+  let args = args.unwrap_or(InitArgs {
+    start_height: None,
+    stop_height: None,
+    from_checkpoint: false,
+    chain: None,
+  });
+  crate::set_stop_height(args.stop_height);
+  crate::set_chain(args.chain);
+  if !args.from_checkpoint {
+    crate::index::init_rune(
+      args.start_height.unwrap_or(crate::FIRST_HEIGHT),
+      &first_block_hash,
+    );
+    crate::index::sync(1);
+  }
   crate::set_first_block_hash(first_block_hash);
-  crate::index::sync(1);
 }
 
 #[pre_upgrade]
@@ -153,7 +815,9 @@ fn pre_upgrade() {
 #[post_upgrade]
 fn post_upgrade() {
   crate::restore();
-  crate::index::sync(1);
+  if !crate::is_bootstrapping() {
+    crate::index::sync(1);
+  }
 }
 
 ic_cdk::export_candid!();
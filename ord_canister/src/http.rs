@@ -0,0 +1,197 @@
+use crate::canister::{
+  get_events_in_block, get_height, get_rune_by_id, get_rune_by_name, is_outpoint_spent,
+  CandidRuneId, OutpointStatus, RuneDetails, RuneEvent,
+};
+use crate::OutPoint;
+use ic_canisters_http_types::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use rune_indexer_interface::RuneBalance;
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+/*
+ * read-only json views of the index for clients without candid tooling:
+ *   /height                 indexed tip
+ *   /rune/{id or name}      `840000:3` or the spaced name with `.` as spacer
+ *   /output/{txid}:{vout}   runes held by the output and whether it's spent
+ *   /block/{height}/events  rune activity of the block
+ * amounts are strings, they overflow javascript numbers
+ */
+pub fn do_reply(req: HttpRequest) -> HttpResponse {
+  let segments = req.path().trim_matches('/').split('/').collect::<Vec<_>>();
+  match segments.as_slice() {
+    ["height"] => height(),
+    ["rune", rune] => rune(rune),
+    ["output", outpoint] => output(outpoint),
+    ["block", height, "events"] => block_events(height),
+    _ => not_found(),
+  }
+}
+
+fn reply(value: Value) -> HttpResponse {
+  HttpResponseBuilder::ok()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .header("Access-Control-Allow-Origin", "*")
+    .with_body_and_content_length(value.to_string())
+    .build()
+}
+
+fn bad_request(message: String) -> HttpResponse {
+  HttpResponseBuilder::bad_request()
+    .with_body_and_content_length(message)
+    .build()
+}
+
+fn not_found() -> HttpResponse {
+  HttpResponseBuilder::not_found().build()
+}
+
+fn runeid_json(runeid: &CandidRuneId) -> Value {
+  json!(format!("{}:{}", runeid.block, runeid.tx))
+}
+
+fn balances_json(balances: &[RuneBalance]) -> Value {
+  balances
+    .iter()
+    .map(|balance| {
+      json!({
+        "id": format!("{}:{}", balance.id.block, balance.id.tx),
+        "balance": balance.balance.to_string(),
+      })
+    })
+    .collect()
+}
+
+fn rune_json(rune: RuneDetails) -> Value {
+  json!({
+    "id": runeid_json(&rune.runeid),
+    "name": rune.runename,
+    "etching": rune.etching,
+    "block": rune.block,
+    "timestamp": rune.timestamp,
+    "divisibility": rune.divisibility,
+    "symbol": rune.symbol.and_then(char::from_u32).map(String::from),
+    "premine": rune.premine.to_string(),
+    "mints": rune.mints.to_string(),
+    "burned": rune.burned.to_string(),
+    "supply": rune.supply.to_string(),
+    "max_supply": rune.max_supply.to_string(),
+    "terms": rune.terms.map(|terms| json!({
+      "amount": terms.amount.map(|amount| amount.to_string()),
+      "cap": terms.cap.map(|cap| cap.to_string()),
+      "height": [terms.height.0, terms.height.1],
+      "offset": [terms.offset.0, terms.offset.1],
+    })),
+    "mint_start": rune.mint_start,
+    "mint_end": rune.mint_end,
+    "turbo": rune.turbo,
+  })
+}
+
+fn event_json(event: RuneEvent) -> Value {
+  match event {
+    RuneEvent::Etched { txid, runeid } => json!({
+      "type": "etched",
+      "txid": txid,
+      "rune": runeid_json(&runeid),
+    }),
+    RuneEvent::Minted {
+      txid,
+      runeid,
+      amount,
+    } => json!({
+      "type": "minted",
+      "txid": txid,
+      "rune": runeid_json(&runeid),
+      "amount": amount.to_string(),
+    }),
+    RuneEvent::Transferred {
+      txid,
+      vout,
+      runeid,
+      amount,
+    } => json!({
+      "type": "transferred",
+      "txid": txid,
+      "vout": vout,
+      "rune": runeid_json(&runeid),
+      "amount": amount.to_string(),
+    }),
+    RuneEvent::Burned {
+      txid,
+      runeid,
+      amount,
+    } => json!({
+      "type": "burned",
+      "txid": txid,
+      "rune": runeid_json(&runeid),
+      "amount": amount.to_string(),
+    }),
+  }
+}
+
+fn height() -> HttpResponse {
+  match get_height() {
+    Ok((height, hash)) => reply(json!({ "height": height, "hash": hash })),
+    Err(e) => bad_request(format!("{:?}", e)),
+  }
+}
+
+fn rune(rune: &str) -> HttpResponse {
+  let details = match ordinals::RuneId::from_str(rune) {
+    Ok(runeid) => get_rune_by_id(CandidRuneId {
+      block: runeid.block,
+      tx: runeid.tx,
+    }),
+    Err(_) => match get_rune_by_name(rune.to_string()) {
+      Ok(details) => details,
+      Err(e) => return bad_request(format!("{:?}", e)),
+    },
+  };
+  match details {
+    Some(details) => reply(rune_json(details)),
+    None => not_found(),
+  }
+}
+
+fn output(outpoint: &str) -> HttpResponse {
+  let outpoint = match OutPoint::from_str(outpoint) {
+    Ok(outpoint) => outpoint,
+    Err(e) => return bad_request(e.to_string()),
+  };
+  let status = match is_outpoint_spent(outpoint.txid.to_string(), outpoint.vout) {
+    Ok(status) => status,
+    Err(e) => return bad_request(format!("{:?}", e)),
+  };
+  let outpoint = outpoint.to_string();
+  reply(match status {
+    OutpointStatus::Unspent { runes } => json!({
+      "outpoint": outpoint,
+      "spent": false,
+      "runes": balances_json(&runes),
+    }),
+    OutpointStatus::Spent { height } => json!({
+      "outpoint": outpoint,
+      "spent": true,
+      "spent_height": height,
+    }),
+    // plain bitcoin or not mined yet, the index can't tell
+    OutpointStatus::Unknown => json!({
+      "outpoint": outpoint,
+      "spent": null,
+      "runes": [],
+    }),
+  })
+}
+
+fn block_events(height: &str) -> HttpResponse {
+  let height = match u32::from_str(height) {
+    Ok(height) => height,
+    Err(_) => return bad_request("failed to parse the block height".to_string()),
+  };
+  reply(
+    get_events_in_block(height)
+      .into_iter()
+      .map(event_json)
+      .collect(),
+  )
+}
@@ -4,32 +4,36 @@ use crate::ic_log::*;
 use bitcoin::block::Header;
 use ic_canister_log::log;
 use rune_indexer_interface::MintError;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 
 pub use self::entry::RuneEntry;
 
+pub(crate) mod checkpoint;
 pub(crate) mod entry;
 pub mod event;
 mod lot;
+pub(crate) mod reorg;
 mod updater;
 
 #[allow(dead_code)]
 pub const SCHEMA_VERSION: u64 = 26;
 
-fn set_beginning_block(hash: &str) {
+fn set_beginning_block(height: u32, hash: &str) {
     let hash = BlockHash::from_str(hash).expect("valid hash");
-    crate::increase_height(FIRST_HEIGHT, hash);
+    crate::increase_height(height, hash);
 }
 
-pub(crate) fn init_rune(hash: &str) {
-    set_beginning_block(hash);
+// `hash` is the block at `height`, indexing continues with the one after it
+pub(crate) fn init_rune(height: u32, hash: &str) {
+    set_beginning_block(height, hash);
     let rune = Rune(2055900680524219742);
 
     let id = RuneId { block: 1, tx: 0 };
     let etching = Txid::all_zeros();
 
     rune_to_rune_id(|r| r.insert(rune.store(), id)).expect("MemoryOverflow");
+    rune_ids(|r| r.insert(id)).expect("MemoryOverflow");
 
     rune_id_to_rune_entry(|r| {
         r.insert(
@@ -104,11 +108,84 @@ pub(crate) fn get_rune_balances_for_output(
     })
 }
 
+pub(crate) struct TransferSimulation {
+    pub(crate) allocation: updater::Allocation,
+    // why runes of the inputs would be burned, `None` when everything lands in outputs
+    pub(crate) burn_reason: Option<String>,
+}
+
+/*
+ * runs a transaction that is not mined yet through the allocation rules of the indexer.
+ * the input balances are only read, and a mint is credited with what the next block
+ * would grant without counting it. etchings are rejected since the id of the new rune
+ * depends on the position of the transaction in its block
+ */
+pub(crate) fn simulate_transfer(tx: &Transaction) -> Result<TransferSimulation> {
+    let artifact = Runestone::decipher(tx);
+    if let Some(Artifact::Runestone(Runestone {
+        etching: Some(_), ..
+    })) = artifact
+    {
+        return Err(OrdError::Params(
+            "etchings can't be validated before they are mined".to_string(),
+        ));
+    }
+
+    let mut unallocated: HashMap<RuneId, Lot> = HashMap::new();
+    for input in &tx.input {
+        crate::outpoint_to_rune_balances(|b| {
+            if let Some(balances) = b.get(&OutPoint::store(input.previous_output)) {
+                for rune in balances.iter() {
+                    let rune = *rune;
+                    *unallocated.entry(rune.id).or_default() += rune.balance;
+                }
+            }
+        });
+    }
+
+    if let Some(id) = artifact.as_ref().and_then(|artifact| artifact.mint()) {
+        let (height, _) = crate::highest_block();
+        let mintable = rune_id_to_rune_entry(|r| {
+            r.get(&id)
+                .map(|entry| entry.mintable(u64::from(height) + 1))
+        });
+        if let Some(Ok(amount)) = mintable {
+            *unallocated.entry(id).or_default() += amount;
+        }
+    }
+
+    let allocation = updater::allocate(tx, artifact.as_ref(), unallocated, None);
+
+    let burn_reason = if allocation.burned.is_empty() {
+        None
+    } else if let Some(Artifact::Cenotaph(cenotaph)) = &artifact {
+        Some(match cenotaph.flaw {
+            Some(flaw) => format!("the runestone is a cenotaph: {}", flaw),
+            None => "the runestone is a cenotaph".to_string(),
+        })
+    } else if !allocation.burned_outputs.is_empty() {
+        Some(format!(
+            "runes are allocated to OP_RETURN outputs {:?}",
+            allocation.burned_outputs
+        ))
+    } else {
+        Some("no non OP_RETURN output receives the unallocated runes".to_string())
+    };
+
+    Ok(TransferSimulation {
+        allocation,
+        burn_reason,
+    })
+}
+
 pub(crate) async fn get_best_from_rpc() -> Result<(u32, BlockHash)> {
-    let url = get_url();
-    let hash = rpc::get_best_block_hash(&url).await?;
-    let header = rpc::get_block_header(&url, hash).await?;
-    Ok((header.height.try_into().expect("usize to u32"), hash))
+    let (_, best) = rpc_pool::call(None, |url| async move {
+        let hash = rpc::get_best_block_hash(&url).await?;
+        let header = rpc::get_block_header(&url, hash).await?;
+        Ok((header.height.try_into().expect("usize to u32"), hash))
+    })
+    .await?;
+    Ok(best)
 }
 
 #[cfg(feature = "cmp-header")]
@@ -129,8 +206,7 @@ pub fn sync(secs: u64) {
     ic_cdk_timers::set_timer(std::time::Duration::from_secs(secs), || {
         ic_cdk::spawn(async move {
             let (height, current) = crate::highest_block();
-            // uncomment this to test
-            if height >= 840_000 {
+            if crate::stop_height().is_some_and(|stop| height >= stop) {
                 ic_cdk::println!("we are done!");
                 return;
             }
@@ -141,18 +217,38 @@ pub fn sync(secs: u64) {
                         sync(5);
                     } else {
                         match updater::get_block(height + 1).await {
-                            Ok(block) => {
+                            Ok((url, block)) => {
                                 #[cfg(feature = "cmp-header")]
                                 cmp_header(height + 1, &block.header.block_hash()).await;
+                                let hash = block.header.block_hash();
+                                if let Err(e) = rpc_pool::cross_check(height + 1, &hash, &url).await
+                                {
+                                    log!(ERROR, "block {} not applied: {:?}", height + 1, e);
+                                    sync(5);
+                                    return;
+                                }
                                 if block.header.prev_blockhash != current {
-                                    log!(
+                                    // unwinds one block per round until the parent matches again
+                                    if reorg::can_rollback(height) {
+                                        log!(
+                                            WARNING,
+                                            "reorg detected! rolling back {}({:x}), the new block to be applied {:?}",
+                                            height,
+                                            current,
+                                            block.header
+                                        );
+                                        reorg::rollback(height);
+                                        sync(0);
+                                    } else {
+                                        log!(
                     CRITICAL,
                     "reorg detected! our best = {}({:x}), the new block to be applied {:?}",
                     height,
                     current,
                     block.header
                   );
-                                    sync(5);
+                                        sync(5);
+                                    }
                                     return;
                                 }
                                 if let Err(e) = updater::index_block(height + 1, block).await {
@@ -0,0 +1,45 @@
+use super::*;
+use ic_stable_memory::collections::SVec;
+
+/*
+ * writes a trusted state snapshot straight into the index. the snapshot is imported
+ * in chunks while the canister has no height yet, the chunk carrying the tip ends
+ * the import and starts the sync from the block after it
+ */
+pub(crate) fn import_rune_entry(id: RuneId, entry: RuneEntry) {
+  rune_id_to_rune_entry(|r| r.insert(id, entry)).expect("MemoryOverflow");
+  rune_ids(|r| r.insert(id)).expect("MemoryOverflow");
+  rune_to_rune_id(|r| {
+    if !r.contains_key(&entry.spaced_rune.rune.store()) {
+      r.insert(entry.spaced_rune.rune.store(), id)
+        .expect("MemoryOverflow");
+    }
+  });
+  transaction_id_to_rune(|t| t.insert(entry.etching.store(), entry.spaced_rune.rune.store()))
+    .expect("MemoryOverflow");
+}
+
+/*
+ * outputs without a script are left out of the address index. an output imported
+ * again drops out of the index first, so a chunk sent twice doesn't list it twice
+ */
+pub(crate) fn import_output(
+  outpoint: OutPoint,
+  balances: Vec<RuneBalance>,
+  script: Option<&Script>,
+) {
+  updater::unindex_output(outpoint);
+  let mut vec = SVec::new_with_capacity(balances.len()).expect("out of memory");
+  for balance in balances {
+    vec.push(balance).expect("MemoryOverflow");
+  }
+  outpoint_to_rune_balances(|b| b.insert(outpoint.store(), vec).expect("MemoryOverflow"));
+  if let Some(script) = script {
+    updater::index_output(outpoint, script);
+  }
+}
+
+pub(crate) fn finish(height: u32, hash: BlockHash) {
+  crate::increase_height(height, hash);
+  sync(1);
+}
@@ -561,6 +561,34 @@ impl AsFixedSizeBytes for TxidValue {
 
 impl StableType for TxidValue {}
 
+// scripts vary in length, the address index keys them by their sha256
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub(crate) struct ScriptHashValue(pub [u8; 32]);
+
+impl ScriptHashValue {
+  pub(crate) fn of(script: &Script) -> Self {
+    Self(bitcoin::hashes::sha256::Hash::hash(script.as_bytes()).to_byte_array())
+  }
+}
+
+impl AsFixedSizeBytes for ScriptHashValue {
+  type Buf = [u8; 32];
+
+  const SIZE: usize = 32;
+
+  fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+    buf.copy_from_slice(&self.0);
+  }
+
+  fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+    let mut value = [0; 32];
+    value.copy_from_slice(buf);
+    Self(value)
+  }
+}
+
+impl StableType for ScriptHashValue {}
+
 #[cfg(test)]
 mod tests {
   use super::*;
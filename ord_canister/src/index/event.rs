@@ -1,4 +1,6 @@
-use crate::{OutPoint, RuneId, Txid};
+use super::entry::Entry;
+use crate::{Hash, OutPoint, RuneId, Txid};
+use ic_stable_memory::{collections::SVec, AsFixedSizeBytes, StableType};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
@@ -42,3 +44,219 @@ pub enum Event {
         txid: Txid,
     },
 }
+
+// kinds as stored, in the order of the variants above
+const BURNED: u8 = 0;
+const ETCHED: u8 = 1;
+const MINTED: u8 = 2;
+const TRANSFERRED: u8 = 3;
+
+// fixed size form of an event, fields a kind doesn't have are stored zeroed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct EventValue {
+    kind: u8,
+    block_height: u32,
+    txid: [u8; 32],
+    rune_id: RuneId,
+    amount: u128,
+    vout: u32,
+}
+
+impl Entry for Event {
+    type Value = EventValue;
+
+    fn load(value: Self::Value) -> Self {
+        let EventValue {
+            kind,
+            block_height,
+            txid,
+            rune_id,
+            amount,
+            vout,
+        } = value;
+        let txid = Txid::from_byte_array(txid);
+        match kind {
+            BURNED => Event::RuneBurned {
+                amount,
+                block_height,
+                rune_id,
+                txid,
+            },
+            ETCHED => Event::RuneEtched {
+                block_height,
+                rune_id,
+                txid,
+            },
+            MINTED => Event::RuneMinted {
+                amount,
+                block_height,
+                rune_id,
+                txid,
+            },
+            _ => Event::RuneTransferred {
+                amount,
+                block_height,
+                outpoint: OutPoint { txid, vout },
+                rune_id,
+                txid,
+            },
+        }
+    }
+
+    fn store(self) -> Self::Value {
+        let (kind, block_height, txid, rune_id, amount, vout) = match self {
+            Event::RuneBurned {
+                amount,
+                block_height,
+                rune_id,
+                txid,
+            } => (BURNED, block_height, txid, rune_id, amount, 0),
+            Event::RuneEtched {
+                block_height,
+                rune_id,
+                txid,
+            } => (ETCHED, block_height, txid, rune_id, 0, 0),
+            Event::RuneMinted {
+                amount,
+                block_height,
+                rune_id,
+                txid,
+            } => (MINTED, block_height, txid, rune_id, amount, 0),
+            Event::RuneTransferred {
+                amount,
+                block_height,
+                outpoint,
+                rune_id,
+                txid,
+            } => (
+                TRANSFERRED,
+                block_height,
+                txid,
+                rune_id,
+                amount,
+                outpoint.vout,
+            ),
+        };
+        EventValue {
+            kind,
+            block_height,
+            txid: txid.to_byte_array(),
+            rune_id,
+            amount,
+            vout,
+        }
+    }
+}
+
+impl AsFixedSizeBytes for EventValue {
+    type Buf = [u8; Self::SIZE];
+
+    const SIZE: usize = 1 + 4 + 32 + RuneId::SIZE + 16 + 4;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let mut offset = 0;
+        self.kind.as_fixed_size_bytes(&mut buf[offset..offset + 1]);
+        offset += 1;
+        self.block_height
+            .as_fixed_size_bytes(&mut buf[offset..offset + 4]);
+        offset += 4;
+        buf[offset..offset + 32].copy_from_slice(&self.txid);
+        offset += 32;
+        self.rune_id
+            .as_fixed_size_bytes(&mut buf[offset..offset + RuneId::SIZE]);
+        offset += RuneId::SIZE;
+        self.amount
+            .as_fixed_size_bytes(&mut buf[offset..offset + 16]);
+        offset += 16;
+        self.vout.as_fixed_size_bytes(&mut buf[offset..offset + 4]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let mut offset = 0;
+        let kind = u8::from_fixed_size_bytes(&buf[offset..offset + 1]);
+        offset += 1;
+        let block_height = u32::from_fixed_size_bytes(&buf[offset..offset + 4]);
+        offset += 4;
+        let mut txid = [0; 32];
+        txid.copy_from_slice(&buf[offset..offset + 32]);
+        offset += 32;
+        let rune_id = RuneId::from_fixed_size_bytes(&buf[offset..offset + RuneId::SIZE]);
+        offset += RuneId::SIZE;
+        let amount = u128::from_fixed_size_bytes(&buf[offset..offset + 16]);
+        offset += 16;
+        let vout = u32::from_fixed_size_bytes(&buf[offset..offset + 4]);
+        Self {
+            kind,
+            block_height,
+            txid,
+            rune_id,
+            amount,
+            vout,
+        }
+    }
+}
+
+impl StableType for EventValue {}
+
+// position of an event in the log of its block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct EventPointer {
+    pub(crate) height: u32,
+    pub(crate) index: u32,
+}
+
+impl AsFixedSizeBytes for EventPointer {
+    type Buf = [u8; Self::SIZE];
+
+    const SIZE: usize = 8;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.height.as_fixed_size_bytes(&mut buf[0..4]);
+        self.index.as_fixed_size_bytes(&mut buf[4..8]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        Self {
+            height: u32::from_fixed_size_bytes(&buf[0..4]),
+            index: u32::from_fixed_size_bytes(&buf[4..8]),
+        }
+    }
+}
+
+impl StableType for EventPointer {}
+
+impl Event {
+    pub(crate) fn rune_id(&self) -> RuneId {
+        match self {
+            Event::RuneBurned { rune_id, .. }
+            | Event::RuneEtched { rune_id, .. }
+            | Event::RuneMinted { rune_id, .. }
+            | Event::RuneTransferred { rune_id, .. } => *rune_id,
+        }
+    }
+}
+
+// appends the events of a freshly indexed block to the block and rune logs
+pub(crate) fn record_block(height: u32, events: Vec<Event>) {
+    if events.is_empty() {
+        return;
+    }
+    let mut values = SVec::new_with_capacity(events.len()).expect("out of memory");
+    for (index, event) in events.into_iter().enumerate() {
+        let pointer = EventPointer {
+            height,
+            index: index.try_into().unwrap(),
+        };
+        let rune_id = event.rune_id();
+        crate::rune_events(|r| match r.get_mut(&rune_id) {
+            Some(mut pointers) => pointers.push(pointer).expect("MemoryOverflow"),
+            None => {
+                let mut pointers = SVec::new();
+                pointers.push(pointer).expect("MemoryOverflow");
+                r.insert(rune_id, pointers).expect("MemoryOverflow");
+            }
+        });
+        values.push(event.store()).expect("MemoryOverflow");
+    }
+    crate::block_events(|b| b.insert(height, values)).expect("MemoryOverflow");
+}
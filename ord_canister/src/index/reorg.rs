@@ -1,120 +1,367 @@
-use {super::*, updater::BlockData};
+use super::{entry::Entry, event::Event, updater};
+use crate::{
+  index::entry::{OutPointValue, ScriptHashValue},
+  *,
+};
+use ic_stable_memory::{collections::SVec, AsFixedSizeBytes, StableType};
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum Error {
-  Recoverable { height: u32, depth: u32 },
-  Unrecoverable,
+// blocks kept unwindable, a fork deeper than this stalls the sync until an operator steps in
+pub(crate) const MAX_REORG_DEPTH: u32 = 12;
+
+// a balance of an output the block spent, put back when the block is unwound
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SpentBalance {
+  pub(crate) outpoint: OutPointValue,
+  pub(crate) balance: RuneBalance,
+  pub(crate) script: Option<ScriptHashValue>,
 }
 
-impl Display for Error {
-  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-    match self {
-      Self::Recoverable { height, depth } => {
-        write!(f, "{depth} block deep reorg detected at height {height}")
+impl AsFixedSizeBytes for SpentBalance {
+  type Buf = [u8; Self::SIZE];
+
+  const SIZE: usize = OutPointValue::SIZE + RuneBalance::SIZE + 1 + ScriptHashValue::SIZE;
+
+  fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+    let mut offset = 0;
+    self
+      .outpoint
+      .as_fixed_size_bytes(&mut buf[offset..offset + OutPointValue::SIZE]);
+    offset += OutPointValue::SIZE;
+    self
+      .balance
+      .as_fixed_size_bytes(&mut buf[offset..offset + RuneBalance::SIZE]);
+    offset += RuneBalance::SIZE;
+    match &self.script {
+      Some(script) => {
+        buf[offset] = 1;
+        script.as_fixed_size_bytes(&mut buf[offset + 1..offset + 1 + ScriptHashValue::SIZE]);
       }
-      Self::Unrecoverable => write!(f, "unrecoverable reorg detected"),
+      None => buf[offset..offset + 1 + ScriptHashValue::SIZE].fill(0),
     }
   }
-}
-
-impl std::error::Error for Error {}
 
-const MAX_SAVEPOINTS: u32 = 2;
-const SAVEPOINT_INTERVAL: u32 = 10;
-const CHAIN_TIP_DISTANCE: u32 = 21;
+  fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+    let mut offset = 0;
+    let outpoint = OutPointValue::from_fixed_size_bytes(&buf[offset..offset + OutPointValue::SIZE]);
+    offset += OutPointValue::SIZE;
+    let balance = RuneBalance::from_fixed_size_bytes(&buf[offset..offset + RuneBalance::SIZE]);
+    offset += RuneBalance::SIZE;
+    let script = (buf[offset] == 1).then(|| {
+      ScriptHashValue::from_fixed_size_bytes(&buf[offset + 1..offset + 1 + ScriptHashValue::SIZE])
+    });
+    Self {
+      outpoint,
+      balance,
+      script,
+    }
+  }
+}
 
-pub(crate) struct Reorg {}
+impl StableType for SpentBalance {}
 
-impl Reorg {
-  pub(crate) fn detect_reorg(block: &BlockData, height: u32, index: &Index) -> Result {
-    let bitcoind_prev_blockhash = block.header.prev_blockhash;
+/*
+ * keeps what the block at `height` spent, everything else it changed can be read back
+ * from its events. undo data older than `MAX_REORG_DEPTH` blocks is dropped
+*/
+pub(crate) fn record_spent(height: u32, spent: Vec<SpentBalance>) {
+  let mut balances = SVec::new_with_capacity(spent.len()).expect("out of memory");
+  for balance in spent {
+    balances.push(balance).expect("MemoryOverflow");
+  }
+  crate::height_to_spent_balances(|h| {
+    h.insert(height, balances).expect("MemoryOverflow");
+    if let Some(expired) = height.checked_sub(MAX_REORG_DEPTH) {
+      h.remove(&expired);
+    }
+  });
+}
 
-    match index.block_hash(height.checked_sub(1))? {
-      Some(index_prev_blockhash) if index_prev_blockhash == bitcoind_prev_blockhash => Ok(()),
-      Some(index_prev_blockhash) if index_prev_blockhash != bitcoind_prev_blockhash => {
-        let max_recoverable_reorg_depth =
-          (MAX_SAVEPOINTS - 1) * SAVEPOINT_INTERVAL + height % SAVEPOINT_INTERVAL;
+// the block at `height` was indexed recently enough to be unwound
+pub(crate) fn can_rollback(height: u32) -> bool {
+  crate::height_to_spent_balances(|h| h.contains_key(&height))
+}
 
-        for depth in 1..max_recoverable_reorg_depth {
-          let index_block_hash = index.block_hash(height.checked_sub(depth))?;
-          let bitcoind_block_hash = index
-            .client
-            .get_block_hash(u64::from(height.saturating_sub(depth)))
-            .into_option()?;
+/*
+ * unwinds the tip block at `height`: spent balances are restored first, so outputs the
+ * block both created and spent end up removed along with the rest it created
+*/
+pub(crate) fn rollback(height: u32) {
+  let spent = crate::height_to_spent_balances(|h| h.remove(&height)).expect("no undo data");
+  for spent in spent.iter() {
+    let SpentBalance {
+      outpoint,
+      balance,
+      script,
+    } = (*spent).clone();
+    crate::outpoint_to_spent_height(|s| s.remove(&outpoint));
+    let restored = crate::outpoint_to_rune_balances(|b| match b.get_mut(&outpoint) {
+      Some(mut balances) => {
+        balances.push(balance).expect("MemoryOverflow");
+        false
+      }
+      None => {
+        let mut balances = SVec::new();
+        balances.push(balance).expect("MemoryOverflow");
+        b.insert(outpoint.clone(), balances)
+          .expect("MemoryOverflow");
+        true
+      }
+    });
+    if let (true, Some(script)) = (restored, script) {
+      updater::index_script_hash(outpoint, script);
+    }
+  }
 
-          if index_block_hash == bitcoind_block_hash {
-            return Err(anyhow!(reorg::Error::Recoverable { height, depth }));
-          }
+  let events = crate::block_events(|b| b.remove(&height))
+    .map(|events| events.iter().map(|e| Event::load(*e)).collect::<Vec<_>>())
+    .unwrap_or_default();
+  for event in events.into_iter().rev() {
+    let rune_id = event.rune_id();
+    crate::rune_events(|r| {
+      let emptied = match r.get_mut(&rune_id) {
+        Some(mut pointers) => {
+          pointers.pop();
+          pointers.is_empty()
         }
-
-        Err(anyhow!(reorg::Error::Unrecoverable))
+        None => false,
+      };
+      if emptied {
+        r.remove(&rune_id);
+      }
+    });
+    match event {
+      Event::RuneTransferred { outpoint, .. } => {
+        crate::outpoint_to_rune_balances(|b| b.remove(&outpoint.store()));
+        updater::unindex_output(outpoint);
+      }
+      Event::RuneMinted { .. } => update_entry(rune_id, |entry| entry.mints -= 1),
+      Event::RuneBurned { amount, .. } => update_entry(rune_id, |entry| entry.burned -= amount),
+      Event::RuneEtched { txid, .. } => {
+        let Some(entry) = crate::rune_id_to_rune_entry(|r| r.remove(&rune_id)) else {
+          continue;
+        };
+        let rune = entry.spaced_rune.rune.store();
+        crate::rune_to_rune_id(|r| {
+          if r.get(&rune).is_some_and(|id| *id == rune_id) {
+            r.remove(&rune);
+          }
+        });
+        crate::transaction_id_to_rune(|t| t.remove(&txid.store()));
+        crate::rune_ids(|r| r.remove(&rune_id));
       }
-      _ => Ok(()),
     }
   }
 
-  pub(crate) fn handle_reorg(index: &Index, height: u32, depth: u32) -> Result {
-    log::info!("rolling back database after reorg of depth {depth} at height {height}");
+  crate::decrease_height(height);
+}
 
-    if let redb::Durability::None = index.durability {
-      panic!("set index durability to `Durability::Immediate` to test reorg handling");
-    }
+fn update_entry(id: RuneId, f: impl FnOnce(&mut RuneEntry)) {
+  let Some(mut entry) = crate::rune_id_to_rune_entry(|r| r.get(&id).map(|e| *e)) else {
+    return;
+  };
+  f(&mut entry);
+  crate::rune_id_to_rune_entry(|r| r.insert(id, entry)).expect("MemoryOverflow");
+}
 
-    let mut wtx = index.begin_write()?;
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::index::updater::{apply_block, BlockData};
+  use bitcoin::{block::Version, CompactTarget};
 
-    let oldest_savepoint =
-      wtx.get_persistent_savepoint(wtx.list_persistent_savepoints()?.min().unwrap())?;
+  const HEIGHT: u32 = 840_000;
 
-    wtx.restore_savepoint(&oldest_savepoint)?;
+  fn script(byte: u8) -> ScriptBuf {
+    ScriptBuf::from_bytes(vec![byte])
+  }
 
-    Index::increment_statistic(&wtx, Statistic::Commits, 1)?;
-    wtx.commit()?;
+  // a transaction paying `script`, after the runestone's OP_RETURN when there is one
+  fn transaction(
+    input: Vec<OutPoint>,
+    runestone: Option<Runestone>,
+    script: ScriptBuf,
+  ) -> Transaction {
+    let mut output = vec![];
+    if let Some(runestone) = runestone {
+      output.push(TxOut {
+        value: 0,
+        script_pubkey: runestone.encipher(),
+      });
+    }
+    output.push(TxOut {
+      value: 10_000,
+      script_pubkey: script,
+    });
+    Transaction {
+      version: 2,
+      lock_time: LockTime::ZERO,
+      input: input
+        .into_iter()
+        .map(|previous_output| TxIn {
+          previous_output,
+          script_sig: ScriptBuf::new(),
+          sequence: Sequence::MAX,
+          witness: Witness::new(),
+        })
+        .collect(),
+      output,
+    }
+  }
 
-    log::info!(
-      "successfully rolled back database to height {}",
-      index.begin_read()?.block_count()?
-    );
+  fn block(height: u32, txdata: Vec<Transaction>) -> BlockData {
+    BlockData {
+      header: Header {
+        version: Version::ONE,
+        prev_blockhash: BlockHash::all_zeros(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: 0,
+        bits: CompactTarget::from_consensus(0),
+        nonce: height,
+      },
+      txdata: txdata
+        .into_iter()
+        .map(|tx| {
+          let txid = tx.txid();
+          (tx, txid)
+        })
+        .collect(),
+    }
+  }
 
-    Ok(())
+  fn balances(outpoint: OutPoint) -> Option<Vec<RuneBalance>> {
+    crate::outpoint_to_rune_balances(|b| {
+      b.get(&outpoint.store())
+        .map(|balances| balances.iter().map(|balance| *balance).collect())
+    })
   }
 
-  pub(crate) fn update_savepoints(index: &Index, height: u32) -> Result {
-    if let redb::Durability::None = index.durability {
-      return Ok(());
+  fn outpoints(script: &ScriptBuf) -> Vec<OutPointValue> {
+    crate::script_to_outpoints(|s| {
+      s.get(&ScriptHashValue::of(script))
+        .map(|outpoints| outpoints.iter().map(|outpoint| outpoint.clone()).collect())
+        .unwrap_or_default()
+    })
+  }
+
+  #[test]
+  fn spent_balance() {
+    let outpoint = OutPoint {
+      txid: Txid::all_zeros(),
+      vout: 1,
+    }
+    .store();
+    let balance = RuneBalance {
+      id: RuneId { block: 2, tx: 3 },
+      balance: u128::MAX,
+    };
+    for script in [None, Some(ScriptHashValue::of(&script(0x51)))] {
+      let spent = SpentBalance {
+        outpoint: outpoint.clone(),
+        balance,
+        script,
+      };
+      let mut buf = [0; SpentBalance::SIZE];
+      spent.as_fixed_size_bytes(&mut buf);
+      assert_eq!(SpentBalance::from_fixed_size_bytes(&buf), spent);
     }
+  }
 
-    if (height < SAVEPOINT_INTERVAL || height % SAVEPOINT_INTERVAL == 0)
-      && u32::try_from(
-        index
-          .settings
-          .bitcoin_rpc_client(None)?
-          .get_blockchain_info()?
-          .headers,
-      )
-      .unwrap()
-      .saturating_sub(height)
-        <= CHAIN_TIP_DISTANCE
-    {
-      let wtx = index.begin_write()?;
-
-      let savepoints = wtx.list_persistent_savepoints()?.collect::<Vec<u64>>();
-
-      if savepoints.len() >= usize::try_from(MAX_SAVEPOINTS).unwrap() {
-        wtx.delete_persistent_savepoint(savepoints.into_iter().min().unwrap())?;
-      }
+  #[test]
+  fn rollback_etch_mint_and_transfer() {
+    crate::init_storage();
+    let id = RuneId {
+      block: HEIGHT.into(),
+      tx: 0,
+    };
+    let etching = transaction(
+      vec![OutPoint::null()],
+      Some(Runestone {
+        etching: Some(Etching {
+          premine: Some(1000),
+          terms: Some(Terms {
+            amount: Some(100),
+            cap: Some(10),
+            ..Default::default()
+          }),
+          ..Default::default()
+        }),
+        ..Default::default()
+      }),
+      script(0x51),
+    );
+    let mint = transaction(
+      vec![OutPoint::null()],
+      Some(Runestone {
+        mint: Some(id),
+        ..Default::default()
+      }),
+      script(0x51),
+    );
+    let etched = OutPoint {
+      txid: etching.txid(),
+      vout: 1,
+    };
+    let minted = OutPoint {
+      txid: mint.txid(),
+      vout: 1,
+    };
+    apply_block(HEIGHT, block(HEIGHT, vec![etching, mint])).unwrap();
 
-      Index::increment_statistic(&wtx, Statistic::Commits, 1)?;
-      wtx.commit()?;
+    let transfer = transaction(vec![etched, minted], None, script(0x52));
+    let transferred = OutPoint {
+      txid: transfer.txid(),
+      vout: 0,
+    };
+    apply_block(HEIGHT + 1, block(HEIGHT + 1, vec![transfer])).unwrap();
+    assert_eq!(balances(etched), None);
+    assert_eq!(
+      balances(transferred),
+      Some(vec![RuneBalance { id, balance: 1100 }])
+    );
+    assert!(can_rollback(HEIGHT + 1));
 
-      let wtx = index.begin_write()?;
+    rollback(HEIGHT + 1);
+    assert_eq!(
+      balances(etched),
+      Some(vec![RuneBalance { id, balance: 1000 }])
+    );
+    assert_eq!(
+      balances(minted),
+      Some(vec![RuneBalance { id, balance: 100 }])
+    );
+    assert_eq!(balances(transferred), None);
+    assert_eq!(
+      outpoints(&script(0x51)),
+      vec![etched.store(), minted.store()]
+    );
+    assert!(outpoints(&script(0x52)).is_empty());
+    assert!(crate::outpoint_to_spent_height(|s| s.is_empty()));
+    assert_eq!(
+      crate::rune_id_to_rune_entry(|r| r.get(&id).map(|entry| entry.mints)),
+      Some(1)
+    );
+    assert_eq!(crate::highest_block().0, HEIGHT);
+    assert!(!can_rollback(HEIGHT + 1));
 
-      log::debug!("creating savepoint at height {}", height);
-      wtx.persistent_savepoint()?;
+    rollback(HEIGHT);
+    assert_eq!(balances(etched), None);
+    assert_eq!(balances(minted), None);
+    assert!(outpoints(&script(0x51)).is_empty());
+    assert!(crate::rune_id_to_rune_entry(|r| r.is_empty()));
+    assert!(crate::rune_to_rune_id(|r| r.is_empty()));
+    assert!(crate::transaction_id_to_rune(|t| t.is_empty()));
+    assert!(crate::rune_ids(|r| r.is_empty()));
+    assert!(crate::rune_events(|r| r.is_empty()));
+    assert!(!can_rollback(HEIGHT));
+  }
 
-      Index::increment_statistic(&wtx, Statistic::Commits, 1)?;
-      wtx.commit()?;
+  #[test]
+  fn undo_data_is_pruned_past_max_reorg_depth() {
+    crate::init_storage();
+    for height in HEIGHT..=HEIGHT + MAX_REORG_DEPTH {
+      record_spent(height, vec![]);
     }
-
-    Ok(())
+    assert!(!can_rollback(HEIGHT));
+    assert!((HEIGHT + 1..=HEIGHT + MAX_REORG_DEPTH).all(can_rollback));
   }
 }
@@ -1,9 +1,15 @@
 mod rune_updater;
 
 use self::rune_updater::RuneUpdater;
-use crate::*;
+pub(super) use self::rune_updater::{allocate, Allocation};
+use super::reorg::{self, SpentBalance};
+use crate::{
+  index::entry::{Entry, OutPointValue, ScriptHashValue},
+  *,
+};
+use ic_stable_memory::collections::SVec;
 use rune_indexer_interface::OrdError;
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 pub(crate) struct BlockData {
   pub(crate) header: Header,
@@ -27,29 +33,113 @@ impl From<Block> for BlockData {
 }
 
 pub(crate) async fn index_block(height: u32, block: BlockData) -> Result<()> {
+  let hash = block.header.block_hash();
+  let rune_events_count = apply_block(height, block)?;
+  subscriber::notify_new_block(height, hash, rune_events_count);
+  Ok(())
+}
+
+// writes everything the block changed into the index, returns how many rune events it raised
+pub(super) fn apply_block(height: u32, block: BlockData) -> Result<u32> {
+  let events = Rc::new(RefCell::new(vec![]));
+  let recorded = events.clone();
   let mut updater = RuneUpdater {
     block_time: block.header.time,
     burned: HashMap::new(),
-    event_handler: None,
+    event_handler: Some(Box::new(move |event| recorded.borrow_mut().push(event))),
     height,
-    minimum: Rune::minimum_at_height(Network::Bitcoin, Height(height)),
+    minimum: Rune::minimum_at_height(crate::rune_network(), Height(height)),
   };
+  let mut spent = vec![];
   for (i, (tx, txid)) in block.txdata.iter().enumerate() {
+    for input in &tx.input {
+      let outpoint = input.previous_output.store();
+      let balances = outpoint_to_rune_balances(|b| {
+        b.get(&outpoint)
+          .map(|balances| balances.iter().map(|balance| *balance).collect::<Vec<_>>())
+      });
+      let script = outpoint_to_script(|o| o.get(&outpoint).map(|s| s.clone()));
+      if balances.is_some() {
+        outpoint_to_spent_height(|s| s.insert(outpoint.clone(), height)).expect("MemoryOverflow");
+      }
+      for balance in balances.unwrap_or_default() {
+        spent.push(SpentBalance {
+          outpoint: outpoint.clone(),
+          balance,
+          script: script.clone(),
+        });
+      }
+      unindex_output(input.previous_output);
+    }
     updater.index_runes(u32::try_from(i).unwrap(), tx, *txid)?;
+    for (vout, output) in tx.output.iter().enumerate() {
+      let outpoint = OutPoint {
+        txid: *txid,
+        vout: vout.try_into().unwrap(),
+      };
+      if outpoint_to_rune_balances(|b| b.contains_key(&outpoint.store())) {
+        index_output(outpoint, &output.script_pubkey);
+      }
+    }
   }
   updater.update()?;
+  let events = events.take();
+  let rune_events_count = u32::try_from(events.len()).unwrap();
+  index::event::record_block(height, events);
+  reorg::record_spent(height, spent);
   index::increase_height(height, block.header.block_hash());
-  Ok(())
+  Ok(rune_events_count)
+}
+
+// runic outputs by the script holding them, so balances can be looked up by address
+pub(crate) fn index_output(outpoint: OutPoint, script: &Script) {
+  index_script_hash(outpoint.store(), ScriptHashValue::of(script));
+}
+
+pub(super) fn index_script_hash(outpoint: OutPointValue, script: ScriptHashValue) {
+  script_to_outpoints(|s| match s.get_mut(&script) {
+    Some(mut outpoints) => outpoints.push(outpoint.clone()).expect("MemoryOverflow"),
+    None => {
+      let mut outpoints = SVec::new();
+      outpoints.push(outpoint.clone()).expect("MemoryOverflow");
+      s.insert(script.clone(), outpoints).expect("MemoryOverflow");
+    }
+  });
+  outpoint_to_script(|o| o.insert(outpoint, script)).expect("MemoryOverflow");
+}
+
+pub(super) fn unindex_output(outpoint: OutPoint) {
+  let outpoint = outpoint.store();
+  let Some(script) = outpoint_to_script(|o| o.remove(&outpoint)) else {
+    return;
+  };
+  script_to_outpoints(|s| {
+    let emptied = match s.get_mut(&script) {
+      Some(mut outpoints) => {
+        if let Some(index) = outpoints.iter().position(|o| *o == outpoint) {
+          outpoints.swap_remove(index);
+        }
+        outpoints.is_empty()
+      }
+      None => false,
+    };
+    if emptied {
+      s.remove(&script);
+    }
+  });
 }
 
-pub(crate) async fn get_block(height: u32) -> Result<BlockData> {
-  let url = get_url();
-  let hash = rpc::get_block_hash(&url, height).await?;
-  let block = rpc::get_block(&url, hash).await?;
-  block
-    .check_merkle_root()
-    .then(|| BlockData::from(block))
-    .ok_or(OrdError::BlockVerification(height))
+// the block along with the endpoint that served it
+pub(crate) async fn get_block(height: u32) -> Result<(String, BlockData)> {
+  rpc_pool::call(None, |url| async move {
+    let hash = rpc::get_block_hash(&url, height).await?;
+    let block = rpc::get_block(&url, hash).await?;
+    block
+      .check_merkle_root()
+      .then(|| BlockData::from(block))
+      .ok_or(OrdError::BlockVerification(height))
+  })
+  .await
 }
 
 // pub(crate) async fn get_raw_tx(txid: Txid) -> Result<GetRawTransactionResult> {
@@ -10,13 +10,166 @@ pub(super) struct RuneUpdater {
   pub(super) minimum: Rune,
 }
 
+// where the runes of a transaction end up, shared by indexing and transfer validation
+pub(crate) struct Allocation {
+  pub(crate) allocated: Vec<HashMap<RuneId, Lot>>,
+  pub(crate) burned: HashMap<RuneId, Lot>,
+  // OP_RETURN outputs that got runes allocated, their balances are part of `burned`
+  pub(crate) burned_outputs: Vec<usize>,
+}
+
+pub(crate) fn allocate(
+  tx: &Transaction,
+  artifact: Option<&Artifact>,
+  mut unallocated: HashMap<RuneId, Lot>,
+  etched: Option<RuneId>,
+) -> Allocation {
+  let mut allocated: Vec<HashMap<RuneId, Lot>> = vec![HashMap::new(); tx.output.len()];
+
+  if let Some(Artifact::Runestone(runestone)) = artifact {
+    if let Some(id) = etched {
+      *unallocated.entry(id).or_default() += runestone.etching.unwrap().premine.unwrap_or_default();
+    }
+
+    for Edict { id, amount, output } in runestone.edicts.iter().copied() {
+      let amount = Lot(amount);
+
+      // edicts with output values greater than the number of outputs
+      // should never be produced by the edict parser
+      let output = usize::try_from(output).unwrap();
+      assert!(output <= tx.output.len());
+
+      let id = if id == RuneId::default() {
+        let Some(id) = etched else {
+          continue;
+        };
+
+        id
+      } else {
+        id
+      };
+
+      let Some(balance) = unallocated.get_mut(&id) else {
+        continue;
+      };
+
+      let mut allocate = |balance: &mut Lot, amount: Lot, output: usize| {
+        if amount > 0 {
+          *balance -= amount;
+          *allocated[output].entry(id).or_default() += amount;
+        }
+      };
+
+      if output == tx.output.len() {
+        // find non-OP_RETURN outputs
+        let destinations = tx
+          .output
+          .iter()
+          .enumerate()
+          .filter_map(|(output, tx_out)| (!tx_out.script_pubkey.is_op_return()).then_some(output))
+          .collect::<Vec<usize>>();
+
+        if !destinations.is_empty() {
+          if amount == 0 {
+            // if amount is zero, divide balance between eligible outputs
+            let amount = *balance / destinations.len() as u128;
+            let remainder = usize::try_from(*balance % destinations.len() as u128).unwrap();
+
+            for (i, output) in destinations.iter().enumerate() {
+              allocate(
+                balance,
+                if i < remainder { amount + 1 } else { amount },
+                *output,
+              );
+            }
+          } else {
+            // if amount is non-zero, distribute amount to eligible outputs
+            for output in destinations {
+              allocate(balance, amount.min(*balance), output);
+            }
+          }
+        }
+      } else {
+        // Get the allocatable amount
+        let amount = if amount == 0 {
+          *balance
+        } else {
+          amount.min(*balance)
+        };
+
+        allocate(balance, amount, output);
+      }
+    }
+  }
+
+  let mut burned: HashMap<RuneId, Lot> = HashMap::new();
+
+  if let Some(Artifact::Cenotaph(_)) = artifact {
+    for (id, balance) in unallocated {
+      *burned.entry(id).or_default() += balance;
+    }
+  } else {
+    let pointer = artifact
+      .map(|artifact| match artifact {
+        Artifact::Runestone(runestone) => runestone.pointer,
+        Artifact::Cenotaph(_) => unreachable!(),
+      })
+      .unwrap_or_default();
+
+    // assign all un-allocated runes to the default output, or the first non
+    // OP_RETURN output if there is no default
+    if let Some(vout) = pointer
+      .map(|pointer| pointer as usize)
+      .inspect(|&pointer| assert!(pointer < allocated.len()))
+      .or_else(|| {
+        tx.output
+          .iter()
+          .enumerate()
+          .find(|(_vout, tx_out)| !tx_out.script_pubkey.is_op_return())
+          .map(|(vout, _tx_out)| vout)
+      })
+    {
+      for (id, balance) in unallocated {
+        if balance > 0 {
+          *allocated[vout].entry(id).or_default() += balance;
+        }
+      }
+    } else {
+      for (id, balance) in unallocated {
+        if balance > 0 {
+          *burned.entry(id).or_default() += balance;
+        }
+      }
+    }
+  }
+
+  let mut burned_outputs = vec![];
+
+  for (vout, balances) in allocated.iter_mut().enumerate() {
+    if balances.is_empty() || !tx.output[vout].script_pubkey.is_op_return() {
+      continue;
+    }
+
+    for (id, balance) in balances.drain() {
+      *burned.entry(id).or_default() += balance;
+    }
+    burned_outputs.push(vout);
+  }
+
+  Allocation {
+    allocated,
+    burned,
+    burned_outputs,
+  }
+}
+
 impl RuneUpdater {
   pub(super) fn index_runes(&mut self, tx_index: u32, tx: &Transaction, txid: Txid) -> Result<()> {
     let artifact = Runestone::decipher(tx);
 
     let mut unallocated = self.unallocated(tx)?;
 
-    let mut allocated: Vec<HashMap<RuneId, Lot>> = vec![HashMap::new(); tx.output.len()];
+    let mut etched = None;
 
     if let Some(artifact) = &artifact {
       if let Some(id) = artifact.mint() {
@@ -34,132 +187,16 @@ impl RuneUpdater {
         }
       }
 
-      let etched = self.etched(tx_index, tx, artifact)?;
-
-      if let Artifact::Runestone(runestone) = artifact {
-        if let Some((id, ..)) = etched {
-          *unallocated.entry(id).or_default() +=
-            runestone.etching.unwrap().premine.unwrap_or_default();
-        }
-
-        for Edict { id, amount, output } in runestone.edicts.iter().copied() {
-          let amount = Lot(amount);
-
-          // edicts with output values greater than the number of outputs
-          // should never be produced by the edict parser
-          let output = usize::try_from(output).unwrap();
-          assert!(output <= tx.output.len());
-
-          let id = if id == RuneId::default() {
-            let Some((id, ..)) = etched else {
-              continue;
-            };
-
-            id
-          } else {
-            id
-          };
-
-          let Some(balance) = unallocated.get_mut(&id) else {
-            continue;
-          };
-
-          let mut allocate = |balance: &mut Lot, amount: Lot, output: usize| {
-            if amount > 0 {
-              *balance -= amount;
-              *allocated[output].entry(id).or_default() += amount;
-            }
-          };
-
-          if output == tx.output.len() {
-            // find non-OP_RETURN outputs
-            let destinations = tx
-              .output
-              .iter()
-              .enumerate()
-              .filter_map(|(output, tx_out)| {
-                (!tx_out.script_pubkey.is_op_return()).then_some(output)
-              })
-              .collect::<Vec<usize>>();
-
-            if !destinations.is_empty() {
-              if amount == 0 {
-                // if amount is zero, divide balance between eligible outputs
-                let amount = *balance / destinations.len() as u128;
-                let remainder = usize::try_from(*balance % destinations.len() as u128).unwrap();
-
-                for (i, output) in destinations.iter().enumerate() {
-                  allocate(
-                    balance,
-                    if i < remainder { amount + 1 } else { amount },
-                    *output,
-                  );
-                }
-              } else {
-                // if amount is non-zero, distribute amount to eligible outputs
-                for output in destinations {
-                  allocate(balance, amount.min(*balance), output);
-                }
-              }
-            }
-          } else {
-            // Get the allocatable amount
-            let amount = if amount == 0 {
-              *balance
-            } else {
-              amount.min(*balance)
-            };
-
-            allocate(balance, amount, output);
-          }
-        }
-      }
+      etched = self.etched(tx_index, tx, artifact)?;
 
       if let Some((id, rune)) = etched {
         self.create_rune_entry(txid, artifact, id, rune)?;
       }
     }
 
-    let mut burned: HashMap<RuneId, Lot> = HashMap::new();
-
-    if let Some(Artifact::Cenotaph(_)) = artifact {
-      for (id, balance) in unallocated {
-        *burned.entry(id).or_default() += balance;
-      }
-    } else {
-      let pointer = artifact
-        .map(|artifact| match artifact {
-          Artifact::Runestone(runestone) => runestone.pointer,
-          Artifact::Cenotaph(_) => unreachable!(),
-        })
-        .unwrap_or_default();
-
-      // assign all un-allocated runes to the default output, or the first non
-      // OP_RETURN output if there is no default
-      if let Some(vout) = pointer
-        .map(|pointer| pointer as usize)
-        .inspect(|&pointer| assert!(pointer < allocated.len()))
-        .or_else(|| {
-          tx.output
-            .iter()
-            .enumerate()
-            .find(|(_vout, tx_out)| !tx_out.script_pubkey.is_op_return())
-            .map(|(vout, _tx_out)| vout)
-        })
-      {
-        for (id, balance) in unallocated {
-          if balance > 0 {
-            *allocated[vout].entry(id).or_default() += balance;
-          }
-        }
-      } else {
-        for (id, balance) in unallocated {
-          if balance > 0 {
-            *burned.entry(id).or_default() += balance;
-          }
-        }
-      }
-    }
+    let Allocation {
+      allocated, burned, ..
+    } = allocate(tx, artifact.as_ref(), unallocated, etched.map(|(id, _)| id));
 
     // update outpoint balances
     for (vout, balances) in allocated.into_iter().enumerate() {
@@ -167,14 +204,6 @@ impl RuneUpdater {
         continue;
       }
 
-      // increment burned balances
-      if tx.output[vout].script_pubkey.is_op_return() {
-        for (id, balance) in &balances {
-          *burned.entry(*id).or_default() += *balance;
-        }
-        continue;
-      }
-
       // let mut balances = balances.into_iter().collect::<Vec<(RuneId, Lot)>>();
 
       // Sort balances by id so tests can assert balances in a fixed order
@@ -240,7 +269,9 @@ impl RuneUpdater {
     id: RuneId,
     rune: Rune,
   ) -> Result<()> {
-    // crate::rune_to_rune_id(|r| r.insert(rune.store(), id)).expect("MemoryOverflow");
+    // `etched` turned away names taken already
+    crate::rune_to_rune_id(|r| r.insert(rune.store(), id)).expect("MemoryOverflow");
+    crate::rune_ids(|r| r.insert(id)).expect("MemoryOverflow");
     crate::transaction_id_to_rune(|t| t.insert(txid.store(), rune.0)).expect("MemoryOverflow");
 
     let entry = match artifact {
@@ -318,8 +349,9 @@ impl RuneUpdater {
     };
 
     let rune = if let Some(rune) = rune {
-      if rune < self.minimum || rune.is_reserved()
-      // || crate::rune_to_rune_id(|r| r.get(&rune.0).is_some())
+      if rune < self.minimum
+        || rune.is_reserved()
+        || crate::rune_to_rune_id(|r| r.contains_key(&rune.0))
       // || !Self::tx_commits_to_rune(tx, rune).await?
       {
         return Ok(None);
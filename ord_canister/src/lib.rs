@@ -1,12 +1,17 @@
 #[cfg(feature = "cmp-header")]
 mod btc_canister;
 mod canister;
+mod http;
 mod ic_log;
 mod index;
 mod rand_setup;
 mod rpc;
+mod rpc_pool;
+mod subscriber;
 
-use self::index::entry::{OutPointValue, TxidValue};
+use self::index::entry::{OutPointValue, ScriptHashValue, TxidValue};
+use self::index::event::{EventPointer, EventValue};
+use self::index::reorg::SpentBalance;
 pub use bitcoin::{
   address::{Address, NetworkUnchecked},
   block::Header,
@@ -20,9 +25,10 @@ pub use bitcoin::{
   script, Amount, Block, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
   Txid, Witness,
 };
+use candid::{CandidType, Deserialize, Principal};
 use core2::io::Cursor;
 use ic_stable_memory::{
-  collections::{SBTreeMap, SHashMap, SVec},
+  collections::{SBTreeMap, SBTreeSet, SHashMap, SVec},
   SBox,
 };
 pub use index::entry::{RuneBalance, RuneEntry};
@@ -39,10 +45,56 @@ thread_local! {
   static OUTPOINT_TO_RUNE_BALANCES: RefCell<Option<SHashMap<OutPointValue, SVec<RuneBalance>>>> = RefCell::new(None);
   static RUNE_ID_TO_RUNE_ENTRY: RefCell<Option<SHashMap<RuneId, RuneEntry>>> = RefCell::new(None);
   static RUNE_TO_RUNE_ID: RefCell<Option<SHashMap<u128, RuneId>>> = RefCell::new(None);
+  // the rune ids in etching order, for paging through the runes
+  static RUNE_IDS: RefCell<Option<SBTreeSet<RuneId>>> = RefCell::new(None);
   static TRANSACTION_ID_TO_RUNE: RefCell<Option<SHashMap<TxidValue, u128>>> = RefCell::new(None);
   static HEIGHT_TO_BLOCK_HASH: RefCell<Option<SBTreeMap<u32, [u8; 32]>>> = RefCell::new(None);
+  static SCRIPT_TO_OUTPOINTS: RefCell<Option<SHashMap<ScriptHashValue, SVec<OutPointValue>>>> = RefCell::new(None);
+  static OUTPOINT_TO_SCRIPT: RefCell<Option<SHashMap<OutPointValue, ScriptHashValue>>> = RefCell::new(None);
   static RPC_URL: RefCell<Option<SBox<String>>> = RefCell::new(None);
   static FIRST_BLOCK_HASH: RefCell<Option<SBox<String>>> = RefCell::new(None);
+  static BLOCK_EVENTS: RefCell<Option<SHashMap<u32, SVec<EventValue>>>> = RefCell::new(None);
+  static RUNE_EVENTS: RefCell<Option<SHashMap<RuneId, SVec<EventPointer>>>> = RefCell::new(None);
+  static HEIGHT_TO_SPENT_BALANCES: RefCell<Option<SHashMap<u32, SVec<SpentBalance>>>> = RefCell::new(None);
+  static OUTPOINT_TO_SPENT_HEIGHT: RefCell<Option<SHashMap<OutPointValue, u32>>> = RefCell::new(None);
+  static STOP_HEIGHT: RefCell<Option<u32>> = const { RefCell::new(None) };
+  static CHAIN: RefCell<Option<Chain>> = const { RefCell::new(None) };
+  static BACKUP_RPC_URLS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+  static BLOCK_SUBSCRIBERS: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
+  static CROSS_CHECK_FAIL_OPEN: RefCell<bool> = const { RefCell::new(false) };
+}
+
+// chain the rpc node follows, unset on canisters from before it could be picked
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+  Mainnet,
+  Testnet,
+  Testnet4,
+  Signet,
+  Regtest,
+}
+
+impl Chain {
+  fn code(self) -> u8 {
+    match self {
+      Chain::Mainnet => 0,
+      Chain::Testnet => 1,
+      Chain::Testnet4 => 2,
+      Chain::Signet => 3,
+      Chain::Regtest => 4,
+    }
+  }
+
+  fn from_code(code: u8) -> Option<Self> {
+    match code {
+      0 => Some(Chain::Mainnet),
+      1 => Some(Chain::Testnet),
+      2 => Some(Chain::Testnet4),
+      3 => Some(Chain::Signet),
+      4 => Some(Chain::Regtest),
+      _ => None,
+    }
+  }
 }
 
 pub const REQUIRED_CONFIRMATIONS: u32 = 1;
@@ -71,6 +123,41 @@ pub(crate) fn highest_block() -> (u32, BlockHash) {
   })
 }
 
+// a canister waiting for a state snapshot has no block yet
+pub(crate) fn is_bootstrapping() -> bool {
+  crate::HEIGHT_TO_BLOCK_HASH.with_borrow(|h| h.as_ref().expect("not initialized").is_empty())
+}
+
+// indexing halts once the height is reached, it follows the chain tip without one
+pub(crate) fn stop_height() -> Option<u32> {
+  crate::STOP_HEIGHT.with_borrow(|s| *s)
+}
+
+pub(crate) fn set_stop_height(height: Option<u32>) {
+  crate::STOP_HEIGHT.with_borrow_mut(|s| *s = height);
+}
+
+pub(crate) fn chain() -> Option<Chain> {
+  crate::CHAIN.with_borrow(|c| *c)
+}
+
+pub(crate) fn set_chain(chain: Option<Chain>) {
+  crate::CHAIN.with_borrow_mut(|c| *c = chain);
+}
+
+/*
+ * network whose first rune height applies. canisters without a chain keep indexing
+ * with mainnet's, runes are active from the genesis block on testnet4 as on signet
+*/
+pub(crate) fn rune_network() -> Network {
+  match chain() {
+    None | Some(Chain::Mainnet) => Network::Bitcoin,
+    Some(Chain::Testnet) => Network::Testnet,
+    Some(Chain::Testnet4 | Chain::Signet) => Network::Signet,
+    Some(Chain::Regtest) => Network::Regtest,
+  }
+}
+
 pub(crate) fn increase_height(height: u32, hash: BlockHash) {
   let mut buffer = Cursor::new([0; 32]);
   hash
@@ -85,6 +172,13 @@ pub(crate) fn increase_height(height: u32, hash: BlockHash) {
   });
 }
 
+// drops the tip block once it has been unwound
+pub(crate) fn decrease_height(height: u32) {
+  crate::HEIGHT_TO_BLOCK_HASH.with_borrow_mut(|h| {
+    h.as_mut().expect("not initialized").remove(&height);
+  });
+}
+
 pub(crate) fn init_storage() {
   ic_stable_memory::stable_memory_init();
   RPC_URL.with_borrow_mut(|r| r.replace(SBox::new("".to_string()).expect("MemoryOverflow")));
@@ -93,8 +187,15 @@ pub(crate) fn init_storage() {
   OUTPOINT_TO_RUNE_BALANCES.with_borrow_mut(|b| b.replace(SHashMap::new()));
   RUNE_ID_TO_RUNE_ENTRY.with_borrow_mut(|r| r.replace(SHashMap::new()));
   RUNE_TO_RUNE_ID.with_borrow_mut(|r| r.replace(SHashMap::new()));
+  RUNE_IDS.with_borrow_mut(|r| r.replace(SBTreeSet::new()));
   TRANSACTION_ID_TO_RUNE.with_borrow_mut(|t| t.replace(SHashMap::new()));
   HEIGHT_TO_BLOCK_HASH.with_borrow_mut(|h| h.replace(SBTreeMap::new()));
+  SCRIPT_TO_OUTPOINTS.with_borrow_mut(|s| s.replace(SHashMap::new()));
+  OUTPOINT_TO_SCRIPT.with_borrow_mut(|o| o.replace(SHashMap::new()));
+  BLOCK_EVENTS.with_borrow_mut(|b| b.replace(SHashMap::new()));
+  RUNE_EVENTS.with_borrow_mut(|r| r.replace(SHashMap::new()));
+  HEIGHT_TO_SPENT_BALANCES.with_borrow_mut(|h| h.replace(SHashMap::new()));
+  OUTPOINT_TO_SPENT_HEIGHT.with_borrow_mut(|s| s.replace(SHashMap::new()));
 }
 
 pub(crate) fn persistence() {
@@ -117,6 +218,12 @@ pub(crate) fn persistence() {
   let height_to_block_hash: SBTreeMap<u32, [u8; 32]> =
     HEIGHT_TO_BLOCK_HASH.with(|h| h.borrow_mut().take().unwrap());
   let boxed_height_to_block_hash = SBox::new(height_to_block_hash).expect("MemoryOverflow");
+  let script_to_outpoints: SHashMap<ScriptHashValue, SVec<OutPointValue>> =
+    SCRIPT_TO_OUTPOINTS.with(|s| s.borrow_mut().take().unwrap());
+  let boxed_script_to_outpoints = SBox::new(script_to_outpoints).expect("MemoryOverflow");
+  let outpoint_to_script: SHashMap<OutPointValue, ScriptHashValue> =
+    OUTPOINT_TO_SCRIPT.with(|o| o.borrow_mut().take().unwrap());
+  let boxed_outpoint_to_script = SBox::new(outpoint_to_script).expect("MemoryOverflow");
   ic_stable_memory::store_custom_data(0, boxed_rpc_url);
   ic_stable_memory::store_custom_data(1, boxed_outpoint_to_balances);
   ic_stable_memory::store_custom_data(2, boxed_rune_id_to_rune_entry);
@@ -124,6 +231,49 @@ pub(crate) fn persistence() {
   ic_stable_memory::store_custom_data(4, boxed_transaction_id_to_rune);
   ic_stable_memory::store_custom_data(5, boxed_height_to_block_hash);
   ic_stable_memory::store_custom_data(6, boxed_first_block_hash);
+  let block_events: SHashMap<u32, SVec<EventValue>> =
+    BLOCK_EVENTS.with(|b| b.borrow_mut().take().unwrap());
+  let boxed_block_events = SBox::new(block_events).expect("MemoryOverflow");
+  let rune_events: SHashMap<RuneId, SVec<EventPointer>> =
+    RUNE_EVENTS.with(|r| r.borrow_mut().take().unwrap());
+  let boxed_rune_events = SBox::new(rune_events).expect("MemoryOverflow");
+  let height_to_spent_balances: SHashMap<u32, SVec<SpentBalance>> =
+    HEIGHT_TO_SPENT_BALANCES.with(|h| h.borrow_mut().take().unwrap());
+  let boxed_height_to_spent_balances = SBox::new(height_to_spent_balances).expect("MemoryOverflow");
+  let outpoint_to_spent_height: SHashMap<OutPointValue, u32> =
+    OUTPOINT_TO_SPENT_HEIGHT.with(|s| s.borrow_mut().take().unwrap());
+  let boxed_outpoint_to_spent_height = SBox::new(outpoint_to_spent_height).expect("MemoryOverflow");
+  let rune_ids: SBTreeSet<RuneId> = RUNE_IDS.with(|r| r.borrow_mut().take().unwrap());
+  let boxed_rune_ids = SBox::new(rune_ids).expect("MemoryOverflow");
+  if let Some(stop_height) = STOP_HEIGHT.with_borrow(|s| *s) {
+    ic_stable_memory::store_custom_data(9, SBox::new(stop_height).expect("MemoryOverflow"));
+  }
+  if let Some(chain) = CHAIN.with_borrow(|c| *c) {
+    ic_stable_memory::store_custom_data(13, SBox::new(chain.code()).expect("MemoryOverflow"));
+  }
+  let backup_urls = BACKUP_RPC_URLS.with_borrow(|u| u.join("\n"));
+  if !backup_urls.is_empty() {
+    ic_stable_memory::store_custom_data(14, SBox::new(backup_urls).expect("MemoryOverflow"));
+  }
+  let subscribers = BLOCK_SUBSCRIBERS.with_borrow(|s| {
+    s.iter()
+      .map(|subscriber| subscriber.to_text())
+      .collect::<Vec<_>>()
+      .join("\n")
+  });
+  if !subscribers.is_empty() {
+    ic_stable_memory::store_custom_data(16, SBox::new(subscribers).expect("MemoryOverflow"));
+  }
+  if CROSS_CHECK_FAIL_OPEN.with_borrow(|f| *f) {
+    ic_stable_memory::store_custom_data(18, SBox::new(1u8).expect("MemoryOverflow"));
+  }
+  ic_stable_memory::store_custom_data(7, boxed_script_to_outpoints);
+  ic_stable_memory::store_custom_data(8, boxed_outpoint_to_script);
+  ic_stable_memory::store_custom_data(10, boxed_block_events);
+  ic_stable_memory::store_custom_data(11, boxed_rune_events);
+  ic_stable_memory::store_custom_data(12, boxed_height_to_spent_balances);
+  ic_stable_memory::store_custom_data(15, boxed_outpoint_to_spent_height);
+  ic_stable_memory::store_custom_data(17, boxed_rune_ids);
   ic_stable_memory::stable_memory_pre_upgrade().expect("MemoryOverflow");
 }
 
@@ -141,6 +291,15 @@ pub(crate) fn restore() {
     ic_stable_memory::retrieve_custom_data::<SHashMap<TxidValue, u128>>(4).unwrap();
   let height_to_block_hash =
     ic_stable_memory::retrieve_custom_data::<SBTreeMap<u32, [u8; 32]>>(5).unwrap();
+  // canisters upgraded from before the address index start it empty
+  let script_to_outpoints =
+    ic_stable_memory::retrieve_custom_data::<SHashMap<ScriptHashValue, SVec<OutPointValue>>>(7)
+      .map(|s| s.into_inner())
+      .unwrap_or_else(SHashMap::new);
+  let outpoint_to_script =
+    ic_stable_memory::retrieve_custom_data::<SHashMap<OutPointValue, ScriptHashValue>>(8)
+      .map(|o| o.into_inner())
+      .unwrap_or_else(SHashMap::new);
   RPC_URL.with_borrow_mut(|r| r.replace(rpc_url.into_inner()));
   FIRST_BLOCK_HASH.with_borrow_mut(|r| r.replace(first_block_hash.into_inner()));
   OUTPOINT_TO_RUNE_BALANCES.with_borrow_mut(|b| b.replace(outpoint_to_rune_balances.into_inner()));
@@ -148,6 +307,65 @@ pub(crate) fn restore() {
   RUNE_TO_RUNE_ID.with_borrow_mut(|r| r.replace(run_to_rune_id.into_inner()));
   TRANSACTION_ID_TO_RUNE.with_borrow_mut(|t| t.replace(transaction_id_to_rune.into_inner()));
   HEIGHT_TO_BLOCK_HASH.with_borrow_mut(|h| h.replace(height_to_block_hash.into_inner()));
+  let block_events = ic_stable_memory::retrieve_custom_data::<SHashMap<u32, SVec<EventValue>>>(10)
+    .map(|b| b.into_inner())
+    .unwrap_or_else(SHashMap::new);
+  let rune_events =
+    ic_stable_memory::retrieve_custom_data::<SHashMap<RuneId, SVec<EventPointer>>>(11)
+      .map(|r| r.into_inner())
+      .unwrap_or_else(SHashMap::new);
+  // without undo data the blocks indexed before the upgrade can't be unwound
+  let height_to_spent_balances =
+    ic_stable_memory::retrieve_custom_data::<SHashMap<u32, SVec<SpentBalance>>>(12)
+      .map(|h| h.into_inner())
+      .unwrap_or_else(SHashMap::new);
+  // outputs spent before the upgrade that added the spent index read as unknown
+  let outpoint_to_spent_height =
+    ic_stable_memory::retrieve_custom_data::<SHashMap<OutPointValue, u32>>(15)
+      .map(|s| s.into_inner())
+      .unwrap_or_else(SHashMap::new);
+  let stop_height = ic_stable_memory::retrieve_custom_data::<u32>(9).map(|s| s.into_inner());
+  STOP_HEIGHT.with_borrow_mut(|s| *s = stop_height);
+  let chain =
+    ic_stable_memory::retrieve_custom_data::<u8>(13).and_then(|c| Chain::from_code(c.into_inner()));
+  CHAIN.with_borrow_mut(|c| *c = chain);
+  // urls can't hold a line break, so the list is kept as lines
+  let backup_urls = ic_stable_memory::retrieve_custom_data::<String>(14)
+    .map(|u| u.into_inner().lines().map(String::from).collect())
+    .unwrap_or_default();
+  BACKUP_RPC_URLS.with_borrow_mut(|u| *u = backup_urls);
+  // kept as lines of principal text the same way
+  let subscribers = ic_stable_memory::retrieve_custom_data::<String>(16)
+    .map(|s| {
+      s.into_inner()
+        .lines()
+        .filter_map(|subscriber| Principal::from_text(subscriber).ok())
+        .collect()
+    })
+    .unwrap_or_default();
+  BLOCK_SUBSCRIBERS.with_borrow_mut(|s| *s = subscribers);
+  let fail_open =
+    ic_stable_memory::retrieve_custom_data::<u8>(18).is_some_and(|f| f.into_inner() == 1);
+  CROSS_CHECK_FAIL_OPEN.with_borrow_mut(|f| *f = fail_open);
+  SCRIPT_TO_OUTPOINTS.with_borrow_mut(|s| s.replace(script_to_outpoints));
+  OUTPOINT_TO_SCRIPT.with_borrow_mut(|o| o.replace(outpoint_to_script));
+  BLOCK_EVENTS.with_borrow_mut(|b| b.replace(block_events));
+  RUNE_EVENTS.with_borrow_mut(|r| r.replace(rune_events));
+  HEIGHT_TO_SPENT_BALANCES.with_borrow_mut(|h| h.replace(height_to_spent_balances));
+  OUTPOINT_TO_SPENT_HEIGHT.with_borrow_mut(|s| s.replace(outpoint_to_spent_height));
+  // canisters upgraded from before the ordered ids get them from the entries once
+  let rune_ids = ic_stable_memory::retrieve_custom_data::<SBTreeSet<RuneId>>(17)
+    .map(|r| r.into_inner())
+    .unwrap_or_else(|| {
+      let ids =
+        rune_id_to_rune_entry(|entries| entries.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+      let mut rune_ids = SBTreeSet::new();
+      for id in ids {
+        rune_ids.insert(id).expect("MemoryOverflow");
+      }
+      rune_ids
+    });
+  RUNE_IDS.with_borrow_mut(|r| r.replace(rune_ids));
 }
 
 pub(crate) fn get_url() -> String {
@@ -166,6 +384,38 @@ pub(crate) fn set_url(url: String) {
   });
 }
 
+// endpoints the rpc calls fail over to, in the order they are tried after the main one
+pub(crate) fn set_backup_urls(urls: Vec<String>) {
+  crate::BACKUP_RPC_URLS.with_borrow_mut(|u| *u = urls);
+}
+
+pub(crate) fn rpc_urls() -> Vec<String> {
+  let mut urls = vec![get_url()];
+  crate::BACKUP_RPC_URLS.with_borrow(|u| urls.extend(u.iter().cloned()));
+  urls
+}
+
+pub(crate) fn set_cross_check_fail_open(enabled: bool) {
+  crate::CROSS_CHECK_FAIL_OPEN.with_borrow_mut(|f| *f = enabled);
+}
+
+// whether a block no second provider answers for is taken on its source's word
+pub(crate) fn cross_check_fail_open() -> bool {
+  crate::CROSS_CHECK_FAIL_OPEN.with_borrow(|f| *f)
+}
+
+// canisters told about every block once it is indexed
+pub(crate) fn block_subscribers() -> Vec<Principal> {
+  crate::BLOCK_SUBSCRIBERS.with_borrow(|s| s.clone())
+}
+
+pub(crate) fn block_subscribers_mut<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut Vec<Principal>) -> R,
+{
+  crate::BLOCK_SUBSCRIBERS.with_borrow_mut(f)
+}
+
 #[allow(dead_code)]
 pub(crate) fn get_first_block_hash() -> String {
   crate::FIRST_BLOCK_HASH.with_borrow_mut(|r| {
@@ -204,9 +454,59 @@ where
   crate::RUNE_TO_RUNE_ID.with_borrow_mut(|r| f(r.as_mut().expect("not initialized")))
 }
 
+pub(crate) fn rune_ids<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SBTreeSet<RuneId>) -> R,
+{
+  crate::RUNE_IDS.with_borrow_mut(|r| f(r.as_mut().expect("not initialized")))
+}
+
 pub(crate) fn transaction_id_to_rune<F, R>(f: F) -> R
 where
   F: Fn(&mut SHashMap<TxidValue, u128>) -> R,
 {
   crate::TRANSACTION_ID_TO_RUNE.with_borrow_mut(|t| f(t.as_mut().expect("not initialized")))
 }
+
+pub(crate) fn script_to_outpoints<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SHashMap<ScriptHashValue, SVec<OutPointValue>>) -> R,
+{
+  crate::SCRIPT_TO_OUTPOINTS.with_borrow_mut(|s| f(s.as_mut().expect("not initialized")))
+}
+
+pub(crate) fn outpoint_to_script<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SHashMap<OutPointValue, ScriptHashValue>) -> R,
+{
+  crate::OUTPOINT_TO_SCRIPT.with_borrow_mut(|o| f(o.as_mut().expect("not initialized")))
+}
+
+pub(crate) fn block_events<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SHashMap<u32, SVec<EventValue>>) -> R,
+{
+  crate::BLOCK_EVENTS.with_borrow_mut(|b| f(b.as_mut().expect("not initialized")))
+}
+
+pub(crate) fn rune_events<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SHashMap<RuneId, SVec<EventPointer>>) -> R,
+{
+  crate::RUNE_EVENTS.with_borrow_mut(|r| f(r.as_mut().expect("not initialized")))
+}
+
+pub(crate) fn height_to_spent_balances<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SHashMap<u32, SVec<SpentBalance>>) -> R,
+{
+  crate::HEIGHT_TO_SPENT_BALANCES.with_borrow_mut(|h| f(h.as_mut().expect("not initialized")))
+}
+
+// runic outputs by the height of the block that spent them
+pub(crate) fn outpoint_to_spent_height<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut SHashMap<OutPointValue, u32>) -> R,
+{
+  crate::OUTPOINT_TO_SPENT_HEIGHT.with_borrow_mut(|s| f(s.as_mut().expect("not initialized")))
+}
@@ -0,0 +1,210 @@
+use crate::{ic_log::*, *};
+use candid::CandidType;
+use ic_canister_log::log;
+use rune_indexer_interface::{OrdError, RpcError};
+use std::cell::RefCell;
+use std::future::Future;
+
+// failed calls in a row after which an endpoint is skipped
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+// skipped endpoints get another chance after this long
+const RETRY_AFTER_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Clone)]
+pub struct RpcEndpointHealth {
+  pub url: String,
+  pub healthy: bool,
+  pub consecutive_failures: u32,
+  pub calls: u64,
+  // times the endpoint's block hash disagreed with another provider's
+  pub divergences: u64,
+  pub last_error: Option<String>,
+  pub unhealthy_since: Option<u64>,
+}
+
+thread_local! {
+  // kept on the heap, an upgrade starts every endpoint over as healthy
+  static HEALTH: RefCell<Vec<RpcEndpointHealth>> = const { RefCell::new(Vec::new()) };
+}
+
+// the configured endpoints in priority order, the tracked health follows the list
+fn with_health<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut Vec<RpcEndpointHealth>) -> R,
+{
+  let urls = crate::rpc_urls();
+  HEALTH.with_borrow_mut(|health| {
+    if health.iter().map(|endpoint| &endpoint.url).ne(urls.iter()) {
+      let mut previous = std::mem::take(health);
+      *health = urls
+        .into_iter()
+        .map(
+          |url| match previous.iter().position(|endpoint| endpoint.url == url) {
+            Some(index) => previous.swap_remove(index),
+            None => RpcEndpointHealth {
+              url,
+              healthy: true,
+              consecutive_failures: 0,
+              calls: 0,
+              divergences: 0,
+              last_error: None,
+              unhealthy_since: None,
+            },
+          },
+        )
+        .collect();
+    }
+    f(health)
+  })
+}
+
+pub(crate) fn health() -> Vec<RpcEndpointHealth> {
+  with_health(|health| health.clone())
+}
+
+// endpoints outside `exclude` to try in turn, unhealthy ones last unless their cooldown is over
+fn candidates(exclude: Option<&str>) -> Vec<String> {
+  let now = ic_cdk::api::time();
+  with_health(|health| {
+    let (available, skipped): (Vec<_>, Vec<_>) = health
+      .iter()
+      .filter(|endpoint| Some(endpoint.url.as_str()) != exclude)
+      .partition(|endpoint| {
+        endpoint.healthy
+          || endpoint
+            .unhealthy_since
+            .is_some_and(|since| now.saturating_sub(since) >= RETRY_AFTER_NANOS)
+      });
+    available
+      .into_iter()
+      .chain(skipped)
+      .map(|endpoint| endpoint.url.clone())
+      .collect()
+  })
+}
+
+fn update<F>(url: &str, f: F)
+where
+  F: FnOnce(&mut RpcEndpointHealth),
+{
+  with_health(|health| {
+    if let Some(endpoint) = health.iter_mut().find(|endpoint| endpoint.url == url) {
+      f(endpoint)
+    }
+  })
+}
+
+fn mark_unhealthy(endpoint: &mut RpcEndpointHealth) {
+  if endpoint.healthy {
+    endpoint.unhealthy_since = Some(ic_cdk::api::time());
+  }
+  endpoint.healthy = false;
+}
+
+fn record_success(url: &str) {
+  update(url, |endpoint| {
+    endpoint.calls += 1;
+    endpoint.consecutive_failures = 0;
+    endpoint.healthy = true;
+    endpoint.unhealthy_since = None;
+  })
+}
+
+fn record_failure(url: &str, error: &OrdError) {
+  log!(WARNING, "rpc endpoint {} failed: {:?}", url, error);
+  update(url, |endpoint| {
+    endpoint.calls += 1;
+    endpoint.consecutive_failures += 1;
+    endpoint.last_error = Some(format!("{:?}", error));
+    if endpoint.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+      mark_unhealthy(endpoint);
+    } else if !endpoint.healthy {
+      // a failed retry restarts the cooldown
+      endpoint.unhealthy_since = Some(ic_cdk::api::time());
+    }
+  })
+}
+
+fn record_divergence(url: &str, height: u32) {
+  update(url, |endpoint| {
+    endpoint.divergences += 1;
+    endpoint.last_error = Some(format!("block hash at {} diverged", height));
+  })
+}
+
+/*
+ * runs `request` against the endpoints in turn until one of them answers, along with
+ * the url that did. `exclude` keeps a provider out, for asking a second opinion
+ */
+pub(crate) async fn call<F, Fut, R>(exclude: Option<&str>, request: F) -> Result<(String, R)>
+where
+  F: Fn(String) -> Fut,
+  Fut: Future<Output = Result<R>>,
+{
+  let mut last_error = None;
+  for url in candidates(exclude) {
+    match request(url.clone()).await {
+      Ok(result) => {
+        record_success(&url);
+        return Ok((url, result));
+      }
+      Err(e) => {
+        record_failure(&url, &e);
+        last_error = Some(e);
+      }
+    }
+  }
+  Err(last_error.unwrap_or_else(|| {
+    OrdError::Rpc(RpcError::Io(
+      "rpc_pool".to_string(),
+      "call".to_string(),
+      "no rpc endpoint configured".to_string(),
+    ))
+  }))
+}
+
+/*
+ * asks a provider other than `source` for the hash at `height`, the block is only
+ * rejected when a second provider disagrees with it. a single configured endpoint is
+ * trusted as is. while none of the others answers the block waits for the next sync,
+ * unless the controllers opted into keeping `source`'s hash then
+ */
+pub(crate) async fn cross_check(height: u32, hash: &BlockHash, source: &str) -> Result<()> {
+  if crate::rpc_urls().len() < 2 {
+    return Ok(());
+  }
+  let answer = call(Some(source), |url| async move {
+    rpc::get_block_hash(&url, height).await
+  })
+  .await;
+  let (url, other) = match answer {
+    Ok(answer) => answer,
+    Err(e) if crate::cross_check_fail_open() => {
+      log!(
+        WARNING,
+        "no second opinion on the block at {}, keeping {}'s: {:?}",
+        height,
+        source,
+        e
+      );
+      return Ok(());
+    }
+    Err(e) => return Err(e),
+  };
+  if other == *hash {
+    return Ok(());
+  }
+  log!(
+    ERROR,
+    "block hash at {} diverged, {}={:x}, {}={:x}",
+    height,
+    source,
+    hash,
+    url,
+    other
+  );
+  record_divergence(source, height);
+  record_divergence(&url, height);
+  Err(OrdError::BlockVerification(height))
+}
@@ -0,0 +1,56 @@
+use crate::{ic_log::*, *};
+use candid::Principal;
+use ic_canister_log::log;
+
+// every notification costs cycles, so the list stays short
+pub const MAX_SUBSCRIBERS: usize = 16;
+
+pub const CALLBACK_METHOD: &str = "on_new_block";
+
+pub(crate) fn subscribe(canister_id: Principal) -> std::result::Result<(), String> {
+  if canister_id == Principal::anonymous() || canister_id == Principal::management_canister() {
+    return Err("subscribers must be canisters".to_string());
+  }
+  crate::block_subscribers_mut(|subscribers| {
+    if subscribers.contains(&canister_id) {
+      return Ok(());
+    }
+    if subscribers.len() >= MAX_SUBSCRIBERS {
+      return Err(format!("at most {} subscribers", MAX_SUBSCRIBERS));
+    }
+    subscribers.push(canister_id);
+    Ok(())
+  })
+}
+
+pub(crate) fn unsubscribe(canister_id: Principal) -> bool {
+  crate::block_subscribers_mut(|subscribers| {
+    let before = subscribers.len();
+    subscribers.retain(|subscriber| *subscriber != canister_id);
+    subscribers.len() != before
+  })
+}
+
+/*
+ * one-way `on_new_block(height, hash, rune_events_count)` calls to the subscribers.
+ * nothing waits on a reply, a subscriber that traps or is gone doesn't hold the
+ * indexing back, and it catches up with `get_height` on its own
+ */
+pub(crate) fn notify_new_block(height: u32, hash: BlockHash, rune_events_count: u32) {
+  let hash = hash.to_string();
+  for subscriber in crate::block_subscribers() {
+    if let Err(code) = ic_cdk::notify(
+      subscriber,
+      CALLBACK_METHOD,
+      (height, hash.clone(), rune_events_count),
+    ) {
+      log!(
+        WARNING,
+        "failed notifying {} of block {}: {:?}",
+        subscriber,
+        height,
+        code
+      );
+    }
+  }
+}
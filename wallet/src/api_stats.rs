@@ -0,0 +1,64 @@
+use std::future::Future;
+
+use candid::CandidType;
+
+use crate::{
+    state::{read_api_stats, write_api_stats},
+    types::WalletError,
+};
+
+#[derive(CandidType)]
+pub struct ApiStats {
+    pub endpoint: String,
+    pub calls: u64,
+    pub errors: Vec<(String, u64)>,
+    pub average_instructions: u64,
+    pub last_call: u64,
+}
+
+/*
+ * runs the endpoint's body and records the outcome.
+ * traps roll back the canister state, so only returned errors are counted
+*/
+pub async fn track<T, F>(endpoint: &str, call: F) -> Result<T, WalletError>
+where
+    F: Future<Output = Result<T, WalletError>>,
+{
+    let result = call.await;
+    record(endpoint, result.as_ref().err());
+    result
+}
+
+pub fn record(endpoint: &str, error: Option<&WalletError>) {
+    let instructions = ic_cdk::api::call_context_instruction_counter();
+    let now = ic_cdk::api::time();
+    write_api_stats(|map| {
+        let endpoint = String::from(endpoint);
+        let mut stats = map.get(&endpoint).unwrap_or_default();
+        stats.calls += 1;
+        stats.total_instructions += instructions as u128;
+        stats.last_call = now;
+        if let Some(error) = error {
+            *stats.errors.entry(error.label().to_string()).or_default() += 1;
+        }
+        map.insert(endpoint, stats);
+    });
+}
+
+pub fn get_api_stats() -> Vec<ApiStats> {
+    read_api_stats(|map| {
+        map.iter()
+            .map(|(endpoint, stats)| ApiStats {
+                endpoint,
+                calls: stats.calls,
+                average_instructions: if stats.calls == 0 {
+                    0
+                } else {
+                    (stats.total_instructions / stats.calls as u128) as u64
+                },
+                errors: stats.errors.into_iter().collect(),
+                last_call: stats.last_call,
+            })
+            .collect()
+    })
+}
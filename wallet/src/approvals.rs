@@ -0,0 +1,192 @@
+use candid::Principal;
+
+use crate::{
+    bitcoin, exchange_rate,
+    state::{
+        read_config, read_withdrawal_proposals, write_withdrawal_proposals, ApprovalPolicy,
+        ProposalStatus, WithdrawalProposal,
+    },
+    transaction_handler::SubmittedTransactionIdType,
+    types::{CoinSelection, FeePayer, TokenType, WalletError},
+    withdrawal_policy,
+};
+
+fn policy() -> Result<ApprovalPolicy, WalletError> {
+    read_config(|config| config.approval_policy.clone()).ok_or_else(|| {
+        WalletError::InvalidArgument(String::from("withdrawal approvals are disabled"))
+    })
+}
+
+// amounts above the approval threshold only leave through `propose_withdrawal`
+pub fn ensure_below_approval_threshold(amount: u64) -> Result<(), WalletError> {
+    match read_config(|config| config.approval_policy.clone()) {
+        Some(policy) if amount > policy.threshold => Err(WalletError::InvalidArgument(format!(
+            "amounts above {} sats need the approvers' quorum through propose_withdrawal",
+            policy.threshold
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// pending proposals past their expiry can't be approved anymore
+fn expire_stale() {
+    let now = ic_cdk::api::time();
+    write_withdrawal_proposals(|proposals| {
+        let expired: Vec<WithdrawalProposal> = proposals
+            .iter()
+            .map(|(_, proposal)| proposal)
+            .filter(|proposal| proposal.is_pending() && proposal.expires_at <= now)
+            .collect();
+        for mut proposal in expired {
+            proposal.status = ProposalStatus::Expired;
+            proposals.insert(proposal.id, proposal);
+        }
+    })
+}
+
+fn update<F>(id: u64, f: F) -> Option<WithdrawalProposal>
+where
+    F: FnOnce(&mut WithdrawalProposal),
+{
+    write_withdrawal_proposals(|proposals| {
+        let mut proposal = proposals.get(&id)?;
+        f(&mut proposal);
+        proposals.insert(id, proposal.clone());
+        Some(proposal)
+    })
+}
+
+pub fn get(id: u64) -> Result<WithdrawalProposal, WalletError> {
+    read_withdrawal_proposals(|proposals| proposals.get(&id))
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no such withdrawal proposal")))
+}
+
+pub fn is_approver(principal: &Principal) -> bool {
+    read_config(|config| {
+        config
+            .approval_policy
+            .as_ref()
+            .is_some_and(|policy| policy.approvers.contains(principal))
+    })
+}
+
+/*
+ * queues a withdrawal for the approvers. only the destination gets checked here, the
+ * balance and the daily and fiat limits are checked once the quorum is reached
+*/
+pub fn propose(
+    caller: Principal,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<WithdrawalProposal, WalletError> {
+    let policy = policy()?;
+    if amount == 0 {
+        return Err(WalletError::InvalidArgument(String::from(
+            "amount must be non-zero",
+        )));
+    }
+    withdrawal_policy::ensure_destination_allowed(&to)?;
+    expire_stale();
+    let now = ic_cdk::api::time();
+    let proposal = write_withdrawal_proposals(|proposals| {
+        let id = proposals.last_key_value().map_or(0, |(id, _)| id + 1);
+        let proposal = WithdrawalProposal {
+            id,
+            caller,
+            to,
+            amount,
+            fee_per_vbytes,
+            approvals: vec![],
+            created_at: now,
+            expires_at: now + policy.expiry_secs * 1_000_000_000,
+            status: ProposalStatus::Pending,
+        };
+        proposals.insert(id, proposal.clone());
+        proposal
+    });
+    Ok(proposal)
+}
+
+/*
+ * records the approver's sign-off and submits the withdrawal once the quorum is
+ * reached. approvals of principals dropped from the policy since don't count
+*/
+pub async fn approve(id: u64, approver: Principal) -> Result<WithdrawalProposal, WalletError> {
+    let policy = policy()?;
+    if !policy.approvers.contains(&approver) {
+        return Err(WalletError::Unauthorized);
+    }
+    expire_stale();
+    let proposal = get(id)?;
+    if !proposal.is_pending() {
+        return Err(WalletError::InvalidArgument(String::from(
+            "withdrawal proposal is no longer pending",
+        )));
+    }
+    let proposal = update(id, |proposal| {
+        if !proposal.approvals.contains(&approver) {
+            proposal.approvals.push(approver);
+        }
+        let approvals = proposal
+            .approvals
+            .iter()
+            .filter(|approval| policy.approvers.contains(approval))
+            .count();
+        if approvals >= policy.quorum as usize {
+            proposal.status = ProposalStatus::Approved;
+        }
+    })
+    .ok_or_else(|| WalletError::InvalidArgument(String::from("no such withdrawal proposal")))?;
+    if !matches!(proposal.status, ProposalStatus::Approved) {
+        return Ok(proposal);
+    }
+    let status = match execute(&proposal).await {
+        Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => ProposalStatus::Executed { txid },
+        Err(err) => ProposalStatus::Failed {
+            reason: format!("{:?}", err),
+        },
+    };
+    update(id, |proposal| proposal.status = status)
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no such withdrawal proposal")))
+}
+
+// the checks of a plain withdrawal, on behalf of the proposer
+async fn execute(proposal: &WithdrawalProposal) -> Result<SubmittedTransactionIdType, WalletError> {
+    let amount = proposal.amount;
+    bitcoin::address_validation(&proposal.to).map_err(WalletError::InvalidAddress)?;
+    withdrawal_policy::ensure_destination_allowed(&proposal.to)?;
+    let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+    crate::ensure_unallocated(proposal.caller, amount).await?;
+    let charge = withdrawal_policy::charge(proposal.caller, TokenType::Bitcoin, amount as u128)?;
+    let txn = crate::bitcoin_withdrawal(
+        proposal.caller,
+        proposal.to.clone(),
+        amount,
+        proposal.fee_per_vbytes,
+        FeePayer::Sender,
+        CoinSelection::default(),
+        None,
+        None,
+    )
+    .await?;
+    let submitted = txn.build_and_submit(None).await;
+    charge.settle_if(&submitted);
+    exchange_rate::record_rate(&submitted, rate);
+    submitted
+}
+
+// withdraws a proposal before it reaches its quorum
+pub fn cancel(id: u64, caller: Principal) -> Result<(), WalletError> {
+    let proposal = get(id)?;
+    if proposal.caller != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    if !proposal.is_pending() {
+        return Err(WalletError::InvalidArgument(String::from(
+            "withdrawal proposal is no longer pending",
+        )));
+    }
+    update(id, |proposal| proposal.status = ProposalStatus::Cancelled);
+    Ok(())
+}
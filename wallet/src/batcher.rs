@@ -0,0 +1,256 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin::{
+        self,
+        batch_txn::{self, BatchPayout},
+        get_fee_per_vbyte, Branch,
+    },
+    state::{
+        read_batched_withdrawals, read_config, read_utxo_manager, write_batched_withdrawals,
+        BatchedWithdrawal, BatchedWithdrawalStatus,
+    },
+    transaction_handler::SubmittedTransactionIdType,
+    types::WalletError,
+    updater,
+    utils::{generate_addresses_from_principal, generate_change_addresses_from_principal},
+};
+
+thread_local! {
+    static FLUSH_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static FLUSHING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped flush doesn't block the following ones
+struct FlushGuard;
+
+impl FlushGuard {
+    fn acquire() -> Option<Self> {
+        if FLUSHING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        FLUSHING.set(false);
+    }
+}
+
+// timers don't survive upgrades, picks up requests queued before one
+pub fn start_batching() {
+    FLUSH_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+    });
+    if let Some(policy) = read_config(|config| config.batching_policy.clone()) {
+        if queued_count() > 0 {
+            schedule_flush(Duration::from_secs(policy.window_secs));
+        }
+    }
+}
+
+fn schedule_flush(delay: Duration) {
+    FLUSH_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer(delay, || {
+            FLUSH_TIMER.with_borrow_mut(|timer| timer.take());
+            ic_cdk::spawn(flush())
+        }));
+    });
+}
+
+fn queued_count() -> u64 {
+    read_batched_withdrawals(|withdrawals| {
+        withdrawals
+            .iter()
+            .filter(|(_, withdrawal)| withdrawal.is_queued())
+            .count() as u64
+    })
+}
+
+/*
+ * queues a withdrawal for the next batch and returns its request id.
+ * the first request of a batch opens the window, reaching the request limit
+ * flushes the batch right away
+*/
+pub async fn enqueue(caller: Principal, to: String, amount: u64) -> Result<u64, WalletError> {
+    let policy = read_config(|config| config.batching_policy.clone()).ok_or_else(|| {
+        WalletError::InvalidArgument(String::from("withdrawal batching is disabled"))
+    })?;
+    bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    let addresses = generate_addresses_from_principal(&caller);
+    let change_addresses = generate_change_addresses_from_principal(&caller);
+    let balance = || {
+        read_utxo_manager(|manager| {
+            manager.get_bitcoin_balance(&addresses.bitcoin)
+                + manager.get_bitcoin_balance(&change_addresses.bitcoin)
+        })
+    };
+    if balance() < amount {
+        updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, amount)
+            .await;
+        if balance() < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+    }
+    let id = write_batched_withdrawals(|withdrawals| {
+        let id = withdrawals.last_key_value().map_or(0, |(id, _)| id + 1);
+        withdrawals.insert(
+            id,
+            BatchedWithdrawal {
+                id,
+                caller,
+                to,
+                amount,
+                queued_at: ic_cdk::api::time(),
+                status: BatchedWithdrawalStatus::Queued,
+            },
+        );
+        id
+    });
+    let window_open = FLUSH_TIMER.with_borrow(|timer| timer.is_some());
+    if queued_count() >= policy.max_requests {
+        schedule_flush(Duration::ZERO);
+    } else if !window_open {
+        schedule_flush(Duration::from_secs(policy.window_secs));
+    }
+    Ok(id)
+}
+
+fn set_status(id: u64, status: BatchedWithdrawalStatus) {
+    write_batched_withdrawals(|withdrawals| {
+        if let Some(mut withdrawal) = withdrawals.get(&id) {
+            withdrawal.status = status;
+            withdrawals.insert(id, withdrawal);
+        }
+    })
+}
+
+/*
+ * pays out up to `max_requests` queued withdrawals in a single transaction.
+ * requests left in the queue open the next window
+*/
+pub async fn flush() {
+    let Some(policy) = read_config(|config| config.batching_policy.clone()) else {
+        return;
+    };
+    let Some(_guard) = FlushGuard::acquire() else {
+        return;
+    };
+    let requests: Vec<BatchedWithdrawal> = read_batched_withdrawals(|withdrawals| {
+        withdrawals
+            .iter()
+            .map(|(_, withdrawal)| withdrawal)
+            .filter(BatchedWithdrawal::is_queued)
+            .take(policy.max_requests as usize)
+            .collect()
+    });
+    if !requests.is_empty() {
+        pay_out(requests).await;
+    }
+    if queued_count() > 0 {
+        schedule_flush(Duration::from_secs(policy.window_secs));
+    }
+}
+
+async fn pay_out(requests: Vec<BatchedWithdrawal>) {
+    let fee_per_vbytes = get_fee_per_vbyte().await;
+    let mut requesters = vec![];
+    for request in requests {
+        let addresses = generate_addresses_from_principal(&request.caller);
+        let change_addresses = generate_change_addresses_from_principal(&request.caller);
+        let parsed = bitcoin::address_validation(&request.to).and_then(|to| {
+            let from = bitcoin::address_validation(&addresses.bitcoin)?;
+            let change = bitcoin::address_validation(&change_addresses.bitcoin)?;
+            Ok((to, from, change))
+        });
+        let (to, from, change) = match parsed {
+            Ok(parsed) => parsed,
+            Err(reason) => {
+                set_status(request.id, BatchedWithdrawalStatus::Failed { reason });
+                continue;
+            }
+        };
+        let balance = read_utxo_manager(|manager| {
+            manager.get_bitcoin_balance(&addresses.bitcoin)
+                + manager.get_bitcoin_balance(&change_addresses.bitcoin)
+        });
+        if balance < request.amount {
+            updater::fetch_bitcoin_branches(
+                &addresses.bitcoin,
+                &change_addresses.bitcoin,
+                request.amount,
+            )
+            .await;
+        }
+        requesters.push((request, addresses, change_addresses, to, from, change));
+    }
+    let payouts = requesters
+        .iter()
+        .map(
+            |(request, addresses, change_addresses, to, from, change)| BatchPayout {
+                request_id: request.id,
+                receive: Branch {
+                    addr: &addresses.bitcoin,
+                    account: addresses.icrc1,
+                    address: from.clone(),
+                },
+                change: Branch {
+                    addr: &change_addresses.bitcoin,
+                    account: change_addresses.icrc1,
+                    address: change.clone(),
+                },
+                to: to.clone(),
+                amount: request.amount,
+            },
+        )
+        .collect();
+    let batch = batch_txn::build(payouts, fee_per_vbytes);
+    for id in batch.rejected {
+        set_status(
+            id,
+            BatchedWithdrawalStatus::Failed {
+                reason: String::from("insufficient balance"),
+            },
+        );
+    }
+    let Some(txn) = batch.txn else {
+        return;
+    };
+    match txn.build_and_submit(None).await {
+        Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => {
+            for (vout, id) in batch.included.into_iter().enumerate() {
+                set_status(
+                    id,
+                    BatchedWithdrawalStatus::Broadcast {
+                        txid: txid.clone(),
+                        vout: vout as u32,
+                    },
+                );
+            }
+        }
+        Err(err) => {
+            for id in batch.included {
+                set_status(
+                    id,
+                    BatchedWithdrawalStatus::Failed {
+                        reason: format!("{:?}", err),
+                    },
+                );
+            }
+        }
+    }
+}
@@ -2,39 +2,65 @@
 #![allow(clippy::type_complexity)]
 
 mod address;
+pub mod batch_txn;
 pub mod combined_txn;
+pub mod cpfp;
+pub mod message;
 pub mod multi_sender_txn;
+pub mod multisig;
+mod planner;
 pub mod runestone;
 mod signer;
 mod transaction;
 mod utils;
+mod verifier;
 
 pub use address::*;
-use ic_cdk::api::management_canister::bitcoin::{
-    bitcoin_get_current_fee_percentiles, GetCurrentFeePercentilesRequest,
+use ic_cdk::api::management_canister::bitcoin::GetCurrentFeePercentilesRequest;
+pub use planner::{Intent, PlanError, TxPlanner};
+pub use signer::{ecdsa_sign, mock_ecdsa_signature, mock_schnorr_signature, schnorr_sign};
+pub use transaction::{
+    transfer, transfer_all, BitcoinSweepArgs, BitcoinTransferArgs, Branch, DUST_THRESHOLD,
+    MAX_CHANGE_OUTPUTS,
 };
-pub use signer::ecdsa_sign;
-pub use transaction::transfer;
 pub use utils::*;
+pub use verifier::verify_signatures;
 
-use crate::state::read_config;
+use crate::{bitcoin_api::bitcoin_get_current_fee_percentiles, fee_tracker, state::read_config};
 
+/*
+ * outgoing transactions stay within the standard transaction size, well below the
+ * bitcoin canister's 400kb send limit, and within a bounded number of signing calls
+*/
+pub const MAX_TX_INPUTS: usize = 250;
+pub const MAX_TX_VSIZE: u64 = 100_000;
+
+// vsize of a signed p2pkh input, the script_sig carries a der signature and a compressed key
+pub const P2PKH_INPUT_VSIZE: u64 = 149;
+
+// data an OP_RETURN output may carry and still be relayed by default
+pub const MAX_OP_RETURN_SIZE: usize = 80;
+
+// the medium suggestion while the fee history is fresh, the current median otherwise
 pub async fn get_fee_per_vbyte() -> u64 {
-    let network = read_config(|config| config.bitcoin_network());
+    if let Some(fee) = fee_tracker::default_fee() {
+        return fee;
+    }
+    let (network, chain) = read_config(|config| (config.bitcoin_network(), config.chain()));
     // Get fee percentiles from previous transactions to estimate our own fee.
     let fee_percentiles =
         bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest { network })
             .await
             .unwrap()
             .0;
+    fee_tracker::record_percentiles(&fee_percentiles);
 
     if fee_percentiles.is_empty() {
-        // There are no fee percentiles. This case can only happen on a regtest
-        // network where there are no non-coinbase transactions. In this case,
-        // we use a default of 2000 millisatoshis/byte (i.e. 2 satoshi/byte)
-        2000
+        // There are no fee percentiles. This case happens on quiet test chains
+        // where there are no recent non-coinbase transactions.
+        chain.fallback_fee_per_vbyte()
     } else {
         // Choose the 50th percentile for sending fees.
-        fee_percentiles[50]
+        fee_tracker::clamp_to_bounds(fee_percentiles[50])
     }
 }
@@ -1,46 +1,75 @@
-use bitcoin::{address::NetworkUnchecked, Address};
+use bitcoin::{
+    address::NetworkUnchecked, key::TweakedPublicKey, secp256k1::XOnlyPublicKey, Address,
+    AddressType,
+};
 use icrc_ledger_types::icrc1::account::Account;
 
-use crate::{bitcoin::utils::derive_public_key, state::read_config};
+use crate::{
+    bitcoin::utils::derive_public_key,
+    state::{read_config, Chain, DerivationScheme},
+};
 
 use bitcoin::Network;
 use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork as IcBitcoinNetwork;
 
-use super::utils::{account_to_derivation_path, ripemd160, sha256};
+use super::utils::{account_to_derivation_path, derivation_path, ripemd160, sha256};
 
-pub fn address_validation(addr: &str) -> Result<Address, String> {
-    read_config(|config| {
-        let bitcoin_network = match config.bitcoin_network() {
-            IcBitcoinNetwork::Mainnet => Network::Bitcoin,
-            IcBitcoinNetwork::Testnet => Network::Testnet,
-            IcBitcoinNetwork::Regtest => Network::Regtest,
-        };
-        let parsed_addr: Address<NetworkUnchecked> = match addr.parse() {
-            Err(_e) => return Err(String::from("failed to parse into bitcoin address")),
-            Ok(addr) => addr,
-        };
-        if !parsed_addr.is_valid_for_network(bitcoin_network) {
-            let msg = format!(
-                "Invalid Address.\n{} isn't valid for {:?} network",
-                addr, bitcoin_network
-            );
-            return Err(msg);
-        }
-        match parsed_addr.require_network(bitcoin_network) {
-            Ok(addr) => Ok(addr),
-            Err(_) => Err(String::from("Failed to validate with network")),
-        }
+// testnet4 addresses are encoded as testnet3 ones
+pub fn bitcoin_network() -> Network {
+    read_config(|config| match config.chain() {
+        Chain::Mainnet => Network::Bitcoin,
+        Chain::Testnet | Chain::Testnet4 => Network::Testnet,
+        Chain::Signet => Network::Signet,
+        Chain::Regtest => Network::Regtest,
     })
 }
 
+pub fn address_validation(addr: &str) -> Result<Address, String> {
+    let bitcoin_network = bitcoin_network();
+    let parsed_addr: Address<NetworkUnchecked> = match addr.parse() {
+        Err(_e) => return Err(String::from("failed to parse into bitcoin address")),
+        Ok(addr) => addr,
+    };
+    if !parsed_addr.is_valid_for_network(bitcoin_network) {
+        let msg = format!(
+            "Invalid Address.\n{} isn't valid for {:?} network",
+            addr, bitcoin_network
+        );
+        return Err(msg);
+    }
+    match parsed_addr.require_network(bitcoin_network) {
+        Ok(addr) => Ok(addr),
+        Err(_) => Err(String::from("Failed to validate with network")),
+    }
+}
+
+/*
+ * script types whose wallets are expected to track runes. script hashes commonly
+ * wrap plain payment wallets and unknown witness versions may be unspendable,
+ * runes sent there are usually lost
+*/
+pub fn rune_receiver_validation(address: &Address) -> Result<(), String> {
+    match address.address_type() {
+        Some(AddressType::P2pkh | AddressType::P2wpkh | AddressType::P2tr) => Ok(()),
+        Some(address_type) => Err(address_type.to_string()),
+        None => Err(String::from("unknown")),
+    }
+}
+
 pub fn account_to_p2pkh_address(account: &Account) -> String {
+    let scheme = read_config(|config| config.derivation_scheme());
+    account_to_p2pkh_address_with(account, scheme)
+}
+
+// the account's address under `scheme`, whichever scheme the canister runs on
+pub fn account_to_p2pkh_address_with(account: &Account, scheme: DerivationScheme) -> String {
     read_config(|config| {
         let prefix = match config.bitcoin_network() {
             IcBitcoinNetwork::Mainnet => 0x00,
             _ => 0x6f, // Regtest | Testnet
         };
         let ecdsa_public_key = config.ecdsa_public_key();
-        let path = account_to_derivation_path(account);
+        let path = derivation_path(account, scheme);
         let derived_public_key = derive_public_key(&ecdsa_public_key, &path).public_key;
         let ripemd_pk = ripemd160(&sha256(&derived_public_key));
         let mut raw_address = vec![prefix];
@@ -50,3 +79,24 @@ pub fn account_to_p2pkh_address(account: &Account) -> String {
         bs58::encode(raw_address).into_string()
     })
 }
+
+/*
+ * the ic signs bip340 with the derived key as is, so the derived key is used as the
+ * output key directly instead of being tweaked. key path spends stay valid, the
+ * address just doesn't commit to an (empty) script tree
+*/
+pub fn account_to_p2tr_address(account: &Account) -> String {
+    let network = bitcoin_network();
+    read_config(|config| {
+        let schnorr_public_key = config.schnorr_public_key();
+        let path = account_to_derivation_path(account);
+        let derived_public_key = derive_public_key(&schnorr_public_key, &path).public_key;
+        let output_key = XOnlyPublicKey::from_slice(&derived_public_key[1..])
+            .expect("derived key should be valid");
+        Address::p2tr_tweaked(
+            TweakedPublicKey::dangerous_assume_tweaked(output_key),
+            network,
+        )
+        .to_string()
+    })
+}
@@ -0,0 +1,199 @@
+use bitcoin::{Address, Amount, Transaction, TxOut};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+
+use crate::{
+    bitcoin::{
+        planner::{change_output, fee_at, settle_fee_by, tx_inputs, unsigned_transaction},
+        Branch,
+    },
+    state::write_utxo_manager,
+    transaction_handler::{BatchSender, TransactionType},
+};
+
+pub struct BatchPayout<'a> {
+    pub request_id: u64,
+    pub receive: Branch<'a>,
+    pub change: Branch<'a>,
+    pub to: Address,
+    pub amount: u64,
+}
+
+pub struct Batch {
+    pub txn: Option<TransactionType>,
+    // ids of the paid requests, output `i` pays `included[i]`
+    pub included: Vec<u64>,
+    // ids of the requests whose custody couldn't cover the payout and its fee share
+    pub rejected: Vec<u64>,
+}
+
+// utxos picked for one payout, receive branch first
+struct Selection {
+    utxos: Vec<Utxo>,
+    change_utxos: Vec<Utxo>,
+    total: u64,
+}
+
+/*
+ * merges the payouts into a single transaction. the fee is split evenly between
+ * the requests and every requester gets its change back on its own change address.
+ * requests that can't be covered are dropped from the batch instead of failing it
+*/
+pub fn build(mut payouts: Vec<BatchPayout>, fee_per_vbytes: u64) -> Batch {
+    let mut rejected = vec![];
+    loop {
+        if payouts.is_empty() {
+            return Batch {
+                txn: None,
+                included: vec![],
+                rejected,
+            };
+        }
+        let count = payouts.len() as u64;
+        let settled = settle_fee_by(
+            |txn| fee_at(txn, fee_per_vbytes).div_ceil(count),
+            |fee_share| {
+                let selections = select_all(&payouts, fee_share)?;
+                let txn = build_transaction(&payouts, &selections, fee_share);
+                Ok((txn, selections))
+            },
+            |selections| {
+                for (payout, selection) in payouts.iter().zip(selections) {
+                    release(payout, selection);
+                }
+            },
+        );
+        let (txn, selections) = match settled {
+            Ok((txn, selections, _)) => (txn, selections),
+            Err(uncovered) => {
+                payouts.retain(|payout| !uncovered.contains(&payout.request_id));
+                rejected.extend(uncovered);
+                continue;
+            }
+        };
+        let mut senders = vec![];
+        for (payout, selection) in payouts.iter().zip(selections) {
+            if !selection.utxos.is_empty() {
+                senders.push(BatchSender {
+                    addr: payout.receive.addr.to_string(),
+                    account: payout.receive.account,
+                    address: payout.receive.address.clone(),
+                    utxos: selection.utxos,
+                });
+            }
+            if !selection.change_utxos.is_empty() {
+                senders.push(BatchSender {
+                    addr: payout.change.addr.to_string(),
+                    account: payout.change.account,
+                    address: payout.change.address.clone(),
+                    utxos: selection.change_utxos,
+                });
+            }
+        }
+        return Batch {
+            included: payouts.iter().map(|payout| payout.request_id).collect(),
+            txn: Some(TransactionType::Batch {
+                senders,
+                payouts: payouts.len(),
+                txn,
+            }),
+            rejected,
+        };
+    }
+}
+
+// utxos of every payout with its fee share, or the ids of those that fall short
+fn select_all(payouts: &[BatchPayout], fee_share: u64) -> Result<Vec<Selection>, Vec<u64>> {
+    let selections: Vec<Option<Selection>> = payouts
+        .iter()
+        .map(|payout| select_utxos(payout, payout.amount + fee_share))
+        .collect();
+    if selections.iter().all(Option::is_some) {
+        return Ok(selections.into_iter().flatten().collect());
+    }
+    let mut uncovered = vec![];
+    for (payout, selection) in payouts.iter().zip(selections) {
+        match selection {
+            Some(selection) => release(payout, selection),
+            None => uncovered.push(payout.request_id),
+        }
+    }
+    Err(uncovered)
+}
+
+fn select_utxos(payout: &BatchPayout, total_amount: u64) -> Option<Selection> {
+    write_utxo_manager(|manager| {
+        let mut utxos = vec![];
+        let mut change_utxos = vec![];
+        let mut sum = 0;
+
+        while sum <= total_amount {
+            match manager.get_bitcoin_utxo(payout.receive.addr) {
+                Some(utxo) => {
+                    sum += utxo.value;
+                    utxos.push(utxo);
+                }
+                None => break,
+            }
+        }
+        while sum <= total_amount {
+            match manager.get_bitcoin_utxo(payout.change.addr) {
+                Some(utxo) => {
+                    sum += utxo.value;
+                    change_utxos.push(utxo);
+                }
+                None => break,
+            }
+        }
+        if sum < total_amount {
+            manager.record_btc_utxos(payout.receive.addr, utxos);
+            manager.record_btc_utxos(payout.change.addr, change_utxos);
+            return None;
+        }
+        Some(Selection {
+            utxos,
+            change_utxos,
+            total: sum,
+        })
+    })
+}
+
+fn release(payout: &BatchPayout, selection: Selection) {
+    write_utxo_manager(|manager| {
+        manager.record_btc_utxos(payout.receive.addr, selection.utxos);
+        manager.record_btc_utxos(payout.change.addr, selection.change_utxos);
+    })
+}
+
+// payouts come first in request order, the change outputs follow
+fn build_transaction(
+    payouts: &[BatchPayout],
+    selections: &[Selection],
+    fee_share: u64,
+) -> Transaction {
+    let input = tx_inputs(
+        selections
+            .iter()
+            .flat_map(|selection| selection.utxos.iter().chain(selection.change_utxos.iter())),
+    );
+
+    let mut output: Vec<TxOut> = payouts
+        .iter()
+        .map(|payout| TxOut {
+            script_pubkey: payout.to.script_pubkey(),
+            value: Amount::from_sat(payout.amount),
+        })
+        .collect();
+    output.extend(
+        payouts
+            .iter()
+            .zip(selections)
+            .filter_map(|(payout, selection)| {
+                change_output(
+                    &payout.change.address,
+                    selection.total - payout.amount - fee_share,
+                )
+            }),
+    );
+
+    unsigned_transaction(input, output)
+}
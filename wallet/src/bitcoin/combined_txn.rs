@@ -1,13 +1,13 @@
-use bitcoin::{
-    absolute::LockTime, hashes::Hash, transaction::Version, Address, Amount, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
-};
+use bitcoin::{Address, Amount, Transaction, TxOut};
 use ic_cdk::api::management_canister::bitcoin::Utxo;
 use icrc_ledger_types::icrc1::account::Account;
 use ordinals::{Edict, Runestone};
 
 use crate::{
-    bitcoin::signer::mock_signature,
+    bitcoin::{
+        cpfp::anchor_output,
+        planner::{anchor_value, change_output, settle_fee, tx_inputs, unsigned_transaction},
+    },
     state::{write_utxo_manager, RunicUtxo},
     transaction_handler::TransactionType,
     types::RuneId,
@@ -46,52 +46,52 @@ pub fn transfer(
         paid_by_sender,
     }: CombinedTransactionRequest,
 ) -> Result<TransactionType, (u128, u64, u64)> {
-    let mut total_fee = 0;
     let postage = Amount::from_sat(postage.unwrap_or(DEFAULT_POSTAGE));
-    loop {
-        let (txn, runic_utxos, btc_utxos, fee_utxos) = build_transaction_with_fee(
-            from_addr,
-            receiver_addr,
-            &sender_address,
-            &receiver_address,
-            &runeid,
-            rune_amount,
-            btc_amount,
-            postage,
-            total_fee,
-            paid_by_sender,
-        )?;
-
-        let signed_txn = mock_signature(&txn);
-
-        let txn_vsize = signed_txn.vsize() as u64;
-        if (txn_vsize * fee_per_vbytes) / 1000 == total_fee {
-            return Ok(TransactionType::Combined {
-                sender_addr: from_addr.to_string(),
-                receiver_addr: receiver_addr.to_string(),
-                sender_address: sender_address.clone(),
-                receiver_address: receiver_address.clone(),
-                sender_account,
-                receiver_account,
-                runic_utxos,
-                btc_utxos,
-                fee_utxos,
-                runeid,
+    let anchor = anchor_output();
+    // the handler lays the transaction out again from the selection
+    let (_, (runic_utxos, btc_utxos, fee_utxos), fee) = settle_fee(
+        fee_per_vbytes,
+        |fee| {
+            build_transaction_with_fee(
+                from_addr,
+                receiver_addr,
+                &sender_address,
+                &receiver_address,
+                &runeid,
                 rune_amount,
                 btc_amount,
-                fee: total_fee,
                 postage,
+                fee,
                 paid_by_sender,
-            });
-        } else {
+                &anchor,
+            )
+        },
+        |(runic_utxos, btc_utxos, fee_utxos)| {
             write_utxo_manager(|manager| {
                 manager.record_runic_utxos(from_addr, runeid.clone(), runic_utxos);
                 manager.record_btc_utxos(from_addr, btc_utxos);
                 manager.record_btc_utxos(receiver_addr, fee_utxos);
-            });
-            total_fee = (txn_vsize * fee_per_vbytes) / 1000;
-        }
-    }
+            })
+        },
+    )?;
+    Ok(TransactionType::Combined {
+        sender_addr: from_addr.to_string(),
+        receiver_addr: receiver_addr.to_string(),
+        sender_address,
+        receiver_address,
+        sender_account,
+        receiver_account,
+        runic_utxos,
+        btc_utxos,
+        fee_utxos,
+        runeid,
+        rune_amount,
+        btc_amount,
+        fee,
+        postage,
+        paid_by_sender,
+        anchor,
+    })
 }
 
 fn build_transaction_with_fee(
@@ -105,8 +105,10 @@ fn build_transaction_with_fee(
     postage: Amount,
     fee: u64,
     paid_by_sender: bool,
-) -> Result<(Transaction, Vec<RunicUtxo>, Vec<Utxo>, Vec<Utxo>), (u128, u64, u64)> {
-    const DUST_THRESHOLD: u64 = 1_000;
+    anchor: &Option<TxOut>,
+) -> Result<(Transaction, (Vec<RunicUtxo>, Vec<Utxo>, Vec<Utxo>)), (u128, u64, u64)> {
+    // the anchor output is paid by the fee payer
+    let fee = fee + anchor_value(anchor);
 
     let (runic_utxos, runic_total_spent, btc_in_runic_spent) = write_utxo_manager(|manager| {
         let mut utxos = vec![];
@@ -181,48 +183,13 @@ fn build_transaction_with_fee(
         }
     })?;
 
-    let mut input = vec![];
-
-    runic_utxos.iter().for_each(|utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::MAX,
-            witness: Witness::new(),
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(
-                    Hash::from_slice(&utxo.utxo.outpoint.txid).expect("should return hash"),
-                ),
-                vout: utxo.utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
-
-    btc_utxos.iter().for_each(|utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::MAX,
-            witness: Witness::new(),
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(Hash::from_slice(&utxo.outpoint.txid).unwrap()),
-                vout: utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
-
-    fee_utxos.iter().for_each(|utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::MAX,
-            witness: Witness::new(),
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(Hash::from_slice(&utxo.outpoint.txid).unwrap()),
-                vout: utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
+    let input = tx_inputs(
+        runic_utxos
+            .iter()
+            .map(|r_utxo| &r_utxo.utxo)
+            .chain(btc_utxos.iter())
+            .chain(fee_utxos.iter()),
+    );
 
     let id = ordinals::RuneId {
         block: runeid.block,
@@ -269,36 +236,24 @@ fn build_transaction_with_fee(
 
     // remaining fee output
     if !paid_by_sender {
-        let remaining_btc_of_sender = btc_total_spent - btc_amount;
-        if remaining_btc_of_sender > DUST_THRESHOLD {
-            output.push(TxOut {
-                value: Amount::from_sat(remaining_btc_of_sender),
-                script_pubkey: sender_address.script_pubkey(),
-            });
-        }
-        let remaining = fee_total_spent - fee - actual_required_btc;
-        if remaining > DUST_THRESHOLD {
-            output.push(TxOut {
-                script_pubkey: receiver_address.script_pubkey(),
-                value: Amount::from_sat(remaining),
-            });
-        }
+        output.extend(change_output(sender_address, btc_total_spent - btc_amount));
+        output.extend(change_output(
+            receiver_address,
+            fee_total_spent - fee - actual_required_btc,
+        ));
     } else {
-        let remaining = btc_total_spent - btc_amount - fee - actual_required_btc;
-        if remaining > DUST_THRESHOLD {
-            output.push(TxOut {
-                value: Amount::from_sat(remaining),
-                script_pubkey: sender_address.script_pubkey(),
-            });
-        }
+        output.extend(change_output(
+            sender_address,
+            btc_total_spent - btc_amount - fee - actual_required_btc,
+        ));
     }
 
-    let txn = Transaction {
-        input,
-        output,
-        version: Version(2),
-        lock_time: LockTime::ZERO,
-    };
+    if let Some(anchor) = anchor {
+        output.push(anchor.clone());
+    }
 
-    Ok((txn, runic_utxos, btc_utxos, fee_utxos))
+    Ok((
+        unsigned_transaction(input, output),
+        (runic_utxos, btc_utxos, fee_utxos),
+    ))
 }
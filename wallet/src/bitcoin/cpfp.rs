@@ -0,0 +1,137 @@
+use std::str::FromStr;
+
+use bitcoin::{hashes::Hash, Address, Amount, Transaction, TxOut, Txid};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{
+    bitcoin::{
+        planner::{change_output, settle_fee_by, tx_inputs, unsigned_transaction},
+        signer::mock_signature,
+    },
+    state::{read_config, write_utxo_manager},
+    transaction_handler::TransactionType,
+    utils::fee_pool_addresses,
+};
+
+use super::{address_validation, DUST_THRESHOLD};
+
+// dust limit of a p2pkh output
+pub const MIN_ANCHOR_VALUE: u64 = 546;
+
+/*
+ * output paying to the fee pool which gets appended to every withdrawal
+ * while anchors are enabled, letting the canister accelerate it with cpfp later on
+*/
+pub fn anchor_output() -> Option<TxOut> {
+    let value = read_config(|config| config.anchor_output_value)?;
+    let fee_pool = fee_pool_addresses();
+    let address = address_validation(&fee_pool.bitcoin).expect("fee pool address should be valid");
+    Some(TxOut {
+        script_pubkey: address.script_pubkey(),
+        value: Amount::from_sat(value),
+    })
+}
+
+pub fn anchor_utxo(txid: &str, vout: u32, value: u64) -> Option<Utxo> {
+    let txid = Txid::from_str(txid).ok()?;
+    Some(Utxo {
+        outpoint: Outpoint {
+            txid: txid.to_byte_array().to_vec(),
+            vout,
+        },
+        value,
+        height: 0,
+    })
+}
+
+pub struct CpfpArgs<'a> {
+    pub fee_pool_addr: &'a str,
+    pub fee_pool_account: Account,
+    pub fee_pool_address: Address,
+    pub anchor: Utxo,
+    pub parent_vsize: u64,
+    pub parent_fee: u64,
+    pub fee_per_vbytes: u64,
+}
+
+/*
+ * builds the child transaction spending the anchor output along with the fee pool's utxos.
+ * the child's fee covers the whole package (parent + child) at the given fee rate
+*/
+pub fn bump_fee(
+    CpfpArgs {
+        fee_pool_addr,
+        fee_pool_account,
+        fee_pool_address,
+        anchor,
+        parent_vsize,
+        parent_fee,
+        fee_per_vbytes,
+    }: CpfpArgs,
+) -> Result<TransactionType, u64> {
+    let (txn, utxos, _) = settle_fee_by(
+        |txn| {
+            let child_vsize = mock_signature(txn).vsize() as u64;
+            let package_fee = ((parent_vsize + child_vsize) * fee_per_vbytes) / 1000;
+            package_fee
+                .saturating_sub(parent_fee)
+                .max((child_vsize * fee_per_vbytes) / 1000)
+        },
+        |child_fee| {
+            build_transaction_with_fee(fee_pool_addr, &fee_pool_address, &anchor, child_fee)
+        },
+        |utxos| write_utxo_manager(|manager| manager.record_btc_utxos(fee_pool_addr, utxos)),
+    )?;
+    let mut inputs = vec![anchor];
+    inputs.extend(utxos);
+    Ok(TransactionType::Bitcoin {
+        addr: fee_pool_addr.to_string(),
+        utxos: inputs,
+        signer_account: fee_pool_account,
+        signer_address: fee_pool_address.clone(),
+        // the fee pool doesn't split its utxos into branches
+        change_addr: fee_pool_addr.to_string(),
+        change_utxos: vec![],
+        change_account: fee_pool_account,
+        change_address: fee_pool_address,
+        txn,
+        anchor: None,
+    })
+}
+
+fn build_transaction_with_fee(
+    addr: &str,
+    fee_pool_address: &Address,
+    anchor: &Utxo,
+    fee: u64,
+) -> Result<(Transaction, Vec<Utxo>), u64> {
+    let required = fee + DUST_THRESHOLD;
+
+    let (utxos_to_spend, total_spent) = write_utxo_manager(|manager| {
+        let mut utxos = vec![];
+        let mut sum = anchor.value;
+
+        while sum <= required {
+            match manager.get_bitcoin_utxo(addr) {
+                Some(utxo) => {
+                    sum += utxo.value;
+                    utxos.push(utxo);
+                }
+                None => break,
+            }
+        }
+        if sum <= required {
+            manager.record_btc_utxos(addr, utxos);
+            return Err(required);
+        }
+        Ok((utxos, sum))
+    })?;
+
+    let input = tx_inputs(std::iter::once(anchor).chain(utxos_to_spend.iter()));
+    // the whole fee pool is swept into the change, `required` keeps it above dust
+    let output = change_output(fee_pool_address, total_spent - fee)
+        .into_iter()
+        .collect();
+    Ok((unsigned_transaction(input, output), utxos_to_spend))
+}
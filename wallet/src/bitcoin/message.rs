@@ -0,0 +1,155 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bitcoin::{
+    absolute::LockTime,
+    consensus::encode::{serialize, VarInt},
+    hashes::{sha256d, Hash},
+    opcodes::all::OP_RETURN,
+    script::{Builder, PushBytesBuf},
+    secp256k1::Secp256k1,
+    sighash::{EcdsaSighashType, SighashCache},
+    sign_message::MessageSignature,
+    transaction::Version,
+    Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use ic_crypto_secp256k1::PublicKey;
+use icrc_ledger_types::icrc1::account::Account;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    state::read_config,
+    types::{MessageSigFormat, WalletError},
+};
+
+use super::{account_to_derivation_path, derive_public_key, ecdsa_sign, sec1_to_der, sha256};
+
+const LEGACY_MESSAGE_PREFIX: &[u8] = b"\x18Bitcoin Signed Message:\n";
+
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+
+// header of a compact signature by a compressed key, the recovery id gets added
+const COMPRESSED_KEY_HEADER: u8 = 27 + 4;
+
+// what bitcoin core's `signmessage` hashes, the prefix and the message each length prefixed
+fn legacy_message_hash(message: &[u8]) -> [u8; 32] {
+    let mut data = LEGACY_MESSAGE_PREFIX.to_vec();
+    data.extend(serialize(&VarInt(message.len() as u64)));
+    data.extend_from_slice(message);
+    sha256d::Hash::hash(&data).to_byte_array()
+}
+
+fn bip322_message_hash(message: &[u8]) -> [u8; 32] {
+    let tag = sha256(BIP322_TAG);
+    let mut hasher = Sha256::new();
+    hasher.update(&tag);
+    hasher.update(&tag);
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+// the virtual transaction whose output the bip322 signature spends
+fn to_spend(message: &[u8], script_pubkey: ScriptBuf) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: Txid::all_zeros(),
+                vout: 0xFFFFFFFF,
+            },
+            script_sig: Builder::new()
+                .push_int(0)
+                .push_slice(bip322_message_hash(message))
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey,
+        }],
+    }
+}
+
+fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: to_spend.compute_txid(),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/*
+ * signs `message` with the key behind `address`, a p2pkh address of `account`.
+ * `Legacy` gives bitcoin core's compact signature, `Bip322` the full format as p2pkh
+ * has no simple one. both come base64 encoded, the way verifiers take them
+*/
+pub async fn sign(
+    message: &[u8],
+    account: &Account,
+    address: &Address,
+    format: MessageSigFormat,
+) -> Result<String, WalletError> {
+    let path = account_to_derivation_path(account);
+    let pubkey =
+        read_config(|config| derive_public_key(&config.ecdsa_public_key(), &path)).public_key;
+    let derivation_path = path.into_iter().map(|index| index.into_vec()).collect();
+    match format {
+        MessageSigFormat::Legacy => {
+            let hash = legacy_message_hash(message);
+            let signature = ecdsa_sign(hash.to_vec(), derivation_path).await.signature;
+            // the ic doesn't hand back the recovery id, so it's found from the known key
+            let recovery_id = PublicKey::deserialize_sec1(&pubkey)
+                .expect("derived key should be valid")
+                .try_recovery_from_digest(&hash, &signature)
+                .map_err(|_| {
+                    WalletError::SignatureVerificationFailed(String::from(
+                        "signature doesn't recover the signing key",
+                    ))
+                })?;
+            let mut compact = vec![COMPRESSED_KEY_HEADER + recovery_id.to_byte()];
+            compact.extend(signature);
+            Ok(STANDARD.encode(compact))
+        }
+        MessageSigFormat::Bip322 => {
+            let script_pubkey = address.script_pubkey();
+            let mut txn = to_sign(&to_spend(message, script_pubkey.clone()));
+            let sighash = SighashCache::new(&txn)
+                .legacy_signature_hash(0, &script_pubkey, EcdsaSighashType::All.to_u32())
+                .expect("input 0 exists");
+            let response = ecdsa_sign(sighash.to_byte_array().to_vec(), derivation_path).await;
+            let mut signature = sec1_to_der(response.signature);
+            signature.push(EcdsaSighashType::All.to_u32() as u8);
+            txn.input[0].script_sig = Builder::new()
+                .push_slice(PushBytesBuf::try_from(signature).unwrap())
+                .push_slice(PushBytesBuf::try_from(pubkey).unwrap())
+                .into_script();
+            Ok(STANDARD.encode(serialize(&txn)))
+        }
+    }
+}
+
+// checks a base64 compact signature over `message`, as `signmessage` makes, against a p2pkh address
+pub fn verify_legacy(message: &[u8], address: &Address, signature: &str) -> Result<(), String> {
+    let signature = STANDARD
+        .decode(signature)
+        .map_err(|_| String::from("signature isn't base64"))?;
+    let signature = MessageSignature::from_slice(&signature).map_err(|e| e.to_string())?;
+    let hash = sha256d::Hash::from_byte_array(legacy_message_hash(message));
+    match signature.is_signed_by_address(&Secp256k1::verification_only(), address, hash) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(String::from("signed by a key not owning the address")),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -1,211 +1,201 @@
-use bitcoin::{
-    absolute::LockTime, hashes::Hash, transaction::Version, Address, Amount, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
-};
+use bitcoin::{Address, Amount, Transaction, TxOut};
 use ic_cdk::api::management_canister::bitcoin::Utxo;
 use icrc_ledger_types::icrc1::account::Account;
 
 use crate::{
-    bitcoin::signer::mock_signature, state::write_utxo_manager,
-    transaction_handler::TransactionType,
+    bitcoin::{
+        cpfp::anchor_output,
+        planner::{anchor_value, change_output, settle_fee, tx_inputs, unsigned_transaction},
+    },
+    state::write_utxo_manager,
+    transaction_handler::{LegoSender, TransactionType},
 };
 
-pub struct MultiSendTransactionArgument<'a> {
-    pub addr0: &'a str,
-    pub addr1: &'a str,
-    pub address0: Address,
-    pub address1: Address,
+// basis points making up the whole fee
+pub const FEE_SPLIT_BPS: u16 = 10_000;
+
+// equal shares of the fee, the rounding remainder going to the last sender
+pub fn even_fee_shares(senders: usize) -> Vec<u16> {
+    let share = FEE_SPLIT_BPS / senders as u16;
+    let mut shares = vec![share; senders];
+    if let Some(last) = shares.last_mut() {
+        *last = FEE_SPLIT_BPS - share * (senders as u16 - 1);
+    }
+    shares
+}
+
+// each sender's share of `fee` rounded down, the last sender pays the rest
+pub fn split_fee(fee: u64, fee_bps: &[u16]) -> Vec<u64> {
+    let mut fees: Vec<u64> = fee_bps
+        .iter()
+        .map(|bps| fee * *bps as u64 / FEE_SPLIT_BPS as u64)
+        .collect();
+    let rounded: u64 = fees.iter().sum();
+    if let Some(last) = fees.last_mut() {
+        *last += fee - rounded;
+    }
+    fees
+}
+
+// one funding address of a multi-sender transfer, before its utxos are picked
+pub struct SenderArgument {
+    pub addr: String,
+    pub address: Address,
+    pub account: Account,
+    pub amount: u64,
+    // the sender's share of the fee in basis points
+    pub fee_bps: u16,
+}
+
+pub struct MultiSendTransactionArgument {
+    pub senders: Vec<SenderArgument>,
     pub receiver: Address,
-    pub account0: Account,
-    pub account1: Account,
-    pub amount0: u64,
-    pub amount1: u64,
     pub fee_per_vbytes: u64,
     pub paid_by_sender: bool,
 }
 
+// Err holds what each sender needed to cover, in sender order
 pub fn transfer(
     MultiSendTransactionArgument {
-        addr0,
-        addr1,
-        address0,
-        address1,
+        senders,
         receiver,
-        account0,
-        account1,
-        amount0,
-        amount1,
         fee_per_vbytes,
         paid_by_sender,
     }: MultiSendTransactionArgument,
-) -> Result<TransactionType, (u64, u64)> {
-    let mut total_fee = 0;
-    loop {
-        let (txn, utxos0, utxos1) = build_transaction_with_fee(
-            addr0,
-            addr1,
-            &address0,
-            &address1,
-            &receiver,
-            amount0,
-            amount1,
-            total_fee,
-            paid_by_sender,
-        )?;
-        let signed_txn = mock_signature(&txn);
-        let txn_vsize = signed_txn.vsize() as u64;
-        if (txn_vsize * fee_per_vbytes) / 1000 == total_fee {
-            return Ok(TransactionType::LegoBitcoin {
-                addr0: addr0.to_string(),
-                addr1: addr1.to_string(),
-                account0,
-                account1,
-                address0,
-                address1,
-                utxos0,
-                utxos1,
-                amount0,
-                amount1,
-                fee: total_fee,
-                paid_by_sender,
-                receiver,
-            });
-        } else {
+) -> Result<TransactionType, Vec<u64>> {
+    let anchor = anchor_output();
+    let (txn, utxos, fee) = settle_fee(
+        fee_per_vbytes,
+        |fee| build_transaction_with_fee(&senders, &receiver, fee, paid_by_sender, &anchor),
+        |utxos| {
             write_utxo_manager(|manager| {
-                manager.record_btc_utxos(addr0, utxos0);
-                manager.record_btc_utxos(addr1, utxos1);
-            });
-            total_fee = (txn_vsize * fee_per_vbytes) / 1000;
-        }
-    }
+                for (sender, utxos) in senders.iter().zip(utxos) {
+                    manager.record_btc_utxos(&sender.addr, utxos);
+                }
+            })
+        },
+    )?;
+    Ok(TransactionType::LegoBitcoin {
+        senders: senders
+            .into_iter()
+            .zip(utxos)
+            .map(|(sender, utxos)| LegoSender {
+                addr: sender.addr,
+                account: sender.account,
+                address: sender.address,
+                utxos,
+                amount: sender.amount,
+            })
+            .collect(),
+        fee,
+        paid_by_sender,
+        txn,
+        anchor,
+    })
 }
 
 /*
  * returns
- * Ok => (txn, utxos_owned_by_addr0, utxos_owned_by_addr1)
- * Err => (required_amount0, required_amount1)
+ * Ok => (txn, utxos of each sender in sender order)
+ * Err => required amount of each sender
 */
 fn build_transaction_with_fee(
-    addr0: &str,
-    addr1: &str,
-    address0: &Address,
-    address1: &Address,
+    senders: &[SenderArgument],
     receiver: &Address,
-    amount0: u64,
-    amount1: u64,
     fee: u64,
     paid_by_sender: bool,
-) -> Result<(Transaction, Vec<Utxo>, Vec<Utxo>), (u64, u64)> {
-    const DUST_THRESHOLD: u64 = 1_000;
-
-    let (fee0, fee1) = {
-        let is_even = fee % 2 == 0;
-        if is_even {
-            let amount_in_half = fee / 2;
-            (amount_in_half, amount_in_half)
-        } else {
-            let amount_in_half = (fee - 1) / 2;
-            (amount_in_half, amount_in_half + 1)
-        }
-    };
-
-    let (total_amount0, total_amount1) = if paid_by_sender {
-        (amount0 + fee0, amount1 + fee1)
-    } else {
-        (amount0, amount1)
-    };
-    let (utxo_to_spend0, total_spent0, utxo_to_spend1, total_spent1) =
-        write_utxo_manager(|manager| {
-            let (mut utxos0, mut utxos1) = (vec![], vec![]);
-            let (mut total_spent0, mut total_spent1) = (0, 0);
-
-            while let Some(utxo) = manager.get_bitcoin_utxo(addr0) {
-                total_spent0 += utxo.value;
-                utxos0.push(utxo);
-                if total_spent0 >= total_amount0 {
-                    break;
-                }
-            }
+    anchor: &Option<TxOut>,
+) -> Result<(Transaction, Vec<Vec<Utxo>>), Vec<u64>> {
+    // the anchor output is split between the senders like the fee
+    let fee = fee + anchor_value(anchor);
+    let fee_bps: Vec<u16> = senders.iter().map(|sender| sender.fee_bps).collect();
+    let fees = split_fee(fee, &fee_bps);
 
-            while let Some(utxo) = manager.get_bitcoin_utxo(addr1) {
-                total_spent1 += utxo.value;
-                utxos1.push(utxo);
-                if total_spent1 >= total_amount1 {
+    let total_amounts: Vec<u64> = senders
+        .iter()
+        .zip(fees.iter())
+        .map(|(sender, fee)| {
+            if paid_by_sender {
+                sender.amount + fee
+            } else {
+                sender.amount
+            }
+        })
+        .collect();
+    let (utxos_to_spend, total_spent) = write_utxo_manager(|manager| {
+        let mut utxos_to_spend = Vec::with_capacity(senders.len());
+        let mut total_spent = Vec::with_capacity(senders.len());
+        for (sender, total_amount) in senders.iter().zip(total_amounts.iter()) {
+            let (mut utxos, mut spent) = (vec![], 0);
+            while let Some(utxo) = manager.get_bitcoin_utxo(&sender.addr) {
+                spent += utxo.value;
+                utxos.push(utxo);
+                if spent >= *total_amount {
                     break;
                 }
             }
+            utxos_to_spend.push(utxos);
+            total_spent.push(spent);
+        }
 
-            if (total_spent0 < total_amount0) || (total_spent1 < total_amount1) {
-                manager.record_btc_utxos(addr0, utxos0);
-                manager.record_btc_utxos(addr1, utxos1);
-                return Err((total_amount0, total_amount1));
+        if total_spent
+            .iter()
+            .zip(total_amounts.iter())
+            .any(|(spent, total_amount)| spent < total_amount)
+        {
+            for (sender, utxos) in senders.iter().zip(utxos_to_spend) {
+                manager.record_btc_utxos(&sender.addr, utxos);
             }
-            Ok((utxos0, total_spent0, utxos1, total_spent1))
-        })?;
-
-    let mut input = vec![];
-
-    utxo_to_spend0.iter().for_each(|utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::MAX,
-            witness: Witness::new(),
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(
-                    Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                ),
-                vout: utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
-
-    utxo_to_spend1.iter().for_each(|utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::MAX,
-            witness: Witness::new(),
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(
-                    Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                ),
-                vout: utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
+            return Err(total_amounts.clone());
+        }
+        Ok((utxos_to_spend, total_spent))
+    })?;
+
+    let input = tx_inputs(utxos_to_spend.iter().flatten());
 
+    let amount: u64 = senders.iter().map(|sender| sender.amount).sum();
     let mut output = vec![TxOut {
         script_pubkey: receiver.script_pubkey(),
         value: if paid_by_sender {
-            Amount::from_sat(amount0 + amount1)
+            Amount::from_sat(amount)
         } else {
-            Amount::from_sat(amount0 + amount1 - fee0 - fee1)
+            Amount::from_sat(amount - fee)
         },
     }];
 
-    // block responsible for calculating and adding remaining account
+    // each sender's change goes back to its own address
+    for ((sender, spent), total_amount) in senders
+        .iter()
+        .zip(total_spent.iter())
+        .zip(total_amounts.iter())
     {
-        let remaining0 = total_spent0 - total_amount0;
-        if remaining0 > DUST_THRESHOLD {
-            output.push(TxOut {
-                script_pubkey: address0.script_pubkey(),
-                value: Amount::from_sat(remaining0),
-            });
-        }
-        let remaining1 = total_spent1 - total_amount1;
-        if remaining1 > DUST_THRESHOLD {
-            output.push(TxOut {
-                script_pubkey: address1.script_pubkey(),
-                value: Amount::from_sat(remaining1),
-            })
-        }
+        output.extend(change_output(&sender.address, spent - total_amount));
+    }
+    if let Some(anchor) = anchor {
+        output.push(anchor.clone());
+    }
+    Ok((unsigned_transaction(input, output), utxos_to_spend))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_fee_gives_the_remainder_to_the_last_sender() {
+        assert_eq!(split_fee(1_001, &[5_000, 5_000]), vec![500, 501]);
+        assert_eq!(split_fee(100, &even_fee_shares(3)), vec![33, 33, 34]);
+    }
+
+    #[test]
+    fn split_fee_single_sender() {
+        assert_eq!(split_fee(1_001, &[FEE_SPLIT_BPS]), vec![1_001]);
+        assert_eq!(split_fee(0, &[2_500, 7_500]), vec![0, 0]);
+    }
+
+    #[test]
+    fn even_fee_shares_add_up() {
+        assert_eq!(even_fee_shares(3), vec![3_333, 3_333, 3_334]);
+        assert_eq!(even_fee_shares(1), vec![FEE_SPLIT_BPS]);
     }
-    let txn = Transaction {
-        version: Version(2),
-        lock_time: LockTime::ZERO,
-        input,
-        output,
-    };
-    Ok((txn, utxo_to_spend0, utxo_to_spend1))
 }
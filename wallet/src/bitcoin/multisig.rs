@@ -0,0 +1,295 @@
+use bitcoin::{
+    ecdsa::Signature,
+    hashes::Hash,
+    opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_2},
+    psbt::Psbt,
+    script::Builder,
+    secp256k1::{self, Message, Secp256k1},
+    sighash::{EcdsaSighashType, SighashCache},
+    Address, Amount, PublicKey, ScriptBuf, Transaction, TxOut, Witness,
+};
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{
+    state::{read_config, write_utxo_manager},
+    utils::principal_to_multisig_subaccount,
+};
+
+use super::{
+    account_to_derivation_path, bitcoin_network, derive_public_key, ecdsa_sign,
+    planner::{change_output, settle_fee_by, tx_inputs, unsigned_transaction},
+};
+
+#[derive(CandidType)]
+pub struct MultisigWithdrawal {
+    // txid of the final transaction, used to finalize or cancel the withdrawal
+    pub txid: String,
+    // serialized psbt carrying the canister's signatures
+    pub psbt: Vec<u8>,
+}
+
+// 2-of-2 between a key derived for the principal and the cosigner's key
+pub struct MultisigWallet {
+    pub account: Account,
+    pub canister_key: PublicKey,
+    pub cosigner_key: PublicKey,
+    pub witness_script: ScriptBuf,
+    pub address: Address,
+}
+
+impl MultisigWallet {
+    pub fn new(principal: &Principal, cosigner_pubkey: &[u8]) -> Result<Self, String> {
+        let cosigner_key = PublicKey::from_slice(cosigner_pubkey)
+            .map_err(|e| format!("invalid cosigner public key: {}", e))?;
+        if !cosigner_key.compressed {
+            return Err(String::from("cosigner public key must be compressed"));
+        }
+        let account = Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(principal_to_multisig_subaccount(principal)),
+        };
+        let canister_key = canister_public_key(&account);
+        let witness_script = multisig_script(&canister_key, &cosigner_key);
+        let address = multisig_address(&witness_script);
+        Ok(Self {
+            account,
+            canister_key,
+            cosigner_key,
+            witness_script,
+            address,
+        })
+    }
+}
+
+// public key of the canister's half of the 2-of-2
+pub fn canister_public_key(account: &Account) -> PublicKey {
+    let pubkey = read_config(|config| {
+        let ecdsa_key = config.ecdsa_public_key();
+        let path = account_to_derivation_path(account);
+        derive_public_key(&ecdsa_key, &path).public_key
+    });
+    PublicKey::from_slice(&pubkey).expect("derived public key should be valid")
+}
+
+// 2-of-2 checkmultisig with the keys sorted, so the script doesn't depend on argument order
+pub fn multisig_script(canister_key: &PublicKey, cosigner_key: &PublicKey) -> ScriptBuf {
+    let (first, second) = if canister_key.to_bytes() <= cosigner_key.to_bytes() {
+        (canister_key, cosigner_key)
+    } else {
+        (cosigner_key, canister_key)
+    };
+    Builder::new()
+        .push_opcode(OP_PUSHNUM_2)
+        .push_key(first)
+        .push_key(second)
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+}
+
+pub fn multisig_address(witness_script: &ScriptBuf) -> Address {
+    Address::p2wsh(witness_script, bitcoin_network())
+}
+
+pub struct MultisigTransferArgs<'a> {
+    pub addr: &'a str,
+    pub witness_script: &'a ScriptBuf,
+    pub to: Address,
+    pub amount: u64,
+    pub fee_per_vbytes: u64,
+}
+
+/*
+ * builds the unsigned withdrawal out of the multisig address as a psbt.
+ * the sender always pays the fee, the change goes back to the multisig address
+*/
+pub fn build_psbt(
+    MultisigTransferArgs {
+        addr,
+        witness_script,
+        to,
+        amount,
+        fee_per_vbytes,
+    }: MultisigTransferArgs,
+) -> Result<(Psbt, Vec<Utxo>), u64> {
+    let (txn, utxos, _) = settle_fee_by(
+        |txn| (mock_witness(txn, witness_script).vsize() as u64 * fee_per_vbytes) / 1000,
+        |total_fee| build_transaction_with_fee(addr, witness_script, &to, amount, total_fee),
+        |utxos| write_utxo_manager(|manager| manager.record_btc_utxos(addr, utxos)),
+    )?;
+    let mut psbt = Psbt::from_unsigned_tx(txn).expect("transaction should be unsigned");
+    let script_pubkey = witness_script.to_p2wsh();
+    for (input, utxo) in psbt.inputs.iter_mut().zip(utxos.iter()) {
+        input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(utxo.value),
+            script_pubkey: script_pubkey.clone(),
+        });
+        input.witness_script = Some(witness_script.clone());
+        input.sighash_type = Some(EcdsaSighashType::All.into());
+    }
+    Ok((psbt, utxos))
+}
+
+// adds the canister's signature of every input to the psbt
+pub async fn sign_psbt(psbt: &mut Psbt, account: &Account) {
+    let canister_key = canister_public_key(account);
+    let path: Vec<Vec<u8>> = account_to_derivation_path(account)
+        .into_iter()
+        .map(|index| index.into_vec())
+        .collect();
+    for (index, sighash) in input_sighashes(psbt)
+        .expect("psbt built by the canister should be complete")
+        .into_iter()
+        .enumerate()
+    {
+        let signature = ecdsa_sign(sighash.to_vec(), path.clone()).await.signature;
+        let mut signature = secp256k1::ecdsa::Signature::from_compact(&signature)
+            .expect("threshold ecdsa should return a compact signature");
+        // standardness requires low-s signatures
+        signature.normalize_s();
+        psbt.inputs[index].partial_sigs.insert(
+            canister_key,
+            Signature {
+                signature,
+                sighash_type: EcdsaSighashType::All,
+            },
+        );
+    }
+}
+
+/*
+ * combines the canister's signatures from `psbt` with the cosigner's ones from `cosigned`
+ * and returns the fully signed transaction. only the cosigner's partial signatures are
+ * taken from the returned psbt, everything else comes from the canister's own copy
+*/
+pub fn finalize_psbt(
+    mut psbt: Psbt,
+    cosigned: &Psbt,
+    canister_key: &PublicKey,
+    cosigner_key: &PublicKey,
+) -> Result<Transaction, String> {
+    if psbt.unsigned_tx != cosigned.unsigned_tx {
+        return Err(String::from("psbt doesn't match the pending withdrawal"));
+    }
+    let secp = Secp256k1::verification_only();
+    let sighashes = input_sighashes(&psbt)?;
+    for (index, sighash) in sighashes.into_iter().enumerate() {
+        let message = Message::from_digest(sighash);
+        let cosigner_sig = cosigned.inputs[index]
+            .partial_sigs
+            .get(cosigner_key)
+            .ok_or_else(|| format!("input {}: missing cosigner signature", index))?;
+        let canister_sig = psbt.inputs[index]
+            .partial_sigs
+            .get(canister_key)
+            .ok_or_else(|| format!("input {}: missing canister signature", index))?;
+        for (key, sig) in [(cosigner_key, cosigner_sig), (canister_key, canister_sig)] {
+            if sig.sighash_type != EcdsaSighashType::All {
+                return Err(format!("input {}: unsupported sighash type", index));
+            }
+            secp.verify_ecdsa(&message, &sig.signature, &key.inner)
+                .map_err(|e| format!("input {}: {}", index, e))?;
+        }
+        let witness_script = psbt.inputs[index]
+            .witness_script
+            .clone()
+            .ok_or_else(|| format!("input {}: missing witness script", index))?;
+        // checkmultisig expects the signatures in the order of the keys in the script
+        let (first, second) = if canister_key.to_bytes() <= cosigner_key.to_bytes() {
+            (canister_sig, cosigner_sig)
+        } else {
+            (cosigner_sig, canister_sig)
+        };
+        let witness = Witness::from_slice(&[
+            vec![],
+            first.to_vec(),
+            second.to_vec(),
+            witness_script.to_bytes(),
+        ]);
+        psbt.inputs[index].final_script_witness = Some(witness);
+    }
+    let mut txn = psbt.unsigned_tx.clone();
+    for (input, psbt_input) in txn.input.iter_mut().zip(psbt.inputs.iter()) {
+        input.witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+    }
+    Ok(txn)
+}
+
+fn input_sighashes(psbt: &Psbt) -> Result<Vec<[u8; 32]>, String> {
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    psbt.inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            let witness_script = input
+                .witness_script
+                .as_ref()
+                .ok_or_else(|| format!("input {}: missing witness script", index))?;
+            let value = input
+                .witness_utxo
+                .as_ref()
+                .ok_or_else(|| format!("input {}: missing witness utxo", index))?
+                .value;
+            cache
+                .p2wsh_signature_hash(index, witness_script, value, EcdsaSighashType::All)
+                .map(|sighash| sighash.to_byte_array())
+                .map_err(|e| format!("input {}: {}", index, e))
+        })
+        .collect()
+}
+
+// fills every witness with placeholder signatures of maximum size for fee estimation
+fn mock_witness(txn: &Transaction, witness_script: &ScriptBuf) -> Transaction {
+    let mut txn = txn.clone();
+    for input in txn.input.iter_mut() {
+        input.witness = Witness::from_slice(&[
+            vec![],
+            vec![255; 73],
+            vec![255; 73],
+            witness_script.to_bytes(),
+        ]);
+    }
+    txn
+}
+
+fn build_transaction_with_fee(
+    addr: &str,
+    witness_script: &ScriptBuf,
+    to: &Address,
+    amount: u64,
+    fee: u64,
+) -> Result<(Transaction, Vec<Utxo>), u64> {
+    let total_amount = amount + fee;
+
+    let (utxos_to_spend, total_spent) = write_utxo_manager(|manager| {
+        let mut utxos = vec![];
+        let mut sum = 0;
+
+        while let Some(utxo) = manager.get_bitcoin_utxo(addr) {
+            sum += utxo.value;
+            utxos.push(utxo);
+            if sum > total_amount {
+                break;
+            }
+        }
+        if sum < total_amount {
+            manager.record_btc_utxos(addr, utxos);
+            return Err(total_amount);
+        }
+        Ok((utxos, sum))
+    })?;
+
+    let input = tx_inputs(&utxos_to_spend);
+    let mut output = vec![TxOut {
+        script_pubkey: to.script_pubkey(),
+        value: Amount::from_sat(amount),
+    }];
+    // the change goes back to the multisig address
+    output.extend(change_output(
+        &multisig_address(witness_script),
+        total_spent - total_amount,
+    ));
+    Ok((unsigned_transaction(input, output), utxos_to_spend))
+}
@@ -0,0 +1,284 @@
+use bitcoin::{
+    absolute::LockTime, hashes::Hash, transaction::Version, Address, Amount, OutPoint, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+
+use crate::{
+    transaction_handler::TransactionType,
+    types::{CoinSelection, FeePayer, RuneId, WalletError},
+};
+
+use super::{
+    combined_txn::{self, CombinedTransactionRequest},
+    runestone::{self, MultiRuneTransferArgs},
+    signer::mock_signature,
+    transaction::{self, BitcoinBatchTransferArgs, BitcoinTransferArgs},
+    Branch, DUST_THRESHOLD,
+};
+
+// what a planned transaction does, the planner works out the inputs and the fee
+pub enum Intent<'a> {
+    // plain bitcoin out of the funding branch, several of them make a batch
+    SendBtc {
+        to: Address,
+        amount: u64,
+    },
+    // runes out of the funding branch, every rune going to the same receiver
+    SendRune {
+        runeid: RuneId,
+        amount: u128,
+        to: Branch<'a>,
+    },
+    // the sender by default. a paying receiver of runes spends its own bitcoin
+    PayFeeFrom(FeePayer),
+    // where the bitcoin change goes, the branch's utxos topping up the funding ones
+    ChangeTo(Branch<'a>),
+    // an address outside the wallet taking the change output of bitcoin payments
+    ChangeOutputTo(Address),
+}
+
+pub enum PlanError {
+    // intents no transaction layout covers
+    Unsupported(&'static str),
+    // bitcoin the selected utxos had to cover, zero when the runes fell short
+    Shortfall(u64),
+}
+
+impl From<PlanError> for WalletError {
+    fn from(err: PlanError) -> Self {
+        match err {
+            PlanError::Unsupported(reason) => WalletError::InvalidArgument(String::from(reason)),
+            PlanError::Shortfall(_) => WalletError::InsufficientBalance,
+        }
+    }
+}
+
+/*
+ * composes a transfer out of `from` from intents and lays it out as the matching
+ * `TransactionType`: one bitcoin payment, a batch of them, runes, or runes along
+ * with bitcoin to their receiver. the utxos are taken out of the manager until the
+ * transaction is submitted or released, on an error they are back in place
+*/
+pub struct TxPlanner<'a> {
+    from: Branch<'a>,
+    fee_per_vbytes: u64,
+    intents: Vec<Intent<'a>>,
+    selection: CoinSelection,
+    memo: Option<Vec<u8>>,
+}
+
+impl<'a> TxPlanner<'a> {
+    pub fn new(from: Branch<'a>, fee_per_vbytes: u64) -> Self {
+        Self {
+            from,
+            fee_per_vbytes,
+            intents: vec![],
+            selection: CoinSelection::default(),
+            memo: None,
+        }
+    }
+
+    pub fn with(mut self, intent: Intent<'a>) -> Self {
+        self.intents.push(intent);
+        self
+    }
+
+    // for a single bitcoin payment
+    pub fn selection(mut self, selection: CoinSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    // tag of an OP_RETURN output on a single bitcoin payment
+    pub fn memo(mut self, memo: Option<Vec<u8>>) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    pub fn plan(self) -> Result<TransactionType, PlanError> {
+        let mut payments = vec![];
+        let mut runes = vec![];
+        let mut rune_receiver: Option<Branch> = None;
+        let mut fee_payer = FeePayer::Sender;
+        let mut change = None;
+        let mut change_to = None;
+        for intent in self.intents {
+            match intent {
+                Intent::SendBtc { to, amount } => payments.push((to, amount)),
+                Intent::SendRune { runeid, amount, to } => {
+                    if rune_receiver
+                        .as_ref()
+                        .is_some_and(|receiver| receiver.addr != to.addr)
+                    {
+                        return Err(PlanError::Unsupported(
+                            "runes can only go to a single receiver",
+                        ));
+                    }
+                    runes.push((runeid, amount));
+                    rune_receiver = Some(to);
+                }
+                Intent::PayFeeFrom(payer) => fee_payer = payer,
+                Intent::ChangeTo(branch) => change = Some(branch),
+                Intent::ChangeOutputTo(address) => change_to = Some(address),
+            }
+        }
+        let change = change.unwrap_or_else(|| self.from.clone());
+        let Some(receiver) = rune_receiver else {
+            return match payments.len() {
+                0 => Err(PlanError::Unsupported("nothing to send")),
+                1 => {
+                    let (to, amount) = payments.remove(0);
+                    transaction::transfer(BitcoinTransferArgs {
+                        receive: self.from,
+                        change,
+                        to,
+                        amount,
+                        fee_payer,
+                        fee_per_vbytes: self.fee_per_vbytes,
+                        selection: self.selection,
+                        memo: self.memo,
+                        change_to,
+                    })
+                    .map_err(PlanError::Shortfall)
+                }
+                _ if fee_payer != FeePayer::Sender || self.memo.is_some() => Err(
+                    PlanError::Unsupported("a batch is paid by the sender and carries no memo"),
+                ),
+                _ => transaction::transfer_batch(BitcoinBatchTransferArgs {
+                    receive: self.from,
+                    change,
+                    payouts: payments,
+                    fee_per_vbytes: self.fee_per_vbytes,
+                    change_to,
+                })
+                .map_err(PlanError::Shortfall),
+            };
+        };
+        if change_to.is_some() {
+            return Err(PlanError::Unsupported(
+                "rune transfers keep their change in the wallet",
+            ));
+        }
+        let paid_by_sender = fee_payer == FeePayer::Sender;
+        match (payments.len(), runes.len()) {
+            (0, _) => runestone::transfer_many(MultiRuneTransferArgs {
+                runes,
+                sender_addr: self.from.addr,
+                receiver_addr: receiver.addr,
+                sender_account: self.from.account,
+                receiver_account: receiver.account,
+                sender_address: self.from.address,
+                receiver_address: receiver.address,
+                fee_per_vbytes: self.fee_per_vbytes,
+                paid_by_sender,
+                postage: None,
+            })
+            .map_err(|(_, fee)| PlanError::Shortfall(fee)),
+            (1, 1) if payments[0].0 == receiver.address => {
+                let (runeid, rune_amount) = runes.remove(0);
+                combined_txn::transfer(CombinedTransactionRequest {
+                    from_addr: self.from.addr,
+                    receiver_addr: receiver.addr,
+                    sender_address: self.from.address,
+                    receiver_address: receiver.address,
+                    runeid,
+                    rune_amount,
+                    btc_amount: payments[0].1,
+                    sender_account: self.from.account,
+                    receiver_account: receiver.account,
+                    postage: None,
+                    fee_per_vbytes: self.fee_per_vbytes,
+                    paid_by_sender,
+                })
+                .map_err(|(_, btc_amount, fee)| PlanError::Shortfall(btc_amount + fee))
+            }
+            _ => Err(PlanError::Unsupported(
+                "bitcoin only goes along with a single rune to its receiver",
+            )),
+        }
+    }
+}
+
+/*
+ * the pieces every layout shares. inputs are unsigned until the handler signs them,
+ * the fee is settled on a copy with mocked signatures
+*/
+
+pub(super) fn tx_inputs<'u>(utxos: impl IntoIterator<Item = &'u Utxo>) -> Vec<TxIn> {
+    utxos
+        .into_iter()
+        .map(|utxo| TxIn {
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+            previous_output: OutPoint {
+                txid: Txid::from_raw_hash(
+                    Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
+                ),
+                vout: utxo.outpoint.vout,
+            },
+        })
+        .collect()
+}
+
+pub(super) fn unsigned_transaction(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        input,
+        output,
+        lock_time: LockTime::ZERO,
+        version: Version(2),
+    }
+}
+
+// the anchor output is paid by whoever pays the fee
+pub(super) fn anchor_value(anchor: &Option<TxOut>) -> u64 {
+    anchor.as_ref().map_or(0, |anchor| anchor.value.to_sat())
+}
+
+// leftovers at or below dust go to the miners
+pub(super) fn change_output(address: &Address, remaining: u64) -> Option<TxOut> {
+    (remaining > DUST_THRESHOLD).then(|| TxOut {
+        script_pubkey: address.script_pubkey(),
+        value: Amount::from_sat(remaining),
+    })
+}
+
+pub(super) fn fee_at(txn: &Transaction, fee_per_vbytes: u64) -> u64 {
+    (mock_signature(txn).vsize() as u64 * fee_per_vbytes) / 1000
+}
+
+/*
+ * rebuilds with the fee of the previous round until the transaction's size stops
+ * changing it. `build` takes the utxos it spends out of the manager, `release` puts
+ * back those of a round that got outdated
+*/
+pub(super) fn settle_fee<T, E>(
+    fee_per_vbytes: u64,
+    build: impl FnMut(u64) -> Result<(Transaction, T), E>,
+    release: impl FnMut(T),
+) -> Result<(Transaction, T, u64), E> {
+    settle_fee_by(|txn| fee_at(txn, fee_per_vbytes), build, release)
+}
+
+/*
+ * the same for a fee other than the transaction's own size at the rate, like a
+ * child paying for its parent or a per-payout share. settles on the first round
+ * whose fee covers it, a change output could otherwise keep flipping in and out
+*/
+pub(super) fn settle_fee_by<T, E>(
+    mut required_fee: impl FnMut(&Transaction) -> u64,
+    mut build: impl FnMut(u64) -> Result<(Transaction, T), E>,
+    mut release: impl FnMut(T),
+) -> Result<(Transaction, T, u64), E> {
+    let mut fee = 0;
+    loop {
+        let (txn, taken) = build(fee)?;
+        let required = required_fee(&txn);
+        if required <= fee {
+            return Ok((txn, taken, fee));
+        }
+        release(taken);
+        fee = required;
+    }
+}
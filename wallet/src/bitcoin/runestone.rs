@@ -1,20 +1,25 @@
-use bitcoin::{
-    absolute::LockTime, hashes::Hash, transaction::Version, Address, Amount, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
-};
+use bitcoin::{Address, Amount, Transaction, TxIn, TxOut};
+use candid::CandidType;
 use ic_cdk::api::management_canister::bitcoin::Utxo;
 use icrc_ledger_types::icrc1::account::Account;
 use ordinals::{Edict, Runestone};
 
 use crate::{
-    state::{write_utxo_manager, RunicUtxo},
-    transaction_handler::TransactionType,
+    state::{read_utxo_manager, write_utxo_manager, RunicUtxo},
+    transaction_handler::{FeeSponsorship, RuneSelection, TransactionType},
     types::RuneId,
 };
 
-const DEFAULT_POSTAGE: u64 = 10_000;
+pub const DEFAULT_POSTAGE: u64 = 10_000;
+
+// dust limit of a p2pkh output, rune outputs can't carry less
+pub const MIN_POSTAGE: u64 = 546;
 
-use super::signer::mock_signature;
+use super::{
+    cpfp::anchor_output,
+    planner::{anchor_value, change_output, fee_at, settle_fee, tx_inputs, unsigned_transaction},
+    DUST_THRESHOLD, MAX_OP_RETURN_SIZE,
+};
 
 pub struct RuneTransferArgs<'a> {
     pub runeid: RuneId,
@@ -45,57 +50,381 @@ pub fn transfer(
         postage,
     }: RuneTransferArgs,
 ) -> Result<TransactionType, (u128, u64)> {
-    let mut total_fee = 0;
-    let postage = Amount::from_sat(postage.unwrap_or(DEFAULT_POSTAGE));
-    loop {
-        let (txn, runic_utxos, fee_utxos) = build_transaction_with_fee(
-            &runeid,
-            amount,
+    transfer_many(MultiRuneTransferArgs {
+        runes: vec![(runeid, amount)],
+        sender_addr,
+        receiver_addr,
+        sender_account,
+        receiver_account,
+        sender_address,
+        receiver_address,
+        fee_per_vbytes,
+        paid_by_sender,
+        postage,
+    })
+}
+
+// every rune is delivered to the same receiver output, rune ids must be distinct
+pub struct MultiRuneTransferArgs<'a> {
+    pub runes: Vec<(RuneId, u128)>,
+    pub sender_addr: &'a str,
+    pub receiver_addr: &'a str,
+    pub sender_account: Account,
+    pub receiver_account: Account,
+    pub sender_address: Address,
+    pub receiver_address: Address,
+    pub fee_per_vbytes: u64,
+    pub paid_by_sender: bool,
+    pub postage: Option<u64>,
+}
+
+pub fn transfer_many(args: MultiRuneTransferArgs) -> Result<TransactionType, (u128, u64)> {
+    transfer_with(args, None)
+}
+
+/*
+ * `transfer` with the fee and every output's postage funded by `sponsor`, whose runes
+ * go to an output right after the receiver's. the selected utxos cover both amounts,
+ * the sender's plain bitcoin is left alone. fails like `transfer`, the fee being the
+ * one the sponsor's bitcoin didn't cover
+*/
+pub fn transfer_sponsored(
+    RuneTransferArgs {
+        runeid,
+        amount,
+        sender_addr,
+        receiver_addr,
+        sender_account,
+        receiver_account,
+        sender_address,
+        receiver_address,
+        fee_per_vbytes,
+        paid_by_sender: _,
+        postage,
+    }: RuneTransferArgs,
+    sponsor: FeeSponsorship,
+) -> Result<TransactionType, (u128, u64)> {
+    transfer_with(
+        MultiRuneTransferArgs {
+            runes: vec![(runeid, amount)],
             sender_addr,
             receiver_addr,
-            &sender_address,
-            &receiver_address,
-            total_fee,
-            paid_by_sender,
+            sender_account,
+            receiver_account,
+            sender_address,
+            receiver_address,
+            fee_per_vbytes,
+            paid_by_sender: true,
             postage,
-        )?;
-
-        let signed_txn = mock_signature(&txn);
-
-        let txn_vsize = signed_txn.vsize() as u64;
-        if (txn_vsize * fee_per_vbytes) / 1000 == total_fee {
-            return Ok(TransactionType::Runestone {
-                sender_addr: sender_addr.to_string(),
-                receiver_addr: receiver_addr.to_string(),
-                sender_account,
-                receiver_account,
-                runeid,
-                amount,
-                fee: total_fee,
-                runic_utxos,
-                fee_utxos,
+        },
+        Some(sponsor),
+    )
+}
+
+fn transfer_with(
+    MultiRuneTransferArgs {
+        runes,
+        sender_addr,
+        receiver_addr,
+        sender_account,
+        receiver_account,
+        sender_address,
+        receiver_address,
+        fee_per_vbytes,
+        paid_by_sender,
+        postage,
+    }: MultiRuneTransferArgs,
+    sponsor: Option<FeeSponsorship>,
+) -> Result<TransactionType, (u128, u64)> {
+    let postage = Amount::from_sat(postage.unwrap_or(DEFAULT_POSTAGE));
+    let anchor = anchor_output();
+    let (_, (selections, fee_utxos), fee) = settle_fee(
+        fee_per_vbytes,
+        |fee| {
+            build_transaction_with_fee(
+                &runes,
+                sender_addr,
+                receiver_addr,
+                &sender_address,
+                &receiver_address,
+                fee,
                 paid_by_sender,
-                sender_address,
-                receiver_address,
                 postage,
-            });
-        } else {
+                &anchor,
+                sponsor.as_ref(),
+            )
+        },
+        |(selections, fee_utxos)| {
             write_utxo_manager(|manager| {
-                manager.record_runic_utxos(sender_addr, runeid.clone(), runic_utxos);
-                if paid_by_sender {
-                    manager.record_btc_utxos(sender_addr, fee_utxos);
-                } else {
-                    manager.record_btc_utxos(receiver_addr, fee_utxos);
+                for selection in selections {
+                    manager.record_runic_utxos(sender_addr, selection.runeid, selection.utxos);
                 }
+                match &sponsor {
+                    Some(sponsor) => manager.record_btc_utxos(&sponsor.addr, fee_utxos),
+                    None if paid_by_sender => manager.record_btc_utxos(sender_addr, fee_utxos),
+                    None => manager.record_btc_utxos(receiver_addr, fee_utxos),
+                }
+            })
+        },
+    )?;
+    Ok(TransactionType::Runestone {
+        sender_addr: sender_addr.to_string(),
+        receiver_addr: receiver_addr.to_string(),
+        sender_account,
+        receiver_account,
+        runes: selections,
+        fee,
+        fee_utxos,
+        paid_by_sender,
+        sender_address,
+        receiver_address,
+        postage,
+        anchor,
+        sponsor,
+    })
+}
+
+// bitcoin the sponsor of a transfer puts in beyond what it gets back as change
+pub fn sponsor_outlay(txn: &TransactionType) -> u64 {
+    let TransactionType::Runestone {
+        runes,
+        fee,
+        postage,
+        anchor,
+        sponsor: Some(_),
+        ..
+    } = txn
+    else {
+        return 0;
+    };
+    let btc_in_runic: u64 = runic_inputs(runes).iter().map(|utxo| utxo.value).sum();
+    (fee + anchor_value(anchor) + (*postage * rune_outputs(runes, true)).to_sat())
+        .saturating_sub(btc_in_runic)
+}
+
+/*
+ * a rune transfer without any plain bitcoin. the postage and the fee come out of
+ * what the runic utxos carry beyond their own postage, more utxos of the rune get
+ * spent until they cover it and the rest goes back to the sender as change. fails
+ * like `transfer` with the rune amount or the fee that wasn't covered
+*/
+pub fn transfer_funded_by_runes(
+    RuneTransferArgs {
+        runeid,
+        amount,
+        sender_addr,
+        receiver_addr,
+        sender_account,
+        receiver_account,
+        sender_address,
+        receiver_address,
+        fee_per_vbytes,
+        paid_by_sender: _,
+        postage,
+    }: RuneTransferArgs,
+) -> Result<TransactionType, (u128, u64)> {
+    let postage = Amount::from_sat(postage.unwrap_or(MIN_POSTAGE));
+    let anchor = anchor_output();
+    let build = |fee: u64| -> Result<_, (u128, u64)> {
+        let selections = write_utxo_manager(|manager| {
+            let mut utxos = vec![];
+            let mut selected = 0;
+            while selected < amount {
+                let Some(utxo) = manager.get_runic_utxo(sender_addr, runeid.clone()) else {
+                    manager.record_runic_utxos(sender_addr, runeid.clone(), utxos);
+                    return Err((amount, 0));
+                };
+                selected += utxo.balance;
+                utxos.push(utxo);
+            }
+            let mut selections = vec![RuneSelection {
+                runeid: runeid.clone(),
+                amount,
+                utxos,
+            }];
+            // the runic inputs carry every output's postage on top of the fee
+            let covered = |selections: &[RuneSelection]| {
+                let btc_in_runic: u64 =
+                    runic_inputs(selections).iter().map(|utxo| utxo.value).sum();
+                btc_in_runic
+                    >= fee
+                        + anchor_value(&anchor)
+                        + (postage * rune_outputs(selections, false)).to_sat()
+            };
+            while !covered(&selections) {
+                let Some(utxo) = manager.get_runic_utxo(sender_addr, runeid.clone()) else {
+                    for selection in selections {
+                        manager.record_runic_utxos(sender_addr, selection.runeid, selection.utxos);
+                    }
+                    return Err((0, fee));
+                };
+                selections[0].utxos.push(utxo);
+            }
+            Ok(selections)
+        })?;
+
+        let txn = assemble(
+            &selections,
+            &[],
+            fee,
+            true,
+            &sender_address,
+            &receiver_address,
+            postage,
+            &anchor,
+            None,
+        );
+        Ok((txn, selections))
+    };
+    let (_, selections, fee) = settle_fee(fee_per_vbytes, build, |selections| {
+        write_utxo_manager(|manager| {
+            for selection in selections {
+                manager.record_runic_utxos(sender_addr, selection.runeid, selection.utxos);
+            }
+        })
+    })?;
+    Ok(TransactionType::Runestone {
+        sender_addr: sender_addr.to_string(),
+        receiver_addr: receiver_addr.to_string(),
+        sender_account,
+        receiver_account,
+        runes: selections,
+        fee,
+        fee_utxos: vec![],
+        paid_by_sender: true,
+        sender_address,
+        receiver_address,
+        postage,
+        anchor,
+        sponsor: None,
+    })
+}
+
+#[derive(CandidType)]
+pub struct RuneTransferRequirements {
+    // value of every output carrying runes
+    pub postage: u64,
+    // the selected utxos hold more of the rune than sent, or several of them get merged
+    pub change_rune_output: bool,
+    pub runic_inputs: u64,
+    // bitcoin already sitting in the selected runic utxos, counted towards the postage
+    pub btc_in_runic_inputs: u64,
+    pub estimated_fee: u64,
+    pub anchor: u64,
+    // bitcoin the fee payer has to hold on top of the runic utxos
+    pub required_btc: u64,
+    pub fee_payer_balance: u64,
+}
+
+/*
+ * dry run of a sender paid `transfer`. utxos are picked in the same order, but
+ * nothing is taken out of the manager. fails with the rune balance when it doesn't
+ * cover `amount`, a short bitcoin balance is still estimated with one funding input
+*/
+pub fn requirements(
+    runeid: &RuneId,
+    amount: u128,
+    sender_addr: &str,
+    sender_address: &Address,
+    receiver_address: &Address,
+    fee_per_vbytes: u64,
+) -> Result<RuneTransferRequirements, u128> {
+    let postage = DEFAULT_POSTAGE;
+    let anchor_output = anchor_output();
+    let anchor = anchor_value(&anchor_output);
+    let (runic_utxos, btc_utxos) = read_utxo_manager(|manager| {
+        (
+            manager.runic_utxos(sender_addr, runeid),
+            manager.bitcoin_utxos(sender_addr),
+        )
+    });
+
+    let (mut runic_total, mut btc_in_runic, mut runic_inputs) = (0, 0, 0);
+    for r_utxo in runic_utxos.iter() {
+        runic_total += r_utxo.balance;
+        btc_in_runic += r_utxo.utxo.value;
+        runic_inputs += 1;
+        if runic_total > amount {
+            break;
+        }
+    }
+    if runic_total < amount {
+        return Err(runic_total);
+    }
+    let change_rune_output = runic_total > amount || runic_inputs > 1;
+    let rune_outputs = if change_rune_output { 2 } else { 1 };
+    let postage_btc = (postage * rune_outputs).saturating_sub(btc_in_runic);
+    let fee_payer_balance: u64 = btc_utxos.iter().map(|utxo| utxo.value).sum();
+
+    let runestone = Runestone {
+        edicts: vec![Edict {
+            id: ordinals::RuneId {
+                block: runeid.block,
+                tx: runeid.tx,
+            },
+            amount,
+            output: 2,
+        }],
+        ..Default::default()
+    };
+    let mut fee = 0;
+    loop {
+        let required_btc = fee + anchor + postage_btc;
+        let (mut fee_inputs, mut spent) = (0, 0);
+        for utxo in btc_utxos.iter() {
+            spent += utxo.value;
+            fee_inputs += 1;
+            if spent > required_btc {
+                break;
+            }
+        }
+        let mut output = vec![];
+        if change_rune_output {
+            output.push(TxOut {
+                script_pubkey: runestone.encipher(),
+                value: Amount::ZERO,
+            });
+            output.push(TxOut {
+                script_pubkey: sender_address.script_pubkey(),
+                value: Amount::from_sat(postage),
+            });
+        }
+        output.push(TxOut {
+            script_pubkey: receiver_address.script_pubkey(),
+            value: Amount::from_sat(postage),
+        });
+        if spent.saturating_sub(required_btc) > DUST_THRESHOLD {
+            output.push(TxOut {
+                script_pubkey: sender_address.script_pubkey(),
+                value: Amount::from_sat(spent - required_btc),
             });
-            total_fee = (txn_vsize * fee_per_vbytes) / 1000;
         }
+        if let Some(ref anchor) = anchor_output {
+            output.push(anchor.clone());
+        }
+        let txn = unsigned_transaction(
+            vec![TxIn::default(); runic_inputs + fee_inputs.max(1)],
+            output,
+        );
+        let required_fee = fee_at(&txn, fee_per_vbytes);
+        if required_fee == fee {
+            return Ok(RuneTransferRequirements {
+                postage,
+                change_rune_output,
+                runic_inputs: runic_inputs as u64,
+                btc_in_runic_inputs: btc_in_runic,
+                estimated_fee: fee,
+                anchor,
+                required_btc,
+                fee_payer_balance,
+            });
+        }
+        fee = required_fee;
     }
 }
 
 pub fn build_transaction_with_fee(
-    runeid: &RuneId,
-    amount: u128,
+    runes: &[(RuneId, u128)],
     sender_addr: &str,
     receiver_addr: &str,
     sender_address: &Address,
@@ -103,108 +432,178 @@ pub fn build_transaction_with_fee(
     fee: u64,
     paid_by_sender: bool,
     postage: Amount,
-) -> Result<(Transaction, Vec<RunicUtxo>, Vec<Utxo>), (u128, u64)> {
-    const DUST_THRESHOLD: u64 = 1_000;
-
-    let (runic_utxos, runic_total_spent, btc_in_runic) = write_utxo_manager(|manager| {
-        let mut r_utxos = vec![];
-        let mut runic_total_spent = 0;
-        let mut btc_in_runic = 0;
-        while let Some(utxo) = manager.get_runic_utxo(sender_addr, runeid.clone()) {
-            runic_total_spent += utxo.balance;
-            btc_in_runic += utxo.utxo.value;
-            r_utxos.push(utxo);
-            if runic_total_spent > amount {
-                break;
+    anchor: &Option<TxOut>,
+    sponsor: Option<&FeeSponsorship>,
+) -> Result<(Transaction, (Vec<RuneSelection>, Vec<Utxo>)), (u128, u64)> {
+    let selections = write_utxo_manager(|manager| {
+        let mut selections: Vec<RuneSelection> = vec![];
+        for (runeid, amount) in runes {
+            // a sponsored transfer carries a single rune, the sponsor's part comes on top
+            let target = amount.saturating_add(sponsor.map_or(0, |sponsor| sponsor.rune_amount));
+            let mut r_utxos = vec![];
+            let mut runic_total_spent = 0;
+            while let Some(utxo) = manager.get_runic_utxo(sender_addr, runeid.clone()) {
+                runic_total_spent += utxo.balance;
+                r_utxos.push(utxo);
+                if runic_total_spent > target {
+                    break;
+                }
+            }
+            selections.push(RuneSelection {
+                runeid: runeid.clone(),
+                amount: *amount,
+                utxos: r_utxos,
+            });
+            if runic_total_spent < target {
+                for selection in selections {
+                    manager.record_runic_utxos(sender_addr, selection.runeid, selection.utxos);
+                }
+                return Err((*amount, 0));
             }
         }
-
-        if runic_total_spent < amount {
-            manager.record_runic_utxos(sender_addr, runeid.clone(), r_utxos);
-            return Err((amount, 0));
-        }
-        Ok((r_utxos, runic_total_spent, btc_in_runic))
+        Ok(selections)
     })?;
 
-    let need_change_rune_output = runic_total_spent > amount || runic_utxos.len() > 1;
-
-    let required_btc_for_rune_output = if need_change_rune_output {
-        postage * 2
-    } else {
-        postage
-    };
+    let btc_in_runic: u64 = runic_inputs(&selections)
+        .iter()
+        .map(|utxo| utxo.value)
+        .sum();
+    // the anchor output is paid by the fee payer
+    let required_btc = fee
+        + anchor_value(anchor)
+        + (postage * rune_outputs(&selections, sponsor.is_some()))
+            .to_sat()
+            .saturating_sub(btc_in_runic);
 
-    let actual_required_btc = required_btc_for_rune_output.to_sat() - btc_in_runic;
-
-    let (fee_utxos, fee_total_spent) = write_utxo_manager(|manager| {
+    let fee_utxos = write_utxo_manager(|manager| {
         let mut utxos = vec![];
         let mut total_spent = 0;
-        let fee_payer = if paid_by_sender {
-            sender_addr
-        } else {
-            receiver_addr
+        let fee_payer = match sponsor {
+            Some(sponsor) => &sponsor.addr,
+            None if paid_by_sender => sender_addr,
+            None => receiver_addr,
         };
         while let Some(utxo) = manager.get_bitcoin_utxo(fee_payer) {
             total_spent += utxo.value;
             utxos.push(utxo);
-            if total_spent > fee + actual_required_btc {
+            if total_spent > required_btc {
                 break;
             }
         }
-        if total_spent < fee + actual_required_btc {
+        if total_spent < required_btc {
             manager.record_btc_utxos(fee_payer, utxos);
+            for selection in selections.iter() {
+                manager.record_runic_utxos(
+                    sender_addr,
+                    selection.runeid.clone(),
+                    selection.utxos.clone(),
+                );
+            }
             return Err((0, fee));
         }
-        Ok((utxos, total_spent))
+        Ok(utxos)
     })?;
 
-    let mut input = vec![];
-
-    runic_utxos.iter().for_each(|r_utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            witness: Witness::new(),
-            sequence: Sequence::MAX,
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(
-                    Hash::from_slice(&r_utxo.utxo.outpoint.txid).expect("should return hash"),
-                ),
-                vout: r_utxo.utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
+    let txn = assemble(
+        &selections,
+        &fee_utxos,
+        fee,
+        paid_by_sender,
+        sender_address,
+        receiver_address,
+        postage,
+        anchor,
+        sponsor,
+    );
+    Ok((txn, (selections, fee_utxos)))
+}
 
-    fee_utxos.iter().for_each(|utxo| {
-        let txin = TxIn {
-            script_sig: ScriptBuf::new(),
-            witness: Witness::new(),
-            sequence: Sequence::MAX,
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(
-                    Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                ),
-                vout: utxo.outpoint.vout,
-            },
-        };
-        input.push(txin);
-    });
+// a utxo holding several of the sent runes is selected once per rune, but spent once
+pub fn runic_inputs(selections: &[RuneSelection]) -> Vec<Utxo> {
+    let mut inputs: Vec<Utxo> = vec![];
+    for r_utxo in selections
+        .iter()
+        .flat_map(|selection| selection.utxos.iter())
+    {
+        if !inputs
+            .iter()
+            .any(|utxo| utxo.outpoint == r_utxo.utxo.outpoint)
+        {
+            inputs.push(r_utxo.utxo.clone());
+        }
+    }
+    inputs
+}
 
-    let id = ordinals::RuneId {
-        block: runeid.block,
-        tx: runeid.tx,
-    };
-    let runestone = Runestone {
-        edicts: vec![Edict {
-            id,
-            amount,
-            output: 2,
-        }],
-        ..Default::default()
-    };
+/*
+ * leftovers or merged utxos need a change output for the runes staying with the sender.
+ * a sponsored transfer always has one, its edicts route the sponsor's runes as well
+*/
+fn rune_outputs(selections: &[RuneSelection], sponsored: bool) -> u64 {
+    if sponsored {
+        return 3;
+    }
+    let need_change_rune_output = selections.len() > 1
+        || runic_inputs(selections).len() > 1
+        || selections.iter().any(|selection| {
+            selection
+                .utxos
+                .iter()
+                .map(|r_utxo| r_utxo.balance)
+                .sum::<u128>()
+                > selection.amount
+        });
+    if need_change_rune_output {
+        2
+    } else {
+        1
+    }
+}
+
+/*
+ * lays out the transfer, the distinct runic utxos come first followed by the fee
+ * utxos. with a change output every rune gets an edict to the receiver and the
+ * rest falls back to the sender's output, the first non OP_RETURN one. a sponsor's
+ * runes go to an output of its own after the receiver's
+*/
+pub fn assemble(
+    selections: &[RuneSelection],
+    fee_utxos: &[Utxo],
+    fee: u64,
+    paid_by_sender: bool,
+    sender_address: &Address,
+    receiver_address: &Address,
+    postage: Amount,
+    anchor: &Option<TxOut>,
+    sponsor: Option<&FeeSponsorship>,
+) -> Transaction {
+    let fee = fee + anchor_value(anchor);
+    let runic_inputs = runic_inputs(selections);
 
-    let mut output = if need_change_rune_output {
-        vec![
+    let input = tx_inputs(runic_inputs.iter().chain(fee_utxos.iter()));
+
+    let rune_outputs = rune_outputs(selections, sponsor.is_some());
+    let mut output = if rune_outputs > 1 {
+        let edict = |runeid: &RuneId, amount: u128, output: u32| Edict {
+            id: ordinals::RuneId {
+                block: runeid.block,
+                tx: runeid.tx,
+            },
+            amount,
+            output,
+        };
+        let mut edicts: Vec<Edict> = selections
+            .iter()
+            .map(|selection| edict(&selection.runeid, selection.amount, 2))
+            .collect();
+        if let (Some(sponsor), Some(selection)) = (sponsor, selections.first()) {
+            edicts.push(edict(&selection.runeid, sponsor.rune_amount, 3));
+        }
+        let runestone = Runestone {
+            edicts,
+            ..Default::default()
+        };
+        let mut output = vec![
             TxOut {
                 script_pubkey: runestone.encipher(),
                 value: Amount::from_sat(0),
@@ -217,7 +616,12 @@ pub fn build_transaction_with_fee(
                 script_pubkey: receiver_address.script_pubkey(),
                 value: postage,
             },
-        ]
+        ];
+        output.extend(sponsor.map(|sponsor| TxOut {
+            script_pubkey: sponsor.address.script_pubkey(),
+            value: postage,
+        }));
+        output
     } else {
         vec![TxOut {
             script_pubkey: receiver_address.script_pubkey(),
@@ -225,28 +629,406 @@ pub fn build_transaction_with_fee(
         }]
     };
 
-    let remaining = fee_total_spent - fee - actual_required_btc;
+    let available: u64 = runic_inputs
+        .iter()
+        .chain(fee_utxos.iter())
+        .map(|utxo| utxo.value)
+        .sum();
+    let remaining = available - fee - (postage * rune_outputs).to_sat();
+    let fee_payer = match sponsor {
+        Some(sponsor) => &sponsor.address,
+        None if paid_by_sender => sender_address,
+        None => receiver_address,
+    };
+    output.extend(change_output(fee_payer, remaining));
 
-    if remaining > DUST_THRESHOLD {
-        if paid_by_sender {
-            output.push(TxOut {
-                script_pubkey: sender_address.script_pubkey(),
-                value: Amount::from_sat(remaining),
-            });
-        } else {
-            output.push(TxOut {
-                script_pubkey: receiver_address.script_pubkey(),
-                value: Amount::from_sat(remaining),
-            });
-        }
+    if let Some(anchor) = anchor {
+        output.push(anchor.clone());
     }
 
-    let txn = Transaction {
-        input,
-        output,
-        version: Version(2),
-        lock_time: LockTime::ZERO,
+    unsigned_transaction(input, output)
+}
+
+// runes of one kind redistributed over several outputs, all of them back to the owner
+pub struct RuneSplitArgs<'a> {
+    pub runeid: RuneId,
+    // rune amount of each new output, in output order
+    pub parts: Vec<u128>,
+    // sent to the OP_RETURN output, which takes the runes out of supply
+    pub burn: u128,
+    pub addr: &'a str,
+    pub account: Account,
+    pub address: Address,
+    pub fee_per_vbytes: u64,
+    pub postage: Option<u64>,
+}
+
+/*
+ * spends the owner's runic utxos until they cover the parts and the burn, the fee
+ * comes out of their bitcoin. fails like `transfer` with the rune amount or the fee
+ * that wasn't covered
+*/
+pub fn split(
+    RuneSplitArgs {
+        runeid,
+        parts,
+        burn,
+        addr,
+        account,
+        address,
+        fee_per_vbytes,
+        postage,
+    }: RuneSplitArgs,
+) -> Result<TransactionType, (u128, u64)> {
+    let total: u128 = parts.iter().sum::<u128>() + burn;
+    let postage = Amount::from_sat(postage.unwrap_or(DEFAULT_POSTAGE));
+    let anchor = anchor_output();
+    let build = |fee: u64| -> Result<_, (u128, u64)> {
+        let runic_utxos = write_utxo_manager(|manager| {
+            let mut utxos = vec![];
+            let mut selected = 0;
+            while selected < total {
+                let Some(utxo) = manager.get_runic_utxo(addr, runeid.clone()) else {
+                    manager.record_runic_utxos(addr, runeid.clone(), utxos);
+                    return Err((total, 0));
+                };
+                selected += utxo.balance;
+                utxos.push(utxo);
+            }
+            Ok(utxos)
+        })?;
+        /*
+         * without an output of the owner, runes the inputs hold besides the split
+         * ones would follow the burn into the OP_RETURN. a burn always keeps one
+         */
+        let rune_change = burn > 0
+            || runic_utxos
+                .iter()
+                .map(|r_utxo| r_utxo.balance)
+                .sum::<u128>()
+                > total;
+        let rune_outputs = parts.len() as u64 + rune_change as u64;
+        let btc_in_runic: u64 = runic_utxos.iter().map(|r_utxo| r_utxo.utxo.value).sum();
+        let required_btc = fee
+            + anchor_value(&anchor)
+            + (postage * rune_outputs)
+                .to_sat()
+                .saturating_sub(btc_in_runic);
+        let fee_utxos = write_utxo_manager(|manager| {
+            let mut utxos = vec![];
+            let mut spent = 0;
+            while spent < required_btc {
+                let Some(utxo) = manager.get_bitcoin_utxo(addr) else {
+                    manager.record_btc_utxos(addr, utxos);
+                    manager.record_runic_utxos(addr, runeid.clone(), runic_utxos.clone());
+                    return Err((0, fee));
+                };
+                spent += utxo.value;
+                utxos.push(utxo);
+            }
+            Ok(utxos)
+        })?;
+
+        let txn = assemble_split(
+            split_runestone(&runeid, &parts, burn, rune_change),
+            rune_outputs,
+            &runic_utxos,
+            &fee_utxos,
+            fee,
+            &address,
+            postage,
+            &anchor,
+        );
+        Ok((txn, (runic_utxos, fee_utxos, rune_change)))
+    };
+    let (txn, (runic_utxos, fee_utxos, rune_change), _) =
+        settle_fee(fee_per_vbytes, build, |(runic_utxos, fee_utxos, _)| {
+            write_utxo_manager(|manager| {
+                manager.record_runic_utxos(addr, runeid.clone(), runic_utxos);
+                manager.record_btc_utxos(addr, fee_utxos);
+            })
+        })?;
+    Ok(TransactionType::RuneSplit {
+        addr: addr.to_string(),
+        account,
+        address,
+        runeid,
+        parts,
+        burn,
+        runic_utxos,
+        fee_utxos,
+        rune_change,
+        txn,
+        anchor,
+    })
+}
+
+// the smallest runic utxos of a rune merged into a single output of the owner
+pub struct RuneConsolidationArgs<'a> {
+    pub runeid: RuneId,
+    pub max_inputs: usize,
+    pub addr: &'a str,
+    pub account: Account,
+    pub address: Address,
+    pub fee_per_vbytes: u64,
+}
+
+/*
+ * a split into one part holding everything the merged utxos carry, at the minimal
+ * postage. the fee comes out of the postage they free up, with the owner's bitcoin
+ * topping it up when it falls short, and the rest goes back as plain change. runes
+ * of other kinds on the merged utxos follow into the same output. fails like `split`
+ * with the fee that wasn't covered
+*/
+pub fn consolidate(
+    RuneConsolidationArgs {
+        runeid,
+        max_inputs,
+        addr,
+        account,
+        address,
+        fee_per_vbytes,
+    }: RuneConsolidationArgs,
+) -> Result<TransactionType, (u128, u64)> {
+    let postage = Amount::from_sat(MIN_POSTAGE);
+    let anchor = anchor_output();
+    let build = |fee: u64| -> Result<_, (u128, u64)> {
+        let runic_utxos = write_utxo_manager(|manager| {
+            let mut utxos = vec![];
+            while utxos.len() < max_inputs {
+                let Some(utxo) = manager.get_runic_utxo(addr, runeid.clone()) else {
+                    break;
+                };
+                utxos.push(utxo);
+            }
+            utxos
+        });
+        let total: u128 = runic_utxos.iter().map(|r_utxo| r_utxo.balance).sum();
+        let btc_in_runic: u64 = runic_utxos.iter().map(|r_utxo| r_utxo.utxo.value).sum();
+        let required_btc =
+            (fee + anchor_value(&anchor) + postage.to_sat()).saturating_sub(btc_in_runic);
+        let fee_utxos = write_utxo_manager(|manager| {
+            let mut utxos = vec![];
+            let mut spent = 0;
+            while spent < required_btc {
+                let Some(utxo) = manager.get_bitcoin_utxo(addr) else {
+                    manager.record_btc_utxos(addr, utxos);
+                    manager.record_runic_utxos(addr, runeid.clone(), runic_utxos.clone());
+                    return Err((0, fee));
+                };
+                spent += utxo.value;
+                utxos.push(utxo);
+            }
+            Ok(utxos)
+        })?;
+
+        let txn = assemble_split(
+            split_runestone(&runeid, &[total], 0, false),
+            1,
+            &runic_utxos,
+            &fee_utxos,
+            fee,
+            &address,
+            postage,
+            &anchor,
+        );
+        Ok((txn, (runic_utxos, fee_utxos, total)))
+    };
+    let (txn, (runic_utxos, fee_utxos, total), _) =
+        settle_fee(fee_per_vbytes, build, |(runic_utxos, fee_utxos, _)| {
+            write_utxo_manager(|manager| {
+                manager.record_runic_utxos(addr, runeid.clone(), runic_utxos);
+                manager.record_btc_utxos(addr, fee_utxos);
+            })
+        })?;
+    Ok(TransactionType::RuneSplit {
+        addr: addr.to_string(),
+        account,
+        address,
+        runeid,
+        parts: vec![total],
+        burn: 0,
+        runic_utxos,
+        fee_utxos,
+        rune_change: false,
+        txn,
+        anchor,
+    })
+}
+
+/*
+ * an edict per part to the outputs right after the OP_RETURN, the burn goes to the
+ * OP_RETURN itself. whatever the inputs hold beyond that reaches the trailing rune
+ * change output through the pointer
+*/
+fn split_runestone(runeid: &RuneId, parts: &[u128], burn: u128, rune_change: bool) -> Runestone {
+    let id = ordinals::RuneId {
+        block: runeid.block,
+        tx: runeid.tx,
     };
+    let burn = (burn > 0).then_some(Edict {
+        id,
+        amount: burn,
+        output: 0,
+    });
+    Runestone {
+        edicts: burn
+            .into_iter()
+            .chain(parts.iter().enumerate().map(|(index, amount)| Edict {
+                id,
+                amount: *amount,
+                output: index as u32 + 1,
+            }))
+            .collect(),
+        pointer: rune_change.then_some(parts.len() as u32 + 1),
+        ..Default::default()
+    }
+}
+
+// the split's runestone has to stay within core's datacarrier limit, which counts the
+// whole script and so leaves 3 bytes on top of the data for the opcodes and the push
+pub fn ensure_split_relayable(runeid: &RuneId, parts: &[u128], burn: u128) -> Result<(), String> {
+    let size = split_runestone(runeid, parts, burn, true).encipher().len();
+    if size > MAX_OP_RETURN_SIZE + 3 {
+        return Err(format!(
+            "the runestone for {} parts takes {} bytes, at most {} are relayed",
+            parts.len(),
+            size,
+            MAX_OP_RETURN_SIZE + 3
+        ));
+    }
+    Ok(())
+}
+
+// the runestone leads, followed by `rune_outputs` outputs of the owner carrying the postage
+fn assemble_split(
+    runestone: Runestone,
+    rune_outputs: u64,
+    runic_utxos: &[RunicUtxo],
+    fee_utxos: &[Utxo],
+    fee: u64,
+    address: &Address,
+    postage: Amount,
+    anchor: &Option<TxOut>,
+) -> Transaction {
+    let fee = fee + anchor_value(anchor);
+    let inputs: Vec<&Utxo> = runic_utxos
+        .iter()
+        .map(|r_utxo| &r_utxo.utxo)
+        .chain(fee_utxos.iter())
+        .collect();
+    let input = tx_inputs(inputs.iter().copied());
+
+    let mut output = vec![TxOut {
+        script_pubkey: runestone.encipher(),
+        value: Amount::ZERO,
+    }];
+    output.extend((0..rune_outputs).map(|_| TxOut {
+        script_pubkey: address.script_pubkey(),
+        value: postage,
+    }));
+
+    let available: u64 = inputs.iter().map(|utxo| utxo.value).sum();
+    let remaining = available - fee - (postage * rune_outputs).to_sat();
+    output.extend(change_output(address, remaining));
+    if let Some(anchor) = anchor {
+        output.push(anchor.clone());
+    }
+
+    unsigned_transaction(input, output)
+}
+
+#[derive(CandidType)]
+pub struct DecodedEdict {
+    pub id: RuneId,
+    pub amount: u128,
+    pub output: u32,
+}
+
+#[derive(CandidType)]
+pub struct DecodedTerms {
+    pub amount: Option<u128>,
+    pub cap: Option<u128>,
+    // absolute and relative (start, end) heights of the mint window
+    pub height: (Option<u64>, Option<u64>),
+    pub offset: (Option<u64>, Option<u64>),
+}
+
+#[derive(CandidType)]
+pub struct DecodedEtching {
+    // spaced rune name, e.g. UNCOMMON•GOODS
+    pub rune: Option<String>,
+    pub divisibility: Option<u8>,
+    pub premine: Option<u128>,
+    pub spacers: Option<u32>,
+    pub symbol: Option<String>,
+    pub terms: Option<DecodedTerms>,
+    pub turbo: bool,
+}
+
+// the runestone of a transaction, a malformed one burns the runes of its inputs
+#[derive(CandidType)]
+pub enum DecodedArtifact {
+    Runestone {
+        edicts: Vec<DecodedEdict>,
+        etching: Option<DecodedEtching>,
+        mint: Option<RuneId>,
+        pointer: Option<u32>,
+    },
+    Cenotaph {
+        flaw: Option<String>,
+        etching: Option<String>,
+        mint: Option<RuneId>,
+    },
+}
+
+fn decoded_rune_id(id: ordinals::RuneId) -> RuneId {
+    RuneId {
+        block: id.block,
+        tx: id.tx,
+    }
+}
 
-    Ok((txn, runic_utxos, fee_utxos))
+// None when the transaction carries no runestone at all
+pub fn decode(txn: &Transaction) -> Option<DecodedArtifact> {
+    Some(match Runestone::decipher(txn)? {
+        ordinals::Artifact::Runestone(runestone) => DecodedArtifact::Runestone {
+            edicts: runestone
+                .edicts
+                .into_iter()
+                .map(|edict| DecodedEdict {
+                    id: decoded_rune_id(edict.id),
+                    amount: edict.amount,
+                    output: edict.output,
+                })
+                .collect(),
+            etching: runestone.etching.map(|etching| DecodedEtching {
+                rune: etching.rune.map(|rune| {
+                    ordinals::SpacedRune {
+                        rune,
+                        spacers: etching.spacers.unwrap_or_default(),
+                    }
+                    .to_string()
+                }),
+                divisibility: etching.divisibility,
+                premine: etching.premine,
+                spacers: etching.spacers,
+                symbol: etching.symbol.map(String::from),
+                terms: etching.terms.map(|terms| DecodedTerms {
+                    amount: terms.amount,
+                    cap: terms.cap,
+                    height: terms.height,
+                    offset: terms.offset,
+                }),
+                turbo: etching.turbo,
+            }),
+            mint: runestone.mint.map(decoded_rune_id),
+            pointer: runestone.pointer,
+        },
+        ordinals::Artifact::Cenotaph(cenotaph) => DecodedArtifact::Cenotaph {
+            flaw: cenotaph.flaw.map(|flaw| flaw.to_string()),
+            etching: cenotaph.etching.map(|rune| rune.to_string()),
+            mint: cenotaph.mint.map(decoded_rune_id),
+        },
+    })
 }
@@ -3,11 +3,12 @@ use bitcoin::{
     sighash::EcdsaSighashType,
     Sequence, Transaction, TxIn, Witness,
 };
-use ic_cdk::api::management_canister::ecdsa::{
-    sign_with_ecdsa, SignWithEcdsaArgument, SignWithEcdsaResponse,
+use ic_cdk::api::management_canister::{
+    ecdsa::{sign_with_ecdsa, SignWithEcdsaArgument, SignWithEcdsaResponse},
+    schnorr::{sign_with_schnorr, SignWithSchnorrArgument},
 };
 
-use crate::state::read_config;
+use crate::{metrics, state::read_config};
 
 use super::utils::*;
 
@@ -21,10 +22,7 @@ pub fn mock_signature(txn: &Transaction) -> Transaction {
         .input
         .iter()
         .map(|input| {
-            let signature = vec![255; 64];
-            let mut der_signature = sec1_to_der(signature);
-            der_signature.push(EcdsaSighashType::All.to_u32() as u8);
-            let signature_as_pushbytes = PushBytesBuf::try_from(der_signature).unwrap();
+            let signature_as_pushbytes = PushBytesBuf::try_from(mock_ecdsa_signature()).unwrap();
             let publickey_as_pushbytes = PushBytesBuf::try_from(pubkey.clone()).unwrap();
             TxIn {
                 previous_output: input.previous_output,
@@ -45,20 +43,50 @@ pub fn mock_signature(txn: &Transaction) -> Transaction {
     }
 }
 
+// stands in for an ecdsa signature where only its size matters, as long as der gets
+pub fn mock_ecdsa_signature() -> Vec<u8> {
+    let mut der_signature = sec1_to_der(vec![255; 64]);
+    der_signature.push(EcdsaSighashType::All.to_u32() as u8);
+    der_signature
+}
+
+// bip340 signatures are always 64 bytes, so any will do for sizing
+pub fn mock_schnorr_signature() -> Vec<u8> {
+    vec![255; 64]
+}
+
 pub async fn ecdsa_sign(
     message_hash: Vec<u8>,
     derivation_path: Vec<Vec<u8>>,
 ) -> SignWithEcdsaResponse {
     let key_id = read_config(|config| config.ecdsakeyid());
 
-    sign_with_ecdsa(SignWithEcdsaArgument {
+    let response = sign_with_ecdsa(SignWithEcdsaArgument {
         message_hash,
         derivation_path,
         key_id,
     })
     .await
     .unwrap()
-    .0
+    .0;
+    metrics::record_signature_call();
+    response
+}
+
+// bip340 signature over a taproot sighash, 64 bytes
+pub async fn schnorr_sign(message: Vec<u8>, derivation_path: Vec<Vec<u8>>) -> Vec<u8> {
+    let key_id = read_config(|config| config.schnorrkeyid());
+
+    let response = sign_with_schnorr(SignWithSchnorrArgument {
+        message,
+        derivation_path,
+        key_id,
+    })
+    .await
+    .unwrap()
+    .0;
+    metrics::record_signature_call();
+    response.signature
 }
 
 pub fn sign_transaction() {}
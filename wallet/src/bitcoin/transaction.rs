@@ -1,111 +1,551 @@
-use bitcoin::{
-    absolute::LockTime, hashes::Hash, transaction::Version, Address, Amount, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
-};
+use bitcoin::{script::PushBytesBuf, Address, Amount, ScriptBuf, Transaction, TxOut};
 use ic_cdk::api::management_canister::bitcoin::Utxo;
 use icrc_ledger_types::icrc1::account::Account;
 
 use crate::{
-    bitcoin::signer::mock_signature, state::write_utxo_manager,
-    transaction_handler::TransactionType,
+    bitcoin::{
+        cpfp::anchor_output,
+        planner::{anchor_value, fee_at, settle_fee, tx_inputs, unsigned_transaction},
+        MAX_TX_INPUTS, P2PKH_INPUT_VSIZE,
+    },
+    state::{read_config, write_utxo_manager},
+    transaction_handler::{BatchSender, TransactionType},
+    types::{CoinSelection, FeePayer},
 };
 
+// outputs at or below are dust, receivers never get one
+pub const DUST_THRESHOLD: u64 = 1_000;
+
+// most change outputs a split may create
+pub const MAX_CHANGE_OUTPUTS: u32 = 20;
+
+// one derivation branch of a principal, either the receive or the change chain
+#[derive(Clone)]
+pub struct Branch<'a> {
+    pub addr: &'a str,
+    pub account: Account,
+    pub address: Address,
+}
+
+pub struct BitcoinTransferArgs<'a> {
+    pub receive: Branch<'a>,
+    pub change: Branch<'a>,
+    pub to: Address,
+    pub amount: u64,
+    pub fee_payer: FeePayer,
+    pub fee_per_vbytes: u64,
+    pub selection: CoinSelection,
+    // tag carried by an OP_RETURN output, at most `MAX_OP_RETURN_SIZE` bytes
+    pub memo: Option<Vec<u8>>,
+    // where the change output goes instead of the change branch, its utxos are spent all the same
+    pub change_to: Option<Address>,
+}
+
+fn memo_output(memo: &[u8]) -> TxOut {
+    let data = PushBytesBuf::try_from(memo.to_vec()).expect("memo should fit a push");
+    TxOut {
+        script_pubkey: ScriptBuf::new_op_return(data),
+        value: Amount::ZERO,
+    }
+}
+
 pub fn transfer(
-    addr: &str,
-    account: Account,
-    from: Address,
-    to: Address,
-    amount: u64,
-    paid_by_sender: bool,
-    fee_per_vbytes: u64,
+    BitcoinTransferArgs {
+        receive,
+        change,
+        to,
+        amount,
+        fee_payer,
+        fee_per_vbytes,
+        selection,
+        memo,
+        change_to,
+    }: BitcoinTransferArgs,
 ) -> Result<TransactionType, u64> {
-    let mut total_fee = 0;
-    loop {
-        let (txn, utxos) =
-            build_transaction_with_fee(addr, &from, &to, amount, total_fee, paid_by_sender)?;
-        let signed_txn = mock_signature(&txn);
-
-        let txn_vsize = signed_txn.vsize() as u64;
-        if (txn_vsize * fee_per_vbytes) / 1000 == total_fee {
-            return Ok(TransactionType::Bitcoin {
-                addr: addr.to_string(),
-                utxos,
-                signer_account: account,
-                signer_address: from,
-                txn,
-            });
-        } else {
-            write_utxo_manager(|state| state.record_btc_utxos(addr, utxos));
-            total_fee = (txn_vsize * fee_per_vbytes) / 1000;
+    let anchor = anchor_output();
+    let memo = memo.as_deref().map(memo_output);
+    let change_to = change_to.unwrap_or_else(|| change.address.clone());
+    let into_transaction = |txn, utxos, change_utxos, anchor| TransactionType::Bitcoin {
+        addr: receive.addr.to_string(),
+        utxos,
+        signer_account: receive.account,
+        signer_address: receive.address.clone(),
+        change_addr: change.addr.to_string(),
+        change_utxos,
+        change_account: change.account,
+        change_address: change.address.clone(),
+        txn,
+        anchor,
+    };
+    // a floored receiver share depends on the fee, left to the regular selection
+    if selection == CoinSelection::BranchAndBound && fee_payer != FeePayer::ReceiverAboveDust {
+        if let Some((txn, utxos, change_utxos)) = build_changeless_transaction(
+            &receive,
+            &change,
+            &to,
+            amount,
+            fee_payer == FeePayer::Sender,
+            fee_per_vbytes,
+            &memo,
+            &anchor,
+        ) {
+            return Ok(into_transaction(txn, utxos, change_utxos, anchor));
+        }
+    }
+    let (txn, (utxos, change_utxos), _) = settle_fee(
+        fee_per_vbytes,
+        |fee| {
+            build_transaction_with_fee(
+                &receive, &change, &change_to, &to, amount, fee, fee_payer, &memo, &anchor,
+                selection,
+            )
+        },
+        |(utxos, change_utxos)| release(&receive, &change, utxos, change_utxos),
+    )?;
+    Ok(into_transaction(txn, utxos, change_utxos, anchor))
+}
+
+// puts the utxos of a discarded selection back on their branches
+fn release(receive: &Branch, change: &Branch, utxos: Vec<Utxo>, change_utxos: Vec<Utxo>) {
+    write_utxo_manager(|manager| {
+        manager.record_btc_utxos(receive.addr, utxos);
+        manager.record_btc_utxos(change.addr, change_utxos);
+    });
+}
+
+pub struct BitcoinSweepArgs<'a> {
+    pub receive: Branch<'a>,
+    pub change: Branch<'a>,
+    pub to: Address,
+    pub fee_per_vbytes: u64,
+}
+
+/*
+ * spends the largest utxos of both branches, up to `MAX_TX_INPUTS`, without a change
+ * output. the receiver gets whatever is left after the fee, which is returned along
+ * with the transaction. the error holds the value the fee needs, the utxos are back
+ * in place then
+*/
+pub fn transfer_all(
+    BitcoinSweepArgs {
+        receive,
+        change,
+        to,
+        fee_per_vbytes,
+    }: BitcoinSweepArgs,
+) -> Result<(TransactionType, u64), u64> {
+    let anchor = anchor_output();
+    let (utxos, change_utxos) = write_utxo_manager(|manager| {
+        let mut utxos = vec![];
+        let mut change_utxos = vec![];
+        while utxos.len() < MAX_TX_INPUTS {
+            match manager.take_bitcoin_utxo(receive.addr, CoinSelection::LargestFirst) {
+                Some(utxo) => utxos.push(utxo),
+                None => break,
+            }
+        }
+        // a hot wallet keeps its change on the deposit address, already drained above
+        while receive.addr != change.addr && utxos.len() + change_utxos.len() < MAX_TX_INPUTS {
+            match manager.take_bitcoin_utxo(change.addr, CoinSelection::LargestFirst) {
+                Some(utxo) => change_utxos.push(utxo),
+                None => break,
+            }
         }
+        (utxos, change_utxos)
+    });
+    let spent: u64 = utxos
+        .iter()
+        .chain(change_utxos.iter())
+        .map(|utxo| utxo.value)
+        .sum();
+    let mut output = vec![TxOut {
+        script_pubkey: to.script_pubkey(),
+        value: Amount::ZERO,
+    }];
+    if let Some(ref anchor) = anchor {
+        output.push(anchor.clone());
+    }
+    let mut txn = unsigned_transaction(tx_inputs(utxos.iter().chain(change_utxos.iter())), output);
+    // every input is known up front, so the size doesn't depend on the swept value
+    let fee = fee_at(&txn, fee_per_vbytes) + anchor_value(&anchor);
+    if spent <= fee + DUST_THRESHOLD {
+        release(&receive, &change, utxos, change_utxos);
+        return Err(fee + DUST_THRESHOLD + 1);
+    }
+    let swept = spent - fee;
+    txn.output[0].value = Amount::from_sat(swept);
+    let txn = TransactionType::Bitcoin {
+        addr: receive.addr.to_string(),
+        utxos,
+        signer_account: receive.account,
+        signer_address: receive.address,
+        change_addr: change.addr.to_string(),
+        change_utxos,
+        change_account: change.account,
+        change_address: change.address,
+        txn,
+        anchor,
+    };
+    Ok((txn, swept))
+}
+
+pub struct BitcoinBatchTransferArgs<'a> {
+    pub receive: Branch<'a>,
+    pub change: Branch<'a>,
+    // receivers in output order
+    pub payouts: Vec<(Address, u64)>,
+    pub fee_per_vbytes: u64,
+    pub change_to: Option<Address>,
+}
+
+/*
+ * pays every receiver out of the sender's branches in a single transaction with one
+ * change output, the sender pays the fee. the receivers' outputs come first, in the
+ * order given. the error holds the value the inputs need, the utxos are back in place then
+*/
+pub fn transfer_batch(
+    BitcoinBatchTransferArgs {
+        receive,
+        change,
+        payouts,
+        fee_per_vbytes,
+        change_to,
+    }: BitcoinBatchTransferArgs,
+) -> Result<TransactionType, u64> {
+    // no set of utxos covers payouts summing past u64
+    let amount = payouts
+        .iter()
+        .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+        .ok_or(u64::MAX)?;
+    let change_to = change_to.unwrap_or_else(|| change.address.clone());
+    let (txn, (utxos, change_utxos), _) = settle_fee(
+        fee_per_vbytes,
+        |fee| -> Result<_, u64> {
+            let (utxos, change_utxos, total_spent) =
+                take_utxos(&receive, &change, amount + fee, CoinSelection::default())?;
+            let mut output: Vec<TxOut> = payouts
+                .iter()
+                .map(|(to, amount)| TxOut {
+                    script_pubkey: to.script_pubkey(),
+                    value: Amount::from_sat(*amount),
+                })
+                .collect();
+            output.extend(change_outputs(&change_to, total_spent - amount - fee));
+            let input = tx_inputs(utxos.iter().chain(change_utxos.iter()));
+            Ok((unsigned_transaction(input, output), (utxos, change_utxos)))
+        },
+        |(utxos, change_utxos)| release(&receive, &change, utxos, change_utxos),
+    )?;
+    let mut senders = vec![];
+    if !utxos.is_empty() {
+        senders.push(BatchSender {
+            addr: receive.addr.to_string(),
+            account: receive.account,
+            address: receive.address,
+            utxos,
+        });
     }
+    if !change_utxos.is_empty() {
+        senders.push(BatchSender {
+            addr: change.addr.to_string(),
+            account: change.account,
+            address: change.address,
+            utxos: change_utxos,
+        });
+    }
+    Ok(TransactionType::Batch {
+        senders,
+        payouts: payouts.len(),
+        txn,
+    })
 }
 
 fn build_transaction_with_fee(
-    addr: &str,
-    from: &Address,
+    receive: &Branch,
+    change: &Branch,
+    change_to: &Address,
     to: &Address,
     amount: u64,
     fee: u64,
-    paid_by_sender: bool,
-) -> Result<(Transaction, Vec<Utxo>), u64> {
-    const DUST_THRESHOLD: u64 = 1_000;
-    let total_amount = if paid_by_sender { amount + fee } else { amount };
+    fee_payer: FeePayer,
+    memo: &Option<TxOut>,
+    anchor: &Option<TxOut>,
+    selection: CoinSelection,
+) -> Result<(Transaction, (Vec<Utxo>, Vec<Utxo>)), u64> {
+    let fee = fee + anchor_value(anchor);
+    let receiver_fee = receiver_fee(amount, fee, fee_payer)?;
+    let total_amount = amount + fee - receiver_fee;
+
+    let (utxos_to_spend, change_utxos_to_spend, total_spent) =
+        take_utxos(receive, change, total_amount, selection)?;
 
-    let (utxos_to_spend, total_spent) = write_utxo_manager(|manager| {
+    let input = tx_inputs(utxos_to_spend.iter().chain(change_utxos_to_spend.iter()));
+
+    let mut output = vec![TxOut {
+        script_pubkey: to.script_pubkey(),
+        value: Amount::from_sat(amount - receiver_fee),
+    }];
+
+    let remaining = total_spent - total_amount;
+    output.extend(change_outputs(change_to, remaining));
+    // ahead of the anchor, which has to stay the last output
+    if let Some(memo) = memo {
+        output.push(memo.clone());
+    }
+    if let Some(anchor) = anchor {
+        output.push(anchor.clone());
+    }
+    Ok((
+        unsigned_transaction(input, output),
+        (utxos_to_spend, change_utxos_to_spend),
+    ))
+}
+
+/*
+ * change above the split policy's threshold is spread over equal outputs, so the
+ * address keeps several coins and concurrent withdrawals don't wait on the same
+ * one. no output drops to dust, the last one takes the rounding remainder
+*/
+fn change_outputs(change: &Address, remaining: u64) -> Vec<TxOut> {
+    if remaining <= DUST_THRESHOLD {
+        return vec![];
+    }
+    let parts = match read_config(|config| config.change_split_policy.clone()) {
+        Some(policy) if remaining > policy.threshold => (policy.outputs as u64)
+            .min(remaining / (DUST_THRESHOLD + 1))
+            .max(1),
+        _ => 1,
+    };
+    let share = remaining / parts;
+    (0..parts)
+        .map(|part| TxOut {
+            script_pubkey: change.script_pubkey(),
+            value: Amount::from_sat(if part + 1 == parts {
+                remaining - share * (parts - 1)
+            } else {
+                share
+            }),
+        })
+        .collect()
+}
+
+/*
+ * the part of the fee taken out of the receiver's output. a receiver paying all of it
+ * has to stay above dust, which is reported as a required value above `amount` that no
+ * balance can satisfy. a floored receiver pays down to just above dust and the sender
+ * covers what's left
+*/
+fn receiver_fee(amount: u64, fee: u64, fee_payer: FeePayer) -> Result<u64, u64> {
+    match fee_payer {
+        FeePayer::Sender => Ok(0),
+        FeePayer::Receiver if amount <= fee + DUST_THRESHOLD => Err(fee + DUST_THRESHOLD + 1),
+        FeePayer::Receiver => Ok(fee),
+        FeePayer::ReceiverAboveDust => Ok(fee.min(amount.saturating_sub(DUST_THRESHOLD + 1))),
+    }
+}
+
+// receive branch utxos are spent first, the change branch tops up the rest
+fn take_utxos(
+    receive: &Branch,
+    change: &Branch,
+    total_amount: u64,
+    selection: CoinSelection,
+) -> Result<(Vec<Utxo>, Vec<Utxo>, u64), u64> {
+    write_utxo_manager(|manager| {
         let mut utxos = vec![];
+        let mut change_utxos = vec![];
         let mut sum = 0;
 
-        while let Some(utxo) = manager.get_bitcoin_utxo(addr) {
-            sum += utxo.value;
-            utxos.push(utxo);
-            if sum > total_amount {
-                break;
+        while sum <= total_amount {
+            match manager.take_bitcoin_utxo(receive.addr, selection) {
+                Some(utxo) => {
+                    sum += utxo.value;
+                    utxos.push(utxo);
+                }
+                None => break,
+            }
+        }
+        while sum <= total_amount {
+            match manager.take_bitcoin_utxo(change.addr, selection) {
+                Some(utxo) => {
+                    sum += utxo.value;
+                    change_utxos.push(utxo);
+                }
+                None => break,
             }
         }
         if sum < total_amount {
-            manager.record_btc_utxos(addr, utxos);
+            manager.record_btc_utxos(receive.addr, utxos);
+            manager.record_btc_utxos(change.addr, change_utxos);
             return Err(total_amount);
         }
-        Ok((utxos, sum))
+        Ok((utxos, change_utxos, sum))
+    })
+}
+
+/*
+ * spends a branch and bound selection that leaves at most dust over the amount and
+ * fee, the leftover goes to the miners instead of a change output. the fee of each
+ * input is taken into account while searching, so the selection still covers the
+ * fee it adds. none when no such selection exists, with every utxo back in place
+*/
+fn build_changeless_transaction(
+    receive: &Branch,
+    change: &Branch,
+    to: &Address,
+    amount: u64,
+    paid_by_sender: bool,
+    fee_per_vbytes: u64,
+    memo: &Option<TxOut>,
+    anchor: &Option<TxOut>,
+) -> Option<(Transaction, Vec<Utxo>, Vec<Utxo>)> {
+    let anchor_value = anchor_value(anchor);
+    let transaction = |input, value| {
+        let mut output = vec![TxOut {
+            script_pubkey: to.script_pubkey(),
+            value: Amount::from_sat(value),
+        }];
+        if let Some(memo) = memo {
+            output.push(memo.clone());
+        }
+        if let Some(anchor) = anchor {
+            output.push(anchor.clone());
+        }
+        unsigned_transaction(input, output)
+    };
+    // a receiver paying the fee absorbs whatever the inputs add to it
+    let (target, input_cost) = if paid_by_sender {
+        let outputs_fee = transaction(vec![], amount).vsize() as u64 * fee_per_vbytes / 1000;
+        (
+            amount + outputs_fee + anchor_value,
+            P2PKH_INPUT_VSIZE * fee_per_vbytes / 1000,
+        )
+    } else {
+        (amount, 0)
+    };
+    // branches sharing an address would offer its utxos twice
+    let addrs = if receive.addr == change.addr {
+        vec![receive.addr]
+    } else {
+        vec![receive.addr, change.addr]
+    };
+    let mut taken = write_utxo_manager(|manager| {
+        manager.take_changeless_bitcoin_utxos(&addrs, target, input_cost, DUST_THRESHOLD)
     })?;
+    let change_utxos = if taken.len() > 1 {
+        taken.pop().unwrap_or_default()
+    } else {
+        vec![]
+    };
+    let utxos = taken.pop().unwrap_or_default();
 
-    let input: Vec<TxIn> = utxos_to_spend
+    // the search estimates the fee, the mocked signatures give the exact one
+    let spent: u64 = utxos
         .iter()
-        .map(|utxo| TxIn {
-            sequence: Sequence::MAX,
-            script_sig: ScriptBuf::new(),
-            witness: Witness::new(),
-            previous_output: OutPoint {
-                txid: Txid::from_raw_hash(
-                    Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                ),
-                vout: utxo.outpoint.vout,
-            },
-        })
-        .collect();
+        .chain(change_utxos.iter())
+        .map(|utxo| utxo.value)
+        .sum();
+    let input = tx_inputs(utxos.iter().chain(change_utxos.iter()));
+    let fee = fee_at(&transaction(input.clone(), amount), fee_per_vbytes) + anchor_value;
+    let (required, value) = if paid_by_sender {
+        (amount + fee, amount)
+    } else {
+        (amount, amount.saturating_sub(fee))
+    };
+    let fits = spent >= required
+        && spent - required <= DUST_THRESHOLD
+        && (paid_by_sender || amount > fee + DUST_THRESHOLD);
+    if !fits {
+        release(receive, change, utxos, change_utxos);
+        return None;
+    }
+    Some((transaction(input, value), utxos, change_utxos))
+}
 
-    let mut output = vec![TxOut {
-        script_pubkey: to.script_pubkey(),
-        value: if paid_by_sender {
-            Amount::from_sat(amount)
-        } else {
-            Amount::from_sat(amount - fee)
-        },
-    }];
+#[cfg(test)]
+mod tests {
+    use bitcoin::address::NetworkUnchecked;
 
-    let remaining = total_spent - total_amount;
-    if remaining > DUST_THRESHOLD {
-        output.push(TxOut {
-            script_pubkey: from.script_pubkey(),
-            value: Amount::from_sat(remaining),
+    use super::*;
+    use crate::state::{write_config, ChangeSplitPolicy};
+
+    fn change_address() -> Address {
+        "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+            .parse::<Address<NetworkUnchecked>>()
+            .unwrap()
+            .assume_checked()
+    }
+
+    fn set_policy(policy: Option<ChangeSplitPolicy>) {
+        write_config(|config| {
+            let mut temp = config.get().clone();
+            temp.change_split_policy = policy;
+            let _ = config.set(temp);
         });
     }
-    let txn = Transaction {
-        input,
-        output,
-        lock_time: LockTime::ZERO,
-        version: Version(2),
-    };
-    Ok((txn, utxos_to_spend))
+
+    fn values(outputs: Vec<TxOut>) -> Vec<u64> {
+        outputs.iter().map(|output| output.value.to_sat()).collect()
+    }
+
+    #[test]
+    fn change_outputs_drop_dust() {
+        assert!(change_outputs(&change_address(), DUST_THRESHOLD).is_empty());
+        assert_eq!(
+            values(change_outputs(&change_address(), DUST_THRESHOLD + 1)),
+            vec![DUST_THRESHOLD + 1]
+        );
+    }
+
+    #[test]
+    fn change_outputs_split_above_threshold() {
+        set_policy(Some(ChangeSplitPolicy {
+            threshold: 10_000,
+            outputs: 3,
+        }));
+        assert_eq!(
+            values(change_outputs(&change_address(), 10_000)),
+            vec![10_000]
+        );
+        assert_eq!(
+            values(change_outputs(&change_address(), 10_001)),
+            vec![3_333, 3_333, 3_335]
+        );
+    }
+
+    #[test]
+    fn change_outputs_stay_above_dust() {
+        set_policy(Some(ChangeSplitPolicy {
+            threshold: 1_500,
+            outputs: 3,
+        }));
+        assert_eq!(
+            values(change_outputs(&change_address(), 2_500)),
+            vec![1_250, 1_250]
+        );
+    }
+
+    #[test]
+    fn receiver_fee_by_payer() {
+        assert_eq!(receiver_fee(5_000, 500, FeePayer::Sender), Ok(0));
+        assert_eq!(receiver_fee(5_000, 500, FeePayer::Receiver), Ok(500));
+        assert_eq!(
+            receiver_fee(5_000, 500, FeePayer::ReceiverAboveDust),
+            Ok(500)
+        );
+    }
+
+    #[test]
+    fn receiver_fee_near_dust() {
+        assert_eq!(
+            receiver_fee(1_500, 500, FeePayer::Receiver),
+            Err(500 + DUST_THRESHOLD + 1)
+        );
+        assert_eq!(receiver_fee(1_501, 500, FeePayer::Receiver), Ok(500));
+        assert_eq!(
+            receiver_fee(1_200, 500, FeePayer::ReceiverAboveDust),
+            Ok(1_200 - DUST_THRESHOLD - 1)
+        );
+        assert_eq!(receiver_fee(800, 500, FeePayer::ReceiverAboveDust), Ok(0));
+    }
 }
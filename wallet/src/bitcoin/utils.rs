@@ -1,16 +1,58 @@
-use crate::EcdsaPublicKey;
+use crate::{
+    state::{read_config, DerivationScheme},
+    EcdsaPublicKey,
+};
+use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
 use ic_crypto_secp256k1::{DerivationIndex, DerivationPath, PublicKey};
 use icrc_ledger_types::icrc1::account::Account;
 use serde_bytes::ByteBuf;
 
 use sha2::Digest;
 
+// BIP44 purpose level
+const BIP44_PURPOSE: u32 = 44;
+
 pub fn account_to_derivation_path(account: &Account) -> Vec<ByteBuf> {
-    vec![
-        ByteBuf::from([1u8]),
-        ByteBuf::from(account.owner.as_slice().to_vec()),
-        ByteBuf::from(account.effective_subaccount()),
+    let scheme = read_config(|config| config.derivation_scheme());
+    derivation_path(account, scheme)
+}
+
+pub fn derivation_path(account: &Account, scheme: DerivationScheme) -> Vec<ByteBuf> {
+    match scheme {
+        DerivationScheme::Legacy => vec![
+            ByteBuf::from([1u8]),
+            ByteBuf::from(account.owner.as_slice().to_vec()),
+            ByteBuf::from(account.effective_subaccount()),
+        ],
+        DerivationScheme::Bip44 => bip44_derivation_path(account),
+    }
+}
+
+/*
+ * m/44/coin/account/0/index, the account and index levels are the first two words
+ * of the subaccount. threshold keys only derive unhardened children, so the levels
+ * BIP44 hardens are unhardened here. the owner is always the canister, whose master
+ * key the path starts from
+*/
+fn bip44_derivation_path(account: &Account) -> Vec<ByteBuf> {
+    let subaccount = account.effective_subaccount();
+    let coin = match read_config(|config| config.bitcoin_network()) {
+        BitcoinNetwork::Mainnet => 0,
+        BitcoinNetwork::Testnet | BitcoinNetwork::Regtest => 1,
+    };
+    let level = |word: &[u8]| {
+        u32::from_be_bytes(word.try_into().expect("subaccount word is 4 bytes")) & 0x7fff_ffff
+    };
+    [
+        BIP44_PURPOSE,
+        coin,
+        level(&subaccount[..4]),
+        0,
+        level(&subaccount[4..8]),
     ]
+    .into_iter()
+    .map(|index| ByteBuf::from(index.to_be_bytes().to_vec()))
+    .collect()
 }
 
 pub fn derive_public_key(ecdsa_public_key: &EcdsaPublicKey, path: &[ByteBuf]) -> EcdsaPublicKey {
@@ -0,0 +1,95 @@
+use bitcoin::{
+    ecdsa::Signature,
+    hashes::Hash,
+    script::Instruction,
+    secp256k1::{Message, Secp256k1, XOnlyPublicKey},
+    sighash::{Prevouts, SighashCache},
+    taproot, PublicKey, ScriptBuf, Transaction, TxIn, TxOut,
+};
+
+/*
+ * checks every input's script_sig against the script_pubkey of the output it spends.
+ * rust-bitcoin doesn't ship a script interpreter, so p2pkh spends are verified by hand:
+ * the pushed public key has to hash to the spent output and the signature has to
+ * validate against the input's legacy sighash. p2tr key path spends carry a single
+ * schnorr signature in the witness, checked against the output key
+*/
+pub fn verify_signatures(txn: &Transaction, spent_outputs: &[TxOut]) -> Result<(), String> {
+    if txn.input.len() != spent_outputs.len() {
+        return Err(format!(
+            "expected {} spent outputs, got {}",
+            txn.input.len(),
+            spent_outputs.len()
+        ));
+    }
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(txn);
+    for (index, (input, spent)) in txn.input.iter().zip(spent_outputs).enumerate() {
+        let script_pubkey = &spent.script_pubkey;
+        if script_pubkey.is_p2tr() {
+            verify_key_path_spend(&secp, &mut cache, index, input, spent_outputs)?;
+            continue;
+        }
+        if !script_pubkey.is_p2pkh() {
+            return Err(format!("input {}: unsupported script type", index));
+        }
+        let mut instructions = input.script_sig.instructions();
+        let (signature, pubkey) = match (
+            instructions.next(),
+            instructions.next(),
+            instructions.next(),
+        ) {
+            (
+                Some(Ok(Instruction::PushBytes(signature))),
+                Some(Ok(Instruction::PushBytes(pubkey))),
+                None,
+            ) => (signature, pubkey),
+            _ => return Err(format!("input {}: malformed script_sig", index)),
+        };
+        let signature = Signature::from_slice(signature.as_bytes())
+            .map_err(|e| format!("input {}: {}", index, e))?;
+        let pubkey = PublicKey::from_slice(pubkey.as_bytes())
+            .map_err(|e| format!("input {}: {}", index, e))?;
+        if ScriptBuf::new_p2pkh(&pubkey.pubkey_hash()) != *script_pubkey {
+            return Err(format!(
+                "input {}: signed with a key not owning the spent output",
+                index
+            ));
+        }
+        let sighash = cache
+            .legacy_signature_hash(index, script_pubkey, signature.sighash_type.to_u32())
+            .map_err(|e| format!("input {}: {}", index, e))?;
+        let message = Message::from_digest(sighash.to_byte_array());
+        secp.verify_ecdsa(&message, &signature.signature, &pubkey.inner)
+            .map_err(|e| format!("input {}: {}", index, e))?;
+    }
+    Ok(())
+}
+
+fn verify_key_path_spend(
+    secp: &Secp256k1<bitcoin::secp256k1::VerifyOnly>,
+    cache: &mut SighashCache<&Transaction>,
+    index: usize,
+    input: &TxIn,
+    spent_outputs: &[TxOut],
+) -> Result<(), String> {
+    if !input.script_sig.is_empty() || input.witness.len() != 1 {
+        return Err(format!("input {}: malformed key path witness", index));
+    }
+    let signature = taproot::Signature::from_slice(&input.witness[0])
+        .map_err(|e| format!("input {}: {}", index, e))?;
+    // p2tr script_pubkey is OP_1 followed by a push of the 32 byte output key
+    let output_key =
+        XOnlyPublicKey::from_slice(&spent_outputs[index].script_pubkey.as_bytes()[2..])
+            .map_err(|e| format!("input {}: {}", index, e))?;
+    let sighash = cache
+        .taproot_key_spend_signature_hash(
+            index,
+            &Prevouts::All(spent_outputs),
+            signature.sighash_type,
+        )
+        .map_err(|e| format!("input {}: {}", index, e))?;
+    let message = Message::from_digest(sighash.to_byte_array());
+    secp.verify_schnorr(&signature.signature, &message, &output_key)
+        .map_err(|e| format!("input {}: {}", index, e))
+}
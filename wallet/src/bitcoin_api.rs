@@ -0,0 +1,15 @@
+/*
+ * the bitcoin canister's endpoints the wallet calls. builds with the `regtest-mock`
+ * feature get the in-canister chain of `regtest_mock` instead, for the pocket-ic suite
+ */
+#[cfg(not(feature = "regtest-mock"))]
+pub use ic_cdk::api::management_canister::bitcoin::{
+    bitcoin_get_balance, bitcoin_get_current_fee_percentiles, bitcoin_get_utxos,
+    bitcoin_send_transaction,
+};
+
+#[cfg(feature = "regtest-mock")]
+pub use crate::regtest_mock::{
+    bitcoin_get_balance, bitcoin_get_current_fee_percentiles, bitcoin_get_utxos,
+    bitcoin_send_transaction,
+};
@@ -0,0 +1,152 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    time::Duration,
+};
+
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    certification::{current_balances, tip_height},
+    state::{
+        read_balance_history, read_latest_balance_samples, write_balance_history,
+        write_latest_balance_samples, BalanceSample, HistoryKey,
+    },
+    types::WalletError,
+};
+
+// holdings are sampled once per day
+const SAMPLE_PERIOD_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// checked more often than the period so upgrades don't keep pushing a sample back
+const CHECK_INTERVAL_SECS: u64 = 60 * 60;
+
+const MAX_SAMPLES_PER_CALL: usize = 1_000;
+
+thread_local! {
+    static SAMPLE_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    // kept on the heap, after an upgrade the next check samples again which only writes changes
+    static LAST_SAMPLED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+pub fn start_sampling() {
+    SAMPLE_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(CHECK_INTERVAL_SECS),
+            sample,
+        ));
+    });
+}
+
+fn record(address: String, sample: BalanceSample) {
+    write_balance_history(|history| {
+        history.insert(
+            HistoryKey {
+                address: address.clone(),
+                taken_at: sample.taken_at,
+            },
+            sample.clone(),
+        );
+    });
+    write_latest_balance_samples(|latest| {
+        if sample.is_empty() {
+            latest.remove(&address);
+        } else {
+            latest.insert(address, sample);
+        }
+    });
+}
+
+/*
+ * writes a sample for every address whose btc or rune balances changed since its
+ * previous one. an address that no longer holds anything gets an empty sample so
+ * charts drop to zero instead of holding the last value
+ */
+fn sample() {
+    let now = ic_cdk::api::time();
+    if LAST_SAMPLED
+        .get()
+        .is_some_and(|last| now.saturating_sub(last) < SAMPLE_PERIOD_NANOS)
+    {
+        return;
+    }
+    LAST_SAMPLED.set(Some(now));
+    let height = tip_height();
+    let balances = current_balances();
+    let present: HashSet<String> = balances
+        .iter()
+        .map(|balance| balance.address.clone())
+        .collect();
+    for balance in balances {
+        let sample = BalanceSample {
+            taken_at: now,
+            height,
+            bitcoin: balance.bitcoin,
+            runes: balance.runes,
+        };
+        let unchanged = read_latest_balance_samples(|latest| {
+            latest
+                .get(&balance.address)
+                .is_some_and(|previous| previous.same_holdings(&sample))
+        });
+        if !unchanged {
+            record(balance.address, sample);
+        }
+    }
+    let emptied: Vec<String> = read_latest_balance_samples(|latest| {
+        latest
+            .iter()
+            .map(|(address, _)| address)
+            .filter(|address| !present.contains(address))
+            .collect()
+    });
+    for address in emptied {
+        record(
+            address,
+            BalanceSample {
+                taken_at: now,
+                height,
+                bitcoin: 0,
+                runes: vec![],
+            },
+        );
+    }
+}
+
+/*
+ * samples of `address` taken between `from_ts` and `to_ts` oldest first, preceded by
+ * the one in force at `from_ts` so a chart starts at the right value
+ */
+pub fn balance_history(
+    address: String,
+    from_ts: u64,
+    to_ts: u64,
+) -> Result<Vec<BalanceSample>, WalletError> {
+    if from_ts > to_ts {
+        return Err(WalletError::InvalidArgument(String::from(
+            "from_ts is after to_ts",
+        )));
+    }
+    let key = |taken_at| HistoryKey {
+        address: address.clone(),
+        taken_at,
+    };
+    read_balance_history(|history| {
+        let opening = history
+            .range(key(0)..key(from_ts))
+            .last()
+            .map(|(_, sample)| sample);
+        Ok(opening
+            .into_iter()
+            .chain(
+                history
+                    .range(key(from_ts)..=key(to_ts))
+                    .map(|(_, sample)| sample),
+            )
+            .take(MAX_SAMPLES_PER_CALL)
+            .collect())
+    })
+}
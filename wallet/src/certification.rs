@@ -0,0 +1,211 @@
+use std::{cell::RefCell, collections::HashSet, time::Duration};
+
+use candid::{CandidType, Encode};
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin::sha256,
+    state::{
+        read_snapshot_balances, read_snapshot_deltas, read_sync_cursors, read_transaction_log,
+        read_utxo_manager, write_snapshot_balances, write_snapshot_deltas, AddressBalance,
+        SnapshotDelta,
+    },
+    types::WalletError,
+};
+
+const SNAPSHOT_INTERVAL_SECS: u64 = 60;
+
+// versions kept for `get_delta_since`, older replicas refetch the full state
+const MAX_DELTAS: u64 = 1_000;
+
+const MAX_DELTAS_PER_CALL: usize = 100;
+
+// transactions committed to by the recent tx root
+const RECENT_TRANSACTIONS: usize = 100;
+
+/*
+ * header of the latest snapshot, its sha256 over the candid encoding is the
+ * canister's certified data. the balances root is sha256 over the concatenated
+ * sha256 of every address balance's candid encoding in address order, the recent
+ * tx root is sha256 over the concatenated txids of the latest transactions, newest first
+*/
+#[derive(CandidType, Clone, Default)]
+pub struct CertifiedSnapshot {
+    pub version: u64,
+    pub height: u32,
+    pub balances_root: Vec<u8>,
+    pub recent_tx_root: Vec<u8>,
+    pub taken_at: u64,
+}
+
+#[derive(CandidType)]
+pub struct CertifiedSnapshotResponse {
+    pub snapshot: CertifiedSnapshot,
+    // only available to query calls
+    pub certificate: Option<Vec<u8>>,
+}
+
+thread_local! {
+    static SNAPSHOT_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static LATEST: RefCell<CertifiedSnapshot> = RefCell::new(CertifiedSnapshot::default());
+}
+
+// certified data doesn't survive an upgrade, the first snapshot runs right away
+pub fn start_snapshots() {
+    ic_cdk_timers::set_timer(Duration::from_secs(0), take_snapshot);
+    SNAPSHOT_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(SNAPSHOT_INTERVAL_SECS),
+            take_snapshot,
+        ));
+    });
+}
+
+pub fn current_balances() -> Vec<AddressBalance> {
+    read_utxo_manager(|manager| {
+        manager
+            .addresses()
+            .into_iter()
+            .map(|address| {
+                let mut runes: Vec<_> = manager
+                    .all_rune_with_balances(&address)
+                    .into_iter()
+                    .filter(|(_, balance)| *balance > 0)
+                    .collect();
+                runes.sort();
+                AddressBalance {
+                    bitcoin: manager.get_bitcoin_balance(&address),
+                    address,
+                    runes,
+                }
+            })
+            .filter(|balance| balance.bitcoin > 0 || !balance.runes.is_empty())
+            .collect()
+    })
+}
+
+// highest tip any sync cursor has seen
+pub fn tip_height() -> u32 {
+    read_sync_cursors(|cursors| {
+        cursors
+            .iter()
+            .map(|(_, cursor)| cursor.tip_height)
+            .max()
+            .unwrap_or_default()
+    })
+}
+
+fn take_snapshot() {
+    let now = ic_cdk::api::time();
+    let height = tip_height();
+    let balances = current_balances();
+    let present: HashSet<&String> = balances.iter().map(|balance| &balance.address).collect();
+    let changed: Vec<AddressBalance> = read_snapshot_balances(|snapshot| {
+        balances
+            .iter()
+            .filter(|balance| snapshot.get(&balance.address).as_ref() != Some(*balance))
+            .cloned()
+            .collect()
+    });
+    let removed: Vec<String> = read_snapshot_balances(|snapshot| {
+        snapshot
+            .iter()
+            .map(|(address, _)| address)
+            .filter(|address| !present.contains(address))
+            .collect()
+    });
+    let mut version =
+        read_snapshot_deltas(|deltas| deltas.last_key_value()).map_or(0, |(version, _)| version);
+    if !changed.is_empty() || !removed.is_empty() {
+        version += 1;
+        write_snapshot_balances(|snapshot| {
+            for balance in changed.iter() {
+                snapshot.insert(balance.address.clone(), balance.clone());
+            }
+            for address in removed.iter() {
+                snapshot.remove(address);
+            }
+        });
+        write_snapshot_deltas(|deltas| {
+            deltas.insert(
+                version,
+                SnapshotDelta {
+                    version,
+                    height,
+                    changed,
+                    removed,
+                    taken_at: now,
+                },
+            );
+            while deltas.len() > MAX_DELTAS {
+                if let Some((oldest, _)) = deltas.first_key_value() {
+                    deltas.remove(&oldest);
+                }
+            }
+        });
+    }
+    let balances_root = read_snapshot_balances(|snapshot| {
+        let leaves: Vec<u8> = snapshot
+            .iter()
+            .flat_map(|(_, balance)| sha256(&Encode!(&balance).expect("should encode")))
+            .collect();
+        sha256(&leaves)
+    });
+    let recent_tx_root = read_transaction_log(|log| {
+        sha256(log.recent_txids(RECENT_TRANSACTIONS).concat().as_bytes())
+    });
+    let snapshot = CertifiedSnapshot {
+        version,
+        height,
+        balances_root,
+        recent_tx_root,
+        taken_at: now,
+    };
+    let hash = sha256(&Encode!(&snapshot).expect("should encode"));
+    ic_cdk::api::set_certified_data(&hash);
+    LATEST.set(snapshot);
+}
+
+pub fn certified_snapshot() -> CertifiedSnapshotResponse {
+    CertifiedSnapshotResponse {
+        snapshot: LATEST.with_borrow(|snapshot| snapshot.clone()),
+        certificate: ic_cdk::api::data_certificate(),
+    }
+}
+
+// deltas after `version` oldest first, callers page on the last returned version
+pub fn delta_since(version: u64) -> Result<Vec<SnapshotDelta>, WalletError> {
+    read_snapshot_deltas(|deltas| {
+        let oldest = deltas.first_key_value().map_or(0, |(oldest, _)| oldest);
+        if version + 1 < oldest {
+            return Err(WalletError::InvalidArgument(String::from(
+                "version was pruned, refetch the full snapshot",
+            )));
+        }
+        Ok(deltas
+            .range(version + 1..)
+            .take(MAX_DELTAS_PER_CALL)
+            .map(|(_, delta)| delta)
+            .collect())
+    })
+}
+
+// the state of the latest version, paged by address
+pub fn snapshot_balances(start_after: Option<String>, limit: u64) -> Vec<AddressBalance> {
+    read_snapshot_balances(|snapshot| match start_after {
+        Some(address) => snapshot
+            .range(address.clone()..)
+            .filter(|(key, _)| *key != address)
+            .take(limit as usize)
+            .map(|(_, balance)| balance)
+            .collect(),
+        None => snapshot
+            .iter()
+            .take(limit as usize)
+            .map(|(_, balance)| balance)
+            .collect(),
+    })
+}
@@ -0,0 +1,75 @@
+use std::{cell::RefCell, collections::VecDeque};
+
+use crate::{
+    state::{read_config, record_event, write_config, EventKind, Pause},
+    types::WalletError,
+};
+
+thread_local! {
+    // times of the submission failures inside the breaker's window, oldest first
+    static FAILURES: RefCell<VecDeque<u64>> = const { RefCell::new(VecDeque::new()) };
+}
+
+// first check of every endpoint that moves funds
+pub fn ensure_running() -> Result<(), WalletError> {
+    match read_config(|config| config.pause.clone()) {
+        Some(pause) => Err(WalletError::Paused(pause.reason)),
+        None => Ok(()),
+    }
+}
+
+pub fn pause(reason: String, tripped: bool) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.pause = Some(Pause {
+            reason: reason.clone(),
+            since: ic_cdk::api::time(),
+            tripped,
+        });
+        let _ = config.set(temp);
+    });
+    record_event(EventKind::Paused { reason, tripped });
+}
+
+// the failures that tripped the breaker are forgotten along with the pause
+pub fn unpause() {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.pause = None;
+        let _ = config.set(temp);
+    });
+    FAILURES.with_borrow_mut(|failures| failures.clear());
+    record_event(EventKind::Unpaused);
+}
+
+/*
+ * counts a failed submission towards the breaker's window and pauses the canister
+ * once the window holds more than `max_failures`. failures are kept on the heap,
+ * an upgrade starts the window over
+*/
+pub fn record_failure() {
+    let Some(breaker) = read_config(|config| config.circuit_breaker.clone()) else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let window = breaker.window_secs.saturating_mul(1_000_000_000);
+    let failures = FAILURES.with_borrow_mut(|failures| {
+        failures.push_back(now);
+        while failures
+            .front()
+            .is_some_and(|&at| now.saturating_sub(at) > window)
+        {
+            failures.pop_front();
+        }
+        failures.len()
+    });
+    if failures > breaker.max_failures as usize && ensure_running().is_ok() {
+        pause(
+            format!(
+                "{} failed submissions within {} seconds",
+                failures, breaker.window_secs
+            ),
+            true,
+        );
+    }
+}
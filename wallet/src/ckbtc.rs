@@ -0,0 +1,295 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use bitcoin::hashes::Hash;
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    state::{
+        read_ckbtc_auto_wrap, read_ckbtc_wraps, read_config, write_ckbtc_wraps, CkbtcWrap,
+        CkbtcWrapStatus,
+    },
+    transaction_handler::SubmittedTransactionIdType,
+    types::{CoinSelection, FeePayer, WalletError},
+};
+
+const MINTING_INTERVAL_SECS: u64 = 10 * 60;
+
+thread_local! {
+    static MINTING_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static MINTING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped round doesn't block the following ones
+struct MintGuard;
+
+impl MintGuard {
+    fn acquire() -> Option<Self> {
+        if MINTING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for MintGuard {
+    fn drop(&mut self) {
+        MINTING.set(false);
+    }
+}
+
+#[derive(CandidType)]
+struct MinterAccount {
+    owner: Option<Principal>,
+    subaccount: Option<Vec<u8>>,
+}
+
+// only the fields needed to match a minted utxo back to its wrap
+#[derive(CandidType, Deserialize)]
+struct MinterOutpoint {
+    txid: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct MinterUtxo {
+    outpoint: MinterOutpoint,
+}
+
+#[derive(CandidType, Deserialize)]
+enum UtxoStatus {
+    ValueTooSmall(MinterUtxo),
+    Tainted(MinterUtxo),
+    Checked(MinterUtxo),
+    Minted {
+        block_index: u64,
+        minted_amount: u64,
+        utxo: MinterUtxo,
+    },
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum UpdateBalanceError {
+    GenericError {
+        error_code: u64,
+        error_message: String,
+    },
+    TemporarilyUnavailable(String),
+    AlreadyProcessing,
+    NoNewUtxos {
+        required_confirmations: u32,
+        current_confirmations: Option<u32>,
+    },
+}
+
+pub fn start_minting() {
+    MINTING_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(MINTING_INTERVAL_SECS),
+            || ic_cdk::spawn(mint_round()),
+        ));
+    });
+}
+
+fn minter() -> Result<Principal, WalletError> {
+    read_config(|config| config.ckbtc_minter()).ok_or_else(|| {
+        WalletError::InvalidArgument(String::from("no ckbtc minter on this network"))
+    })
+}
+
+// the minter's deposit address minting to the principal's default icrc-1 account
+async fn deposit_address(minter: Principal, principal: Principal) -> Result<String, WalletError> {
+    let account = MinterAccount {
+        owner: Some(principal),
+        subaccount: None,
+    };
+    ic_cdk::call::<_, (String,)>(minter, "get_btc_address", (account,))
+        .await
+        .map(|(address,)| address)
+        .map_err(|(code, msg)| {
+            WalletError::InvalidArgument(format!("ckbtc minter unreachable: {:?} {}", code, msg))
+        })
+}
+
+fn set_status(id: u64, status: CkbtcWrapStatus) {
+    write_ckbtc_wraps(|wraps| {
+        if let Some(mut wrap) = wraps.get(&id) {
+            wrap.status = status;
+            wraps.insert(id, wrap);
+        }
+    })
+}
+
+fn insert(principal: Principal, amount: u64, fee_payer: FeePayer) -> CkbtcWrap {
+    write_ckbtc_wraps(|wraps| {
+        let id = wraps.last_key_value().map_or(0, |(id, _)| id + 1);
+        let wrap = CkbtcWrap {
+            id,
+            principal,
+            amount,
+            fee_payer,
+            created_at: ic_cdk::api::time(),
+            status: CkbtcWrapStatus::Requested,
+        };
+        wraps.insert(id, wrap.clone());
+        wrap
+    })
+}
+
+// pays `wrap` out to the minter's deposit address of its principal
+async fn submit(wrap: &CkbtcWrap) -> Result<String, WalletError> {
+    let address = deposit_address(minter()?, wrap.principal).await?;
+    crate::ensure_unallocated(wrap.principal, wrap.amount).await?;
+    let txn = crate::bitcoin_withdrawal(
+        wrap.principal,
+        address,
+        wrap.amount,
+        None,
+        wrap.fee_payer,
+        CoinSelection::default(),
+        None,
+        None,
+    )
+    .await?;
+    let SubmittedTransactionIdType::Bitcoin { txid, .. } = txn.build_and_submit(None).await?;
+    Ok(txid)
+}
+
+// sends `amount` of the principal's bitcoin to the minter, the sender pays the fee
+pub async fn wrap(principal: Principal, amount: u64) -> Result<CkbtcWrap, WalletError> {
+    let mut wrap = insert(principal, amount, FeePayer::Sender);
+    wrap.status = match submit(&wrap).await {
+        Ok(txid) => CkbtcWrapStatus::Submitted { txid },
+        Err(err) => {
+            set_status(
+                wrap.id,
+                CkbtcWrapStatus::Failed {
+                    reason: format!("{:?}", err),
+                },
+            );
+            return Err(err);
+        }
+    };
+    set_status(wrap.id, wrap.status.clone());
+    Ok(wrap)
+}
+
+pub fn is_auto_wrapping(principal: &Principal) -> bool {
+    read_ckbtc_auto_wrap(|principals| principals.contains_key(principal))
+}
+
+// queues the deposit for the next minting round, the network fee comes out of it
+pub fn on_deposit(principal: Principal, value: u64) {
+    if is_auto_wrapping(&principal) {
+        insert(principal, value, FeePayer::Receiver);
+    }
+}
+
+fn txid_to_string(txid: &[u8]) -> String {
+    bitcoin::Txid::from_raw_hash(Hash::from_slice(txid).unwrap()).to_string()
+}
+
+/*
+ * sends the requested wraps to the minter and asks the minter to mint the submitted
+ * ones. the minter only mints once the deposit has its required confirmations,
+ * until then the wrap stays submitted and is retried on the next round
+*/
+pub async fn mint_round() {
+    let Some(_guard) = MintGuard::acquire() else {
+        return;
+    };
+    let wraps: Vec<CkbtcWrap> = read_ckbtc_wraps(|wraps| {
+        wraps
+            .iter()
+            .map(|(_, wrap)| wrap)
+            .filter(|wrap| {
+                matches!(
+                    wrap.status,
+                    CkbtcWrapStatus::Requested | CkbtcWrapStatus::Submitted { .. }
+                )
+            })
+            .collect()
+    });
+    for wrap in wraps.iter() {
+        if let CkbtcWrapStatus::Requested = wrap.status {
+            let status = match submit(wrap).await {
+                Ok(txid) => CkbtcWrapStatus::Submitted { txid },
+                Err(err) => CkbtcWrapStatus::Failed {
+                    reason: format!("{:?}", err),
+                },
+            };
+            set_status(wrap.id, status);
+        }
+    }
+    let Ok(minter) = minter() else {
+        return;
+    };
+    let mut principals: Vec<Principal> = wraps.iter().map(|wrap| wrap.principal).collect();
+    principals.sort();
+    principals.dedup();
+    for principal in principals {
+        let account = MinterAccount {
+            owner: Some(principal),
+            subaccount: None,
+        };
+        let statuses = match ic_cdk::call::<_, (Result<Vec<UtxoStatus>, UpdateBalanceError>,)>(
+            minter,
+            "update_balance",
+            (account,),
+        )
+        .await
+        {
+            Ok((Ok(statuses),)) => statuses,
+            Ok((Err(err),)) => {
+                ic_cdk::println!("ckbtc minter didn't mint for {}: {:?}", principal, err);
+                continue;
+            }
+            Err((code, msg)) => {
+                ic_cdk::println!("ckbtc minter unreachable: {:?} {}", code, msg);
+                return;
+            }
+        };
+        for status in statuses {
+            let (utxo, outcome) = match status {
+                UtxoStatus::Minted {
+                    block_index,
+                    minted_amount,
+                    utxo,
+                } => (utxo, Ok((minted_amount, block_index))),
+                UtxoStatus::ValueTooSmall(utxo) => (utxo, Err("below the minter's minimum")),
+                UtxoStatus::Tainted(utxo) => (utxo, Err("rejected by the minter's checks")),
+                // passed the checks, minted on a later round
+                UtxoStatus::Checked(_) => continue,
+            };
+            let txid = txid_to_string(&utxo.outpoint.txid);
+            let submitted_as = |wrap: &CkbtcWrap| match wrap.status {
+                CkbtcWrapStatus::Submitted {
+                    txid: ref submitted,
+                } => *submitted == txid,
+                _ => false,
+            };
+            let Some(wrap) =
+                read_ckbtc_wraps(|wraps| wraps.iter().map(|(_, wrap)| wrap).find(submitted_as))
+            else {
+                continue;
+            };
+            let status = match outcome {
+                Ok((minted, block_index)) => CkbtcWrapStatus::Minted {
+                    txid,
+                    minted,
+                    block_index,
+                },
+                Err(reason) => CkbtcWrapStatus::Failed {
+                    reason: String::from(reason),
+                },
+            };
+            set_status(wrap.id, status);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::{cell::RefCell, time::Duration};
+
+use ic_cdk_timers::TimerId;
+
+use crate::{metrics, pending_change, transaction_handler};
+
+/*
+ * reaps the stores that only ever grew on their own: change of transactions the chain
+ * never reported back and withdrawals prepared but never broadcast. the latter are only
+ * cancelled, their inputs wait for the sync to settle them. each store keeps its own ttl,
+ * the reaped counts go into the metrics
+*/
+
+const COLLECT_INTERVAL_SECS: u64 = 10 * 60;
+
+thread_local! {
+    static COLLECT_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+pub fn start_collecting() {
+    COLLECT_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(COLLECT_INTERVAL_SECS),
+            collect,
+        ));
+    });
+}
+
+fn collect() {
+    let pending_change = pending_change::expire();
+    let prepared_withdrawals = transaction_handler::expire_prepared();
+    metrics::record_reaped(pending_change, prepared_withdrawals);
+}
@@ -0,0 +1,117 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use candid::CandidType;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    state::{read_config, record_event, CycleThresholds, EventKind},
+    types::{CycleLevel, WalletError},
+    webhook,
+};
+
+const CHECK_INTERVAL_SECS: u64 = 10 * 60;
+
+const NANOS_PER_DAY: u128 = 24 * 3_600 * 1_000_000_000;
+
+thread_local! {
+    static MONITOR_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    // level of the previous check, an upgrade reports a level below healthy once more
+    static LAST_LEVEL: Cell<Option<CycleLevel>> = const { Cell::new(None) };
+    // time and balance of the previous check
+    static LAST_CHECK: Cell<Option<(u64, u128)>> = const { Cell::new(None) };
+    static BURN_PER_DAY: Cell<Option<u128>> = const { Cell::new(None) };
+}
+
+#[derive(CandidType)]
+pub struct CycleStatus {
+    pub balance: u128,
+    pub level: CycleLevel,
+    pub thresholds: Option<CycleThresholds>,
+    // cycles burned per day between the last two checks, none before the second one
+    pub burn_per_day: Option<u128>,
+    // false while full rescans and metered address syncs are refused
+    pub discretionary_enabled: bool,
+}
+
+pub fn start_monitoring() {
+    MONITOR_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(CHECK_INTERVAL_SECS),
+            || ic_cdk::spawn(check()),
+        ));
+    });
+}
+
+fn level_at(balance: u128) -> CycleLevel {
+    match read_config(|config| config.cycle_thresholds.clone()) {
+        Some(thresholds) if balance < thresholds.critical => CycleLevel::Critical,
+        Some(thresholds) if balance < thresholds.low => CycleLevel::Low,
+        _ => CycleLevel::Healthy,
+    }
+}
+
+pub fn level() -> CycleLevel {
+    level_at(ic_cdk::api::canister_balance128())
+}
+
+pub fn is_degraded() -> bool {
+    level() == CycleLevel::Critical
+}
+
+/*
+ * first check of the work the canister can go without: timers rescanning every
+ * address and syncs of arbitrary addresses. withdrawals never call it, so funds can
+ * still leave until the canister freezes
+ */
+pub fn ensure_discretionary() -> Result<(), WalletError> {
+    if is_degraded() {
+        Err(WalletError::CyclesLow)
+    } else {
+        Ok(())
+    }
+}
+
+// records the burn rate and reports the level once it moved
+async fn check() {
+    let now = ic_cdk::api::time();
+    let balance = ic_cdk::api::canister_balance128();
+    if let Some((at, previous)) = LAST_CHECK.replace(Some((now, balance))) {
+        // a top-up in between leaves the previous rate standing
+        if previous >= balance && now > at {
+            BURN_PER_DAY.set(Some(
+                (previous - balance) * NANOS_PER_DAY / (now - at) as u128,
+            ));
+        }
+    }
+    let level = level_at(balance);
+    let previous = LAST_LEVEL
+        .replace(Some(level))
+        .unwrap_or(CycleLevel::Healthy);
+    if previous == level {
+        return;
+    }
+    record_event(EventKind::CycleLevelChanged {
+        previous,
+        level,
+        balance,
+    });
+    webhook::post_cycle_alert(previous, level, balance).await;
+}
+
+pub fn status() -> CycleStatus {
+    let balance = ic_cdk::api::canister_balance128();
+    let level = level_at(balance);
+    CycleStatus {
+        balance,
+        level,
+        thresholds: read_config(|config| config.cycle_thresholds.clone()),
+        burn_per_day: BURN_PER_DAY.get(),
+        discretionary_enabled: level != CycleLevel::Critical,
+    }
+}
@@ -0,0 +1,112 @@
+use candid::Principal;
+
+use crate::{
+    state::{read_delegations, write_delegations, DelegationKey},
+    types::{TokenType, WalletError},
+};
+
+fn key(owner: Principal, spender: Principal, token: TokenType) -> DelegationKey {
+    DelegationKey {
+        owner,
+        spender,
+        token,
+    }
+}
+
+// bitcoin and runes held on the owner's deposit address are the only ones delegated
+pub fn approve(
+    owner: Principal,
+    spender: Principal,
+    token: TokenType,
+    amount: u128,
+) -> Result<(), WalletError> {
+    if owner == spender {
+        return Err(WalletError::InvalidArgument(String::from(
+            "the spender has to be another principal",
+        )));
+    }
+    if matches!(token, TokenType::Icp | TokenType::CkBTC) {
+        return Err(WalletError::InvalidArgument(String::from(
+            "only bitcoin and runes can be delegated",
+        )));
+    }
+    let key = key(owner, spender, token);
+    write_delegations(|delegations| {
+        if amount == 0 {
+            delegations.remove(&key);
+        } else {
+            delegations.insert(key, amount);
+        }
+    });
+    Ok(())
+}
+
+pub fn allowance(owner: Principal, spender: Principal, token: TokenType) -> u128 {
+    read_delegations(|delegations| delegations.get(&key(owner, spender, token))).unwrap_or(0)
+}
+
+// every allowance the owner granted and has left
+pub fn allowances_of(owner: Principal) -> Vec<(Principal, TokenType, u128)> {
+    read_delegations(|delegations| {
+        delegations
+            .iter()
+            .filter(|(key, _)| key.owner == owner)
+            .map(|(key, allowance)| (key.spender, key.token, allowance))
+            .collect()
+    })
+}
+
+/*
+ * part of an allowance taken by a withdrawal in flight. whatever isn't settled goes
+ * back to the allowance once the charge is dropped, so an early return before the
+ * broadcast leaves it as it was
+*/
+pub struct DelegatedCharge {
+    key: DelegationKey,
+    amount: u128,
+}
+
+impl DelegatedCharge {
+    // keeps the whole amount when the withdrawal went through
+    pub fn settle_if<T, E>(mut self, result: &Result<T, E>) {
+        if result.is_ok() {
+            self.amount = 0;
+        }
+    }
+}
+
+impl Drop for DelegatedCharge {
+    fn drop(&mut self) {
+        if self.amount == 0 {
+            return;
+        }
+        write_delegations(|delegations| {
+            // added on top of whatever `approve` set in the meantime
+            let allowance = delegations.get(&self.key).unwrap_or(0);
+            delegations.insert(self.key.clone(), allowance.saturating_add(self.amount));
+        });
+    }
+}
+
+// takes `amount` out of what `spender` may move of the owner's `token`
+pub fn charge(
+    owner: Principal,
+    spender: Principal,
+    token: TokenType,
+    amount: u128,
+) -> Result<DelegatedCharge, WalletError> {
+    let key = key(owner, spender, token);
+    write_delegations(|delegations| {
+        let allowance = delegations.get(&key).unwrap_or(0);
+        if allowance < amount {
+            return Err(WalletError::InsufficientAllowance { allowance });
+        }
+        if allowance == amount {
+            delegations.remove(&key);
+        } else {
+            delegations.insert(key.clone(), allowance - amount);
+        }
+        Ok(())
+    })?;
+    Ok(DelegatedCharge { key, amount })
+}
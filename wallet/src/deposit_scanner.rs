@@ -0,0 +1,72 @@
+use std::{cell::RefCell, time::Duration};
+
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    cycle_monitor, external_addresses,
+    state::{read_config, read_deposit_addresses, write_deposit_addresses, DepositAddress},
+    updater::{self, TargetType},
+    utils::generate_addresses_from_principal,
+};
+
+thread_local! {
+    static SCAN_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+pub fn start_scanning() {
+    let policy = read_config(|config| config.deposit_scan_policy.clone());
+    SCAN_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        if let Some(policy) = policy.filter(|policy| policy.enabled) {
+            *timer = Some(ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(policy.interval_mins * 60),
+                // the scheduled rounds pause while cycles are critically low
+                || {
+                    if !cycle_monitor::is_degraded() {
+                        ic_cdk::spawn(scan())
+                    }
+                },
+            ));
+        }
+    });
+}
+
+// remembers the principal's deposit address for the background scan
+pub fn register(principal: Principal) {
+    let address = generate_addresses_from_principal(&principal).bitcoin;
+    if read_deposit_addresses(|addresses| addresses.contains_key(&address)) {
+        return;
+    }
+    write_deposit_addresses(|addresses| {
+        addresses.insert(
+            address,
+            DepositAddress {
+                principal,
+                registered_at: ic_cdk::api::time(),
+            },
+        )
+    });
+}
+
+/*
+ * syncs every registered deposit address, crediting new utxos to the utxo manager and
+ * recording them as deposits of the owner. the sync cursors keep a round down to the
+ * blocks since the previous one and an overlapping round finds the utxos already recorded.
+ * external addresses are synced along, their utxos aren't deposits
+*/
+pub async fn scan() {
+    let addresses: Vec<(String, DepositAddress)> =
+        read_deposit_addresses(|addresses| addresses.iter().collect());
+    for (address, registered) in addresses {
+        updater::fetch_utxos_for_owner(
+            &address,
+            Some(registered.principal),
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+    }
+    external_addresses::sync_all().await;
+}
@@ -0,0 +1,207 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{
+    state::{read_config, write_transaction_log},
+    transaction_handler::SubmittedTransactionIdType,
+    types::{FiatRate, TokenType, WalletError},
+};
+
+const EXCHANGE_RATE_CANISTER: &str = "uxrqq-vaaaa-aaaaa-aaafa-cai";
+
+// cycles the exchange rate canister charges per request
+const RATE_REQUEST_CYCLES: u128 = 1_000_000_000;
+
+// cached rates are served for this long before the oracle is asked again
+const CACHE_TTL_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+const SATS_DECIMALS: u32 = 8;
+
+thread_local! {
+    // symbol => (rate, fetched at)
+    static RATES: RefCell<HashMap<String, (FiatRate, u64)>> = RefCell::default();
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+enum AssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct Asset {
+    symbol: String,
+    class: AssetClass,
+}
+
+#[derive(CandidType)]
+struct GetExchangeRateRequest {
+    base_asset: Asset,
+    quote_asset: Asset,
+    timestamp: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ExchangeRateMetadata {
+    decimals: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ExchangeRate {
+    timestamp: u64,
+    rate: u64,
+    metadata: ExchangeRateMetadata,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct OtherError {
+    code: u32,
+    description: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoBaseAssetNotFound,
+    CryptoQuoteAssetNotFound,
+    StablecoinRateNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other(OtherError),
+}
+
+// the oracle's symbol for the token along with the decimals of its smallest unit
+fn quote_source(token: &TokenType) -> Result<(String, u32), WalletError> {
+    match token {
+        TokenType::Bitcoin | TokenType::CkBTC => Ok((String::from("BTC"), SATS_DECIMALS)),
+        TokenType::Icp => Ok((String::from("ICP"), SATS_DECIMALS)),
+        TokenType::Runestone(runeid) => read_config(|config| config.rune_quote_sources.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .find(|source| source.runeid == *runeid)
+            .map(|source| (source.symbol, source.divisibility as u32))
+            .ok_or_else(|| {
+                WalletError::ExchangeRateUnavailable(format!(
+                    "no quote source for rune {:?}",
+                    runeid
+                ))
+            }),
+    }
+}
+
+async fn fetch(symbol: &str) -> Result<FiatRate, WalletError> {
+    let request = GetExchangeRateRequest {
+        base_asset: Asset {
+            symbol: symbol.to_string(),
+            class: AssetClass::Cryptocurrency,
+        },
+        quote_asset: Asset {
+            symbol: String::from("USD"),
+            class: AssetClass::FiatCurrency,
+        },
+        timestamp: None,
+    };
+    let canister = Principal::from_text(EXCHANGE_RATE_CANISTER).unwrap();
+    match ic_cdk::api::call::call_with_payment128::<_, (Result<ExchangeRate, ExchangeRateError>,)>(
+        canister,
+        "get_exchange_rate",
+        (request,),
+        RATE_REQUEST_CYCLES,
+    )
+    .await
+    {
+        Ok((Ok(rate),)) => Ok(FiatRate {
+            symbol: symbol.to_string(),
+            rate: rate.rate,
+            decimals: rate.metadata.decimals,
+            timestamp: rate.timestamp,
+        }),
+        Ok((Err(err),)) => Err(WalletError::ExchangeRateUnavailable(format!("{:?}", err))),
+        Err((code, msg)) => Err(WalletError::ExchangeRateUnavailable(format!(
+            "{:?}: {}",
+            code, msg
+        ))),
+    }
+}
+
+// usd rate of the token, served from the cache while fresh
+pub async fn usd_rate(token: &TokenType) -> Result<FiatRate, WalletError> {
+    let (symbol, _) = quote_source(token)?;
+    let now = ic_cdk::api::time();
+    let cached = RATES.with_borrow(|rates| {
+        rates
+            .get(&symbol)
+            .filter(|(_, fetched_at)| now.saturating_sub(*fetched_at) < CACHE_TTL_NANOS)
+            .map(|(rate, _)| rate.clone())
+    });
+    if let Some(rate) = cached {
+        return Ok(rate);
+    }
+    let rate = fetch(&symbol).await?;
+    RATES.with_borrow_mut(|rates| rates.insert(symbol, (rate.clone(), now)));
+    Ok(rate)
+}
+
+// rates fetched so far, stale ones included
+pub fn cached_rates() -> Vec<FiatRate> {
+    RATES.with_borrow(|rates| rates.values().map(|(rate, _)| rate.clone()).collect())
+}
+
+// `amount` of the token's smallest unit in usd cents
+pub async fn usd_cents(token: &TokenType, amount: u128) -> Result<u128, WalletError> {
+    let (_, unit_decimals) = quote_source(token)?;
+    let rate = usd_rate(token).await?;
+    Ok(to_cents(amount, unit_decimals, &rate))
+}
+
+fn to_cents(amount: u128, unit_decimals: u32, rate: &FiatRate) -> u128 {
+    amount.saturating_mul(rate.rate as u128).saturating_mul(100)
+        / 10u128.pow(unit_decimals + rate.decimals)
+}
+
+/*
+ * checks `amount` against the controller's usd limit for the token at the current
+ * rate. returns the rate the check used, none when no limit applies
+*/
+pub async fn ensure_within_limit(
+    token: &TokenType,
+    amount: u128,
+) -> Result<Option<FiatRate>, WalletError> {
+    let limit = read_config(|config| config.fiat_limits.clone()).and_then(|limits| match token {
+        TokenType::Runestone(_) => limits.max_rune_withdrawal_cents,
+        _ => limits.max_bitcoin_withdrawal_cents,
+    });
+    let Some(limit) = limit else {
+        return Ok(None);
+    };
+    let (_, unit_decimals) = quote_source(token)?;
+    let rate = usd_rate(token).await?;
+    let value = to_cents(amount, unit_decimals, &rate);
+    if value > limit as u128 {
+        return Err(WalletError::FiatLimitExceeded(format!(
+            "{} cents at {} over the limit of {} cents",
+            value, rate.symbol, limit
+        )));
+    }
+    Ok(Some(rate))
+}
+
+// keeps the rate a limit was checked against next to the submitted transaction
+pub fn record_rate(
+    submitted: &Result<SubmittedTransactionIdType, WalletError>,
+    rate: Option<FiatRate>,
+) {
+    if let (Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }), Some(rate)) = (submitted, rate) {
+        write_transaction_log(|log| log.attach_fiat_rate(txid, rate));
+    }
+}
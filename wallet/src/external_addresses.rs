@@ -0,0 +1,110 @@
+use candid::Principal;
+
+use crate::{
+    bitcoin::{self, message},
+    state::{read_config, read_external_addresses, write_external_addresses, ExternalAddress},
+    types::WalletError,
+    updater::{self, TargetType},
+    utils::caller_owning,
+};
+
+/*
+ * what the address's key signs to bind it to `principal`. naming the canister keeps
+ * a proof from being replayed on another wallet, binding the same address twice
+ * changes nothing
+*/
+pub fn challenge(principal: &Principal, address: &str) -> String {
+    format!(
+        "Register {} to {} on wallet {}",
+        address,
+        principal,
+        ic_cdk::id()
+    )
+}
+
+/*
+ * binds a p2pkh address to the principal once `signature`, a legacy signed message
+ * as bitcoin core's `signmessage` makes, proves control of its key. a proof by
+ * another principal moves the address over to it
+*/
+pub fn register(principal: Principal, address: String, signature: &str) -> Result<(), WalletError> {
+    let parsed = bitcoin::address_validation(&address).map_err(WalletError::InvalidAddress)?;
+    if !parsed.script_pubkey().is_p2pkh() {
+        return Err(WalletError::InvalidAddress(String::from(
+            "only p2pkh addresses can prove ownership",
+        )));
+    }
+    message::verify_legacy(
+        challenge(&principal, &address).as_bytes(),
+        &parsed,
+        signature,
+    )
+    .map_err(WalletError::SignatureVerificationFailed)?;
+    write_external_addresses(|addresses| {
+        addresses.insert(
+            address,
+            ExternalAddress {
+                principal,
+                registered_at: ic_cdk::api::time(),
+            },
+        )
+    });
+    Ok(())
+}
+
+pub fn unregister(principal: &Principal, address: &str) -> Result<(), WalletError> {
+    let address = address.to_string();
+    write_external_addresses(|addresses| match addresses.get(&address) {
+        Some(registered) if registered.principal == *principal => {
+            addresses.remove(&address);
+            Ok(())
+        }
+        _ => Err(WalletError::InvalidArgument(String::from(
+            "the address isn't registered to the caller",
+        ))),
+    })
+}
+
+pub fn addresses_of(principal: &Principal) -> Vec<String> {
+    read_external_addresses(|addresses| {
+        addresses
+            .iter()
+            .filter(|(_, registered)| registered.principal == *principal)
+            .map(|(address, _)| address)
+            .collect()
+    })
+}
+
+pub fn owner_of(address: &str) -> Option<Principal> {
+    read_external_addresses(|addresses| addresses.get(&address.to_string()))
+        .map(|registered| registered.principal)
+}
+
+// with `owner_gated_balances` on, only the owner of an address and controllers see its balances
+pub fn ensure_balance_access(address: &str) -> Result<(), WalletError> {
+    if !read_config(|config| config.owner_gated_balances.unwrap_or_default()) {
+        return Ok(());
+    }
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) || caller_owning(address).is_some() {
+        return Ok(());
+    }
+    if owner_of(address) == Some(caller) {
+        Ok(())
+    } else {
+        Err(WalletError::Unauthorized)
+    }
+}
+
+// keeps every registered address synced, the utxos only count towards its balances
+pub async fn sync_all() {
+    let addresses: Vec<String> =
+        read_external_addresses(|addresses| addresses.iter().map(|(address, _)| address).collect());
+    for address in addresses {
+        updater::fetch_utxos_and_update_balances(
+            &address,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+    }
+}
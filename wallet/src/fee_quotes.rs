@@ -0,0 +1,182 @@
+use std::{cell::RefCell, time::Duration};
+
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin::{cpfp::anchor_output, get_fee_per_vbyte},
+    state::{read_fee_quotes, write_fee_quotes, FeeQuote, TransactionKind},
+    types::{FeeEstimate, WalletError},
+};
+
+// how long a locked fee rate stays valid
+const FEE_QUOTE_TTL_SECS: u64 = 24 * 60 * 60;
+
+const EXPIRY_INTERVAL_SECS: u64 = 60;
+
+// quoted sizes beyond a single standard transaction aren't reserved for
+const MAX_QUOTED_VSIZE: u64 = crate::bitcoin::MAX_TX_VSIZE;
+
+thread_local! {
+    static EXPIRY_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+pub fn start_expiry() {
+    EXPIRY_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(EXPIRY_INTERVAL_SECS),
+            expire,
+        ));
+    });
+}
+
+// typical vsize of a transaction of the kind, with one input per side
+fn default_vsize(kind: TransactionKind) -> u64 {
+    match kind {
+        TransactionKind::Bitcoin | TransactionKind::Multisig => 230,
+        TransactionKind::MultiSender => 380,
+        TransactionKind::Runestone => 420,
+        TransactionKind::Combined => 570,
+        TransactionKind::Batch => 600,
+        TransactionKind::RuneSplit => 500,
+        TransactionKind::RuneBurn => 420,
+    }
+}
+
+// fee of a transaction of `kind` at the current rate, anchor output included
+pub async fn estimate(
+    kind: TransactionKind,
+    size_estimate: Option<u64>,
+) -> Result<FeeEstimate, WalletError> {
+    let vsize = size_estimate.unwrap_or_else(|| default_vsize(kind));
+    if vsize == 0 || vsize > MAX_QUOTED_VSIZE {
+        return Err(WalletError::InvalidArgument(format!(
+            "size estimate must be within 1 and {} vbytes",
+            MAX_QUOTED_VSIZE
+        )));
+    }
+    let fee_per_vbytes = get_fee_per_vbyte().await;
+    Ok(FeeEstimate {
+        kind,
+        vsize,
+        fee_per_vbytes,
+        fee: vsize * fee_per_vbytes / 1000
+            + anchor_output().map_or(0, |anchor| anchor.value.to_sat()),
+    })
+}
+
+/*
+ * quotes the current fee rate for a transaction of `kind` and holds back its fee
+ * from the caller's bitcoin. the caller is expected to have checked that the
+ * reservation is covered
+*/
+pub async fn quote(
+    caller: Principal,
+    kind: TransactionKind,
+    size_estimate: Option<u64>,
+) -> Result<FeeQuote, WalletError> {
+    let estimate = estimate(kind, size_estimate).await?;
+    Ok(FeeQuote {
+        id: 0,
+        caller,
+        kind,
+        vsize: estimate.vsize,
+        fee_per_vbytes: estimate.fee_per_vbytes,
+        reserved: estimate.fee,
+        expires_at: ic_cdk::api::time() + FEE_QUOTE_TTL_SECS * 1_000_000_000,
+    })
+}
+
+pub fn lock(mut quote: FeeQuote) -> u64 {
+    write_fee_quotes(|quotes| {
+        let id = quotes.last_key_value().map_or(0, |(id, _)| id + 1);
+        quote.id = id;
+        quotes.insert(id, quote);
+        id
+    })
+}
+
+// sats held back for the principal's quotes that are still valid
+pub fn reserved_by(principal: &Principal) -> u64 {
+    let now = ic_cdk::api::time();
+    read_fee_quotes(|quotes| {
+        quotes
+            .iter()
+            .map(|(_, quote)| quote)
+            .filter(|quote| quote.caller == *principal && quote.expires_at > now)
+            .map(|quote| quote.reserved)
+            .sum()
+    })
+}
+
+pub fn quotes_of(principal: &Principal) -> Vec<FeeQuote> {
+    read_fee_quotes(|quotes| {
+        quotes
+            .iter()
+            .map(|(_, quote)| quote)
+            .filter(|quote| quote.caller == *principal)
+            .collect()
+    })
+}
+
+// consumes the quote, releasing its reservation for the withdrawal using it
+pub fn take(id: u64, caller: Principal, kind: TransactionKind) -> Result<FeeQuote, WalletError> {
+    let quote = read_fee_quotes(|quotes| quotes.get(&id))
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no such fee quote")))?;
+    if quote.caller != caller {
+        return Err(WalletError::Unauthorized);
+    }
+    if quote.expires_at <= ic_cdk::api::time() {
+        write_fee_quotes(|quotes| quotes.remove(&id));
+        return Err(WalletError::InvalidArgument(String::from(
+            "fee quote expired",
+        )));
+    }
+    if quote.kind != kind {
+        return Err(WalletError::InvalidArgument(format!(
+            "fee quote was locked for a {:?} transaction",
+            quote.kind
+        )));
+    }
+    write_fee_quotes(|quotes| quotes.remove(&id));
+    Ok(quote)
+}
+
+// puts a quote taken by a withdrawal that failed back in place
+pub fn restore(quote: FeeQuote) {
+    if quote.expires_at > ic_cdk::api::time() {
+        write_fee_quotes(|quotes| quotes.insert(quote.id, quote));
+    }
+}
+
+pub fn release(id: u64, caller: Principal) -> Result<(), WalletError> {
+    match read_fee_quotes(|quotes| quotes.get(&id)) {
+        None => Err(WalletError::InvalidArgument(String::from(
+            "no such fee quote",
+        ))),
+        Some(quote) if quote.caller != caller => Err(WalletError::Unauthorized),
+        Some(_) => {
+            write_fee_quotes(|quotes| quotes.remove(&id));
+            Ok(())
+        }
+    }
+}
+
+fn expire() {
+    let now = ic_cdk::api::time();
+    let expired: Vec<u64> = read_fee_quotes(|quotes| {
+        quotes
+            .iter()
+            .filter(|(_, quote)| quote.expires_at <= now)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    write_fee_quotes(|quotes| {
+        for id in expired {
+            quotes.remove(&id);
+        }
+    });
+}
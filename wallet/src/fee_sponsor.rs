@@ -0,0 +1,291 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{
+    bitcoin::{self, get_fee_per_vbyte, runestone::RuneTransferArgs},
+    state::{
+        read_config, read_fee_escrows, read_utxo_manager, record_event_for, write_fee_escrows,
+        EscrowStatus, EventKind, FeeEscrow,
+    },
+    transaction_handler::{
+        release_locked_utxos, FeeSponsorship, SubmittedTransactionIdType, TransactionType,
+    },
+    types::{RuneId, WalletError},
+    updater::{self, TargetType},
+    utils::{caller_owning, generate_addresses_from_principal, Addresses},
+};
+
+/*
+ * the sponsor canister's side, both called by the wallet:
+ *   quote_fee_sponsorship : (FeeSponsorshipRequest) -> (variant { Ok : FeeSponsorshipQuote; Err : text })
+ *   settle_fee_sponsorship : (FeeSponsorshipSettlement) -> ()   one-way, once the withdrawal is submitted
+ *   release_fee_sponsorship : (nat64) -> ()                      one-way, with the quote id of a dropped quote
+ * the sponsor funds the fee out of the bitcoin on its own deposit address of this
+ * wallet, and receives the runes there
+ */
+const QUOTE_METHOD: &str = "quote_fee_sponsorship";
+const SETTLE_METHOD: &str = "settle_fee_sponsorship";
+const RELEASE_METHOD: &str = "release_fee_sponsorship";
+
+// vbytes of a p2pkh input, a quote leaves room for the selection growing by one
+const INPUT_VBYTES: u64 = 148;
+
+#[derive(CandidType, Deserialize)]
+pub struct FeeSponsorshipRequest {
+    pub principal: Principal,
+    pub runeid: RuneId,
+    // bitcoin the withdrawal takes from the sponsor at most
+    pub btc_amount: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct FeeSponsorshipQuote {
+    pub quote_id: u64,
+    // runes of `runeid` the sponsor wants for the bitcoin
+    pub rune_amount: u128,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct FeeSponsorshipSettlement {
+    pub quote_id: u64,
+    pub txid: String,
+    pub rune_amount: u128,
+    pub btc_amount: u64,
+}
+
+pub fn sponsor() -> Option<Principal> {
+    read_config(|config| config.fee_sponsor)
+}
+
+pub fn escrows_of(principal: &Principal) -> Vec<(u64, FeeEscrow)> {
+    read_fee_escrows(|escrows| {
+        escrows
+            .iter()
+            .filter(|(_, escrow)| escrow.principal == *principal)
+            .collect()
+    })
+}
+
+async fn quote(
+    sponsor: Principal,
+    request: FeeSponsorshipRequest,
+) -> Result<FeeSponsorshipQuote, WalletError> {
+    let (result,): (Result<FeeSponsorshipQuote, String>,) =
+        ic_cdk::call(sponsor, QUOTE_METHOD, (request,))
+            .await
+            .map_err(|(code, msg)| {
+                WalletError::SponsorUnavailable(format!("{:?}: {}", code, msg))
+            })?;
+    let quote = result.map_err(WalletError::SponsorUnavailable)?;
+    if quote.expires_at <= ic_cdk::api::time() {
+        return Err(WalletError::SponsorUnavailable(String::from(
+            "the quote expired before it could be used",
+        )));
+    }
+    Ok(quote)
+}
+
+fn hold(
+    principal: Principal,
+    sponsor: Principal,
+    runeid: &RuneId,
+    btc_amount: u64,
+    quote: &FeeSponsorshipQuote,
+) -> u64 {
+    let now = ic_cdk::api::time();
+    write_fee_escrows(|escrows| {
+        let id = escrows.last_key_value().map_or(0, |(id, _)| id + 1);
+        escrows.insert(
+            id,
+            FeeEscrow {
+                principal,
+                sponsor,
+                quote_id: quote.quote_id,
+                runeid: runeid.clone(),
+                rune_amount: quote.rune_amount,
+                btc_amount,
+                status: EscrowStatus::Held,
+                created_at: now,
+                updated_at: now,
+            },
+        );
+        id
+    })
+}
+
+fn close(id: u64, status: EscrowStatus) -> Option<FeeEscrow> {
+    write_fee_escrows(|escrows| {
+        let mut escrow = escrows.get(&id)?;
+        escrow.status = status;
+        escrow.updated_at = ic_cdk::api::time();
+        escrows.insert(id, escrow.clone());
+        Some(escrow)
+    })
+}
+
+fn settle(id: u64, txid: &str) {
+    let Some(escrow) = close(
+        id,
+        EscrowStatus::Settled {
+            txid: txid.to_string(),
+        },
+    ) else {
+        return;
+    };
+    record_event_for(
+        escrow.principal,
+        EventKind::FeeSponsored {
+            sponsor: escrow.sponsor,
+            runeid: escrow.runeid,
+            rune_amount: escrow.rune_amount,
+            btc_amount: escrow.btc_amount,
+            txid: txid.to_string(),
+        },
+    );
+    let settlement = FeeSponsorshipSettlement {
+        quote_id: escrow.quote_id,
+        txid: txid.to_string(),
+        rune_amount: escrow.rune_amount,
+        btc_amount: escrow.btc_amount,
+    };
+    if let Err(code) = ic_cdk::notify(escrow.sponsor, SETTLE_METHOD, (settlement,)) {
+        ic_cdk::println!(
+            "failed notifying {} of a settlement: {:?}",
+            escrow.sponsor,
+            code
+        );
+    }
+}
+
+fn release(id: u64, reason: &WalletError) {
+    let Some(escrow) = close(
+        id,
+        EscrowStatus::Released {
+            reason: format!("{:?}", reason),
+        },
+    ) else {
+        return;
+    };
+    if let Err(code) = ic_cdk::notify(escrow.sponsor, RELEASE_METHOD, (escrow.quote_id,)) {
+        ic_cdk::println!(
+            "failed notifying {} of a release: {:?}",
+            escrow.sponsor,
+            code
+        );
+    }
+}
+
+/*
+ * a rune withdrawal whose fee and postage the configured sponsor funds, for senders
+ * short of bitcoin. the sponsor is asked for a quote covering what a trial build
+ * takes from it, then the withdrawal is built again with the sponsor's runes on an
+ * output of its own. the escrow holds the quote until the withdrawal is submitted
+ * and the sponsor is told to settle, or fails and the sponsor is told to release
+ */
+pub async fn withdraw_sponsored(
+    sender_addresses: Addresses,
+    runeid: RuneId,
+    amount: u128,
+    to: String,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    // only the caller's own runes pay a sponsor, not the treasury's or a delegated owner's
+    let (Some(sponsor), Some(principal)) = (sponsor(), caller_owning(&sender_addresses.bitcoin))
+    else {
+        return Err(WalletError::InsufficientBalance);
+    };
+    // only bitcoin is short here, the sponsor isn't bothered for missing runes
+    if read_utxo_manager(|manager| {
+        manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+    }) < amount
+    {
+        return Err(WalletError::InsufficientBalance);
+    }
+    let sponsor_addresses = generate_addresses_from_principal(&sponsor);
+    let sender = bitcoin::address_validation(&sender_addresses.bitcoin)
+        .map_err(WalletError::InvalidAddress)?;
+    let receiver = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    let sponsor_address = bitcoin::address_validation(&sponsor_addresses.bitcoin)
+        .map_err(WalletError::InvalidAddress)?;
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+
+    let transfer = |rune_amount: u128| {
+        bitcoin::runestone::transfer_sponsored(
+            RuneTransferArgs {
+                runeid: runeid.clone(),
+                amount,
+                sender_addr: &sender_addresses.bitcoin,
+                receiver_addr: &to,
+                sender_account: sender_addresses.icrc1,
+                receiver_account: sender_addresses.icrc1,
+                sender_address: sender.clone(),
+                receiver_address: receiver.clone(),
+                fee_per_vbytes,
+                paid_by_sender: true,
+                postage: None,
+            },
+            FeeSponsorship {
+                addr: sponsor_addresses.bitcoin.clone(),
+                account: sponsor_addresses.icrc1,
+                address: sponsor_address.clone(),
+                rune_amount,
+            },
+        )
+    };
+    // the trial takes the least runes, the quote doesn't know the sponsor's share yet
+    let trial = match transfer(1) {
+        Ok(trial) => trial,
+        Err(_) => {
+            updater::fetch_utxos_and_update_balances(
+                &sponsor_addresses.bitcoin,
+                TargetType::Bitcoin { target: u64::MAX },
+            )
+            .await;
+            transfer(1).map_err(|_| WalletError::InsufficientBalance)?
+        }
+    };
+    let btc_amount =
+        bitcoin::runestone::sponsor_outlay(&trial) + INPUT_VBYTES * fee_per_vbytes / 1000;
+    release_locked_utxos(trial.locked_utxos());
+
+    let quote = quote(
+        sponsor,
+        FeeSponsorshipRequest {
+            principal,
+            runeid: runeid.clone(),
+            btc_amount,
+        },
+    )
+    .await?;
+    let id = hold(principal, sponsor, &runeid, btc_amount, &quote);
+    let txn = match transfer(quote.rune_amount) {
+        Ok(txn) if bitcoin::runestone::sponsor_outlay(&txn) <= btc_amount => txn,
+        Ok(txn) => {
+            release_locked_utxos(txn.locked_utxos());
+            let err = WalletError::SponsorUnavailable(String::from(
+                "the withdrawal costs more than the quote covers",
+            ));
+            release(id, &err);
+            return Err(err);
+        }
+        Err(_) => {
+            let err = WalletError::InsufficientBalance;
+            release(id, &err);
+            return Err(err);
+        }
+    };
+    let submitted = txn.build_and_submit(None).await;
+    match &submitted {
+        Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => settle(id, txid),
+        Err(err) => release(id, err),
+    }
+    submitted
+}
+
+// whether the withdrawal fell short of bitcoin the sponsor could provide
+pub fn applies(result: &Result<TransactionType, WalletError>) -> bool {
+    matches!(result, Err(WalletError::InsufficientBalance)) && sponsor().is_some()
+}
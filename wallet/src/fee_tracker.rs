@@ -0,0 +1,208 @@
+use std::{cell::RefCell, time::Duration};
+
+use candid::CandidType;
+use ic_cdk::api::management_canister::bitcoin::GetCurrentFeePercentilesRequest;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin_api::bitcoin_get_current_fee_percentiles,
+    state::{
+        read_config, read_fee_history, record_event, write_fee_history, ConfirmationSample,
+        EventKind, FeeSample, TransactionRecord,
+    },
+};
+
+const NANOS_PER_HOUR: u64 = 3_600 * 1_000_000_000;
+
+// samples the suggested tiers are averaged over
+const SUGGESTION_WINDOW_HOURS: u64 = 6;
+
+// weight of the newer sample in the moving averages, in tenths
+const EWMA_WEIGHT: u64 = 3;
+
+// our own confirmations the latency is averaged over
+const LATENCY_SAMPLES: usize = 20;
+
+// slower confirmations push the medium and high tiers up, by at most twice the rate
+const TARGET_CONFIRMATION_SECS: u64 = 60 * 60;
+
+// lowest rate relayed by default, the floor unless one is configured
+const MIN_RELAY_FEE: u64 = 1_000;
+
+thread_local! {
+    static SAMPLING_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+// summary of the median fee over a window, in millisatoshi per vbyte
+#[derive(CandidType)]
+pub struct FeeTrend {
+    pub samples: u64,
+    pub average_median: u64,
+    pub lowest_median: u64,
+    pub lowest_median_at: u64,
+    pub highest_median: u64,
+    // median of the latest sample minus the one of the oldest sample in the window
+    pub change: i64,
+}
+
+// rates in millisatoshi per vbyte, each tier at least as high as the one before
+#[derive(CandidType)]
+pub struct FeeSuggestions {
+    pub low: u64,
+    pub medium: u64,
+    pub high: u64,
+    // timestamp of the newest sample behind the tiers
+    pub sampled_at: u64,
+    // average time our recent withdrawals took to confirm
+    pub confirmation_latency_secs: Option<u64>,
+}
+
+// (re)starts the periodic sampling with the configured interval
+pub fn start_sampling() {
+    let interval = read_config(|config| config.fee_sampling_interval_mins());
+    SAMPLING_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(interval * 60),
+            || ic_cdk::spawn(sample()),
+        ));
+    });
+}
+
+async fn sample() {
+    let network = read_config(|config| config.bitcoin_network());
+    let percentiles = match bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest {
+        network,
+    })
+    .await
+    {
+        Ok((percentiles,)) => percentiles,
+        Err((_, err)) => {
+            ic_cdk::println!("failed to sample fee percentiles: {}", err);
+            return;
+        }
+    };
+    record_percentiles(&percentiles);
+}
+
+// every percentile lookup feeds the history, the sampling timer's and withdrawals' alike
+pub fn record_percentiles(percentiles: &[u64]) {
+    // regtest without any non-coinbase transactions has nothing to sample
+    if percentiles.len() < 91 {
+        return;
+    }
+    let previous_median = read_fee_history(|history| history.latest()).map(|sample| sample.p50);
+    if let Some(previous_median) = previous_median.filter(|median| *median != percentiles[50]) {
+        record_event(EventKind::FeeReestimated {
+            previous_median,
+            median: percentiles[50],
+        });
+    }
+    write_fee_history(|history| {
+        history.record(FeeSample {
+            timestamp: ic_cdk::api::time(),
+            p10: percentiles[10],
+            p25: percentiles[25],
+            p50: percentiles[50],
+            p75: percentiles[75],
+            p90: percentiles[90],
+        })
+    });
+}
+
+pub fn get_fee_history(hours: u64) -> Vec<FeeSample> {
+    let since = ic_cdk::api::time().saturating_sub(hours.saturating_mul(NANOS_PER_HOUR));
+    read_fee_history(|history| history.since(since))
+}
+
+pub fn get_fee_trend(hours: u64) -> Option<FeeTrend> {
+    let samples = get_fee_history(hours);
+    let (first, last) = (samples.first()?, samples.last()?);
+    let lowest = samples.iter().min_by_key(|sample| sample.p50)?;
+    let highest = samples.iter().map(|sample| sample.p50).max()?;
+    let total: u128 = samples.iter().map(|sample| sample.p50 as u128).sum();
+    Some(FeeTrend {
+        samples: samples.len() as u64,
+        average_median: (total / samples.len() as u128) as u64,
+        lowest_median: lowest.p50,
+        lowest_median_at: lowest.timestamp,
+        highest_median: highest,
+        change: last.p50 as i64 - first.p50 as i64,
+    })
+}
+
+// called once the outputs of one of our withdrawals were found confirmed
+pub fn record_confirmation(record: &TransactionRecord) {
+    let Some(vsize) = record.vsize.filter(|vsize| *vsize > 0) else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    write_fee_history(|history| {
+        history.record_confirmation(ConfirmationSample {
+            txid: record.txid.clone(),
+            fee_rate: record.fee * 1000 / vsize,
+            latency_secs: now.saturating_sub(record.timestamp) / 1_000_000_000,
+            confirmed_at: now,
+        })
+    });
+}
+
+pub fn confirmation_latency() -> Option<u64> {
+    let samples = read_fee_history(|history| history.recent_confirmations(LATENCY_SAMPLES));
+    if samples.is_empty() {
+        return None;
+    }
+    let total: u64 = samples.iter().map(|sample| sample.latency_secs).sum();
+    Some(total / samples.len() as u64)
+}
+
+fn ewma(values: impl Iterator<Item = u64>) -> Option<u64> {
+    values.fold(None, |average, value| {
+        Some(match average {
+            None => value,
+            Some(average) => (value * EWMA_WEIGHT + average * (10 - EWMA_WEIGHT)) / 10,
+        })
+    })
+}
+
+pub fn clamp_to_bounds(fee: u64) -> u64 {
+    match read_config(|config| config.fee_bounds.clone()) {
+        Some(bounds) => fee.clamp(bounds.floor, bounds.ceiling),
+        None => fee.max(MIN_RELAY_FEE),
+    }
+}
+
+/*
+ * moving averages of the 25th, 50th and 75th percentiles over the recent samples.
+ * while our own withdrawals confirm slower than the target, the medium and high
+ * tiers go up in proportion. none without samples in the window
+*/
+pub fn get_fee_suggestions() -> Option<FeeSuggestions> {
+    let samples = get_fee_history(SUGGESTION_WINDOW_HOURS);
+    let sampled_at = samples.last()?.timestamp;
+    let low = ewma(samples.iter().map(|sample| sample.p25))?;
+    let medium = ewma(samples.iter().map(|sample| sample.p50))?;
+    let high = ewma(samples.iter().map(|sample| sample.p75))?;
+    let latency = confirmation_latency();
+    let permille = latency.map_or(1_000, |latency| {
+        (latency * 1_000 / TARGET_CONFIRMATION_SECS).clamp(1_000, 2_000)
+    });
+    Some(FeeSuggestions {
+        low: clamp_to_bounds(low),
+        medium: clamp_to_bounds(medium * permille / 1_000),
+        high: clamp_to_bounds(high * permille / 1_000),
+        sampled_at,
+        confirmation_latency_secs: latency,
+    })
+}
+
+// the medium tier while the sampling keeps it fresh, saving a percentile lookup
+pub fn default_fee() -> Option<u64> {
+    let suggestions = get_fee_suggestions()?;
+    let max_age =
+        2 * read_config(|config| config.fee_sampling_interval_mins()) * 60 * 1_000_000_000;
+    (ic_cdk::api::time().saturating_sub(suggestions.sampled_at) <= max_age)
+        .then_some(suggestions.medium)
+}
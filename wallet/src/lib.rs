@@ -1,35 +1,184 @@
+mod api_stats;
+mod approvals;
+mod batcher;
 mod bitcoin;
+mod bitcoin_api;
+mod bookkeeping;
+mod certification;
+mod circuit_breaker;
+mod ckbtc;
+mod collector;
+mod cycle_monitor;
+mod delegation;
+mod deposit_scanner;
+mod exchange_rate;
+mod external_addresses;
+mod fee_quotes;
+mod fee_sponsor;
+mod fee_tracker;
+mod metrics;
+mod migration;
 mod ord_canister;
+mod outbox;
+mod pending_change;
+mod rate_limiter;
+mod reconciler;
+#[cfg(feature = "regtest-mock")]
+mod regtest_mock;
+mod rune_ledger;
+mod rune_metadata;
+mod splitter;
 mod state;
+mod statement;
+mod submission_queue;
+mod subscriptions;
+mod sweeper;
+mod templates;
 mod transaction_handler;
+mod treasury;
 mod types;
 mod updater;
 mod utils;
+mod webhook;
+mod withdrawal_policy;
 
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
+use ::bitcoin::psbt::Psbt;
+use api_stats::ApiStats;
 use bitcoin::{
-    account_to_p2pkh_address, combined_txn::CombinedTransactionRequest, get_fee_per_vbyte,
-    multi_sender_txn::MultiSendTransactionArgument, runestone::RuneTransferArgs,
+    account_to_p2pkh_address,
+    cpfp::{anchor_utxo, CpfpArgs, MIN_ANCHOR_VALUE},
+    get_fee_per_vbyte,
+    multi_sender_txn::{self, MultiSendTransactionArgument, SenderArgument},
+    multisig::{MultisigTransferArgs, MultisigWallet, MultisigWithdrawal},
+    runestone::{
+        DecodedArtifact, MultiRuneTransferArgs, RuneConsolidationArgs, RuneSplitArgs,
+        RuneTransferArgs, RuneTransferRequirements,
+    },
+    BitcoinSweepArgs, BitcoinTransferArgs, Branch, Intent, PlanError, TxPlanner,
 };
-use candid::Principal;
+use bitcoin_api::bitcoin_get_balance;
+use candid::{Nat, Principal};
+use certification::CertifiedSnapshotResponse;
+use cycle_monitor::CycleStatus;
+use fee_tracker::{FeeSuggestions, FeeTrend};
+use metrics::Metrics;
+use ord_canister::OrdBackend;
 // re export
 use ic_cdk::{
     api::management_canister::{
-        bitcoin::{bitcoin_get_balance, BitcoinNetwork, GetBalanceRequest},
+        bitcoin::{BitcoinNetwork, GetBalanceRequest, Outpoint, Utxo},
         ecdsa::{
             ecdsa_public_key, EcdsaKeyId, EcdsaPublicKeyArgument,
             EcdsaPublicKeyResponse as EcdsaPublicKey,
         },
+        http_request::{HttpResponse, TransformArgs},
+        schnorr::{schnorr_public_key, SchnorrPublicKeyArgument},
     },
     init, post_upgrade, pre_upgrade, query, update,
 };
-use icrc_ledger_types::icrc1::account::Account;
-use state::{read_config, read_utxo_manager, write_config};
-use transaction_handler::SubmittedTransactionIdType;
-use types::RuneId;
-use updater::TargetType;
-use utils::{generate_addresses_from_principal, subaccount_with_num, Addresses};
+use icrc_ledger_types::{
+    icrc::generic_metadata_value::MetadataValue,
+    icrc1::{
+        account::Account,
+        transfer::{TransferArg, TransferError},
+    },
+};
+use state::{
+    read_batched_withdrawals, read_ckbtc_wraps, read_config, read_contacts, read_event_log,
+    read_imported_addresses, read_jars, read_outbox, read_pending_multisig,
+    read_prepared_withdrawals, read_split_withdrawals, read_submission_queue, read_sync_cursors,
+    read_transaction_log, read_utxo_manager, read_webhook_queue, read_withdrawal_proposals,
+    record_event, record_event_for, write_ckbtc_auto_wrap, write_config, write_contacts,
+    write_jars, write_pending_multisig, write_transaction_log, write_utxo_manager, AddressBalance,
+    ApprovalPolicy, BalanceSample, BatchedWithdrawal, BatchingPolicy, CallUsage, Chain,
+    ChangeSplitPolicy, CircuitBreaker, CkbtcWrap, Contact, CycleThresholds, DailyLimits,
+    DailyUsage, DepositScanPolicy, DerivationScheme, DestinationPolicy, Event, EventKind,
+    FeeBounds, FeeEscrow, FeeQuote, FeeSample, FiatLimits, ImportedAddress, Jar, LedgerToken,
+    LockedUtxos, OutboxEntry, OutputRole, Pause, PendingMultisig, PendingSubmission, RateLimits,
+    ReconciliationPolicy, RuneMetadata, RunePolicy, RuneQuoteSource, RunicUtxo, SnapshotDelta,
+    SplitPolicy, SplitWithdrawal, SweepPolicy, TransactionKind, TransactionRecord,
+    TransactionStatus, TreasuryMode, WebhookDelivery, WithdrawalProposal, MAX_MEMO_SIZE,
+};
+use statement::Statement;
+use transaction_handler::{
+    InternalTransfer, PsbtWithdrawal, SubmittedTransactionIdType, TransactionType,
+};
+use types::{
+    BatchPayoutReceipt, BitcoinBatchReceipt, CachedBalances, ChunkedWithdrawal, CoinSelection,
+    Destination, Feature, FeeEstimate, FeePayer, FiatRate, Health, InitArgs, MessageSigFormat,
+    PendingTransaction, PublicConfig, RuneBalanceDetail, RuneId, SenderShortfall, SignedMessage,
+    SweepAll, TemplateArgs, TemplateKind, TokenType, TreasuryBalance, UnsignedTemplate, UtxoInfo,
+    UtxoInvariantReport, WalletError,
+};
+use updater::{ScanReport, TargetType};
+use utils::{
+    fee_pool_addresses, generate_addresses_from_principal,
+    generate_change_addresses_from_principal, generate_numbered_addresses_from_principal,
+    generate_taproot_addresses_from_principal, is_controller, subaccount_with_num,
+    treasury_addresses, Addresses,
+};
+
+// bumped whenever an existing method's signature changes incompatibly,
+// additions are advertised through `get_supported_features` instead
+pub const INTERFACE_VERSION: u32 = 4;
+
+fn validate_memo(memo: &Option<Vec<u8>>) -> Result<(), WalletError> {
+    match memo {
+        Some(memo) if memo.len() > MAX_MEMO_SIZE => Err(WalletError::InvalidArgument(format!(
+            "memo exceeds {} bytes",
+            MAX_MEMO_SIZE
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// the payload of an on-chain memo, unlike `validate_memo` which covers internal transfers
+fn validate_op_return(memo: &Option<Vec<u8>>) -> Result<(), WalletError> {
+    match memo {
+        Some(memo) if memo.len() > bitcoin::MAX_OP_RETURN_SIZE => {
+            Err(WalletError::InvalidArgument(format!(
+                "memo exceeds {} bytes",
+                bitcoin::MAX_OP_RETURN_SIZE
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn ensure_rune_supported(runeid: &RuneId) -> Result<(), WalletError> {
+    if read_config(|config| config.rune_policy().is_supported(runeid)) {
+        Ok(())
+    } else {
+        Err(WalletError::RuneNotSupported(runeid.clone()))
+    }
+}
+
+// `allow_any_script` lets advanced users send to script types without rune support
+fn ensure_rune_receiver(
+    receiver: &::bitcoin::Address,
+    allow_any_script: Option<bool>,
+) -> Result<(), WalletError> {
+    if allow_any_script.unwrap_or(false) {
+        return Ok(());
+    }
+    bitcoin::rune_receiver_validation(receiver).map_err(WalletError::UnsupportedReceiverScript)
+}
+
+// amounts above the split threshold only leave through `withdraw_bitcoin_split`
+fn ensure_below_split_threshold(amount: u64) -> Result<(), WalletError> {
+    match read_config(|config| config.split_policy.clone()) {
+        Some(policy) if amount > policy.threshold => Err(WalletError::InvalidArgument(format!(
+            "amounts above {} sats are paid out through withdraw_bitcoin_split",
+            policy.threshold
+        ))),
+        _ => Ok(()),
+    }
+}
 
 async fn lazy_ecdsa_setup() {
     let ecdsa_keyid: EcdsaKeyId = read_config(|config| config.ecdsakeyid());
@@ -49,478 +198,4643 @@ async fn lazy_ecdsa_setup() {
     });
 }
 
+async fn lazy_schnorr_setup() {
+    let schnorr_keyid = read_config(|config| config.schnorrkeyid());
+    let schnorr_response = schnorr_public_key(SchnorrPublicKeyArgument {
+        canister_id: None,
+        derivation_path: vec![],
+        key_id: schnorr_keyid,
+    })
+    .await
+    .expect("Failed to get schnorr key")
+    .0;
+
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.schnorr_public_key = Some(EcdsaPublicKey {
+            public_key: schnorr_response.public_key,
+            chain_code: schnorr_response.chain_code,
+        });
+        let _ = config.set(temp);
+    });
+}
+
 #[init]
-pub fn init(bitcoin_network: BitcoinNetwork) {
-    let keyname = match bitcoin_network {
-        BitcoinNetwork::Mainnet => "key_1".to_string(),
-        BitcoinNetwork::Testnet => "test_key_1".to_string(),
-        BitcoinNetwork::Regtest => "dfx_test_key".to_string(),
-    };
+pub fn init(bitcoin_network: BitcoinNetwork, args: Option<InitArgs>) {
+    let args = args.unwrap_or(InitArgs {
+        derivation_scheme: None,
+        chain: None,
+    });
+    let chain = args.chain.unwrap_or_else(|| Chain::from(bitcoin_network));
+    if chain.bitcoin_network() != bitcoin_network {
+        ic_cdk::trap(&format!(
+            "{:?} runs on the {:?} bitcoin canister",
+            chain,
+            chain.bitcoin_network()
+        ));
+    }
     write_config(|config| {
         let mut temp = config.get().clone();
-        temp.keyname.replace(keyname);
+        temp.keyname.replace(chain.keyname());
         temp.bitcoin_network.replace(bitcoin_network);
+        temp.chain = Some(chain);
+        temp.derivation_scheme = args.derivation_scheme;
         let _ = config.set(temp);
     });
     ic_cdk_timers::set_timer(Duration::from_secs(0), || ic_cdk::spawn(lazy_ecdsa_setup()));
+    ic_cdk_timers::set_timer(Duration::from_secs(0), || {
+        ic_cdk::spawn(lazy_schnorr_setup())
+    });
+    fee_tracker::start_sampling();
+    sweeper::start_sweeping();
+    reconciler::start_reconciliation();
+    batcher::start_batching();
+    outbox::start_delivery();
+    templates::start_expiry();
+    collector::start_collecting();
+    fee_quotes::start_expiry();
+    certification::start_snapshots();
+    bookkeeping::start_sampling();
+    splitter::start_splitting();
+    ckbtc::start_minting();
+    deposit_scanner::start_scanning();
+    webhook::start_delivery();
+    submission_queue::start_retries();
+    treasury::start_pooling();
+    cycle_monitor::start_monitoring();
 }
 
 #[pre_upgrade]
 pub fn pre_upgrade() {}
 
 #[post_upgrade]
-pub fn post_upgrade() {}
+pub fn post_upgrade() {
+    write_utxo_manager(|manager| manager.migrate_legacy_records());
+    // canisters installed before taproot support don't have the schnorr key yet
+    if read_config(|config| config.schnorr_public_key.is_none()) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), || {
+            ic_cdk::spawn(lazy_schnorr_setup())
+        });
+    }
+    fee_tracker::start_sampling();
+    sweeper::start_sweeping();
+    reconciler::start_reconciliation();
+    batcher::start_batching();
+    outbox::start_delivery();
+    templates::start_expiry();
+    collector::start_collecting();
+    fee_quotes::start_expiry();
+    certification::start_snapshots();
+    bookkeeping::start_sampling();
+    splitter::start_splitting();
+    ckbtc::start_minting();
+    deposit_scanner::start_scanning();
+    webhook::start_delivery();
+    submission_queue::start_retries();
+    treasury::start_pooling();
+    cycle_monitor::start_monitoring();
+}
 
 #[update]
 pub async fn withdraw_bitcoin(
-    to: String,
+    to: Destination,
     amount: u64,
     fee_per_vbytes: Option<u64>,
-) -> SubmittedTransactionIdType {
-    let caller = ic_cdk::caller();
-    let addresses = generate_addresses_from_principal(&caller);
-    let to = bitcoin::address_validation(&to).unwrap();
-    let from = bitcoin::address_validation(&addresses.bitcoin).unwrap();
-    let mut utxo_synced = false;
-    let mut current_balance =
-        read_utxo_manager(|manager| manager.get_bitcoin_balance(&addresses.bitcoin));
-    if current_balance < amount {
-        utxo_synced = true;
-        updater::fetch_utxos_and_update_balances(
-            &addresses.bitcoin,
-            TargetType::Bitcoin { target: amount },
-        )
-        .await;
-        current_balance =
-            read_utxo_manager(|manager| manager.get_bitcoin_balance(&addresses.bitcoin));
-        if current_balance < amount {
-            ic_cdk::trap("not enough balance")
+    fee_payer: FeePayer,
+    coin_selection: Option<CoinSelection>,
+    memo: Option<Vec<u8>>,
+    change_address: Option<String>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_bitcoin", async move {
+        circuit_breaker::ensure_running()?;
+        let to = resolve_destination(&ic_cdk::caller(), to)?;
+        validate_op_return(&memo)?;
+        let caller = ic_cdk::caller();
+        // credits left over from treasury mode still pay out of the pool once it's off
+        let pooled = treasury::is_enabled() || treasury::credit_of(&caller) >= amount;
+        let change_to = resolve_change_address(&caller, change_address)?;
+        if pooled && change_to.is_some() {
+            return Err(WalletError::InvalidArgument(String::from(
+                "withdrawals out of the treasury pool keep their change in it",
+            )));
         }
-    }
-    let fee_per_vbytes = match fee_per_vbytes {
-        None => get_fee_per_vbyte().await,
-        Some(fee) => fee,
-    };
-    let txn = match bitcoin::transfer(
-        &addresses.bitcoin,
-        addresses.icrc1,
-        from.clone(),
-        to.clone(),
-        amount,
-        true,
-        fee_per_vbytes,
-    ) {
-        Err(required_value) => {
-            if utxo_synced && required_value < current_balance {
-                ic_cdk::trap("not enough balance")
-            }
-            updater::fetch_utxos_and_update_balances(
-                &addresses.bitcoin,
-                TargetType::Bitcoin {
-                    target: required_value,
-                },
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        if !pooled {
+            ensure_unallocated(caller, amount).await?;
+        }
+        let charge =
+            withdrawal_policy::charge(ic_cdk::caller(), TokenType::Bitcoin, amount as u128)?;
+        let submitted = if pooled {
+            treasury::withdraw(
+                caller,
+                to,
+                amount,
+                fee_per_vbytes,
+                fee_payer,
+                coin_selection.unwrap_or_default(),
+                memo,
             )
-            .await;
-            if let Ok(txn) = bitcoin::transfer(
-                &addresses.bitcoin,
-                addresses.icrc1,
-                from,
+            .await
+        } else {
+            let txn = bitcoin_withdrawal(
+                caller,
                 to,
                 amount,
-                true,
                 fee_per_vbytes,
-            ) {
-                txn
-            } else {
-                ic_cdk::trap("not enough balance")
+                fee_payer,
+                coin_selection.unwrap_or_default(),
+                memo,
+                change_to,
+            )
+            .await?;
+            txn.build_and_submit(None).await
+        };
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// runes per sweep transaction, keeping the runestone within the standard OP_RETURN size
+const RUNES_PER_SWEEP: usize = 8;
+
+/*
+ * moves everything on the caller's deposit and change addresses to `to`, together
+ * with all of its supported runes when `include_runes` is set. the runes leave first
+ * as their fees come out of the bitcoin, then the bitcoin goes without a change
+ * output in as few transactions as the input limit allows. the bitcoin change of the
+ * rune transfers only arrives once they confirm and takes another sweep
+ */
+#[update]
+pub async fn sweep_all(
+    to: String,
+    include_runes: bool,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SweepAll, WalletError> {
+    api_stats::track("sweep_all", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let receiver = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+        if include_runes {
+            ensure_rune_receiver(&receiver, None)?;
+        }
+        if jar_allocation(&caller) + fee_quotes::reserved_by(&caller) > 0 {
+            return Err(WalletError::InvalidArgument(String::from(
+                "jars and fee quotes holding bitcoin have to be released first",
+            )));
+        }
+        let addresses = generate_addresses_from_principal(&caller);
+        let change_addresses = generate_change_addresses_from_principal(&caller);
+        let from =
+            bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+        let change = bitcoin::address_validation(&change_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+        updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, u64::MAX)
+            .await;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let mut sweep = SweepAll {
+            txids: vec![],
+            bitcoin: 0,
+            runes: vec![],
+            error: None,
+        };
+
+        if include_runes {
+            let policy = read_config(|config| config.rune_policy());
+            let runes: Vec<(RuneId, u128)> =
+                read_utxo_manager(|manager| manager.all_rune_with_balances(&addresses.bitcoin))
+                    .into_iter()
+                    .filter(|(runeid, balance)| *balance > 0 && policy.is_supported(runeid))
+                    .collect();
+            for batch in runes.chunks(RUNES_PER_SWEEP) {
+                let args = MultiRuneTransferArgs {
+                    runes: batch.to_vec(),
+                    sender_addr: &addresses.bitcoin,
+                    receiver_addr: &to,
+                    sender_account: addresses.icrc1,
+                    receiver_account: addresses.icrc1, // sender is the fee payer
+                    sender_address: from.clone(),
+                    receiver_address: receiver.clone(),
+                    fee_per_vbytes,
+                    paid_by_sender: true,
+                    postage: None,
+                };
+                match sweep_runes(caller, batch, args).await {
+                    Ok(txid) => {
+                        sweep.txids.push(txid);
+                        sweep.runes.extend_from_slice(batch);
+                    }
+                    Err(err) if sweep.txids.is_empty() => return Err(err),
+                    Err(err) => {
+                        sweep.error = Some(err);
+                        return Ok(sweep);
+                    }
+                }
             }
         }
-        Ok(txn) => txn,
+
+        // paper trading hands the utxos back, so the rounds are fixed up front
+        let utxos = read_utxo_manager(|manager| {
+            manager.bitcoin_utxos(&addresses.bitcoin).len()
+                + manager.bitcoin_utxos(&change_addresses.bitcoin).len()
+        });
+        for _ in 0..utxos.div_ceil(bitcoin::MAX_TX_INPUTS) {
+            let args = BitcoinSweepArgs {
+                receive: Branch {
+                    addr: &addresses.bitcoin,
+                    account: addresses.icrc1,
+                    address: from.clone(),
+                },
+                change: Branch {
+                    addr: &change_addresses.bitcoin,
+                    account: change_addresses.icrc1,
+                    address: change.clone(),
+                },
+                to: receiver.clone(),
+                fee_per_vbytes,
+            };
+            // whatever can't pay for its own inputs stays behind
+            let Ok((txn, swept)) = bitcoin::transfer_all(args) else {
+                break;
+            };
+            match sweep_bitcoin(caller, txn, swept).await {
+                Ok(txid) => {
+                    sweep.txids.push(txid);
+                    sweep.bitcoin += swept;
+                }
+                Err(err) if sweep.txids.is_empty() => return Err(err),
+                Err(err) => {
+                    sweep.error = Some(err);
+                    break;
+                }
+            }
+        }
+        if sweep.txids.is_empty() {
+            return Err(WalletError::InsufficientBalance);
+        }
+        Ok(sweep)
+    })
+    .await
+}
+
+// one batch of a rune sweep, charged against the daily limits of each rune
+async fn sweep_runes(
+    caller: Principal,
+    runes: &[(RuneId, u128)],
+    args: MultiRuneTransferArgs<'_>,
+) -> Result<String, WalletError> {
+    for (runeid, amount) in runes {
+        exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), *amount).await?;
+    }
+    let charges = runes
+        .iter()
+        .map(|(runeid, amount)| {
+            withdrawal_policy::charge(caller, TokenType::Runestone(runeid.clone()), *amount)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let txn =
+        bitcoin::runestone::transfer_many(args).map_err(|_| WalletError::InsufficientBalance)?;
+    let submitted = txn.build_and_submit(None).await;
+    for charge in charges {
+        charge.settle_if(&submitted);
+    }
+    let SubmittedTransactionIdType::Bitcoin { txid, .. } = submitted?;
+    Ok(txid)
+}
+
+// the checks of a plain withdrawal, run once the swept value is known
+async fn sweep_bitcoin(
+    caller: Principal,
+    txn: TransactionType,
+    swept: u64,
+) -> Result<String, WalletError> {
+    let checked = async {
+        ensure_below_split_threshold(swept)?;
+        approvals::ensure_below_approval_threshold(swept)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, swept as u128).await?;
+        let charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, swept as u128)?;
+        Ok::<_, WalletError>((charge, rate))
+    }
+    .await;
+    let (charge, rate) = match checked {
+        Ok(charged) => charged,
+        Err(err) => {
+            transaction_handler::release_locked_utxos(txn.locked_utxos());
+            return Err(err);
+        }
     };
-    txn.build_and_submit().await.expect("should submit the txn")
+    let submitted = txn.build_and_submit(None).await;
+    charge.settle_if(&submitted);
+    exchange_rate::record_rate(&submitted, rate);
+    let SubmittedTransactionIdType::Bitcoin { txid, .. } = submitted?;
+    Ok(txid)
 }
 
+/*
+ * for wallets holding many small utxos. the withdrawal is paid out over as many
+ * transactions as needed, each spending at most `MAX_TX_INPUTS` utxos with the
+ * sender paying every fee
+ */
 #[update]
-pub async fn withdraw_bitcoin_from_multiple_addresses(
-    principal0: Principal,
+pub async fn withdraw_bitcoin_chunked(
     to: String,
     amount: u64,
     fee_per_vbytes: Option<u64>,
-) -> SubmittedTransactionIdType {
-    let caller = ic_cdk::caller();
-    let (amount0, amount1) = {
-        let is_even = amount % 2 == 0;
-        if is_even {
-            let amount_in_half = amount / 2;
-            (amount_in_half, amount_in_half)
-        } else {
-            let amount_in_half = (amount - 1) / 2;
-            (amount_in_half + 1, amount_in_half)
+) -> Result<ChunkedWithdrawal, WalletError> {
+    api_stats::track("withdraw_bitcoin_chunked", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        ensure_unallocated(caller, amount).await?;
+        let addresses = generate_addresses_from_principal(&caller);
+        let change_addresses = generate_change_addresses_from_principal(&caller);
+        updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, amount)
+            .await;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, amount as u128)?;
+        // receiver, change and anchor outputs along with the transaction overhead
+        const OUTPUTS_VSIZE: u64 = 150;
+        const DUST_THRESHOLD: u64 = 1_000;
+        let mut withdrawal = ChunkedWithdrawal {
+            txids: vec![],
+            withdrawn: 0,
+            error: None,
+        };
+        while withdrawal.withdrawn < amount {
+            // the builder picks the smallest utxos first, receive branch before change
+            let utxos: Vec<Utxo> = read_utxo_manager(|manager| {
+                let mut utxos = manager.bitcoin_utxos(&addresses.bitcoin);
+                utxos.extend(manager.bitcoin_utxos(&change_addresses.bitcoin));
+                utxos.truncate(bitcoin::MAX_TX_INPUTS);
+                utxos
+            });
+            let capacity: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+            let vsize = utxos.len() as u64 * bitcoin::P2PKH_INPUT_VSIZE + OUTPUTS_VSIZE;
+            let fee = vsize * fee_per_vbytes / 1000
+                + bitcoin::cpfp::anchor_output().map_or(0, |anchor| anchor.value.to_sat());
+            let chunk = (amount - withdrawal.withdrawn)
+                .min(capacity.saturating_sub(fee + DUST_THRESHOLD + 1));
+            if chunk <= DUST_THRESHOLD {
+                if withdrawal.txids.is_empty() {
+                    return Err(WalletError::InsufficientBalance);
+                }
+                withdrawal.error = Some(WalletError::InsufficientBalance);
+                break;
+            }
+            let submitted = match bitcoin_withdrawal(
+                caller,
+                to.clone(),
+                chunk,
+                Some(fee_per_vbytes),
+                FeePayer::Sender,
+                CoinSelection::default(),
+                None,
+                None,
+            )
+            .await
+            {
+                Ok(txn) => txn.build_and_submit(None).await,
+                Err(e) => Err(e),
+            };
+            exchange_rate::record_rate(&submitted, rate.clone());
+            match submitted {
+                Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => {
+                    withdrawal.txids.push(txid);
+                    withdrawal.withdrawn += chunk;
+                }
+                Err(e) if withdrawal.txids.is_empty() => return Err(e),
+                Err(e) => {
+                    withdrawal.error = Some(e);
+                    break;
+                }
+            }
         }
-    };
-    let addresses0 = generate_addresses_from_principal(&principal0);
-    let addresses1 = generate_addresses_from_principal(&caller);
-    let address0 = bitcoin::address_validation(&addresses0.bitcoin).unwrap();
-    let address1 = bitcoin::address_validation(&addresses1.bitcoin).unwrap();
-    let to = bitcoin::address_validation(&to).unwrap();
-    let fee_per_vbytes = match fee_per_vbytes {
-        None => get_fee_per_vbyte().await,
-        Some(fee) => fee,
-    };
-    let (mut utxo_synced0, mut utxo_synced1) = (false, false);
-    let (mut current_balance0, mut current_balance1) = read_utxo_manager(|manager| {
-        let balance0 = manager.get_bitcoin_balance(&addresses0.bitcoin);
-        let balance1 = manager.get_bitcoin_balance(&addresses1.bitcoin);
-        (balance0, balance1)
-    });
-    if current_balance0 < amount0 {
-        utxo_synced0 = true;
-        updater::fetch_utxos_and_update_balances(
-            &addresses0.bitcoin,
-            TargetType::Bitcoin { target: amount0 },
-        )
-        .await;
-    }
-    if current_balance1 < amount1 {
-        utxo_synced1 = true;
-        updater::fetch_utxos_and_update_balances(
-            &addresses1.bitcoin,
-            TargetType::Bitcoin { target: amount1 },
-        )
-        .await;
-    }
-    read_utxo_manager(|manager| {
-        current_balance0 = manager.get_bitcoin_balance(&addresses0.bitcoin);
-        current_balance1 = manager.get_bitcoin_balance(&addresses1.bitcoin);
-    });
-    if current_balance0 < amount0 || current_balance1 < amount1 {
-        ic_cdk::trap("not enough balance")
-    }
-    let txn = match bitcoin::multi_sender_txn::transfer(MultiSendTransactionArgument {
-        addr0: &addresses0.bitcoin,
-        addr1: &addresses1.bitcoin,
-        address0: address0.clone(),
-        address1: address1.clone(),
-        account0: addresses0.icrc1,
-        account1: addresses1.icrc1,
-        amount1,
-        amount0,
-        paid_by_sender: true,
-        receiver: to.clone(),
-        fee_per_vbytes,
-    }) {
-        Ok(txn) => txn,
-        Err((required_amount0, required_amount1)) => {
-            if required_amount0 > current_balance0 && !utxo_synced0 {
-                updater::fetch_utxos_and_update_balances(
-                    &addresses0.bitcoin,
-                    TargetType::Bitcoin {
-                        target: required_amount0,
+        // the part left unpaid goes back to the daily allowance
+        charge.settle(withdrawal.withdrawn as u128);
+        Ok(withdrawal)
+    })
+    .await
+}
+
+// payouts per `withdraw_bitcoin_batch`, keeping the transaction well within the standard size
+const MAX_BATCH_PAYOUTS: usize = 100;
+
+/*
+ * pays every receiver out of the caller's balance in a single transaction, the caller
+ * pays the fee. the receipt splits the fee between the payouts in proportion to their
+ * amounts, the rounding remainder is put on the first one
+ */
+#[update]
+pub async fn withdraw_bitcoin_batch(
+    payouts: Vec<(String, u64)>,
+    fee_per_vbytes: Option<u64>,
+    change_address: Option<String>,
+) -> Result<BitcoinBatchReceipt, WalletError> {
+    api_stats::track("withdraw_bitcoin_batch", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let change_to = resolve_change_address(&caller, change_address)?;
+        if payouts.is_empty() || payouts.len() > MAX_BATCH_PAYOUTS {
+            return Err(WalletError::InvalidArgument(format!(
+                "a batch pays between 1 and {} receivers",
+                MAX_BATCH_PAYOUTS
+            )));
+        }
+        let mut receivers = vec![];
+        for (to, amount) in payouts.iter() {
+            if *amount <= bitcoin::DUST_THRESHOLD {
+                return Err(WalletError::InvalidArgument(format!(
+                    "payout of {} sats to {} is below the dust threshold",
+                    amount, to
+                )));
+            }
+            let receiver = bitcoin::address_validation(to).map_err(WalletError::InvalidAddress)?;
+            withdrawal_policy::ensure_destination_allowed(to)?;
+            receivers.push((receiver, *amount));
+        }
+        let amount = payouts
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or_else(|| {
+                WalletError::InvalidArgument(String::from("payouts exceed the bitcoin supply"))
+            })?;
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        ensure_unallocated(caller, amount).await?;
+        let addresses = generate_addresses_from_principal(&caller);
+        let change_addresses = generate_change_addresses_from_principal(&caller);
+        let from =
+            bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+        let change = bitcoin::address_validation(&change_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+        updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, amount)
+            .await;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, amount as u128)?;
+        let plan = || {
+            let planner = receivers
+                .iter()
+                .fold(
+                    TxPlanner::new(
+                        Branch {
+                            addr: &addresses.bitcoin,
+                            account: addresses.icrc1,
+                            address: from.clone(),
+                        },
+                        fee_per_vbytes,
+                    ),
+                    |planner, (to, amount)| {
+                        planner.with(Intent::SendBtc {
+                            to: to.clone(),
+                            amount: *amount,
+                        })
                     },
                 )
-                .await;
+                .with(Intent::ChangeTo(Branch {
+                    addr: &change_addresses.bitcoin,
+                    account: change_addresses.icrc1,
+                    address: change.clone(),
+                }));
+            match change_to.clone() {
+                Some(address) => planner.with(Intent::ChangeOutputTo(address)),
+                None => planner,
             }
-            if required_amount1 > current_balance1 && !utxo_synced1 {
-                updater::fetch_utxos_and_update_balances(
-                    &addresses1.bitcoin,
-                    TargetType::Bitcoin {
-                        target: required_amount1,
-                    },
+            .plan()
+        };
+        let txn = match plan() {
+            Ok(txn) => txn,
+            Err(PlanError::Shortfall(required_value)) => {
+                updater::fetch_bitcoin_branches(
+                    &addresses.bitcoin,
+                    &change_addresses.bitcoin,
+                    required_value,
                 )
                 .await;
+                plan()?
             }
-            read_utxo_manager(|manager| {
-                current_balance0 = manager.get_bitcoin_balance(&addresses0.bitcoin);
-                current_balance1 = manager.get_bitcoin_balance(&addresses1.bitcoin);
-            });
-            if current_balance0 < required_amount0 || current_balance1 < required_amount1 {
-                ic_cdk::trap("not enough balance")
-            }
-            if let Ok(txn) = bitcoin::multi_sender_txn::transfer(MultiSendTransactionArgument {
-                addr0: &addresses0.bitcoin,
-                addr1: &addresses1.bitcoin,
-                address0,
-                address1,
-                account0: addresses0.icrc1,
-                account1: addresses1.icrc1,
-                amount1,
-                amount0,
-                paid_by_sender: true,
-                receiver: to,
-                fee_per_vbytes,
-            }) {
-                txn
-            } else {
-                ic_cdk::trap("not enough balance")
-            }
-        }
-    };
-    txn.build_and_submit().await.expect("failed to submit txn")
+            Err(err) => return Err(err.into()),
+        };
+        let fee = txn.fee_with_anchor();
+        let submitted = txn.build_and_submit(None).await;
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        let SubmittedTransactionIdType::Bitcoin { txid, .. } = submitted?;
+        let mut receipts: Vec<BatchPayoutReceipt> = payouts
+            .into_iter()
+            .map(|(to, payout)| BatchPayoutReceipt {
+                fee: (fee as u128 * payout as u128 / amount as u128) as u64,
+                to,
+                amount: payout,
+            })
+            .collect();
+        let remainder = fee - receipts.iter().map(|receipt| receipt.fee).sum::<u64>();
+        receipts[0].fee += remainder;
+        Ok(BitcoinBatchReceipt {
+            txid,
+            fee,
+            payouts: receipts,
+        })
+    })
+    .await
 }
 
+/*
+ * pays the withdrawal out as a job of several transactions spread over the split
+ * policy's interval. the returned job lists each part's txid once it is submitted
+ */
 #[update]
-pub async fn withdraw_runestone(
-    runeid: RuneId,
-    amount: u128,
+pub async fn withdraw_bitcoin_split(
     to: String,
+    amount: u64,
     fee_per_vbytes: Option<u64>,
-) -> SubmittedTransactionIdType {
+) -> Result<SplitWithdrawal, WalletError> {
+    api_stats::track("withdraw_bitcoin_split", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        if amount == 0 {
+            return Err(WalletError::InvalidArgument(String::from(
+                "amount must be non-zero",
+            )));
+        }
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        ensure_unallocated(caller, amount).await?;
+        let addresses = generate_addresses_from_principal(&caller);
+        let change_addresses = generate_change_addresses_from_principal(&caller);
+        updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, amount)
+            .await;
+        if bitcoin_branches_balance(&caller) < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+        let charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, amount as u128)?;
+        let job = splitter::create(caller, to, amount, fee_per_vbytes);
+        charge.settle_if(&job);
+        job
+    })
+    .await
+}
+
+#[query]
+pub fn get_split_withdrawal(id: u64) -> Result<SplitWithdrawal, WalletError> {
     let caller = ic_cdk::caller();
-    let sender_addresses = generate_addresses_from_principal(&caller);
+    let withdrawal = read_split_withdrawals(|withdrawals| withdrawals.get(&id))
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no such withdrawal job")))?;
+    if withdrawal.caller != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(withdrawal)
+}
 
-    let sender = bitcoin::address_validation(&sender_addresses.bitcoin).unwrap();
-    let receiver = bitcoin::address_validation(&to).unwrap();
-    let fee_per_vbytes = match fee_per_vbytes {
-        None => get_fee_per_vbyte().await,
-        Some(fee) => fee,
-    };
+#[query]
+pub fn get_split_withdrawals() -> Vec<SplitWithdrawal> {
+    let caller = ic_cdk::caller();
+    read_split_withdrawals(|withdrawals| {
+        withdrawals
+            .iter()
+            .map(|(_, withdrawal)| withdrawal)
+            .filter(|withdrawal| withdrawal.caller == caller)
+            .collect()
+    })
+}
 
-    let mut utxo_synced = false;
-    let mut current_rune_balance = read_utxo_manager(|manager| {
-        manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
-    });
+// parts already submitted stay submitted
+#[update]
+pub fn cancel_split_withdrawal(id: u64) -> Result<(), WalletError> {
+    splitter::cancel(id, ic_cdk::caller())
+}
 
-    if current_rune_balance < amount {
-        utxo_synced = true;
-        updater::fetch_utxos_and_update_balances(
-            &sender_addresses.bitcoin,
-            TargetType::Bitcoin { target: u64::MAX },
-        )
-        .await;
-        current_rune_balance = read_utxo_manager(|manager| {
-            manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
-        });
+/*
+ * queues a bitcoin withdrawal above the approval threshold. it gets submitted by the
+ * approval that completes the quorum, unless it expires first
+ */
+#[update]
+pub fn propose_withdrawal(
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<WithdrawalProposal, WalletError> {
+    approvals::propose(ic_cdk::caller(), to, amount, fee_per_vbytes)
+}
 
-        if current_rune_balance < amount {
-            ic_cdk::trap("not enough balance")
-        }
+#[update]
+pub async fn approve_withdrawal(id: u64) -> Result<WithdrawalProposal, WalletError> {
+    api_stats::track("approve_withdrawal", async move {
+        circuit_breaker::ensure_running()?;
+        approvals::approve(id, ic_cdk::caller()).await
+    })
+    .await
+}
+
+#[update]
+pub fn cancel_withdrawal_proposal(id: u64) -> Result<(), WalletError> {
+    approvals::cancel(id, ic_cdk::caller())
+}
+
+#[query]
+pub fn get_withdrawal_proposal(id: u64) -> Result<WithdrawalProposal, WalletError> {
+    let caller = ic_cdk::caller();
+    let proposal = approvals::get(id)?;
+    if proposal.caller != caller
+        && !approvals::is_approver(&caller)
+        && !ic_cdk::api::is_controller(&caller)
+    {
+        return Err(WalletError::Unauthorized);
     }
-    let txn = match bitcoin::runestone::transfer(RuneTransferArgs {
-        runeid: runeid.clone(),
-        amount,
-        sender_addr: &sender_addresses.bitcoin,
-        receiver_addr: &to,
-        sender_account: sender_addresses.icrc1,
-        receiver_account: sender_addresses.icrc1, // sender is the fee payer
-        sender_address: sender.clone(),
-        receiver_address: receiver.clone(),
-        paid_by_sender: true,
-        fee_per_vbytes,
-        postage: None,
-    }) {
-        Ok(txn) => txn,
-        Err((_, fee)) => {
-            // ignoring the rune amount, as it is checked earlier
-            let mut current_btc_balance =
-                read_utxo_manager(|manager| manager.get_bitcoin_balance(&sender_addresses.bitcoin));
-            if fee > current_btc_balance && !utxo_synced {
-                updater::fetch_utxos_and_update_balances(
-                    &sender_addresses.bitcoin,
-                    TargetType::Bitcoin { target: u64::MAX },
-                )
-                .await;
-                current_btc_balance = read_utxo_manager(|manager| {
-                    manager.get_bitcoin_balance(&sender_addresses.bitcoin)
-                });
-                if current_btc_balance < fee {
-                    ic_cdk::trap("not enough balance")
+    Ok(proposal)
+}
+
+// approvers see the proposals of every principal, everyone else only their own
+#[query]
+pub fn get_withdrawal_proposals() -> Vec<WithdrawalProposal> {
+    let caller = ic_cdk::caller();
+    let all = approvals::is_approver(&caller) || ic_cdk::api::is_controller(&caller);
+    read_withdrawal_proposals(|proposals| {
+        proposals
+            .iter()
+            .map(|(_, proposal)| proposal)
+            .filter(|proposal| all || proposal.caller == caller)
+            .collect()
+    })
+}
+
+// usd rate of the token, fetched from the exchange rate canister unless cached
+#[update]
+pub async fn get_usd_rate(token: TokenType) -> Result<FiatRate, WalletError> {
+    exchange_rate::usd_rate(&token).await
+}
+
+// value of `amount` in the token's smallest unit, in usd cents
+#[update]
+pub async fn get_usd_value(token: TokenType, amount: u128) -> Result<u64, WalletError> {
+    exchange_rate::usd_cents(&token, amount)
+        .await
+        .map(|cents| cents.min(u64::MAX as u128) as u64)
+}
+
+#[query]
+pub fn get_cached_usd_rates() -> Vec<FiatRate> {
+    exchange_rate::cached_rates()
+}
+
+#[update(guard = "is_controller")]
+pub fn set_fiat_limits(limits: Option<FiatLimits>) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.fiat_limits = limits;
+        let _ = config.set(temp);
+    })
+}
+
+#[query]
+pub fn get_fiat_limits() -> Option<FiatLimits> {
+    read_config(|config| config.fiat_limits.clone())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_rune_quote_sources(sources: Vec<RuneQuoteSource>) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.rune_quote_sources = Some(sources);
+        let _ = config.set(temp);
+    })
+}
+
+#[update(guard = "is_controller")]
+pub fn set_split_policy(policy: Option<SplitPolicy>) -> Result<(), WalletError> {
+    if policy
+        .as_ref()
+        .is_some_and(|policy| policy.threshold == 0 || policy.max_part == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "split threshold and part size must be non-zero",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.split_policy = policy;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_split_policy() -> Option<SplitPolicy> {
+    read_config(|config| config.split_policy.clone())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_change_split_policy(policy: Option<ChangeSplitPolicy>) -> Result<(), WalletError> {
+    if policy.as_ref().is_some_and(|policy| {
+        policy.outputs == 0
+            || policy.outputs > bitcoin::MAX_CHANGE_OUTPUTS
+            || policy.threshold <= bitcoin::DUST_THRESHOLD
+    }) {
+        return Err(WalletError::InvalidArgument(format!(
+            "change splits need a threshold above dust and 1 to {} outputs",
+            bitcoin::MAX_CHANGE_OUTPUTS
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.change_split_policy = policy;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_change_split_policy() -> Option<ChangeSplitPolicy> {
+    read_config(|config| config.change_split_policy.clone())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_approval_policy(policy: Option<ApprovalPolicy>) -> Result<(), WalletError> {
+    if policy
+        .as_ref()
+        .is_some_and(|policy| policy.quorum == 0 || policy.quorum as usize > policy.approvers.len())
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "quorum must be between one and the number of approvers",
+        )));
+    }
+    if policy
+        .as_ref()
+        .is_some_and(|policy| policy.expiry_secs == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "proposal expiry must be non-zero",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.approval_policy = policy;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_approval_policy() -> Option<ApprovalPolicy> {
+    read_config(|config| config.approval_policy.clone())
+}
+
+/*
+ * quotas on the endpoints syncing arbitrary addresses, since their bitcoin api calls
+ * are paid by the canister. none lifts every quota
+ */
+#[update(guard = "is_controller")]
+pub fn set_rate_limits(limits: Option<RateLimits>) -> Result<(), WalletError> {
+    if limits
+        .as_ref()
+        .is_some_and(|limits| limits.window_secs == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "rate limit window must be non-zero",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.rate_limits = limits;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_rate_limits() -> Option<RateLimits> {
+    read_config(|config| config.rate_limits.clone())
+}
+
+// metered calls of the principal and the cycles they cost
+#[query]
+pub fn get_call_usage(principal: Principal) -> Result<CallUsage, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(rate_limiter::usage_of(&principal))
+}
+
+/*
+ * moves `amount` of the caller's bitcoin to the ckbtc minter, which mints it to the
+ * caller's icrc-1 account once the transaction has the minter's confirmations
+ */
+#[update]
+pub async fn withdraw_as_ckbtc(amount: u64) -> Result<CkbtcWrap, WalletError> {
+    api_stats::track("withdraw_as_ckbtc", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        let charge =
+            withdrawal_policy::charge(ic_cdk::caller(), TokenType::Bitcoin, amount as u128)?;
+        let wrap = ckbtc::wrap(ic_cdk::caller(), amount).await;
+        charge.settle_if(&wrap);
+        wrap
+    })
+    .await
+}
+
+// opts the caller's future bitcoin deposits in or out of being wrapped into ckbtc
+#[update]
+pub fn set_ckbtc_auto_wrap(enabled: bool) {
+    let caller = ic_cdk::caller();
+    write_ckbtc_auto_wrap(|principals| {
+        if enabled {
+            principals.insert(caller, ());
+        } else {
+            principals.remove(&caller);
+        }
+    })
+}
+
+#[query]
+pub fn get_ckbtc_wraps() -> Vec<CkbtcWrap> {
+    let caller = ic_cdk::caller();
+    read_ckbtc_wraps(|wraps| {
+        wraps
+            .iter()
+            .map(|(_, wrap)| wrap)
+            .filter(|wrap| wrap.principal == caller)
+            .collect()
+    })
+}
+
+#[update(guard = "is_controller")]
+pub fn set_ckbtc_minter(minter: Option<Principal>) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.ckbtc_minter = minter;
+        let _ = config.set(temp);
+    })
+}
+
+// signs a bitcoin withdrawal without broadcasting it, returns the txid to pass to `broadcast_withdrawal`
+// within a day, after that the withdrawal is cancelled like by `cancel_prepared_withdrawal`
+#[update]
+pub async fn prepare_withdrawal(
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+    fee_payer: FeePayer,
+    coin_selection: Option<CoinSelection>,
+) -> Result<String, WalletError> {
+    api_stats::track("prepare_withdrawal", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        ensure_unallocated(ic_cdk::caller(), amount).await?;
+        let charge =
+            withdrawal_policy::charge(ic_cdk::caller(), TokenType::Bitcoin, amount as u128)?;
+        let txn = bitcoin_withdrawal(
+            ic_cdk::caller(),
+            to,
+            amount,
+            fee_per_vbytes,
+            fee_payer,
+            coin_selection.unwrap_or_default(),
+            None,
+            None,
+        )
+        .await?;
+        let prepared = txn.prepare(None).await;
+        charge.settle_if(&prepared);
+        prepared
+    })
+    .await
+}
+
+// safe to retry, an already broadcast withdrawal returns its txid again
+#[update]
+pub async fn broadcast_withdrawal(txid: String) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("broadcast_withdrawal", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        match read_prepared_withdrawals(|prepared| prepared.get(&txid)) {
+            Some(prepared) if prepared.record.caller != caller => Err(WalletError::Unauthorized),
+            Some(_) => transaction_handler::broadcast_prepared(&txid).await,
+            None => match read_transaction_log(|log| log.find_by_txid(&txid)) {
+                Some(record) if record.caller == caller => {
+                    let raw_transaction =
+                        read_transaction_log(|log| log.raw_transaction(&txid)).unwrap_or_default();
+                    Ok(SubmittedTransactionIdType::receipt(
+                        &record,
+                        raw_transaction,
+                        None,
+                    ))
                 }
+                Some(_) => Err(WalletError::TransactionNotFound),
+                // the first broadcast is still awaiting the bitcoin canister's answer
+                None => match read_submission_queue(|queue| queue.get(&txid)) {
+                    Some(submission) if submission.record.caller == caller => {
+                        Ok(SubmittedTransactionIdType::receipt(
+                            &submission.record,
+                            submission.raw_transaction,
+                            None,
+                        ))
+                    }
+                    _ => Err(WalletError::TransactionNotFound),
+                },
+            },
+        }
+    })
+    .await
+}
+
+/*
+ * runs the selection and fee logic of a withdrawal without signing anything. the
+ * selected utxos are held for a few minutes, `commit_unsigned_template` signs the
+ * transaction as a prepared withdrawal and `release_unsigned_template` gives them back
+ */
+#[update]
+pub async fn build_unsigned(
+    kind: TemplateKind,
+    args: TemplateArgs,
+) -> Result<UnsignedTemplate, WalletError> {
+    api_stats::track("build_unsigned", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let TemplateArgs {
+            to,
+            amount,
+            fee_per_vbytes,
+        } = args;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let token = match kind {
+            TemplateKind::Bitcoin { .. } => TokenType::Bitcoin,
+            TemplateKind::Runestone { ref runeid } => TokenType::Runestone(runeid.clone()),
+        };
+        exchange_rate::ensure_within_limit(&token, amount).await?;
+        if let TemplateKind::Bitcoin { .. } = kind {
+            let amount = template_sats(amount)?;
+            ensure_below_split_threshold(amount)?;
+            approvals::ensure_below_approval_threshold(amount)?;
+            ensure_unallocated(caller, amount).await?;
+        }
+        let charge = withdrawal_policy::charge(caller, token.clone(), amount)?;
+        let txn = match kind {
+            TemplateKind::Bitcoin {
+                fee_payer,
+                coin_selection,
+            } => {
+                let amount = template_sats(amount)?;
+                bitcoin_withdrawal(
+                    caller,
+                    to,
+                    amount,
+                    fee_per_vbytes,
+                    fee_payer,
+                    coin_selection.unwrap_or_default(),
+                    None,
+                    None,
+                )
+                .await?
             }
-            if let Ok(txn) = bitcoin::runestone::transfer(RuneTransferArgs {
-                runeid,
-                amount,
-                sender_addr: &sender_addresses.bitcoin,
-                receiver_addr: &to,
-                sender_account: sender_addresses.icrc1,
-                receiver_account: sender_addresses.icrc1, // sender is the fee payer
-                sender_address: sender,
-                receiver_address: receiver,
-                paid_by_sender: true,
-                fee_per_vbytes,
-                postage: None,
-            }) {
-                txn
-            } else {
-                ic_cdk::trap("not enough balance")
+            TemplateKind::Runestone { runeid } => {
+                ensure_rune_supported(&runeid)?;
+                runestone_withdrawal(
+                    generate_addresses_from_principal(&caller),
+                    runeid,
+                    amount,
+                    to,
+                    fee_per_vbytes,
+                    None,
+                )
+                .await?
+            }
+        };
+        let template = templates::reserve(txn);
+        charge.settle_if(&template);
+        template
+    })
+    .await
+}
+
+fn template_sats(amount: u128) -> Result<u64, WalletError> {
+    u64::try_from(amount).map_err(|_| {
+        WalletError::InvalidArgument(String::from("amount exceeds the bitcoin supply"))
+    })
+}
+
+/*
+ * builds and signs a withdrawal like `build_unsigned` followed by a commit, but returns
+ * it as a psbt for the caller to finalize, the finalized transaction goes through
+ * `submit_raw_transaction`. the signatures are out, so it can't be cancelled
+ */
+#[update]
+pub async fn build_psbt(
+    kind: TemplateKind,
+    args: TemplateArgs,
+) -> Result<PsbtWithdrawal, WalletError> {
+    api_stats::track("build_psbt", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let TemplateArgs {
+            to,
+            amount,
+            fee_per_vbytes,
+        } = args;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let token = match kind {
+            TemplateKind::Bitcoin { .. } => TokenType::Bitcoin,
+            TemplateKind::Runestone { ref runeid } => TokenType::Runestone(runeid.clone()),
+        };
+        exchange_rate::ensure_within_limit(&token, amount).await?;
+        if let TemplateKind::Bitcoin { .. } = kind {
+            let amount = template_sats(amount)?;
+            ensure_below_split_threshold(amount)?;
+            approvals::ensure_below_approval_threshold(amount)?;
+            ensure_unallocated(caller, amount).await?;
+        }
+        let charge = withdrawal_policy::charge(caller, token.clone(), amount)?;
+        let txn = match kind {
+            TemplateKind::Bitcoin {
+                fee_payer,
+                coin_selection,
+            } => {
+                let amount = template_sats(amount)?;
+                bitcoin_withdrawal(
+                    caller,
+                    to,
+                    amount,
+                    fee_per_vbytes,
+                    fee_payer,
+                    coin_selection.unwrap_or_default(),
+                    None,
+                    None,
+                )
+                .await?
+            }
+            TemplateKind::Runestone { runeid } => {
+                ensure_rune_supported(&runeid)?;
+                runestone_withdrawal(
+                    generate_addresses_from_principal(&caller),
+                    runeid,
+                    amount,
+                    to,
+                    fee_per_vbytes,
+                    None,
+                )
+                .await?
+            }
+        };
+        let prepared = txn.prepare_psbt(None).await;
+        charge.settle_if(&prepared);
+        prepared
+    })
+    .await
+}
+
+// broadcasts a withdrawal from `build_psbt` or `prepare_withdrawal` once finalized elsewhere
+#[update]
+pub async fn submit_raw_transaction(
+    raw_transaction: Vec<u8>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("submit_raw_transaction", async move {
+        circuit_breaker::ensure_running()?;
+        let txn: ::bitcoin::Transaction = ::bitcoin::consensus::deserialize(&raw_transaction)
+            .map_err(|e| WalletError::InvalidArgument(format!("invalid transaction: {}", e)))?;
+        let txid = txn.compute_txid().to_string();
+        match read_prepared_withdrawals(|prepared| prepared.get(&txid)) {
+            Some(prepared) if prepared.record.caller != ic_cdk::caller() => {
+                Err(WalletError::Unauthorized)
             }
+            Some(_) => transaction_handler::broadcast_finalized(&txid, raw_transaction).await,
+            None => Err(WalletError::TransactionNotFound),
         }
+    })
+    .await
+}
+
+#[update]
+pub async fn commit_unsigned_template(id: u64) -> Result<String, WalletError> {
+    api_stats::track("commit_unsigned_template", async move {
+        circuit_breaker::ensure_running()?;
+        templates::commit(id, ic_cdk::caller()).await
+    })
+    .await
+}
+
+#[update]
+pub fn release_unsigned_template(id: u64) -> Result<(), WalletError> {
+    templates::release(id, ic_cdk::caller())
+}
+
+// the utxos come back once the chain still shows them unspent a few blocks later
+#[update]
+pub fn cancel_prepared_withdrawal(txid: String) -> Result<(), WalletError> {
+    let caller = ic_cdk::caller();
+    let prepared = read_prepared_withdrawals(|prepared| prepared.get(&txid))
+        .ok_or(WalletError::TransactionNotFound)?;
+    if prepared.record.caller != caller {
+        return Err(WalletError::Unauthorized);
+    }
+    transaction_handler::cancel_prepared(&txid)
+}
+
+const MAX_CONTACTS: usize = 128;
+const MAX_CONTACT_LABEL_LEN: usize = 64;
+
+fn validate_contact_label(label: &str) -> Result<(), WalletError> {
+    if label.trim().is_empty()
+        || label.len() > MAX_CONTACT_LABEL_LEN
+        || label.chars().any(|c| c.is_control())
+    {
+        return Err(WalletError::InvalidArgument(format!(
+            "contact labels are 1 to {} bytes without control characters",
+            MAX_CONTACT_LABEL_LEN
+        )));
+    }
+    Ok(())
+}
+
+// the address a withdrawal pays to, contacts resolve against the caller's address book
+fn resolve_destination(caller: &Principal, to: Destination) -> Result<String, WalletError> {
+    match to {
+        Destination::Address(address) => Ok(address),
+        Destination::Contact(label) => read_contacts(|contacts| contacts.get(caller))
+            .and_then(|contacts| contacts.get(&label).map(|contact| contact.address.clone()))
+            .ok_or_else(|| WalletError::InvalidArgument(String::from("no such contact"))),
+    }
+}
+
+/*
+ * change leaving the caller's branches only goes to an address registered to it, so
+ * a withdrawal can't pay out more than its amount. controllers may pick any address
+ */
+fn resolve_change_address(
+    caller: &Principal,
+    change_address: Option<String>,
+) -> Result<Option<::bitcoin::Address>, WalletError> {
+    let Some(change_address) = change_address else {
+        return Ok(None);
     };
-    txn.build_and_submit().await.unwrap()
+    let address =
+        bitcoin::address_validation(&change_address).map_err(WalletError::InvalidAddress)?;
+    if !ic_cdk::api::is_controller(caller)
+        && external_addresses::owner_of(&change_address) != Some(*caller)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "the change address has to be registered to the caller",
+        )));
+    }
+    Ok(Some(address))
 }
 
+// the address is stored canonical, the destination policy still applies on every withdrawal
 #[update]
-pub async fn withdraw_runestone_with_fee_paid_by_receiver(
-    runeid: RuneId,
-    amount: u128,
-    to: Principal,
-    fee_per_vbytes: Option<u64>,
-) -> SubmittedTransactionIdType {
+pub fn add_contact(label: String, address: String) -> Result<Contact, WalletError> {
+    validate_contact_label(&label)?;
+    let address = withdrawal_policy::canonical_address(&address)?;
+    let caller = ic_cdk::caller();
+    write_contacts(|contacts| {
+        let mut caller_contacts = contacts.get(&caller).unwrap_or_default();
+        if caller_contacts.get(&label).is_some() {
+            return Err(WalletError::InvalidArgument(String::from(
+                "contact already exists",
+            )));
+        }
+        if caller_contacts.0.len() >= MAX_CONTACTS {
+            return Err(WalletError::InvalidArgument(format!(
+                "at most {} contacts per principal",
+                MAX_CONTACTS
+            )));
+        }
+        let contact = Contact {
+            label,
+            address,
+            added_at: ic_cdk::api::time(),
+        };
+        caller_contacts.0.push(contact.clone());
+        contacts.insert(caller, caller_contacts);
+        Ok(contact)
+    })
+}
+
+#[update]
+pub fn remove_contact(label: String) -> Result<(), WalletError> {
     let caller = ic_cdk::caller();
-    let sender_addresses = generate_addresses_from_principal(&caller);
-    let receiver_addresses = generate_addresses_from_principal(&to);
+    write_contacts(|contacts| {
+        let mut caller_contacts = contacts.get(&caller).unwrap_or_default();
+        let before = caller_contacts.0.len();
+        caller_contacts.0.retain(|contact| contact.label != label);
+        if caller_contacts.0.len() == before {
+            return Err(WalletError::InvalidArgument(String::from(
+                "no such contact",
+            )));
+        }
+        if caller_contacts.0.is_empty() {
+            contacts.remove(&caller);
+        } else {
+            contacts.insert(caller, caller_contacts);
+        }
+        Ok(())
+    })
+}
+
+#[query]
+pub fn list_contacts() -> Vec<Contact> {
+    read_contacts(|contacts| contacts.get(&ic_cdk::caller()))
+        .unwrap_or_default()
+        .0
+}
 
-    let sender = bitcoin::address_validation(&sender_addresses.bitcoin).unwrap();
-    let receiver = bitcoin::address_validation(&receiver_addresses.bitcoin).unwrap();
+const MAX_JARS: usize = 32;
+const MAX_JAR_NAME_LEN: usize = 32;
 
-    let (mut current_rune_balance, mut current_btc_balance) = read_utxo_manager(|manager| {
-        (
-            manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid),
-            manager.get_bitcoin_balance(&receiver_addresses.bitcoin),
-        )
-    });
+fn validate_jar_name(name: &str) -> Result<(), WalletError> {
+    if name.is_empty()
+        || name.len() > MAX_JAR_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(WalletError::InvalidArgument(format!(
+            "jar names are 1 to {} characters out of a-z, A-Z, 0-9, - and _",
+            MAX_JAR_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+// bitcoin of the principal's receive and change branches as last synced
+fn bitcoin_branches_balance(principal: &Principal) -> u64 {
+    let addresses = generate_addresses_from_principal(principal);
+    let change_addresses = generate_change_addresses_from_principal(principal);
+    read_utxo_manager(|manager| {
+        manager.get_bitcoin_balance(&addresses.bitcoin)
+            + manager.get_bitcoin_balance(&change_addresses.bitcoin)
+    })
+}
+
+fn jar_allocation(principal: &Principal) -> u64 {
+    read_jars(|jars| jars.get(principal)).map_or(0, |jars| jars.allocated())
+}
+
+// plain withdrawals and allocations into jars only take what no jar or fee quote holds
+async fn ensure_unallocated(principal: Principal, amount: u64) -> Result<(), WalletError> {
+    let allocated = jar_allocation(&principal) + fee_quotes::reserved_by(&principal);
+    if allocated == 0 {
+        return Ok(());
+    }
+    let unallocated = || bitcoin_branches_balance(&principal).saturating_sub(allocated);
+    if unallocated() >= amount {
+        return Ok(());
+    }
+    updater::fetch_bitcoin_branches(
+        &generate_addresses_from_principal(&principal).bitcoin,
+        &generate_change_addresses_from_principal(&principal).bitcoin,
+        amount + allocated,
+    )
+    .await;
+    if unallocated() < amount {
+        return Err(WalletError::InsufficientBalance);
+    }
+    Ok(())
+}
+
+#[update]
+pub fn create_jar(name: String) -> Result<(), WalletError> {
+    validate_jar_name(&name)?;
+    let caller = ic_cdk::caller();
+    write_jars(|jars| {
+        let mut caller_jars = jars.get(&caller).unwrap_or_default();
+        if caller_jars.get(&name).is_some() {
+            return Err(WalletError::InvalidArgument(String::from(
+                "jar already exists",
+            )));
+        }
+        if caller_jars.0.len() >= MAX_JARS {
+            return Err(WalletError::InvalidArgument(format!(
+                "at most {} jars per principal",
+                MAX_JARS
+            )));
+        }
+        caller_jars.0.push(Jar {
+            name,
+            balance: 0,
+            created_at: ic_cdk::api::time(),
+        });
+        jars.insert(caller, caller_jars);
+        Ok(())
+    })
+}
+
+// the jar's balance goes back to the unallocated part
+#[update]
+pub fn delete_jar(name: String) -> Result<(), WalletError> {
+    let caller = ic_cdk::caller();
+    write_jars(|jars| {
+        let mut caller_jars = jars.get(&caller).unwrap_or_default();
+        let before = caller_jars.0.len();
+        caller_jars.0.retain(|jar| jar.name != name);
+        if caller_jars.0.len() == before {
+            return Err(WalletError::InvalidArgument(String::from("no such jar")));
+        }
+        if caller_jars.0.is_empty() {
+            jars.remove(&caller);
+        } else {
+            jars.insert(caller, caller_jars);
+        }
+        Ok(())
+    })
+}
+
+#[query]
+pub fn get_jars() -> Vec<Jar> {
+    read_jars(|jars| jars.get(&ic_cdk::caller()))
+        .unwrap_or_default()
+        .0
+}
+
+// `None` returns the unallocated part of the caller's bitcoin balance
+#[query]
+pub fn get_jar_balance(name: Option<String>) -> Result<u64, WalletError> {
+    let caller = ic_cdk::caller();
+    let jars = read_jars(|jars| jars.get(&caller)).unwrap_or_default();
+    match name {
+        None => Ok(bitcoin_branches_balance(&caller).saturating_sub(jars.allocated())),
+        Some(name) => jars
+            .get(&name)
+            .map(|jar| jar.balance)
+            .ok_or_else(|| WalletError::InvalidArgument(String::from("no such jar"))),
+    }
+}
+
+// `None` on either side stands for the unallocated part of the balance
+#[update]
+pub async fn move_between_jars(
+    from: Option<String>,
+    to: Option<String>,
+    amount: u64,
+) -> Result<(), WalletError> {
+    api_stats::track("move_between_jars", async move {
+        circuit_breaker::ensure_running()?;
+        if from == to || amount == 0 {
+            return Err(WalletError::InvalidArgument(String::from(
+                "a non-zero amount has to move between two different jars",
+            )));
+        }
+        let caller = ic_cdk::caller();
+        if from.is_none() {
+            ensure_unallocated(caller, amount).await?;
+        }
+        write_jars(|jars| {
+            let mut caller_jars = jars.get(&caller).unwrap_or_default();
+            if let Some(ref from) = from {
+                let jar = caller_jars
+                    .get_mut(from)
+                    .ok_or_else(|| WalletError::InvalidArgument(String::from("no such jar")))?;
+                if jar.balance < amount {
+                    return Err(WalletError::InsufficientBalance);
+                }
+                jar.balance -= amount;
+            }
+            if let Some(ref to) = to {
+                caller_jars
+                    .get_mut(to)
+                    .ok_or_else(|| WalletError::InvalidArgument(String::from("no such jar")))?
+                    .balance += amount;
+            }
+            jars.insert(caller, caller_jars);
+            Ok(())
+        })
+    })
+    .await
+}
+
+// the amount along with the fee is taken out of the jar, the sender pays the fee
+#[update]
+pub async fn withdraw_from_jar(
+    name: String,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_from_jar", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let jar_balance = || {
+            read_jars(|jars| jars.get(&caller))
+                .and_then(|jars| jars.get(&name).map(|jar| jar.balance))
+                .ok_or_else(|| WalletError::InvalidArgument(String::from("no such jar")))
+        };
+        if jar_balance()? < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        let charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, amount as u128)?;
+        let txn = bitcoin_withdrawal(
+            caller,
+            to,
+            amount,
+            fee_per_vbytes,
+            FeePayer::Sender,
+            CoinSelection::default(),
+            None,
+            None,
+        )
+        .await?;
+        let charged = amount + txn.fee_with_anchor();
+        // taken before the broadcast, a concurrent withdrawal sees the reduced balance
+        let reserved = write_jars(|jars| {
+            let mut caller_jars = jars.get(&caller)?;
+            let jar = caller_jars.get_mut(&name)?;
+            if jar.balance < charged {
+                return None;
+            }
+            jar.balance -= charged;
+            jars.insert(caller, caller_jars);
+            Some(())
+        });
+        if reserved.is_none() {
+            transaction_handler::release_locked_utxos(txn.locked_utxos());
+            return Err(WalletError::InsufficientBalance);
+        }
+        let submitted = txn.build_and_submit(None).await;
+        if submitted.is_err() {
+            write_jars(|jars| {
+                if let Some(mut caller_jars) = jars.get(&caller) {
+                    if let Some(jar) = caller_jars.get_mut(&name) {
+                        jar.balance += charged;
+                        jars.insert(caller, caller_jars);
+                    }
+                }
+            });
+        }
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// selects the utxos for a withdrawal out of the principal's receive and change branches
+async fn bitcoin_withdrawal(
+    caller: Principal,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+    fee_payer: FeePayer,
+    coin_selection: CoinSelection,
+    memo: Option<Vec<u8>>,
+    change_to: Option<::bitcoin::Address>,
+) -> Result<TransactionType, WalletError> {
+    // a receiver share of the fee can't go below what it's deducted from
+    if fee_payer != FeePayer::Sender && amount <= bitcoin::DUST_THRESHOLD {
+        return Err(WalletError::AmountBelowFee {
+            minimum: bitcoin::DUST_THRESHOLD + 1,
+        });
+    }
+    let addresses = generate_addresses_from_principal(&caller);
+    let change_addresses = generate_change_addresses_from_principal(&caller);
+    let to = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    let from =
+        bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+    let change = bitcoin::address_validation(&change_addresses.bitcoin)
+        .map_err(WalletError::InvalidAddress)?;
+    let balance = || {
+        read_utxo_manager(|manager| {
+            manager.get_bitcoin_balance(&addresses.bitcoin)
+                + manager.get_bitcoin_balance(&change_addresses.bitcoin)
+        })
+    };
+    let mut utxo_synced = false;
+    let mut current_balance = balance();
+    if current_balance < amount {
+        utxo_synced = true;
+        updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, amount)
+            .await;
+        current_balance = balance();
+        if current_balance < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+    }
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+    let args = || BitcoinTransferArgs {
+        receive: Branch {
+            addr: &addresses.bitcoin,
+            account: addresses.icrc1,
+            address: from.clone(),
+        },
+        change: Branch {
+            addr: &change_addresses.bitcoin,
+            account: change_addresses.icrc1,
+            address: change.clone(),
+        },
+        to: to.clone(),
+        amount,
+        fee_payer,
+        fee_per_vbytes,
+        selection: coin_selection,
+        memo: memo.clone(),
+        change_to: change_to.clone(),
+    };
+    let txn = match bitcoin::transfer(args()) {
+        Err(required_value) if fee_payer == FeePayer::Receiver && required_value > amount => {
+            return Err(WalletError::AmountBelowFee {
+                minimum: required_value,
+            });
+        }
+        Err(required_value) => {
+            if utxo_synced && required_value < current_balance {
+                return Err(WalletError::InsufficientBalance);
+            }
+            updater::fetch_bitcoin_branches(
+                &addresses.bitcoin,
+                &change_addresses.bitcoin,
+                required_value,
+            )
+            .await;
+            if let Ok(txn) = bitcoin::transfer(args()) {
+                txn
+            } else {
+                return Err(WalletError::InsufficientBalance);
+            }
+        }
+        Ok(txn) => txn,
+    };
+    Ok(txn)
+}
+
+// queues the withdrawal for the next batch, its txid and vout show up in
+// `get_batched_withdrawal` once the batch is broadcast
+#[update]
+pub async fn queue_bitcoin_withdrawal(to: String, amount: u64) -> Result<u64, WalletError> {
+    api_stats::track("queue_bitcoin_withdrawal", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        ensure_unallocated(ic_cdk::caller(), amount).await?;
+        let charge =
+            withdrawal_policy::charge(ic_cdk::caller(), TokenType::Bitcoin, amount as u128)?;
+        let queued = batcher::enqueue(ic_cdk::caller(), to, amount).await;
+        charge.settle_if(&queued);
+        queued
+    })
+    .await
+}
+
+#[query]
+pub fn get_batched_withdrawal(id: u64) -> Result<BatchedWithdrawal, WalletError> {
+    let caller = ic_cdk::caller();
+    let withdrawal = read_batched_withdrawals(|withdrawals| withdrawals.get(&id))
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no such withdrawal request")))?;
+    if withdrawal.caller != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(withdrawal)
+}
+
+#[query]
+pub fn get_batched_withdrawals() -> Vec<BatchedWithdrawal> {
+    let caller = ic_cdk::caller();
+    read_batched_withdrawals(|withdrawals| {
+        withdrawals
+            .iter()
+            .map(|(_, withdrawal)| withdrawal)
+            .filter(|withdrawal| withdrawal.caller == caller)
+            .collect()
+    })
+}
+
+#[update(guard = "is_controller")]
+pub fn set_batching_policy(policy: Option<BatchingPolicy>) -> Result<(), WalletError> {
+    if policy
+        .as_ref()
+        .is_some_and(|policy| policy.window_secs == 0 || policy.max_requests == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "batching window and request limit must be non-zero",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.batching_policy = policy;
+        let _ = config.set(temp);
+    });
+    batcher::start_batching();
+    Ok(())
+}
+
+#[query]
+pub fn get_batching_policy() -> Option<BatchingPolicy> {
+    read_config(|config| config.batching_policy.clone())
+}
+
+// pays out the queued withdrawals without waiting for the window to close
+#[update(guard = "is_controller")]
+pub async fn flush_withdrawal_batch() {
+    batcher::flush().await
+}
+
+// each sender's share in basis points, every sender listed once and the shares summing to 100%
+fn fee_shares(
+    fee_split: Option<Vec<(Account, u16)>>,
+    accounts: &[Account],
+) -> Result<Vec<u16>, WalletError> {
+    let Some(fee_split) = fee_split else {
+        return Ok(multi_sender_txn::even_fee_shares(accounts.len()));
+    };
+    let share_of = |account: &Account| {
+        let mut shares = fee_split.iter().filter(|(party, _)| party == account);
+        match (shares.next(), shares.next()) {
+            (Some((_, bps)), None) => Ok(*bps),
+            _ => Err(WalletError::InvalidArgument(String::from(
+                "fee split must list each sender exactly once",
+            ))),
+        }
+    };
+    let shares = accounts
+        .iter()
+        .map(share_of)
+        .collect::<Result<Vec<u16>, WalletError>>()?;
+    let total: u32 = shares.iter().map(|bps| *bps as u32).sum();
+    if fee_split.len() != accounts.len() || total != multi_sender_txn::FEE_SPLIT_BPS as u32 {
+        return Err(WalletError::InvalidArgument(format!(
+            "fee split must cover every sender and sum to {} bps",
+            multi_sender_txn::FEE_SPLIT_BPS
+        )));
+    }
+    Ok(shares)
+}
+
+/*
+ * syncs every sender below its required amount, at most once per sender, then lists
+ * the ones still short so the caller learns about all of them at once
+ */
+async fn sender_shortfalls(
+    senders: &[(Principal, String)],
+    required: &[u64],
+    synced: &mut [bool],
+) -> Vec<SenderShortfall> {
+    for (((_, addr), required), synced) in senders.iter().zip(required).zip(synced.iter_mut()) {
+        let balance = read_utxo_manager(|manager| manager.get_bitcoin_balance(addr));
+        if balance < *required && !*synced {
+            *synced = true;
+            updater::fetch_utxos_and_update_balances(
+                addr,
+                TargetType::Bitcoin { target: *required },
+            )
+            .await;
+        }
+    }
+    senders
+        .iter()
+        .zip(required)
+        .filter_map(|((principal, addr), required)| {
+            let available = read_utxo_manager(|manager| manager.get_bitcoin_balance(addr));
+            (available < *required).then(|| SenderShortfall {
+                principal: *principal,
+                address: addr.clone(),
+                required: *required,
+                available,
+            })
+        })
+        .collect()
+}
+
+// most principals funding a single multi-sender transfer
+const MAX_SENDERS: usize = 16;
+
+/*
+ * pays `to` out of the addresses of several principals, each contributing its listed
+ * amount, the caller being one of them. `fee_split` weighs the fee between the
+ * senders' icrc-1 accounts, evenly while unset. the other senders' amounts and fee
+ * shares come out of the bitcoin allowance they gave the caller through `approve`
+ */
+#[update]
+pub async fn withdraw_bitcoin_from_multiple_addresses(
+    senders: Vec<(Principal, u64)>,
+    to: String,
+    fee_per_vbytes: Option<u64>,
+    fee_split: Option<Vec<(Account, u16)>>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_bitcoin_from_multiple_addresses", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        if senders.is_empty() || senders.len() > MAX_SENDERS {
+            return Err(WalletError::InvalidArgument(format!(
+                "between 1 and {} senders are supported",
+                MAX_SENDERS
+            )));
+        }
+        let principals: HashSet<&Principal> =
+            senders.iter().map(|(principal, _)| principal).collect();
+        if principals.len() != senders.len() {
+            return Err(WalletError::InvalidArgument(String::from(
+                "each sender can only be listed once",
+            )));
+        }
+        if !principals.contains(&caller) {
+            return Err(WalletError::Unauthorized);
+        }
+        if senders.iter().any(|(_, amount)| *amount == 0) {
+            return Err(WalletError::InvalidArgument(String::from(
+                "every sender has to contribute a positive amount",
+            )));
+        }
+        let total = senders
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or_else(|| {
+                WalletError::InvalidArgument(String::from("amounts exceed the bitcoin supply"))
+            })?;
+        ensure_below_split_threshold(total)?;
+        approvals::ensure_below_approval_threshold(total)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, total as u128).await?;
+        let addresses: Vec<_> = senders
+            .iter()
+            .map(|(principal, _)| generate_addresses_from_principal(principal))
+            .collect();
+        let accounts: Vec<Account> = addresses.iter().map(|addresses| addresses.icrc1).collect();
+        let fee_shares = fee_shares(fee_split, &accounts)?;
+        let sender_addresses = addresses
+            .iter()
+            .map(|addresses| {
+                bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)
+            })
+            .collect::<Result<Vec<_>, WalletError>>()?;
+        let to = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        // each sender's share counts against its own allowance
+        let charges = senders
+            .iter()
+            .map(|(principal, amount)| {
+                withdrawal_policy::charge(*principal, TokenType::Bitcoin, *amount as u128)
+            })
+            .collect::<Result<Vec<_>, WalletError>>()?;
+        let mut delegated = senders
+            .iter()
+            .filter(|(principal, _)| *principal != caller)
+            .map(|(principal, amount)| {
+                delegation::charge(*principal, caller, TokenType::Bitcoin, *amount as u128)
+            })
+            .collect::<Result<Vec<_>, WalletError>>()?;
+        let args = || MultiSendTransactionArgument {
+            senders: senders
+                .iter()
+                .zip(addresses.iter())
+                .zip(sender_addresses.iter())
+                .zip(fee_shares.iter())
+                .map(
+                    |((((_, amount), addresses), address), fee_bps)| SenderArgument {
+                        addr: addresses.bitcoin.clone(),
+                        address: address.clone(),
+                        account: addresses.icrc1,
+                        amount: *amount,
+                        fee_bps: *fee_bps,
+                    },
+                )
+                .collect(),
+            receiver: to.clone(),
+            fee_per_vbytes,
+            paid_by_sender: true,
+        };
+        let funding: Vec<(Principal, String)> = senders
+            .iter()
+            .zip(addresses.iter())
+            .map(|((principal, _), addresses)| (*principal, addresses.bitcoin.clone()))
+            .collect();
+        let mut required: Vec<u64> = senders.iter().map(|(_, amount)| *amount).collect();
+        let mut synced = vec![false; senders.len()];
+        let mut attempts = 0;
+        let txn = loop {
+            let shortfalls = sender_shortfalls(&funding, &required, &mut synced).await;
+            if !shortfalls.is_empty() {
+                return Err(WalletError::InsufficientSenderBalance(shortfalls));
+            }
+            attempts += 1;
+            match multi_sender_txn::transfer(args()) {
+                Ok(txn) => break txn,
+                // the fee shares raised what the senders need, short ones get synced for it
+                Err(needed) if attempts < 2 => required = needed,
+                Err(needed) => {
+                    let shortfalls = sender_shortfalls(&funding, &needed, &mut synced).await;
+                    return Err(if shortfalls.is_empty() {
+                        WalletError::InsufficientBalance
+                    } else {
+                        WalletError::InsufficientSenderBalance(shortfalls)
+                    });
+                }
+            }
+        };
+        let fees = multi_sender_txn::split_fee(txn.fee_with_anchor(), &fee_shares);
+        for ((principal, _), fee) in senders.iter().zip(fees) {
+            if *principal == caller || fee == 0 {
+                continue;
+            }
+            match delegation::charge(*principal, caller, TokenType::Bitcoin, fee as u128) {
+                Ok(charge) => delegated.push(charge),
+                Err(err) => {
+                    transaction_handler::release_locked_utxos(txn.locked_utxos());
+                    return Err(err);
+                }
+            }
+        }
+        let submitted = txn.build_and_submit(None).await;
+        for charge in charges {
+            charge.settle_if(&submitted);
+        }
+        for charge in delegated {
+            charge.settle_if(&submitted);
+        }
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// lets `spender` withdraw up to `amount` of the caller's `token_type` through
+// `withdraw_from`, replacing the previous allowance. zero revokes it
+#[update]
+pub fn approve(spender: Principal, token_type: TokenType, amount: u128) -> Result<(), WalletError> {
+    delegation::approve(ic_cdk::caller(), spender, token_type, amount)
+}
+
+#[query]
+pub fn get_allowance(owner: Principal, spender: Principal, token_type: TokenType) -> u128 {
+    delegation::allowance(owner, spender, token_type)
+}
+
+// the allowances the caller granted, by spender
+#[query]
+pub fn get_allowances() -> Vec<(Principal, TokenType, u128)> {
+    delegation::allowances_of(ic_cdk::caller())
+}
+
+/*
+ * withdraws `amount` of the owner's `token_type` on their behalf out of the caller's
+ * allowance. bitcoin goes with the fee deducted from the amount so the owner parts
+ * with exactly what the allowance shrinks by, rune fees come out of the owner's bitcoin
+ */
+#[update]
+pub async fn withdraw_from(
+    owner: Principal,
+    token_type: TokenType,
+    to: String,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_from", async move {
+        circuit_breaker::ensure_running()?;
+        let runeid = match token_type {
+            TokenType::Bitcoin => None,
+            TokenType::Runestone(ref runeid) => {
+                ensure_rune_supported(runeid)?;
+                Some(runeid.clone())
+            }
+            _ => {
+                return Err(WalletError::InvalidArgument(String::from(
+                    "only bitcoin and runes can be delegated",
+                )))
+            }
+        };
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate = exchange_rate::ensure_within_limit(&token_type, amount).await?;
+        // bitcoin the owner parts with, a rune withdrawal takes postage and fee out of it
+        let btc_amount = match runeid {
+            None => {
+                let amount = u64::try_from(amount).map_err(|_| WalletError::InsufficientBalance)?;
+                ensure_below_split_threshold(amount)?;
+                amount
+            }
+            Some(ref runeid) => {
+                rune_withdrawal_btc(owner, runeid, amount, &to, fee_per_vbytes).await?
+            }
+        };
+        approvals::ensure_below_approval_threshold(btc_amount)?;
+        ensure_unallocated(owner, btc_amount).await?;
+        let delegated = delegation::charge(owner, ic_cdk::caller(), token_type.clone(), amount)?;
+        let charge = withdrawal_policy::charge(owner, token_type, amount)?;
+        let submitted = match runeid {
+            None => {
+                let txn = bitcoin_withdrawal(
+                    owner,
+                    to,
+                    btc_amount,
+                    fee_per_vbytes,
+                    FeePayer::Receiver,
+                    CoinSelection::default(),
+                    None,
+                    None,
+                )
+                .await?;
+                txn.build_and_submit(None).await
+            }
+            Some(runeid) => {
+                withdraw_runestone_from(
+                    generate_addresses_from_principal(&owner),
+                    runeid,
+                    amount,
+                    to,
+                    fee_per_vbytes,
+                    None,
+                )
+                .await
+            }
+        };
+        delegated.settle_if(&submitted);
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// bitcoin a rune withdrawal of the principal needs on top of its runic utxos, nothing gets spent
+async fn rune_withdrawal_btc(
+    principal: Principal,
+    runeid: &RuneId,
+    amount: u128,
+    to: &str,
+    fee_per_vbytes: Option<u64>,
+) -> Result<u64, WalletError> {
+    let addr = generate_addresses_from_principal(&principal).bitcoin;
+    let sender = bitcoin::address_validation(&addr).map_err(WalletError::InvalidAddress)?;
+    let receiver = bitcoin::address_validation(to).map_err(WalletError::InvalidAddress)?;
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+    if read_utxo_manager(|manager| manager.get_runestone_balance(&addr, runeid)) < amount {
+        updater::fetch_utxos_and_update_balances(
+            &addr,
+            TargetType::Runic {
+                runeid: runeid.clone(),
+                target: amount,
+            },
+        )
+        .await;
+    }
+    bitcoin::runestone::requirements(runeid, amount, &addr, &sender, &receiver, fee_per_vbytes)
+        .map(|requirements| requirements.required_btc)
+        .map_err(|_| WalletError::InsufficientBalance)
+}
+
+#[update]
+pub async fn withdraw_runestone(
+    runeid: RuneId,
+    amount: u128,
+    to: Destination,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestone", async move {
+        circuit_breaker::ensure_running()?;
+        let to = resolve_destination(&ic_cdk::caller(), to)?;
+        ensure_rune_supported(&runeid)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate =
+            exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), amount)
+                .await?;
+        let charge = withdrawal_policy::charge(
+            ic_cdk::caller(),
+            TokenType::Runestone(runeid.clone()),
+            amount,
+        )?;
+        let sender_addresses = generate_addresses_from_principal(&ic_cdk::caller());
+        let submitted = withdraw_runestone_from(
+            sender_addresses,
+            runeid,
+            amount,
+            to,
+            fee_per_vbytes,
+            allow_any_script,
+        )
+        .await;
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+/*
+ * splits the caller's `runeid` into one output per part, all of them paying back to
+ * the caller's address with `postage` each. whatever the spent utxos hold beyond the
+ * parts lands in one more output, the fee comes out of the caller's bitcoin
+ */
+#[update]
+pub async fn split_rune_utxo(
+    runeid: RuneId,
+    parts: Vec<u128>,
+    postage: Option<u64>,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("split_rune_utxo", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_rune_supported(&runeid)?;
+        if parts.is_empty() || parts.contains(&0) {
+            return Err(WalletError::InvalidArgument(String::from(
+                "parts must be non-empty and non-zero",
+            )));
+        }
+        if postage.is_some_and(|postage| postage < bitcoin::runestone::MIN_POSTAGE) {
+            return Err(WalletError::InvalidArgument(format!(
+                "postage must be at least {}",
+                bitcoin::runestone::MIN_POSTAGE
+            )));
+        }
+        restructure_runes(runeid, parts, 0, postage, fee_per_vbytes).await
+    })
+    .await
+}
+
+// inputs a single consolidation merges at most
+const MAX_CONSOLIDATION_INPUTS: u64 = 100;
+
+/*
+ * merges up to `max_inputs` of the caller's smallest `runeid` utxos into one output
+ * at the minimal postage. the postage the merged utxos carried beyond it pays the
+ * fee and comes back as plain bitcoin change
+ */
+#[update]
+pub async fn consolidate_runic_utxos(
+    runeid: RuneId,
+    max_inputs: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("consolidate_runic_utxos", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_rune_supported(&runeid)?;
+        if !(2..=MAX_CONSOLIDATION_INPUTS).contains(&max_inputs) {
+            return Err(WalletError::InvalidArgument(format!(
+                "max_inputs must be between 2 and {}",
+                MAX_CONSOLIDATION_INPUTS
+            )));
+        }
+        let max_inputs = max_inputs as usize;
+        let addresses = generate_addresses_from_principal(&ic_cdk::caller());
+        let address =
+            bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+
+        // rune held by the utxos that would get merged, along with their count
+        let selected = || {
+            read_utxo_manager(|manager| {
+                let utxos = manager.runic_utxos(&addresses.bitcoin, &runeid);
+                let utxos = &utxos[..utxos.len().min(max_inputs)];
+                (
+                    utxos.iter().map(|r_utxo| r_utxo.balance).sum::<u128>(),
+                    utxos.len(),
+                )
+            })
+        };
+        if selected().1 < max_inputs {
+            updater::fetch_utxos_and_update_balances(
+                &addresses.bitcoin,
+                TargetType::Runic {
+                    runeid: runeid.clone(),
+                    target: u128::MAX,
+                },
+            )
+            .await;
+        }
+        updater::verify_runic_selection(&addresses.bitcoin, &runeid, selected().0).await?;
+        if selected().1 < 2 {
+            return Err(WalletError::InvalidArgument(String::from(
+                "fewer than two utxos hold the rune, there is nothing to merge",
+            )));
+        }
+
+        let consolidate = || {
+            bitcoin::runestone::consolidate(RuneConsolidationArgs {
+                runeid: runeid.clone(),
+                max_inputs,
+                addr: &addresses.bitcoin,
+                account: addresses.icrc1,
+                address: address.clone(),
+                fee_per_vbytes,
+            })
+        };
+        let txn = match consolidate() {
+            Ok(txn) => txn,
+            // the fee wasn't covered, the bitcoin balance may just be stale
+            Err(_) => {
+                updater::fetch_utxos_and_update_balances(
+                    &addresses.bitcoin,
+                    TargetType::Bitcoin { target: u64::MAX },
+                )
+                .await;
+                consolidate().map_err(|_| WalletError::InsufficientBalance)?
+            }
+        };
+        txn.build_and_submit(None).await
+    })
+    .await
+}
+
+/*
+ * burns `amount` of the caller's `runeid` by sending it to the OP_RETURN output, the
+ * ord indexer counts it as burned once the transaction is mined. the rest of the
+ * spent runes stay with the caller
+ */
+#[update]
+pub async fn burn_runestone(
+    runeid: RuneId,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("burn_runestone", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_rune_supported(&runeid)?;
+        if amount == 0 {
+            return Err(WalletError::InvalidArgument(String::from(
+                "amount must be non-zero",
+            )));
+        }
+        let charge = withdrawal_policy::charge(
+            ic_cdk::caller(),
+            TokenType::Runestone(runeid.clone()),
+            amount,
+        )?;
+        let submitted = restructure_runes(runeid, vec![], amount, None, fee_per_vbytes).await;
+        charge.settle_if(&submitted);
+        submitted
+    })
+    .await
+}
+
+// splits and burns of the caller's runes, the outputs all pay back to the caller
+async fn restructure_runes(
+    runeid: RuneId,
+    parts: Vec<u128>,
+    burn: u128,
+    postage: Option<u64>,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let total = parts
+        .iter()
+        .try_fold(burn, |total, part| total.checked_add(*part))
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("amounts overflow")))?;
+    bitcoin::runestone::ensure_split_relayable(&runeid, &parts, burn)
+        .map_err(WalletError::InvalidArgument)?;
+    let addresses = generate_addresses_from_principal(&ic_cdk::caller());
+    let address =
+        bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+
+    let rune_balance =
+        || read_utxo_manager(|manager| manager.get_runestone_balance(&addresses.bitcoin, &runeid));
+    if rune_balance() < total {
+        updater::fetch_utxos_and_update_balances(
+            &addresses.bitcoin,
+            TargetType::Runic {
+                runeid: runeid.clone(),
+                target: total,
+            },
+        )
+        .await;
+        if rune_balance() < total {
+            return Err(WalletError::InsufficientBalance);
+        }
+    }
+    updater::verify_runic_selection(&addresses.bitcoin, &runeid, total).await?;
+    if rune_balance() < total {
+        return Err(WalletError::InsufficientBalance);
+    }
+
+    let split = || {
+        bitcoin::runestone::split(RuneSplitArgs {
+            runeid: runeid.clone(),
+            parts: parts.clone(),
+            burn,
+            addr: &addresses.bitcoin,
+            account: addresses.icrc1,
+            address: address.clone(),
+            fee_per_vbytes,
+            postage,
+        })
+    };
+    let txn = match split() {
+        Ok(txn) => txn,
+        // the fee wasn't covered, the bitcoin balance may just be stale
+        Err(_) => {
+            updater::fetch_utxos_and_update_balances(
+                &addresses.bitcoin,
+                TargetType::Bitcoin { target: u64::MAX },
+            )
+            .await;
+            split().map_err(|_| WalletError::InsufficientBalance)?
+        }
+    };
+    txn.build_and_submit(None).await
+}
+
+// what a withdrawal of `kind` costs at the current rate, nothing gets held back
+#[update]
+pub async fn get_fee_estimate(
+    kind: TransactionKind,
+    size_estimate: Option<u64>,
+) -> Result<FeeEstimate, WalletError> {
+    api_stats::track("get_fee_estimate", async move {
+        fee_quotes::estimate(kind, size_estimate).await
+    })
+    .await
+}
+
+/*
+ * locks today's fee rate for a later withdrawal of `kind`, the quoted fee is held
+ * back from the caller's bitcoin until the quote is used, released or expires.
+ * `size_estimate` in vbytes defaults to a typical transaction of the kind
+ */
+#[update]
+pub async fn lock_fee_quote(
+    kind: TransactionKind,
+    size_estimate: Option<u64>,
+) -> Result<u64, WalletError> {
+    api_stats::track("lock_fee_quote", async move {
+        let caller = ic_cdk::caller();
+        let quote = fee_quotes::quote(caller, kind, size_estimate).await?;
+        ensure_unallocated(caller, quote.reserved).await?;
+        Ok(fee_quotes::lock(quote))
+    })
+    .await
+}
+
+#[update]
+pub fn release_fee_quote(id: u64) -> Result<(), WalletError> {
+    fee_quotes::release(id, ic_cdk::caller())
+}
+
+#[query]
+pub fn get_fee_quotes() -> Vec<FeeQuote> {
+    fee_quotes::quotes_of(&ic_cdk::caller())
+}
+
+// pays the fee at the quote's locked rate, the quote is used up once submitted
+#[update]
+pub async fn withdraw_runestone_with_quote(
+    quote_id: u64,
+    runeid: RuneId,
+    amount: u128,
+    to: String,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestone_with_quote", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_rune_supported(&runeid)?;
+        let caller = ic_cdk::caller();
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate =
+            exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), amount)
+                .await?;
+        let charge =
+            withdrawal_policy::charge(caller, TokenType::Runestone(runeid.clone()), amount)?;
+        let quote = fee_quotes::take(quote_id, caller, TransactionKind::Runestone)?;
+        let submitted = withdraw_runestone_from(
+            generate_addresses_from_principal(&caller),
+            runeid,
+            amount,
+            to,
+            Some(quote.fee_per_vbytes),
+            allow_any_script,
+        )
+        .await;
+        if submitted.is_err() {
+            fee_quotes::restore(quote);
+        }
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// sends several runes to `to` atomically, the sender pays the fee
+#[update]
+pub async fn withdraw_runestones(
+    runes: Vec<(RuneId, u128)>,
+    to: Destination,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestones", async move {
+        circuit_breaker::ensure_running()?;
+        let to = resolve_destination(&ic_cdk::caller(), to)?;
+        if runes.is_empty() || runes.iter().any(|(_, amount)| *amount == 0) {
+            return Err(WalletError::InvalidArgument(String::from(
+                "at least one rune with a non-zero amount is required",
+            )));
+        }
+        let unique: HashSet<&RuneId> = runes.iter().map(|(runeid, _)| runeid).collect();
+        if unique.len() != runes.len() {
+            return Err(WalletError::InvalidArgument(String::from(
+                "rune ids must be distinct",
+            )));
+        }
+        for (runeid, _) in runes.iter() {
+            ensure_rune_supported(runeid)?;
+        }
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        for (runeid, amount) in runes.iter() {
+            exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), *amount)
+                .await?;
+        }
+        let sender_addresses = generate_addresses_from_principal(&ic_cdk::caller());
+        let sender = bitcoin::address_validation(&sender_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+        let receiver = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+        ensure_rune_receiver(&receiver, allow_any_script)?;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let charges = runes
+            .iter()
+            .map(|(runeid, amount)| {
+                withdrawal_policy::charge(
+                    ic_cdk::caller(),
+                    TokenType::Runestone(runeid.clone()),
+                    *amount,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let args = || MultiRuneTransferArgs {
+            runes: runes.clone(),
+            sender_addr: &sender_addresses.bitcoin,
+            receiver_addr: &to,
+            sender_account: sender_addresses.icrc1,
+            receiver_account: sender_addresses.icrc1, // sender is the fee payer
+            sender_address: sender.clone(),
+            receiver_address: receiver.clone(),
+            fee_per_vbytes,
+            paid_by_sender: true,
+            postage: None,
+        };
+        let txn = match bitcoin::runestone::transfer_many(args()) {
+            Ok(txn) => txn,
+            Err(_) => {
+                updater::fetch_utxos_and_update_balances(
+                    &sender_addresses.bitcoin,
+                    TargetType::Bitcoin { target: u64::MAX },
+                )
+                .await;
+                bitcoin::runestone::transfer_many(args())
+                    .map_err(|_| WalletError::InsufficientBalance)?
+            }
+        };
+        let submitted = txn.build_and_submit(None).await;
+        for charge in charges {
+            charge.settle_if(&submitted);
+        }
+        submitted
+    })
+    .await
+}
+
+#[update]
+pub async fn get_rune_metadata(runeid: RuneId) -> Result<RuneMetadata, WalletError> {
+    api_stats::track("get_rune_metadata", rune_metadata::metadata(&runeid)).await
+}
+
+/*
+ * `withdraw_runestone` with `amount` in whole units, e.g. "12.5" of a rune with
+ * divisibility 2. amounts finer than the rune's divisibility are rejected
+*/
+#[update]
+pub async fn withdraw_runestone_decimal(
+    runeid: RuneId,
+    amount: String,
+    to: Destination,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let amount = api_stats::track(
+        "withdraw_runestone_decimal",
+        rune_metadata::parse_amount(&runeid, &amount),
+    )
+    .await?;
+    withdraw_runestone(runeid, amount, to, fee_per_vbytes, allow_any_script).await
+}
+
+#[update]
+pub async fn withdraw_runestones_decimal(
+    runes: Vec<(RuneId, String)>,
+    to: Destination,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let runes = api_stats::track("withdraw_runestones_decimal", async move {
+        let mut parsed = Vec::with_capacity(runes.len());
+        for (runeid, amount) in runes {
+            let amount = rune_metadata::parse_amount(&runeid, &amount).await?;
+            parsed.push((runeid, amount));
+        }
+        Ok(parsed)
+    })
+    .await?;
+    withdraw_runestones(runes, to, fee_per_vbytes, allow_any_script).await
+}
+
+// the sender pays the fee out of its own bitcoin, or in runes to the fee sponsor when short of it
+async fn withdraw_runestone_from(
+    sender_addresses: Addresses,
+    runeid: RuneId,
+    amount: u128,
+    to: String,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let txn = runestone_withdrawal(
+        Addresses {
+            bitcoin: sender_addresses.bitcoin.clone(),
+            icrc1: sender_addresses.icrc1,
+        },
+        runeid.clone(),
+        amount,
+        to.clone(),
+        fee_per_vbytes,
+        allow_any_script,
+    )
+    .await;
+    if fee_sponsor::applies(&txn) {
+        return fee_sponsor::withdraw_sponsored(
+            sender_addresses,
+            runeid,
+            amount,
+            to,
+            fee_per_vbytes,
+        )
+        .await;
+    }
+    txn?.build_and_submit(None).await
+}
+
+// selects the runic and fee utxos for a rune withdrawal paid by the sender
+async fn runestone_withdrawal(
+    sender_addresses: Addresses,
+    runeid: RuneId,
+    amount: u128,
+    to: String,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<TransactionType, WalletError> {
+    let sender = bitcoin::address_validation(&sender_addresses.bitcoin)
+        .map_err(WalletError::InvalidAddress)?;
+    let receiver = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    ensure_rune_receiver(&receiver, allow_any_script)?;
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+
+    let mut current_rune_balance = read_utxo_manager(|manager| {
+        manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+    });
+
+    if current_rune_balance < amount {
+        updater::fetch_utxos_and_update_balances(
+            &sender_addresses.bitcoin,
+            TargetType::Runic {
+                runeid: runeid.clone(),
+                target: amount,
+            },
+        )
+        .await;
+        current_rune_balance = read_utxo_manager(|manager| {
+            manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+        });
+
+        if current_rune_balance < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+    }
+    // the ord indexer has the last word on what the selected utxos hold
+    updater::verify_runic_selection(&sender_addresses.bitcoin, &runeid, amount).await?;
+    if read_utxo_manager(|manager| {
+        manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+    }) < amount
+    {
+        return Err(WalletError::InsufficientBalance);
+    }
+    let txn = match bitcoin::runestone::transfer(RuneTransferArgs {
+        runeid: runeid.clone(),
+        amount,
+        sender_addr: &sender_addresses.bitcoin,
+        receiver_addr: &to,
+        sender_account: sender_addresses.icrc1,
+        receiver_account: sender_addresses.icrc1, // sender is the fee payer
+        sender_address: sender.clone(),
+        receiver_address: receiver.clone(),
+        paid_by_sender: true,
+        fee_per_vbytes,
+        postage: None,
+    }) {
+        Ok(txn) => txn,
+        Err((_, fee)) => {
+            // ignoring the rune amount, as it is checked earlier
+            let mut current_btc_balance =
+                read_utxo_manager(|manager| manager.get_bitcoin_balance(&sender_addresses.bitcoin));
+            // the rune sync stops once the runes are covered, fee utxos may be left unseen
+            if fee > current_btc_balance {
+                updater::fetch_utxos_and_update_balances(
+                    &sender_addresses.bitcoin,
+                    TargetType::Bitcoin { target: u64::MAX },
+                )
+                .await;
+                current_btc_balance = read_utxo_manager(|manager| {
+                    manager.get_bitcoin_balance(&sender_addresses.bitcoin)
+                });
+                if current_btc_balance < fee {
+                    return Err(WalletError::InsufficientBalance);
+                }
+            }
+            if let Ok(txn) = bitcoin::runestone::transfer(RuneTransferArgs {
+                runeid,
+                amount,
+                sender_addr: &sender_addresses.bitcoin,
+                receiver_addr: &to,
+                sender_account: sender_addresses.icrc1,
+                receiver_account: sender_addresses.icrc1, // sender is the fee payer
+                sender_address: sender,
+                receiver_address: receiver,
+                paid_by_sender: true,
+                fee_per_vbytes,
+                postage: None,
+            }) {
+                txn
+            } else {
+                return Err(WalletError::InsufficientBalance);
+            }
+        }
+    };
+    Ok(txn)
+}
+
+#[update]
+pub async fn withdraw_runestone_with_fee_paid_by_receiver(
+    runeid: RuneId,
+    amount: u128,
+    to: Principal,
+    fee_per_vbytes: Option<u64>,
+    memo: Option<Vec<u8>>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestone_with_fee_paid_by_receiver", async move {
+        circuit_breaker::ensure_running()?;
+        validate_memo(&memo)?;
+        ensure_rune_supported(&runeid)?;
+        let caller = ic_cdk::caller();
+        let sender_addresses = generate_addresses_from_principal(&caller);
+        let receiver_addresses = generate_addresses_from_principal(&to);
+
+        withdrawal_policy::ensure_destination_allowed(&receiver_addresses.bitcoin)?;
+        let rate =
+            exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), amount)
+                .await?;
+        let sender = bitcoin::address_validation(&sender_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+        let receiver = bitcoin::address_validation(&receiver_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+
+        let (mut current_rune_balance, mut current_btc_balance) = read_utxo_manager(|manager| {
+            (
+                manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid),
+                manager.get_bitcoin_balance(&receiver_addresses.bitcoin),
+            )
+        });
+
+        if current_rune_balance < amount {
+            updater::fetch_utxos_and_update_balances(
+                &sender_addresses.bitcoin,
+                TargetType::Runic {
+                    runeid: runeid.clone(),
+                    target: amount,
+                },
+            )
+            .await;
+            current_rune_balance = read_utxo_manager(|manager| {
+                manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+            });
+
+            if current_rune_balance < amount {
+                return Err(WalletError::InsufficientBalance);
+            }
+        }
+
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let charge =
+            withdrawal_policy::charge(caller, TokenType::Runestone(runeid.clone()), amount)?;
+
+        let txn = match bitcoin::runestone::transfer(RuneTransferArgs {
+            runeid: runeid.clone(),
+            amount,
+            sender_addr: &sender_addresses.bitcoin,
+            receiver_addr: &receiver_addresses.bitcoin,
+            sender_address: sender.clone(),
+            receiver_address: receiver.clone(),
+            sender_account: sender_addresses.icrc1,
+            receiver_account: receiver_addresses.icrc1,
+            fee_per_vbytes,
+            paid_by_sender: true,
+            postage: None,
+        }) {
+            Ok(txn) => txn,
+            Err((_, fee)) => {
+                if fee > current_btc_balance {
+                    updater::fetch_utxos_and_update_balances(
+                        &receiver_addresses.bitcoin,
+                        TargetType::Bitcoin { target: u64::MAX },
+                    )
+                    .await;
+                    current_btc_balance = read_utxo_manager(|manager| {
+                        manager.get_bitcoin_balance(&receiver_addresses.bitcoin)
+                    });
+                    if current_btc_balance < fee {
+                        return Err(WalletError::InsufficientBalance);
+                    }
+                }
+
+                if let Ok(txn) = bitcoin::runestone::transfer(RuneTransferArgs {
+                    runeid,
+                    amount,
+                    sender_addr: &sender_addresses.bitcoin,
+                    receiver_addr: &receiver_addresses.bitcoin,
+                    sender_address: sender,
+                    receiver_address: receiver,
+                    sender_account: sender_addresses.icrc1,
+                    receiver_account: receiver_addresses.icrc1,
+                    fee_per_vbytes,
+                    paid_by_sender: true,
+                    postage: None,
+                }) {
+                    txn
+                } else {
+                    return Err(WalletError::InsufficientBalance);
+                }
+            }
+        };
+        let submitted = txn
+            .build_and_submit(Some(InternalTransfer { receiver: to, memo }))
+            .await;
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+/*
+ * sends runes to any address without touching the caller's plain bitcoin. the fee and
+ * the `postage` of the receiver's output, `MIN_POSTAGE` by default, come out of the
+ * bitcoin the caller's runic utxos carry, for holders whose bitcoin all sits in them
+ */
+#[update]
+pub async fn withdraw_runestone_funded_by_runes(
+    runeid: RuneId,
+    amount: u128,
+    to: Destination,
+    fee_per_vbytes: Option<u64>,
+    postage: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestone_funded_by_runes", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let to = resolve_destination(&caller, to)?;
+        ensure_rune_supported(&runeid)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        if postage.is_some_and(|postage| postage < bitcoin::runestone::MIN_POSTAGE) {
+            return Err(WalletError::InvalidArgument(format!(
+                "postage must be at least {}",
+                bitcoin::runestone::MIN_POSTAGE
+            )));
+        }
+        let rate =
+            exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), amount)
+                .await?;
+        let sender_addresses = generate_addresses_from_principal(&caller);
+        let sender = bitcoin::address_validation(&sender_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+        let receiver = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+        ensure_rune_receiver(&receiver, allow_any_script)?;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let charge =
+            withdrawal_policy::charge(caller, TokenType::Runestone(runeid.clone()), amount)?;
+
+        let rune_balance = || {
+            read_utxo_manager(|manager| {
+                manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+            })
+        };
+        if rune_balance() < amount {
+            updater::fetch_utxos_and_update_balances(
+                &sender_addresses.bitcoin,
+                TargetType::Runic {
+                    runeid: runeid.clone(),
+                    target: amount,
+                },
+            )
+            .await;
+            if rune_balance() < amount {
+                return Err(WalletError::InsufficientBalance);
+            }
+        }
+        updater::verify_runic_selection(&sender_addresses.bitcoin, &runeid, amount).await?;
+        if rune_balance() < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+
+        let transfer = || {
+            bitcoin::runestone::transfer_funded_by_runes(RuneTransferArgs {
+                runeid: runeid.clone(),
+                amount,
+                sender_addr: &sender_addresses.bitcoin,
+                receiver_addr: &to,
+                sender_account: sender_addresses.icrc1,
+                receiver_account: sender_addresses.icrc1, // sender is the fee payer
+                sender_address: sender.clone(),
+                receiver_address: receiver.clone(),
+                paid_by_sender: true,
+                fee_per_vbytes,
+                postage,
+            })
+        };
+        let txn = match transfer() {
+            Ok(txn) => txn,
+            // runic utxos the canister doesn't know of yet might cover the fee
+            Err(_) => {
+                updater::fetch_utxos_and_update_balances(
+                    &sender_addresses.bitcoin,
+                    TargetType::Bitcoin { target: u64::MAX },
+                )
+                .await;
+                transfer().map_err(|_| WalletError::InsufficientBalance)?
+            }
+        };
+        let submitted = txn.build_and_submit(None).await;
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+#[update]
+pub async fn withdraw_combined(
+    runeid: RuneId,
+    rune_amount: u128,
+    btc_amount: u64,
+    receiver_principal: Principal,
+    fee_per_vbytes: Option<u64>,
+    memo: Option<Vec<u8>>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_combined", async move {
+        circuit_breaker::ensure_running()?;
+        validate_memo(&memo)?;
+        ensure_rune_supported(&runeid)?;
+        let caller = ic_cdk::caller();
+        let addresses = generate_addresses_from_principal(&caller);
+        let receiver_addresses = generate_addresses_from_principal(&receiver_principal);
+        ensure_below_split_threshold(btc_amount)?;
+        approvals::ensure_below_approval_threshold(btc_amount)?;
+        withdrawal_policy::ensure_destination_allowed(&receiver_addresses.bitcoin)?;
+        exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), rune_amount)
+            .await?;
+        let rate =
+            exchange_rate::ensure_within_limit(&TokenType::Bitcoin, btc_amount as u128).await?;
+        let sender_address =
+            bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+        let receiver_address = bitcoin::address_validation(&receiver_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+
+        updater::fetch_utxos_and_update_balances(
+            &addresses.bitcoin,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+
+        updater::fetch_utxos_and_update_balances(
+            &receiver_addresses.bitcoin,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let rune_charge =
+            withdrawal_policy::charge(caller, TokenType::Runestone(runeid.clone()), rune_amount)?;
+        let btc_charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, btc_amount as u128)?;
+        // the receiver covers the fee out of its own bitcoin
+        let txn = TxPlanner::new(
+            Branch {
+                addr: &addresses.bitcoin,
+                account: addresses.icrc1,
+                address: sender_address,
+            },
+            fee_per_vbytes,
+        )
+        .with(Intent::SendRune {
+            runeid,
+            amount: rune_amount,
+            to: Branch {
+                addr: &receiver_addresses.bitcoin,
+                account: receiver_addresses.icrc1,
+                address: receiver_address.clone(),
+            },
+        })
+        .with(Intent::SendBtc {
+            to: receiver_address,
+            amount: btc_amount,
+        })
+        .with(Intent::PayFeeFrom(FeePayer::Receiver))
+        .plan()?;
+        let submitted = txn
+            .build_and_submit(Some(InternalTransfer {
+                receiver: receiver_principal,
+                memo,
+            }))
+            .await;
+        rune_charge.settle_if(&submitted);
+        btc_charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// registers the address for the background deposit scan, hence an update
+#[update]
+pub fn get_deposit_addresses() -> Addresses {
+    let caller = ic_cdk::caller();
+    deposit_scanner::register(caller);
+    generate_addresses_from_principal(&caller)
+}
+
+// the message `register_external_address` expects the address's key to have signed
+#[query]
+pub fn get_ownership_challenge(address: String) -> String {
+    external_addresses::challenge(&ic_cdk::caller(), &address)
+}
+
+/*
+ * binds an address the caller controls outside the canister, proven by a legacy
+ * signed message over `get_ownership_challenge`. the address gets synced with the
+ * deposit scan and its balances are served to the caller once they're gated
+ */
+#[update]
+pub fn register_external_address(address: String, signature: String) -> Result<(), WalletError> {
+    external_addresses::register(ic_cdk::caller(), address, &signature)
+}
+
+#[update]
+pub fn unregister_external_address(address: String) -> Result<(), WalletError> {
+    external_addresses::unregister(&ic_cdk::caller(), &address)
+}
+
+#[query]
+pub fn get_external_addresses() -> Vec<String> {
+    external_addresses::addresses_of(&ic_cdk::caller())
+}
+
+// restricts the `*_of` and cached balance endpoints to the address's owner and controllers
+#[update(guard = "is_controller")]
+pub fn set_owner_gated_balances(enabled: bool) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.owner_gated_balances = Some(enabled);
+        let _ = config.set(temp);
+    });
+}
+
+#[query]
+pub fn get_change_addresses() -> Addresses {
+    let caller = ic_cdk::caller();
+    generate_change_addresses_from_principal(&caller)
+}
+
+#[query]
+pub fn get_taproot_deposit_addresses() -> Addresses {
+    generate_taproot_addresses_from_principal(&ic_cdk::caller())
+}
+
+// runes held on the caller's taproot address, the fee is paid from its bitcoin
+#[update]
+pub async fn withdraw_runestone_from_taproot(
+    runeid: RuneId,
+    amount: u128,
+    to: String,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestone_from_taproot", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_rune_supported(&runeid)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate =
+            exchange_rate::ensure_within_limit(&TokenType::Runestone(runeid.clone()), amount)
+                .await?;
+        let charge = withdrawal_policy::charge(
+            ic_cdk::caller(),
+            TokenType::Runestone(runeid.clone()),
+            amount,
+        )?;
+        let sender_addresses = generate_taproot_addresses_from_principal(&ic_cdk::caller());
+        let submitted = withdraw_runestone_from(
+            sender_addresses,
+            runeid,
+            amount,
+            to,
+            fee_per_vbytes,
+            allow_any_script,
+        )
+        .await;
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// numbered wallet of the caller, derived from its principal so only it can spend from it
+#[query]
+pub fn get_subaccount_addresses(num: u128) -> Addresses {
+    generate_numbered_addresses_from_principal(&ic_cdk::caller(), num)
+}
+
+#[update]
+pub async fn withdraw_bitcoin_from_subaccount(
+    num: u128,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_bitcoin_from_subaccount", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let rate = exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        let charge =
+            withdrawal_policy::charge(ic_cdk::caller(), TokenType::Bitcoin, amount as u128)?;
+        let sender = generate_numbered_addresses_from_principal(&ic_cdk::caller(), num);
+        let submitted =
+            withdraw_bitcoin_from_address(sender, &to, amount, fee_per_vbytes, None).await;
+        charge.settle_if(&submitted);
+        exchange_rate::record_rate(&submitted, rate);
+        submitted
+    })
+    .await
+}
+
+// derived from the canister alone, not tied to the caller and not spendable
+// through the api. `get_subaccount_addresses` gives the caller's own wallets
+#[query]
+pub fn generate_address(num: u128) -> String {
+    let subaccount = subaccount_with_num(num);
+    let account = Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(subaccount),
+    };
+    account_to_p2pkh_address(&account)
+}
+
+// metered, see `set_rate_limits`
+#[update]
+pub async fn get_bitcoin_balance_of(of: String) -> Result<u64, WalletError> {
+    external_addresses::ensure_balance_access(&of)?;
+    let metered = rate_limiter::metered(ic_cdk::caller(), async move {
+        let network = read_config(|config| config.bitcoin_network());
+        let balance = bitcoin_get_balance(GetBalanceRequest {
+            address: of.to_string(),
+            network,
+            min_confirmations: None,
+        })
+        .await
+        .unwrap()
+        .0;
+        Ok(balance)
+    });
+    api_stats::track("get_bitcoin_balance_of", metered).await
+}
+
+// metered, see `set_rate_limits`
+#[update]
+pub async fn get_runestone_balance_of(of: String) -> Result<HashMap<RuneId, u128>, WalletError> {
+    external_addresses::ensure_balance_access(&of)?;
+    let metered = rate_limiter::metered(ic_cdk::caller(), async move {
+        updater::fetch_utxos_and_update_balances(&of, TargetType::Bitcoin { target: u64::MAX })
+            .await;
+        Ok(read_utxo_manager(|manager| {
+            manager.all_rune_with_balances(&of)
+        }))
+    });
+    api_stats::track("get_runestone_balance_of", metered).await
+}
+
+fn cached_balances(address: &str) -> CachedBalances {
+    let synced_at_height = read_sync_cursors(|cursors| cursors.get(&address.to_string()))
+        .map(|cursor| cursor.tip_height);
+    read_utxo_manager(|manager| CachedBalances {
+        bitcoin: manager.get_bitcoin_balance(address),
+        runes: manager
+            .all_rune_with_balances(address)
+            .into_iter()
+            .collect(),
+        synced_at_height,
+    })
+}
+
+// balances as last recorded, utxos that arrived since the last sync aren't included
+#[query]
+pub fn get_cached_balances(address: String) -> Result<CachedBalances, WalletError> {
+    external_addresses::ensure_balance_access(&address)?;
+    Ok(cached_balances(&address))
+}
+
+#[update]
+pub async fn refresh_balances(address: String) -> Result<CachedBalances, WalletError> {
+    api_stats::track("refresh_balances", async move {
+        bitcoin::address_validation(&address).map_err(WalletError::InvalidAddress)?;
+        external_addresses::ensure_balance_access(&address)?;
+        rate_limiter::metered(ic_cdk::caller(), async move {
+            updater::fetch_utxos_and_update_balances(
+                &address,
+                TargetType::Bitcoin { target: u64::MAX },
+            )
+            .await;
+            Ok(cached_balances(&address))
+        })
+        .await
+    })
+    .await
+}
+
+// unsupported runes stay tracked, but can't be withdrawn
+#[update]
+pub async fn get_runestone_balance_details_of(
+    of: String,
+) -> Result<Vec<RuneBalanceDetail>, WalletError> {
+    external_addresses::ensure_balance_access(&of)?;
+    let metered = rate_limiter::metered(ic_cdk::caller(), async move {
+        updater::fetch_utxos_and_update_balances(&of, TargetType::Bitcoin { target: u64::MAX })
+            .await;
+        let policy = read_config(|config| config.rune_policy());
+        Ok(
+            read_utxo_manager(|manager| manager.all_rune_with_balances(&of))
+                .into_iter()
+                .map(|(runeid, balance)| RuneBalanceDetail {
+                    spendable: policy.is_supported(&runeid),
+                    runeid,
+                    balance,
+                })
+                .collect(),
+        )
+    });
+    api_stats::track("get_runestone_balance_details_of", metered).await
+}
+
+// what a `withdraw_runestone` of the caller to `receiver` would need, without spending anything
+#[update]
+pub async fn get_rune_transfer_requirements(
+    runeid: RuneId,
+    amount: u128,
+    receiver: String,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<RuneTransferRequirements, WalletError> {
+    api_stats::track("get_rune_transfer_requirements", async move {
+        let sender_addresses = generate_addresses_from_principal(&ic_cdk::caller());
+        let sender = bitcoin::address_validation(&sender_addresses.bitcoin)
+            .map_err(WalletError::InvalidAddress)?;
+        let receiver =
+            bitcoin::address_validation(&receiver).map_err(WalletError::InvalidAddress)?;
+        ensure_rune_receiver(&receiver, allow_any_script)?;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        updater::fetch_utxos_and_update_balances(
+            &sender_addresses.bitcoin,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+        bitcoin::runestone::requirements(
+            &runeid,
+            amount,
+            &sender_addresses.bitcoin,
+            &sender,
+            &receiver,
+            fee_per_vbytes,
+        )
+        .map_err(|_| WalletError::InsufficientBalance)
+    })
+    .await
+}
+
+/*
+ * icrc-1 ledger per rune in `rune_ledger_tokens`, the methods take the rune next
+ * to the standard arguments. balances move inside the canister and only touch
+ * bitcoin on `deposit_to_rune_ledger` and `withdraw_from_rune_ledger`
+*/
+#[query]
+pub fn icrc1_balance_of(runeid: RuneId, account: Account) -> Nat {
+    Nat::from(rune_ledger::balance_of(&runeid, &account))
+}
+
+#[update]
+pub fn icrc1_transfer(runeid: RuneId, arg: TransferArg) -> Result<Nat, TransferError> {
+    if circuit_breaker::ensure_running().is_err() {
+        return Err(TransferError::TemporarilyUnavailable);
+    }
+    rune_ledger::transfer(ic_cdk::caller(), &runeid, arg)
+}
+
+#[query]
+pub fn icrc1_metadata(runeid: RuneId) -> Result<Vec<(String, MetadataValue)>, WalletError> {
+    rune_ledger::metadata(&runeid)
+}
+
+#[update]
+pub async fn deposit_to_rune_ledger(
+    runeid: RuneId,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("deposit_to_rune_ledger", async move {
+        circuit_breaker::ensure_running()?;
+        ensure_rune_supported(&runeid)?;
+        rune_ledger::deposit(ic_cdk::caller(), runeid, amount, fee_per_vbytes).await
+    })
+    .await
+}
+
+#[update]
+pub async fn withdraw_from_rune_ledger(
+    runeid: RuneId,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_from_rune_ledger", async move {
+        circuit_breaker::ensure_running()?;
+        rune_ledger::withdraw(ic_cdk::caller(), runeid, amount, fee_per_vbytes).await
+    })
+    .await
+}
+
+#[update(guard = "is_controller")]
+pub fn set_rune_ledger_tokens(tokens: Vec<LedgerToken>) -> Result<(), WalletError> {
+    let mut runeids = HashSet::new();
+    if !tokens
+        .iter()
+        .all(|token| runeids.insert(token.runeid.clone()))
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "rune listed more than once",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.rune_ledger_tokens = Some(tokens);
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_rune_ledger_tokens() -> Vec<LedgerToken> {
+    read_config(|config| config.rune_ledger_tokens.clone()).unwrap_or_default()
+}
+
+#[query]
+pub fn list_supported_runes() -> RunePolicy {
+    read_config(|config| config.rune_policy())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_rune_allow_list(allow_list: Option<Vec<RuneId>>) {
+    update_rune_policy(|policy| policy.allow_list = allow_list);
+}
+
+#[update(guard = "is_controller")]
+pub fn set_rune_deny_list(deny_list: Vec<RuneId>) {
+    update_rune_policy(|policy| policy.deny_list = deny_list);
+}
+
+fn update_rune_policy<F: FnOnce(&mut RunePolicy)>(f: F) {
+    let policy = write_config(|config| {
+        let mut temp = config.get().clone();
+        let mut policy = temp.rune_policy();
+        f(&mut policy);
+        temp.rune_policy.replace(policy.clone());
+        let _ = config.set(temp);
+        policy
+    });
+    record_event(EventKind::RunePolicyUpdated { policy });
+}
+
+#[query]
+pub fn get_destination_policy() -> DestinationPolicy {
+    read_config(|config| config.destination_policy())
+}
+
+// withdrawals may only pay out to the listed addresses, `None` lifts the restriction
+#[update(guard = "is_controller")]
+pub fn set_destination_allow_list(allow_list: Option<Vec<String>>) -> Result<(), WalletError> {
+    let allow_list = allow_list.map(canonical_addresses).transpose()?;
+    update_destination_policy(|policy| policy.allow_list = allow_list);
+    Ok(())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_destination_deny_list(deny_list: Vec<String>) -> Result<(), WalletError> {
+    let deny_list = canonical_addresses(deny_list)?;
+    update_destination_policy(|policy| policy.deny_list = deny_list);
+    Ok(())
+}
+
+fn canonical_addresses(addresses: Vec<String>) -> Result<Vec<String>, WalletError> {
+    addresses
+        .iter()
+        .map(|address| withdrawal_policy::canonical_address(address))
+        .collect()
+}
+
+fn update_destination_policy<F: FnOnce(&mut DestinationPolicy)>(f: F) {
+    let policy = write_config(|config| {
+        let mut temp = config.get().clone();
+        let mut policy = temp.destination_policy();
+        f(&mut policy);
+        temp.destination_policy.replace(policy.clone());
+        let _ = config.set(temp);
+        policy
+    });
+    record_event(EventKind::DestinationPolicyUpdated { policy });
+}
+
+// limits of every principal without limits of its own
+#[update(guard = "is_controller")]
+pub fn set_default_daily_limits(limits: Option<DailyLimits>) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.daily_limits = limits;
+        let _ = config.set(temp);
+    });
+}
+
+// `None` puts the principal back on the default limits
+#[update(guard = "is_controller")]
+pub fn set_daily_limits(principal: Principal, limits: Option<DailyLimits>) {
+    withdrawal_policy::set_limits(principal, limits);
+}
+
+#[query]
+pub fn get_daily_limits(principal: Principal) -> Result<DailyLimits, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(withdrawal_policy::limits_of(&principal))
+}
+
+// what the principal withdrew since the start of the current utc day
+#[query]
+pub fn get_daily_usage(principal: Principal) -> Result<DailyUsage, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(withdrawal_policy::usage_of(&principal))
+}
+
+#[update(guard = "is_controller")]
+pub fn set_paper_trading(enabled: bool) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.paper_trading.replace(enabled);
+        let _ = config.set(temp);
+    });
+}
+
+#[query]
+pub fn is_paper_trading() -> bool {
+    read_config(|config| config.is_paper_trading())
+}
+
+/*
+ * freezes every endpoint that moves funds, withdrawals already broadcast are
+ * unaffected. the circuit breaker pauses the same way on its own
+*/
+#[update(guard = "is_controller")]
+pub fn pause(reason: Option<String>) {
+    circuit_breaker::pause(
+        reason.unwrap_or_else(|| String::from("paused by a controller")),
+        false,
+    );
+}
+
+#[update(guard = "is_controller")]
+pub fn unpause() {
+    circuit_breaker::unpause();
+}
+
+#[query]
+pub fn get_pause() -> Option<Pause> {
+    read_config(|config| config.pause.clone())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_circuit_breaker(breaker: Option<CircuitBreaker>) -> Result<(), WalletError> {
+    if breaker
+        .as_ref()
+        .is_some_and(|breaker| breaker.window_secs == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "window must be non-zero",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.circuit_breaker = breaker;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query(guard = "is_controller")]
+pub fn get_circuit_breaker() -> Option<CircuitBreaker> {
+    read_config(|config| config.circuit_breaker.clone())
+}
+
+// `None` stops the posts, queued ones stay until a url is set again
+#[update(guard = "is_controller")]
+pub fn set_webhook(url: Option<String>) -> Result<(), WalletError> {
+    if let Some(ref url) = url {
+        webhook::validate_url(url)?;
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.webhook_url = url;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query(guard = "is_controller")]
+pub fn get_webhook() -> Option<String> {
+    read_config(|config| config.webhook_url.clone())
+}
+
+#[query(guard = "is_controller")]
+pub fn get_webhook_deliveries() -> Vec<WebhookDelivery> {
+    read_webhook_queue(|queue| queue.iter().map(|(_, delivery)| delivery).collect())
+}
+
+// `None` requeues every dead-lettered post, returns how many were requeued
+#[update(guard = "is_controller")]
+pub fn retry_dead_lettered_webhooks(txids: Option<Vec<String>>) -> u64 {
+    webhook::retry_dead_lettered(txids)
+}
+
+// signed transactions whose broadcast failed, along with their retry state
+#[query(guard = "is_controller")]
+pub fn get_outbox() -> Vec<PendingSubmission> {
+    read_submission_queue(|queue| queue.iter().map(|(_, submission)| submission).collect())
+}
+
+// broadcasts every queued transaction again right away, returns how many were queued
+#[update(guard = "is_controller")]
+pub fn retry_pending_submissions() -> u64 {
+    submission_queue::retry_now()
+}
+
+// gives up on a dead-lettered transaction, the utxos it spends become spendable again
+#[update(guard = "is_controller")]
+pub fn abandon_submission(txid: String) -> Result<(), WalletError> {
+    submission_queue::abandon(&txid)
+}
+
+#[query(hidden = true)]
+pub fn webhook_transform(args: TransformArgs) -> HttpResponse {
+    webhook::transform(args)
+}
+
+#[query]
+pub fn get_public_config() -> PublicConfig {
+    read_config(|config| PublicConfig {
+        network: config.bitcoin_network(),
+        chain: config.chain(),
+        keyname: config.keyname(),
+        derivation_scheme: config.derivation_scheme(),
+        paper_trading: config.is_paper_trading(),
+        taproot_addresses: config.schnorr_public_key.is_some(),
+        min_confirmations: updater::MIN_CONFIRMATIONS,
+        dust_threshold: bitcoin::DUST_THRESHOLD,
+        default_postage: bitcoin::runestone::DEFAULT_POSTAGE,
+        anchor_output_value: config.anchor_output_value,
+        paused: config.pause.is_some(),
+    })
+}
+
+// the caller's submitted transactions whose change the chain hasn't reported yet
+#[query]
+pub fn get_pending_transactions() -> Vec<PendingTransaction> {
+    pending_change::pending_for(ic_cdk::caller())
+}
+
+#[query]
+pub fn get_interface_version() -> u32 {
+    INTERFACE_VERSION
+}
+
+#[query]
+pub fn get_supported_features() -> Vec<Feature> {
+    vec![
+        Feature::RunestoneTransfer,
+        Feature::CombinedTransfer,
+        Feature::MultiSenderTransfer,
+        Feature::ReceiverPaysFee,
+        Feature::AnchorFeeBump,
+        Feature::MultisigWithdrawal,
+        Feature::BatchedWithdrawal,
+        Feature::TransferMemo,
+        Feature::Statement,
+        Feature::ChangeAddress,
+        Feature::ColdStorageSweep,
+        Feature::RunePolicy,
+        Feature::RawTransaction,
+        Feature::FeeHistory,
+        Feature::BalanceReconciliation,
+        Feature::Treasury,
+        Feature::TwoPhaseWithdrawal,
+        Feature::PaperTrading,
+        Feature::OrdBackendFailover,
+        Feature::SubaccountWallets,
+        Feature::MultiRuneTransfer,
+        Feature::Notifications,
+        Feature::TaprootAddresses,
+        Feature::Jars,
+        Feature::CkbtcBridge,
+        Feature::WithdrawalPolicy,
+        Feature::BalanceSubscriptions,
+        Feature::PsbtExport,
+        Feature::CoinSelection,
+        Feature::RateLimits,
+        Feature::OpReturnMemo,
+        Feature::SweepAll,
+        Feature::WithdrawalApprovals,
+        Feature::DepositScanning,
+        Feature::BatchPayouts,
+        Feature::Metrics,
+        Feature::RuneLedger,
+        Feature::Bip44Derivation,
+        Feature::ChangeSplitting,
+        Feature::Signet,
+        Feature::EmergencyPause,
+        Feature::Webhook,
+        Feature::PublicConfig,
+        Feature::RuneSplit,
+        Feature::RuneBurn,
+        Feature::PendingChange,
+        Feature::AddressBook,
+        Feature::RuneFundedWithdrawal,
+        Feature::BalanceHistory,
+        Feature::RunestoneDecoding,
+        Feature::TreasuryMode,
+        Feature::MessageSigning,
+        Feature::Delegation,
+        Feature::ExternalAddresses,
+        Feature::CycleMonitoring,
+        Feature::FeeSuggestions,
+        Feature::StatementExport,
+        Feature::RuneConsolidation,
+        Feature::FeeSponsorship,
+    ]
+}
+
+#[query]
+pub fn get_transaction_history(offset: u64, limit: u64) -> Vec<TransactionRecord> {
+    let caller = ic_cdk::caller();
+    read_transaction_log(|log| log.records_of(&caller, offset, limit))
+}
+
+#[update(guard = "is_controller")]
+pub fn admin_remove_utxo(address: String, outpoint: Outpoint) -> Result<(), WalletError> {
+    let removed = write_utxo_manager(|manager| manager.remove_utxo(&address, &outpoint));
+    if !removed {
+        let err = WalletError::UtxoNotFound;
+        api_stats::record("admin_remove_utxo", Some(&err));
+        return Err(err);
+    }
+    record_event(EventKind::AdminUtxoRemoved { address, outpoint });
+    api_stats::record("admin_remove_utxo", None);
+    Ok(())
+}
+
+#[update(guard = "is_controller")]
+pub fn admin_insert_utxo(
+    address: String,
+    utxo: Utxo,
+    rune_info: Option<(RuneId, u128)>,
+) -> Result<(), WalletError> {
+    bitcoin::address_validation(&address).map_err(WalletError::InvalidAddress)?;
+    write_utxo_manager(|manager| {
+        // the utxo might be recorded under another classification already
+        manager.remove_utxo(&address, &utxo.outpoint);
+        match rune_info {
+            None => manager.record_btc_utxos(&address, vec![utxo.clone()]),
+            Some((ref runeid, balance)) => manager.record_runic_utxos(
+                &address,
+                runeid.clone(),
+                vec![RunicUtxo {
+                    utxo: utxo.clone(),
+                    balance,
+                }],
+            ),
+        }
+    });
+    record_event(EventKind::AdminUtxoInserted {
+        address,
+        utxo,
+        rune: rune_info,
+    });
+    api_stats::record("admin_insert_utxo", None);
+    Ok(())
+}
+
+#[update(guard = "is_controller")]
+pub async fn admin_resync_address(address: String) -> Result<u64, WalletError> {
+    api_stats::track("admin_resync_address", async move {
+        bitcoin::address_validation(&address).map_err(WalletError::InvalidAddress)?;
+        let btc_balance = updater::resync_address(&address).await;
+        record_event(EventKind::AdminAddressResynced {
+            address,
+            btc_balance,
+        });
+        Ok(btc_balance)
+    })
+    .await
+}
+
+// registers the indexed addresses of a migrated principal, stops after `updater::GAP_LIMIT` unused ones
+#[update(guard = "is_controller")]
+pub async fn scan_principal_addresses(principal: Principal, max_index: u32) -> ScanReport {
+    let report = updater::scan_indexed_addresses(principal, max_index).await;
+    record_event_for(
+        principal,
+        EventKind::PrincipalAddressesScanned {
+            principal,
+            scanned: report.scanned,
+            found: report.found.len() as u32,
+        },
+    );
+    api_stats::record("scan_principal_addresses", None);
+    report
+}
+
+#[query]
+pub fn get_imported_addresses() -> Vec<ImportedAddress> {
+    let caller = ic_cdk::caller();
+    read_imported_addresses(|imported| imported.get(&caller).unwrap_or_default().0)
+}
+
+// subscribers are called with `wallet_notification : (nat64, Notification) -> ()`
+#[update(guard = "is_controller")]
+pub fn set_notification_subscribers(subscribers: Vec<Principal>) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.notification_subscribers = Some(subscribers);
+        let _ = config.set(temp);
+    });
+}
+
+#[query(guard = "is_controller")]
+pub fn get_notification_subscribers() -> Vec<Principal> {
+    read_config(|config| config.notification_subscribers.clone()).unwrap_or_default()
+}
+
+/*
+ * deposits to the principal's addresses get pushed one-way to `callback_canister` as
+ * `wallet_balance_changed : (BalanceChange) -> ()`. the principal itself or a
+ * controller may subscribe
+ */
+#[update]
+pub fn subscribe_balance_changes(
+    callback_canister: Principal,
+    principal: Principal,
+) -> Result<(), WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    subscriptions::subscribe(principal, callback_canister)
+}
+
+#[update]
+pub fn unsubscribe_balance_changes(
+    callback_canister: Principal,
+    principal: Principal,
+) -> Result<(), WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    subscriptions::unsubscribe(principal, callback_canister);
+    Ok(())
+}
+
+#[query]
+pub fn get_balance_subscriptions(principal: Principal) -> Result<Vec<Principal>, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(subscriptions::callbacks_of(&principal))
+}
+
+#[query(guard = "is_controller")]
+pub fn get_dead_lettered_notifications() -> Vec<OutboxEntry> {
+    read_outbox(|outbox| {
+        outbox
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.status.is_dead_lettered())
+            .collect()
+    })
+}
+
+// `None` requeues every dead-lettered notification, returns how many were requeued
+#[update(guard = "is_controller")]
+pub fn retry_dead_lettered_notifications(ids: Option<Vec<u64>>) -> u64 {
+    outbox::retry_dead_lettered(ids)
+}
+
+// the utxos recorded for the address, to check how they got classified
+#[query]
+pub fn list_utxos(address: String) -> Vec<UtxoInfo> {
+    read_utxo_manager(|manager| manager.utxo_infos(&address))
+}
+
+/*
+ * paged views of the recorded utxos, for addresses holding too many to fit a single
+ * response. `start_after` is the last outpoint or rune of the previous page, at most
+ * `MAX_PAGE_SIZE` entries come back per call
+ */
+#[query]
+pub fn list_bitcoin_utxos(address: String, start_after: Option<Outpoint>, limit: u64) -> Vec<Utxo> {
+    read_utxo_manager(|manager| manager.bitcoin_utxos_page(&address, start_after, limit))
+}
+
+#[query]
+pub fn list_runic_utxos(
+    address: String,
+    runeid: RuneId,
+    start_after: Option<Outpoint>,
+    limit: u64,
+) -> Vec<RunicUtxo> {
+    read_utxo_manager(|manager| manager.runic_utxos_page(&address, &runeid, start_after, limit))
+}
+
+// balances as last recorded, `get_runestone_balance_of` syncs the address first
+#[query]
+pub fn list_rune_balances(
+    address: String,
+    start_after: Option<RuneId>,
+    limit: u64,
+) -> Vec<(RuneId, u128)> {
+    read_utxo_manager(|manager| manager.rune_balances_page(&address, start_after, limit))
+}
+
+/*
+ * lists outpoints counted twice towards a balance and recorded outpoints that are also
+ * inputs of a pending or prepared transaction. `repair` keeps a single record for each
+ * of the former and drops the latter, the sync records them again if the spend is dropped
+ */
+#[update(guard = "is_controller")]
+pub fn check_utxo_invariants(repair: bool) -> UtxoInvariantReport {
+    write_utxo_manager(|manager| {
+        let addresses = manager.addresses();
+        let mut violations = vec![];
+        let mut held = vec![];
+        for address in addresses.iter() {
+            let spent =
+                manager.recorded_among(address, &transaction_handler::held_outpoints(address));
+            if repair {
+                for outpoint in spent.iter() {
+                    manager.remove_utxo(address, outpoint);
+                }
+            }
+            held.extend(
+                spent
+                    .into_iter()
+                    .map(|outpoint| (address.clone(), outpoint)),
+            );
+            let duplicates = manager.duplicate_outpoints(address);
+            if duplicates.is_empty() {
+                continue;
+            }
+            if repair {
+                manager.dedupe_address(address);
+            }
+            violations.extend(
+                duplicates
+                    .into_iter()
+                    .map(|outpoint| (address.clone(), outpoint)),
+            );
+        }
+        UtxoInvariantReport {
+            addresses_checked: addresses.len() as u64,
+            repaired: repair && !(violations.is_empty() && held.is_empty()),
+            violations,
+            held,
+            duplicates_prevented: manager.duplicates_prevented,
+        }
+    })
+}
+
+// indexers queried in weighted rotation to classify utxos, `None` restores the default one
+#[update(guard = "is_controller")]
+pub fn set_ord_backends(backends: Option<Vec<OrdBackend>>) -> Result<(), WalletError> {
+    if let Some(ref backends) = backends {
+        if backends.is_empty() || backends.iter().any(|backend| backend.weight == 0) {
+            return Err(WalletError::InvalidArgument(String::from(
+                "at least one backend with a non-zero weight is required",
+            )));
+        }
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.ord_backends = backends;
+        let _ = config.set(temp);
+    });
+    ord_canister::reset_health();
+    Ok(())
+}
+
+#[query(guard = "is_controller")]
+pub fn get_ord_backends() -> Vec<OrdBackend> {
+    read_config(|config| config.ord_backends())
+}
+
+#[query]
+pub fn get_health() -> Health {
+    let ord_backends = ord_canister::health();
+    Health {
+        healthy: ord_backends.iter().any(|backend| backend.healthy),
+        ord_backends,
+    }
+}
+
+#[update(guard = "is_controller")]
+pub fn set_reconciliation_policy(policy: Option<ReconciliationPolicy>) -> Result<(), WalletError> {
+    if policy
+        .as_ref()
+        .is_some_and(|policy| policy.interval_mins == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "reconciliation interval must be at least a minute",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.reconciliation_policy = policy;
+        let _ = config.set(temp);
+    });
+    reconciler::start_reconciliation();
+    Ok(())
+}
+
+#[query(guard = "is_controller")]
+pub fn get_reconciliation_policy() -> Option<ReconciliationPolicy> {
+    read_config(|config| config.reconciliation_policy.clone())
+}
+
+// runs a reconciliation pass right away
+#[update(guard = "is_controller")]
+pub async fn reconcile_balances() {
+    reconciler::reconcile().await
+}
+
+#[update(guard = "is_controller")]
+pub fn set_deposit_scan_policy(policy: Option<DepositScanPolicy>) -> Result<(), WalletError> {
+    if policy
+        .as_ref()
+        .is_some_and(|policy| policy.interval_mins == 0)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "deposit scan interval must be at least a minute",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.deposit_scan_policy = policy;
+        let _ = config.set(temp);
+    });
+    deposit_scanner::start_scanning();
+    Ok(())
+}
+
+#[query(guard = "is_controller")]
+pub fn get_deposit_scan_policy() -> Option<DepositScanPolicy> {
+    read_config(|config| config.deposit_scan_policy.clone())
+}
+
+// runs a deposit scan over every registered address right away
+#[update(guard = "is_controller")]
+pub async fn scan_deposits() {
+    deposit_scanner::scan().await
+}
+
+/*
+ * treasury mode sweeps the registered deposit addresses into the deposit pool and pays
+ * bitcoin withdrawals out of it, turning it off stops the sweeps while the credits
+ * stay withdrawable
+*/
+#[update(guard = "is_controller")]
+pub fn set_treasury_mode(mode: Option<TreasuryMode>) -> Result<(), WalletError> {
+    if mode.as_ref().is_some_and(|mode| mode.interval_mins == 0) {
+        return Err(WalletError::InvalidArgument(String::from(
+            "pooling interval must be at least a minute",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.treasury_mode = mode;
+        let _ = config.set(temp);
+    });
+    treasury::start_pooling();
+    Ok(())
+}
+
+#[query]
+pub fn get_treasury_mode() -> Option<TreasuryMode> {
+    read_config(|config| config.treasury_mode.clone())
+}
+
+// sweeps the registered deposit addresses into the pool right away
+#[update(guard = "is_controller")]
+pub async fn pool_deposits() {
+    treasury::pool_round().await
+}
+
+// sats of the deposit pool the caller can withdraw
+#[query]
+pub fn get_treasury_credit() -> u64 {
+    treasury::credit_of(&ic_cdk::caller())
+}
+
+#[query]
+pub fn get_derivation_scheme() -> DerivationScheme {
+    read_config(|config| config.derivation_scheme())
+}
+
+/*
+ * sweeps the principal's legacy addresses to their bip44 counterparts. once every
+ * principal is migrated `set_derivation_scheme` switches the canister over, funds
+ * arriving on legacy addresses afterwards need the scheme switched back to move
+*/
+#[update(guard = "is_controller")]
+pub async fn migrate_to_bip44(
+    principal: Principal,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SweepAll, WalletError> {
+    api_stats::track("migrate_to_bip44", async move {
+        circuit_breaker::ensure_running()?;
+        migration::migrate_to_bip44(principal, fee_per_vbytes).await
+    })
+    .await
+}
+
+#[update(guard = "is_controller")]
+pub fn set_derivation_scheme(scheme: DerivationScheme) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.derivation_scheme = Some(scheme);
+        let _ = config.set(temp);
+    });
+}
+
+#[update(guard = "is_controller")]
+pub fn set_anchor_output_value(value: Option<u64>) -> Result<(), WalletError> {
+    if let Some(value) = value {
+        if value < MIN_ANCHOR_VALUE {
+            return Err(WalletError::InvalidArgument(format!(
+                "anchor output must be at least {} sats",
+                MIN_ANCHOR_VALUE
+            )));
+        }
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.anchor_output_value = value;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_fee_pool_address() -> String {
+    fee_pool_addresses().bitcoin
+}
+
+#[query]
+pub fn get_treasury_addresses() -> Addresses {
+    treasury_addresses()
+}
+
+#[update(guard = "is_controller")]
+pub async fn get_treasury_balance() -> TreasuryBalance {
+    let treasury = treasury_addresses();
+    updater::fetch_utxos_and_update_balances(
+        &treasury.bitcoin,
+        TargetType::Bitcoin { target: u64::MAX },
+    )
+    .await;
+    api_stats::record("get_treasury_balance", None);
+    read_utxo_manager(|manager| TreasuryBalance {
+        bitcoin: manager.get_bitcoin_balance(&treasury.bitcoin),
+        runes: manager
+            .all_rune_with_balances(&treasury.bitcoin)
+            .into_iter()
+            .collect(),
+    })
+}
 
-    if current_rune_balance < amount {
-        updater::fetch_utxos_and_update_balances(
-            &sender_addresses.bitcoin,
-            TargetType::Bitcoin { target: u64::MAX },
+// the treasury keeps its change on the same address unless sent to cold storage
+#[update(guard = "is_controller")]
+pub async fn withdraw_bitcoin_from_treasury(
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+    change_address: Option<String>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_bitcoin_from_treasury", async move {
+        circuit_breaker::ensure_running()?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let change_to = resolve_change_address(&ic_cdk::caller(), change_address)?;
+        let result = withdraw_bitcoin_from_address(
+            treasury_addresses(),
+            &to,
+            amount,
+            fee_per_vbytes,
+            change_to,
         )
-        .await;
-        current_rune_balance = read_utxo_manager(|manager| {
-            manager.get_runestone_balance(&sender_addresses.bitcoin, &runeid)
+        .await?;
+        let SubmittedTransactionIdType::Bitcoin { ref txid, .. } = result;
+        record_event(EventKind::TreasuryWithdrawal {
+            to,
+            btc_amount: amount,
+            rune: None,
+            txid: txid.clone(),
         });
+        Ok(result)
+    })
+    .await
+}
 
-        if current_rune_balance < amount {
-            ic_cdk::trap("not enough balance")
-        }
-    }
-
+// spends from a single address, the change goes back to it unless `change_to` is set
+async fn withdraw_bitcoin_from_address(
+    sender: Addresses,
+    to: &str,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+    change_to: Option<::bitcoin::Address>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let receiver = bitcoin::address_validation(to).map_err(WalletError::InvalidAddress)?;
+    let from = bitcoin::address_validation(&sender.bitcoin).map_err(WalletError::InvalidAddress)?;
     let fee_per_vbytes = match fee_per_vbytes {
         None => get_fee_per_vbyte().await,
         Some(fee) => fee,
     };
-
-    let txn = match bitcoin::runestone::transfer(RuneTransferArgs {
-        runeid: runeid.clone(),
+    let args = || BitcoinTransferArgs {
+        receive: Branch {
+            addr: &sender.bitcoin,
+            account: sender.icrc1,
+            address: from.clone(),
+        },
+        change: Branch {
+            addr: &sender.bitcoin,
+            account: sender.icrc1,
+            address: from.clone(),
+        },
+        to: receiver.clone(),
         amount,
-        sender_addr: &sender_addresses.bitcoin,
-        receiver_addr: &receiver_addresses.bitcoin,
-        sender_address: sender.clone(),
-        receiver_address: receiver.clone(),
-        sender_account: sender_addresses.icrc1,
-        receiver_account: receiver_addresses.icrc1,
+        fee_payer: FeePayer::Sender,
         fee_per_vbytes,
-        paid_by_sender: true,
-        postage: None,
-    }) {
+        selection: CoinSelection::default(),
+        memo: None,
+        change_to: change_to.clone(),
+    };
+    let txn = match bitcoin::transfer(args()) {
         Ok(txn) => txn,
-        Err((_, fee)) => {
-            if fee > current_btc_balance {
+        Err(required_value) => {
+            updater::fetch_utxos_and_update_balances(
+                &sender.bitcoin,
+                TargetType::Bitcoin {
+                    target: required_value,
+                },
+            )
+            .await;
+            bitcoin::transfer(args()).map_err(|_| WalletError::InsufficientBalance)?
+        }
+    };
+    txn.build_and_submit(None).await
+}
+
+// the rune policy doesn't apply, operators can always move the treasury's runes
+#[update(guard = "is_controller")]
+pub async fn withdraw_runestone_from_treasury(
+    runeid: RuneId,
+    amount: u128,
+    to: String,
+    fee_per_vbytes: Option<u64>,
+    allow_any_script: Option<bool>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("withdraw_runestone_from_treasury", async move {
+        circuit_breaker::ensure_running()?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        let result = withdraw_runestone_from(
+            treasury_addresses(),
+            runeid.clone(),
+            amount,
+            to.clone(),
+            fee_per_vbytes,
+            allow_any_script,
+        )
+        .await?;
+        let SubmittedTransactionIdType::Bitcoin { ref txid, .. } = result;
+        record_event(EventKind::TreasuryWithdrawal {
+            to,
+            btc_amount: 0,
+            rune: Some((runeid, amount)),
+            txid: txid.clone(),
+        });
+        Ok(result)
+    })
+    .await
+}
+
+// accelerates a stuck withdrawal by spending its anchor output with a child paying for the package
+#[update(guard = "is_controller")]
+pub async fn bump_fee_with_anchor(
+    txid: String,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("bump_fee_with_anchor", async move {
+        circuit_breaker::ensure_running()?;
+        let record = read_transaction_log(|log| log.find_by_txid(&txid))
+            .ok_or(WalletError::TransactionNotFound)?;
+        let ((anchor_vout, anchor_value), parent_vsize) = match (record.anchor, record.vsize) {
+            (Some(anchor), Some(vsize)) => (anchor, vsize),
+            _ => return Err(WalletError::AnchorUnavailable),
+        };
+        let anchor = anchor_utxo(&txid, anchor_vout, anchor_value)
+            .ok_or_else(|| WalletError::InvalidArgument(String::from("invalid txid")))?;
+        let fee_pool = fee_pool_addresses();
+        let fee_pool_address =
+            bitcoin::address_validation(&fee_pool.bitcoin).map_err(WalletError::InvalidAddress)?;
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let args = || CpfpArgs {
+            fee_pool_addr: &fee_pool.bitcoin,
+            fee_pool_account: fee_pool.icrc1,
+            fee_pool_address: fee_pool_address.clone(),
+            anchor: anchor.clone(),
+            parent_vsize,
+            parent_fee: record.fee,
+            fee_per_vbytes,
+        };
+        let txn = match bitcoin::cpfp::bump_fee(args()) {
+            Ok(txn) => txn,
+            Err(required_value) => {
                 updater::fetch_utxos_and_update_balances(
-                    &receiver_addresses.bitcoin,
-                    TargetType::Bitcoin { target: u64::MAX },
+                    &fee_pool.bitcoin,
+                    TargetType::Bitcoin {
+                        target: required_value,
+                    },
                 )
                 .await;
-                current_btc_balance = read_utxo_manager(|manager| {
-                    manager.get_bitcoin_balance(&receiver_addresses.bitcoin)
-                });
-                if current_btc_balance < fee {
-                    ic_cdk::trap("not enough balance")
-                }
+                bitcoin::cpfp::bump_fee(args()).map_err(|_| WalletError::InsufficientBalance)?
             }
+        };
+        txn.build_and_submit(None).await
+    })
+    .await
+}
 
-            if let Ok(txn) = bitcoin::runestone::transfer(RuneTransferArgs {
-                runeid,
-                amount,
-                sender_addr: &sender_addresses.bitcoin,
-                receiver_addr: &receiver_addresses.bitcoin,
-                sender_address: sender,
-                receiver_address: receiver,
-                sender_account: sender_addresses.icrc1,
-                receiver_account: receiver_addresses.icrc1,
-                fee_per_vbytes,
-                paid_by_sender: true,
-                postage: None,
-            }) {
-                txn
-            } else {
-                ic_cdk::trap("not enough balance")
+#[query]
+pub fn get_multisig_address(cosigner_pubkey: Vec<u8>) -> Result<String, WalletError> {
+    let caller = ic_cdk::caller();
+    let wallet =
+        MultisigWallet::new(&caller, &cosigner_pubkey).map_err(WalletError::InvalidArgument)?;
+    Ok(wallet.address.to_string())
+}
+
+#[update]
+pub async fn build_multisig_withdrawal(
+    cosigner_pubkey: Vec<u8>,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<MultisigWithdrawal, WalletError> {
+    api_stats::track("build_multisig_withdrawal", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let wallet =
+            MultisigWallet::new(&caller, &cosigner_pubkey).map_err(WalletError::InvalidArgument)?;
+        let addr = wallet.address.to_string();
+        ensure_below_split_threshold(amount)?;
+        approvals::ensure_below_approval_threshold(amount)?;
+        withdrawal_policy::ensure_destination_allowed(&to)?;
+        exchange_rate::ensure_within_limit(&TokenType::Bitcoin, amount as u128).await?;
+        let to = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+        let current_balance = read_utxo_manager(|manager| manager.get_bitcoin_balance(&addr));
+        if current_balance < amount {
+            updater::fetch_utxos_and_update_balances(&addr, TargetType::Bitcoin { target: amount })
+                .await;
+            if read_utxo_manager(|manager| manager.get_bitcoin_balance(&addr)) < amount {
+                return Err(WalletError::InsufficientBalance);
             }
         }
-    };
-    txn.build_and_submit().await.unwrap()
+        let fee_per_vbytes = match fee_per_vbytes {
+            None => get_fee_per_vbyte().await,
+            Some(fee) => fee,
+        };
+        let charge = withdrawal_policy::charge(caller, TokenType::Bitcoin, amount as u128)?;
+        let args = || MultisigTransferArgs {
+            addr: &addr,
+            witness_script: &wallet.witness_script,
+            to: to.clone(),
+            amount,
+            fee_per_vbytes,
+        };
+        let (mut psbt, utxos) = match bitcoin::multisig::build_psbt(args()) {
+            Ok(built) => built,
+            Err(required_value) => {
+                updater::fetch_utxos_and_update_balances(
+                    &addr,
+                    TargetType::Bitcoin {
+                        target: required_value,
+                    },
+                )
+                .await;
+                bitcoin::multisig::build_psbt(args())
+                    .map_err(|_| WalletError::InsufficientBalance)?
+            }
+        };
+        bitcoin::multisig::sign_psbt(&mut psbt, &wallet.account).await;
+        let txid = psbt.unsigned_tx.compute_txid().to_string();
+        let psbt = psbt.serialize();
+        write_pending_multisig(|pending| {
+            pending.insert(
+                txid.clone(),
+                PendingMultisig {
+                    caller,
+                    cosigner_pubkey,
+                    addr,
+                    utxos,
+                    psbt: psbt.clone(),
+                    created_at: ic_cdk::api::time(),
+                },
+            )
+        });
+        charge.settle(amount as u128);
+        Ok(MultisigWithdrawal { txid, psbt })
+    })
+    .await
 }
 
 #[update]
-pub async fn withdraw_combined(
-    runeid: RuneId,
-    rune_amount: u128,
-    btc_amount: u64,
-    receiver_principal: Principal,
-    fee_per_vbytes: Option<u64>,
-) -> SubmittedTransactionIdType {
+pub async fn finalize_multisig_withdrawal(
+    psbt: Vec<u8>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    api_stats::track("finalize_multisig_withdrawal", async move {
+        circuit_breaker::ensure_running()?;
+        let caller = ic_cdk::caller();
+        let cosigned =
+            Psbt::deserialize(&psbt).map_err(|e| WalletError::InvalidPsbt(e.to_string()))?;
+        let txid = cosigned.unsigned_tx.compute_txid().to_string();
+        let pending = read_pending_multisig(|pending| pending.get(&txid))
+            .filter(|pending| pending.caller == caller)
+            .ok_or(WalletError::TransactionNotFound)?;
+        let wallet = MultisigWallet::new(&caller, &pending.cosigner_pubkey)
+            .map_err(WalletError::InvalidArgument)?;
+        let signed = Psbt::deserialize(&pending.psbt).expect("stored psbt should decode");
+        let txn = bitcoin::multisig::finalize_psbt(
+            signed,
+            &cosigned,
+            &wallet.canister_key,
+            &wallet.cosigner_key,
+        )
+        .map_err(WalletError::SignatureVerificationFailed)?;
+        let total_input: u64 = pending.utxos.iter().map(|utxo| utxo.value).sum();
+        let total_output: u64 = txn.output.iter().map(|output| output.value.to_sat()).sum();
+        let raw_transaction = ::bitcoin::consensus::serialize(&txn);
+        write_pending_multisig(|pending| pending.remove(&txid));
+        let record = TransactionRecord {
+            txid,
+            kind: TransactionKind::Multisig,
+            caller,
+            fee: total_input.saturating_sub(total_output),
+            status: TransactionStatus::Submitted,
+            timestamp: ic_cdk::api::time(),
+            vsize: Some(txn.vsize() as u64),
+            anchor: None,
+            amount: txn.output.first().map(|output| output.value.to_sat()),
+            rune: None,
+            additional_runes: None,
+            counterparty: None,
+            fee_payer: Some(caller),
+            memo: None,
+            fiat_rate: None,
+        };
+        // the receiver comes first, anything after it is change back to the multisig address
+        let roles = (0..txn.output.len())
+            .map(|index| {
+                if index == 0 {
+                    OutputRole::Recipient
+                } else {
+                    OutputRole::Change
+                }
+            })
+            .collect();
+        let spent: Vec<u64> = pending.utxos.iter().map(|utxo| utxo.value).collect();
+        let summary = transaction_handler::summarize(&txn, &spent, roles, &record);
+        let locked = vec![LockedUtxos::Bitcoin {
+            addr: pending.addr,
+            utxos: pending.utxos,
+        }];
+        Ok(transaction_handler::broadcast(record, raw_transaction, locked, Some(summary)).await)
+    })
+    .await
+}
+
+#[update]
+pub fn cancel_multisig_withdrawal(txid: String) -> Result<(), WalletError> {
     let caller = ic_cdk::caller();
-    let addresses = generate_addresses_from_principal(&caller);
-    let receiver_addresses = generate_addresses_from_principal(&receiver_principal);
-    let sender_address = bitcoin::address_validation(&addresses.bitcoin).unwrap();
-    let receiver_address = bitcoin::address_validation(&receiver_addresses.bitcoin).unwrap();
+    let pending = read_pending_multisig(|pending| pending.get(&txid))
+        .filter(|pending| pending.caller == caller)
+        .ok_or(WalletError::TransactionNotFound)?;
+    write_pending_multisig(|pending| pending.remove(&txid));
+    write_utxo_manager(|manager| manager.record_btc_utxos(&pending.addr, pending.utxos));
+    Ok(())
+}
 
-    updater::fetch_utxos_and_update_balances(
-        &addresses.bitcoin,
-        TargetType::Bitcoin { target: u64::MAX },
-    )
-    .await;
+#[update(guard = "is_controller")]
+pub fn set_sweep_policy(policy: Option<SweepPolicy>) -> Result<(), WalletError> {
+    if let Some(ref policy) = policy {
+        bitcoin::address_validation(&policy.vault_address).map_err(WalletError::InvalidAddress)?;
+        if policy.max_per_sweep == 0 || policy.interval_mins == 0 {
+            return Err(WalletError::InvalidArgument(String::from(
+                "sweep limit and interval must be positive",
+            )));
+        }
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.sweep_policy = policy.clone();
+        let _ = config.set(temp);
+    });
+    record_event(EventKind::SweepPolicyUpdated { policy });
+    sweeper::start_sweeping();
+    Ok(())
+}
 
-    updater::fetch_utxos_and_update_balances(
-        &receiver_addresses.bitcoin,
-        TargetType::Bitcoin { target: u64::MAX },
-    )
-    .await;
+#[query(guard = "is_controller")]
+pub fn get_sweep_policy() -> Option<SweepPolicy> {
+    read_config(|config| config.sweep_policy.clone())
+}
 
-    let fee_per_vbytes = match fee_per_vbytes {
-        None => get_fee_per_vbyte().await,
-        Some(fee) => fee,
-    };
-    let txn = bitcoin::combined_txn::transfer(CombinedTransactionRequest {
-        from_addr: &addresses.bitcoin,
-        receiver_addr: &receiver_addresses.bitcoin,
-        sender_address,
-        receiver_address,
-        sender_account: addresses.icrc1,
-        receiver_account: receiver_addresses.icrc1,
-        runeid,
-        rune_amount,
-        btc_amount,
-        postage: None,
-        paid_by_sender: false,
-        fee_per_vbytes,
+// sweeps right away, `amount` bypasses the ceiling and the per-sweep limit
+#[update(guard = "is_controller")]
+pub async fn sweep_to_vault(amount: Option<u64>) -> Result<Option<String>, WalletError> {
+    api_stats::track("sweep_to_vault", async move {
+        circuit_breaker::ensure_running()?;
+        sweeper::sweep(amount).await
     })
-    .unwrap();
-    txn.build_and_submit().await.unwrap()
+    .await
 }
 
+// signed bytes of a logged transaction, for its sender or controllers to rebroadcast or inspect
 #[query]
-pub fn get_deposit_addresses() -> Addresses {
+pub fn get_raw_transaction(txid: String) -> Result<Vec<u8>, WalletError> {
     let caller = ic_cdk::caller();
-    generate_addresses_from_principal(&caller)
+    let record = read_transaction_log(|log| log.find_by_txid(&txid))
+        .ok_or(WalletError::TransactionNotFound)?;
+    if record.caller != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    read_transaction_log(|log| log.raw_transaction(&txid)).ok_or(WalletError::TransactionNotFound)
+}
+
+/*
+ * signs `message` with the key of the caller's deposit address, so the caller can
+ * prove owning it to a third party. metered, see `set_rate_limits`
+*/
+#[update]
+pub async fn sign_message(
+    message: Vec<u8>,
+    format: MessageSigFormat,
+) -> Result<SignedMessage, WalletError> {
+    let metered = rate_limiter::metered(ic_cdk::caller(), async move {
+        let addresses = generate_addresses_from_principal(&ic_cdk::caller());
+        let address =
+            bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+        let signature =
+            bitcoin::message::sign(&message, &addresses.icrc1, &address, format).await?;
+        Ok(SignedMessage {
+            address: addresses.bitcoin,
+            signature,
+        })
+    });
+    api_stats::track("sign_message", metered).await
 }
 
+// the runestone a raw transaction carries, None when it has none
 #[query]
-pub fn generate_address(num: u128) -> String {
-    let subaccount = subaccount_with_num(num);
-    let account = Account {
-        owner: ic_cdk::id(),
-        subaccount: Some(subaccount),
-    };
-    account_to_p2pkh_address(&account)
+pub fn decode_runestone(raw_tx_hex: String) -> Result<Option<DecodedArtifact>, WalletError> {
+    let raw = hex::decode(raw_tx_hex.trim())
+        .map_err(|e| WalletError::InvalidArgument(format!("invalid hex: {}", e)))?;
+    let txn: ::bitcoin::Transaction = ::bitcoin::consensus::deserialize(&raw)
+        .map_err(|e| WalletError::InvalidArgument(format!("invalid transaction: {}", e)))?;
+    Ok(bitcoin::runestone::decode(&txn))
 }
 
-#[update]
-pub async fn get_bitcoin_balance_of(of: String) -> u64 {
-    let network = read_config(|config| config.bitcoin_network());
-    bitcoin_get_balance(GetBalanceRequest {
-        address: of.to_string(),
-        network,
-        min_confirmations: None,
+// only the sender and the receiver of the transfer can read its memo
+#[query]
+pub fn get_memo(txid: String) -> Result<Option<Vec<u8>>, WalletError> {
+    let caller = ic_cdk::caller();
+    let record = read_transaction_log(|log| log.find_by_txid(&txid))
+        .ok_or(WalletError::TransactionNotFound)?;
+    if record.caller != caller && record.counterparty != Some(caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(record.memo)
+}
+
+// principals can fetch their own statement, controllers any of them
+#[query]
+pub fn get_statement(
+    principal: Principal,
+    from_ts: u64,
+    to_ts: u64,
+) -> Result<Statement, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(statement::get_statement(principal, from_ts, to_ts))
+}
+
+// the principal's activity as csv for accounting tools, open to the same callers
+#[query]
+pub fn export_statement(
+    principal: Principal,
+    from_ts: u64,
+    to_ts: u64,
+) -> Result<Vec<u8>, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(statement::export_csv(principal, from_ts, to_ts))
+}
+
+#[query(guard = "is_controller")]
+pub fn get_events(offset: u64, limit: u64) -> Vec<Event> {
+    read_event_log(|log| {
+        (offset..log.len().min(offset.saturating_add(limit)))
+            .filter_map(|index| log.get(index))
+            .collect()
     })
-    .await
-    .unwrap()
-    .0
 }
 
-#[update]
-pub async fn get_runestone_balance_of(of: String) -> HashMap<RuneId, u128> {
-    updater::fetch_utxos_and_update_balances(&of, TargetType::Bitcoin { target: u64::MAX }).await;
-    read_utxo_manager(|manager| manager.all_rune_with_balances(&of))
+// activity feed of a principal, open to the principal itself and the controllers
+#[query]
+pub fn get_events_for_principal(
+    principal: Principal,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<Event>, WalletError> {
+    let caller = ic_cdk::caller();
+    if caller != principal && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    Ok(state::read_principal_events(principal, offset, limit))
+}
+
+// header of the latest balance snapshot along with the certificate covering it
+#[query]
+pub fn get_certified_snapshot() -> CertifiedSnapshotResponse {
+    certification::certified_snapshot()
+}
+
+#[query]
+pub fn get_delta_since(version: u64) -> Result<Vec<SnapshotDelta>, WalletError> {
+    certification::delta_since(version)
+}
+
+// full state of the latest snapshot version for replicas starting out
+#[query]
+pub fn get_snapshot_balances(start_after: Option<String>, limit: u64) -> Vec<AddressBalance> {
+    certification::snapshot_balances(start_after, limit)
+}
+
+// holdings of an address over time, sampled daily whenever they changed
+#[query]
+pub fn get_balance_history(
+    address: String,
+    from_ts: u64,
+    to_ts: u64,
+) -> Result<Vec<BalanceSample>, WalletError> {
+    bookkeeping::balance_history(address, from_ts, to_ts)
+}
+
+#[query]
+pub fn get_api_stats() -> Vec<ApiStats> {
+    api_stats::get_api_stats()
+}
+
+// counters for monitoring, along with the canister's memory and cycles
+#[query]
+pub fn get_metrics() -> Metrics {
+    metrics::get_metrics()
+}
+
+// for automating top-ups before the canister freezes
+#[query]
+pub fn get_cycle_status() -> CycleStatus {
+    cycle_monitor::status()
+}
+
+#[update(guard = "is_controller")]
+pub fn set_cycle_thresholds(thresholds: Option<CycleThresholds>) -> Result<(), WalletError> {
+    if thresholds
+        .as_ref()
+        .is_some_and(|thresholds| thresholds.critical > thresholds.low)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "the critical threshold can't be above the low one",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.cycle_thresholds = thresholds;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_fee_history(hours: u64) -> Vec<FeeSample> {
+    fee_tracker::get_fee_history(hours)
+}
+
+#[query]
+pub fn get_fee_trend(hours: u64) -> Option<FeeTrend> {
+    fee_tracker::get_fee_trend(hours)
+}
+
+// none until the fee history has samples from the last hours
+#[query]
+pub fn get_fee_suggestions() -> Option<FeeSuggestions> {
+    fee_tracker::get_fee_suggestions()
+}
+
+#[update(guard = "is_controller")]
+pub fn set_fee_bounds(bounds: Option<FeeBounds>) -> Result<(), WalletError> {
+    if bounds
+        .as_ref()
+        .is_some_and(|bounds| bounds.floor == 0 || bounds.floor > bounds.ceiling)
+    {
+        return Err(WalletError::InvalidArgument(String::from(
+            "the fee floor must be non-zero and at most the ceiling",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.fee_bounds = bounds;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_fee_bounds() -> Option<FeeBounds> {
+    read_config(|config| config.fee_bounds.clone())
+}
+
+#[update(guard = "is_controller")]
+pub fn set_fee_sampling_interval(minutes: u64) -> Result<(), WalletError> {
+    if minutes == 0 {
+        return Err(WalletError::InvalidArgument(String::from(
+            "sampling interval must be at least a minute",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.fee_sampling_interval_mins.replace(minutes);
+        let _ = config.set(temp);
+    });
+    fee_tracker::start_sampling();
+    Ok(())
+}
+
+/*
+ * the canister a rune withdrawal short of bitcoin for its fee turns to, `None` turns
+ * sponsoring off. it has to answer `quote_fee_sponsorship`, see `fee_sponsor`
+ */
+#[update(guard = "is_controller")]
+pub fn set_fee_sponsor(sponsor: Option<Principal>) -> Result<(), WalletError> {
+    if sponsor.is_some_and(|sponsor| sponsor == Principal::anonymous() || sponsor == ic_cdk::id()) {
+        return Err(WalletError::InvalidArgument(String::from(
+            "the sponsor has to be another canister",
+        )));
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.fee_sponsor = sponsor;
+        let _ = config.set(temp);
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_fee_sponsor() -> Option<Principal> {
+    fee_sponsor::sponsor()
+}
+
+// the caller's sponsored fees, keyed by escrow id
+#[query]
+pub fn get_fee_escrows() -> Vec<(u64, FeeEscrow)> {
+    fee_sponsor::escrows_of(&ic_cdk::caller())
+}
+
+// seeding and inspection of the mock chain, only in builds with the `regtest-mock` feature
+#[cfg(feature = "regtest-mock")]
+#[update(guard = "is_controller")]
+pub fn mock_deposit(address: String, value: u64, runes: Vec<(RuneId, u128)>) -> Outpoint {
+    regtest_mock::deposit(address, value, runes)
+}
+
+#[cfg(feature = "regtest-mock")]
+#[update(guard = "is_controller")]
+pub fn mock_mine_blocks(blocks: u32) {
+    regtest_mock::mine_blocks(blocks)
+}
+
+#[cfg(feature = "regtest-mock")]
+#[update(guard = "is_controller")]
+pub fn mock_set_fee_percentiles(percentiles: Vec<u64>) {
+    regtest_mock::set_fee_percentiles(percentiles)
+}
+
+// raw transactions in the order they were sent
+#[cfg(feature = "regtest-mock")]
+#[query]
+pub fn mock_sent_transactions() -> Vec<Vec<u8>> {
+    regtest_mock::sent_transactions()
 }
 
 ic_cdk::export_candid!();
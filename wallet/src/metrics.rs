@@ -0,0 +1,105 @@
+use candid::CandidType;
+
+use crate::{
+    state::{read_metric_counters, write_metric_counters, TransactionKind, TransactionRecord},
+    types::RuneId,
+};
+
+const WASM_PAGE_SIZE: u64 = 65_536;
+
+#[derive(CandidType)]
+pub struct Metrics {
+    pub transactions_submitted: Vec<(TransactionKind, u64)>,
+    pub sats_withdrawn: u64,
+    pub runes_withdrawn: Vec<(RuneId, u128)>,
+    pub utxo_fetches: u64,
+    // fetches answered from a cursor synced moments before
+    pub skipped_utxo_fetches: u64,
+    pub signature_calls: u64,
+    // returned errors only, a trapped broadcast rolls its count back along with the rest
+    pub failed_submissions: u64,
+    // entries the collector dropped after their ttl
+    pub reaped_pending_change: u64,
+    pub reaped_prepared_withdrawals: u64,
+    pub stable_memory_bytes: u64,
+    pub cycle_balance: u128,
+}
+
+// a broadcast transaction, what left towards other wallets counts as withdrawn
+pub fn record_submission(record: &TransactionRecord) {
+    write_metric_counters(|counters| {
+        match counters
+            .transactions_submitted
+            .iter_mut()
+            .find(|(kind, _)| *kind == record.kind)
+        {
+            Some((_, count)) => *count += 1,
+            None => counters.transactions_submitted.push((record.kind, 1)),
+        }
+        if record.counterparty.is_some() {
+            return;
+        }
+        counters.sats_withdrawn += record.amount.unwrap_or_default();
+        let runes = record
+            .rune
+            .iter()
+            .chain(record.additional_runes.iter().flatten());
+        for (runeid, amount) in runes {
+            match counters
+                .runes_withdrawn
+                .iter_mut()
+                .find(|(withdrawn, _)| withdrawn == runeid)
+            {
+                Some((_, total)) => *total += amount,
+                None => counters.runes_withdrawn.push((runeid.clone(), *amount)),
+            }
+        }
+    })
+}
+
+pub fn record_failed_submission() {
+    write_metric_counters(|counters| counters.failed_submissions += 1)
+}
+
+pub fn record_utxo_fetch() {
+    write_metric_counters(|counters| counters.utxo_fetches += 1)
+}
+
+pub fn record_skipped_utxo_fetch() {
+    write_metric_counters(|counters| {
+        counters.skipped_utxo_fetches = Some(counters.skipped_utxo_fetches.unwrap_or_default() + 1)
+    })
+}
+
+pub fn record_reaped(pending_change: u64, prepared_withdrawals: u64) {
+    if pending_change == 0 && prepared_withdrawals == 0 {
+        return;
+    }
+    write_metric_counters(|counters| {
+        counters.reaped_pending_change =
+            Some(counters.reaped_pending_change.unwrap_or_default() + pending_change);
+        counters.reaped_prepared_withdrawals =
+            Some(counters.reaped_prepared_withdrawals.unwrap_or_default() + prepared_withdrawals);
+    })
+}
+
+pub fn record_signature_call() {
+    write_metric_counters(|counters| counters.signature_calls += 1)
+}
+
+pub fn get_metrics() -> Metrics {
+    let counters = read_metric_counters(|counters| counters.clone());
+    Metrics {
+        transactions_submitted: counters.transactions_submitted,
+        sats_withdrawn: counters.sats_withdrawn,
+        runes_withdrawn: counters.runes_withdrawn,
+        utxo_fetches: counters.utxo_fetches,
+        skipped_utxo_fetches: counters.skipped_utxo_fetches.unwrap_or_default(),
+        signature_calls: counters.signature_calls,
+        failed_submissions: counters.failed_submissions,
+        reaped_pending_change: counters.reaped_pending_change.unwrap_or_default(),
+        reaped_prepared_withdrawals: counters.reaped_prepared_withdrawals.unwrap_or_default(),
+        stable_memory_bytes: ic_cdk::api::stable::stable_size() * WASM_PAGE_SIZE,
+        cycle_balance: ic_cdk::api::canister_balance128(),
+    }
+}
@@ -0,0 +1,124 @@
+use candid::Principal;
+
+use crate::{
+    bitcoin::{self, runestone::MultiRuneTransferArgs, BitcoinSweepArgs, Branch},
+    state::{read_config, read_utxo_manager, DerivationScheme},
+    transaction_handler::SubmittedTransactionIdType,
+    types::{RuneId, SweepAll, WalletError},
+    updater,
+    utils::{generate_addresses_from_principal, generate_change_addresses_from_principal},
+};
+
+// runes moved per transaction, the same batches as `sweep_all`
+const RUNES_PER_TRANSFER: usize = 8;
+
+/*
+ * moves the principal's funds from its legacy deposit and change addresses to the
+ * bip44 address of its deposit account, ahead of switching the canister over. the
+ * funds stay in custody, so the withdrawal limits don't apply. taproot and numbered
+ * addresses are left to their owners to empty
+*/
+pub async fn migrate_to_bip44(
+    principal: Principal,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SweepAll, WalletError> {
+    if read_config(|config| config.derivation_scheme()) != DerivationScheme::Legacy {
+        return Err(WalletError::InvalidArgument(String::from(
+            "addresses are already derived by bip44",
+        )));
+    }
+    let addresses = generate_addresses_from_principal(&principal);
+    let change_addresses = generate_change_addresses_from_principal(&principal);
+    let to = bitcoin::account_to_p2pkh_address_with(&addresses.icrc1, DerivationScheme::Bip44);
+    let from =
+        bitcoin::address_validation(&addresses.bitcoin).map_err(WalletError::InvalidAddress)?;
+    let change = bitcoin::address_validation(&change_addresses.bitcoin)
+        .map_err(WalletError::InvalidAddress)?;
+    let receiver = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    updater::fetch_bitcoin_branches(&addresses.bitcoin, &change_addresses.bitcoin, u64::MAX).await;
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => bitcoin::get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+    let mut migration = SweepAll {
+        txids: vec![],
+        bitcoin: 0,
+        runes: vec![],
+        error: None,
+    };
+
+    let runes: Vec<(RuneId, u128)> =
+        read_utxo_manager(|manager| manager.all_rune_with_balances(&addresses.bitcoin))
+            .into_iter()
+            .filter(|(_, balance)| *balance > 0)
+            .collect();
+    for batch in runes.chunks(RUNES_PER_TRANSFER) {
+        let args = MultiRuneTransferArgs {
+            runes: batch.to_vec(),
+            sender_addr: &addresses.bitcoin,
+            receiver_addr: &to,
+            sender_account: addresses.icrc1,
+            receiver_account: addresses.icrc1, // sender is the fee payer
+            sender_address: from.clone(),
+            receiver_address: receiver.clone(),
+            fee_per_vbytes,
+            paid_by_sender: true,
+            postage: None,
+        };
+        let submitted = match bitcoin::runestone::transfer_many(args) {
+            Ok(txn) => txn.build_and_submit(None).await,
+            Err(_) => Err(WalletError::InsufficientBalance),
+        };
+        match submitted {
+            Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => {
+                migration.txids.push(txid);
+                migration.runes.extend_from_slice(batch);
+            }
+            Err(err) if migration.txids.is_empty() => return Err(err),
+            Err(err) => {
+                migration.error = Some(err);
+                return Ok(migration);
+            }
+        }
+    }
+
+    // paper trading hands the utxos back, so the rounds are fixed up front
+    let utxos = read_utxo_manager(|manager| {
+        manager.bitcoin_utxos(&addresses.bitcoin).len()
+            + manager.bitcoin_utxos(&change_addresses.bitcoin).len()
+    });
+    for _ in 0..utxos.div_ceil(bitcoin::MAX_TX_INPUTS) {
+        let args = BitcoinSweepArgs {
+            receive: Branch {
+                addr: &addresses.bitcoin,
+                account: addresses.icrc1,
+                address: from.clone(),
+            },
+            change: Branch {
+                addr: &change_addresses.bitcoin,
+                account: change_addresses.icrc1,
+                address: change.clone(),
+            },
+            to: receiver.clone(),
+            fee_per_vbytes,
+        };
+        let Ok((txn, swept)) = bitcoin::transfer_all(args) else {
+            break;
+        };
+        match txn.build_and_submit(None).await {
+            Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => {
+                migration.txids.push(txid);
+                migration.bitcoin += swept;
+            }
+            Err(err) if migration.txids.is_empty() => return Err(err),
+            Err(err) => {
+                migration.error = Some(err);
+                break;
+            }
+        }
+    }
+    if migration.txids.is_empty() {
+        return Err(WalletError::InsufficientBalance);
+    }
+    Ok(migration)
+}
@@ -1,17 +1,24 @@
+use std::cell::RefCell;
+
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::call::CallResult;
 
-use crate::types::RuneId;
+use crate::{state::read_config, types::RuneId};
 
 const ORD_CANISTER: &str = "o25oi-jaaaa-aaaal-ajj6a-cai";
 
-#[derive(CandidType, Deserialize, Debug)]
+// failed calls in a row after which a backend leaves the rotation
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+// unhealthy backends get another chance after this long
+const RETRY_AFTER_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RuneBalance {
     pub id: RuneId,
     pub balance: u128,
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Debug)]
 pub enum MintError {
     Cap(u128),
     End(u64),
@@ -19,14 +26,14 @@ pub enum MintError {
     Unmintable,
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Debug)]
 pub enum RpcError {
     Io(String, String, String),
     Decode(String, String, String),
     Endpoint(String, String, String),
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Debug)]
 pub enum OrdError {
     Params(String),
     Overflow,
@@ -37,7 +44,287 @@ pub enum OrdError {
 
 pub type GetRunesResult = Result<Vec<RuneBalance>, OrdError>;
 
-pub async fn get_runes_by_utxo(txid: String, vout: u32) -> CallResult<(GetRunesResult,)> {
-    let ord_canister = Principal::from_text(ORD_CANISTER).unwrap();
-    ic_cdk::call(ord_canister, "get_runes_by_utxo", (txid, vout)).await
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OrdBackend {
+    pub canister: Principal,
+    pub weight: u32,
+}
+
+#[derive(CandidType, Clone)]
+pub struct OrdBackendHealth {
+    pub canister: Principal,
+    pub weight: u32,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    // times the backend's classification disagreed with the majority
+    pub divergences: u64,
+    pub calls: u64,
+    pub last_error: Option<String>,
+    pub unhealthy_since: Option<u64>,
+}
+
+struct BackendState {
+    health: OrdBackendHealth,
+    // smooth weighted round-robin counter
+    current_weight: i64,
+}
+
+thread_local! {
+    static BACKENDS: RefCell<Vec<BackendState>> = const { RefCell::new(Vec::new()) };
+}
+
+pub enum ClassificationError {
+    // no backend answered
+    Unavailable,
+    // backends answered, but no two of them agreed
+    Divergent,
+}
+
+pub fn default_backends() -> Vec<OrdBackend> {
+    vec![OrdBackend {
+        canister: Principal::from_text(ORD_CANISTER).unwrap(),
+        weight: 1,
+    }]
+}
+
+// drops the tracked health, the configured backends are picked up on the next call
+pub fn reset_health() {
+    BACKENDS.with_borrow_mut(|backends| backends.clear());
+}
+
+pub fn health() -> Vec<OrdBackendHealth> {
+    with_backends(|backends| {
+        backends
+            .iter()
+            .map(|backend| backend.health.clone())
+            .collect()
+    })
+}
+
+fn with_backends<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Vec<BackendState>) -> R,
+{
+    BACKENDS.with_borrow_mut(|backends| {
+        if backends.is_empty() {
+            *backends = read_config(|config| config.ord_backends())
+                .into_iter()
+                .map(|backend| BackendState {
+                    health: OrdBackendHealth {
+                        canister: backend.canister,
+                        weight: backend.weight,
+                        healthy: true,
+                        consecutive_failures: 0,
+                        divergences: 0,
+                        calls: 0,
+                        last_error: None,
+                        unhealthy_since: None,
+                    },
+                    current_weight: 0,
+                })
+                .collect();
+        }
+        f(backends)
+    })
+}
+
+// next backend of the rotation outside `exclude`, unhealthy ones are only
+// picked once their cooldown is over or when nothing else is left
+fn pick(exclude: &[Principal]) -> Option<Principal> {
+    let now = ic_cdk::api::time();
+    with_backends(|backends| {
+        let available = |backend: &BackendState| {
+            backend.health.healthy
+                || backend
+                    .health
+                    .unhealthy_since
+                    .is_some_and(|since| now.saturating_sub(since) >= RETRY_AFTER_NANOS)
+        };
+        let remaining: Vec<usize> = (0..backends.len())
+            .filter(|&index| !exclude.contains(&backends[index].health.canister))
+            .collect();
+        let mut candidates: Vec<usize> = remaining
+            .iter()
+            .copied()
+            .filter(|&index| available(&backends[index]))
+            .collect();
+        if candidates.is_empty() {
+            candidates = remaining;
+        }
+        let total: i64 = candidates
+            .iter()
+            .map(|&index| backends[index].health.weight as i64)
+            .sum();
+        for &index in candidates.iter() {
+            backends[index].current_weight += backends[index].health.weight as i64;
+        }
+        let best = candidates
+            .into_iter()
+            .max_by_key(|&index| backends[index].current_weight)?;
+        backends[best].current_weight -= total;
+        Some(backends[best].health.canister)
+    })
+}
+
+fn update_health<F>(canister: Principal, f: F)
+where
+    F: FnOnce(&mut OrdBackendHealth),
+{
+    with_backends(|backends| {
+        if let Some(backend) = backends
+            .iter_mut()
+            .find(|backend| backend.health.canister == canister)
+        {
+            f(&mut backend.health)
+        }
+    })
+}
+
+fn mark_unhealthy(health: &mut OrdBackendHealth) {
+    if health.healthy {
+        health.unhealthy_since = Some(ic_cdk::api::time());
+    }
+    health.healthy = false;
+}
+
+fn record_success(canister: Principal) {
+    update_health(canister, |health| {
+        health.calls += 1;
+        health.consecutive_failures = 0;
+        health.healthy = true;
+        health.unhealthy_since = None;
+    })
+}
+
+fn record_failure(canister: Principal, error: String) {
+    ic_cdk::println!("ord backend {} failed: {}", canister, error);
+    update_health(canister, |health| {
+        health.calls += 1;
+        health.consecutive_failures += 1;
+        health.last_error = Some(error);
+        if health.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+            mark_unhealthy(health);
+        } else if !health.healthy {
+            // a failed retry restarts the cooldown
+            health.unhealthy_since = Some(ic_cdk::api::time());
+        }
+    })
+}
+
+fn record_divergence(canister: Principal) {
+    update_health(canister, |health| {
+        health.divergences += 1;
+        health.last_error = Some(String::from("classification diverged from the majority"));
+        mark_unhealthy(health);
+    })
+}
+
+#[cfg(not(feature = "regtest-mock"))]
+async fn query(canister: Principal, txid: &str, vout: u32) -> Result<Vec<RuneBalance>, String> {
+    match ic_cdk::call::<_, (GetRunesResult,)>(canister, "get_runes_by_utxo", (txid, vout)).await {
+        Ok((Ok(mut runes),)) => {
+            runes.sort();
+            Ok(runes)
+        }
+        Ok((Err(err),)) => Err(format!("{:?}", err)),
+        Err((code, msg)) => Err(format!("{:?}: {}", code, msg)),
+    }
+}
+
+// every backend answers from the mock chain, which knows the runes of the utxos it holds
+#[cfg(feature = "regtest-mock")]
+async fn query(_: Principal, txid: &str, vout: u32) -> Result<Vec<RuneBalance>, String> {
+    Ok(crate::regtest_mock::runes_by_utxo(txid, vout))
+}
+
+/*
+ * asks the backends in rotation until two of them agree on the utxo's runes, a single
+ * configured backend is trusted as is. backends outvoted by the agreeing pair are
+ * taken out of the rotation
+*/
+pub async fn get_runes_by_utxo(
+    txid: String,
+    vout: u32,
+) -> Result<Vec<RuneBalance>, ClassificationError> {
+    let mut tried = vec![];
+    let mut answers: Vec<(Principal, Vec<RuneBalance>)> = vec![];
+    let agreed = loop {
+        let Some(canister) = pick(&tried) else {
+            break None;
+        };
+        tried.push(canister);
+        match query(canister, &txid, vout).await {
+            Ok(runes) => {
+                record_success(canister);
+                let agreed = answers.iter().any(|(_, answer)| *answer == runes);
+                answers.push((canister, runes.clone()));
+                if agreed {
+                    break Some(runes);
+                }
+            }
+            Err(err) => record_failure(canister, err),
+        }
+    };
+    let runes = match agreed {
+        Some(runes) => runes,
+        None => match answers.len() {
+            0 => return Err(ClassificationError::Unavailable),
+            1 => answers[0].1.clone(),
+            _ => return Err(ClassificationError::Divergent),
+        },
+    };
+    for (canister, answer) in answers.iter() {
+        if *answer != runes {
+            record_divergence(*canister);
+        }
+    }
+    Ok(runes)
+}
+
+// the ord canister's view of an etched rune
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RuneEntry {
+    pub runeid: RuneId,
+    pub block: u64,
+    pub divisibility: u8,
+    pub id: u128,
+    pub runename: String,
+    pub symbol: Option<u32>,
+}
+
+#[cfg(not(feature = "regtest-mock"))]
+async fn query_entry(canister: Principal, runeid: &RuneId) -> Result<Option<RuneEntry>, String> {
+    ic_cdk::call::<_, (Option<RuneEntry>,)>(canister, "get_rune_entry_by_runeid", (runeid,))
+        .await
+        .map(|(entry,)| entry)
+        .map_err(|(code, msg)| format!("{:?}: {}", code, msg))
+}
+
+// the mock chain doesn't etch, every rune it allocates is indivisible
+#[cfg(feature = "regtest-mock")]
+async fn query_entry(_: Principal, runeid: &RuneId) -> Result<Option<RuneEntry>, String> {
+    Ok(Some(RuneEntry {
+        runeid: runeid.clone(),
+        block: runeid.block,
+        divisibility: 0,
+        id: 0,
+        runename: format!("{}:{}", runeid.block, runeid.tx),
+        symbol: None,
+    }))
+}
+
+// an etching never changes, so the first backend to answer is taken at its word
+pub async fn get_rune_entry(runeid: &RuneId) -> Result<Option<RuneEntry>, ClassificationError> {
+    let mut tried = vec![];
+    while let Some(canister) = pick(&tried) {
+        tried.push(canister);
+        match query_entry(canister, runeid).await {
+            Ok(entry) => {
+                record_success(canister);
+                return Ok(entry);
+            }
+            Err(err) => record_failure(canister, err),
+        }
+    }
+    Err(ClassificationError::Unavailable)
 }
@@ -0,0 +1,164 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use ic_cdk_timers::TimerId;
+
+use crate::state::{
+    read_config, read_outbox, record_event, write_outbox, EventKind, Notification, OutboxEntry,
+    OutboxStatus,
+};
+
+// method every subscriber exposes, called with the entry id and the notification
+const DELIVERY_METHOD: &str = "wallet_notification";
+
+// failed deliveries after which an entry is dead-lettered
+pub const MAX_ATTEMPTS: u32 = 8;
+
+const DELIVERY_INTERVAL_SECS: u64 = 30;
+const BASE_BACKOFF_SECS: u64 = 30;
+const MAX_BACKOFF_SECS: u64 = 6 * 60 * 60;
+
+thread_local! {
+    static DELIVERY_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static DELIVERING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped delivery round doesn't block the following ones
+struct DeliveryGuard;
+
+impl DeliveryGuard {
+    fn acquire() -> Option<Self> {
+        if DELIVERING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for DeliveryGuard {
+    fn drop(&mut self) {
+        DELIVERING.set(false);
+    }
+}
+
+pub fn start_delivery() {
+    DELIVERY_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(DELIVERY_INTERVAL_SECS),
+            || ic_cdk::spawn(deliver()),
+        ));
+    });
+}
+
+// queues the notification once for every subscriber
+pub fn notify(notification: Notification) {
+    let subscribers =
+        read_config(|config| config.notification_subscribers.clone()).unwrap_or_default();
+    if subscribers.is_empty() {
+        return;
+    }
+    let now = ic_cdk::api::time();
+    write_outbox(|outbox| {
+        for subscriber in subscribers {
+            let id = outbox.last_key_value().map_or(0, |(id, _)| id + 1);
+            outbox.insert(
+                id,
+                OutboxEntry {
+                    id,
+                    subscriber,
+                    notification: notification.clone(),
+                    created_at: now,
+                    attempts: 0,
+                    next_attempt_at: now,
+                    last_error: None,
+                    status: OutboxStatus::Pending,
+                },
+            );
+        }
+    });
+}
+
+pub fn backoff(attempts: u32) -> Duration {
+    let secs = BASE_BACKOFF_SECS
+        .saturating_mul(1 << attempts.saturating_sub(1).min(32))
+        .min(MAX_BACKOFF_SECS);
+    Duration::from_secs(secs)
+}
+
+/*
+ * delivers the due entries, an entry only leaves the outbox once its subscriber
+ * accepted it. delivery is at least once and unordered, subscribers dedupe by id
+*/
+pub async fn deliver() {
+    let Some(_guard) = DeliveryGuard::acquire() else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let due: Vec<OutboxEntry> = read_outbox(|outbox| {
+        outbox
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.status.is_due(entry.next_attempt_at, now))
+            .collect()
+    });
+    for mut entry in due {
+        let result = ic_cdk::call::<_, ()>(
+            entry.subscriber,
+            DELIVERY_METHOD,
+            (entry.id, entry.notification.clone()),
+        )
+        .await;
+        match result {
+            Ok(()) => {
+                write_outbox(|outbox| outbox.remove(&entry.id));
+            }
+            Err((code, msg)) => {
+                let now = ic_cdk::api::time();
+                entry.attempts += 1;
+                entry.last_error = Some(format!("{:?}: {}", code, msg));
+                if entry.attempts >= MAX_ATTEMPTS {
+                    entry.status = OutboxStatus::DeadLettered { at: now };
+                    record_event(EventKind::NotificationDeadLettered {
+                        id: entry.id,
+                        subscriber: entry.subscriber,
+                        attempts: entry.attempts,
+                    });
+                } else {
+                    entry.next_attempt_at = now + backoff(entry.attempts).as_nanos() as u64;
+                }
+                write_outbox(|outbox| outbox.insert(entry.id, entry));
+            }
+        }
+    }
+}
+
+// puts dead-lettered entries back in the queue with a fresh attempt budget,
+// `None` retries all of them. returns the number of requeued entries
+pub fn retry_dead_lettered(ids: Option<Vec<u64>>) -> u64 {
+    let now = ic_cdk::api::time();
+    write_outbox(|outbox| {
+        let entries: Vec<OutboxEntry> = outbox
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.status.is_dead_lettered())
+            .filter(|entry| match ids {
+                Some(ref ids) => ids.contains(&entry.id),
+                None => true,
+            })
+            .collect();
+        let requeued = entries.len() as u64;
+        for mut entry in entries {
+            entry.attempts = 0;
+            entry.next_attempt_at = now;
+            entry.status = OutboxStatus::Pending;
+            outbox.insert(entry.id, entry);
+        }
+        requeued
+    })
+}
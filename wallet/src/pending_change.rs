@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use bitcoin::{hashes::Hash, Transaction};
+use candid::Principal;
+use ic_cdk::api::management_canister::bitcoin::Outpoint;
+
+use crate::{
+    bitcoin::address_validation,
+    state::{
+        read_pending_change, read_spent_inputs, write_pending_change, write_spent_inputs,
+        LockedUtxos, PendingChange, SpentInput, TransactionRecord,
+    },
+    types::PendingTransaction,
+};
+
+// change and inputs the chain never settled are dropped after this long, the transaction got replaced or evicted
+const EXPIRY_NANOS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+
+fn outpoint_key(txid: &str, vout: u32) -> String {
+    format!("{}:{}", txid, vout)
+}
+
+fn outpoint_txid(outpoint: &Outpoint) -> String {
+    bitcoin::Txid::from_raw_hash(Hash::from_slice(&outpoint.txid).unwrap()).to_string()
+}
+
+/*
+ * remembers the outputs of a submitted transaction paying back to an address it spent
+ * from. they only become spendable utxos once `bitcoin_get_utxos` reports them, until
+ * then they show up as pending for the caller. the spent inputs are held meanwhile, so
+ * a sync seeing them before the transaction gets mined doesn't record them again
+ */
+pub fn register(record: &TransactionRecord, txn: &Transaction, locked: &[LockedUtxos]) {
+    let addresses: Vec<(String, bitcoin::ScriptBuf)> = locked
+        .iter()
+        .map(|utxos| match utxos {
+            LockedUtxos::Bitcoin { addr, .. } | LockedUtxos::Runic { addr, .. } => addr,
+        })
+        .filter_map(|addr| {
+            address_validation(addr)
+                .ok()
+                .map(|address| (addr.clone(), address.script_pubkey()))
+        })
+        .collect();
+    let now = ic_cdk::api::time();
+    write_spent_inputs(|inputs| {
+        for utxos in locked {
+            let (addr, spent): (&String, Vec<_>) = match utxos {
+                LockedUtxos::Bitcoin { addr, utxos } => (addr, utxos.iter().collect()),
+                LockedUtxos::Runic { addr, utxos, .. } => {
+                    (addr, utxos.iter().map(|utxo| &utxo.utxo).collect())
+                }
+            };
+            for utxo in spent {
+                inputs.insert(
+                    outpoint_key(&outpoint_txid(&utxo.outpoint), utxo.outpoint.vout),
+                    SpentInput {
+                        spender: record.txid.clone(),
+                        address: addr.clone(),
+                        outpoint: utxo.outpoint.clone(),
+                        height: utxo.height,
+                        submitted_at: now,
+                    },
+                );
+            }
+        }
+    });
+    write_pending_change(|pending| {
+        for (vout, output) in txn.output.iter().enumerate() {
+            let Some((address, _)) = addresses
+                .iter()
+                .find(|(_, script)| *script == output.script_pubkey)
+            else {
+                continue;
+            };
+            let vout = vout as u32;
+            pending.insert(
+                outpoint_key(&record.txid, vout),
+                PendingChange {
+                    owner: record.caller,
+                    txid: record.txid.clone(),
+                    vout,
+                    address: address.clone(),
+                    value: output.value.to_sat(),
+                    submitted_at: now,
+                },
+            );
+        }
+    })
+}
+
+// drops change and spent inputs older than `EXPIRY_NANOS`, returns how many entries went
+pub fn expire() -> u64 {
+    let now = ic_cdk::api::time();
+    let inputs = write_spent_inputs(|inputs| {
+        let expired: Vec<String> = inputs
+            .iter()
+            .filter(|(_, input)| now.saturating_sub(input.submitted_at) > EXPIRY_NANOS)
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired.iter() {
+            inputs.remove(key);
+        }
+        expired.len() as u64
+    });
+    let change = write_pending_change(|pending| {
+        let expired: Vec<String> = pending
+            .iter()
+            .filter(|(_, change)| now.saturating_sub(change.submitted_at) > EXPIRY_NANOS)
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired.iter() {
+            pending.remove(key);
+        }
+        expired.len() as u64
+    });
+    inputs + change
+}
+
+// called for every utxo the chain reports, a known pending output is no longer pending
+pub fn confirm(txid: &str, vout: u32) {
+    write_pending_change(|pending| pending.remove(&outpoint_key(txid, vout)));
+}
+
+/*
+ * drops the held inputs of `addr` a walk down to `boundary` no longer found, the
+ * transaction spending them got mined
+ */
+pub fn settle_inputs(addr: &str, boundary: u32, unspent: &HashSet<Outpoint>) {
+    write_spent_inputs(|inputs| {
+        let spent: Vec<String> = inputs
+            .iter()
+            .filter(|(_, input)| {
+                input.address == addr
+                    && input.height > boundary
+                    && !unspent.contains(&input.outpoint)
+            })
+            .map(|(key, _)| key)
+            .collect();
+        for key in spent.iter() {
+            inputs.remove(key);
+        }
+    })
+}
+
+// inputs of `addr` spent by a submitted transaction the chain hasn't mined yet
+pub fn spent_inputs_of(addr: &str) -> Vec<Outpoint> {
+    read_spent_inputs(|inputs| {
+        inputs
+            .iter()
+            .filter(|(_, input)| input.address == addr)
+            .map(|(_, input)| input.outpoint)
+            .collect()
+    })
+}
+
+// the caller's submitted transactions with change still unseen, oldest first
+pub fn pending_for(owner: Principal) -> Vec<PendingTransaction> {
+    let mut transactions: Vec<PendingTransaction> = vec![];
+    read_pending_change(|pending| {
+        for (_, change) in pending.iter() {
+            if change.owner != owner {
+                continue;
+            }
+            match transactions
+                .iter_mut()
+                .find(|transaction| transaction.txid == change.txid)
+            {
+                Some(transaction) => transaction.outputs.push(change),
+                None => transactions.push(PendingTransaction {
+                    txid: change.txid.clone(),
+                    submitted_at: change.submitted_at,
+                    outputs: vec![change],
+                }),
+            }
+        }
+    });
+    transactions.sort_by_key(|transaction| transaction.submitted_at);
+    transactions
+}
@@ -0,0 +1,97 @@
+use std::future::Future;
+
+use candid::Principal;
+
+use crate::{
+    cycle_monitor,
+    state::{read_call_usage, read_config, write_call_usage, CallUsage, RateLimits},
+    types::WalletError,
+};
+
+// window usage is tracked in while no limits are configured
+const DEFAULT_WINDOW_SECS: u64 = 60 * 60;
+
+fn window_nanos(limits: Option<&RateLimits>) -> u64 {
+    limits
+        .map_or(DEFAULT_WINDOW_SECS, |limits| limits.window_secs)
+        .saturating_mul(1_000_000_000)
+}
+
+// the principal's usage with a lapsed window counted as unused
+pub fn usage_of(principal: &Principal) -> CallUsage {
+    let limits = read_config(|config| config.rate_limits.clone());
+    current_usage(principal, window_nanos(limits.as_ref()))
+}
+
+fn current_usage(principal: &Principal, window: u64) -> CallUsage {
+    let now = ic_cdk::api::time();
+    let mut usage = read_call_usage(|usage| usage.get(principal)).unwrap_or_default();
+    if now.saturating_sub(usage.window_start) >= window {
+        usage.window_start = now;
+        usage.calls = 0;
+        usage.cycles = 0;
+    }
+    usage
+}
+
+// controllers are never limited, anyone else past the quota has to pay for the call
+fn admit(caller: Principal) -> Result<(), WalletError> {
+    let Some(limits) = read_config(|config| config.rate_limits.clone()) else {
+        return Ok(());
+    };
+    if ic_cdk::api::is_controller(&caller) {
+        return Ok(());
+    }
+    let mut usage = current_usage(&caller, window_nanos(Some(&limits)));
+    if usage.calls < limits.max_calls && usage.cycles < limits.max_cycles {
+        return Ok(());
+    }
+    match limits.price_per_call {
+        Some(price) if ic_cdk::api::call::msg_cycles_available128() >= price => {
+            usage.cycles_paid += ic_cdk::api::call::msg_cycles_accept128(price);
+            write_call_usage(|map| map.insert(caller, usage));
+            Ok(())
+        }
+        Some(price) => Err(WalletError::RateLimited(format!(
+            "quota used up, attach {} cycles to call past it",
+            price
+        ))),
+        None => {
+            let resets_at = usage.window_start + window_nanos(Some(&limits));
+            Err(WalletError::RateLimited(format!(
+                "quota used up, resets in {} seconds",
+                resets_at.saturating_sub(ic_cdk::api::time()) / 1_000_000_000
+            )))
+        }
+    }
+}
+
+fn record(caller: Principal, cycles: u128) {
+    let limits = read_config(|config| config.rate_limits.clone());
+    let mut usage = current_usage(&caller, window_nanos(limits.as_ref()));
+    usage.calls += 1;
+    usage.cycles += cycles;
+    usage.total_calls += 1;
+    usage.total_cycles += cycles;
+    write_call_usage(|map| map.insert(caller, usage));
+}
+
+/*
+ * runs an endpoint whose bitcoin api calls the canister pays for on behalf of `caller`.
+ * the cycles it cost are read off the canister balance, so concurrent calls can get
+ * part of each other's cost attributed
+*/
+pub async fn metered<T, F>(caller: Principal, call: F) -> Result<T, WalletError>
+where
+    F: Future<Output = Result<T, WalletError>>,
+{
+    cycle_monitor::ensure_discretionary()?;
+    admit(caller)?;
+    let balance = ic_cdk::api::canister_balance128();
+    let result = call.await;
+    record(
+        caller,
+        balance.saturating_sub(ic_cdk::api::canister_balance128()),
+    );
+    result
+}
@@ -0,0 +1,77 @@
+use std::{cell::RefCell, time::Duration};
+
+use ic_cdk::api::management_canister::bitcoin::GetBalanceRequest;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin_api::bitcoin_get_balance,
+    cycle_monitor,
+    state::{read_config, read_utxo_manager, record_event, EventKind},
+    updater,
+};
+
+thread_local! {
+    static RECONCILIATION_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+pub fn start_reconciliation() {
+    let policy = read_config(|config| config.reconciliation_policy.clone());
+    RECONCILIATION_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        if let Some(policy) = policy.filter(|policy| policy.enabled) {
+            *timer = Some(ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(policy.interval_mins * 60),
+                || {
+                    if !cycle_monitor::is_degraded() {
+                        ic_cdk::spawn(reconcile())
+                    }
+                },
+            ));
+        }
+    });
+}
+
+/*
+ * compares the sats recorded for every address with the chain's view.
+ * the chain keeps counting utxos of unconfirmed withdrawals, so small transient
+ * drift is expected and only drift above the threshold gets recorded.
+ * rune balances aren't queried from ord here, a resync refreshes them along with bitcoin
+*/
+pub async fn reconcile() {
+    let Some(policy) = read_config(|config| config.reconciliation_policy.clone()) else {
+        return;
+    };
+    let network = read_config(|config| config.bitcoin_network());
+    let addresses = read_utxo_manager(|manager| manager.addresses());
+    for address in addresses {
+        let chain_balance = match bitcoin_get_balance(GetBalanceRequest {
+            address: address.clone(),
+            network,
+            min_confirmations: None,
+        })
+        .await
+        {
+            Ok((balance,)) => balance,
+            Err((_, err)) => {
+                ic_cdk::println!("failed to get the balance of {}: {}", address, err);
+                continue;
+            }
+        };
+        let local_balance = read_utxo_manager(|manager| manager.total_value(&address));
+        if local_balance.abs_diff(chain_balance) <= policy.drift_threshold {
+            continue;
+        }
+        let resynced = policy.auto_resync;
+        if resynced {
+            updater::resync_address(&address).await;
+        }
+        record_event(EventKind::BalanceDrift {
+            address,
+            local_balance,
+            chain_balance,
+            resynced,
+        });
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use bitcoin::{hashes::Hash, Address, Transaction, Txid};
+use ic_cdk::api::{
+    call::{CallResult, RejectionCode},
+    management_canister::bitcoin::{
+        GetBalanceRequest, GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse,
+        MillisatoshiPerByte, Outpoint, Satoshi, SendTransactionRequest, Utxo,
+    },
+};
+use ordinals::{Artifact, Runestone};
+
+use crate::{
+    bitcoin::{bitcoin_network, sha256},
+    ord_canister::RuneBalance,
+    types::RuneId,
+};
+
+/*
+ * stand-in for the bitcoin canister and the ord backends. every sent transaction gets
+ * mined right away: its inputs leave the utxo set and its outputs join it one block
+ * higher, with the runes moved as the runestone's edicts say
+ */
+#[derive(Default)]
+struct MockChain {
+    height: u32,
+    utxos: HashMap<String, Vec<Utxo>>,
+    runes: HashMap<Outpoint, Vec<RuneBalance>>,
+    sent: Vec<Vec<u8>>,
+    fee_percentiles: Vec<MillisatoshiPerByte>,
+    deposits: u64,
+}
+
+thread_local! {
+    static CHAIN: RefCell<MockChain> = RefCell::default();
+}
+
+impl MockChain {
+    fn add_utxo(&mut self, address: String, utxo: Utxo, runes: Vec<RuneBalance>) {
+        if !runes.is_empty() {
+            self.runes.insert(utxo.outpoint.clone(), runes);
+        }
+        self.utxos.entry(address).or_default().push(utxo);
+    }
+
+    fn mine(&mut self, txn: &Transaction, raw_transaction: Vec<u8>) {
+        self.height += 1;
+        let mut unallocated: BTreeMap<RuneId, u128> = BTreeMap::new();
+        for input in txn.input.iter() {
+            let outpoint = Outpoint {
+                txid: input.previous_output.txid.to_byte_array().to_vec(),
+                vout: input.previous_output.vout,
+            };
+            for utxos in self.utxos.values_mut() {
+                utxos.retain(|utxo| utxo.outpoint != outpoint);
+            }
+            for rune in self.runes.remove(&outpoint).unwrap_or_default() {
+                *unallocated.entry(rune.id).or_default() += rune.balance;
+            }
+        }
+        let allocated = allocate_runes(txn, unallocated);
+        let txid = txn.compute_txid().to_byte_array().to_vec();
+        for (vout, output) in txn.output.iter().enumerate() {
+            let Ok(address) = Address::from_script(&output.script_pubkey, bitcoin_network()) else {
+                continue;
+            };
+            let utxo = Utxo {
+                outpoint: Outpoint {
+                    txid: txid.clone(),
+                    vout: vout as u32,
+                },
+                value: output.value.to_sat(),
+                height: self.height,
+            };
+            let runes = allocated
+                .get(&vout)
+                .map(|runes| {
+                    runes
+                        .iter()
+                        .map(|(id, balance)| RuneBalance {
+                            id: id.clone(),
+                            balance: *balance,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.add_utxo(address.to_string(), utxo, runes);
+        }
+        self.sent.push(raw_transaction);
+    }
+}
+
+// edicts first, whatever is left goes to the pointer or the first non OP_RETURN output
+fn allocate_runes(
+    txn: &Transaction,
+    mut unallocated: BTreeMap<RuneId, u128>,
+) -> HashMap<usize, BTreeMap<RuneId, u128>> {
+    let mut allocated: HashMap<usize, BTreeMap<RuneId, u128>> = HashMap::new();
+    let mut default_output = txn
+        .output
+        .iter()
+        .position(|output| !output.script_pubkey.is_op_return());
+    if let Some(Artifact::Runestone(runestone)) = Runestone::decipher(txn) {
+        if let Some(pointer) = runestone.pointer {
+            default_output = Some(pointer as usize);
+        }
+        for edict in runestone.edicts {
+            let id = RuneId {
+                block: edict.id.block,
+                tx: edict.id.tx,
+            };
+            let available = unallocated.get(&id).copied().unwrap_or_default();
+            // an amount of zero moves everything left of the rune
+            let amount = if edict.amount == 0 {
+                available
+            } else {
+                edict.amount.min(available)
+            };
+            if amount == 0 {
+                continue;
+            }
+            unallocated.insert(id.clone(), available - amount);
+            *allocated
+                .entry(edict.output as usize)
+                .or_default()
+                .entry(id)
+                .or_default() += amount;
+        }
+    }
+    if let Some(output) = default_output {
+        for (id, balance) in unallocated.into_iter().filter(|(_, balance)| *balance > 0) {
+            *allocated.entry(output).or_default().entry(id).or_default() += balance;
+        }
+    }
+    allocated
+}
+
+// funds the address with a fresh utxo one block higher, returns its outpoint
+pub fn deposit(address: String, value: u64, runes: Vec<(RuneId, u128)>) -> Outpoint {
+    CHAIN.with_borrow_mut(|chain| {
+        chain.height += 1;
+        chain.deposits += 1;
+        let txid = sha256(format!("mock-deposit-{}", chain.deposits).as_bytes());
+        let utxo = Utxo {
+            outpoint: Outpoint { txid, vout: 0 },
+            value,
+            height: chain.height,
+        };
+        let outpoint = utxo.outpoint.clone();
+        let runes = runes
+            .into_iter()
+            .map(|(id, balance)| RuneBalance { id, balance })
+            .collect();
+        chain.add_utxo(address, utxo, runes);
+        outpoint
+    })
+}
+
+// empty blocks, for deposits to sink below the wallet's reorg window
+pub fn mine_blocks(blocks: u32) {
+    CHAIN.with_borrow_mut(|chain| chain.height += blocks)
+}
+
+pub fn sent_transactions() -> Vec<Vec<u8>> {
+    CHAIN.with_borrow(|chain| chain.sent.clone())
+}
+
+// an empty list has the wallet fall back to the chain's default fee
+pub fn set_fee_percentiles(percentiles: Vec<MillisatoshiPerByte>) {
+    CHAIN.with_borrow_mut(|chain| chain.fee_percentiles = percentiles)
+}
+
+pub fn runes_by_utxo(txid: &str, vout: u32) -> Vec<RuneBalance> {
+    let Ok(txid) = Txid::from_str(txid) else {
+        return vec![];
+    };
+    let outpoint = Outpoint {
+        txid: txid.to_byte_array().to_vec(),
+        vout,
+    };
+    let mut runes =
+        CHAIN.with_borrow(|chain| chain.runes.get(&outpoint).cloned().unwrap_or_default());
+    runes.sort();
+    runes
+}
+
+pub async fn bitcoin_get_utxos(arg: GetUtxosRequest) -> CallResult<(GetUtxosResponse,)> {
+    CHAIN.with_borrow(|chain| {
+        let mut utxos = chain.utxos.get(&arg.address).cloned().unwrap_or_default();
+        // newest first, the order the bitcoin canister reports them in
+        utxos.sort_by(|a, b| b.height.cmp(&a.height));
+        Ok((GetUtxosResponse {
+            utxos,
+            tip_block_hash: vec![0; 32],
+            tip_height: chain.height,
+            next_page: None,
+        },))
+    })
+}
+
+pub async fn bitcoin_get_balance(arg: GetBalanceRequest) -> CallResult<(Satoshi,)> {
+    CHAIN.with_borrow(|chain| {
+        let balance = chain
+            .utxos
+            .get(&arg.address)
+            .map_or(0, |utxos| utxos.iter().map(|utxo| utxo.value).sum());
+        Ok((balance,))
+    })
+}
+
+pub async fn bitcoin_get_current_fee_percentiles(
+    _: GetCurrentFeePercentilesRequest,
+) -> CallResult<(Vec<MillisatoshiPerByte>,)> {
+    Ok((CHAIN.with_borrow(|chain| chain.fee_percentiles.clone()),))
+}
+
+pub async fn bitcoin_send_transaction(arg: SendTransactionRequest) -> CallResult<()> {
+    let txn: Transaction = bitcoin::consensus::deserialize(&arg.transaction)
+        .map_err(|err| (RejectionCode::CanisterReject, err.to_string()))?;
+    CHAIN.with_borrow_mut(|chain| chain.mine(&txn, arg.transaction));
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use candid::{Nat, Principal};
+use icrc_ledger_types::{
+    icrc::generic_metadata_value::MetadataValue,
+    icrc1::{
+        account::Account,
+        transfer::{TransferArg, TransferError},
+    },
+};
+
+use crate::{
+    bitcoin::{self, Branch, Intent, PlanError, TxPlanner},
+    state::{
+        read_config, read_ledger_balances, read_utxo_manager, write_ledger_balances,
+        write_ledger_transfers, LedgerToken, LedgerTransfer,
+    },
+    transaction_handler::SubmittedTransactionIdType,
+    types::{FeePayer, RuneId, WalletError},
+    updater::{self, TargetType},
+    utils::{generate_addresses_from_principal, rune_ledger_addresses},
+};
+
+// longest memo an internal transfer carries, as on the icrc-1 reference ledger
+const MAX_MEMO_SIZE: usize = 32;
+
+pub fn token(runeid: &RuneId) -> Result<LedgerToken, WalletError> {
+    read_config(|config| config.rune_ledger_tokens.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .find(|token| token.runeid == *runeid)
+        .ok_or_else(|| WalletError::RuneNotSupported(runeid.clone()))
+}
+
+pub fn metadata(runeid: &RuneId) -> Result<Vec<(String, MetadataValue)>, WalletError> {
+    let token = token(runeid)?;
+    Ok(vec![
+        (String::from("icrc1:name"), MetadataValue::Text(token.name)),
+        (
+            String::from("icrc1:symbol"),
+            MetadataValue::Text(token.symbol),
+        ),
+        (
+            String::from("icrc1:decimals"),
+            MetadataValue::Nat(Nat::from(token.decimals)),
+        ),
+        (
+            String::from("icrc1:fee"),
+            MetadataValue::Nat(Nat::from(0u64)),
+        ),
+    ])
+}
+
+pub fn balance_of(runeid: &RuneId, account: &Account) -> u128 {
+    read_ledger_balances(|balances| balances.get(&account.to_string()))
+        .map_or(0, |balances| balances.get(runeid))
+}
+
+fn credit(account: &Account, runeid: &RuneId, amount: u128) {
+    write_ledger_balances(|balances| {
+        let key = account.to_string();
+        let mut account_balances = balances.get(&key).unwrap_or_default();
+        let balance = account_balances.get(runeid);
+        account_balances.set(runeid, balance + amount);
+        balances.insert(key, account_balances);
+    })
+}
+
+// fails with the account's balance when it doesn't cover `amount`
+fn debit(account: &Account, runeid: &RuneId, amount: u128) -> Result<(), u128> {
+    write_ledger_balances(|balances| {
+        let key = account.to_string();
+        let mut account_balances = balances.get(&key).unwrap_or_default();
+        let balance = account_balances.get(runeid);
+        if balance < amount {
+            return Err(balance);
+        }
+        account_balances.set(runeid, balance - amount);
+        if account_balances.is_empty() {
+            balances.remove(&key);
+        } else {
+            balances.insert(key, account_balances);
+        }
+        Ok(())
+    })
+}
+
+fn record(transfer: LedgerTransfer) -> u64 {
+    write_ledger_transfers(|transfers| {
+        let index = transfers.last_key_value().map_or(0, |(index, _)| index + 1);
+        transfers.insert(index, transfer);
+        index
+    })
+}
+
+/*
+ * moves runes between two accounts of the internal ledger, nothing is broadcast.
+ * transfers are free and not deduplicated, `created_at_time` is ignored
+*/
+pub fn transfer(
+    caller: Principal,
+    runeid: &RuneId,
+    arg: TransferArg,
+) -> Result<Nat, TransferError> {
+    token(runeid).map_err(|_| TransferError::GenericError {
+        error_code: Nat::from(0u64),
+        message: String::from("rune isn't tracked by the ledger"),
+    })?;
+    if arg.fee.as_ref().is_some_and(|fee| *fee != Nat::from(0u64)) {
+        return Err(TransferError::BadFee {
+            expected_fee: Nat::from(0u64),
+        });
+    }
+    if arg
+        .memo
+        .as_ref()
+        .is_some_and(|memo| memo.0.len() > MAX_MEMO_SIZE)
+    {
+        return Err(TransferError::GenericError {
+            error_code: Nat::from(1u64),
+            message: format!("memo is longer than {} bytes", MAX_MEMO_SIZE),
+        });
+    }
+    let from = Account {
+        owner: caller,
+        subaccount: arg.from_subaccount,
+    };
+    let balance = balance_of(runeid, &from);
+    let amount = u128::try_from(&arg.amount.0).map_err(|_| TransferError::InsufficientFunds {
+        balance: Nat::from(balance),
+    })?;
+    debit(&from, runeid, amount).map_err(|balance| TransferError::InsufficientFunds {
+        balance: Nat::from(balance),
+    })?;
+    credit(&arg.to, runeid, amount);
+    let index = record(LedgerTransfer {
+        runeid: runeid.clone(),
+        from: Some(from),
+        to: Some(arg.to),
+        amount,
+        memo: arg.memo.map(|memo| memo.0.into_vec()),
+        timestamp: ic_cdk::api::time(),
+    });
+    Ok(Nat::from(index))
+}
+
+/*
+ * sends the caller's runes to the ledger's pool address and credits them to the
+ * caller's default account once the transaction is submitted
+*/
+pub async fn deposit(
+    caller: Principal,
+    runeid: RuneId,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    token(&runeid)?;
+    let submitted = crate::withdraw_runestone_from(
+        generate_addresses_from_principal(&caller),
+        runeid.clone(),
+        amount,
+        rune_ledger_addresses().bitcoin,
+        fee_per_vbytes,
+        None,
+    )
+    .await?;
+    let account = Account {
+        owner: caller,
+        subaccount: None,
+    };
+    credit(&account, &runeid, amount);
+    record(LedgerTransfer {
+        runeid,
+        from: None,
+        to: Some(account),
+        amount,
+        memo: None,
+        timestamp: ic_cdk::api::time(),
+    });
+    Ok(submitted)
+}
+
+/*
+ * takes runes out of the caller's default account back to its deposit address,
+ * the fee comes out of the caller's bitcoin. the debit is refunded when nothing
+ * gets submitted
+*/
+pub async fn withdraw(
+    caller: Principal,
+    runeid: RuneId,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    token(&runeid)?;
+    let account = Account {
+        owner: caller,
+        subaccount: None,
+    };
+    debit(&account, &runeid, amount).map_err(|_| WalletError::InsufficientBalance)?;
+    let submitted = withdraw_from_pool(caller, &runeid, amount, fee_per_vbytes).await;
+    if submitted.is_ok() {
+        record(LedgerTransfer {
+            runeid,
+            from: Some(account),
+            to: None,
+            amount,
+            memo: None,
+            timestamp: ic_cdk::api::time(),
+        });
+    } else {
+        credit(&account, &runeid, amount);
+    }
+    submitted
+}
+
+async fn withdraw_from_pool(
+    caller: Principal,
+    runeid: &RuneId,
+    amount: u128,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let pool = rune_ledger_addresses();
+    let receiver_addresses = generate_addresses_from_principal(&caller);
+    let sender = bitcoin::address_validation(&pool.bitcoin).map_err(WalletError::InvalidAddress)?;
+    let receiver = bitcoin::address_validation(&receiver_addresses.bitcoin)
+        .map_err(WalletError::InvalidAddress)?;
+    let pool_balance =
+        || read_utxo_manager(|manager| manager.get_runestone_balance(&pool.bitcoin, runeid));
+    if pool_balance() < amount {
+        updater::fetch_utxos_and_update_balances(
+            &pool.bitcoin,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+        // deposits into the pool count once they are confirmed
+        if pool_balance() < amount {
+            return Err(WalletError::InsufficientBalance);
+        }
+    }
+    updater::verify_runic_selection(&pool.bitcoin, runeid, amount).await?;
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => bitcoin::get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+    // the receiver pays the fee, the pool only holds runes
+    let plan = || {
+        TxPlanner::new(
+            Branch {
+                addr: &pool.bitcoin,
+                account: pool.icrc1,
+                address: sender.clone(),
+            },
+            fee_per_vbytes,
+        )
+        .with(Intent::SendRune {
+            runeid: runeid.clone(),
+            amount,
+            to: Branch {
+                addr: &receiver_addresses.bitcoin,
+                account: receiver_addresses.icrc1,
+                address: receiver.clone(),
+            },
+        })
+        .with(Intent::PayFeeFrom(FeePayer::Receiver))
+        .plan()
+    };
+    let txn = match plan() {
+        Ok(txn) => txn,
+        Err(PlanError::Shortfall(fee)) => {
+            if read_utxo_manager(|manager| manager.get_bitcoin_balance(&receiver_addresses.bitcoin))
+                < fee
+            {
+                updater::fetch_utxos_and_update_balances(
+                    &receiver_addresses.bitcoin,
+                    TargetType::Bitcoin { target: u64::MAX },
+                )
+                .await;
+            }
+            plan()?
+        }
+        Err(err) => return Err(err.into()),
+    };
+    txn.build_and_submit(None).await
+}
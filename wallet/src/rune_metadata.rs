@@ -0,0 +1,105 @@
+use crate::{
+    ord_canister,
+    state::{read_rune_metadata, write_rune_metadata, RuneMetadata},
+    types::{RuneId, WalletError},
+};
+
+// cached after the first lookup, the ord canister is only asked about unseen runes
+pub async fn metadata(runeid: &RuneId) -> Result<RuneMetadata, WalletError> {
+    if let Some(metadata) = read_rune_metadata(|metadata| metadata.get(runeid)) {
+        return Ok(metadata);
+    }
+    let entry = match ord_canister::get_rune_entry(runeid).await {
+        Ok(Some(entry)) => entry,
+        Ok(None) => {
+            return Err(WalletError::InvalidArgument(format!(
+                "rune {}:{} isn't etched",
+                runeid.block, runeid.tx
+            )))
+        }
+        Err(_) => {
+            return Err(WalletError::IndexerUnavailable(String::from(
+                "no ord backend answered for the rune's entry",
+            )))
+        }
+    };
+    let metadata = RuneMetadata {
+        runeid: runeid.clone(),
+        runename: entry.runename,
+        divisibility: entry.divisibility,
+        symbol: entry
+            .symbol
+            .and_then(char::from_u32)
+            .map(|symbol| symbol.to_string()),
+    };
+    write_rune_metadata(|map| map.insert(runeid.clone(), metadata.clone()));
+    Ok(metadata)
+}
+
+// `amount` in whole units of the rune, e.g. "12.5", converted to base units
+pub async fn parse_amount(runeid: &RuneId, amount: &str) -> Result<u128, WalletError> {
+    let divisibility = metadata(runeid).await?.divisibility;
+    to_base_units(amount, divisibility).map_err(WalletError::InvalidArgument)
+}
+
+/*
+ * digits with an optional fractional part. fractional digits past the rune's
+ * divisibility are only accepted when they are zeros, anything else would be
+ * rounded away
+*/
+fn to_base_units(amount: &str, divisibility: u8) -> Result<u128, String> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty()
+        || !is_digits(whole)
+        || !is_digits(fraction)
+        || (amount.contains('.') && fraction.is_empty())
+    {
+        return Err(format!("{} isn't a decimal amount", amount));
+    }
+    let divisibility = divisibility as usize;
+    if fraction.len() > divisibility && fraction[divisibility..].bytes().any(|byte| byte != b'0') {
+        return Err(format!(
+            "{} has more than {} decimal places",
+            amount, divisibility
+        ));
+    }
+    let fraction = &fraction[..fraction.len().min(divisibility)];
+    let digits = format!("{}{:0<width$}", whole, fraction, width = divisibility);
+    digits
+        .parse::<u128>()
+        .map_err(|_| format!("{} is too large", amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_base_units_scales_by_divisibility() {
+        assert_eq!(to_base_units("12", 2), Ok(1_200));
+        assert_eq!(to_base_units("1.5", 2), Ok(150));
+        assert_eq!(to_base_units("0.01", 2), Ok(1));
+        assert_eq!(to_base_units("7", 0), Ok(7));
+    }
+
+    #[test]
+    fn to_base_units_accepts_trailing_zeros() {
+        assert_eq!(to_base_units("1.50", 1), Ok(15));
+        assert_eq!(to_base_units("3.000", 0), Ok(3));
+        assert!(to_base_units("1.05", 1).is_err());
+    }
+
+    #[test]
+    fn to_base_units_rejects_malformed_amounts() {
+        for amount in ["", "1.", ".5", "-1", "1e3", "1.2.3", " 1"] {
+            assert!(to_base_units(amount, 2).is_err(), "{}", amount);
+        }
+    }
+
+    #[test]
+    fn to_base_units_rejects_overflow() {
+        assert_eq!(to_base_units(&u128::MAX.to_string(), 0), Ok(u128::MAX));
+        assert!(to_base_units(&u128::MAX.to_string(), 1).is_err());
+    }
+}
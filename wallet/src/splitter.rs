@@ -0,0 +1,219 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin,
+    state::{
+        read_config, read_split_withdrawals, write_split_withdrawals, SplitPart, SplitWithdrawal,
+        SplitWithdrawalStatus,
+    },
+    transaction_handler::SubmittedTransactionIdType,
+    types::{CoinSelection, FeePayer, WalletError},
+};
+
+thread_local! {
+    static PART_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static SUBMITTING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped run doesn't block the following ones
+struct SubmitGuard;
+
+impl SubmitGuard {
+    fn acquire() -> Option<Self> {
+        if SUBMITTING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for SubmitGuard {
+    fn drop(&mut self) {
+        SUBMITTING.set(false);
+    }
+}
+
+// timers don't survive upgrades, picks up the jobs still in progress
+pub fn start_splitting() {
+    schedule_next();
+}
+
+// wakes up when the earliest pending part of any job is due
+fn schedule_next() {
+    let next_part_at = read_split_withdrawals(|withdrawals| {
+        withdrawals
+            .iter()
+            .filter(|(_, withdrawal)| withdrawal.is_in_progress())
+            .map(|(_, withdrawal)| withdrawal.next_part_at)
+            .min()
+    });
+    PART_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        let Some(next_part_at) = next_part_at else {
+            return;
+        };
+        let delay = Duration::from_nanos(next_part_at.saturating_sub(ic_cdk::api::time()));
+        *timer = Some(ic_cdk_timers::set_timer(delay, || {
+            PART_TIMER.with_borrow_mut(|timer| timer.take());
+            ic_cdk::spawn(submit_due_parts())
+        }));
+    });
+}
+
+// `amount` spread evenly over as few parts of at most `max_part` as possible
+fn split(amount: u64, max_part: u64) -> Vec<SplitPart> {
+    let count = amount.div_ceil(max_part);
+    (0..count)
+        .map(|index| SplitPart {
+            amount: amount / count + u64::from(index < amount % count),
+            txid: None,
+            submitted_at: None,
+        })
+        .collect()
+}
+
+/*
+ * registers a withdrawal as a job and submits its first part right away. amounts
+ * up to the policy's threshold make a single part job
+*/
+pub fn create(
+    caller: Principal,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+) -> Result<SplitWithdrawal, WalletError> {
+    let policy = read_config(|config| config.split_policy.clone()).ok_or_else(|| {
+        WalletError::InvalidArgument(String::from("withdrawal splitting is disabled"))
+    })?;
+    bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    let parts = if amount > policy.threshold {
+        split(amount, policy.max_part)
+    } else {
+        split(amount, amount.max(1))
+    };
+    let now = ic_cdk::api::time();
+    let withdrawal = write_split_withdrawals(|withdrawals| {
+        let id = withdrawals.last_key_value().map_or(0, |(id, _)| id + 1);
+        let withdrawal = SplitWithdrawal {
+            id,
+            caller,
+            to,
+            amount,
+            fee_per_vbytes,
+            parts,
+            interval_secs: policy.interval_secs,
+            next_part_at: now,
+            created_at: now,
+            status: SplitWithdrawalStatus::InProgress,
+        };
+        withdrawals.insert(id, withdrawal.clone());
+        withdrawal
+    });
+    schedule_next();
+    Ok(withdrawal)
+}
+
+// stops a job before its remaining parts are submitted
+pub fn cancel(id: u64, caller: Principal) -> Result<(), WalletError> {
+    let mut withdrawal = read_split_withdrawals(|withdrawals| withdrawals.get(&id))
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no such withdrawal job")))?;
+    if withdrawal.caller != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err(WalletError::Unauthorized);
+    }
+    if !withdrawal.is_in_progress() {
+        return Err(WalletError::InvalidArgument(String::from(
+            "withdrawal job already finished",
+        )));
+    }
+    withdrawal.status = SplitWithdrawalStatus::Cancelled;
+    write_split_withdrawals(|withdrawals| withdrawals.insert(id, withdrawal));
+    schedule_next();
+    Ok(())
+}
+
+fn update<F>(id: u64, f: F)
+where
+    F: FnOnce(&mut SplitWithdrawal),
+{
+    write_split_withdrawals(|withdrawals| {
+        if let Some(mut withdrawal) = withdrawals.get(&id) {
+            f(&mut withdrawal);
+            withdrawals.insert(id, withdrawal);
+        }
+    })
+}
+
+// submits one part of every job that is due, a failed part fails its whole job
+pub async fn submit_due_parts() {
+    let Some(_guard) = SubmitGuard::acquire() else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let due: Vec<SplitWithdrawal> = read_split_withdrawals(|withdrawals| {
+        withdrawals
+            .iter()
+            .map(|(_, withdrawal)| withdrawal)
+            .filter(|withdrawal| withdrawal.is_in_progress() && withdrawal.next_part_at <= now)
+            .collect()
+    });
+    for withdrawal in due {
+        let Some(index) = withdrawal.next_part() else {
+            update(withdrawal.id, |withdrawal| {
+                withdrawal.status = SplitWithdrawalStatus::Completed
+            });
+            continue;
+        };
+        let part = withdrawal.parts[index].amount;
+        let submitted = match crate::ensure_unallocated(withdrawal.caller, part).await {
+            Ok(()) => match crate::bitcoin_withdrawal(
+                withdrawal.caller,
+                withdrawal.to.clone(),
+                part,
+                withdrawal.fee_per_vbytes,
+                FeePayer::Sender,
+                CoinSelection::default(),
+                None,
+                None,
+            )
+            .await
+            {
+                Ok(txn) => txn.build_and_submit(None).await,
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        update(withdrawal.id, |withdrawal| {
+            // cancelled while the part was being built
+            if !withdrawal.is_in_progress() {
+                return;
+            }
+            match submitted {
+                Ok(SubmittedTransactionIdType::Bitcoin { txid, .. }) => {
+                    withdrawal.parts[index].txid = Some(txid);
+                    withdrawal.parts[index].submitted_at = Some(ic_cdk::api::time());
+                    if withdrawal.next_part().is_none() {
+                        withdrawal.status = SplitWithdrawalStatus::Completed;
+                    } else {
+                        withdrawal.next_part_at =
+                            ic_cdk::api::time() + withdrawal.interval_secs * 1_000_000_000;
+                    }
+                }
+                Err(err) => {
+                    withdrawal.status = SplitWithdrawalStatus::Failed {
+                        reason: format!("part {} failed: {:?}", index, err),
+                    }
+                }
+            }
+        });
+    }
+    schedule_next();
+}
@@ -1,18 +1,177 @@
 use std::cell::RefCell;
 
+use candid::Principal;
+
+use api_stats::{init_api_stats_map, ApiStatsMap};
+use balance_history::{
+    init_balance_history_map, init_latest_sample_map, BalanceHistoryMap, LatestSampleMap,
+};
+pub use balance_history::{BalanceSample, HistoryKey};
+pub use balance_subscriptions::BalanceSubscription;
+use balance_subscriptions::{init_balance_subscription_map, BalanceSubscriptionMap};
+use batched_withdrawals::{init_batched_withdrawal_map, BatchedWithdrawalMap};
+pub use batched_withdrawals::{BatchedWithdrawal, BatchedWithdrawalStatus};
+pub use call_usage::CallUsage;
+use call_usage::{init_call_usage_map, CallUsageMap};
+use ckbtc_wraps::{init_ckbtc_auto_wrap_set, init_ckbtc_wrap_map, CkbtcAutoWrapSet, CkbtcWrapMap};
+pub use ckbtc_wraps::{CkbtcWrap, CkbtcWrapStatus};
 use config::{init_stable_config, Config, StableConfig};
+pub use config::{
+    ApprovalPolicy, BatchingPolicy, Chain, ChangeSplitPolicy, CircuitBreaker, CycleThresholds,
+    DailyLimits, DepositScanPolicy, DerivationScheme, DestinationPolicy, FeeBounds, FiatLimits,
+    LedgerToken, Pause, RateLimits, ReconciliationPolicy, RunePolicy, RuneQuoteSource, SplitPolicy,
+    SweepPolicy, TreasuryMode,
+};
+use contacts::{init_contact_map, ContactMap};
+pub use contacts::{Contact, Contacts};
+pub use delegations::DelegationKey;
+use delegations::{init_delegation_map, DelegationMap};
+pub use deposit_addresses::DepositAddress;
+use deposit_addresses::{init_deposit_address_map, DepositAddressMap};
+pub use deposits::DepositRecord;
+use deposits::{init_deposit_map, DepositMap};
+use event_log::{init_event_log, init_principal_event_index, EventLog, PrincipalEventIndex};
+pub use event_log::{Event, EventKind};
+pub use external_addresses::ExternalAddress;
+use external_addresses::{init_external_address_map, ExternalAddressMap};
+use fee_escrows::{init_fee_escrow_map, FeeEscrowMap};
+pub use fee_escrows::{EscrowStatus, FeeEscrow};
+use fee_history::FeeHistory;
+pub use fee_history::{ConfirmationSample, FeeSample};
+pub use fee_quotes::FeeQuote;
+use fee_quotes::{init_fee_quote_map, FeeQuoteMap};
 use ic_stable_structures::{memory_manager::MemoryManager, DefaultMemoryImpl};
+use imported_addresses::{init_imported_address_map, ImportedAddressMap};
+pub use imported_addresses::{ImportedAddress, ImportedAddresses};
+use jars::{init_jar_map, JarMap};
+pub use jars::{Jar, Jars};
+pub use metrics::MetricCounters;
+use metrics::{init_metric_counters, StableMetricCounters};
+pub use multisig::PendingMultisig;
+use multisig::{init_pending_multisig_map, PendingMultisigMap};
+use outbox::{init_outbox_map, OutboxMap};
+pub use outbox::{Notification, OutboxEntry, OutboxStatus};
+use pending_change::{
+    init_pending_change_map, init_spent_input_map, PendingChangeMap, SpentInputMap,
+};
+pub use pending_change::{PendingChange, SpentInput};
+use prepared_withdrawals::{init_prepared_withdrawal_map, PreparedWithdrawalMap};
+pub use prepared_withdrawals::{LockedUtxos, PreparedWithdrawal};
+use rune_ledger::{
+    init_ledger_balance_map, init_ledger_transfer_map, LedgerBalanceMap, LedgerTransferMap,
+};
+pub use rune_ledger::{LedgerBalances, LedgerTransfer};
+pub use rune_metadata::RuneMetadata;
+use rune_metadata::{init_rune_metadata_map, RuneMetadataMap};
+use snapshots::{
+    init_snapshot_balance_map, init_snapshot_delta_map, SnapshotBalanceMap, SnapshotDeltaMap,
+};
+pub use snapshots::{AddressBalance, SnapshotDelta};
+use split_withdrawals::{init_split_withdrawal_map, SplitWithdrawalMap};
+pub use split_withdrawals::{SplitPart, SplitWithdrawal, SplitWithdrawalStatus};
+pub use submission_queue::PendingSubmission;
+use submission_queue::{init_submission_queue, SubmissionQueue};
+pub use sync_cursors::SyncCursor;
+use sync_cursors::{init_sync_cursor_map, SyncCursorMap};
+use templates::{init_template_reservation_map, TemplateReservationMap};
+pub use templates::{ReservedSelection, TemplateReservation};
+use transaction_log::TransactionLog;
+pub use transaction_log::{
+    OutputRole, SummaryInput, SummaryOutput, TransactionKind, TransactionRecord, TransactionStatus,
+    TransactionSummary, MAX_MEMO_SIZE,
+};
+use treasury_credits::{init_treasury_credit_map, TreasuryCreditMap};
 pub use utxo_manager::RunicUtxo;
 use utxo_manager::UtxoManager;
+use webhooks::{init_webhook_queue, WebhookQueue};
+pub use webhooks::{WebhookDelivery, WithdrawalNotice};
+use withdrawal_allowances::{init_withdrawal_allowance_map, WithdrawalAllowanceMap};
+pub use withdrawal_allowances::{DailyUsage, WithdrawalAllowance};
+use withdrawal_proposals::{init_withdrawal_proposal_map, WithdrawalProposalMap};
+pub use withdrawal_proposals::{ProposalStatus, WithdrawalProposal};
 
+mod api_stats;
+mod balance_history;
+mod balance_subscriptions;
+mod batched_withdrawals;
+mod call_usage;
+mod ckbtc_wraps;
 mod config;
+mod contacts;
+mod delegations;
+mod deposit_addresses;
+mod deposits;
+mod event_log;
+mod external_addresses;
+mod fee_escrows;
+mod fee_history;
+mod fee_quotes;
+mod imported_addresses;
+mod jars;
 mod memory;
+mod metrics;
+mod multisig;
+mod outbox;
+mod pending_change;
+mod prepared_withdrawals;
+mod rune_ledger;
+mod rune_metadata;
+mod snapshots;
+mod split_withdrawals;
+mod submission_queue;
+mod sync_cursors;
+mod templates;
+mod transaction_log;
+mod treasury_credits;
 mod utxo_manager;
+mod webhooks;
+mod withdrawal_allowances;
+mod withdrawal_proposals;
 
 thread_local! {
     pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
     pub static CONFIG: RefCell<StableConfig> = RefCell::new(init_stable_config());
     pub static UTXO_MANAGER: RefCell<UtxoManager> = RefCell::default();
+    pub static TRANSACTION_LOG: RefCell<TransactionLog> = RefCell::default();
+    pub static API_STATS: RefCell<ApiStatsMap> = RefCell::new(init_api_stats_map());
+    pub static EVENT_LOG: RefCell<EventLog> = RefCell::new(init_event_log());
+    pub static PRINCIPAL_EVENTS: RefCell<PrincipalEventIndex> = RefCell::new(init_principal_event_index());
+    pub static FEE_HISTORY: RefCell<FeeHistory> = RefCell::default();
+    pub static PENDING_MULTISIG: RefCell<PendingMultisigMap> = RefCell::new(init_pending_multisig_map());
+    pub static DEPOSITS: RefCell<DepositMap> = RefCell::new(init_deposit_map());
+    pub static BATCHED_WITHDRAWALS: RefCell<BatchedWithdrawalMap> = RefCell::new(init_batched_withdrawal_map());
+    pub static PREPARED_WITHDRAWALS: RefCell<PreparedWithdrawalMap> = RefCell::new(init_prepared_withdrawal_map());
+    pub static IMPORTED_ADDRESSES: RefCell<ImportedAddressMap> = RefCell::new(init_imported_address_map());
+    pub static SYNC_CURSORS: RefCell<SyncCursorMap> = RefCell::new(init_sync_cursor_map());
+    pub static OUTBOX: RefCell<OutboxMap> = RefCell::new(init_outbox_map());
+    pub static JARS: RefCell<JarMap> = RefCell::new(init_jar_map());
+    pub static SNAPSHOT_BALANCES: RefCell<SnapshotBalanceMap> = RefCell::new(init_snapshot_balance_map());
+    pub static SNAPSHOT_DELTAS: RefCell<SnapshotDeltaMap> = RefCell::new(init_snapshot_delta_map());
+    pub static SPLIT_WITHDRAWALS: RefCell<SplitWithdrawalMap> = RefCell::new(init_split_withdrawal_map());
+    pub static CKBTC_WRAPS: RefCell<CkbtcWrapMap> = RefCell::new(init_ckbtc_wrap_map());
+    pub static CKBTC_AUTO_WRAP: RefCell<CkbtcAutoWrapSet> = RefCell::new(init_ckbtc_auto_wrap_set());
+    pub static FEE_QUOTES: RefCell<FeeQuoteMap> = RefCell::new(init_fee_quote_map());
+    pub static TEMPLATE_RESERVATIONS: RefCell<TemplateReservationMap> = RefCell::new(init_template_reservation_map());
+    pub static WITHDRAWAL_ALLOWANCES: RefCell<WithdrawalAllowanceMap> = RefCell::new(init_withdrawal_allowance_map());
+    pub static BALANCE_SUBSCRIPTIONS: RefCell<BalanceSubscriptionMap> = RefCell::new(init_balance_subscription_map());
+    pub static CALL_USAGE: RefCell<CallUsageMap> = RefCell::new(init_call_usage_map());
+    pub static WITHDRAWAL_PROPOSALS: RefCell<WithdrawalProposalMap> = RefCell::new(init_withdrawal_proposal_map());
+    pub static DEPOSIT_ADDRESSES: RefCell<DepositAddressMap> = RefCell::new(init_deposit_address_map());
+    pub static METRIC_COUNTERS: RefCell<StableMetricCounters> = RefCell::new(init_metric_counters());
+    pub static LEDGER_BALANCES: RefCell<LedgerBalanceMap> = RefCell::new(init_ledger_balance_map());
+    pub static LEDGER_TRANSFERS: RefCell<LedgerTransferMap> = RefCell::new(init_ledger_transfer_map());
+    pub static RUNE_METADATA: RefCell<RuneMetadataMap> = RefCell::new(init_rune_metadata_map());
+    pub static WEBHOOK_QUEUE: RefCell<WebhookQueue> = RefCell::new(init_webhook_queue());
+    pub static PENDING_CHANGE: RefCell<PendingChangeMap> = RefCell::new(init_pending_change_map());
+    pub static SPENT_INPUTS: RefCell<SpentInputMap> = RefCell::new(init_spent_input_map());
+    pub static CONTACTS: RefCell<ContactMap> = RefCell::new(init_contact_map());
+    pub static BALANCE_HISTORY: RefCell<BalanceHistoryMap> = RefCell::new(init_balance_history_map());
+    pub static LATEST_BALANCE_SAMPLES: RefCell<LatestSampleMap> = RefCell::new(init_latest_sample_map());
+    pub static SUBMISSION_QUEUE: RefCell<SubmissionQueue> = RefCell::new(init_submission_queue());
+    pub static TREASURY_CREDITS: RefCell<TreasuryCreditMap> = RefCell::new(init_treasury_credit_map());
+    pub static DELEGATIONS: RefCell<DelegationMap> = RefCell::new(init_delegation_map());
+    pub static EXTERNAL_ADDRESSES: RefCell<ExternalAddressMap> = RefCell::new(init_external_address_map());
+    pub static FEE_ESCROWS: RefCell<FeeEscrowMap> = RefCell::new(init_fee_escrow_map());
 }
 
 pub fn read_memory_manager<F, R>(f: F) -> R
@@ -49,3 +208,589 @@ where
 {
     UTXO_MANAGER.with_borrow_mut(|manager| f(manager))
 }
+
+pub fn read_transaction_log<F, R>(f: F) -> R
+where
+    F: FnOnce(&TransactionLog) -> R,
+{
+    TRANSACTION_LOG.with_borrow(|log| f(log))
+}
+
+pub fn write_transaction_log<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut TransactionLog) -> R,
+{
+    TRANSACTION_LOG.with_borrow_mut(|log| f(log))
+}
+
+pub fn read_api_stats<F, R>(f: F) -> R
+where
+    F: FnOnce(&ApiStatsMap) -> R,
+{
+    API_STATS.with_borrow(|stats| f(stats))
+}
+
+pub fn write_api_stats<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ApiStatsMap) -> R,
+{
+    API_STATS.with_borrow_mut(|stats| f(stats))
+}
+
+pub fn read_event_log<F, R>(f: F) -> R
+where
+    F: FnOnce(&EventLog) -> R,
+{
+    EVENT_LOG.with_borrow(|log| f(log))
+}
+
+pub fn record_event(kind: EventKind) {
+    EVENT_LOG.with_borrow_mut(|log| {
+        log.append(&Event::new(kind, None))
+            .expect("failed to append to event log");
+    })
+}
+
+// also indexed under the principal for `read_principal_events`
+pub fn record_event_for(principal: Principal, kind: EventKind) {
+    record_event_shared(principal, &[], kind)
+}
+
+// about `principal`, and shows up in the activity of the `others` as well
+pub fn record_event_shared(principal: Principal, others: &[Principal], kind: EventKind) {
+    let index = EVENT_LOG.with_borrow_mut(|log| {
+        log.append(&Event::new(kind, Some(principal)))
+            .expect("failed to append to event log")
+    });
+    PRINCIPAL_EVENTS.with_borrow_mut(|events| {
+        for principal in std::iter::once(&principal).chain(others) {
+            events.insert((*principal, index), ());
+        }
+    });
+}
+
+// events about the principal in the order they were recorded
+pub fn read_principal_events(principal: Principal, offset: u64, limit: u64) -> Vec<Event> {
+    let indices: Vec<u64> = PRINCIPAL_EVENTS.with_borrow(|events| {
+        events
+            .range((principal, 0)..=(principal, u64::MAX))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|((_, index), _)| index)
+            .collect()
+    });
+    EVENT_LOG.with_borrow(|log| {
+        indices
+            .into_iter()
+            .filter_map(|index| log.get(index))
+            .collect()
+    })
+}
+
+pub fn read_fee_history<F, R>(f: F) -> R
+where
+    F: FnOnce(&FeeHistory) -> R,
+{
+    FEE_HISTORY.with_borrow(|history| f(history))
+}
+
+pub fn write_fee_history<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut FeeHistory) -> R,
+{
+    FEE_HISTORY.with_borrow_mut(|history| f(history))
+}
+
+pub fn read_pending_multisig<F, R>(f: F) -> R
+where
+    F: FnOnce(&PendingMultisigMap) -> R,
+{
+    PENDING_MULTISIG.with_borrow(|pending| f(pending))
+}
+
+pub fn write_pending_multisig<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut PendingMultisigMap) -> R,
+{
+    PENDING_MULTISIG.with_borrow_mut(|pending| f(pending))
+}
+
+pub fn read_deposits<F, R>(f: F) -> R
+where
+    F: FnOnce(&DepositMap) -> R,
+{
+    DEPOSITS.with_borrow(|deposits| f(deposits))
+}
+
+pub fn write_deposits<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut DepositMap) -> R,
+{
+    DEPOSITS.with_borrow_mut(|deposits| f(deposits))
+}
+
+pub fn read_batched_withdrawals<F, R>(f: F) -> R
+where
+    F: FnOnce(&BatchedWithdrawalMap) -> R,
+{
+    BATCHED_WITHDRAWALS.with_borrow(|withdrawals| f(withdrawals))
+}
+
+pub fn write_batched_withdrawals<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut BatchedWithdrawalMap) -> R,
+{
+    BATCHED_WITHDRAWALS.with_borrow_mut(|withdrawals| f(withdrawals))
+}
+
+pub fn read_prepared_withdrawals<F, R>(f: F) -> R
+where
+    F: FnOnce(&PreparedWithdrawalMap) -> R,
+{
+    PREPARED_WITHDRAWALS.with_borrow(|prepared| f(prepared))
+}
+
+pub fn write_prepared_withdrawals<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut PreparedWithdrawalMap) -> R,
+{
+    PREPARED_WITHDRAWALS.with_borrow_mut(|prepared| f(prepared))
+}
+
+pub fn read_imported_addresses<F, R>(f: F) -> R
+where
+    F: FnOnce(&ImportedAddressMap) -> R,
+{
+    IMPORTED_ADDRESSES.with_borrow(|addresses| f(addresses))
+}
+
+pub fn write_imported_addresses<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ImportedAddressMap) -> R,
+{
+    IMPORTED_ADDRESSES.with_borrow_mut(|addresses| f(addresses))
+}
+
+pub fn read_sync_cursors<F, R>(f: F) -> R
+where
+    F: FnOnce(&SyncCursorMap) -> R,
+{
+    SYNC_CURSORS.with_borrow(|cursors| f(cursors))
+}
+
+pub fn write_sync_cursors<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SyncCursorMap) -> R,
+{
+    SYNC_CURSORS.with_borrow_mut(|cursors| f(cursors))
+}
+
+pub fn read_outbox<F, R>(f: F) -> R
+where
+    F: FnOnce(&OutboxMap) -> R,
+{
+    OUTBOX.with_borrow(|outbox| f(outbox))
+}
+
+pub fn write_outbox<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut OutboxMap) -> R,
+{
+    OUTBOX.with_borrow_mut(|outbox| f(outbox))
+}
+
+pub fn read_template_reservations<F, R>(f: F) -> R
+where
+    F: FnOnce(&TemplateReservationMap) -> R,
+{
+    TEMPLATE_RESERVATIONS.with_borrow(|reservations| f(reservations))
+}
+
+pub fn write_template_reservations<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut TemplateReservationMap) -> R,
+{
+    TEMPLATE_RESERVATIONS.with_borrow_mut(|reservations| f(reservations))
+}
+
+pub fn read_jars<F, R>(f: F) -> R
+where
+    F: FnOnce(&JarMap) -> R,
+{
+    JARS.with_borrow(|jars| f(jars))
+}
+
+pub fn write_jars<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut JarMap) -> R,
+{
+    JARS.with_borrow_mut(|jars| f(jars))
+}
+
+pub fn read_snapshot_balances<F, R>(f: F) -> R
+where
+    F: FnOnce(&SnapshotBalanceMap) -> R,
+{
+    SNAPSHOT_BALANCES.with_borrow(|balances| f(balances))
+}
+
+pub fn write_snapshot_balances<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SnapshotBalanceMap) -> R,
+{
+    SNAPSHOT_BALANCES.with_borrow_mut(|balances| f(balances))
+}
+
+pub fn read_snapshot_deltas<F, R>(f: F) -> R
+where
+    F: FnOnce(&SnapshotDeltaMap) -> R,
+{
+    SNAPSHOT_DELTAS.with_borrow(|deltas| f(deltas))
+}
+
+pub fn write_snapshot_deltas<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SnapshotDeltaMap) -> R,
+{
+    SNAPSHOT_DELTAS.with_borrow_mut(|deltas| f(deltas))
+}
+
+pub fn read_split_withdrawals<F, R>(f: F) -> R
+where
+    F: FnOnce(&SplitWithdrawalMap) -> R,
+{
+    SPLIT_WITHDRAWALS.with_borrow(|withdrawals| f(withdrawals))
+}
+
+pub fn write_split_withdrawals<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SplitWithdrawalMap) -> R,
+{
+    SPLIT_WITHDRAWALS.with_borrow_mut(|withdrawals| f(withdrawals))
+}
+
+pub fn read_ckbtc_wraps<F, R>(f: F) -> R
+where
+    F: FnOnce(&CkbtcWrapMap) -> R,
+{
+    CKBTC_WRAPS.with_borrow(|wraps| f(wraps))
+}
+
+pub fn write_ckbtc_wraps<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut CkbtcWrapMap) -> R,
+{
+    CKBTC_WRAPS.with_borrow_mut(|wraps| f(wraps))
+}
+
+pub fn read_ckbtc_auto_wrap<F, R>(f: F) -> R
+where
+    F: FnOnce(&CkbtcAutoWrapSet) -> R,
+{
+    CKBTC_AUTO_WRAP.with_borrow(|principals| f(principals))
+}
+
+pub fn write_ckbtc_auto_wrap<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut CkbtcAutoWrapSet) -> R,
+{
+    CKBTC_AUTO_WRAP.with_borrow_mut(|principals| f(principals))
+}
+
+pub fn read_fee_quotes<F, R>(f: F) -> R
+where
+    F: FnOnce(&FeeQuoteMap) -> R,
+{
+    FEE_QUOTES.with_borrow(|quotes| f(quotes))
+}
+
+pub fn write_fee_quotes<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut FeeQuoteMap) -> R,
+{
+    FEE_QUOTES.with_borrow_mut(|quotes| f(quotes))
+}
+
+pub fn read_withdrawal_allowances<F, R>(f: F) -> R
+where
+    F: FnOnce(&WithdrawalAllowanceMap) -> R,
+{
+    WITHDRAWAL_ALLOWANCES.with_borrow(|allowances| f(allowances))
+}
+
+pub fn write_withdrawal_allowances<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut WithdrawalAllowanceMap) -> R,
+{
+    WITHDRAWAL_ALLOWANCES.with_borrow_mut(|allowances| f(allowances))
+}
+
+pub fn read_balance_subscriptions<F, R>(f: F) -> R
+where
+    F: FnOnce(&BalanceSubscriptionMap) -> R,
+{
+    BALANCE_SUBSCRIPTIONS.with_borrow(|subscriptions| f(subscriptions))
+}
+
+pub fn write_balance_subscriptions<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut BalanceSubscriptionMap) -> R,
+{
+    BALANCE_SUBSCRIPTIONS.with_borrow_mut(|subscriptions| f(subscriptions))
+}
+
+pub fn read_call_usage<F, R>(f: F) -> R
+where
+    F: FnOnce(&CallUsageMap) -> R,
+{
+    CALL_USAGE.with_borrow(|usage| f(usage))
+}
+
+pub fn write_call_usage<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut CallUsageMap) -> R,
+{
+    CALL_USAGE.with_borrow_mut(|usage| f(usage))
+}
+
+pub fn read_withdrawal_proposals<F, R>(f: F) -> R
+where
+    F: FnOnce(&WithdrawalProposalMap) -> R,
+{
+    WITHDRAWAL_PROPOSALS.with_borrow(|proposals| f(proposals))
+}
+
+pub fn write_withdrawal_proposals<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut WithdrawalProposalMap) -> R,
+{
+    WITHDRAWAL_PROPOSALS.with_borrow_mut(|proposals| f(proposals))
+}
+
+pub fn read_deposit_addresses<F, R>(f: F) -> R
+where
+    F: FnOnce(&DepositAddressMap) -> R,
+{
+    DEPOSIT_ADDRESSES.with_borrow(|addresses| f(addresses))
+}
+
+pub fn write_deposit_addresses<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut DepositAddressMap) -> R,
+{
+    DEPOSIT_ADDRESSES.with_borrow_mut(|addresses| f(addresses))
+}
+
+pub fn read_metric_counters<F, R>(f: F) -> R
+where
+    F: FnOnce(&MetricCounters) -> R,
+{
+    METRIC_COUNTERS.with_borrow(|counters| f(counters.get()))
+}
+
+pub fn write_metric_counters<F>(f: F)
+where
+    F: FnOnce(&mut MetricCounters),
+{
+    METRIC_COUNTERS.with_borrow_mut(|counters| {
+        let mut temp = counters.get().clone();
+        f(&mut temp);
+        let _ = counters.set(temp);
+    })
+}
+
+pub fn read_ledger_balances<F, R>(f: F) -> R
+where
+    F: FnOnce(&LedgerBalanceMap) -> R,
+{
+    LEDGER_BALANCES.with_borrow(|balances| f(balances))
+}
+
+pub fn write_ledger_balances<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut LedgerBalanceMap) -> R,
+{
+    LEDGER_BALANCES.with_borrow_mut(|balances| f(balances))
+}
+
+pub fn read_ledger_transfers<F, R>(f: F) -> R
+where
+    F: FnOnce(&LedgerTransferMap) -> R,
+{
+    LEDGER_TRANSFERS.with_borrow(|transfers| f(transfers))
+}
+
+pub fn write_ledger_transfers<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut LedgerTransferMap) -> R,
+{
+    LEDGER_TRANSFERS.with_borrow_mut(|transfers| f(transfers))
+}
+
+pub fn read_rune_metadata<F, R>(f: F) -> R
+where
+    F: FnOnce(&RuneMetadataMap) -> R,
+{
+    RUNE_METADATA.with_borrow(|metadata| f(metadata))
+}
+
+pub fn write_rune_metadata<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut RuneMetadataMap) -> R,
+{
+    RUNE_METADATA.with_borrow_mut(|metadata| f(metadata))
+}
+
+pub fn read_webhook_queue<F, R>(f: F) -> R
+where
+    F: FnOnce(&WebhookQueue) -> R,
+{
+    WEBHOOK_QUEUE.with_borrow(|queue| f(queue))
+}
+
+pub fn write_webhook_queue<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut WebhookQueue) -> R,
+{
+    WEBHOOK_QUEUE.with_borrow_mut(|queue| f(queue))
+}
+
+pub fn read_pending_change<F, R>(f: F) -> R
+where
+    F: FnOnce(&PendingChangeMap) -> R,
+{
+    PENDING_CHANGE.with_borrow(|pending| f(pending))
+}
+
+pub fn write_pending_change<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut PendingChangeMap) -> R,
+{
+    PENDING_CHANGE.with_borrow_mut(|pending| f(pending))
+}
+
+pub fn read_spent_inputs<F, R>(f: F) -> R
+where
+    F: FnOnce(&SpentInputMap) -> R,
+{
+    SPENT_INPUTS.with_borrow(|inputs| f(inputs))
+}
+
+pub fn write_spent_inputs<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SpentInputMap) -> R,
+{
+    SPENT_INPUTS.with_borrow_mut(|inputs| f(inputs))
+}
+
+pub fn read_contacts<F, R>(f: F) -> R
+where
+    F: FnOnce(&ContactMap) -> R,
+{
+    CONTACTS.with_borrow(|contacts| f(contacts))
+}
+
+pub fn write_contacts<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ContactMap) -> R,
+{
+    CONTACTS.with_borrow_mut(|contacts| f(contacts))
+}
+
+pub fn read_submission_queue<F, R>(f: F) -> R
+where
+    F: FnOnce(&SubmissionQueue) -> R,
+{
+    SUBMISSION_QUEUE.with_borrow(|queue| f(queue))
+}
+
+pub fn write_submission_queue<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SubmissionQueue) -> R,
+{
+    SUBMISSION_QUEUE.with_borrow_mut(|queue| f(queue))
+}
+
+pub fn read_balance_history<F, R>(f: F) -> R
+where
+    F: FnOnce(&BalanceHistoryMap) -> R,
+{
+    BALANCE_HISTORY.with_borrow(|history| f(history))
+}
+
+pub fn write_balance_history<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut BalanceHistoryMap) -> R,
+{
+    BALANCE_HISTORY.with_borrow_mut(|history| f(history))
+}
+
+pub fn read_latest_balance_samples<F, R>(f: F) -> R
+where
+    F: FnOnce(&LatestSampleMap) -> R,
+{
+    LATEST_BALANCE_SAMPLES.with_borrow(|samples| f(samples))
+}
+
+pub fn write_latest_balance_samples<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut LatestSampleMap) -> R,
+{
+    LATEST_BALANCE_SAMPLES.with_borrow_mut(|samples| f(samples))
+}
+
+pub fn read_treasury_credits<F, R>(f: F) -> R
+where
+    F: FnOnce(&TreasuryCreditMap) -> R,
+{
+    TREASURY_CREDITS.with_borrow(|credits| f(credits))
+}
+
+pub fn write_treasury_credits<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut TreasuryCreditMap) -> R,
+{
+    TREASURY_CREDITS.with_borrow_mut(|credits| f(credits))
+}
+
+pub fn read_delegations<F, R>(f: F) -> R
+where
+    F: FnOnce(&DelegationMap) -> R,
+{
+    DELEGATIONS.with_borrow(|delegations| f(delegations))
+}
+
+pub fn write_delegations<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut DelegationMap) -> R,
+{
+    DELEGATIONS.with_borrow_mut(|delegations| f(delegations))
+}
+
+pub fn read_external_addresses<F, R>(f: F) -> R
+where
+    F: FnOnce(&ExternalAddressMap) -> R,
+{
+    EXTERNAL_ADDRESSES.with_borrow(|addresses| f(addresses))
+}
+
+pub fn write_external_addresses<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ExternalAddressMap) -> R,
+{
+    EXTERNAL_ADDRESSES.with_borrow_mut(|addresses| f(addresses))
+}
+
+pub fn read_fee_escrows<F, R>(f: F) -> R
+where
+    F: FnOnce(&FeeEscrowMap) -> R,
+{
+    FEE_ESCROWS.with_borrow(|escrows| f(escrows))
+}
+
+pub fn write_fee_escrows<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut FeeEscrowMap) -> R,
+{
+    FEE_ESCROWS.with_borrow_mut(|escrows| f(escrows))
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct EndpointStats {
+    pub calls: u64,
+    pub errors: BTreeMap<String, u64>,
+    pub total_instructions: u128,
+    pub last_call: u64,
+}
+
+impl Storable for EndpointStats {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type ApiStatsMap = StableBTreeMap<String, EndpointStats, Memory>;
+
+pub fn init_api_stats_map() -> ApiStatsMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::ApiStats.into());
+        ApiStatsMap::init(memory)
+    })
+}
@@ -0,0 +1,86 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// candid encoded keys stay well below this for the longest bech32 address
+const MAX_KEY_SIZE: u32 = 256;
+
+// (address, taken_at), so the samples of an address sit next to each other in time order
+#[derive(CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct HistoryKey {
+    pub address: String,
+    pub taken_at: u64,
+}
+
+impl Storable for HistoryKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_KEY_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+// holdings of an address from `taken_at` until its next sample, runes sorted by id
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BalanceSample {
+    pub taken_at: u64,
+    pub height: u32,
+    pub bitcoin: u64,
+    pub runes: Vec<(RuneId, u128)>,
+}
+
+impl BalanceSample {
+    pub fn same_holdings(&self, other: &BalanceSample) -> bool {
+        self.bitcoin == other.bitcoin && self.runes == other.runes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bitcoin == 0 && self.runes.is_empty()
+    }
+}
+
+impl Storable for BalanceSample {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// a sample is only written when the holdings changed since the previous one
+pub type BalanceHistoryMap = StableBTreeMap<HistoryKey, BalanceSample, Memory>;
+
+// keyed by address, the latest sample of every address still holding something
+pub type LatestSampleMap = StableBTreeMap<String, BalanceSample, Memory>;
+
+pub fn init_balance_history_map() -> BalanceHistoryMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::BalanceHistory.into());
+        BalanceHistoryMap::init(memory)
+    })
+}
+
+pub fn init_latest_sample_map() -> LatestSampleMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::LatestBalanceSamples.into());
+        LatestSampleMap::init(memory)
+    })
+}
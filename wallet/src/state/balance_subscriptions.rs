@@ -0,0 +1,37 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// canisters told about deposits to an address of `principal`
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BalanceSubscription {
+    pub principal: Principal,
+    pub callbacks: Vec<Principal>,
+}
+
+impl Storable for BalanceSubscription {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by the tracked address, so syncs not triggered by the owner find it as well
+pub type BalanceSubscriptionMap = StableBTreeMap<String, BalanceSubscription, Memory>;
+
+pub fn init_balance_subscription_map() -> BalanceSubscriptionMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::BalanceSubscriptions.into());
+        BalanceSubscriptionMap::init(memory)
+    })
+}
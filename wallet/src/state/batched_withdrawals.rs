@@ -0,0 +1,54 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum BatchedWithdrawalStatus {
+    Queued,
+    // `vout` is the output of the batch transaction paying this request
+    Broadcast { txid: String, vout: u32 },
+    Failed { reason: String },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BatchedWithdrawal {
+    pub id: u64,
+    pub caller: Principal,
+    pub to: String,
+    pub amount: u64,
+    pub queued_at: u64,
+    pub status: BatchedWithdrawalStatus,
+}
+
+impl BatchedWithdrawal {
+    pub fn is_queued(&self) -> bool {
+        matches!(self.status, BatchedWithdrawalStatus::Queued)
+    }
+}
+
+impl Storable for BatchedWithdrawal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by request id, ids are handed out in queueing order
+pub type BatchedWithdrawalMap = StableBTreeMap<u64, BatchedWithdrawal, Memory>;
+
+pub fn init_batched_withdrawal_map() -> BatchedWithdrawalMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::BatchedWithdrawals.into());
+        BatchedWithdrawalMap::init(memory)
+    })
+}
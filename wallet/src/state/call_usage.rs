@@ -0,0 +1,41 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// metered calls of a principal within the current window and since it was first seen
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct CallUsage {
+    pub window_start: u64,
+    pub calls: u64,
+    pub cycles: u128,
+    pub total_calls: u64,
+    pub total_cycles: u128,
+    // cycles attached to calls past the quota
+    pub cycles_paid: u128,
+}
+
+impl Storable for CallUsage {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type CallUsageMap = StableBTreeMap<Principal, CallUsage, Memory>;
+
+pub fn init_call_usage_map() -> CallUsageMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::CallUsage.into());
+        CallUsageMap::init(memory)
+    })
+}
@@ -0,0 +1,71 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::FeePayer;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum CkbtcWrapStatus {
+    // waiting for the next minting round to send the bitcoin to the minter
+    Requested,
+    // sent to the minter's deposit address, minted once the minter sees it confirmed
+    Submitted {
+        txid: String,
+    },
+    Minted {
+        txid: String,
+        minted: u64,
+        block_index: u64,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+// bitcoin of the principal moved to the ckbtc minter and minted to its icrc-1 account
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CkbtcWrap {
+    pub id: u64,
+    pub principal: Principal,
+    pub amount: u64,
+    pub fee_payer: FeePayer,
+    pub created_at: u64,
+    pub status: CkbtcWrapStatus,
+}
+
+impl Storable for CkbtcWrap {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by wrap id, ids are handed out in request order
+pub type CkbtcWrapMap = StableBTreeMap<u64, CkbtcWrap, Memory>;
+
+// principals whose confirmed bitcoin deposits get wrapped into ckbtc
+pub type CkbtcAutoWrapSet = StableBTreeMap<Principal, (), Memory>;
+
+pub fn init_ckbtc_wrap_map() -> CkbtcWrapMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::CkbtcWraps.into());
+        CkbtcWrapMap::init(memory)
+    })
+}
+
+pub fn init_ckbtc_auto_wrap_set() -> CkbtcAutoWrapSet {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::CkbtcAutoWrap.into());
+        CkbtcAutoWrapSet::init(memory)
+    })
+}
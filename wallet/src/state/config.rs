@@ -1,22 +1,329 @@
 use crate::EcdsaPublicKey;
-use candid::{CandidType, Decode, Encode};
+use candid::{CandidType, Decode, Encode, Principal};
 use ic_cdk::api::management_canister::{
     bitcoin::BitcoinNetwork,
     ecdsa::{EcdsaCurve, EcdsaKeyId},
+    schnorr::{SchnorrAlgorithm, SchnorrKeyId},
 };
 use ic_stable_structures::{storable::Bound, StableCell, Storable};
 use serde::Deserialize;
 
+use crate::{
+    ord_canister::{self, OrdBackend},
+    types::RuneId,
+};
+
 use super::{
     memory::{Memory, MemoryIds},
     read_memory_manager,
 };
 
+const CKBTC_MINTER: &str = "mqygn-kiaaa-aaaar-qaadq-cai";
+
+const CKTESTBTC_MINTER: &str = "ml52i-qqaaa-aaaar-qaaba-cai";
+
+// moves the hot wallet's bitcoin above the ceiling to the vault on a schedule
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SweepPolicy {
+    pub vault_address: String,
+    pub hot_wallet_ceiling: u64,
+    pub max_per_sweep: u64,
+    pub interval_mins: u64,
+    pub enabled: bool,
+}
+
+// runes the operator supports, the allow list is ignored while unset
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct RunePolicy {
+    pub allow_list: Option<Vec<RuneId>>,
+    pub deny_list: Vec<RuneId>,
+}
+
+impl RunePolicy {
+    pub fn is_supported(&self, runeid: &RuneId) -> bool {
+        if self.deny_list.contains(runeid) {
+            return false;
+        }
+        match self.allow_list {
+            Some(ref allow_list) => allow_list.contains(runeid),
+            None => true,
+        }
+    }
+}
+
+// addresses withdrawals may pay out to, the allow list is ignored while unset.
+// entries are kept in the canonical form of the parsed address
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct DestinationPolicy {
+    pub allow_list: Option<Vec<String>>,
+    pub deny_list: Vec<String>,
+}
+
+impl DestinationPolicy {
+    pub fn is_allowed(&self, address: &str) -> bool {
+        if self.deny_list.iter().any(|denied| denied == address) {
+            return false;
+        }
+        match self.allow_list {
+            Some(ref allow_list) => allow_list.iter().any(|allowed| allowed == address),
+            None => true,
+        }
+    }
+}
+
+// what a principal may withdraw per utc day, unset or unlisted means unlimited
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct DailyLimits {
+    pub sats: Option<u64>,
+    pub runes: Vec<(RuneId, u128)>,
+}
+
+// periodic comparison of the utxo manager's totals with the chain
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReconciliationPolicy {
+    pub interval_mins: u64,
+    // drift in sats above which an address counts as out of sync
+    pub drift_threshold: u64,
+    pub auto_resync: bool,
+    pub enabled: bool,
+}
+
+// opt-in withdrawals are queued and paid out together, a batch is flushed once the
+// window since its first request elapses or it holds `max_requests` requests
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BatchingPolicy {
+    pub window_secs: u64,
+    pub max_requests: u64,
+}
+
+// bitcoin withdrawals above `threshold` are paid out in parts of at most
+// `max_part` sats, one part every `interval_secs`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SplitPolicy {
+    pub threshold: u64,
+    pub max_part: u64,
+    pub interval_secs: u64,
+}
+
+// change above `threshold` sats comes back as `outputs` equal coins
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ChangeSplitPolicy {
+    pub threshold: u64,
+    pub outputs: u32,
+}
+
+// withdrawal limits in usd cents, converted with the oracle's rate at call time
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FiatLimits {
+    pub max_bitcoin_withdrawal_cents: Option<u64>,
+    pub max_rune_withdrawal_cents: Option<u64>,
+}
+
+// rune quoted by the exchange rate canister under `symbol`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RuneQuoteSource {
+    pub runeid: RuneId,
+    pub symbol: String,
+    pub divisibility: u8,
+}
+
+/*
+ * quotas per principal and window on the endpoints whose bitcoin api calls the
+ * canister pays for. a call past a quota goes through when it carries
+ * `price_per_call` cycles, without a price it gets rejected
+*/
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RateLimits {
+    pub window_secs: u64,
+    pub max_calls: u64,
+    pub max_cycles: u128,
+    pub price_per_call: Option<u128>,
+}
+
+/*
+ * bitcoin withdrawals above `threshold` sats only leave through a proposal that
+ * `quorum` of the `approvers` signed off on before `expiry_secs` passed
+*/
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApprovalPolicy {
+    pub approvers: Vec<Principal>,
+    pub quorum: u32,
+    pub threshold: u64,
+    pub expiry_secs: u64,
+}
+
+// periodic sync of every registered deposit address, so deposits show up unprompted
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DepositScanPolicy {
+    pub interval_mins: u64,
+    pub enabled: bool,
+}
+
+/*
+ * sweeps confirmed deposits into the deposit pool and credits their owners on an
+ * internal ledger, withdrawals are then paid out of the pool. deposits below
+ * `min_amount` sats stay on the deposit address until more arrive
+*/
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TreasuryMode {
+    pub interval_mins: u64,
+    pub min_amount: u64,
+    pub enabled: bool,
+}
+
+/*
+ * chain the canister runs against, finer than the bitcoin canister's network. the
+ * ic's testnet follows testnet4 and signet has no bitcoin canister of its own, it's
+ * served by one in regtest mode fed by a signet node
+*/
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    Mainnet,
+    Testnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl Chain {
+    pub fn bitcoin_network(&self) -> BitcoinNetwork {
+        match self {
+            Chain::Mainnet => BitcoinNetwork::Mainnet,
+            Chain::Testnet | Chain::Testnet4 => BitcoinNetwork::Testnet,
+            Chain::Signet | Chain::Regtest => BitcoinNetwork::Regtest,
+        }
+    }
+
+    // threshold key the chain signs with, local replicas only have the dfx key
+    pub fn keyname(&self) -> String {
+        match self {
+            Chain::Mainnet => String::from("key_1"),
+            Chain::Testnet | Chain::Testnet4 => String::from("test_key_1"),
+            Chain::Signet | Chain::Regtest => String::from("dfx_test_key"),
+        }
+    }
+
+    /*
+     * millisatoshis per vbyte while the bitcoin canister has no fee percentiles, which
+     * happens on chains with hardly any traffic. the test chains get the minimum relay
+     * fee, regtest keeps 2 sat/vbyte
+     */
+    pub fn fallback_fee_per_vbyte(&self) -> u64 {
+        match self {
+            Chain::Mainnet => 5_000,
+            Chain::Testnet | Chain::Testnet4 | Chain::Signet => 1_000,
+            Chain::Regtest => 2_000,
+        }
+    }
+}
+
+impl From<BitcoinNetwork> for Chain {
+    fn from(network: BitcoinNetwork) -> Self {
+        match network {
+            BitcoinNetwork::Mainnet => Chain::Mainnet,
+            BitcoinNetwork::Testnet => Chain::Testnet,
+            BitcoinNetwork::Regtest => Chain::Regtest,
+        }
+    }
+}
+
+/*
+ * where account keys sit under the canister's master key. `Legacy` derives from the
+ * owner and subaccount bytes, `Bip44` from the purpose, coin, account, change and
+ * index levels so standard wallets find the addresses from the master public key
+*/
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DerivationScheme {
+    #[default]
+    Legacy,
+    Bip44,
+}
+
+// set while the canister refuses to move funds
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Pause {
+    pub reason: String,
+    pub since: u64,
+    // set by the circuit breaker rather than a controller
+    pub tripped: bool,
+}
+
+// pauses the canister once more than `max_failures` submissions fail within `window_secs`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CircuitBreaker {
+    pub window_secs: u64,
+    pub max_failures: u32,
+}
+
+// cycle balances alerted on, below `critical` the canister stops discretionary work
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CycleThresholds {
+    pub low: u128,
+    pub critical: u128,
+}
+
+// limits of the default fee rate and the suggested tiers, in millisatoshi per vbyte
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FeeBounds {
+    pub floor: u64,
+    pub ceiling: u64,
+}
+
+// rune held on the canister's internal ledger, described as an icrc-1 token
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LedgerToken {
+    pub runeid: RuneId,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
 #[derive(CandidType, Deserialize, Default, Clone)]
 pub struct Config {
     pub bitcoin_network: Option<BitcoinNetwork>,
     pub keyname: Option<String>,
     pub ecdsa_public_key: Option<EcdsaPublicKey>,
+    pub paper_trading: Option<bool>,
+    pub anchor_output_value: Option<u64>,
+    pub fee_sampling_interval_mins: Option<u64>,
+    pub sweep_policy: Option<SweepPolicy>,
+    pub rune_policy: Option<RunePolicy>,
+    pub reconciliation_policy: Option<ReconciliationPolicy>,
+    pub batching_policy: Option<BatchingPolicy>,
+    pub ord_backends: Option<Vec<OrdBackend>>,
+    // canisters receiving deposit and confirmation notifications
+    pub notification_subscribers: Option<Vec<Principal>>,
+    // bip340 master key behind the taproot addresses, same shape as the ecdsa one
+    pub schnorr_public_key: Option<EcdsaPublicKey>,
+    pub split_policy: Option<SplitPolicy>,
+    // falls back to the network's ckbtc minter while unset
+    pub ckbtc_minter: Option<Principal>,
+    pub fiat_limits: Option<FiatLimits>,
+    pub rune_quote_sources: Option<Vec<RuneQuoteSource>>,
+    pub destination_policy: Option<DestinationPolicy>,
+    // applies to every principal without limits of its own
+    pub daily_limits: Option<DailyLimits>,
+    pub rate_limits: Option<RateLimits>,
+    pub approval_policy: Option<ApprovalPolicy>,
+    pub deposit_scan_policy: Option<DepositScanPolicy>,
+    // runes users can move between each other without touching bitcoin
+    pub rune_ledger_tokens: Option<Vec<LedgerToken>>,
+    // picked at init, unset on canisters from before bip44 support
+    pub derivation_scheme: Option<DerivationScheme>,
+    pub change_split_policy: Option<ChangeSplitPolicy>,
+    // unset on canisters from before testnet4 and signet support
+    pub chain: Option<Chain>,
+    pub pause: Option<Pause>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    // https endpoint posted to for every submitted withdrawal
+    pub webhook_url: Option<String>,
+    pub treasury_mode: Option<TreasuryMode>,
+    // `*_of` balance endpoints only serve an address to its owner
+    pub owner_gated_balances: Option<bool>,
+    pub cycle_thresholds: Option<CycleThresholds>,
+    pub fee_bounds: Option<FeeBounds>,
+    // canister converting runes into the bitcoin a withdrawal's fee is short of
+    pub fee_sponsor: Option<Principal>,
 }
 
 impl Storable for Config {
@@ -40,6 +347,11 @@ impl Config {
         }
     }
 
+    pub fn chain(&self) -> Chain {
+        self.chain
+            .unwrap_or_else(|| Chain::from(self.bitcoin_network()))
+    }
+
     pub fn keyname(&self) -> String {
         if let Some(ref keyname) = self.keyname {
             keyname.clone()
@@ -56,6 +368,50 @@ impl Config {
         }
     }
 
+    pub fn schnorr_public_key(&self) -> EcdsaPublicKey {
+        if let Some(ref schnorr_key) = self.schnorr_public_key {
+            schnorr_key.clone()
+        } else {
+            ic_cdk::trap("canister's schnorr key uninitialized")
+        }
+    }
+
+    pub fn is_paper_trading(&self) -> bool {
+        self.paper_trading.unwrap_or_default()
+    }
+
+    pub fn fee_sampling_interval_mins(&self) -> u64 {
+        self.fee_sampling_interval_mins.unwrap_or(10)
+    }
+
+    pub fn derivation_scheme(&self) -> DerivationScheme {
+        self.derivation_scheme.unwrap_or_default()
+    }
+
+    pub fn rune_policy(&self) -> RunePolicy {
+        self.rune_policy.clone().unwrap_or_default()
+    }
+
+    pub fn destination_policy(&self) -> DestinationPolicy {
+        self.destination_policy.clone().unwrap_or_default()
+    }
+
+    pub fn ord_backends(&self) -> Vec<OrdBackend> {
+        self.ord_backends
+            .clone()
+            .unwrap_or_else(ord_canister::default_backends)
+    }
+
+    pub fn ckbtc_minter(&self) -> Option<Principal> {
+        let minter = match self.bitcoin_network() {
+            BitcoinNetwork::Mainnet => CKBTC_MINTER,
+            BitcoinNetwork::Testnet => CKTESTBTC_MINTER,
+            BitcoinNetwork::Regtest => return self.ckbtc_minter,
+        };
+        self.ckbtc_minter
+            .or_else(|| Some(Principal::from_text(minter).unwrap()))
+    }
+
     pub fn ecdsakeyid(&self) -> EcdsaKeyId {
         let name = self.keyname();
         EcdsaKeyId {
@@ -63,6 +419,14 @@ impl Config {
             curve: EcdsaCurve::Secp256k1,
         }
     }
+
+    pub fn schnorrkeyid(&self) -> SchnorrKeyId {
+        let name = self.keyname();
+        SchnorrKeyId {
+            name,
+            algorithm: SchnorrAlgorithm::Bip340secp256k1,
+        }
+    }
 }
 
 pub type StableConfig = StableCell<Config, Memory>;
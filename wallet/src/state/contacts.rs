@@ -0,0 +1,46 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// recurring payee of a principal, withdrawals name it instead of repeating the address
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Contact {
+    pub label: String,
+    pub address: String,
+    pub added_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct Contacts(pub Vec<Contact>);
+
+impl Contacts {
+    pub fn get(&self, label: &str) -> Option<&Contact> {
+        self.0.iter().find(|contact| contact.label == label)
+    }
+}
+
+impl Storable for Contacts {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type ContactMap = StableBTreeMap<Principal, Contacts, Memory>;
+
+pub fn init_contact_map() -> ContactMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Contacts.into());
+        ContactMap::init(memory)
+    })
+}
@@ -0,0 +1,46 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::TokenType;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// two principals and the longest rune id stay well below this once candid encoded
+const MAX_KEY_SIZE: u32 = 256;
+
+// (owner, spender, token), so everything an owner approved sits together
+#[derive(CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct DelegationKey {
+    pub owner: Principal,
+    pub spender: Principal,
+    pub token: TokenType,
+}
+
+impl Storable for DelegationKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_KEY_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+// what's left of an allowance, entries spent down to zero are removed
+pub type DelegationMap = StableBTreeMap<DelegationKey, u128, Memory>;
+
+pub fn init_delegation_map() -> DelegationMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Delegations.into());
+        DelegationMap::init(memory)
+    })
+}
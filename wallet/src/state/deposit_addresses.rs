@@ -0,0 +1,37 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// a deposit address handed out by `get_deposit_addresses`
+#[derive(CandidType, Deserialize, Clone)]
+pub struct DepositAddress {
+    pub principal: Principal,
+    pub registered_at: u64,
+}
+
+impl Storable for DepositAddress {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by the address, the background scan walks it in order
+pub type DepositAddressMap = StableBTreeMap<String, DepositAddress, Memory>;
+
+pub fn init_deposit_address_map() -> DepositAddressMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::DepositAddresses.into());
+        DepositAddressMap::init(memory)
+    })
+}
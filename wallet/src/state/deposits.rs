@@ -0,0 +1,45 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// utxo seen for the first time by the updater
+#[derive(CandidType, Deserialize, Clone)]
+pub struct DepositRecord {
+    pub address: String,
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    pub runes: Vec<(RuneId, u128)>,
+    pub timestamp: u64,
+    // change or anchor output of a transaction submitted by the canister itself
+    pub own: bool,
+}
+
+impl Storable for DepositRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by the outpoint as `txid:vout`
+pub type DepositMap = StableBTreeMap<String, DepositRecord, Memory>;
+
+pub fn init_deposit_map() -> DepositMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Deposits.into());
+        DepositMap::init(memory)
+    })
+}
@@ -0,0 +1,184 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, StableLog, Storable};
+use serde::Deserialize;
+
+use crate::types::{CycleLevel, RuneId};
+
+use super::transaction_log::{TransactionKind, TransactionStatus};
+
+use super::{DestinationPolicy, RunePolicy, SweepPolicy};
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum EventKind {
+    AdminUtxoRemoved {
+        address: String,
+        outpoint: Outpoint,
+    },
+    AdminUtxoInserted {
+        address: String,
+        utxo: Utxo,
+        rune: Option<(RuneId, u128)>,
+    },
+    AdminAddressResynced {
+        address: String,
+        btc_balance: u64,
+    },
+    SweepPolicyUpdated {
+        policy: Option<SweepPolicy>,
+    },
+    RunePolicyUpdated {
+        policy: RunePolicy,
+    },
+    DestinationPolicyUpdated {
+        policy: DestinationPolicy,
+    },
+    BalanceDrift {
+        address: String,
+        local_balance: u64,
+        chain_balance: u64,
+        resynced: bool,
+    },
+    ColdStorageSweep {
+        vault_address: String,
+        amount: u64,
+        txid: String,
+        // triggered by a controller instead of the schedule
+        manual: bool,
+    },
+    PrincipalAddressesScanned {
+        principal: Principal,
+        scanned: u32,
+        found: u32,
+    },
+    TreasuryWithdrawal {
+        to: String,
+        btc_amount: u64,
+        rune: Option<(RuneId, u128)>,
+        txid: String,
+    },
+    NotificationDeadLettered {
+        id: u64,
+        subscriber: Principal,
+        attempts: u32,
+    },
+    DepositDetected {
+        address: String,
+        txid: String,
+        vout: u32,
+        value: u64,
+        runes: Vec<(RuneId, u128)>,
+    },
+    WithdrawalSubmitted {
+        txid: String,
+        kind: TransactionKind,
+        fee: u64,
+        status: TransactionStatus,
+        // what the receiver got, unset on events from before statement exports
+        amount: Option<u64>,
+        runes: Option<Vec<(RuneId, u128)>>,
+        counterparty: Option<Principal>,
+    },
+    // median fee in millisatoshi per vbyte moved between two samples
+    FeeReestimated {
+        previous_median: u64,
+        median: u64,
+    },
+    UtxosSynced {
+        address: String,
+        tip_height: Option<u32>,
+        btc_balance: u64,
+    },
+    Paused {
+        reason: String,
+        tripped: bool,
+    },
+    Unpaused,
+    WebhookDeadLettered {
+        txid: String,
+        attempts: u32,
+    },
+    SubmissionDeadLettered {
+        txid: String,
+        attempts: u32,
+    },
+    SubmissionAbandoned {
+        txid: String,
+    },
+    // `credited` is what reached the pool once the sweep's fee came out of `amount`
+    DepositPooled {
+        address: String,
+        amount: u64,
+        credited: u64,
+        txid: String,
+    },
+    CycleLevelChanged {
+        previous: CycleLevel,
+        level: CycleLevel,
+        balance: u128,
+    }, // `sponsor` took `rune_amount` for the `btc_amount` at most it spent on the fee
+    FeeSponsored {
+        sponsor: Principal,
+        runeid: RuneId,
+        rune_amount: u128,
+        btc_amount: u64,
+        txid: String,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Event {
+    pub timestamp: u64,
+    pub caller: Principal,
+    pub kind: EventKind,
+    // principal whose funds the event is about, `None` for operator events
+    pub principal: Option<Principal>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, principal: Option<Principal>) -> Self {
+        Self {
+            timestamp: ic_cdk::api::time(),
+            caller: ic_cdk::caller(),
+            kind,
+            principal,
+        }
+    }
+}
+
+impl Storable for Event {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type EventLog = StableLog<Event, Memory, Memory>;
+
+pub fn init_event_log() -> EventLog {
+    read_memory_manager(|manager| {
+        let index = manager.get(MemoryIds::EventLogIndex.into());
+        let data = manager.get(MemoryIds::EventLogData.into());
+        EventLog::init(index, data).expect("failed to initialize event log")
+    })
+}
+
+// (principal, position in the event log) of every event about a principal
+pub type PrincipalEventIndex = StableBTreeMap<(Principal, u64), (), Memory>;
+
+pub fn init_principal_event_index() -> PrincipalEventIndex {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::PrincipalEvents.into());
+        PrincipalEventIndex::init(memory)
+    })
+}
@@ -0,0 +1,37 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// an address outside the canister's keys, bound through `register_external_address`
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ExternalAddress {
+    pub principal: Principal,
+    pub registered_at: u64,
+}
+
+impl Storable for ExternalAddress {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by the address, an address has a single owner at a time
+pub type ExternalAddressMap = StableBTreeMap<String, ExternalAddress, Memory>;
+
+pub fn init_external_address_map() -> ExternalAddressMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::ExternalAddresses.into());
+        ExternalAddressMap::init(memory)
+    })
+}
@@ -0,0 +1,61 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum EscrowStatus {
+    // quoted, the transfer paying the sponsor isn't submitted yet
+    Held,
+    // the transfer is submitted and the sponsor was told about it
+    Settled { txid: String },
+    // nothing got submitted, the sponsor was told to drop the quote
+    Released { reason: String },
+}
+
+/*
+ * a sponsor's quote for the fee of one rune withdrawal, held from the quote until the
+ * withdrawal either reaches the network or fails. the sponsor's bitcoin and the
+ * principal's runes only move together, in the withdrawal itself
+*/
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FeeEscrow {
+    pub principal: Principal,
+    pub sponsor: Principal,
+    pub quote_id: u64,
+    pub runeid: RuneId,
+    // runes the sponsor gets for funding at most `btc_amount`
+    pub rune_amount: u128,
+    pub btc_amount: u64,
+    pub status: EscrowStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Storable for FeeEscrow {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by an id counting up from 0
+pub type FeeEscrowMap = StableBTreeMap<u64, FeeEscrow, Memory>;
+
+pub fn init_fee_escrow_map() -> FeeEscrowMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::FeeEscrows.into());
+        FeeEscrowMap::init(memory)
+    })
+}
@@ -0,0 +1,150 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// number of samples kept before the oldest ones get overwritten
+pub const MAX_FEE_SAMPLES: u64 = 4_032;
+
+pub const MAX_CONFIRMATION_SAMPLES: u64 = 256;
+
+// fee percentiles observed at a point in time, in millisatoshi per vbyte
+#[derive(CandidType, Deserialize, Clone, Copy, Default)]
+pub struct FeeSample {
+    pub timestamp: u64,
+    pub p10: u64,
+    pub p25: u64,
+    pub p50: u64,
+    pub p75: u64,
+    pub p90: u64,
+}
+
+impl Storable for FeeSample {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/*
+ * how long one of our withdrawals took from its submission until a sync found its
+ * outputs confirmed, with the rate it paid in millisatoshi per vbyte
+*/
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ConfirmationSample {
+    pub txid: String,
+    pub fee_rate: u64,
+    pub latency_secs: u64,
+    pub confirmed_at: u64,
+}
+
+impl Storable for ConfirmationSample {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type FeeHistoryMap = StableBTreeMap<u64, FeeSample, Memory>;
+
+pub type ConfirmationSampleMap = StableBTreeMap<u64, ConfirmationSample, Memory>;
+
+pub fn init_fee_history_map() -> FeeHistoryMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::FeeHistory.into());
+        FeeHistoryMap::init(memory)
+    })
+}
+
+pub fn init_confirmation_sample_map() -> ConfirmationSampleMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::ConfirmationSamples.into());
+        ConfirmationSampleMap::init(memory)
+    })
+}
+
+// ring buffers of fee and confirmation samples keyed by ever increasing sequence numbers
+pub struct FeeHistory {
+    pub samples: FeeHistoryMap,
+    pub confirmations: ConfirmationSampleMap,
+}
+
+impl Default for FeeHistory {
+    fn default() -> Self {
+        Self {
+            samples: init_fee_history_map(),
+            confirmations: init_confirmation_sample_map(),
+        }
+    }
+}
+
+impl FeeHistory {
+    pub fn record(&mut self, sample: FeeSample) {
+        let seq = self
+            .samples
+            .last_key_value()
+            .map(|(seq, _)| seq + 1)
+            .unwrap_or_default();
+        self.samples.insert(seq, sample);
+        while self.samples.len() > MAX_FEE_SAMPLES {
+            if let Some((oldest, _)) = self.samples.first_key_value() {
+                self.samples.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn record_confirmation(&mut self, sample: ConfirmationSample) {
+        let seq = self
+            .confirmations
+            .last_key_value()
+            .map(|(seq, _)| seq + 1)
+            .unwrap_or_default();
+        self.confirmations.insert(seq, sample);
+        while self.confirmations.len() > MAX_CONFIRMATION_SAMPLES {
+            if let Some((oldest, _)) = self.confirmations.first_key_value() {
+                self.confirmations.remove(&oldest);
+            }
+        }
+    }
+
+    // the latest `count` confirmations, newest first
+    pub fn recent_confirmations(&self, count: usize) -> Vec<ConfirmationSample> {
+        self.confirmations
+            .iter()
+            .rev()
+            .take(count)
+            .map(|(_, sample)| sample)
+            .collect()
+    }
+
+    pub fn latest(&self) -> Option<FeeSample> {
+        self.samples.last_key_value().map(|(_, sample)| sample)
+    }
+
+    // samples taken at or after the given timestamp, oldest first
+    pub fn since(&self, timestamp: u64) -> Vec<FeeSample> {
+        let mut samples: Vec<FeeSample> = self
+            .samples
+            .iter()
+            .rev()
+            .map(|(_, sample)| sample)
+            .take_while(|sample| sample.timestamp >= timestamp)
+            .collect();
+        samples.reverse();
+        samples
+    }
+}
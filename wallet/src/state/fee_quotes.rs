@@ -0,0 +1,45 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+    transaction_log::TransactionKind,
+};
+
+// fee rate locked for a later withdrawal, `reserved` sats of the caller's bitcoin
+// are held back from other withdrawals until the quote is used or expires
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FeeQuote {
+    pub id: u64,
+    pub caller: Principal,
+    pub kind: TransactionKind,
+    pub vsize: u64,
+    // millisatoshis per vbyte
+    pub fee_per_vbytes: u64,
+    pub reserved: u64,
+    pub expires_at: u64,
+}
+
+impl Storable for FeeQuote {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by quote id
+pub type FeeQuoteMap = StableBTreeMap<u64, FeeQuote, Memory>;
+
+pub fn init_fee_quote_map() -> FeeQuoteMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::FeeQuotes.into());
+        FeeQuoteMap::init(memory)
+    })
+}
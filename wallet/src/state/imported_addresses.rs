@@ -0,0 +1,44 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// indexed address of a principal found holding funds by a scan
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ImportedAddress {
+    pub index: u32,
+    pub address: String,
+    pub btc_balance: u64,
+    pub runes: Vec<(RuneId, u128)>,
+    pub found_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct ImportedAddresses(pub Vec<ImportedAddress>);
+
+impl Storable for ImportedAddresses {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type ImportedAddressMap = StableBTreeMap<Principal, ImportedAddresses, Memory>;
+
+pub fn init_imported_address_map() -> ImportedAddressMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::ImportedAddresses.into());
+        ImportedAddressMap::init(memory)
+    })
+}
@@ -0,0 +1,55 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// named share of a principal's bitcoin, a logical allocation over the same utxos
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Jar {
+    pub name: String,
+    pub balance: u64,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct Jars(pub Vec<Jar>);
+
+impl Jars {
+    pub fn get(&self, name: &str) -> Option<&Jar> {
+        self.0.iter().find(|jar| jar.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Jar> {
+        self.0.iter_mut().find(|jar| jar.name == name)
+    }
+
+    // sats held by all jars, unavailable to plain withdrawals
+    pub fn allocated(&self) -> u64 {
+        self.0.iter().map(|jar| jar.balance).sum()
+    }
+}
+
+impl Storable for Jars {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type JarMap = StableBTreeMap<Principal, Jars, Memory>;
+
+pub fn init_jar_map() -> JarMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Jars.into());
+        JarMap::init(memory)
+    })
+}
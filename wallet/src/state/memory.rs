@@ -7,8 +7,54 @@ pub type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 pub enum MemoryIds {
     Config,
+    // per address utxo records, emptied into `RunicByOutpoint` and `BitcoinByOutpoint`
     Runic,
     Bitcoin,
+    TransactionLog,
+    ApiStats,
+    EventLogIndex,
+    EventLogData,
+    FeeHistory,
+    PendingMultisig,
+    Deposits,
+    RawTransactions,
+    BatchedWithdrawals,
+    PreparedWithdrawals,
+    ImportedAddresses,
+    SyncCursors,
+    Outbox,
+    TemplateReservations,
+    Jars,
+    PrincipalEvents,
+    SnapshotBalances,
+    SnapshotDeltas,
+    SplitWithdrawals,
+    CkbtcWraps,
+    CkbtcAutoWrap,
+    FeeQuotes,
+    WithdrawalAllowances,
+    BalanceSubscriptions,
+    CallUsage,
+    WithdrawalProposals,
+    DepositAddresses,
+    Metrics,
+    RuneLedgerBalances,
+    RuneLedgerTransfers,
+    RunicByOutpoint,
+    BitcoinByOutpoint,
+    RuneMetadata,
+    WebhookQueue,
+    PendingChange,
+    Contacts,
+    SubmissionQueue,
+    BalanceHistory,
+    LatestBalanceSamples,
+    TreasuryCredits,
+    Delegations,
+    ExternalAddresses,
+    ConfirmationSamples,
+    FeeEscrows,
+    SpentInputs,
 }
 
 impl From<MemoryIds> for MemoryId {
@@ -17,6 +63,51 @@ impl From<MemoryIds> for MemoryId {
             MemoryIds::Config => MemoryId::new(0),
             MemoryIds::Runic => MemoryId::new(1),
             MemoryIds::Bitcoin => MemoryId::new(2),
+            MemoryIds::TransactionLog => MemoryId::new(3),
+            MemoryIds::ApiStats => MemoryId::new(4),
+            MemoryIds::EventLogIndex => MemoryId::new(5),
+            MemoryIds::EventLogData => MemoryId::new(6),
+            MemoryIds::FeeHistory => MemoryId::new(7),
+            MemoryIds::PendingMultisig => MemoryId::new(8),
+            MemoryIds::Deposits => MemoryId::new(9),
+            MemoryIds::RawTransactions => MemoryId::new(10),
+            MemoryIds::BatchedWithdrawals => MemoryId::new(11),
+            MemoryIds::PreparedWithdrawals => MemoryId::new(12),
+            MemoryIds::ImportedAddresses => MemoryId::new(13),
+            MemoryIds::SyncCursors => MemoryId::new(14),
+            MemoryIds::Outbox => MemoryId::new(15),
+            MemoryIds::TemplateReservations => MemoryId::new(16),
+            MemoryIds::Jars => MemoryId::new(17),
+            MemoryIds::PrincipalEvents => MemoryId::new(18),
+            MemoryIds::SnapshotBalances => MemoryId::new(19),
+            MemoryIds::SnapshotDeltas => MemoryId::new(20),
+            MemoryIds::SplitWithdrawals => MemoryId::new(21),
+            MemoryIds::CkbtcWraps => MemoryId::new(22),
+            MemoryIds::CkbtcAutoWrap => MemoryId::new(23),
+            MemoryIds::FeeQuotes => MemoryId::new(24),
+            MemoryIds::WithdrawalAllowances => MemoryId::new(25),
+            MemoryIds::BalanceSubscriptions => MemoryId::new(26),
+            MemoryIds::CallUsage => MemoryId::new(27),
+            MemoryIds::WithdrawalProposals => MemoryId::new(28),
+            MemoryIds::DepositAddresses => MemoryId::new(29),
+            MemoryIds::Metrics => MemoryId::new(30),
+            MemoryIds::RuneLedgerBalances => MemoryId::new(31),
+            MemoryIds::RuneLedgerTransfers => MemoryId::new(32),
+            MemoryIds::RunicByOutpoint => MemoryId::new(33),
+            MemoryIds::BitcoinByOutpoint => MemoryId::new(34),
+            MemoryIds::RuneMetadata => MemoryId::new(35),
+            MemoryIds::WebhookQueue => MemoryId::new(36),
+            MemoryIds::PendingChange => MemoryId::new(37),
+            MemoryIds::Contacts => MemoryId::new(38),
+            MemoryIds::SubmissionQueue => MemoryId::new(39),
+            MemoryIds::BalanceHistory => MemoryId::new(40),
+            MemoryIds::LatestBalanceSamples => MemoryId::new(41),
+            MemoryIds::TreasuryCredits => MemoryId::new(42),
+            MemoryIds::Delegations => MemoryId::new(43),
+            MemoryIds::ExternalAddresses => MemoryId::new(44),
+            MemoryIds::ConfirmationSamples => MemoryId::new(45),
+            MemoryIds::FeeEscrows => MemoryId::new(46),
+            MemoryIds::SpentInputs => MemoryId::new(47),
         }
     }
 }
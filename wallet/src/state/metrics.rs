@@ -0,0 +1,48 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableCell, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager, TransactionKind,
+};
+
+// counters kept since the canister was installed
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct MetricCounters {
+    pub transactions_submitted: Vec<(TransactionKind, u64)>,
+    pub sats_withdrawn: u64,
+    pub runes_withdrawn: Vec<(RuneId, u128)>,
+    pub utxo_fetches: u64,
+    pub signature_calls: u64,
+    pub failed_submissions: u64,
+    // missing on counters stored before fresh cursors skipped the fetch
+    pub skipped_utxo_fetches: Option<u64>,
+    // missing on counters stored before the collector reaped expired entries
+    pub reaped_pending_change: Option<u64>,
+    pub reaped_prepared_withdrawals: Option<u64>,
+}
+
+impl Storable for MetricCounters {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type StableMetricCounters = StableCell<MetricCounters, Memory>;
+
+pub fn init_metric_counters() -> StableMetricCounters {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Metrics.into());
+        StableMetricCounters::new(memory, MetricCounters::default())
+            .expect("failed to initialize the metric counters")
+    })
+}
@@ -0,0 +1,43 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// withdrawal signed by the canister, waiting for the cosigner's signatures
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PendingMultisig {
+    pub caller: Principal,
+    pub cosigner_pubkey: Vec<u8>,
+    pub addr: String,
+    pub utxos: Vec<Utxo>,
+    // serialized psbt carrying the canister's partial signatures
+    pub psbt: Vec<u8>,
+    pub created_at: u64,
+}
+
+impl Storable for PendingMultisig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by the txid of the unsigned transaction
+pub type PendingMultisigMap = StableBTreeMap<String, PendingMultisig, Memory>;
+
+pub fn init_pending_multisig_map() -> PendingMultisigMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::PendingMultisig.into());
+        PendingMultisigMap::init(memory)
+    })
+}
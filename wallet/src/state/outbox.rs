@@ -0,0 +1,68 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager, DepositRecord,
+};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum Notification {
+    Deposit(DepositRecord),
+    // an output of a transaction submitted by the canister showed up on chain
+    TransactionConfirmed { txid: String },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum OutboxStatus {
+    Pending,
+    // gave up after too many failed attempts, only retried by an operator
+    DeadLettered { at: u64 },
+}
+
+// shared by the outbox, the submission queue and the webhook deliveries
+impl OutboxStatus {
+    pub fn is_due(&self, next_attempt_at: u64, now: u64) -> bool {
+        matches!(self, OutboxStatus::Pending) && next_attempt_at <= now
+    }
+
+    pub fn is_dead_lettered(&self) -> bool {
+        matches!(self, OutboxStatus::DeadLettered { .. })
+    }
+}
+
+// one notification for one subscriber, removed once delivered
+#[derive(CandidType, Deserialize, Clone)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub subscriber: Principal,
+    pub notification: Notification,
+    pub created_at: u64,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    pub status: OutboxStatus,
+}
+
+impl Storable for OutboxEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by entry id, ids are handed out in enqueueing order
+pub type OutboxMap = StableBTreeMap<u64, OutboxEntry, Memory>;
+
+pub fn init_outbox_map() -> OutboxMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Outbox.into());
+        OutboxMap::init(memory)
+    })
+}
@@ -0,0 +1,74 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::management_canister::bitcoin::Outpoint;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// an output of a submitted transaction paying back to one of the spent addresses
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PendingChange {
+    pub owner: Principal,
+    pub txid: String,
+    pub vout: u32,
+    pub address: String,
+    pub value: u64,
+    pub submitted_at: u64,
+}
+
+impl Storable for PendingChange {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by "txid:vout" like the deposits, so the outputs of a transaction sit together
+pub type PendingChangeMap = StableBTreeMap<String, PendingChange, Memory>;
+
+pub fn init_pending_change_map() -> PendingChangeMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::PendingChange.into());
+        PendingChangeMap::init(memory)
+    })
+}
+
+// an input of a submitted transaction the chain still shows unspent
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SpentInput {
+    pub spender: String,
+    pub address: String,
+    pub outpoint: Outpoint,
+    pub height: u32,
+    pub submitted_at: u64,
+}
+
+impl Storable for SpentInput {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by "txid:vout" of the spent outpoint
+pub type SpentInputMap = StableBTreeMap<String, SpentInput, Memory>;
+
+pub fn init_spent_input_map() -> SpentInputMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::SpentInputs.into());
+        SpentInputMap::init(memory)
+    })
+}
@@ -0,0 +1,71 @@
+use candid::{CandidType, Decode, Encode};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+    transaction_log::{TransactionRecord, TransactionSummary},
+    utxo_manager::RunicUtxo,
+};
+
+// utxos taken out of the utxo manager by a transaction that hasn't been broadcast yet
+#[derive(CandidType, Deserialize, Clone)]
+pub enum LockedUtxos {
+    Bitcoin {
+        addr: String,
+        utxos: Vec<Utxo>,
+    },
+    Runic {
+        addr: String,
+        runeid: RuneId,
+        utxos: Vec<RunicUtxo>,
+    },
+}
+
+// signed withdrawal waiting for its broadcast
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PreparedWithdrawal {
+    pub record: TransactionRecord,
+    pub raw_transaction: Vec<u8>,
+    pub locked: Vec<LockedUtxos>,
+    pub prepared_at: u64,
+    // unset for withdrawals prepared before summaries were kept
+    pub summary: Option<TransactionSummary>,
+    // set once cancelled or expired, the signed inputs stay held until the chain settles them
+    pub cancelled_at: Option<u64>,
+    // tip height from which the inputs of a cancelled withdrawal still unspent are released
+    pub release_height: Option<u32>, // a psbt with the canister's signatures went out, only the chain settles its inputs
+    pub handed_out: Option<bool>,
+}
+
+impl PreparedWithdrawal {
+    pub fn is_handed_out(&self) -> bool {
+        self.handed_out.unwrap_or_default()
+    }
+}
+
+impl Storable for PreparedWithdrawal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by txid
+pub type PreparedWithdrawalMap = StableBTreeMap<String, PreparedWithdrawal, Memory>;
+
+pub fn init_prepared_withdrawal_map() -> PreparedWithdrawalMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::PreparedWithdrawals.into());
+        PreparedWithdrawalMap::init(memory)
+    })
+}
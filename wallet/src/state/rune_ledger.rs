@@ -0,0 +1,93 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use icrc_ledger_types::icrc1::account::Account;
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// an account's runes on the internal ledger, backed by the ledger's pool address
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct LedgerBalances(pub Vec<(RuneId, u128)>);
+
+impl LedgerBalances {
+    pub fn get(&self, runeid: &RuneId) -> u128 {
+        self.0
+            .iter()
+            .find(|(id, _)| id == runeid)
+            .map_or(0, |(_, balance)| *balance)
+    }
+
+    pub fn set(&mut self, runeid: &RuneId, balance: u128) {
+        self.0.retain(|(id, _)| id != runeid);
+        if balance > 0 {
+            self.0.push((runeid.clone(), balance));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Storable for LedgerBalances {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by the textual encoding of the icrc-1 account
+pub type LedgerBalanceMap = StableBTreeMap<String, LedgerBalances, Memory>;
+
+pub fn init_ledger_balance_map() -> LedgerBalanceMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::RuneLedgerBalances.into());
+        LedgerBalanceMap::init(memory)
+    })
+}
+
+/*
+ * movement on the internal ledger. `from` is unset for runes credited from an
+ * on-chain deposit into the pool, `to` for runes withdrawn out of it
+*/
+#[derive(CandidType, Deserialize, Clone)]
+pub struct LedgerTransfer {
+    pub runeid: RuneId,
+    pub from: Option<Account>,
+    pub to: Option<Account>,
+    pub amount: u128,
+    pub memo: Option<Vec<u8>>,
+    pub timestamp: u64,
+}
+
+impl Storable for LedgerTransfer {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by block index, the index `icrc1_transfer` hands back
+pub type LedgerTransferMap = StableBTreeMap<u64, LedgerTransfer, Memory>;
+
+pub fn init_ledger_transfer_map() -> LedgerTransferMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::RuneLedgerTransfers.into());
+        LedgerTransferMap::init(memory)
+    })
+}
@@ -0,0 +1,40 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+use crate::types::RuneId;
+
+// what a caller needs to turn a rune's base units into whole amounts
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RuneMetadata {
+    pub runeid: RuneId,
+    pub runename: String,
+    pub divisibility: u8,
+    pub symbol: Option<String>,
+}
+
+impl Storable for RuneMetadata {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// entries never expire, an etching's divisibility is fixed for good
+pub type RuneMetadataMap = StableBTreeMap<RuneId, RuneMetadata, Memory>;
+
+pub fn init_rune_metadata_map() -> RuneMetadataMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::RuneMetadata.into());
+        RuneMetadataMap::init(memory)
+    })
+}
@@ -0,0 +1,73 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// balances of an address as of a snapshot, runes sorted by id
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq)]
+pub struct AddressBalance {
+    pub address: String,
+    pub bitcoin: u64,
+    pub runes: Vec<(RuneId, u128)>,
+}
+
+impl Storable for AddressBalance {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// addresses whose balances changed between two consecutive snapshot versions
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SnapshotDelta {
+    pub version: u64,
+    pub height: u32,
+    pub changed: Vec<AddressBalance>,
+    // addresses no longer holding anything
+    pub removed: Vec<String>,
+    pub taken_at: u64,
+}
+
+impl Storable for SnapshotDelta {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by address, the state the latest snapshot version describes
+pub type SnapshotBalanceMap = StableBTreeMap<String, AddressBalance, Memory>;
+
+// keyed by version
+pub type SnapshotDeltaMap = StableBTreeMap<u64, SnapshotDelta, Memory>;
+
+pub fn init_snapshot_balance_map() -> SnapshotBalanceMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::SnapshotBalances.into());
+        SnapshotBalanceMap::init(memory)
+    })
+}
+
+pub fn init_snapshot_delta_map() -> SnapshotDeltaMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::SnapshotDeltas.into());
+        SnapshotDeltaMap::init(memory)
+    })
+}
@@ -0,0 +1,80 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum SplitWithdrawalStatus {
+    InProgress,
+    Completed,
+    Cancelled,
+    // parts submitted before the failure stay submitted
+    Failed { reason: String },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SplitPart {
+    pub amount: u64,
+    pub txid: Option<String>,
+    pub submitted_at: Option<u64>,
+}
+
+// one logical withdrawal paid out over several transactions
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SplitWithdrawal {
+    pub id: u64,
+    pub caller: Principal,
+    pub to: String,
+    pub amount: u64,
+    pub fee_per_vbytes: Option<u64>,
+    pub parts: Vec<SplitPart>,
+    pub interval_secs: u64,
+    pub next_part_at: u64,
+    pub created_at: u64,
+    pub status: SplitWithdrawalStatus,
+}
+
+impl SplitWithdrawal {
+    pub fn is_in_progress(&self) -> bool {
+        matches!(self.status, SplitWithdrawalStatus::InProgress)
+    }
+
+    // first part still waiting for its transaction
+    pub fn next_part(&self) -> Option<usize> {
+        self.parts.iter().position(|part| part.txid.is_none())
+    }
+
+    pub fn submitted(&self) -> u64 {
+        self.parts
+            .iter()
+            .filter(|part| part.txid.is_some())
+            .map(|part| part.amount)
+            .sum()
+    }
+}
+
+impl Storable for SplitWithdrawal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by job id, ids are handed out in creation order
+pub type SplitWithdrawalMap = StableBTreeMap<u64, SplitWithdrawal, Memory>;
+
+pub fn init_split_withdrawal_map() -> SplitWithdrawalMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::SplitWithdrawals.into());
+        SplitWithdrawalMap::init(memory)
+    })
+}
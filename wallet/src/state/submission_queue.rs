@@ -0,0 +1,42 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager, LockedUtxos, OutboxStatus, TransactionRecord,
+};
+
+// a signed transaction the bitcoin canister hasn't accepted yet
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PendingSubmission {
+    pub record: TransactionRecord,
+    pub raw_transaction: Vec<u8>,
+    pub locked: Vec<LockedUtxos>,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    pub status: OutboxStatus,
+}
+
+impl Storable for PendingSubmission {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by txid, an entry leaves once the bitcoin canister took the transaction
+pub type SubmissionQueue = StableBTreeMap<String, PendingSubmission, Memory>;
+
+pub fn init_submission_queue() -> SubmissionQueue {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::SubmissionQueue.into());
+        SubmissionQueue::init(memory)
+    })
+}
@@ -0,0 +1,37 @@
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// chain tip up to which every utxo of the address has been classified
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SyncCursor {
+    pub tip_height: u32,
+    pub tip_block_hash: Vec<u8>,
+    pub synced_at: u64,
+}
+
+impl Storable for SyncCursor {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type SyncCursorMap = StableBTreeMap<String, SyncCursor, Memory>;
+
+pub fn init_sync_cursor_map() -> SyncCursorMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::SyncCursors.into());
+        SyncCursorMap::init(memory)
+    })
+}
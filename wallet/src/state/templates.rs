@@ -0,0 +1,73 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use icrc_ledger_types::icrc1::account::Account;
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    prepared_withdrawals::LockedUtxos,
+    read_memory_manager,
+    utxo_manager::RunicUtxo,
+};
+
+// enough of a built transaction to rebuild and sign it once its template is committed
+#[derive(CandidType, Deserialize, Clone)]
+pub enum ReservedSelection {
+    Bitcoin {
+        addr: String,
+        utxos: Vec<Utxo>,
+        signer_account: Account,
+        change_addr: String,
+        change_utxos: Vec<Utxo>,
+        change_account: Account,
+        raw_transaction: Vec<u8>,
+        // (script_pubkey, value) of the anchor output
+        anchor: Option<(Vec<u8>, u64)>,
+    },
+    Runestone {
+        sender_addr: String,
+        receiver_addr: String,
+        sender_account: Account,
+        receiver_account: Account,
+        runes: Vec<(RuneId, u128, Vec<RunicUtxo>)>,
+        fee: u64,
+        fee_utxos: Vec<Utxo>,
+        paid_by_sender: bool,
+        postage: u64,
+        anchor: Option<(Vec<u8>, u64)>,
+    },
+}
+
+// utxos held for an unsigned template until it's committed, released or expires
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TemplateReservation {
+    pub caller: Principal,
+    pub selection: ReservedSelection,
+    pub locked: Vec<LockedUtxos>,
+    pub expires_at: u64,
+}
+
+impl Storable for TemplateReservation {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by template id
+pub type TemplateReservationMap = StableBTreeMap<u64, TemplateReservation, Memory>;
+
+pub fn init_template_reservation_map() -> TemplateReservationMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::TemplateReservations.into());
+        TemplateReservationMap::init(memory)
+    })
+}
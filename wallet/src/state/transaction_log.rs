@@ -0,0 +1,230 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::{FiatRate, RuneId};
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+pub const MAX_MEMO_SIZE: usize = 1_024;
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransactionStatus {
+    Submitted,
+    // built and signed, but never broadcasted (paper trading mode)
+    Simulated,
+    // signed, the bitcoin canister rejected the broadcast and it's retried from the submission queue
+    Queued,
+    // dropped from the submission queue after it was dead-lettered, its utxos spendable again
+    Abandoned,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransactionKind {
+    Bitcoin,
+    MultiSender,
+    Runestone,
+    Combined,
+    // 2-of-2 withdrawal completed by an external cosigner
+    Multisig,
+    // payouts of several queued withdrawals merged into one transaction
+    Batch,
+    // a rune redistributed over new outputs of its owner
+    RuneSplit,
+    // runes sent to an OP_RETURN output, out of supply for good
+    RuneBurn,
+}
+
+// what an output of a withdrawal pays for
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputRole {
+    Recipient,
+    // leftover bitcoin going back to the senders or the fee payer
+    Change,
+    // sats carrying runes, delivered or returned as rune change
+    Postage,
+    OpReturn,
+    // output paying the fee pool
+    Anchor,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SummaryInput {
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SummaryOutput {
+    // unset for outputs without an address, such as OP_RETURN
+    pub address: Option<String>,
+    pub value: u64,
+    pub role: OutputRole,
+}
+
+// breakdown of a withdrawal handed back with its receipt, in input and output order
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TransactionSummary {
+    pub inputs: Vec<SummaryInput>,
+    pub outputs: Vec<SummaryOutput>,
+    pub fee: u64,
+    pub fee_payer: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TransactionRecord {
+    pub txid: String,
+    pub kind: TransactionKind,
+    pub caller: Principal,
+    pub fee: u64,
+    pub status: TransactionStatus,
+    pub timestamp: u64,
+    pub vsize: Option<u64>,
+    // (vout, value) of the anchor output paying to the fee pool
+    pub anchor: Option<(u32, u64)>,
+    // bitcoin and runes delivered to the receiver
+    pub amount: Option<u64>,
+    pub rune: Option<(RuneId, u128)>,
+    // further runes of a multi-rune transfer, `rune` holds the first one
+    pub additional_runes: Option<Vec<(RuneId, u128)>>,
+    // receiving principal of transfers between wallets of this canister
+    pub counterparty: Option<Principal>,
+    pub fee_payer: Option<Principal>,
+    // encrypted memo attached by the sender of an internal transfer
+    pub memo: Option<Vec<u8>>,
+    // rate a fiat-denominated limit was checked against
+    pub fiat_rate: Option<FiatRate>,
+}
+
+impl Storable for TransactionRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type TransactionLogMap = StableBTreeMap<u64, TransactionRecord, Memory>;
+
+pub fn init_transaction_log_map() -> TransactionLogMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::TransactionLog.into());
+        TransactionLogMap::init(memory)
+    })
+}
+
+// signed transactions as broadcasted, keyed by txid
+pub type RawTransactionMap = StableBTreeMap<String, Vec<u8>, Memory>;
+
+pub fn init_raw_transaction_map() -> RawTransactionMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::RawTransactions.into());
+        RawTransactionMap::init(memory)
+    })
+}
+
+pub struct TransactionLog {
+    pub log: TransactionLogMap,
+    pub raw: RawTransactionMap,
+}
+
+impl Default for TransactionLog {
+    fn default() -> Self {
+        Self {
+            log: init_transaction_log_map(),
+            raw: init_raw_transaction_map(),
+        }
+    }
+}
+
+impl TransactionLog {
+    pub fn record(&mut self, record: TransactionRecord, raw_transaction: Vec<u8>) -> u64 {
+        let id = self
+            .log
+            .last_key_value()
+            .map(|(id, _)| id + 1)
+            .unwrap_or_default();
+        self.raw.insert(record.txid.clone(), raw_transaction);
+        self.log.insert(id, record);
+        id
+    }
+
+    pub fn raw_transaction(&self, txid: &str) -> Option<Vec<u8>> {
+        self.raw.get(&txid.to_string())
+    }
+
+    pub fn attach_fiat_rate(&mut self, txid: &str, rate: FiatRate) {
+        if let Some((id, mut record)) = self
+            .log
+            .iter()
+            .rev()
+            .find(|(_, record)| record.txid == txid)
+        {
+            record.fiat_rate = Some(rate);
+            self.log.insert(id, record);
+        }
+    }
+
+    pub fn set_status(&mut self, txid: &str, status: TransactionStatus) {
+        if let Some((id, mut record)) = self
+            .log
+            .iter()
+            .rev()
+            .find(|(_, record)| record.txid == txid)
+        {
+            record.status = status;
+            self.log.insert(id, record);
+        }
+    }
+
+    // txids of the latest records, newest first
+    pub fn recent_txids(&self, count: usize) -> Vec<String> {
+        self.log
+            .iter()
+            .rev()
+            .take(count)
+            .map(|(_, record)| record.txid)
+            .collect()
+    }
+
+    pub fn find_by_txid(&self, txid: &str) -> Option<TransactionRecord> {
+        self.log
+            .iter()
+            .rev()
+            .find(|(_, record)| record.txid == txid)
+            .map(|(_, record)| record)
+    }
+
+    // records with a timestamp within [from, to], oldest first
+    pub fn records_between(&self, from: u64, to: u64) -> Vec<TransactionRecord> {
+        self.log
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.timestamp >= from && record.timestamp <= to)
+            .collect()
+    }
+
+    pub fn records_of(
+        &self,
+        caller: &Principal,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<TransactionRecord> {
+        self.log
+            .iter()
+            .rev()
+            .filter(|(_, record)| record.caller == *caller)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(_, record)| record)
+            .collect()
+    }
+}
@@ -0,0 +1,17 @@
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+// sats each principal owns of the deposit pool, principals without credit have no entry
+pub type TreasuryCreditMap = StableBTreeMap<Principal, u64, Memory>;
+
+pub fn init_treasury_credit_map() -> TreasuryCreditMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::TreasuryCredits.into());
+        TreasuryCreditMap::init(memory)
+    })
+}
@@ -1,17 +1,25 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use candid::{CandidType, Decode, Encode};
-use ic_cdk::api::management_canister::bitcoin::Utxo;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
 use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
 use serde::{Deserialize, Serialize};
 
-use crate::types::RuneId;
+use crate::types::{CoinSelection, RuneId, UtxoInfo};
 
 use super::{
     memory::{Memory, MemoryIds},
     read_memory_manager,
 };
 
+// most utxos or balances a single page hands out
+pub const MAX_PAGE_SIZE: u64 = 1_000;
+
+// candid encoded keys stay well below this for the longest bech32 address
+const MAX_KEY_SIZE: u32 = 256;
+
+const MAX_ENTRY_SIZE: u32 = 96;
+
 #[derive(CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
 pub struct RunicUtxo {
     pub utxo: Utxo,
@@ -42,10 +50,34 @@ impl Storable for RunicUtxo {
     const BOUND: Bound = Bound::Unbounded;
 }
 
-#[derive(CandidType, Deserialize, Default)]
-pub struct RunicUtxoMap(HashMap<RuneId, HashSet<RunicUtxo>>);
+// smallest outpoint, lower end of the range of an address or rune
+fn first_outpoint() -> Outpoint {
+    Outpoint {
+        txid: vec![],
+        vout: 0,
+    }
+}
+
+/*
+ * (address, outpoint), so the utxos of an address sit next to each other and get
+ * walked with a range instead of loading one value holding all of them
+ */
+#[derive(CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct BtcKey {
+    pub addr: String,
+    pub outpoint: Outpoint,
+}
+
+impl BtcKey {
+    fn new(addr: &str, outpoint: Outpoint) -> Self {
+        Self {
+            addr: String::from(addr),
+            outpoint,
+        }
+    }
+}
 
-impl Storable for RunicUtxoMap {
+impl Storable for BtcKey {
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).expect("should decode")
     }
@@ -54,22 +86,158 @@ impl Storable for RunicUtxoMap {
         std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
     }
 
-    const BOUND: Bound = Bound::Unbounded;
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_KEY_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BtcEntry {
+    pub value: u64,
+    pub height: u32,
+}
+
+impl BtcEntry {
+    fn utxo(self, outpoint: Outpoint) -> Utxo {
+        Utxo {
+            outpoint,
+            value: self.value,
+            height: self.height,
+        }
+    }
+}
+
+impl From<&Utxo> for BtcEntry {
+    fn from(utxo: &Utxo) -> Self {
+        Self {
+            value: utxo.value,
+            height: utxo.height,
+        }
+    }
+}
+
+impl Storable for BtcEntry {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_ENTRY_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+pub type BtcMap = StableBTreeMap<BtcKey, BtcEntry, Memory>;
+
+pub fn init_btc_map() -> BtcMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::BitcoinByOutpoint.into());
+        BtcMap::init(memory)
+    })
+}
+
+// (address, rune, outpoint), a utxo holding several runes has one entry per rune
+#[derive(CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct RunicKey {
+    pub addr: String,
+    pub runeid: RuneId,
+    pub outpoint: Outpoint,
+}
+
+impl RunicKey {
+    fn new(addr: &str, runeid: RuneId, outpoint: Outpoint) -> Self {
+        Self {
+            addr: String::from(addr),
+            runeid,
+            outpoint,
+        }
+    }
+
+    // first key of the address
+    fn first(addr: &str) -> Self {
+        Self::new(addr, RuneId { block: 0, tx: 0 }, first_outpoint())
+    }
+}
+
+impl Storable for RunicKey {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_KEY_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RunicEntry {
+    pub value: u64,
+    pub height: u32,
+    pub balance: u128,
+}
+
+impl RunicEntry {
+    fn runic_utxo(self, outpoint: Outpoint) -> RunicUtxo {
+        RunicUtxo {
+            utxo: Utxo {
+                outpoint,
+                value: self.value,
+                height: self.height,
+            },
+            balance: self.balance,
+        }
+    }
+}
+
+impl From<&RunicUtxo> for RunicEntry {
+    fn from(r_utxo: &RunicUtxo) -> Self {
+        Self {
+            value: r_utxo.utxo.value,
+            height: r_utxo.utxo.height,
+            balance: r_utxo.balance,
+        }
+    }
+}
+
+impl Storable for RunicEntry {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_ENTRY_SIZE,
+        is_fixed_size: false,
+    };
 }
 
-pub type RunicMap = StableBTreeMap<String, RunicUtxoMap, Memory>;
+pub type RunicMap = StableBTreeMap<RunicKey, RunicEntry, Memory>;
 
 pub fn init_runic_map() -> RunicMap {
     read_memory_manager(|manager| {
-        let memory = manager.get(MemoryIds::Runic.into());
+        let memory = manager.get(MemoryIds::RunicByOutpoint.into());
         RunicMap::init(memory)
     })
 }
 
+// per address records of canisters upgraded from before the composite keys
 #[derive(CandidType, Deserialize, Default)]
-pub struct BitcoinUtxos(HashSet<Utxo>);
+struct LegacyRunicUtxos(HashMap<RuneId, HashSet<RunicUtxo>>);
 
-impl Storable for BitcoinUtxos {
+impl Storable for LegacyRunicUtxos {
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).expect("should decode")
     }
@@ -81,13 +249,19 @@ impl Storable for BitcoinUtxos {
     const BOUND: Bound = Bound::Unbounded;
 }
 
-pub type BtcMap = StableBTreeMap<String, BitcoinUtxos, Memory>;
+#[derive(CandidType, Deserialize, Default)]
+struct LegacyBitcoinUtxos(HashSet<Utxo>);
 
-pub fn init_btc_map() -> BtcMap {
-    read_memory_manager(|manager| {
-        let memory = manager.get(MemoryIds::Bitcoin.into());
-        BtcMap::init(memory)
-    })
+impl Storable for LegacyBitcoinUtxos {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 #[derive(Serialize, Deserialize)]
@@ -96,6 +270,9 @@ pub struct UtxoManager {
     pub r: RunicMap,
     #[serde(skip, default = "init_btc_map")]
     pub b: BtcMap,
+    // re-recorded outpoints replaced instead of being counted twice, since the last upgrade
+    #[serde(skip)]
+    pub duplicates_prevented: u64,
 }
 
 impl Default for UtxoManager {
@@ -103,113 +280,557 @@ impl Default for UtxoManager {
         Self {
             r: init_runic_map(),
             b: init_btc_map(),
+            duplicates_prevented: 0,
         }
     }
 }
 
 impl UtxoManager {
-    pub fn record_runic_utxos(&mut self, addr: &str, runeid: RuneId, utxos: Vec<RunicUtxo>) {
-        let addr = String::from(addr);
-        let mut map = self.r.get(&addr).unwrap_or_default().0;
-        let mut current_utxos = map.remove(&runeid).unwrap_or_default();
-        for utxo in utxos {
-            if current_utxos.contains(&utxo) {
+    /*
+     * moves the per address records kept before the composite keys into the new maps
+     * and empties the old ones, a no-op once they are empty. runs in `post_upgrade`
+     */
+    pub fn migrate_legacy_records(&mut self) {
+        let mut legacy_runic: StableBTreeMap<String, LegacyRunicUtxos, Memory> =
+            read_memory_manager(|manager| {
+                StableBTreeMap::init(manager.get(MemoryIds::Runic.into()))
+            });
+        let addrs: Vec<String> = legacy_runic.iter().map(|(addr, _)| addr).collect();
+        for addr in addrs {
+            let Some(LegacyRunicUtxos(map)) = legacy_runic.remove(&addr) else {
                 continue;
+            };
+            for (runeid, utxos) in map {
+                self.record_runic_utxos(&addr, runeid, utxos.into_iter().collect());
+            }
+        }
+        let mut legacy_btc: StableBTreeMap<String, LegacyBitcoinUtxos, Memory> =
+            read_memory_manager(|manager| {
+                StableBTreeMap::init(manager.get(MemoryIds::Bitcoin.into()))
+            });
+        let addrs: Vec<String> = legacy_btc.iter().map(|(addr, _)| addr).collect();
+        for addr in addrs {
+            if let Some(LegacyBitcoinUtxos(utxos)) = legacy_btc.remove(&addr) {
+                self.record_btc_utxos(&addr, utxos.into_iter().collect());
+            }
+        }
+        // the old maps allowed duplicates, merging them isn't something new to report
+        self.duplicates_prevented = 0;
+    }
+
+    // upserts by outpoint, a utxo seen again replaces its previous record
+    pub fn record_runic_utxos(&mut self, addr: &str, runeid: RuneId, utxos: Vec<RunicUtxo>) {
+        for r_utxo in utxos {
+            let outpoint = r_utxo.utxo.outpoint.clone();
+            let entry = RunicEntry::from(&r_utxo);
+            if self
+                .r
+                .insert(RunicKey::new(addr, runeid.clone(), outpoint.clone()), entry)
+                .is_some()
+            {
+                self.duplicates_prevented += 1;
+            }
+            // a runic utxo never counts towards the bitcoin balance
+            if self.b.remove(&BtcKey::new(addr, outpoint)).is_some() {
+                self.duplicates_prevented += 1;
             }
-            current_utxos.insert(utxo);
         }
-        map.insert(runeid, current_utxos);
-        self.r.insert(addr, RunicUtxoMap(map));
     }
 
+    // upserts by outpoint, utxos already recorded as runic are left out
     pub fn record_btc_utxos(&mut self, addr: &str, utxos: Vec<Utxo>) {
-        let addr = String::from(addr);
-        let mut current_utxos = self.b.get(&addr).unwrap_or_default().0;
+        let runic = self.runic_outpoints(addr);
         for utxo in utxos {
-            if current_utxos.contains(&utxo) {
+            if runic.contains(&utxo.outpoint) {
+                self.duplicates_prevented += 1;
                 continue;
             }
-            current_utxos.insert(utxo);
+            let entry = BtcEntry::from(&utxo);
+            if self
+                .b
+                .insert(BtcKey::new(addr, utxo.outpoint), entry)
+                .is_some()
+            {
+                self.duplicates_prevented += 1;
+            }
+        }
+    }
+
+    // bitcoin utxos of the address in outpoint order, starting after `start_after`
+    fn btc_range<'a>(
+        &'a self,
+        addr: &'a str,
+        start_after: Option<Outpoint>,
+    ) -> impl Iterator<Item = Utxo> + 'a {
+        let start = BtcKey::new(addr, start_after.clone().unwrap_or_else(first_outpoint));
+        self.b
+            .range(start..)
+            .skip_while(move |(key, _)| Some(&key.outpoint) == start_after.as_ref())
+            .take_while(move |(key, _)| key.addr == addr)
+            .map(|(key, entry)| entry.utxo(key.outpoint))
+    }
+
+    // runic entries of the address ordered by rune then outpoint
+    fn runic_range<'a>(&'a self, addr: &'a str) -> impl Iterator<Item = (RuneId, RunicUtxo)> + 'a {
+        self.r
+            .range(RunicKey::first(addr)..)
+            .take_while(move |(key, _)| key.addr == addr)
+            .map(|(key, entry)| (key.runeid, entry.runic_utxo(key.outpoint)))
+    }
+
+    // utxos of a single rune in outpoint order, starting after `start_after`
+    fn rune_range<'a>(
+        &'a self,
+        addr: &'a str,
+        runeid: &'a RuneId,
+        start_after: Option<Outpoint>,
+    ) -> impl Iterator<Item = RunicUtxo> + 'a {
+        let start = RunicKey::new(
+            addr,
+            runeid.clone(),
+            start_after.clone().unwrap_or_else(first_outpoint),
+        );
+        self.r
+            .range(start..)
+            .skip_while(move |(key, _)| Some(&key.outpoint) == start_after.as_ref())
+            .take_while(move |(key, _)| key.addr == addr && key.runeid == *runeid)
+            .map(|(key, entry)| entry.runic_utxo(key.outpoint))
+    }
+
+    fn runic_outpoints(&self, addr: &str) -> HashSet<Outpoint> {
+        self.runic_range(addr)
+            .map(|(_, r_utxo)| r_utxo.utxo.outpoint)
+            .collect()
+    }
+
+    /*
+     * outpoints counted more than once towards the address' balances. keyed by outpoint
+     * a utxo can't be recorded twice for bitcoin or for the same rune, only as both. a
+     * utxo holding several runes is expected under each of them and isn't reported
+     */
+    pub fn duplicate_outpoints(&self, addr: &str) -> Vec<Outpoint> {
+        let runic = self.runic_outpoints(addr);
+        self.btc_range(addr, None)
+            .filter(|utxo| runic.contains(&utxo.outpoint))
+            .map(|utxo| utxo.outpoint)
+            .collect()
+    }
+
+    // keeps a single record per outpoint, runic records win over bitcoin ones
+    pub fn dedupe_address(&mut self, addr: &str) {
+        for outpoint in self.duplicate_outpoints(addr) {
+            self.b.remove(&BtcKey::new(addr, outpoint));
         }
-        self.b.insert(addr, BitcoinUtxos(current_utxos));
     }
 
     pub fn get_bitcoin_utxo(&mut self, addr: &str) -> Option<Utxo> {
-        let addr = String::from(addr);
-        ic_cdk::println!("checking for utxo with lowest balance");
-        let mut utxos = self.b.get(&addr)?.0;
-        let min_utxo = utxos.iter().min_by_key(|utxo| utxo.value)?.clone();
-        ic_cdk::println!("utxo found with balance of: {}", min_utxo.value);
-        utxos.remove(&min_utxo);
-        self.b.insert(addr, BitcoinUtxos(utxos));
-        Some(min_utxo)
+        self.take_bitcoin_utxo(addr, CoinSelection::SmallestFirst)
+    }
+
+    // next utxo in the order of `selection`, branch and bound falls back to largest first
+    pub fn take_bitcoin_utxo(&mut self, addr: &str, selection: CoinSelection) -> Option<Utxo> {
+        let utxos = self.btc_range(addr, None);
+        let utxo = match selection {
+            CoinSelection::LargestFirst | CoinSelection::BranchAndBound => {
+                utxos.max_by_key(|utxo| utxo.value)?
+            }
+            CoinSelection::SmallestFirst => utxos.min_by_key(|utxo| utxo.value)?,
+        };
+        ic_cdk::println!("utxo found with balance of: {}", utxo.value);
+        self.b.remove(&BtcKey::new(addr, utxo.outpoint.clone()));
+        Some(utxo)
+    }
+
+    /*
+     * takes utxos of `addrs` whose values, less `input_cost` each, add up to at least
+     * `target` and at most `target + tolerance`, so the spend needs no change output.
+     * returns the utxos per address in the order of `addrs`, none when no such set
+     * turns up within the search budget
+     */
+    pub fn take_changeless_bitcoin_utxos(
+        &mut self,
+        addrs: &[&str],
+        target: u64,
+        input_cost: u64,
+        tolerance: u64,
+    ) -> Option<Vec<Vec<Utxo>>> {
+        // utxos worth less than spending them never help reaching the target
+        let mut candidates: Vec<(usize, Utxo)> = addrs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, addr)| {
+                self.btc_range(addr, None)
+                    .map(move |utxo| (index, utxo))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|(_, utxo)| utxo.value > input_cost)
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| b.value.cmp(&a.value));
+        let values: Vec<u64> = candidates
+            .iter()
+            .map(|(_, utxo)| utxo.value - input_cost)
+            .collect();
+        let selected = branch_and_bound(&values, target, tolerance)?;
+        let mut taken = vec![vec![]; addrs.len()];
+        for index in selected {
+            let (addr_index, utxo) = candidates[index].clone();
+            taken[addr_index].push(utxo);
+        }
+        for (addr, utxos) in addrs.iter().zip(taken.iter()) {
+            for utxo in utxos {
+                self.b.remove(&BtcKey::new(addr, utxo.outpoint.clone()));
+            }
+        }
+        Some(taken)
     }
 
     pub fn get_runic_utxo(&mut self, addr: &str, runeid: RuneId) -> Option<RunicUtxo> {
-        let addr = String::from(addr);
         ic_cdk::println!("checking for utxo with lowest balance");
-        let mut map = self.r.get(&addr)?.0;
-        let mut utxos = map.remove(&runeid).unwrap_or_default();
-        let min_utxo = utxos.iter().min_by_key(|utxo| utxo.balance)?.clone();
+        let min_utxo = self
+            .rune_range(addr, &runeid, None)
+            .min_by_key(|utxo| utxo.balance)?;
         ic_cdk::println!("utxo found with balance of: {}", min_utxo.balance);
-        utxos.remove(&min_utxo);
-        map.insert(runeid, utxos);
-        self.r.insert(addr, RunicUtxoMap(map));
+        self.r
+            .remove(&RunicKey::new(addr, runeid, min_utxo.utxo.outpoint.clone()));
         Some(min_utxo)
     }
 
-    pub fn is_recorded_as_runic(&self, addr: &str, utxo: &Utxo) -> bool {
-        let addr = String::from(addr);
-        let mut flag = false;
-        if let Some(map) = self.r.get(&addr) {
-            for (_, utxos) in map.0.iter() {
-                if utxos.contains(utxo) {
-                    flag = true;
-                    break;
+    // read-only view of the selection order used by `get_runic_utxo`
+    pub fn runic_utxos(&self, addr: &str, runeid: &RuneId) -> Vec<RunicUtxo> {
+        let mut utxos: Vec<RunicUtxo> = self.rune_range(addr, runeid, None).collect();
+        utxos.sort_by_key(|utxo| utxo.balance);
+        utxos
+    }
+
+    // read-only view of the selection order used by `get_bitcoin_utxo`
+    pub fn bitcoin_utxos(&self, addr: &str) -> Vec<Utxo> {
+        let mut utxos: Vec<Utxo> = self.btc_range(addr, None).collect();
+        utxos.sort_by_key(|utxo| utxo.value);
+        utxos
+    }
+
+    // a page of the address' bitcoin utxos in outpoint order
+    pub fn bitcoin_utxos_page(
+        &self,
+        addr: &str,
+        start_after: Option<Outpoint>,
+        limit: u64,
+    ) -> Vec<Utxo> {
+        self.btc_range(addr, start_after)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect()
+    }
+
+    // a page of the utxos holding the rune in outpoint order
+    pub fn runic_utxos_page(
+        &self,
+        addr: &str,
+        runeid: &RuneId,
+        start_after: Option<Outpoint>,
+        limit: u64,
+    ) -> Vec<RunicUtxo> {
+        self.rune_range(addr, runeid, start_after)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect()
+    }
+
+    // a page of the address' rune balances ordered by rune id
+    pub fn rune_balances_page(
+        &self,
+        addr: &str,
+        start_after: Option<RuneId>,
+        limit: u64,
+    ) -> Vec<(RuneId, u128)> {
+        let start = match start_after {
+            Some(ref runeid) => RunicKey::new(addr, runeid.clone(), first_outpoint()),
+            None => RunicKey::first(addr),
+        };
+        let mut balances: Vec<(RuneId, u128)> = vec![];
+        for (key, entry) in self
+            .r
+            .range(start..)
+            .take_while(|(key, _)| key.addr == addr)
+            .filter(|(key, _)| Some(&key.runeid) != start_after.as_ref())
+        {
+            if let Some((runeid, balance)) = balances.last_mut() {
+                if *runeid == key.runeid {
+                    *balance += entry.balance;
+                    continue;
                 }
             }
+            if balances.len() as u64 == limit.min(MAX_PAGE_SIZE) {
+                break;
+            }
+            balances.push((key.runeid, entry.balance));
         }
-        flag
+        balances
     }
 
-    pub fn get_runestone_balance(&self, addr: &str, runeid: &RuneId) -> u128 {
-        let addr = String::from(addr);
-        let mut balance = 0;
-        if let Some(map) = self.r.get(&addr) {
-            if let Some(utxos) = map.0.get(runeid) {
-                balance = utxos.iter().fold(0, |balance, utxo| balance + utxo.balance);
-            }
+    // runes recorded on the outpoint, sorted by id
+    pub fn runes_of(&self, addr: &str, outpoint: &Outpoint) -> Vec<(RuneId, u128)> {
+        self.runic_range(addr)
+            .filter(|(_, r_utxo)| r_utxo.utxo.outpoint == *outpoint)
+            .map(|(runeid, r_utxo)| (runeid, r_utxo.balance))
+            .collect()
+    }
+
+    // every recorded utxo of the address once, oldest first, with the runes it carries
+    pub fn utxo_infos(&self, addr: &str) -> Vec<UtxoInfo> {
+        let mut infos: BTreeMap<Outpoint, UtxoInfo> = self
+            .btc_range(addr, None)
+            .map(|utxo| {
+                (
+                    utxo.outpoint.clone(),
+                    UtxoInfo {
+                        outpoint: utxo.outpoint,
+                        value: utxo.value,
+                        height: utxo.height,
+                        runes: vec![],
+                    },
+                )
+            })
+            .collect();
+        // ordered by rune, so the runes of every utxo come out sorted
+        for (runeid, r_utxo) in self.runic_range(addr) {
+            infos
+                .entry(r_utxo.utxo.outpoint.clone())
+                .or_insert_with(|| UtxoInfo {
+                    outpoint: r_utxo.utxo.outpoint,
+                    value: r_utxo.utxo.value,
+                    height: r_utxo.utxo.height,
+                    runes: vec![],
+                })
+                .runes
+                .push((runeid, r_utxo.balance));
         }
-        balance
+        let mut infos: Vec<UtxoInfo> = infos.into_values().collect();
+        infos.sort_by(|a, b| (a.height, &a.outpoint).cmp(&(b.height, &b.outpoint)));
+        infos
+    }
+
+    pub fn is_recorded_as_runic(&self, addr: &str, utxo: &Utxo) -> bool {
+        self.runic_range(addr)
+            .any(|(_, r_utxo)| r_utxo.utxo.outpoint == utxo.outpoint)
+    }
+
+    pub fn get_runestone_balance(&self, addr: &str, runeid: &RuneId) -> u128 {
+        self.rune_range(addr, runeid, None)
+            .fold(0, |balance, utxo| balance + utxo.balance)
     }
 
     pub fn get_bitcoin_balance(&self, addr: &str) -> u64 {
-        let addr = String::from(addr);
-        let mut balance = 0;
-        if let Some(utxos) = self.b.get(&addr) {
-            balance = utxos.0.iter().fold(0, |balance, utxo| balance + utxo.value);
-        }
-        balance
+        self.btc_range(addr, None)
+            .fold(0, |balance, utxo| balance + utxo.value)
     }
 
     pub fn all_rune_with_balances(&self, addr: &str) -> HashMap<RuneId, u128> {
-        let addr = String::from(addr);
         let mut balances = HashMap::new();
-        if let Some(map) = self.r.get(&addr) {
-            for (r, utxos) in map.0.iter() {
-                let balance = utxos.iter().fold(0, |balance, utxo| balance + utxo.balance);
-                balances.insert(r.clone(), balance);
-            }
+        for (runeid, r_utxo) in self.runic_range(addr) {
+            *balances.entry(runeid).or_insert(0) += r_utxo.balance;
         }
         balances
     }
 
-    pub fn remove_btc_utxo(&mut self, addr: &str, utxo: &Utxo) {
-        let addr = String::from(addr);
-        let mut current_utxos = self.b.get(&addr).unwrap_or_default().0;
-        ic_cdk::println!("btx utxo's len before removal: {}", current_utxos.len());
-        current_utxos.remove(utxo);
-        ic_cdk::println!("btc utxo's len after removal: {}", current_utxos.len());
-        self.b.insert(addr, BitcoinUtxos(current_utxos));
+    // removes the utxo from both bitcoin and runic records, returns whether it was found
+    pub fn remove_utxo(&mut self, addr: &str, outpoint: &Outpoint) -> bool {
+        let mut found = self
+            .b
+            .remove(&BtcKey::new(addr, outpoint.clone()))
+            .is_some();
+        let runes: Vec<RuneId> = self
+            .runic_range(addr)
+            .filter(|(_, r_utxo)| r_utxo.utxo.outpoint == *outpoint)
+            .map(|(runeid, _)| runeid)
+            .collect();
+        for runeid in runes {
+            found |= self
+                .r
+                .remove(&RunicKey::new(addr, runeid, outpoint.clone()))
+                .is_some();
+        }
+        found
+    }
+
+    // every address holding recorded utxos
+    pub fn addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<String> = vec![];
+        // keys are ordered by address, so repeats are always adjacent
+        for addr in self.b.iter().map(|(key, _)| key.addr) {
+            if addresses.last() != Some(&addr) {
+                addresses.push(addr);
+            }
+        }
+        let mut runic: Vec<String> = vec![];
+        for addr in self.r.iter().map(|(key, _)| key.addr) {
+            if runic.last() != Some(&addr) {
+                runic.push(addr);
+            }
+        }
+        for addr in runic {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses
+    }
+
+    // sats held by the address, runic utxos included
+    pub fn total_value(&self, addr: &str) -> u64 {
+        let mut seen = HashSet::new();
+        let mut total = self.get_bitcoin_balance(addr);
+        for (_, r_utxo) in self.runic_range(addr) {
+            // a utxo holding several runes is recorded once per rune
+            if seen.insert(r_utxo.utxo.outpoint.clone()) {
+                total += r_utxo.utxo.value;
+            }
+        }
+        total
+    }
+
+    // which of `outpoints` are recorded for the address, each listed once
+    pub fn recorded_among(&self, addr: &str, outpoints: &HashSet<Outpoint>) -> Vec<Outpoint> {
+        let mut recorded: Vec<Outpoint> = self
+            .btc_range(addr, None)
+            .map(|utxo| utxo.outpoint)
+            .chain(self.runic_outpoints(addr))
+            .filter(|outpoint| outpoints.contains(outpoint))
+            .collect();
+        recorded.sort();
+        recorded.dedup();
+        recorded
+    }
+
+    pub fn is_recorded(&self, addr: &str, utxo: &Utxo) -> bool {
+        self.b
+            .contains_key(&BtcKey::new(addr, utxo.outpoint.clone()))
+            || self.is_recorded_as_runic(addr, utxo)
+    }
+
+    // drops the utxos above `height` missing from `unspent`, they were spent or reorged out
+    pub fn prune_spent(&mut self, addr: &str, height: u32, unspent: &HashSet<Outpoint>) {
+        let stale: Vec<Outpoint> = self
+            .btc_range(addr, None)
+            .chain(self.runic_range(addr).map(|(_, r_utxo)| r_utxo.utxo))
+            .filter(|utxo| utxo.height > height && !unspent.contains(&utxo.outpoint))
+            .map(|utxo| utxo.outpoint)
+            .collect();
+        for outpoint in stale.iter() {
+            self.remove_utxo(addr, outpoint);
+        }
+    }
+
+    pub fn clear_address(&mut self, addr: &str) {
+        let btc: Vec<BtcKey> = self
+            .btc_range(addr, None)
+            .map(|utxo| BtcKey::new(addr, utxo.outpoint))
+            .collect();
+        for key in btc {
+            self.b.remove(&key);
+        }
+        let runic: Vec<RunicKey> = self
+            .runic_range(addr)
+            .map(|(runeid, r_utxo)| RunicKey::new(addr, runeid, r_utxo.utxo.outpoint))
+            .collect();
+        for key in runic {
+            self.r.remove(&key);
+        }
+    }
+}
+
+// upper bound on the branches `branch_and_bound` walks before settling for its best find
+const BNB_MAX_TRIES: usize = 100_000;
+
+/*
+ * depth first search over `values`, sorted largest first, for a subset summing into
+ * `target..=target + tolerance`. every branch includes the next value before it tries
+ * leaving it out, and gets cut as soon as it overshoots the window or the values left
+ * can no longer reach the target. returns the indices of the subset with the least
+ * excess found
+*/
+fn branch_and_bound(values: &[u64], target: u64, tolerance: u64) -> Option<Vec<usize>> {
+    let mut available: u64 = values.iter().sum();
+    let mut value = 0;
+    let mut selection: Vec<usize> = vec![];
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    let mut index = 0;
+    for _ in 0..BNB_MAX_TRIES {
+        let backtrack = if value + available < target || value > target + tolerance {
+            true
+        } else if value >= target {
+            let excess = value - target;
+            let improves = match &best {
+                Some((best_excess, _)) => excess < *best_excess,
+                None => true,
+            };
+            if improves {
+                best = Some((excess, selection.clone()));
+            }
+            if excess == 0 {
+                break;
+            }
+            true
+        } else {
+            false
+        };
+        if backtrack {
+            let Some(last) = selection.pop() else {
+                break;
+            };
+            // values left out below the last included one count as available again
+            available += values[last + 1..index].iter().sum::<u64>();
+            value -= values[last];
+            index = last + 1;
+        } else {
+            available -= values[index];
+            value += values[index];
+            selection.push(index);
+            index += 1;
+        }
+    }
+    best.map(|(_, selection)| selection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected(values: &[u64], target: u64, tolerance: u64) -> Option<Vec<u64>> {
+        branch_and_bound(values, target, tolerance)
+            .map(|indices| indices.into_iter().map(|index| values[index]).collect())
+    }
+
+    #[test]
+    fn branch_and_bound_exact_match() {
+        assert_eq!(selected(&[8, 5, 3, 1], 9, 0), Some(vec![8, 1]));
+        assert_eq!(selected(&[5, 3, 2], 5, 0), Some(vec![5]));
+    }
+
+    #[test]
+    fn branch_and_bound_prefers_the_least_excess() {
+        assert_eq!(selected(&[7, 6, 5], 5, 3), Some(vec![5]));
+    }
+
+    #[test]
+    fn branch_and_bound_no_solution() {
+        assert_eq!(selected(&[10, 7], 5, 1), None);
+        assert_eq!(selected(&[2, 1], 5, 0), None);
+    }
+
+    #[test]
+    fn branch_and_bound_tolerance_boundary() {
+        assert_eq!(selected(&[6], 5, 1), Some(vec![6]));
+        assert_eq!(selected(&[6], 5, 0), None);
+        assert_eq!(selected(&[7], 5, 1), None);
+    }
+
+    #[test]
+    fn branch_and_bound_empty_input() {
+        assert_eq!(selected(&[], 5, 0), None);
+    }
+
+    #[test]
+    fn branch_and_bound_gives_up_within_budget() {
+        // every subset sums to an even value, the search space is far beyond the budget
+        assert_eq!(selected(&[2; 60], 61, 0), None);
     }
 }
@@ -0,0 +1,53 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager, OutboxStatus, TransactionKind,
+};
+use crate::types::RuneId;
+
+// what the webhook gets posted about a submitted withdrawal
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WithdrawalNotice {
+    pub txid: String,
+    pub caller: Principal,
+    pub kind: TransactionKind,
+    pub fee: u64,
+    pub amount: Option<u64>,
+    pub runes: Vec<(RuneId, u128)>,
+    pub submitted_at: u64,
+}
+
+// a pending post to the webhook, removed once the endpoint accepted it
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WebhookDelivery {
+    pub notice: WithdrawalNotice,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    pub status: OutboxStatus,
+}
+
+impl Storable for WebhookDelivery {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by txid, a withdrawal is queued at most once however often it's broadcast
+pub type WebhookQueue = StableBTreeMap<String, WebhookDelivery, Memory>;
+
+pub fn init_webhook_queue() -> WebhookQueue {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::WebhookQueue.into());
+        WebhookQueue::init(memory)
+    })
+}
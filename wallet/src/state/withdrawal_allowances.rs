@@ -0,0 +1,46 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::types::RuneId;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager, DailyLimits,
+};
+
+// what the principal withdrew on `day`, counted in days since the unix epoch
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct DailyUsage {
+    pub day: u64,
+    pub sats: u64,
+    pub runes: Vec<(RuneId, u128)>,
+}
+
+// `limits` overrides the configured defaults for the principal
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct WithdrawalAllowance {
+    pub limits: Option<DailyLimits>,
+    pub usage: DailyUsage,
+}
+
+impl Storable for WithdrawalAllowance {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type WithdrawalAllowanceMap = StableBTreeMap<Principal, WithdrawalAllowance, Memory>;
+
+pub fn init_withdrawal_allowance_map() -> WithdrawalAllowanceMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::WithdrawalAllowances.into());
+        WithdrawalAllowanceMap::init(memory)
+    })
+}
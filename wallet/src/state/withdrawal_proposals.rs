@@ -0,0 +1,61 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use super::{
+    memory::{Memory, MemoryIds},
+    read_memory_manager,
+};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum ProposalStatus {
+    Pending,
+    // quorum reached, the withdrawal is being built and submitted
+    Approved,
+    Executed { txid: String },
+    Failed { reason: String },
+    Cancelled,
+    Expired,
+}
+
+// withdrawal above the approval threshold, waiting for the approvers' quorum
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WithdrawalProposal {
+    pub id: u64,
+    pub caller: Principal,
+    pub to: String,
+    pub amount: u64,
+    pub fee_per_vbytes: Option<u64>,
+    pub approvals: Vec<Principal>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub status: ProposalStatus,
+}
+
+impl WithdrawalProposal {
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, ProposalStatus::Pending)
+    }
+}
+
+impl Storable for WithdrawalProposal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("should decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// keyed by proposal id, ids are handed out in creation order
+pub type WithdrawalProposalMap = StableBTreeMap<u64, WithdrawalProposal, Memory>;
+
+pub fn init_withdrawal_proposal_map() -> WithdrawalProposalMap {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::WithdrawalProposals.into());
+        WithdrawalProposalMap::init(memory)
+    })
+}
@@ -0,0 +1,330 @@
+use std::collections::{BTreeMap, HashSet};
+
+use candid::{CandidType, Principal};
+
+use crate::{
+    state::{
+        read_deposits, read_principal_events, read_transaction_log, EventKind, TransactionStatus,
+    },
+    types::RuneId,
+    utils::{generate_addresses_from_principal, generate_change_addresses_from_principal},
+};
+
+#[derive(CandidType, Clone, Copy, PartialEq, Eq)]
+pub enum StatementEntryKind {
+    Deposit,
+    Withdrawal,
+    TransferIn,
+    TransferOut,
+}
+
+#[derive(CandidType)]
+pub struct StatementEntry {
+    pub timestamp: u64,
+    pub txid: String,
+    pub kind: StatementEntryKind,
+    pub btc: u64,
+    pub runes: Vec<(RuneId, u128)>,
+    // fee charged to the principal by this entry
+    pub fee: u64,
+    pub counterparty: Option<Principal>,
+}
+
+#[derive(CandidType, Default)]
+pub struct FlowSummary {
+    pub deposited: u128,
+    pub withdrawn: u128,
+    pub transferred_in: u128,
+    pub transferred_out: u128,
+    // inflows minus outflows, bitcoin fees included
+    pub net: i128,
+}
+
+impl FlowSummary {
+    fn add(&mut self, kind: StatementEntryKind, value: u128) {
+        match kind {
+            StatementEntryKind::Deposit => self.deposited += value,
+            StatementEntryKind::Withdrawal => self.withdrawn += value,
+            StatementEntryKind::TransferIn => self.transferred_in += value,
+            StatementEntryKind::TransferOut => self.transferred_out += value,
+        }
+        match kind {
+            StatementEntryKind::Deposit | StatementEntryKind::TransferIn => {
+                self.net += value as i128
+            }
+            StatementEntryKind::Withdrawal | StatementEntryKind::TransferOut => {
+                self.net -= value as i128
+            }
+        }
+    }
+}
+
+#[derive(CandidType)]
+pub struct Statement {
+    pub principal: Principal,
+    pub from_ts: u64,
+    pub to_ts: u64,
+    pub btc: FlowSummary,
+    pub fees_paid: u64,
+    pub runes: Vec<(RuneId, FlowSummary)>,
+    pub entries: Vec<StatementEntry>,
+}
+
+/*
+ * aggregates the principal's flows within [from_ts, to_ts].
+ * deposits come from the updater's detection, everything else from the transaction log.
+ * simulated (paper trading) transactions never moved funds and are left out
+*/
+pub fn get_statement(principal: Principal, from_ts: u64, to_ts: u64) -> Statement {
+    let addresses: HashSet<String> = [
+        generate_addresses_from_principal(&principal).bitcoin,
+        generate_change_addresses_from_principal(&principal).bitcoin,
+    ]
+    .into_iter()
+    .collect();
+
+    let mut entries: Vec<StatementEntry> = read_deposits(|deposits| {
+        deposits
+            .iter()
+            .map(|(_, deposit)| deposit)
+            .filter(|deposit| {
+                !deposit.own
+                    && deposit.timestamp >= from_ts
+                    && deposit.timestamp <= to_ts
+                    && addresses.contains(&deposit.address)
+            })
+            .map(|deposit| StatementEntry {
+                timestamp: deposit.timestamp,
+                txid: deposit.txid,
+                kind: StatementEntryKind::Deposit,
+                btc: deposit.value,
+                runes: deposit.runes,
+                fee: 0,
+                counterparty: None,
+            })
+            .collect()
+    });
+
+    let records = read_transaction_log(|log| log.records_between(from_ts, to_ts));
+    for record in records {
+        if record.status == TransactionStatus::Simulated {
+            continue;
+        }
+        let kind = if record.caller == principal {
+            match record.counterparty {
+                Some(_) => StatementEntryKind::TransferOut,
+                None => StatementEntryKind::Withdrawal,
+            }
+        } else if record.counterparty == Some(principal) {
+            StatementEntryKind::TransferIn
+        } else {
+            continue;
+        };
+        let fee_payer = record.fee_payer.unwrap_or(record.caller);
+        let counterparty = match kind {
+            StatementEntryKind::TransferIn => Some(record.caller),
+            _ => record.counterparty,
+        };
+        entries.push(StatementEntry {
+            timestamp: record.timestamp,
+            txid: record.txid,
+            kind,
+            btc: record.amount.unwrap_or_default(),
+            runes: record
+                .rune
+                .into_iter()
+                .chain(record.additional_runes.unwrap_or_default())
+                .collect(),
+            fee: if fee_payer == principal {
+                record.fee
+            } else {
+                0
+            },
+            counterparty,
+        });
+    }
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    let mut btc = FlowSummary::default();
+    let mut runes: BTreeMap<RuneId, FlowSummary> = BTreeMap::new();
+    let mut fees_paid = 0;
+    for entry in entries.iter() {
+        btc.add(entry.kind, entry.btc as u128);
+        for (runeid, amount) in entry.runes.iter() {
+            runes
+                .entry(runeid.clone())
+                .or_default()
+                .add(entry.kind, *amount);
+        }
+        fees_paid += entry.fee;
+    }
+    btc.net -= fees_paid as i128;
+
+    Statement {
+        principal,
+        from_ts,
+        to_ts,
+        btc,
+        fees_paid,
+        runes: runes.into_iter().collect(),
+        entries,
+    }
+}
+
+const CSV_HEADER: &str = "date,timestamp,type,txid,asset,amount,fee,counterparty\n";
+
+// utc date and time of a timestamp in nanoseconds, as iso 8601
+fn iso_8601(timestamp: u64) -> String {
+    let secs = timestamp / 1_000_000_000;
+    let (days, time) = ((secs / 86_400) as i64, secs % 86_400);
+    // civil date of days since the unix epoch, the proleptic gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+fn rune_asset(runeid: &RuneId) -> String {
+    format!("{}:{}", runeid.block, runeid.tx)
+}
+
+/*
+ * renders the principal's events within [from_ts, to_ts] as csv, one row per asset
+ * an event moved. amounts are signed in the asset's smallest unit, outflows negative,
+ * and the bitcoin fee of a row comes on top of its amount. withdrawals from before
+ * the event log carried amounts only show their fee, queued broadcasts show up once
+ * they went out
+*/
+pub fn export_csv(principal: Principal, from_ts: u64, to_ts: u64) -> Vec<u8> {
+    let mut csv = String::from(CSV_HEADER);
+    let events = read_principal_events(principal, 0, u64::MAX);
+    for event in events
+        .into_iter()
+        .filter(|event| event.timestamp >= from_ts && event.timestamp <= to_ts)
+    {
+        let mut row = |kind: &str,
+                       txid: &str,
+                       asset: String,
+                       amount: i128,
+                       fee: u64,
+                       counterparty: Option<Principal>| {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                iso_8601(event.timestamp),
+                event.timestamp,
+                kind,
+                txid,
+                asset,
+                amount,
+                fee,
+                counterparty
+                    .map(|counterparty| counterparty.to_text())
+                    .unwrap_or_default(),
+            ));
+        };
+        match event.kind {
+            EventKind::DepositDetected {
+                txid, value, runes, ..
+            } => {
+                row(
+                    "deposit",
+                    &txid,
+                    String::from("BTC"),
+                    value as i128,
+                    0,
+                    None,
+                );
+                for (runeid, amount) in runes {
+                    row(
+                        "deposit",
+                        &txid,
+                        rune_asset(&runeid),
+                        amount as i128,
+                        0,
+                        None,
+                    );
+                }
+            }
+            EventKind::WithdrawalSubmitted {
+                txid,
+                fee,
+                status: TransactionStatus::Submitted,
+                amount,
+                runes,
+                counterparty,
+                ..
+            } => {
+                // the receiver of an internal transfer sees it as incoming, without the fee
+                let (kind, sign, fee, counterparty) = if event.principal == Some(principal) {
+                    ("withdrawal", -1, fee, counterparty)
+                } else {
+                    ("transfer_in", 1, 0, event.principal)
+                };
+                let amount = amount.unwrap_or_default() as i128;
+                row(
+                    kind,
+                    &txid,
+                    String::from("BTC"),
+                    sign * amount,
+                    fee,
+                    counterparty,
+                );
+                for (runeid, amount) in runes.unwrap_or_default() {
+                    row(
+                        kind,
+                        &txid,
+                        rune_asset(&runeid),
+                        sign * amount as i128,
+                        0,
+                        counterparty,
+                    );
+                }
+            }
+            // the deposit was booked when detected, sweeping it into the pool only costs the fee
+            EventKind::DepositPooled {
+                txid,
+                amount,
+                credited,
+                ..
+            } => row(
+                "pooled",
+                &txid,
+                String::from("BTC"),
+                0,
+                amount.saturating_sub(credited),
+                None,
+            ),
+            // the runes the sponsor took for funding a withdrawal's fee
+            EventKind::FeeSponsored {
+                sponsor,
+                runeid,
+                rune_amount,
+                txid,
+                ..
+            } => row(
+                "fee_sponsored",
+                &txid,
+                rune_asset(&runeid),
+                -(rune_amount as i128),
+                0,
+                Some(sponsor),
+            ),
+            _ => {}
+        }
+    }
+    csv.into_bytes()
+}
@@ -0,0 +1,186 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    outbox::{backoff, MAX_ATTEMPTS},
+    state::{
+        read_submission_queue, record_event, write_submission_queue, write_transaction_log,
+        EventKind, LockedUtxos, OutboxStatus, PendingSubmission, TransactionRecord,
+        TransactionStatus,
+    },
+    transaction_handler,
+    types::WalletError,
+};
+
+const RETRY_INTERVAL_SECS: u64 = 60;
+
+thread_local! {
+    static RETRY_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static RETRYING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped retry round doesn't block the following ones
+struct RetryGuard;
+
+impl RetryGuard {
+    fn acquire() -> Option<Self> {
+        if RETRYING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for RetryGuard {
+    fn drop(&mut self) {
+        RETRYING.set(false);
+    }
+}
+
+pub fn start_retries() {
+    RETRY_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(RETRY_INTERVAL_SECS),
+            || ic_cdk::spawn(retry()),
+        ));
+    });
+}
+
+/*
+ * keeps the signed transaction before it's handed to the bitcoin canister. the first
+ * retry is only due after a backoff, so a broadcast still awaiting its answer isn't
+ * sent a second time, and one that trapped past the call gets picked up from here
+ */
+pub fn enqueue(record: &TransactionRecord, raw_transaction: &[u8], locked: &[LockedUtxos]) {
+    let now = ic_cdk::api::time();
+    write_submission_queue(|queue| {
+        queue.insert(
+            record.txid.clone(),
+            PendingSubmission {
+                record: record.clone(),
+                raw_transaction: raw_transaction.to_vec(),
+                locked: locked.to_vec(),
+                attempts: 0,
+                next_attempt_at: now + backoff(1).as_nanos() as u64,
+                last_error: None,
+                status: OutboxStatus::Pending,
+            },
+        )
+    });
+}
+
+pub fn remove(txid: &str) {
+    write_submission_queue(|queue| queue.remove(&txid.to_string()));
+}
+
+// counts a failed broadcast of a queued transaction, dead-lettered after `MAX_ATTEMPTS`
+pub fn record_failure(txid: &str, err: String) {
+    let Some(mut submission) = read_submission_queue(|queue| queue.get(&txid.to_string())) else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    submission.attempts += 1;
+    submission.last_error = Some(err);
+    if submission.attempts >= MAX_ATTEMPTS {
+        submission.status = OutboxStatus::DeadLettered { at: now };
+        record_event(EventKind::SubmissionDeadLettered {
+            txid: txid.to_string(),
+            attempts: submission.attempts,
+        });
+    } else {
+        submission.next_attempt_at = now + backoff(submission.attempts).as_nanos() as u64;
+    }
+    write_submission_queue(|queue| queue.insert(txid.to_string(), submission));
+}
+
+// broadcasts the due transactions again, the utxos they spend stay locked meanwhile
+pub async fn retry() {
+    let Some(_guard) = RetryGuard::acquire() else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let due: Vec<PendingSubmission> = read_submission_queue(|queue| {
+        queue
+            .iter()
+            .map(|(_, submission)| submission)
+            .filter(|submission| submission.status.is_due(submission.next_attempt_at, now))
+            .collect()
+    });
+    for mut submission in due {
+        let txid = submission.record.txid.clone();
+        match transaction_handler::send_transaction(&submission.raw_transaction).await {
+            Ok(()) => {
+                remove(&txid);
+                submission.record.status = TransactionStatus::Submitted;
+                submission.record.timestamp = ic_cdk::api::time();
+                write_transaction_log(|log| log.set_status(&txid, TransactionStatus::Submitted));
+                transaction_handler::record_submission(&submission.record);
+                transaction_handler::on_submitted(
+                    &submission.record,
+                    &submission.raw_transaction,
+                    &submission.locked,
+                );
+            }
+            Err(err) => record_failure(&txid, err),
+        }
+    }
+}
+
+/*
+ * makes every queued transaction due right away, dead-lettered ones start over with
+ * their attempts. one whose first broadcast is still awaiting its answer is left
+ * alone. returns how many got queued for the immediate round
+ */
+pub fn retry_now() -> u64 {
+    let now = ic_cdk::api::time();
+    let queued = write_submission_queue(|queue| {
+        let submissions: Vec<PendingSubmission> = queue
+            .iter()
+            .map(|(_, submission)| submission)
+            .filter(|submission| submission.attempts > 0)
+            .collect();
+        let queued = submissions.len() as u64;
+        for mut submission in submissions {
+            if submission.status.is_dead_lettered() {
+                submission.attempts = 0;
+                submission.status = OutboxStatus::Pending;
+            }
+            submission.next_attempt_at = now;
+            queue.insert(submission.record.txid.clone(), submission);
+        }
+        queued
+    });
+    if queued > 0 {
+        ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(retry()));
+    }
+    queued
+}
+
+/*
+ * gives up on a dead-lettered transaction, the utxos it spends go back to the manager.
+ * should it get mined after all, its spends show up once the addresses are synced
+ */
+pub fn abandon(txid: &str) -> Result<(), WalletError> {
+    let submission = read_submission_queue(|queue| queue.get(&txid.to_string()))
+        .ok_or(WalletError::TransactionNotFound)?;
+    if !submission.status.is_dead_lettered() {
+        return Err(WalletError::InvalidArgument(String::from(
+            "only dead-lettered transactions can be abandoned",
+        )));
+    }
+    remove(txid);
+    transaction_handler::release_locked_utxos(submission.locked);
+    write_transaction_log(|log| log.set_status(txid, TransactionStatus::Abandoned));
+    record_event(EventKind::SubmissionAbandoned {
+        txid: txid.to_string(),
+    });
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use candid::{CandidType, Principal};
+
+use crate::{
+    state::{
+        read_balance_subscriptions, read_config, write_balance_subscriptions, BalanceSubscription,
+        DepositRecord,
+    },
+    types::WalletError,
+    utils::{
+        generate_addresses_from_principal, generate_change_addresses_from_principal,
+        generate_taproot_addresses_from_principal,
+    },
+};
+
+// method every callback canister exposes, called one-way with a `BalanceChange`
+const CALLBACK_METHOD: &str = "wallet_balance_changed";
+
+// callbacks a single principal may register
+pub const MAX_CALLBACKS: usize = 8;
+
+#[derive(CandidType)]
+pub struct BalanceChange {
+    pub principal: Principal,
+    pub deposit: DepositRecord,
+}
+
+// every address a deposit for the principal can land on
+fn tracked_addresses(principal: &Principal) -> Vec<String> {
+    let mut addresses = vec![
+        generate_addresses_from_principal(principal).bitcoin,
+        generate_change_addresses_from_principal(principal).bitcoin,
+    ];
+    if read_config(|config| config.schnorr_public_key.is_some()) {
+        addresses.push(generate_taproot_addresses_from_principal(principal).bitcoin);
+    }
+    addresses
+}
+
+pub fn callbacks_of(principal: &Principal) -> Vec<Principal> {
+    let address = generate_addresses_from_principal(principal).bitcoin;
+    read_balance_subscriptions(|subscriptions| subscriptions.get(&address))
+        .map(|subscription| subscription.callbacks)
+        .unwrap_or_default()
+}
+
+pub fn subscribe(principal: Principal, callback: Principal) -> Result<(), WalletError> {
+    let callbacks = callbacks_of(&principal);
+    if callbacks.contains(&callback) {
+        return Ok(());
+    }
+    if callbacks.len() >= MAX_CALLBACKS {
+        return Err(WalletError::InvalidArgument(format!(
+            "at most {} callbacks per principal",
+            MAX_CALLBACKS
+        )));
+    }
+    write_balance_subscriptions(|subscriptions| {
+        for address in tracked_addresses(&principal) {
+            let mut subscription =
+                subscriptions
+                    .get(&address)
+                    .unwrap_or_else(|| BalanceSubscription {
+                        principal,
+                        callbacks: vec![],
+                    });
+            if !subscription.callbacks.contains(&callback) {
+                subscription.callbacks.push(callback);
+            }
+            subscriptions.insert(address, subscription);
+        }
+    });
+    Ok(())
+}
+
+pub fn unsubscribe(principal: Principal, callback: Principal) {
+    write_balance_subscriptions(|subscriptions| {
+        for address in tracked_addresses(&principal) {
+            let Some(mut subscription) = subscriptions.get(&address) else {
+                continue;
+            };
+            subscription
+                .callbacks
+                .retain(|registered| *registered != callback);
+            if subscription.callbacks.is_empty() {
+                subscriptions.remove(&address);
+            } else {
+                subscriptions.insert(address, subscription);
+            }
+        }
+    });
+}
+
+/*
+ * pushes the deposit to the callbacks registered for its address. the calls are
+ * one-way, a callback that is down or rejects misses the change and has to fall
+ * back to reading the balances
+*/
+pub fn notify_deposit(deposit: &DepositRecord) {
+    let Some(subscription) =
+        read_balance_subscriptions(|subscriptions| subscriptions.get(&deposit.address))
+    else {
+        return;
+    };
+    for callback in subscription.callbacks {
+        let change = BalanceChange {
+            principal: subscription.principal,
+            deposit: deposit.clone(),
+        };
+        if let Err(code) = ic_cdk::notify(callback, CALLBACK_METHOD, (change,)) {
+            ic_cdk::println!("failed notifying {} of a deposit: {:?}", callback, code);
+        }
+    }
+}
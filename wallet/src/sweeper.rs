@@ -0,0 +1,90 @@
+use std::{cell::RefCell, time::Duration};
+
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin::{self, get_fee_per_vbyte, Branch, Intent, TxPlanner},
+    state::{read_config, read_utxo_manager, record_event, EventKind},
+    transaction_handler::SubmittedTransactionIdType,
+    types::WalletError,
+    updater::{self, TargetType},
+    utils::fee_pool_addresses,
+};
+
+// sweeps below this aren't worth the fee
+const MIN_SWEEP_AMOUNT: u64 = 10_000;
+
+thread_local! {
+    static SWEEP_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+// (re)schedules the sweeps according to the current policy
+pub fn start_sweeping() {
+    let policy = read_config(|config| config.sweep_policy.clone());
+    SWEEP_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        if let Some(policy) = policy.filter(|policy| policy.enabled) {
+            *timer = Some(ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(policy.interval_mins * 60),
+                || {
+                    ic_cdk::spawn(async {
+                        if let Err(err) = sweep(None).await {
+                            ic_cdk::println!("scheduled sweep failed: {:?}", err);
+                        }
+                    })
+                },
+            ));
+        }
+    });
+}
+
+/*
+ * sends the hot wallet's excess above the ceiling to the vault, capped by the per-sweep limit.
+ * `amount_override` comes from a controller and bypasses both the ceiling and the limit
+*/
+pub async fn sweep(amount_override: Option<u64>) -> Result<Option<String>, WalletError> {
+    let policy = read_config(|config| config.sweep_policy.clone())
+        .ok_or_else(|| WalletError::InvalidArgument(String::from("no sweep policy set")))?;
+    let vault =
+        bitcoin::address_validation(&policy.vault_address).map_err(WalletError::InvalidAddress)?;
+    let hot_wallet = fee_pool_addresses();
+    let from =
+        bitcoin::address_validation(&hot_wallet.bitcoin).map_err(WalletError::InvalidAddress)?;
+    updater::fetch_utxos_and_update_balances(
+        &hot_wallet.bitcoin,
+        TargetType::Bitcoin { target: u64::MAX },
+    )
+    .await;
+    let balance = read_utxo_manager(|manager| manager.get_bitcoin_balance(&hot_wallet.bitcoin));
+    let amount = match amount_override {
+        Some(amount) => amount,
+        None => balance
+            .saturating_sub(policy.hot_wallet_ceiling)
+            .min(policy.max_per_sweep),
+    };
+    if amount < MIN_SWEEP_AMOUNT {
+        return Ok(None);
+    }
+    let fee_per_vbytes = get_fee_per_vbyte().await;
+    // the hot wallet keeps its change on the same address
+    let txn = TxPlanner::new(
+        Branch {
+            addr: &hot_wallet.bitcoin,
+            account: hot_wallet.icrc1,
+            address: from,
+        },
+        fee_per_vbytes,
+    )
+    .with(Intent::SendBtc { to: vault, amount })
+    .plan()?;
+    let SubmittedTransactionIdType::Bitcoin { txid, .. } = txn.build_and_submit(None).await?;
+    record_event(EventKind::ColdStorageSweep {
+        vault_address: policy.vault_address,
+        amount,
+        txid: txid.clone(),
+        manual: amount_override.is_some(),
+    });
+    Ok(Some(txid))
+}
@@ -0,0 +1,165 @@
+use std::{cell::RefCell, time::Duration};
+
+use ::bitcoin::Address;
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin::bitcoin_network,
+    state::{read_template_reservations, write_template_reservations, TemplateReservation},
+    transaction_handler::{release_locked_utxos, TransactionType},
+    types::{
+        SigningInstruction, SigningScheme, TemplateInput, TemplateOutput, UnsignedTemplate,
+        WalletError,
+    },
+};
+
+// how long the inputs of an unsigned template stay reserved
+const TEMPLATE_TTL_SECS: u64 = 5 * 60;
+
+const EXPIRY_INTERVAL_SECS: u64 = 60;
+
+thread_local! {
+    static EXPIRY_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+pub fn start_expiry() {
+    EXPIRY_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(EXPIRY_INTERVAL_SECS),
+            expire,
+        ));
+    });
+}
+
+// holds the utxos taken by `txn` and describes what committing it would sign
+pub fn reserve(txn: TransactionType) -> Result<UnsignedTemplate, WalletError> {
+    txn.ensure_within_limits()?;
+    let Some((unsigned, selection)) = txn.to_reserved() else {
+        release_locked_utxos(txn.locked_utxos());
+        return Err(WalletError::InvalidArgument(String::from(
+            "transaction can't be held as a template",
+        )));
+    };
+    let network = bitcoin_network();
+    let address_of = |script_pubkey: &::bitcoin::Script| {
+        Address::from_script(script_pubkey, network)
+            .ok()
+            .map(|address| address.to_string())
+    };
+    let spent_outputs = txn.spent_outputs();
+    let inputs = unsigned
+        .input
+        .iter()
+        .zip(spent_outputs.iter())
+        .map(|(input, spent)| TemplateInput {
+            txid: input.previous_output.txid.to_string(),
+            vout: input.previous_output.vout,
+            value: spent.value.to_sat(),
+            address: address_of(&spent.script_pubkey).unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    let signing_instructions = spent_outputs
+        .iter()
+        .enumerate()
+        .map(|(index, spent)| SigningInstruction {
+            input: index as u32,
+            address: address_of(&spent.script_pubkey).unwrap_or_default(),
+            scheme: if spent.script_pubkey.is_p2tr() {
+                SigningScheme::Schnorr
+            } else {
+                SigningScheme::Ecdsa
+            },
+        })
+        .collect();
+    let outputs = unsigned
+        .output
+        .iter()
+        .map(|output| TemplateOutput {
+            script_pubkey: output.script_pubkey.to_bytes(),
+            value: output.value.to_sat(),
+            address: address_of(&output.script_pubkey),
+        })
+        .collect::<Vec<_>>();
+    let spent: u64 = inputs.iter().map(|input| input.value).sum();
+    let sent: u64 = outputs.iter().map(|output| output.value).sum();
+    let expires_at = ic_cdk::api::time() + TEMPLATE_TTL_SECS * 1_000_000_000;
+    let id = write_template_reservations(|reservations| {
+        let id = reservations.last_key_value().map_or(0, |(id, _)| id + 1);
+        reservations.insert(
+            id,
+            TemplateReservation {
+                caller: ic_cdk::caller(),
+                selection,
+                locked: txn.locked_utxos(),
+                expires_at,
+            },
+        );
+        id
+    });
+    Ok(UnsignedTemplate {
+        id,
+        unsigned_transaction: ::bitcoin::consensus::serialize(&unsigned),
+        inputs,
+        outputs,
+        fee: spent.saturating_sub(sent),
+        signing_instructions,
+        expires_at,
+    })
+}
+
+fn take(id: u64, caller: Principal) -> Result<TemplateReservation, WalletError> {
+    match read_template_reservations(|reservations| reservations.get(&id)) {
+        None => Err(WalletError::TransactionNotFound),
+        Some(reservation) if reservation.caller != caller => Err(WalletError::Unauthorized),
+        Some(_) => write_template_reservations(|reservations| reservations.remove(&id))
+            .ok_or(WalletError::TransactionNotFound),
+    }
+}
+
+/*
+ * signs the template's transaction as a prepared withdrawal, broadcast it with
+ * `broadcast_withdrawal`. expired templates are gone along with their reservation
+ */
+pub async fn commit(id: u64, caller: Principal) -> Result<String, WalletError> {
+    let reservation = take(id, caller)?;
+    if reservation.expires_at <= ic_cdk::api::time() {
+        release_locked_utxos(reservation.locked);
+        return Err(WalletError::TransactionNotFound);
+    }
+    let txn = match TransactionType::from_reserved(reservation.selection) {
+        Ok(txn) => txn,
+        Err(e) => {
+            release_locked_utxos(reservation.locked);
+            return Err(e);
+        }
+    };
+    txn.prepare(None).await
+}
+
+pub fn release(id: u64, caller: Principal) -> Result<(), WalletError> {
+    let reservation = take(id, caller)?;
+    release_locked_utxos(reservation.locked);
+    Ok(())
+}
+
+fn expire() {
+    let now = ic_cdk::api::time();
+    let expired: Vec<u64> = read_template_reservations(|reservations| {
+        reservations
+            .iter()
+            .filter(|(_, reservation)| reservation.expires_at <= now)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    for id in expired {
+        if let Some(reservation) =
+            write_template_reservations(|reservations| reservations.remove(&id))
+        {
+            release_locked_utxos(reservation.locked);
+        }
+    }
+}
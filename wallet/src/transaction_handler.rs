@@ -1,62 +1,89 @@
 use bitcoin::{
     absolute::LockTime,
+    ecdsa,
     hashes::Hash,
-    script::{Builder, PushBytesBuf},
-    sighash::{EcdsaSighashType, SighashCache},
+    psbt::Psbt,
+    script::{Builder, Instruction, PushBytesBuf},
+    sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType},
+    taproot,
     transaction::Version,
-    Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
-};
-use candid::CandidType;
-use ic_cdk::api::management_canister::bitcoin::{
-    bitcoin_send_transaction, SendTransactionRequest, Utxo,
+    Address, Amount, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
 };
+use candid::{CandidType, Principal};
+use futures::future::join_all;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, SendTransactionRequest, Utxo};
 use ic_management_canister_types::DerivationPath;
 use icrc_ledger_types::icrc1::account::Account;
 use ordinals::{Edict, Runestone};
+use std::collections::HashSet;
 
 use crate::{
-    bitcoin::{account_to_derivation_path, derive_public_key, ecdsa_sign, sec1_to_der},
-    state::{read_config, RunicUtxo},
-    types::RuneId,
+    bitcoin::{
+        account_to_derivation_path, address_validation, bitcoin_network, derive_public_key,
+        ecdsa_sign, mock_ecdsa_signature, mock_schnorr_signature, runestone, schnorr_sign,
+        sec1_to_der, verify_signatures, MAX_TX_INPUTS, MAX_TX_VSIZE,
+    },
+    bitcoin_api::bitcoin_send_transaction,
+    circuit_breaker, metrics, pending_change,
+    state::{
+        read_config, read_prepared_withdrawals, read_submission_queue, read_template_reservations,
+        record_event_shared, write_prepared_withdrawals, write_sync_cursors, write_transaction_log,
+        write_utxo_manager, EventKind, LockedUtxos, OutputRole, PreparedWithdrawal,
+        ReservedSelection, RunicUtxo, SummaryInput, SummaryOutput, TransactionKind,
+        TransactionRecord, TransactionStatus, TransactionSummary,
+    },
+    submission_queue,
+    types::{RuneId, WalletError},
+    updater,
+    utils::generate_addresses_from_principal,
+    webhook,
 };
 
+// a prepared withdrawal nobody broadcast gets cancelled after this long
+const PREPARED_TTL_SECS: u64 = 24 * 60 * 60;
+
+// blocks the inputs of a cancelled withdrawal stay unspent before they're released
+const CANCEL_SETTLE_DEPTH: u32 = 6;
+
 pub enum TransactionType {
     Bitcoin {
         addr: String,
         utxos: Vec<Utxo>,
         signer_account: Account,
         signer_address: Address,
+        // utxos of the internal (change) branch, spent after the receive branch ones
+        change_addr: String,
+        change_utxos: Vec<Utxo>,
+        change_account: Account,
+        change_address: Address,
         txn: Transaction,
+        anchor: Option<TxOut>,
     },
     LegoBitcoin {
-        addr0: String,
-        addr1: String,
-        account0: Account,
-        account1: Account,
-        address0: Address,
-        address1: Address,
-        utxos0: Vec<Utxo>,
-        utxos1: Vec<Utxo>,
-        amount0: u64,
-        amount1: u64,
+        // inputs are ordered sender by sender
+        senders: Vec<LegoSender>,
         fee: u64,
         paid_by_sender: bool,
-        receiver: Address,
+        txn: Transaction,
+        anchor: Option<TxOut>,
     },
     Runestone {
         sender_addr: String,
         receiver_addr: String,
         sender_account: Account,
         receiver_account: Account,
-        runeid: RuneId,
-        amount: u128,
+        // one selection per sent rune, in edict order
+        runes: Vec<RuneSelection>,
         fee: u64,
-        runic_utxos: Vec<RunicUtxo>,
         fee_utxos: Vec<Utxo>,
         paid_by_sender: bool,
         sender_address: Address,
         receiver_address: Address,
         postage: Amount,
+        anchor: Option<TxOut>,
+        // pays the fee in place of the sender, see `fee_sponsor`
+        sponsor: Option<FeeSponsorship>,
     },
     Combined {
         sender_addr: String,
@@ -74,460 +101,391 @@ pub enum TransactionType {
         fee: u64,
         postage: Amount,
         paid_by_sender: bool,
+        anchor: Option<TxOut>,
     },
+    Batch {
+        // inputs are ordered sender by sender
+        senders: Vec<BatchSender>,
+        // number of leading outputs paying the batched requests
+        payouts: usize,
+        txn: Transaction,
+    },
+    // runes moved between outputs of the same owner or burned, see `runestone::split`
+    RuneSplit {
+        addr: String,
+        account: Account,
+        address: Address,
+        runeid: RuneId,
+        parts: Vec<u128>,
+        burn: u128,
+        runic_utxos: Vec<RunicUtxo>,
+        fee_utxos: Vec<Utxo>,
+        // the inputs held more of the rune than the parts, the rest follows them
+        rune_change: bool,
+        txn: Transaction,
+        anchor: Option<TxOut>,
+    },
+}
+
+// one funding address of a multi-sender transfer along with the utxos it spends
+pub struct LegoSender {
+    pub addr: String,
+    pub account: Account,
+    pub address: Address,
+    pub utxos: Vec<Utxo>,
+    pub amount: u64,
+}
+
+// one signer of a batch transaction along with the utxos it spends
+pub struct BatchSender {
+    pub addr: String,
+    pub account: Account,
+    pub address: Address,
+    pub utxos: Vec<Utxo>,
+}
+
+/*
+ * a wallet of this canister funding the fee and postage of a single rune transfer,
+ * paid with `rune_amount` of the sent rune on an output of its own. the fee utxos
+ * and the bitcoin change are the sponsor's
+*/
+pub struct FeeSponsorship {
+    pub addr: String,
+    pub account: Account,
+    pub address: Address,
+    pub rune_amount: u128,
+}
+
+// utxos picked to cover one rune of a runestone transfer
+pub struct RuneSelection {
+    pub runeid: RuneId,
+    pub amount: u128,
+    pub utxos: Vec<RunicUtxo>,
+}
+
+// receiving side of a transfer between wallets of this canister
+pub struct InternalTransfer {
+    pub receiver: Principal,
+    // encrypted by the sender, the canister only stores it
+    pub memo: Option<Vec<u8>>,
 }
 
 #[derive(CandidType)]
 pub enum SubmittedTransactionIdType {
-    Bitcoin { txid: String },
+    Bitcoin {
+        txid: String,
+        // signed transaction as broadcast, for archiving or rebroadcasting elsewhere
+        raw_transaction: Vec<u8>,
+        vsize: u64,
+        fee: u64,
+        // unset when the receipt is rebuilt from the transaction log
+        summary: Option<TransactionSummary>,
+    },
+}
+
+impl SubmittedTransactionIdType {
+    pub fn receipt(
+        record: &TransactionRecord,
+        raw_transaction: Vec<u8>,
+        summary: Option<TransactionSummary>,
+    ) -> Self {
+        Self::Bitcoin {
+            txid: record.txid.clone(),
+            raw_transaction,
+            vsize: record.vsize.unwrap_or_default(),
+            fee: record.fee,
+            summary,
+        }
+    }
+}
+
+// `spent` holds the value of the output spent by each input, `roles` one role per output
+pub fn summarize(
+    txn: &Transaction,
+    spent: &[u64],
+    roles: Vec<OutputRole>,
+    record: &TransactionRecord,
+) -> TransactionSummary {
+    let network = bitcoin_network();
+    TransactionSummary {
+        inputs: txn
+            .input
+            .iter()
+            .zip(spent)
+            .map(|(input, value)| SummaryInput {
+                txid: input.previous_output.txid.to_string(),
+                vout: input.previous_output.vout,
+                value: *value,
+            })
+            .collect(),
+        outputs: txn
+            .output
+            .iter()
+            .zip(roles)
+            .map(|(output, role)| SummaryOutput {
+                address: Address::from_script(&output.script_pubkey, network)
+                    .ok()
+                    .map(|address| address.to_string()),
+                value: output.value.to_sat(),
+                role,
+            })
+            .collect(),
+        fee: record.fee,
+        fee_payer: record.fee_payer,
+    }
+}
+
+#[derive(CandidType)]
+pub struct PsbtWithdrawal {
+    // txid of the transaction as the canister signed it, used to cancel the withdrawal
+    pub txid: String,
+    // serialized psbt carrying the canister's signatures
+    pub psbt: Vec<u8>,
 }
 
 impl TransactionType {
-    pub async fn build_and_submit(&self) -> Option<SubmittedTransactionIdType> {
+    // `internal` is set when the funds stay within the canister
+    pub async fn build_and_submit(
+        &self,
+        internal: Option<InternalTransfer>,
+    ) -> Result<SubmittedTransactionIdType, WalletError> {
+        let submitted = async {
+            // timers and queued payouts reach here without passing an endpoint's check
+            if let Err(err) = circuit_breaker::ensure_running() {
+                self.release_utxos();
+                return Err(err);
+            }
+            self.ensure_within_limits()?;
+            let simulated = read_config(|config| config.is_paper_trading());
+            let txn = self.sign(simulated).await;
+            self.submit(txn, internal, simulated).await
+        }
+        .await;
+        if submitted
+            .as_ref()
+            .is_err_and(|err| !matches!(err, WalletError::Paused(_)))
+        {
+            metrics::record_failed_submission();
+            circuit_breaker::record_failure();
+        }
+        submitted
+    }
+
+    // checked before signing so an oversized selection never costs a signing call
+    pub fn ensure_within_limits(&self) -> Result<(), WalletError> {
+        let inputs = self.spent_amounts().len();
+        if inputs > MAX_TX_INPUTS {
+            self.release_utxos();
+            return Err(WalletError::TransactionTooLarge(format!(
+                "spends {} inputs, a transaction takes at most {}",
+                inputs, MAX_TX_INPUTS
+            )));
+        }
+        Ok(())
+    }
+
+    /*
+     * signs the transaction and stores it without broadcasting, the utxos stay
+     * locked until `broadcast_prepared` is called with the txid or a cancellation
+     * gets settled on chain
+     */
+    pub async fn prepare(&self, internal: Option<InternalTransfer>) -> Result<String, WalletError> {
+        self.ensure_within_limits()?;
+        let simulated = read_config(|config| config.is_paper_trading());
+        let txn = self.sign(simulated).await;
+        self.store_prepared(&txn, internal, false, simulated)
+    }
+
+    /*
+     * same as `prepare` but hands the transaction out as a psbt holding the canister's
+     * signatures, for wallets that finalize and broadcast it themselves. the withdrawal
+     * can't be cancelled or expire, it stays prepared until `submit_raw_transaction`
+     * sees it or the chain shows its inputs spent
+     */
+    pub async fn prepare_psbt(
+        &self,
+        internal: Option<InternalTransfer>,
+    ) -> Result<PsbtWithdrawal, WalletError> {
+        self.ensure_within_limits()?;
+        let simulated = read_config(|config| config.is_paper_trading());
+        let txn = self.sign(simulated).await;
+        let txid = self.store_prepared(&txn, internal, true, simulated)?;
+        let psbt = signed_psbt(&txn, self.spent_outputs(), simulated);
+        Ok(PsbtWithdrawal {
+            txid,
+            psbt: psbt.serialize(),
+        })
+    }
+
+    fn store_prepared(
+        &self,
+        txn: &Transaction,
+        internal: Option<InternalTransfer>,
+        handed_out: bool,
+        simulated: bool,
+    ) -> Result<String, WalletError> {
+        let (record, raw_transaction, summary) = self.finalize(txn, internal, simulated)?;
+        let txid = record.txid.clone();
+        write_prepared_withdrawals(|prepared| {
+            prepared.insert(
+                txid.clone(),
+                PreparedWithdrawal {
+                    record,
+                    raw_transaction,
+                    locked: self.locked_utxos(),
+                    prepared_at: ic_cdk::api::time(),
+                    summary: Some(summary),
+                    cancelled_at: None,
+                    release_height: None,
+                    handed_out: Some(handed_out),
+                },
+            )
+        });
+        Ok(txid)
+    }
+
+    async fn sign(&self, simulated: bool) -> Transaction {
         match self {
             Self::Bitcoin {
                 addr: _,
-                utxos: _,
+                utxos,
                 signer_account,
                 signer_address,
+                change_addr: _,
+                change_utxos: _,
+                change_account,
+                change_address,
                 txn,
+                anchor: _,
             } => {
                 let mut txn = txn.clone();
-                let (path, pubkey) = read_config(|config| {
-                    let ecdsa_key = config.ecdsa_public_key();
-                    let path = account_to_derivation_path(signer_account);
-                    let pubkey = derive_public_key(&ecdsa_key, &path).public_key;
-                    (DerivationPath::new(path), pubkey)
-                });
-                let txn_cache = SighashCache::new(txn.clone());
-                for (index, input) in txn.input.iter_mut().enumerate() {
-                    let sighash = txn_cache
-                        .legacy_signature_hash(
-                            index,
-                            &signer_address.script_pubkey(),
-                            EcdsaSighashType::All.to_u32(),
-                        )
-                        .unwrap();
-                    let signature = ecdsa_sign(
-                        sighash.to_raw_hash().to_byte_array().to_vec(),
-                        path.clone().into_inner(),
-                    )
-                    .await
-                    .signature;
-                    let mut signature = sec1_to_der(signature);
-                    signature.push(EcdsaSighashType::All.to_u32() as u8);
-                    let signature = PushBytesBuf::try_from(signature).unwrap();
-                    let pubkey = PushBytesBuf::try_from(pubkey.clone()).unwrap();
-                    input.script_sig = Builder::new()
-                        .push_slice(signature)
-                        .push_slice(pubkey)
-                        .into_script();
-                    input.witness.clear();
-                }
-                let txid = txn.compute_txid().to_string();
-                let txn_bytes = bitcoin::consensus::serialize(&txn);
-                ic_cdk::println!("{}", hex::encode(&txn_bytes));
-                bitcoin_send_transaction(SendTransactionRequest {
-                    transaction: txn_bytes,
-                    network: read_config(|config| config.bitcoin_network()),
-                })
-                .await
-                .unwrap();
-                Some(SubmittedTransactionIdType::Bitcoin { txid })
-            }
-            Self::LegoBitcoin {
-                addr0: _,
-                addr1: _,
-                account0,
-                account1,
-                address0,
-                address1,
-                utxos0,
-                utxos1,
-                amount0,
-                amount1,
-                fee,
-                paid_by_sender,
-                receiver,
-            } => {
-                const DUST_THRESHOLD: u64 = 1_000;
-                let mut input = Vec::with_capacity(utxos0.len() + utxos1.len());
-                let mut index_of_utxos_of_addr0 = vec![];
-                let mut index_of_utxos_of_addr1 = vec![];
-                let (mut total_spent0, mut total_spent1) = (0, 0);
-
-                utxos0.iter().for_each(|utxo| {
-                    let txin = TxIn {
-                        sequence: Sequence::MAX,
-                        script_sig: ScriptBuf::new(),
-                        witness: Witness::new(),
-                        previous_output: OutPoint {
-                            txid: Txid::from_raw_hash(
-                                Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                            ),
-                            vout: utxo.outpoint.vout,
-                        },
-                    };
-                    total_spent0 += utxo.value;
-                    let current_len = input.len();
-                    input.insert(current_len, txin);
-                    index_of_utxos_of_addr0.push(current_len);
-                });
-                utxos1.iter().for_each(|utxo| {
-                    let txin = TxIn {
-                        sequence: Sequence::MAX,
-                        script_sig: ScriptBuf::new(),
-                        witness: Witness::new(),
-                        previous_output: OutPoint {
-                            txid: Txid::from_raw_hash(
-                                Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                            ),
-                            vout: utxo.outpoint.vout,
-                        },
-                    };
-                    total_spent1 += utxo.value;
-                    let current_len = input.len();
-                    input.insert(current_len, txin);
-                    index_of_utxos_of_addr1.push(current_len);
-                });
-
-                let mut output = vec![TxOut {
-                    script_pubkey: receiver.script_pubkey(),
-                    value: if *paid_by_sender {
-                        Amount::from_sat(amount0 + amount1)
-                    } else {
-                        Amount::from_sat(amount0 + amount1 - fee)
-                    },
-                }];
-
-                // block responsible for calculating and adding remaining account
-                {
-                    let (fee0, fee1) = {
-                        let is_even = fee % 2 == 0;
-                        if is_even {
-                            let fee_in_half = fee / 2;
-                            (fee_in_half, fee_in_half)
+                let [key, change_key] = signing_keys([
+                    (signer_account, signer_address),
+                    (change_account, change_address),
+                ]);
+                let plan = (0..txn.input.len())
+                    .map(|index| {
+                        if index < utxos.len() {
+                            InputSigner::new(index, &key)
                         } else {
-                            let fee_in_half = (fee - 1) / 2;
-                            (fee_in_half, fee_in_half + 1)
+                            InputSigner::new(index, &change_key)
                         }
-                    };
-                    let (amount0, amount1) = if *paid_by_sender {
-                        (amount0 + fee0, amount1 + fee1)
-                    } else {
-                        (*amount0, *amount1)
-                    };
-                    let remaining0 = total_spent0 - amount0;
-                    if remaining0 > DUST_THRESHOLD {
-                        output.push(TxOut {
-                            script_pubkey: address0.script_pubkey(),
-                            value: Amount::from_sat(remaining0),
-                        });
-                    }
-                    let remaining1 = total_spent1 - amount1;
-                    if remaining1 > DUST_THRESHOLD {
-                        output.push(TxOut {
-                            script_pubkey: address1.script_pubkey(),
-                            value: Amount::from_sat(remaining1),
-                        })
-                    }
-                }
-
-                let mut txn = Transaction {
-                    input,
-                    output,
-                    lock_time: LockTime::ZERO,
-                    version: Version(2),
-                };
-
-                // signing the transaction
-
-                let (path0, pubkey0, path1, pubkey1) = read_config(|config| {
-                    let ecdsa_key = config.ecdsa_public_key();
-                    let path0 = account_to_derivation_path(account0);
-                    let path1 = account_to_derivation_path(account1);
-                    let pubkey0 = derive_public_key(&ecdsa_key, &path0).public_key;
-                    let pubkey1 = derive_public_key(&ecdsa_key, &path1).public_key;
-                    (
-                        DerivationPath::new(path0),
-                        pubkey0,
-                        DerivationPath::new(path1),
-                        pubkey1,
-                    )
-                });
-                let txn_cache = SighashCache::new(txn.clone());
-                for (i, input) in txn.input.iter_mut().enumerate() {
-                    if index_of_utxos_of_addr0.contains(&i) {
-                        let sighash = txn_cache
-                            .legacy_signature_hash(
-                                i,
-                                &address0.script_pubkey(),
-                                EcdsaSighashType::All.to_u32(),
-                            )
-                            .unwrap();
-                        let signature = ecdsa_sign(
-                            sighash.as_byte_array().to_vec(),
-                            path0.clone().into_inner(),
-                        )
-                        .await
-                        .signature;
-                        let mut signature = sec1_to_der(signature);
-                        signature.push(EcdsaSighashType::All.to_u32() as u8);
-                        let signature = PushBytesBuf::try_from(signature).unwrap();
-                        let pubkey = PushBytesBuf::try_from(pubkey0.clone()).unwrap();
-                        input.script_sig = Builder::new()
-                            .push_slice(signature)
-                            .push_slice(pubkey)
-                            .into_script();
-                        input.witness.clear();
-                    } else {
-                        let sighash = txn_cache
-                            .legacy_signature_hash(
-                                i,
-                                &address1.script_pubkey(),
-                                EcdsaSighashType::All.to_u32(),
-                            )
-                            .unwrap();
-                        let signature = ecdsa_sign(
-                            sighash.as_byte_array().to_vec(),
-                            path1.clone().into_inner(),
-                        )
-                        .await
-                        .signature;
-                        let mut signature = sec1_to_der(signature);
-                        signature.push(EcdsaSighashType::All.to_u32() as u8);
-                        let signature = PushBytesBuf::try_from(signature).unwrap();
-                        let pubkey = PushBytesBuf::try_from(pubkey1.clone()).unwrap();
-                        input.script_sig = Builder::new()
-                            .push_slice(signature)
-                            .push_slice(pubkey)
-                            .into_script();
-                        input.witness.clear();
+                    })
+                    .collect();
+                sign_inputs(&mut txn, plan, simulated).await;
+                txn
+            }
+            Self::LegoBitcoin { senders, txn, .. } => {
+                let mut txn = txn.clone();
+                let keys = derive_signing_keys(
+                    senders
+                        .iter()
+                        .map(|sender| (&sender.account, &sender.address)),
+                );
+                let mut index = 0;
+                let mut plan = Vec::with_capacity(txn.input.len());
+                for (sender, key) in senders.iter().zip(keys.iter()) {
+                    for _ in 0..sender.utxos.len() {
+                        plan.push(InputSigner::new(index, key));
+                        index += 1;
                     }
                 }
-                let txid = txn.compute_txid().to_string();
-                let txn_bytes = bitcoin::consensus::serialize(&txn);
-                ic_cdk::println!("{}", hex::encode(&txn_bytes));
-                bitcoin_send_transaction(SendTransactionRequest {
-                    network: read_config(|config| config.bitcoin_network()),
-                    transaction: txn_bytes,
-                })
-                .await
-                .expect("failed to submit transaction");
-                Some(SubmittedTransactionIdType::Bitcoin { txid })
+                sign_inputs(&mut txn, plan, simulated).await;
+                txn
             }
             Self::Runestone {
                 sender_addr: _,
                 receiver_addr: _,
                 sender_account,
                 receiver_account,
-                runeid,
-                amount,
+                runes,
                 fee,
-                runic_utxos,
                 fee_utxos,
                 paid_by_sender,
                 sender_address,
                 receiver_address,
                 postage,
+                anchor,
+                sponsor,
             } => {
-                const DUST_THRESHOLD: u64 = 1_000;
-
-                let mut runic_total_spent = 0;
-                let mut btc_in_runic_spent = 0;
-                let mut fee_total_spent = 0;
-
-                let mut index_of_utxos_of_sender = vec![];
-
-                let mut input = vec![];
-                runic_utxos.iter().for_each(|r_utxo| {
-                    runic_total_spent += r_utxo.balance;
-                    btc_in_runic_spent += r_utxo.utxo.value;
-                    let txin = TxIn {
-                        script_sig: ScriptBuf::new(),
-                        witness: Witness::new(),
-                        sequence: Sequence::MAX,
-                        previous_output: OutPoint {
-                            txid: Txid::from_raw_hash(
-                                Hash::from_slice(&r_utxo.utxo.outpoint.txid)
-                                    .expect("should return hash"),
-                            ),
-                            vout: r_utxo.utxo.outpoint.vout,
-                        },
-                    };
-                    let i = input.len();
-                    index_of_utxos_of_sender.push(i);
-                    input.push(txin);
-                });
-
-                let need_change_rune_output = runic_total_spent > *amount || runic_utxos.len() > 1;
-
-                let required_btc_for_rune_output = if need_change_rune_output {
-                    *postage * 2
-                } else {
-                    *postage
-                };
-
-                let actual_required_btc =
-                    required_btc_for_rune_output.to_sat() - btc_in_runic_spent;
-
-                fee_utxos.iter().for_each(|utxo| {
-                    fee_total_spent += utxo.value;
-                    let txin = TxIn {
-                        script_sig: ScriptBuf::new(),
-                        witness: Witness::new(),
-                        sequence: Sequence::MAX,
-                        previous_output: OutPoint {
-                            txid: Txid::from_raw_hash(
-                                Hash::from_slice(&utxo.outpoint.txid).expect("should return hash"),
-                            ),
-                            vout: utxo.outpoint.vout,
-                        },
-                    };
-                    let i = input.len();
-                    if *paid_by_sender {
-                        index_of_utxos_of_sender.push(i);
-                    }
-                    input.push(txin);
-                });
-
-                let id = ordinals::RuneId {
-                    block: runeid.block,
-                    tx: runeid.tx,
-                };
-                let runestone = Runestone {
-                    edicts: vec![Edict {
-                        id,
-                        amount: *amount,
-                        output: 2,
-                    }],
-                    ..Default::default()
-                };
-
-                let mut output = if need_change_rune_output {
-                    vec![
-                        TxOut {
-                            script_pubkey: runestone.encipher(),
-                            value: Amount::from_sat(0),
-                        },
-                        TxOut {
-                            script_pubkey: sender_address.script_pubkey(),
-                            value: *postage,
-                        },
-                        TxOut {
-                            script_pubkey: receiver_address.script_pubkey(),
-                            value: *postage,
-                        },
-                    ]
-                } else {
-                    vec![TxOut {
-                        script_pubkey: receiver_address.script_pubkey(),
-                        value: *postage,
-                    }]
-                };
-
-                let remaining = fee_total_spent - fee - actual_required_btc;
-
-                if remaining > DUST_THRESHOLD {
-                    if *paid_by_sender {
-                        output.push(TxOut {
-                            script_pubkey: sender_address.script_pubkey(),
-                            value: Amount::from_sat(remaining),
-                        });
+                let mut txn = runestone::assemble(
+                    runes,
+                    fee_utxos,
+                    *fee,
+                    *paid_by_sender,
+                    sender_address,
+                    receiver_address,
+                    *postage,
+                    anchor,
+                    sponsor.as_ref(),
+                );
+                let runic_inputs = runestone::runic_inputs(runes).len();
+                let index_of_utxos_of_sender: HashSet<usize> =
+                    if *paid_by_sender && sponsor.is_none() {
+                        (0..txn.input.len()).collect()
                     } else {
-                        output.push(TxOut {
-                            script_pubkey: receiver_address.script_pubkey(),
-                            value: Amount::from_sat(remaining),
-                        });
-                    }
-                }
+                        (0..runic_inputs).collect()
+                    };
 
-                let mut txn = Transaction {
-                    input,
-                    output,
-                    lock_time: LockTime::ZERO,
-                    version: Version(2),
+                // signing the transaction
+                let fee_payer = match sponsor {
+                    Some(sponsor) => (&sponsor.account, &sponsor.address),
+                    None => (receiver_account, receiver_address),
                 };
+                let [sender_key, fee_payer_key] =
+                    signing_keys([(sender_account, sender_address), fee_payer]);
 
-                // signing the transaction
-                let (sender_path, sender_pubkey, receiver_path, receiver_pubkey) =
-                    read_config(|config| {
-                        let ecdsa_key = config.ecdsa_public_key();
-                        let sender_path = account_to_derivation_path(sender_account);
-                        let receiver_path = account_to_derivation_path(receiver_account);
-                        let pubkey0 = derive_public_key(&ecdsa_key, &sender_path).public_key;
-                        let pubkey1 = derive_public_key(&ecdsa_key, &receiver_path).public_key;
-                        (
-                            DerivationPath::new(sender_path),
-                            pubkey0,
-                            DerivationPath::new(receiver_path),
-                            pubkey1,
-                        )
-                    });
-
-                let txn_cache = SighashCache::new(txn.clone());
-                for (index, input) in txn.input.iter_mut().enumerate() {
-                    if index_of_utxos_of_sender.contains(&index) {
-                        let sighash = txn_cache
-                            .legacy_signature_hash(
+                // taproot senders sign key path spends, which commit to every spent output
+                let taproot_sender = sender_address.script_pubkey().is_p2tr();
+                let prevouts = self.spent_outputs();
+                let taproot_inputs: Vec<usize> = (0..txn.input.len())
+                    .filter(|index| index_of_utxos_of_sender.contains(index) && taproot_sender)
+                    .collect();
+                let taproot_index: HashSet<usize> = taproot_inputs.iter().copied().collect();
+                let mut txn_cache = SighashCache::new(txn.clone());
+                let sighashes: Vec<Vec<u8>> = taproot_inputs
+                    .iter()
+                    .map(|&index| {
+                        txn_cache
+                            .taproot_key_spend_signature_hash(
                                 index,
-                                &sender_address.script_pubkey(),
-                                EcdsaSighashType::All.to_u32(),
+                                &Prevouts::All(&prevouts),
+                                TapSighashType::Default,
                             )
-                            .unwrap();
-                        let signature = ecdsa_sign(
-                            sighash.as_byte_array().to_vec(),
-                            sender_path.clone().into_inner(),
-                        )
-                        .await
-                        .signature;
-                        let mut signature = sec1_to_der(signature);
-                        signature.push(EcdsaSighashType::All.to_u32() as u8);
-                        let signature = PushBytesBuf::try_from(signature).unwrap();
-                        let pubkey = PushBytesBuf::try_from(sender_pubkey.clone()).unwrap();
-                        input.script_sig = Builder::new()
-                            .push_slice(signature)
-                            .push_slice(pubkey)
-                            .into_script();
-                        input.witness.clear();
+                            .unwrap()
+                            .as_byte_array()
+                            .to_vec()
+                    })
+                    .collect();
+                let signatures =
+                    if simulated {
+                        vec![mock_schnorr_signature(); sighashes.len()]
                     } else {
-                        let sighash = txn_cache
-                            .legacy_signature_hash(
-                                index,
-                                &receiver_address.script_pubkey(),
-                                EcdsaSighashType::All.to_u32(),
-                            )
-                            .unwrap();
-                        let signature = ecdsa_sign(
-                            sighash.as_byte_array().to_vec(),
-                            receiver_path.clone().into_inner(),
-                        )
+                        join_all(sighashes.into_iter().map(|sighash| {
+                            schnorr_sign(sighash, sender_key.path.clone().into_inner())
+                        }))
                         .await
-                        .signature;
-                        let mut signature = sec1_to_der(signature);
-                        signature.push(EcdsaSighashType::All.to_u32() as u8);
-                        let signature = PushBytesBuf::try_from(signature).unwrap();
-                        let pubkey = PushBytesBuf::try_from(receiver_pubkey.clone()).unwrap();
-                        input.script_sig = Builder::new()
-                            .push_slice(signature)
-                            .push_slice(pubkey)
-                            .into_script();
-                        input.witness.clear();
-                    }
+                    };
+                for (&index, signature) in taproot_inputs.iter().zip(signatures) {
+                    let input = &mut txn.input[index];
+                    input.script_sig = ScriptBuf::new();
+                    input.witness = Witness::from_slice(&[signature]);
                 }
+                let plan = (0..txn.input.len())
+                    .filter(|index| !taproot_index.contains(index))
+                    .map(|index| {
+                        if index_of_utxos_of_sender.contains(&index) {
+                            InputSigner::new(index, &sender_key)
+                        } else {
+                            InputSigner::new(index, &fee_payer_key)
+                        }
+                    })
+                    .collect();
+                sign_inputs(&mut txn, plan, simulated).await;
                 /* let total_btc_in_ouput: u64 =
                     txn.output.iter().map(|output| output.value.to_sat()).sum();
                 ic_cdk::println!("btc in outout: {}", total_btc_in_ouput); */
-                let txid = txn.compute_txid().to_string();
-                let txn_bytes = bitcoin::consensus::serialize(&txn);
-                ic_cdk::println!("{}", hex::encode(&txn_bytes));
-                bitcoin_send_transaction(SendTransactionRequest {
-                    network: read_config(|config| config.bitcoin_network()),
-                    transaction: txn_bytes,
-                })
-                .await
-                .expect("failed to submit transaction");
-                Some(SubmittedTransactionIdType::Bitcoin { txid })
+                txn
             }
             Self::Combined {
                 sender_addr: _,
@@ -545,8 +503,10 @@ impl TransactionType {
                 fee,
                 postage,
                 paid_by_sender,
+                anchor,
             } => {
                 const DUST_THRESHOLD: u64 = 1_000;
+                let fee = *fee + anchor.as_ref().map_or(0, |anchor| anchor.value.to_sat());
                 let (
                     mut runic_total_spent,
                     mut btc_in_runic_spent,
@@ -555,7 +515,7 @@ impl TransactionType {
                 ) = (0, 0, 0, 0);
 
                 let mut input = vec![];
-                let mut index_of_utxos_receiver = vec![];
+                let mut index_of_utxos_receiver = HashSet::with_capacity(fee_utxos.len());
 
                 runic_utxos.iter().for_each(|utxo| {
                     runic_total_spent += utxo.balance;
@@ -606,7 +566,7 @@ impl TransactionType {
                     };
                     if !paid_by_sender {
                         let len = input.len();
-                        index_of_utxos_receiver.push(len);
+                        index_of_utxos_receiver.insert(len);
                     }
                     input.push(txin);
                 });
@@ -666,7 +626,7 @@ impl TransactionType {
                 });
 
                 if *paid_by_sender {
-                    let remaining = btc_total_spent - *btc_amount - *fee - actual_required_btc;
+                    let remaining = btc_total_spent - *btc_amount - fee - actual_required_btc;
                     if remaining > DUST_THRESHOLD {
                         output.push(TxOut {
                             value: Amount::from_sat(remaining),
@@ -690,6 +650,10 @@ impl TransactionType {
                     }
                 }
 
+                if let Some(anchor) = anchor {
+                    output.push(anchor.clone());
+                }
+
                 let mut txn = Transaction {
                     input,
                     output,
@@ -700,87 +664,1150 @@ impl TransactionType {
                 ic_cdk::println!(
                     "input's length to be signed by receiver: {}\nfee: {}",
                     index_of_utxos_receiver.len(),
-                    *fee
+                    fee
                 );
 
                 // signing logic
 
-                let (sender_path, sender_pubkey, receiver_path, receiver_pubkey) =
-                    read_config(|config| {
-                        let ecdsa_key = config.ecdsa_public_key();
-                        let sender_path = account_to_derivation_path(sender_account);
-                        let receiver_path = account_to_derivation_path(receiver_account);
-                        let pubkey0 = derive_public_key(&ecdsa_key, &sender_path).public_key;
-                        let pubkey1 = derive_public_key(&ecdsa_key, &receiver_path).public_key;
-                        (
-                            DerivationPath::new(sender_path),
-                            pubkey0,
-                            DerivationPath::new(receiver_path),
-                            pubkey1,
-                        )
-                    });
-
-                let txn_cache = SighashCache::new(txn.clone());
-                for (index, input) in txn.input.iter_mut().enumerate() {
-                    if index_of_utxos_receiver.contains(&index) {
-                        let sighash = txn_cache
-                            .legacy_signature_hash(
-                                index,
-                                &receiver_address.script_pubkey(),
-                                EcdsaSighashType::All.to_u32(),
-                            )
-                            .unwrap();
-                        let signature = ecdsa_sign(
-                            sighash.as_byte_array().to_vec(),
-                            receiver_path.clone().into_inner(),
-                        )
-                        .await
-                        .signature;
-                        let mut signature = sec1_to_der(signature);
-                        signature.push(EcdsaSighashType::All.to_u32() as u8);
-                        let signature = PushBytesBuf::try_from(signature).unwrap();
-                        let pubkey = PushBytesBuf::try_from(receiver_pubkey.clone()).unwrap();
-                        input.script_sig = Builder::new()
-                            .push_slice(signature)
-                            .push_slice(pubkey)
-                            .into_script();
-                        input.witness.clear();
-                    } else {
-                        let sighash = txn_cache
-                            .legacy_signature_hash(
-                                index,
-                                &sender_address.script_pubkey(),
-                                EcdsaSighashType::All.to_u32(),
+                let [sender_key, receiver_key] = signing_keys([
+                    (sender_account, sender_address),
+                    (receiver_account, receiver_address),
+                ]);
+                let plan = (0..txn.input.len())
+                    .map(|index| {
+                        if index_of_utxos_receiver.contains(&index) {
+                            InputSigner::new(index, &receiver_key)
+                        } else {
+                            InputSigner::new(index, &sender_key)
+                        }
+                    })
+                    .collect();
+                sign_inputs(&mut txn, plan, simulated).await;
+                txn
+            }
+            Self::Batch { senders, txn, .. } => {
+                let mut txn = txn.clone();
+                let keys = derive_signing_keys(
+                    senders
+                        .iter()
+                        .map(|sender| (&sender.account, &sender.address)),
+                );
+                let mut index = 0;
+                let mut plan = Vec::with_capacity(txn.input.len());
+                for (sender, key) in senders.iter().zip(keys.iter()) {
+                    for _ in 0..sender.utxos.len() {
+                        plan.push(InputSigner::new(index, key));
+                        index += 1;
+                    }
+                }
+                sign_inputs(&mut txn, plan, simulated).await;
+                txn
+            }
+            Self::RuneSplit {
+                account,
+                address,
+                txn,
+                ..
+            } => {
+                let mut txn = txn.clone();
+                let [key] = signing_keys([(account, address)]);
+                let plan = (0..txn.input.len())
+                    .map(|index| InputSigner::new(index, &key))
+                    .collect();
+                sign_inputs(&mut txn, plan, simulated).await;
+                txn
+            }
+        }
+    }
+}
+
+impl TransactionType {
+    /*
+     * the unsigned transaction along with what's needed to rebuild it later, only
+     * bitcoin and unsponsored runestone transfers can be held as templates
+     */
+    pub fn to_reserved(&self) -> Option<(Transaction, ReservedSelection)> {
+        let anchor = self
+            .anchor()
+            .map(|anchor| (anchor.script_pubkey.to_bytes(), anchor.value.to_sat()));
+        match self {
+            Self::Bitcoin {
+                addr,
+                utxos,
+                signer_account,
+                change_addr,
+                change_utxos,
+                change_account,
+                txn,
+                ..
+            } => Some((
+                txn.clone(),
+                ReservedSelection::Bitcoin {
+                    addr: addr.clone(),
+                    utxos: utxos.clone(),
+                    signer_account: *signer_account,
+                    change_addr: change_addr.clone(),
+                    change_utxos: change_utxos.clone(),
+                    change_account: *change_account,
+                    raw_transaction: bitcoin::consensus::serialize(txn),
+                    anchor,
+                },
+            )),
+            Self::Runestone {
+                sender_addr,
+                receiver_addr,
+                sender_account,
+                receiver_account,
+                runes,
+                fee,
+                fee_utxos,
+                paid_by_sender,
+                sender_address,
+                receiver_address,
+                postage,
+                anchor: anchor_output,
+                sponsor: None,
+            } => Some((
+                runestone::assemble(
+                    runes,
+                    fee_utxos,
+                    *fee,
+                    *paid_by_sender,
+                    sender_address,
+                    receiver_address,
+                    *postage,
+                    anchor_output,
+                    None,
+                ),
+                ReservedSelection::Runestone {
+                    sender_addr: sender_addr.clone(),
+                    receiver_addr: receiver_addr.clone(),
+                    sender_account: *sender_account,
+                    receiver_account: *receiver_account,
+                    runes: runes
+                        .iter()
+                        .map(|selection| {
+                            (
+                                selection.runeid.clone(),
+                                selection.amount,
+                                selection.utxos.clone(),
                             )
-                            .unwrap();
-                        let signature = ecdsa_sign(
-                            sighash.as_byte_array().to_vec(),
-                            sender_path.clone().into_inner(),
-                        )
-                        .await
-                        .signature;
-                        let mut signature = sec1_to_der(signature);
-                        signature.push(EcdsaSighashType::All.to_u32() as u8);
-                        let signature = PushBytesBuf::try_from(signature).unwrap();
-                        let pubkey = PushBytesBuf::try_from(sender_pubkey.clone()).unwrap();
-                        input.script_sig = Builder::new()
-                            .push_slice(signature)
-                            .push_slice(pubkey)
-                            .into_script();
-                        input.witness.clear();
+                        })
+                        .collect(),
+                    fee: *fee,
+                    fee_utxos: fee_utxos.clone(),
+                    paid_by_sender: *paid_by_sender,
+                    postage: postage.to_sat(),
+                    anchor,
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn from_reserved(selection: ReservedSelection) -> Result<Self, WalletError> {
+        let to_anchor = |anchor: Option<(Vec<u8>, u64)>| {
+            anchor.map(|(script_pubkey, value)| TxOut {
+                script_pubkey: ScriptBuf::from_bytes(script_pubkey),
+                value: Amount::from_sat(value),
+            })
+        };
+        match selection {
+            ReservedSelection::Bitcoin {
+                addr,
+                utxos,
+                signer_account,
+                change_addr,
+                change_utxos,
+                change_account,
+                raw_transaction,
+                anchor,
+            } => Ok(Self::Bitcoin {
+                signer_address: address_validation(&addr).map_err(WalletError::InvalidAddress)?,
+                change_address: address_validation(&change_addr)
+                    .map_err(WalletError::InvalidAddress)?,
+                txn: bitcoin::consensus::deserialize(&raw_transaction)
+                    .map_err(|e| WalletError::InvalidArgument(e.to_string()))?,
+                addr,
+                utxos,
+                signer_account,
+                change_addr,
+                change_utxos,
+                change_account,
+                anchor: to_anchor(anchor),
+            }),
+            ReservedSelection::Runestone {
+                sender_addr,
+                receiver_addr,
+                sender_account,
+                receiver_account,
+                runes,
+                fee,
+                fee_utxos,
+                paid_by_sender,
+                postage,
+                anchor,
+            } => Ok(Self::Runestone {
+                sender_address: address_validation(&sender_addr)
+                    .map_err(WalletError::InvalidAddress)?,
+                receiver_address: address_validation(&receiver_addr)
+                    .map_err(WalletError::InvalidAddress)?,
+                sender_addr,
+                receiver_addr,
+                sender_account,
+                receiver_account,
+                runes: runes
+                    .into_iter()
+                    .map(|(runeid, amount, utxos)| RuneSelection {
+                        runeid,
+                        amount,
+                        utxos,
+                    })
+                    .collect(),
+                fee,
+                fee_utxos,
+                paid_by_sender,
+                postage: Amount::from_sat(postage),
+                anchor: to_anchor(anchor),
+                sponsor: None,
+            }),
+        }
+    }
+
+    fn kind(&self) -> TransactionKind {
+        match self {
+            Self::Bitcoin { .. } => TransactionKind::Bitcoin,
+            Self::LegoBitcoin { .. } => TransactionKind::MultiSender,
+            Self::Runestone { .. } => TransactionKind::Runestone,
+            Self::Combined { .. } => TransactionKind::Combined,
+            Self::Batch { .. } => TransactionKind::Batch,
+            Self::RuneSplit { burn: 0, .. } => TransactionKind::RuneSplit,
+            Self::RuneSplit { .. } => TransactionKind::RuneBurn,
+        }
+    }
+
+    // what the fee payer spends on top of the delivered amount, network fee and anchor
+    pub fn fee_with_anchor(&self) -> u64 {
+        let anchor = self.anchor().map_or(0, |anchor| anchor.value.to_sat());
+        match self {
+            Self::Bitcoin { txn, .. } | Self::Batch { txn, .. } | Self::RuneSplit { txn, .. } => {
+                let total_output: u64 = txn.output.iter().map(|output| output.value.to_sat()).sum();
+                self.spent_value().saturating_sub(total_output) + anchor
+            }
+            Self::LegoBitcoin { fee, .. }
+            | Self::Runestone { fee, .. }
+            | Self::Combined { fee, .. } => fee + anchor,
+        }
+    }
+
+    fn anchor(&self) -> Option<&TxOut> {
+        match self {
+            Self::Bitcoin { anchor, .. }
+            | Self::LegoBitcoin { anchor, .. }
+            | Self::Runestone { anchor, .. }
+            | Self::Combined { anchor, .. }
+            | Self::RuneSplit { anchor, .. } => anchor.as_ref(),
+            Self::Batch { .. } => None,
+        }
+    }
+
+    // bitcoin and runes delivered to the receiver
+    fn transferred(&self) -> (u64, Vec<(RuneId, u128)>) {
+        match self {
+            Self::Bitcoin { txn, .. } => (
+                txn.output.first().map_or(0, |output| output.value.to_sat()),
+                vec![],
+            ),
+            Self::LegoBitcoin { senders, .. } => {
+                (senders.iter().map(|sender| sender.amount).sum(), vec![])
+            }
+            Self::Runestone { runes, .. } => (
+                0,
+                runes
+                    .iter()
+                    .map(|selection| (selection.runeid.clone(), selection.amount))
+                    .collect(),
+            ),
+            Self::Combined {
+                runeid,
+                rune_amount,
+                btc_amount,
+                ..
+            } => (*btc_amount, vec![(runeid.clone(), *rune_amount)]),
+            Self::Batch { payouts, txn, .. } => (
+                txn.output
+                    .iter()
+                    .take(*payouts)
+                    .map(|output| output.value.to_sat())
+                    .sum(),
+                vec![],
+            ),
+            // only burned runes leave the owner
+            Self::RuneSplit { runeid, burn, .. } => (
+                0,
+                (*burn > 0)
+                    .then(|| (runeid.clone(), *burn))
+                    .into_iter()
+                    .collect(),
+            ),
+        }
+    }
+
+    /*
+     * what each output is for, in output order. rune transfers lead with the runestone
+     * and the sender's rune change when there is any, the receiver's postage follows
+     */
+    fn output_roles(&self, txn: &Transaction) -> Vec<OutputRole> {
+        let receiver_postage = if txn
+            .output
+            .first()
+            .is_some_and(|output| output.script_pubkey.is_op_return())
+        {
+            2
+        } else {
+            0
+        };
+        // anchors are always the last output of the transaction
+        let anchor = self.anchor().map(|_| txn.output.len() - 1);
+        txn.output
+            .iter()
+            .enumerate()
+            .map(|(index, output)| {
+                if Some(index) == anchor {
+                    return OutputRole::Anchor;
+                }
+                if output.script_pubkey.is_op_return() {
+                    return OutputRole::OpReturn;
+                }
+                match self {
+                    Self::Bitcoin { .. } | Self::LegoBitcoin { .. } if index == 0 => {
+                        OutputRole::Recipient
+                    }
+                    Self::Batch { payouts, .. } if index < *payouts => OutputRole::Recipient,
+                    Self::Runestone { .. } | Self::Combined { .. } if index <= receiver_postage => {
+                        OutputRole::Postage
                     }
+                    Self::Combined { .. } if index == receiver_postage + 1 => OutputRole::Recipient,
+                    Self::Runestone {
+                        sponsor: Some(_), ..
+                    } if index == receiver_postage + 1 => OutputRole::Postage,
+                    Self::RuneSplit {
+                        parts, rune_change, ..
+                    } if index <= parts.len() + *rune_change as usize => OutputRole::Postage,
+                    _ => OutputRole::Change,
                 }
-                let txid = txn.compute_txid().to_string();
-                let txn_bytes = bitcoin::consensus::serialize(&txn);
-                ic_cdk::println!("{}", hex::encode(&txn_bytes));
-                bitcoin_send_transaction(SendTransactionRequest {
-                    network: read_config(|config| config.bitcoin_network()),
-                    transaction: txn_bytes,
+            })
+            .collect()
+    }
+
+    fn paid_by_receiver(&self) -> bool {
+        match self {
+            Self::Bitcoin { .. } | Self::Batch { .. } | Self::RuneSplit { .. } => false,
+            Self::LegoBitcoin { paid_by_sender, .. }
+            | Self::Runestone { paid_by_sender, .. }
+            | Self::Combined { paid_by_sender, .. } => !paid_by_sender,
+        }
+    }
+
+    // total value of every utxo consumed by the transaction
+    fn spent_value(&self) -> u64 {
+        self.spent_amounts().iter().sum()
+    }
+
+    // value of the output spent by each input, in input order
+    fn spent_amounts(&self) -> Vec<u64> {
+        match self {
+            Self::Bitcoin {
+                utxos,
+                change_utxos,
+                ..
+            } => utxos
+                .iter()
+                .chain(change_utxos.iter())
+                .map(|utxo| utxo.value)
+                .collect(),
+            Self::LegoBitcoin { senders, .. } => senders
+                .iter()
+                .flat_map(|sender| sender.utxos.iter())
+                .map(|utxo| utxo.value)
+                .collect(),
+            Self::Runestone {
+                runes, fee_utxos, ..
+            } => runestone::runic_inputs(runes)
+                .iter()
+                .chain(fee_utxos.iter())
+                .map(|utxo| utxo.value)
+                .collect(),
+            Self::Combined {
+                runic_utxos,
+                btc_utxos,
+                fee_utxos,
+                ..
+            } => runic_utxos
+                .iter()
+                .map(|r_utxo| r_utxo.utxo.value)
+                .chain(
+                    btc_utxos
+                        .iter()
+                        .chain(fee_utxos.iter())
+                        .map(|utxo| utxo.value),
+                )
+                .collect(),
+            Self::Batch { senders, .. } => senders
+                .iter()
+                .flat_map(|sender| sender.utxos.iter())
+                .map(|utxo| utxo.value)
+                .collect(),
+            Self::RuneSplit {
+                runic_utxos,
+                fee_utxos,
+                ..
+            } => runic_utxos
+                .iter()
+                .map(|r_utxo| r_utxo.utxo.value)
+                .chain(fee_utxos.iter().map(|utxo| utxo.value))
+                .collect(),
+        }
+    }
+
+    // the outputs spent by each input, in input order
+    pub fn spent_outputs(&self) -> Vec<TxOut> {
+        self.spent_scripts()
+            .into_iter()
+            .zip(self.spent_amounts())
+            .map(|(script_pubkey, value)| TxOut {
+                script_pubkey,
+                value: Amount::from_sat(value),
+            })
+            .collect()
+    }
+
+    // script_pubkeys of the outputs spent by each input, in input order
+    fn spent_scripts(&self) -> Vec<ScriptBuf> {
+        match self {
+            Self::Bitcoin {
+                utxos,
+                signer_address,
+                change_utxos,
+                change_address,
+                ..
+            } => {
+                let mut scripts = vec![signer_address.script_pubkey(); utxos.len()];
+                scripts.extend(vec![change_address.script_pubkey(); change_utxos.len()]);
+                scripts
+            }
+            Self::LegoBitcoin { senders, .. } => senders
+                .iter()
+                .flat_map(|sender| vec![sender.address.script_pubkey(); sender.utxos.len()])
+                .collect(),
+            Self::Runestone {
+                runes,
+                fee_utxos,
+                paid_by_sender,
+                sender_address,
+                receiver_address,
+                sponsor,
+                ..
+            } => {
+                let fee_payer = match sponsor {
+                    Some(sponsor) => &sponsor.address,
+                    None if *paid_by_sender => sender_address,
+                    None => receiver_address,
+                };
+                let mut scripts =
+                    vec![sender_address.script_pubkey(); runestone::runic_inputs(runes).len()];
+                scripts.extend(vec![fee_payer.script_pubkey(); fee_utxos.len()]);
+                scripts
+            }
+            Self::Combined {
+                runic_utxos,
+                btc_utxos,
+                fee_utxos,
+                paid_by_sender,
+                sender_address,
+                receiver_address,
+                ..
+            } => {
+                let fee_payer = if *paid_by_sender {
+                    sender_address
+                } else {
+                    receiver_address
+                };
+                let mut scripts =
+                    vec![sender_address.script_pubkey(); runic_utxos.len() + btc_utxos.len()];
+                scripts.extend(vec![fee_payer.script_pubkey(); fee_utxos.len()]);
+                scripts
+            }
+            Self::Batch { senders, .. } => senders
+                .iter()
+                .flat_map(|sender| vec![sender.address.script_pubkey(); sender.utxos.len()])
+                .collect(),
+            Self::RuneSplit {
+                address,
+                runic_utxos,
+                fee_utxos,
+                ..
+            } => vec![address.script_pubkey(); runic_utxos.len() + fee_utxos.len()],
+        }
+    }
+
+    // utxos taken out of the manager for this transaction
+    pub fn locked_utxos(&self) -> Vec<LockedUtxos> {
+        match self {
+            Self::Bitcoin {
+                addr,
+                utxos,
+                change_addr,
+                change_utxos,
+                ..
+            } => vec![
+                LockedUtxos::Bitcoin {
+                    addr: addr.clone(),
+                    utxos: utxos.clone(),
+                },
+                LockedUtxos::Bitcoin {
+                    addr: change_addr.clone(),
+                    utxos: change_utxos.clone(),
+                },
+            ],
+            Self::LegoBitcoin { senders, .. } => senders
+                .iter()
+                .map(|sender| LockedUtxos::Bitcoin {
+                    addr: sender.addr.clone(),
+                    utxos: sender.utxos.clone(),
+                })
+                .collect(),
+            Self::Runestone {
+                sender_addr,
+                receiver_addr,
+                runes,
+                fee_utxos,
+                paid_by_sender,
+                sponsor,
+                ..
+            } => {
+                let fee_payer = match sponsor {
+                    Some(sponsor) => &sponsor.addr,
+                    None if *paid_by_sender => sender_addr,
+                    None => receiver_addr,
+                };
+                let mut locked: Vec<LockedUtxos> = runes
+                    .iter()
+                    .map(|selection| LockedUtxos::Runic {
+                        addr: sender_addr.clone(),
+                        runeid: selection.runeid.clone(),
+                        utxos: selection.utxos.clone(),
+                    })
+                    .collect();
+                locked.push(LockedUtxos::Bitcoin {
+                    addr: fee_payer.clone(),
+                    utxos: fee_utxos.clone(),
+                });
+                locked
+            }
+            Self::Combined {
+                sender_addr,
+                receiver_addr,
+                runeid,
+                runic_utxos,
+                btc_utxos,
+                fee_utxos,
+                ..
+            } => vec![
+                LockedUtxos::Runic {
+                    addr: sender_addr.clone(),
+                    runeid: runeid.clone(),
+                    utxos: runic_utxos.clone(),
+                },
+                LockedUtxos::Bitcoin {
+                    addr: sender_addr.clone(),
+                    utxos: btc_utxos.clone(),
+                },
+                LockedUtxos::Bitcoin {
+                    addr: receiver_addr.clone(),
+                    utxos: fee_utxos.clone(),
+                },
+            ],
+            Self::Batch { senders, .. } => senders
+                .iter()
+                .map(|sender| LockedUtxos::Bitcoin {
+                    addr: sender.addr.clone(),
+                    utxos: sender.utxos.clone(),
                 })
-                .await
-                .expect("failed to submit transaction");
-                Some(SubmittedTransactionIdType::Bitcoin { txid })
+                .collect(),
+            Self::RuneSplit {
+                addr,
+                runeid,
+                runic_utxos,
+                fee_utxos,
+                ..
+            } => vec![
+                LockedUtxos::Runic {
+                    addr: addr.clone(),
+                    runeid: runeid.clone(),
+                    utxos: runic_utxos.clone(),
+                },
+                LockedUtxos::Bitcoin {
+                    addr: addr.clone(),
+                    utxos: fee_utxos.clone(),
+                },
+            ],
+        }
+    }
+
+    // hands the selected utxos back to the manager, used when the transaction
+    // never reaches the network
+    fn release_utxos(&self) {
+        release_locked_utxos(self.locked_utxos())
+    }
+
+    // verifies the signatures and builds the transaction's log entry along with its raw bytes
+    // and the summary handed back with the receipt. a `simulated` one carries mock
+    // signatures, so there is nothing to verify and it can only ever be simulated
+    fn finalize(
+        &self,
+        txn: &Transaction,
+        internal: Option<InternalTransfer>,
+        simulated: bool,
+    ) -> Result<(TransactionRecord, Vec<u8>, TransactionSummary), WalletError> {
+        let caller = ic_cdk::caller();
+        let (counterparty, memo) = match internal {
+            Some(InternalTransfer { receiver, memo }) => (Some(receiver), memo),
+            None => (None, None),
+        };
+        // catches derivation mixups before anything reaches the network
+        let verified = if simulated {
+            Ok(())
+        } else {
+            verify_signatures(txn, &self.spent_outputs())
+        };
+        if let Err(reason) = verified {
+            ic_cdk::println!("signature verification failed: {}", reason);
+            self.release_utxos();
+            return Err(WalletError::SignatureVerificationFailed(reason));
+        }
+        let vsize = txn.vsize() as u64;
+        if vsize > MAX_TX_VSIZE {
+            self.release_utxos();
+            return Err(WalletError::TransactionTooLarge(format!(
+                "{} vbytes, a transaction takes at most {}",
+                vsize, MAX_TX_VSIZE
+            )));
+        }
+        let txid = txn.compute_txid().to_string();
+        let total_output: u64 = txn.output.iter().map(|output| output.value.to_sat()).sum();
+        let fee = self.spent_value().saturating_sub(total_output);
+        let (amount, mut runes) = self.transferred();
+        let rune = (!runes.is_empty()).then(|| runes.remove(0));
+        // anchors are always the last output of the transaction
+        let anchor = self
+            .anchor()
+            .map(|anchor| ((txn.output.len() - 1) as u32, anchor.value.to_sat()));
+        let record = TransactionRecord {
+            txid,
+            kind: self.kind(),
+            caller,
+            fee,
+            status: if simulated {
+                TransactionStatus::Simulated
+            } else {
+                TransactionStatus::Submitted
+            },
+            timestamp: ic_cdk::api::time(),
+            vsize: Some(vsize),
+            anchor,
+            amount: Some(amount),
+            rune,
+            additional_runes: (!runes.is_empty()).then_some(runes),
+            counterparty,
+            fee_payer: if self.paid_by_receiver() {
+                counterparty
+            } else {
+                Some(caller)
+            },
+            memo,
+            fiat_rate: None,
+        };
+        let summary = summarize(txn, &self.spent_amounts(), self.output_roles(txn), &record);
+        Ok((record, bitcoin::consensus::serialize(txn), summary))
+    }
+
+    async fn submit(
+        &self,
+        txn: Transaction,
+        internal: Option<InternalTransfer>,
+        simulated: bool,
+    ) -> Result<SubmittedTransactionIdType, WalletError> {
+        let (record, raw_transaction, summary) = self.finalize(&txn, internal, simulated)?;
+        Ok(broadcast(record, raw_transaction, self.locked_utxos(), Some(summary)).await)
+    }
+}
+
+pub fn release_locked_utxos(locked: Vec<LockedUtxos>) {
+    write_utxo_manager(|manager| {
+        for utxos in locked {
+            match utxos {
+                LockedUtxos::Bitcoin { addr, utxos } => manager.record_btc_utxos(&addr, utxos),
+                LockedUtxos::Runic {
+                    addr,
+                    runeid,
+                    utxos,
+                } => manager.record_runic_utxos(&addr, runeid, utxos),
+            }
+        }
+    })
+}
+
+// an account's key along with what its p2pkh inputs need, derived once per transaction
+#[derive(Clone)]
+struct SigningKey {
+    path: DerivationPath,
+    pubkey: PushBytesBuf,
+    script_pubkey: ScriptBuf,
+}
+
+/*
+ * keys of the transaction's owners in the order given. the same account shows up
+ * more than once when it pays its own fee or sends twice in a batch, those reuse
+ * the key derived the first time
+*/
+fn derive_signing_keys<'a>(
+    owners: impl IntoIterator<Item = (&'a Account, &'a Address)>,
+) -> Vec<SigningKey> {
+    let ecdsa_key = read_config(|config| config.ecdsa_public_key());
+    let mut derived: Vec<(Account, SigningKey)> = vec![];
+    owners
+        .into_iter()
+        .map(|(account, address)| {
+            let script_pubkey = address.script_pubkey();
+            if let Some((_, key)) = derived
+                .iter()
+                .find(|(owner, key)| owner == account && key.script_pubkey == script_pubkey)
+            {
+                return key.clone();
             }
+            let path = account_to_derivation_path(account);
+            let pubkey = derive_public_key(&ecdsa_key, &path).public_key;
+            let key = SigningKey {
+                path: DerivationPath::new(path),
+                pubkey: PushBytesBuf::try_from(pubkey).unwrap(),
+                script_pubkey,
+            };
+            derived.push((*account, key.clone()));
+            key
+        })
+        .collect()
+}
+
+fn signing_keys<const N: usize>(owners: [(&Account, &Address); N]) -> [SigningKey; N] {
+    let mut keys = derive_signing_keys(owners).into_iter();
+    std::array::from_fn(|_| keys.next().unwrap())
+}
+
+// a p2pkh input along with the key it gets signed with
+struct InputSigner<'a> {
+    index: usize,
+    key: &'a SigningKey,
+}
+
+impl<'a> InputSigner<'a> {
+    fn new(index: usize, key: &'a SigningKey) -> Self {
+        Self { index, key }
+    }
+}
+
+/*
+ * legacy sighashes only commit to the unsigned transaction, so every input can be
+ * hashed up front and all the signing calls get issued at once. a consolidation
+ * waits for the slowest call instead of one round trip per input. a `simulated`
+ * transaction gets mock signatures of the longest size instead of any signing call
+*/
+async fn sign_inputs(txn: &mut Transaction, plan: Vec<InputSigner<'_>>, simulated: bool) {
+    let signatures: Vec<Vec<u8>> = if simulated {
+        plan.iter().map(|_| mock_ecdsa_signature()).collect()
+    } else {
+        let txn_cache = SighashCache::new(txn.clone());
+        join_all(plan.iter().map(|signer| {
+            let sighash = txn_cache
+                .legacy_signature_hash(
+                    signer.index,
+                    &signer.key.script_pubkey,
+                    EcdsaSighashType::All.to_u32(),
+                )
+                .unwrap();
+            ecdsa_sign(
+                sighash.to_raw_hash().to_byte_array().to_vec(),
+                signer.key.path.clone().into_inner(),
+            )
+        }))
+        .await
+        .into_iter()
+        .map(|response| {
+            let mut signature = sec1_to_der(response.signature);
+            signature.push(EcdsaSighashType::All.to_u32() as u8);
+            signature
+        })
+        .collect()
+    };
+    for (signer, signature) in plan.iter().zip(signatures) {
+        let signature = PushBytesBuf::try_from(signature).unwrap();
+        let input = &mut txn.input[signer.index];
+        input.script_sig = Builder::new()
+            .push_slice(signature)
+            .push_slice(&signer.key.pubkey)
+            .into_script();
+        input.witness.clear();
+    }
+}
+
+/*
+ * broadcasts the signed transaction and records it in the transaction log. in paper
+ * trading mode, or for a transaction that only carries mock signatures, the broadcast
+ * is skipped and the utxos stay spendable. a broadcast the bitcoin canister rejects
+ * stays in the submission queue and is retried from there
+*/
+pub async fn broadcast(
+    mut record: TransactionRecord,
+    mut raw_transaction: Vec<u8>,
+    locked: Vec<LockedUtxos>,
+    summary: Option<TransactionSummary>,
+) -> SubmittedTransactionIdType {
+    let mock_signed = record.status == TransactionStatus::Simulated;
+    let simulated = mock_signed || read_config(|config| config.is_paper_trading());
+    // signed for real before paper trading got switched on, the signatures stay out of the log
+    if simulated && !mock_signed {
+        raw_transaction = strip_signatures(&raw_transaction);
+    }
+    ic_cdk::println!("{}", hex::encode(&raw_transaction));
+    record.status = if simulated {
+        release_locked_utxos(locked.clone());
+        TransactionStatus::Simulated
+    } else {
+        submission_queue::enqueue(&record, &raw_transaction, &locked);
+        match send_transaction(&raw_transaction).await {
+            Ok(()) => {
+                submission_queue::remove(&record.txid);
+                TransactionStatus::Submitted
+            }
+            Err(err) => {
+                ic_cdk::println!("broadcast of {} failed: {}", record.txid, err);
+                submission_queue::record_failure(&record.txid, err);
+                metrics::record_failed_submission();
+                circuit_breaker::record_failure();
+                TransactionStatus::Queued
+            }
+        }
+    };
+    record.timestamp = ic_cdk::api::time();
+    record_submission(&record);
+    if record.status == TransactionStatus::Submitted {
+        on_submitted(&record, &raw_transaction, &locked);
+    }
+    let receipt = SubmittedTransactionIdType::receipt(&record, raw_transaction.clone(), summary);
+    write_transaction_log(|log| log.record(record, raw_transaction));
+    receipt
+}
+
+// the transaction with every script sig and witness emptied, nothing if it doesn't parse
+fn strip_signatures(raw_transaction: &[u8]) -> Vec<u8> {
+    let Ok(mut txn) = bitcoin::consensus::deserialize::<Transaction>(raw_transaction) else {
+        return Vec::new();
+    };
+    for input in txn.input.iter_mut() {
+        input.script_sig = ScriptBuf::new();
+        input.witness.clear();
+    }
+    bitcoin::consensus::serialize(&txn)
+}
+
+// a withdrawal to another principal of the canister shows up in the receiver's activity too
+pub fn record_submission(record: &TransactionRecord) {
+    let receivers: Vec<Principal> = record
+        .counterparty
+        .filter(|counterparty| *counterparty != record.caller)
+        .into_iter()
+        .collect();
+    record_event_shared(
+        record.caller,
+        &receivers,
+        EventKind::WithdrawalSubmitted {
+            txid: record.txid.clone(),
+            kind: record.kind,
+            fee: record.fee,
+            status: record.status,
+            amount: record.amount,
+            runes: Some(
+                record
+                    .rune
+                    .iter()
+                    .chain(record.additional_runes.iter().flatten())
+                    .cloned()
+                    .collect(),
+            ),
+            counterparty: record.counterparty,
+        },
+    );
+}
+
+pub async fn send_transaction(raw_transaction: &[u8]) -> Result<(), String> {
+    bitcoin_send_transaction(SendTransactionRequest {
+        network: read_config(|config| config.bitcoin_network()),
+        transaction: raw_transaction.to_vec(),
+    })
+    .await
+    .map_err(|(code, msg)| format!("{:?}: {}", code, msg))
+}
+
+// what follows the bitcoin canister accepting the transaction, right away or on a retry
+pub fn on_submitted(record: &TransactionRecord, raw_transaction: &[u8], locked: &[LockedUtxos]) {
+    metrics::record_submission(record);
+    webhook::enqueue(record);
+    if let Ok(txn) = bitcoin::consensus::deserialize::<Transaction>(raw_transaction) {
+        pending_change::register(record, &txn, locked);
+    }
+    // the spends and the new outputs only show up once the addresses get fetched again
+    for utxos in locked {
+        match utxos {
+            LockedUtxos::Bitcoin { addr, .. } | LockedUtxos::Runic { addr, .. } => {
+                updater::mark_stale(addr)
+            }
+        }
+    }
+    if let Some(counterparty) = record.counterparty {
+        updater::mark_stale(&generate_addresses_from_principal(&counterparty).bitcoin);
+    }
+}
+
+/*
+ * second half of `prepare`, a rejected broadcast moves over to the submission queue.
+ * the withdrawal leaves the prepared ones before the broadcast is awaited, so neither
+ * a cancel nor a second broadcast can get hold of it meanwhile
+ */
+pub async fn broadcast_prepared(txid: &str) -> Result<SubmittedTransactionIdType, WalletError> {
+    let prepared = take_prepared(txid)?;
+    Ok(broadcast(
+        prepared.record,
+        prepared.raw_transaction,
+        prepared.locked,
+        prepared.summary,
+    )
+    .await)
+}
+
+/*
+ * broadcasts a prepared withdrawal finalized outside the canister. finalizing only
+ * fills in the signatures, so the txid still matches the one the withdrawal was
+ * prepared under and the externally finalized bytes replace the canister's own
+ */
+pub async fn broadcast_finalized(
+    txid: &str,
+    raw_transaction: Vec<u8>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    let prepared = take_prepared(txid)?;
+    Ok(broadcast(
+        prepared.record,
+        raw_transaction,
+        prepared.locked,
+        prepared.summary,
+    )
+    .await)
+}
+
+fn take_prepared(txid: &str) -> Result<PreparedWithdrawal, WalletError> {
+    write_prepared_withdrawals(|prepared| prepared.remove(&txid.to_string()))
+        .ok_or(WalletError::TransactionNotFound)
+}
+
+/*
+ * the signed bytes may have left the canister already, so a cancelled withdrawal keeps
+ * its inputs held until `settle_prepared` sees the chain answer for them. broadcasting
+ * it is still allowed meanwhile, nothing else spends the inputs
+*/
+pub fn cancel_prepared(txid: &str) -> Result<(), WalletError> {
+    write_prepared_withdrawals(|prepared| {
+        let mut withdrawal = prepared
+            .get(&txid.to_string())
+            .ok_or(WalletError::TransactionNotFound)?;
+        if withdrawal.is_handed_out() {
+            return Err(WalletError::InvalidArgument(String::from(
+                "a psbt withdrawal can't be cancelled, it settles once its inputs are spent",
+            )));
+        }
+        if withdrawal.cancelled_at.is_none() {
+            withdrawal.cancelled_at = Some(ic_cdk::api::time());
+            prepared.insert(txid.to_string(), withdrawal);
+        }
+        Ok(())
+    })
+}
+
+// cancels the withdrawals left prepared for longer than `PREPARED_TTL_SECS`, returns how many
+pub fn expire_prepared() -> u64 {
+    let cutoff = ic_cdk::api::time().saturating_sub(PREPARED_TTL_SECS * 1_000_000_000);
+    let expired: Vec<String> = read_prepared_withdrawals(|prepared| {
+        prepared
+            .iter()
+            .filter(|(_, withdrawal)| {
+                withdrawal.cancelled_at.is_none()
+                    && !withdrawal.is_handed_out()
+                    && withdrawal.prepared_at <= cutoff
+            })
+            .map(|(txid, _)| txid)
+            .collect()
+    });
+    expired
+        .iter()
+        .filter(|txid| cancel_prepared(txid).is_ok())
+        .count() as u64
+}
+
+/*
+ * settles the cancelled withdrawals and handed out psbts holding inputs of `addr`
+ * against a walk of its utxos down to `boundary`. an input gone from the chain means
+ * the signed transaction went out anyway and it gets logged as submitted. psbts are
+ * left at that, for a cancelled withdrawal once the tip is
+ * `CANCEL_SETTLE_DEPTH` blocks past the first walk after the cancellation with every
+ * input still unspent, they are spendable again. inputs below the boundary weren't
+ * walked, the address gets walked in full the next time before deciding on those
+*/
+pub fn settle_prepared(addr: &str, boundary: u32, unspent: &HashSet<Outpoint>, tip_height: u32) {
+    let signed: Vec<(String, PreparedWithdrawal)> = read_prepared_withdrawals(|prepared| {
+        prepared
+            .iter()
+            .filter(|(_, withdrawal)| {
+                withdrawal.cancelled_at.is_some() || withdrawal.is_handed_out()
+            })
+            .collect()
+    });
+    for (txid, mut withdrawal) in signed {
+        let inputs: Vec<&Utxo> = withdrawal
+            .locked
+            .iter()
+            .flat_map(|locked| locked_utxos_of(locked, addr))
+            .collect();
+        if inputs.is_empty() {
+            continue;
+        }
+        let walked = |utxo: &Utxo| utxo.height > boundary;
+        if inputs
+            .iter()
+            .any(|utxo| walked(utxo) && !unspent.contains(&utxo.outpoint))
+        {
+            write_prepared_withdrawals(|prepared| prepared.remove(&txid));
+            let mut record = withdrawal.record;
+            record.status = TransactionStatus::Submitted;
+            record.timestamp = ic_cdk::api::time();
+            write_transaction_log(|log| log.record(record, withdrawal.raw_transaction));
+            continue;
+        }
+        if withdrawal.cancelled_at.is_none() {
+            continue;
+        }
+        match withdrawal.release_height {
+            None => {
+                withdrawal.release_height = Some(tip_height + CANCEL_SETTLE_DEPTH);
+                write_prepared_withdrawals(|prepared| prepared.insert(txid, withdrawal));
+            }
+            Some(height) if tip_height < height => {}
+            Some(_) if !inputs.iter().all(|utxo| walked(utxo)) => {
+                write_sync_cursors(|cursors| cursors.remove(&addr.to_string()));
+            }
+            Some(_) => {
+                write_prepared_withdrawals(|prepared| prepared.remove(&txid));
+                release_locked_utxos(withdrawal.locked);
+            }
+        }
+    }
+}
+
+/*
+ * outpoints of `addr` spent by a transaction of the canister that is prepared, queued
+ * for submission, reserved for a template or submitted but not yet mined. the chain
+ * keeps reporting them until the spend confirms or gets abandoned
+ */
+pub fn held_outpoints(addr: &str) -> HashSet<Outpoint> {
+    let mut locked: Vec<LockedUtxos> = read_prepared_withdrawals(|prepared| {
+        prepared
+            .iter()
+            .flat_map(|(_, withdrawal)| withdrawal.locked)
+            .collect()
+    });
+    locked.extend(read_submission_queue(|queue| {
+        queue
+            .iter()
+            .flat_map(|(_, submission)| submission.locked)
+            .collect::<Vec<_>>()
+    }));
+    locked.extend(read_template_reservations(|reservations| {
+        reservations
+            .iter()
+            .flat_map(|(_, reservation)| reservation.locked)
+            .collect::<Vec<_>>()
+    }));
+    locked
+        .iter()
+        .flat_map(|locked| locked_utxos_of(locked, addr))
+        .map(|utxo| utxo.outpoint.clone())
+        .chain(pending_change::spent_inputs_of(addr))
+        .collect()
+}
+
+fn locked_utxos_of<'a>(locked: &'a LockedUtxos, addr: &str) -> Vec<&'a Utxo> {
+    match locked {
+        LockedUtxos::Bitcoin { addr: owner, utxos } if owner == addr => utxos.iter().collect(),
+        LockedUtxos::Runic {
+            addr: owner, utxos, ..
+        } if owner == addr => utxos.iter().map(|utxo| &utxo.utxo).collect(),
+        _ => vec![],
+    }
+}
+
+/*
+ * strips the signatures off a transaction signed by the canister and carries them over
+ * into a psbt as partial signatures. legacy inputs only come with their spent output,
+ * the canister never sees the full previous transactions. the mock signatures of a
+ * `simulated` transaction aren't carried over
+*/
+fn signed_psbt(txn: &Transaction, spent_outputs: Vec<TxOut>, simulated: bool) -> Psbt {
+    let mut unsigned = txn.clone();
+    for input in unsigned.input.iter_mut() {
+        input.script_sig = ScriptBuf::new();
+        input.witness.clear();
+    }
+    let mut psbt = Psbt::from_unsigned_tx(unsigned).expect("transaction should be unsigned");
+    for ((input, signed), spent) in psbt
+        .inputs
+        .iter_mut()
+        .zip(txn.input.iter())
+        .zip(spent_outputs)
+    {
+        input.witness_utxo = Some(spent);
+        if simulated {
+            continue;
+        }
+        // taproot inputs are key path spends, the witness holds just the signature
+        if let Some(signature) = signed.witness.nth(0) {
+            let signature = taproot::Signature::from_slice(signature)
+                .expect("canister should produce valid schnorr signatures");
+            input.sighash_type = Some(signature.sighash_type.into());
+            input.tap_key_sig = Some(signature);
+            continue;
+        }
+        let pushes: Vec<&[u8]> = signed
+            .script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        if let [signature, pubkey] = pushes.as_slice() {
+            let signature = ecdsa::Signature::from_slice(signature)
+                .expect("canister should produce valid ecdsa signatures");
+            let pubkey = PublicKey::from_slice(pubkey).expect("canister keys should be valid");
+            input.sighash_type = Some(signature.sighash_type.into());
+            input.partial_sigs.insert(pubkey, signature);
         }
     }
+    psbt
 }
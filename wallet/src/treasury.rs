@@ -0,0 +1,253 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use bitcoin::ScriptBuf;
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    bitcoin::{self, get_fee_per_vbyte, Branch, Intent, PlanError, TxPlanner},
+    fee_quotes,
+    state::{
+        read_config, read_deposit_addresses, read_treasury_credits, read_utxo_manager,
+        record_event_for, write_treasury_credits, DepositAddress, EventKind, TreasuryMode,
+    },
+    transaction_handler::{release_locked_utxos, SubmittedTransactionIdType, TransactionType},
+    types::{CoinSelection, FeePayer, WalletError},
+    updater::{self, TargetType},
+    utils::deposit_pool_addresses,
+};
+
+thread_local! {
+    static POOLING_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static POOLING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped round doesn't block the following ones
+struct PoolGuard;
+
+impl PoolGuard {
+    fn acquire() -> Option<Self> {
+        if POOLING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for PoolGuard {
+    fn drop(&mut self) {
+        POOLING.set(false);
+    }
+}
+
+fn mode() -> Option<TreasuryMode> {
+    read_config(|config| config.treasury_mode.clone()).filter(|mode| mode.enabled)
+}
+
+pub fn is_enabled() -> bool {
+    mode().is_some()
+}
+
+pub fn start_pooling() {
+    let mode = mode();
+    POOLING_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        if let Some(mode) = mode {
+            *timer = Some(ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(mode.interval_mins * 60),
+                || ic_cdk::spawn(pool_round()),
+            ));
+        }
+    });
+}
+
+pub fn credit_of(principal: &Principal) -> u64 {
+    read_treasury_credits(|credits| credits.get(principal)).unwrap_or_default()
+}
+
+fn credit(principal: Principal, amount: u64) {
+    write_treasury_credits(|credits| {
+        let balance = credits.get(&principal).unwrap_or_default();
+        credits.insert(principal, balance + amount);
+    })
+}
+
+// fails with the principal's credit when it doesn't cover `amount`
+fn debit(principal: Principal, amount: u64) -> Result<(), u64> {
+    write_treasury_credits(|credits| {
+        let balance = credits.get(&principal).unwrap_or_default();
+        if balance < amount {
+            return Err(balance);
+        }
+        if balance == amount {
+            credits.remove(&principal);
+        } else {
+            credits.insert(principal, balance - amount);
+        }
+        Ok(())
+    })
+}
+
+// sats the unsigned transfer pays to `script_pubkey`, pool movements are plain bitcoin ones
+fn paid_to(txn: &TransactionType, script_pubkey: &ScriptBuf) -> u64 {
+    let TransactionType::Bitcoin { txn, .. } = txn else {
+        return 0;
+    };
+    txn.output
+        .iter()
+        .filter(|output| output.script_pubkey == *script_pubkey)
+        .map(|output| output.value.to_sat())
+        .sum()
+}
+
+/*
+ * sweeps the principal's unallocated bitcoin into the pool, the fee coming out of
+ * the swept amount, and credits the principal what reaches the pool once the
+ * sweep is submitted. runes stay on the deposit address
+*/
+async fn pool_deposits(
+    principal: Principal,
+    address: &str,
+    min_amount: u64,
+) -> Result<Option<u64>, WalletError> {
+    updater::fetch_utxos_for_owner(
+        address,
+        Some(principal),
+        TargetType::Bitcoin { target: u64::MAX },
+    )
+    .await;
+    let allocated = crate::jar_allocation(&principal) + fee_quotes::reserved_by(&principal);
+    let amount = crate::bitcoin_branches_balance(&principal).saturating_sub(allocated);
+    if amount == 0 || amount < min_amount {
+        return Ok(None);
+    }
+    let pool = deposit_pool_addresses();
+    let pool_script = bitcoin::address_validation(&pool.bitcoin)
+        .map_err(WalletError::InvalidAddress)?
+        .script_pubkey();
+    let txn = crate::bitcoin_withdrawal(
+        principal,
+        pool.bitcoin,
+        amount,
+        None,
+        FeePayer::Receiver,
+        CoinSelection::default(),
+        None,
+        None,
+    )
+    .await?;
+    let credited = paid_to(&txn, &pool_script);
+    let SubmittedTransactionIdType::Bitcoin { txid, .. } = txn.build_and_submit(None).await?;
+    credit(principal, credited);
+    record_event_for(
+        principal,
+        EventKind::DepositPooled {
+            address: String::from(address),
+            amount,
+            credited,
+            txid,
+        },
+    );
+    Ok(Some(credited))
+}
+
+// sweeps every registered deposit address holding at least the mode's minimum
+pub async fn pool_round() {
+    let Some(_guard) = PoolGuard::acquire() else {
+        return;
+    };
+    let Some(mode) = mode() else {
+        return;
+    };
+    let addresses: Vec<(String, DepositAddress)> =
+        read_deposit_addresses(|addresses| addresses.iter().collect());
+    for (address, registered) in addresses {
+        if let Err(err) = pool_deposits(registered.principal, &address, mode.min_amount).await {
+            ic_cdk::println!("pooling {} failed: {:?}", address, err);
+        }
+    }
+}
+
+/*
+ * pays `amount` out of the pool, debiting the caller's credit with everything the
+ * transfer takes out of the pool apart from its change. the debit is refunded when
+ * nothing gets submitted
+*/
+pub async fn withdraw(
+    caller: Principal,
+    to: String,
+    amount: u64,
+    fee_per_vbytes: Option<u64>,
+    fee_payer: FeePayer,
+    coin_selection: CoinSelection,
+    memo: Option<Vec<u8>>,
+) -> Result<SubmittedTransactionIdType, WalletError> {
+    if fee_payer != FeePayer::Sender && amount <= bitcoin::DUST_THRESHOLD {
+        return Err(WalletError::AmountBelowFee {
+            minimum: bitcoin::DUST_THRESHOLD + 1,
+        });
+    }
+    if credit_of(&caller) < amount {
+        return Err(WalletError::InsufficientBalance);
+    }
+    let pool = deposit_pool_addresses();
+    let to = bitcoin::address_validation(&to).map_err(WalletError::InvalidAddress)?;
+    let from = bitcoin::address_validation(&pool.bitcoin).map_err(WalletError::InvalidAddress)?;
+    if read_utxo_manager(|manager| manager.get_bitcoin_balance(&pool.bitcoin)) < amount {
+        // swept deposits count once they are confirmed
+        updater::fetch_utxos_and_update_balances(
+            &pool.bitcoin,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+    }
+    let fee_per_vbytes = match fee_per_vbytes {
+        None => get_fee_per_vbyte().await,
+        Some(fee) => fee,
+    };
+    // the pool keeps its change on the same address
+    let txn = TxPlanner::new(
+        Branch {
+            addr: &pool.bitcoin,
+            account: pool.icrc1,
+            address: from.clone(),
+        },
+        fee_per_vbytes,
+    )
+    .with(Intent::SendBtc { to, amount })
+    .with(Intent::PayFeeFrom(fee_payer))
+    .selection(coin_selection)
+    .memo(memo)
+    .plan()
+    .map_err(|err| match err {
+        PlanError::Shortfall(required_value)
+            if fee_payer == FeePayer::Receiver && required_value > amount =>
+        {
+            WalletError::AmountBelowFee {
+                minimum: required_value,
+            }
+        }
+        err => err.into(),
+    })?;
+    let spent: u64 = txn
+        .spent_outputs()
+        .iter()
+        .map(|output| output.value.to_sat())
+        .sum();
+    let charged = spent - paid_to(&txn, &from.script_pubkey());
+    if debit(caller, charged).is_err() {
+        release_locked_utxos(txn.locked_utxos());
+        return Err(WalletError::InsufficientBalance);
+    }
+    let submitted = txn.build_and_submit(None).await;
+    if submitted.is_err() {
+        credit(caller, charged);
+    }
+    submitted
+}
@@ -1,6 +1,12 @@
-use candid::{CandidType, Decode, Deserialize, Encode};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint};
 use ic_stable_structures::{storable::Bound, Storable};
 
+use crate::{
+    ord_canister::OrdBackendHealth,
+    state::{Chain, DerivationScheme, PendingChange, TransactionKind},
+};
+
 #[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct RuneId {
     pub block: u64,
@@ -19,6 +25,14 @@ impl Storable for RuneId {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+// optional install arguments next to the bitcoin canister's network
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    pub derivation_scheme: Option<DerivationScheme>,
+    // defaults to the chain the network stands for
+    pub chain: Option<Chain>,
+}
+
 #[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TokenType {
     Bitcoin,
@@ -38,3 +52,390 @@ impl Storable for TokenType {
 
     const BOUND: Bound = Bound::Unbounded;
 }
+
+// usd per whole unit of `symbol` is `rate / 10^decimals`, `timestamp` in seconds
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FiatRate {
+    pub symbol: String,
+    pub rate: u64,
+    pub decimals: u32,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FeePayer {
+    Sender,
+    // the fee is deducted from the amount delivered to the receiver
+    Receiver,
+    // as `Receiver` while the delivered amount stays above dust, the sender pays the rest
+    ReceiverAboveDust,
+}
+
+// encoding of a `sign_message` signature
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageSigFormat {
+    // bitcoin core's `signmessage`
+    Legacy,
+    Bip322,
+}
+
+#[derive(CandidType)]
+pub struct SignedMessage {
+    pub address: String,
+    // base64 encoded
+    pub signature: String,
+}
+
+// where the canister's cycle balance stands against the configured thresholds
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CycleLevel {
+    Healthy,
+    Low,
+    // discretionary calls are off until the canister is topped up
+    Critical,
+}
+
+// receiver of a withdrawal, a contact gets looked up in the caller's address book
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Destination {
+    Address(String),
+    Contact(String),
+}
+
+// order the utxos of a bitcoin withdrawal get picked in
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CoinSelection {
+    // sweeps the dust first, the behaviour withdrawals always had
+    #[default]
+    SmallestFirst,
+    // fewest inputs, so the lowest fee for the transaction itself
+    LargestFirst,
+    // looks for utxos that cover the amount without a change output, largest first otherwise
+    BranchAndBound,
+}
+
+// capabilities advertised to clients through `get_supported_features`
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    RunestoneTransfer,
+    CombinedTransfer,
+    MultiSenderTransfer,
+    ReceiverPaysFee,
+    AnchorFeeBump,
+    MultisigWithdrawal,
+    BatchedWithdrawal,
+    TransferMemo,
+    Statement,
+    ChangeAddress,
+    ColdStorageSweep,
+    RunePolicy,
+    RawTransaction,
+    FeeHistory,
+    BalanceReconciliation,
+    Treasury,
+    TwoPhaseWithdrawal,
+    PaperTrading,
+    OrdBackendFailover,
+    SubaccountWallets,
+    MultiRuneTransfer,
+    Notifications,
+    TaprootAddresses,
+    Jars,
+    CkbtcBridge,
+    WithdrawalPolicy,
+    BalanceSubscriptions,
+    PsbtExport,
+    CoinSelection,
+    RateLimits,
+    OpReturnMemo,
+    SweepAll,
+    WithdrawalApprovals,
+    DepositScanning,
+    BatchPayouts,
+    Metrics,
+    RuneLedger,
+    Bip44Derivation,
+    ChangeSplitting,
+    Signet,
+    EmergencyPause,
+    Webhook,
+    PublicConfig,
+    RuneSplit,
+    RuneBurn,
+    PendingChange,
+    AddressBook,
+    RuneFundedWithdrawal,
+    BalanceHistory,
+    RunestoneDecoding,
+    TreasuryMode,
+    MessageSigning,
+    Delegation,
+    ExternalAddresses,
+    CycleMonitoring,
+    FeeSuggestions,
+    StatementExport,
+    RuneConsolidation,
+    FeeSponsorship,
+}
+
+#[derive(CandidType)]
+pub struct Health {
+    // false once every ord backend is out of the rotation
+    pub healthy: bool,
+    pub ord_backends: Vec<OrdBackendHealth>,
+}
+
+#[derive(CandidType)]
+pub struct CachedBalances {
+    pub bitcoin: u64,
+    pub runes: Vec<(RuneId, u128)>,
+    // tip height of the last complete sync, `None` if the address never synced
+    pub synced_at_height: Option<u32>,
+}
+
+#[derive(CandidType)]
+pub struct UtxoInvariantReport {
+    pub addresses_checked: u64,
+    // outpoints counted more than once, found before any repair
+    pub violations: Vec<(String, Outpoint)>,
+    // recorded outpoints a prepared, queued or submitted transaction already spends
+    pub held: Vec<(String, Outpoint)>,
+    pub repaired: bool,
+    pub duplicates_prevented: u64,
+}
+
+// a utxo as the canister classified it, plain bitcoin ones carry no runes
+#[derive(CandidType)]
+pub struct UtxoInfo {
+    pub outpoint: Outpoint,
+    pub value: u64,
+    pub height: u32,
+    pub runes: Vec<(RuneId, u128)>,
+}
+
+// the settings clients build transactions against, the canister's keys aren't part of it
+#[derive(CandidType)]
+pub struct PublicConfig {
+    pub network: BitcoinNetwork,
+    pub chain: Chain,
+    pub keyname: String,
+    pub derivation_scheme: DerivationScheme,
+    pub paper_trading: bool,
+    pub taproot_addresses: bool,
+    // confirmations a deposit needs before it can be spent
+    pub min_confirmations: u32,
+    // change at or below this is left to the fee rather than given an output
+    pub dust_threshold: u64,
+    // value of the output carrying runes to the receiver, unless the caller picks one
+    pub default_postage: u64,
+    pub anchor_output_value: Option<u64>,
+    pub paused: bool,
+}
+
+// in millisatoshis per vbyte like the bitcoin canister's percentiles, `fee` in satoshis
+#[derive(CandidType)]
+pub struct FeeEstimate {
+    pub kind: TransactionKind,
+    pub vsize: u64,
+    pub fee_per_vbytes: u64,
+    pub fee: u64,
+}
+
+// a submitted transaction whose outputs back to the wallet weren't reported by the chain yet
+#[derive(CandidType)]
+pub struct PendingTransaction {
+    pub txid: String,
+    pub submitted_at: u64,
+    pub outputs: Vec<PendingChange>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum TemplateKind {
+    Bitcoin {
+        fee_payer: FeePayer,
+        coin_selection: Option<CoinSelection>,
+    },
+    Runestone {
+        runeid: RuneId,
+    },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TemplateArgs {
+    pub to: String,
+    // sats for bitcoin templates
+    pub amount: u128,
+    pub fee_per_vbytes: Option<u64>,
+}
+
+#[derive(CandidType)]
+pub struct TemplateInput {
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    pub address: String,
+}
+
+#[derive(CandidType)]
+pub struct TemplateOutput {
+    pub script_pubkey: Vec<u8>,
+    pub value: u64,
+    // `None` for op_return outputs
+    pub address: Option<String>,
+}
+
+#[derive(CandidType)]
+pub enum SigningScheme {
+    // legacy sighash, SIGHASH_ALL
+    Ecdsa,
+    // bip341 key path sighash, SIGHASH_DEFAULT
+    Schnorr,
+}
+
+#[derive(CandidType)]
+pub struct SigningInstruction {
+    pub input: u32,
+    pub address: String,
+    pub scheme: SigningScheme,
+}
+
+// transaction built by the canister's selection and fee logic, left unsigned
+#[derive(CandidType)]
+pub struct UnsignedTemplate {
+    pub id: u64,
+    pub unsigned_transaction: Vec<u8>,
+    pub inputs: Vec<TemplateInput>,
+    pub outputs: Vec<TemplateOutput>,
+    pub fee: u64,
+    pub signing_instructions: Vec<SigningInstruction>,
+    // the inputs are released back to the wallet afterwards
+    pub expires_at: u64,
+}
+
+// outcome of a withdrawal split over several transactions, `error` stops the
+// remaining chunks once at least one transaction went out
+#[derive(CandidType)]
+pub struct ChunkedWithdrawal {
+    pub txids: Vec<String>,
+    pub withdrawn: u64,
+    pub error: Option<WalletError>,
+}
+
+// one receiver of a `withdraw_bitcoin_batch`, `fee` is its share of the network fee
+#[derive(CandidType)]
+pub struct BatchPayoutReceipt {
+    pub to: String,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[derive(CandidType)]
+pub struct BitcoinBatchReceipt {
+    pub txid: String,
+    pub fee: u64,
+    pub payouts: Vec<BatchPayoutReceipt>,
+}
+
+// what a `sweep_all` moved, `error` stops the sweep after the listed transactions
+#[derive(CandidType)]
+pub struct SweepAll {
+    pub txids: Vec<String>,
+    // sats delivered to the destination, after fees
+    pub bitcoin: u64,
+    pub runes: Vec<(RuneId, u128)>,
+    pub error: Option<WalletError>,
+}
+
+#[derive(CandidType)]
+pub struct TreasuryBalance {
+    pub bitcoin: u64,
+    pub runes: Vec<(RuneId, u128)>,
+}
+
+#[derive(CandidType)]
+pub struct RuneBalanceDetail {
+    pub runeid: RuneId,
+    pub balance: u128,
+    // false for runes outside the operator's rune policy
+    pub spendable: bool,
+}
+
+// a sender of a multi-sender transfer holding less than its part, fee share included once known
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SenderShortfall {
+    pub principal: Principal,
+    pub address: String,
+    pub required: u64,
+    pub available: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum WalletError {
+    InvalidAddress(String),
+    InsufficientBalance,
+    UtxoNotFound,
+    TransactionNotFound,
+    AnchorUnavailable,
+    InvalidArgument(String),
+    SignatureVerificationFailed(String),
+    InvalidPsbt(String),
+    Unauthorized,
+    RuneNotSupported(RuneId),
+    // the receiver's script type, runes sent to it would likely be lost
+    UnsupportedReceiverScript(String),
+    // above the input or size limit of a single transaction
+    TransactionTooLarge(String),
+    // the ord indexer could not confirm the runes of a selected utxo
+    IndexerUnavailable(String),
+    ExchangeRateUnavailable(String),
+    // above the controller's limit once converted to usd
+    FiatLimitExceeded(String),
+    // the destination is denied or missing from the allow list
+    DestinationNotAllowed(String),
+    DailyLimitExceeded(String),
+    // the caller's quota on metered endpoints is used up for the window
+    RateLimited(String),
+    // the receiver's output wouldn't stay above dust, `minimum` is the smallest sendable amount
+    AmountBelowFee { minimum: u64 },
+    // funds are frozen until a controller unpauses the canister
+    Paused(String),
+    // the senders of a multi-sender transfer that couldn't cover their part
+    InsufficientSenderBalance(Vec<SenderShortfall>),
+    // the spender's allowance from the owner doesn't cover the withdrawal
+    InsufficientAllowance { allowance: u128 },
+    // refused while the cycle balance is below the critical threshold
+    CyclesLow,
+    // the fee sponsor couldn't be reached or turned the withdrawal down
+    SponsorUnavailable(String),
+}
+
+impl WalletError {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::InvalidAddress(_) => "InvalidAddress",
+            Self::InsufficientBalance => "InsufficientBalance",
+            Self::UtxoNotFound => "UtxoNotFound",
+            Self::TransactionNotFound => "TransactionNotFound",
+            Self::AnchorUnavailable => "AnchorUnavailable",
+            Self::InvalidArgument(_) => "InvalidArgument",
+            Self::SignatureVerificationFailed(_) => "SignatureVerificationFailed",
+            Self::InvalidPsbt(_) => "InvalidPsbt",
+            Self::Unauthorized => "Unauthorized",
+            Self::RuneNotSupported(_) => "RuneNotSupported",
+            Self::UnsupportedReceiverScript(_) => "UnsupportedReceiverScript",
+            Self::TransactionTooLarge(_) => "TransactionTooLarge",
+            Self::IndexerUnavailable(_) => "IndexerUnavailable",
+            Self::ExchangeRateUnavailable(_) => "ExchangeRateUnavailable",
+            Self::FiatLimitExceeded(_) => "FiatLimitExceeded",
+            Self::DestinationNotAllowed(_) => "DestinationNotAllowed",
+            Self::DailyLimitExceeded(_) => "DailyLimitExceeded",
+            Self::RateLimited(_) => "RateLimited",
+            Self::AmountBelowFee { .. } => "AmountBelowFee",
+            Self::Paused(_) => "Paused",
+            Self::InsufficientSenderBalance(_) => "InsufficientSenderBalance",
+            Self::InsufficientAllowance { .. } => "InsufficientAllowance",
+            Self::CyclesLow => "CyclesLow",
+            Self::SponsorUnavailable(_) => "SponsorUnavailable",
+        }
+    }
+}
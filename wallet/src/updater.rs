@@ -1,57 +1,280 @@
+use std::collections::HashSet;
+
 use bitcoin::hashes::Hash;
-use ic_cdk::api::management_canister::bitcoin::{bitcoin_get_utxos, GetUtxosRequest, UtxoFilter};
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::bitcoin::{GetUtxosRequest, Utxo, UtxoFilter};
 
 use crate::{
-    ord_canister,
-    state::{read_config, read_utxo_manager, write_utxo_manager, RunicUtxo},
-    types::RuneId,
+    bitcoin_api::bitcoin_get_utxos,
+    ckbtc, fee_tracker, metrics,
+    ord_canister::{self, ClassificationError},
+    outbox, pending_change,
+    state::{
+        read_config, read_deposit_addresses, read_deposits, read_sync_cursors,
+        read_transaction_log, read_utxo_manager, record_event, record_event_for, write_deposits,
+        write_imported_addresses, write_sync_cursors, write_utxo_manager, DepositRecord, EventKind,
+        ImportedAddress, ImportedAddresses, Notification, RunicUtxo, SyncCursor,
+    },
+    subscriptions, transaction_handler,
+    types::{RuneId, WalletError},
+    utils::{
+        caller_owning, generate_addresses_from_principal, generate_indexed_addresses_from_principal,
+    },
 };
 
+// consecutive unused addresses after which a scan stops
+pub const GAP_LIMIT: u32 = 20;
+
+// utxos are fetched without a filter, so anything in a block counts
+pub const MIN_CONFIRMATIONS: u32 = 1;
+
+#[derive(CandidType)]
+pub struct ScanReport {
+    pub principal: Principal,
+    pub scanned: u32,
+    pub found: Vec<ImportedAddress>,
+}
+
 fn txid_to_string(txid: &[u8]) -> String {
     bitcoin::Txid::from_raw_hash(Hash::from_slice(txid).unwrap()).to_string()
 }
 
+// blocks below the cursor that are still checked again in case of a reorg
+const REORG_DEPTH: u32 = 6;
+
+// a cursor this recent is taken as the chain's state, blocks come minutes apart
+const FRESH_CURSOR_NANOS: u64 = 60 * 1_000_000_000;
+
+// drops everything recorded for the address and fetches it again from the chain,
+// returns the resulting bitcoin balance
+pub async fn resync_address(addr: &str) -> u64 {
+    write_utxo_manager(|manager| manager.clear_address(addr));
+    write_sync_cursors(|cursors| cursors.remove(&addr.to_string()));
+    fetch_utxos_and_update_balances(addr, TargetType::Bitcoin { target: u64::MAX }).await;
+    read_utxo_manager(|manager| manager.get_bitcoin_balance(addr))
+}
+
+/*
+ * derives the principal's indexed addresses up to `max_index` and registers the ones
+ * holding funds. the bitcoin canister only reports unspent outputs, so an address whose
+ * history is entirely spent counts towards the gap
+*/
+pub async fn scan_indexed_addresses(principal: Principal, max_index: u32) -> ScanReport {
+    let mut found = vec![];
+    let (mut scanned, mut gap) = (0, 0);
+    for index in 0..=max_index {
+        if gap >= GAP_LIMIT {
+            break;
+        }
+        let addresses = generate_indexed_addresses_from_principal(&principal, index);
+        fetch_utxos_and_update_balances(
+            &addresses.bitcoin,
+            TargetType::Bitcoin { target: u64::MAX },
+        )
+        .await;
+        scanned += 1;
+        let (total_value, btc_balance, runes) = read_utxo_manager(|manager| {
+            (
+                manager.total_value(&addresses.bitcoin),
+                manager.get_bitcoin_balance(&addresses.bitcoin),
+                manager
+                    .all_rune_with_balances(&addresses.bitcoin)
+                    .into_iter()
+                    .collect(),
+            )
+        });
+        if total_value == 0 {
+            gap += 1;
+            continue;
+        }
+        gap = 0;
+        found.push(ImportedAddress {
+            index,
+            address: addresses.bitcoin,
+            btc_balance,
+            runes,
+            found_at: ic_cdk::api::time(),
+        });
+    }
+    write_imported_addresses(|imported| {
+        let mut entries = imported.get(&principal).unwrap_or_default().0;
+        for address in found.iter() {
+            entries.retain(|entry| entry.index != address.index);
+            entries.push(address.clone());
+        }
+        entries.sort_by_key(|entry| entry.index);
+        imported.insert(principal, ImportedAddresses(entries));
+    });
+    ScanReport {
+        principal,
+        scanned,
+        found,
+    }
+}
+
+// records the utxo the first time it shows up for the address
+fn detect_deposit(addr: &str, owner: Option<Principal>, utxo: &Utxo, runes: Vec<(RuneId, u128)>) {
+    // a registered deposit address books the deposit to its owner whoever triggered the sync
+    let owner = owner.or_else(|| {
+        read_deposit_addresses(|addresses| addresses.get(&addr.to_string()))
+            .map(|registered| registered.principal)
+    });
+    let txid = txid_to_string(&utxo.outpoint.txid);
+    let key = format!("{}:{}", txid, utxo.outpoint.vout);
+    if read_deposits(|deposits| deposits.contains_key(&key)) {
+        return;
+    }
+    let record = read_transaction_log(|log| log.find_by_txid(&txid));
+    let own = record.is_some();
+    // change and anchor outputs of the same transaction confirm it only once
+    let confirmed_before = own
+        && read_deposits(|deposits| {
+            deposits
+                .range(format!("{}:", txid)..)
+                .next()
+                .is_some_and(|(key, _)| key.starts_with(&format!("{}:", txid)))
+        });
+    let deposit = DepositRecord {
+        address: addr.to_string(),
+        txid: txid.clone(),
+        vout: utxo.outpoint.vout,
+        value: utxo.value,
+        runes,
+        timestamp: ic_cdk::api::time(),
+        own,
+    };
+    write_deposits(|deposits| deposits.insert(key, deposit.clone()));
+    if !own {
+        let event = EventKind::DepositDetected {
+            address: deposit.address.clone(),
+            txid: deposit.txid.clone(),
+            vout: deposit.vout,
+            value: deposit.value,
+            runes: deposit.runes.clone(),
+        };
+        match owner {
+            Some(owner) => record_event_for(owner, event),
+            None => record_event(event),
+        }
+        // only plain bitcoin landing on the owner's deposit address gets wrapped
+        if let Some(owner) = owner {
+            if deposit.runes.is_empty()
+                && generate_addresses_from_principal(&owner).bitcoin == deposit.address
+            {
+                ckbtc::on_deposit(owner, deposit.value);
+            }
+        }
+        subscriptions::notify_deposit(&deposit);
+        outbox::notify(Notification::Deposit(deposit));
+    } else if !confirmed_before {
+        if let Some(record) = record {
+            fee_tracker::record_confirmation(&record);
+        }
+        outbox::notify(Notification::TransactionConfirmed { txid });
+    }
+}
+
 pub enum TargetType {
     Bitcoin { target: u64 },
     Runic { runeid: RuneId, target: u128 },
 }
 
+/*
+ * the bitcoin canister returns utxos newest first, so once an address has a cursor
+ * only the pages above it (minus the reorg window) are walked and only unknown
+ * outpoints are classified. recorded utxos inside the walked window that are no
+ * longer unspent get dropped. spends below the window are left to `resync_address`.
+ * an address synced less than a minute ago isn't fetched again, and the walk stops
+ * on the first page covering `target`
+*/
 pub async fn fetch_utxos_and_update_balances(addr: &str, target: TargetType) {
+    // deposits and syncs show up in the owner's activity when it triggered the fetch
+    fetch_utxos_for_owner(addr, caller_owning(addr), target).await
+}
+
+// the next fetch of the address walks the chain again even though its cursor is fresh
+pub fn mark_stale(addr: &str) {
+    write_sync_cursors(|cursors| {
+        if let Some(mut cursor) = cursors.get(&addr.to_string()) {
+            cursor.synced_at = 0;
+            cursors.insert(addr.to_string(), cursor);
+        }
+    });
+}
+
+// the same as `fetch_utxos_and_update_balances` with the owner known up front
+pub async fn fetch_utxos_for_owner(addr: &str, owner: Option<Principal>, target: TargetType) {
     let network = read_config(|config| config.bitcoin_network());
+    let cursor = read_sync_cursors(|cursors| cursors.get(&addr.to_string()));
+    if cursor.as_ref().is_some_and(|cursor| {
+        ic_cdk::api::time().saturating_sub(cursor.synced_at) < FRESH_CURSOR_NANOS
+    }) {
+        metrics::record_skipped_utxo_fetch();
+        return;
+    }
+    let boundary = cursor.map(|cursor| cursor.tip_height.saturating_sub(REORG_DEPTH));
     let mut arg = GetUtxosRequest {
         address: addr.to_string(),
         network,
         filter: None,
     };
+    let mut unspent = HashSet::new();
+    // the cursor only moves once every utxo above it got classified
+    let mut complete = true;
     loop {
         let utxo_response = bitcoin_get_utxos(arg.clone())
             .await
             .expect("failed getting the utxo response")
             .0;
+        metrics::record_utxo_fetch();
+        let held = transaction_handler::held_outpoints(addr);
         let mut btc_utxos = vec![];
+        let mut reached_boundary = false;
         for utxo in utxo_response.utxos {
-            if read_utxo_manager(|manager| manager.is_recorded_as_runic(addr, &utxo)) {
+            if boundary.is_some_and(|boundary| utxo.height <= boundary) {
+                reached_boundary = true;
+                break;
+            }
+            unspent.insert(utxo.outpoint.clone());
+            pending_change::confirm(&txid_to_string(&utxo.outpoint.txid), utxo.outpoint.vout);
+            // spent by a transaction of the canister the chain hasn't settled yet
+            if held.contains(&utxo.outpoint) {
+                continue;
+            }
+            if read_utxo_manager(|manager| manager.is_recorded(addr, &utxo)) {
                 continue;
             }
             let txid = txid_to_string(&utxo.outpoint.txid);
-            match ord_canister::get_runes_by_utxo(txid, utxo.outpoint.vout)
-                .await
-                .unwrap()
-                .0
-            {
-                Err(_) => {
+            match ord_canister::get_runes_by_utxo(txid, utxo.outpoint.vout).await {
+                // picked up again by a later fetch once the backends agree
+                Err(ClassificationError::Divergent) => {
+                    ic_cdk::println!("ord backends disagree on the utxo, leaving it unrecorded");
+                    complete = false;
+                    continue;
+                }
+                Err(ClassificationError::Unavailable) => {
                     ic_cdk::println!("err while checking for runes, recording as non runic utxo");
+                    detect_deposit(addr, owner, &utxo, vec![]);
                     btc_utxos.push(utxo);
                     continue;
                 }
                 Ok(runes) => {
                     if runes.is_empty() {
+                        detect_deposit(addr, owner, &utxo, vec![]);
                         btc_utxos.push(utxo);
                         continue;
                     }
+                    detect_deposit(
+                        addr,
+                        owner,
+                        &utxo,
+                        runes
+                            .iter()
+                            .map(|rune| (rune.id.clone(), rune.balance))
+                            .collect(),
+                    );
                     for rune in runes {
                         write_utxo_manager(|manager| {
-                            manager.remove_btc_utxo(addr, &utxo);
                             manager.record_runic_utxos(
                                 addr,
                                 rune.id,
@@ -68,26 +291,149 @@ pub async fn fetch_utxos_and_update_balances(addr: &str, target: TargetType) {
         // recording of bitcoin utxo
         write_utxo_manager(|manager| manager.record_btc_utxos(addr, btc_utxos));
 
-        match target {
+        let covered = match target {
             TargetType::Runic { ref runeid, target } => {
-                let balance =
-                    read_utxo_manager(|manager| manager.get_runestone_balance(addr, runeid));
-                if balance < target && utxo_response.next_page.is_some() {
-                    arg.filter = Some(UtxoFilter::Page(utxo_response.next_page.unwrap()));
-                    continue;
-                } else {
-                    break;
-                }
+                read_utxo_manager(|manager| manager.get_runestone_balance(addr, runeid)) >= target
             }
             TargetType::Bitcoin { target } => {
-                let balance = read_utxo_manager(|manager| manager.get_bitcoin_balance(addr));
-                if balance < target && utxo_response.next_page.is_some() {
-                    arg.filter = Some(UtxoFilter::Page(utxo_response.next_page.unwrap()));
-                    continue;
-                } else {
+                read_utxo_manager(|manager| manager.get_bitcoin_balance(addr)) >= target
+            }
+        };
+        match utxo_response.next_page {
+            Some(page) if !reached_boundary && !covered => {
+                arg.filter = Some(UtxoFilter::Page(page));
+            }
+            next_page => {
+                // stopping on the target before the old cursor leaves utxos unseen
+                if next_page.is_some() && !reached_boundary {
                     break;
                 }
+                write_utxo_manager(|manager| {
+                    manager.prune_spent(addr, boundary.unwrap_or(0), &unspent)
+                });
+                pending_change::settle_inputs(addr, boundary.unwrap_or(0), &unspent);
+                transaction_handler::settle_prepared(
+                    addr,
+                    boundary.unwrap_or(0),
+                    &unspent,
+                    utxo_response.tip_height,
+                );
+                if complete {
+                    write_sync_cursors(|cursors| {
+                        cursors.insert(
+                            addr.to_string(),
+                            SyncCursor {
+                                tip_height: utxo_response.tip_height,
+                                tip_block_hash: utxo_response.tip_block_hash,
+                                synced_at: ic_cdk::api::time(),
+                            },
+                        )
+                    });
+                }
+                let event = EventKind::UtxosSynced {
+                    address: addr.to_string(),
+                    tip_height: complete.then_some(utxo_response.tip_height),
+                    btc_balance: read_utxo_manager(|manager| manager.get_bitcoin_balance(addr)),
+                };
+                match owner {
+                    Some(owner) => record_event_for(owner, event),
+                    None => record_event(event),
+                }
+                break;
             }
         }
     }
 }
+
+// syncs the receive branch first and falls back to the change branch
+// only when the former can't cover the target
+pub async fn fetch_bitcoin_branches(receive_addr: &str, change_addr: &str, target: u64) {
+    fetch_utxos_and_update_balances(receive_addr, TargetType::Bitcoin { target }).await;
+    let balance = read_utxo_manager(|manager| manager.get_bitcoin_balance(receive_addr));
+    if balance < target {
+        fetch_utxos_and_update_balances(
+            change_addr,
+            TargetType::Bitcoin {
+                target: target - balance,
+            },
+        )
+        .await;
+    }
+}
+
+/*
+ * cross-checks the runic utxos a transfer of `amount` would select against the ord
+ * indexer before anything gets built. utxos whose indexed runes disagree with the
+ * local records are re-classified, which can change the selection, so the walk
+ * restarts until every utxo of the selection got confirmed
+*/
+pub async fn verify_runic_selection(
+    addr: &str,
+    runeid: &RuneId,
+    amount: u128,
+) -> Result<(), WalletError> {
+    let mut verified = HashSet::new();
+    loop {
+        let mut total = 0;
+        let mut pending = None;
+        for r_utxo in read_utxo_manager(|manager| manager.runic_utxos(addr, runeid)) {
+            if !verified.contains(&r_utxo.utxo.outpoint) {
+                pending = Some(r_utxo.utxo);
+                break;
+            }
+            total += r_utxo.balance;
+            if total > amount {
+                return Ok(());
+            }
+        }
+        // every recorded utxo got confirmed, the balance check is left to the caller
+        let Some(utxo) = pending else {
+            return Ok(());
+        };
+        verified.insert(utxo.outpoint.clone());
+        let txid = txid_to_string(&utxo.outpoint.txid);
+        let indexed: Vec<(RuneId, u128)> =
+            match ord_canister::get_runes_by_utxo(txid.clone(), utxo.outpoint.vout).await {
+                Ok(runes) => runes
+                    .into_iter()
+                    .map(|rune| (rune.id, rune.balance))
+                    .collect(),
+                Err(ClassificationError::Unavailable) => {
+                    return Err(WalletError::IndexerUnavailable(format!(
+                        "no ord backend answered for {}:{}",
+                        txid, utxo.outpoint.vout
+                    )))
+                }
+                Err(ClassificationError::Divergent) => {
+                    return Err(WalletError::IndexerUnavailable(format!(
+                        "ord backends disagree on {}:{}",
+                        txid, utxo.outpoint.vout
+                    )))
+                }
+            };
+        if read_utxo_manager(|manager| manager.runes_of(addr, &utxo.outpoint)) == indexed {
+            continue;
+        }
+        ic_cdk::println!(
+            "indexed runes of {}:{} disagree with the local records, re-classifying",
+            txid,
+            utxo.outpoint.vout
+        );
+        write_utxo_manager(|manager| {
+            manager.remove_utxo(addr, &utxo.outpoint);
+            if indexed.is_empty() {
+                manager.record_btc_utxos(addr, vec![utxo.clone()]);
+            }
+            for (id, balance) in indexed {
+                manager.record_runic_utxos(
+                    addr,
+                    id,
+                    vec![RunicUtxo {
+                        utxo: utxo.clone(),
+                        balance,
+                    }],
+                );
+            }
+        });
+    }
+}
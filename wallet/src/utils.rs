@@ -2,7 +2,10 @@ use candid::{CandidType, Principal};
 use icrc_ledger_types::icrc1::account::Account;
 use tiny_keccak::{Hasher, Sha3};
 
-use crate::bitcoin::account_to_p2pkh_address;
+use crate::{
+    bitcoin::{account_to_p2pkh_address, account_to_p2tr_address},
+    state::read_config,
+};
 
 #[derive(CandidType)]
 pub struct Addresses {
@@ -18,6 +21,43 @@ pub fn principal_to_subaccount(principal: &Principal) -> [u8; 32] {
     hash
 }
 
+// internal chain of the principal, receives the change of its withdrawals
+// so deposits and change never share an address
+pub fn principal_to_change_subaccount(principal: &Principal) -> [u8; 32] {
+    tagged_subaccount(principal, b"change")
+}
+
+// canister's half of the principal's 2-of-2 multisig
+pub fn principal_to_multisig_subaccount(principal: &Principal) -> [u8; 32] {
+    tagged_subaccount(principal, b"multisig")
+}
+
+// index 0 is the principal's deposit address, higher indices cover wallets migrated in
+pub fn principal_to_indexed_subaccount(principal: &Principal, index: u32) -> [u8; 32] {
+    if index == 0 {
+        principal_to_subaccount(principal)
+    } else {
+        tagged_subaccount(
+            principal,
+            &[b"index".as_slice(), &index.to_be_bytes()].concat(),
+        )
+    }
+}
+
+// numbered wallets the principal opens next to its deposit address
+pub fn principal_to_numbered_subaccount(principal: &Principal, num: u128) -> [u8; 32] {
+    tagged_subaccount(principal, &[b"num".as_slice(), &num.to_be_bytes()].concat())
+}
+
+fn tagged_subaccount(principal: &Principal, tag: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut hasher = Sha3::v256();
+    hasher.update(principal.as_slice());
+    hasher.update(tag);
+    hasher.finalize(&mut hash);
+    hash
+}
+
 pub fn generate_addresses_from_principal(principal: &Principal) -> Addresses {
     let canister_id = ic_cdk::id();
     let subaccount = principal_to_subaccount(principal);
@@ -32,6 +72,117 @@ pub fn generate_addresses_from_principal(principal: &Principal) -> Addresses {
     }
 }
 
+// taproot counterpart of the deposit address, spent with schnorr key path signatures
+pub fn generate_taproot_addresses_from_principal(principal: &Principal) -> Addresses {
+    let canister_id = ic_cdk::id();
+    let subaccount = principal_to_subaccount(principal);
+    let account = Account {
+        owner: canister_id,
+        subaccount: Some(subaccount),
+    };
+    let bitcoin_address = account_to_p2tr_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+// the caller when the address is one of its deposit, change or taproot addresses
+pub fn caller_owning(addr: &str) -> Option<Principal> {
+    let caller = ic_cdk::caller();
+    let owned = generate_addresses_from_principal(&caller).bitcoin == addr
+        || generate_change_addresses_from_principal(&caller).bitcoin == addr
+        || (read_config(|config| config.schnorr_public_key.is_some())
+            && generate_taproot_addresses_from_principal(&caller).bitcoin == addr);
+    owned.then_some(caller)
+}
+
+pub fn generate_change_addresses_from_principal(principal: &Principal) -> Addresses {
+    let canister_id = ic_cdk::id();
+    let subaccount = principal_to_change_subaccount(principal);
+    let account = Account {
+        owner: canister_id,
+        subaccount: Some(subaccount),
+    };
+    let bitcoin_address = account_to_p2pkh_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+pub fn generate_indexed_addresses_from_principal(principal: &Principal, index: u32) -> Addresses {
+    let canister_id = ic_cdk::id();
+    let subaccount = principal_to_indexed_subaccount(principal, index);
+    let account = Account {
+        owner: canister_id,
+        subaccount: Some(subaccount),
+    };
+    let bitcoin_address = account_to_p2pkh_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+pub fn generate_numbered_addresses_from_principal(principal: &Principal, num: u128) -> Addresses {
+    let canister_id = ic_cdk::id();
+    let subaccount = principal_to_numbered_subaccount(principal, num);
+    let account = Account {
+        owner: canister_id,
+        subaccount: Some(subaccount),
+    };
+    let bitcoin_address = account_to_p2pkh_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+// the canister's default (no subaccount) account, owned by the operator
+pub fn treasury_addresses() -> Addresses {
+    let account = Account {
+        owner: ic_cdk::id(),
+        subaccount: None,
+    };
+    let bitcoin_address = account_to_p2pkh_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+// holds the runes of the internal ledger, users only own shares of it
+pub fn rune_ledger_addresses() -> Addresses {
+    let account = Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(tagged_subaccount(&ic_cdk::id(), b"rune-ledger")),
+    };
+    let bitcoin_address = account_to_p2pkh_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+// holds the bitcoin swept from deposit addresses in treasury mode, users own credits on it
+pub fn deposit_pool_addresses() -> Addresses {
+    let account = Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(tagged_subaccount(&ic_cdk::id(), b"deposit-pool")),
+    };
+    let bitcoin_address = account_to_p2pkh_address(&account);
+    Addresses {
+        icrc1: account,
+        bitcoin: bitcoin_address,
+    }
+}
+
+// the treasury funds cpfp fee bumps through anchor outputs
+pub fn fee_pool_addresses() -> Addresses {
+    treasury_addresses()
+}
+
 pub fn subaccount_with_num(num: u128) -> [u8; 32] {
     let mut hash = [8; 32];
     let mut hasher = Sha3::v256();
@@ -40,3 +191,11 @@ pub fn subaccount_with_num(num: u128) -> [u8; 32] {
     hasher.finalize(&mut hash);
     hash
 }
+
+pub fn is_controller() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(String::from("Not authorized"))
+    }
+}
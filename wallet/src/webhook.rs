@@ -0,0 +1,289 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use candid::Nat;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use ic_cdk_timers::TimerId;
+
+use crate::{
+    outbox::{backoff, MAX_ATTEMPTS},
+    state::{
+        read_config, read_webhook_queue, record_event, write_webhook_queue, EventKind,
+        OutboxStatus, TransactionRecord, WebhookDelivery, WithdrawalNotice,
+    },
+    types::{CycleLevel, WalletError},
+};
+
+const DELIVERY_INTERVAL_SECS: u64 = 30;
+
+// only the status line matters, the transform drops the rest
+const MAX_RESPONSE_BYTES: u64 = 4_096;
+
+const MAX_URL_LENGTH: usize = 2_048;
+
+// nodes of the subnet the outcall gets replicated on
+const SUBNET_SIZE: u128 = 13;
+
+thread_local! {
+    static DELIVERY_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static DELIVERING: Cell<bool> = const { Cell::new(false) };
+}
+
+// cleared on drop so a trapped delivery round doesn't block the following ones
+struct DeliveryGuard;
+
+impl DeliveryGuard {
+    fn acquire() -> Option<Self> {
+        if DELIVERING.replace(true) {
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for DeliveryGuard {
+    fn drop(&mut self) {
+        DELIVERING.set(false);
+    }
+}
+
+pub fn start_delivery() {
+    DELIVERY_TIMER.with_borrow_mut(|timer| {
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        *timer = Some(ic_cdk_timers::set_timer_interval(
+            Duration::from_secs(DELIVERY_INTERVAL_SECS),
+            || ic_cdk::spawn(deliver()),
+        ));
+    });
+}
+
+/*
+ * https outcalls leave the subnet over ipv6 only, so the host has to be reachable
+ * there. literal addresses go in brackets, e.g. https://[2001:db8::1]/hook
+ */
+pub fn validate_url(url: &str) -> Result<(), WalletError> {
+    let host = url
+        .strip_prefix("https://")
+        .map(|rest| rest.split(['/', '?', '#']).next().unwrap_or_default())
+        .unwrap_or_default();
+    if host.is_empty() || url.len() > MAX_URL_LENGTH || url.chars().any(char::is_whitespace) {
+        return Err(WalletError::InvalidArgument(format!(
+            "{} isn't an https url",
+            url
+        )));
+    }
+    Ok(())
+}
+
+// queues a post about a withdrawal that just went out, a txid is only ever queued once
+pub fn enqueue(record: &TransactionRecord) {
+    if read_config(|config| config.webhook_url.is_none()) {
+        return;
+    }
+    let now = ic_cdk::api::time();
+    let queued = write_webhook_queue(|queue| {
+        if queue.contains_key(&record.txid) {
+            return false;
+        }
+        let runes = record
+            .rune
+            .iter()
+            .chain(record.additional_runes.iter().flatten())
+            .cloned()
+            .collect();
+        queue.insert(
+            record.txid.clone(),
+            WebhookDelivery {
+                notice: WithdrawalNotice {
+                    txid: record.txid.clone(),
+                    caller: record.caller,
+                    kind: record.kind,
+                    fee: record.fee,
+                    amount: record.amount,
+                    runes,
+                    submitted_at: record.timestamp,
+                },
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+                status: OutboxStatus::Pending,
+            },
+        );
+        true
+    });
+    // posted right away rather than on the next tick of the interval
+    if queued {
+        ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(deliver()));
+    }
+}
+
+fn body(notice: &WithdrawalNotice) -> Vec<u8> {
+    let runes: Vec<serde_json::Value> = notice
+        .runes
+        .iter()
+        .map(|(runeid, amount)| {
+            serde_json::json!({
+                "rune": format!("{}:{}", runeid.block, runeid.tx),
+                // u128 doesn't fit a json number
+                "amount": amount.to_string(),
+            })
+        })
+        .collect();
+    serde_json::to_vec(&serde_json::json!({
+        "txid": notice.txid,
+        "caller": notice.caller.to_text(),
+        "type": format!("{:?}", notice.kind),
+        "fee": notice.fee,
+        "amount": notice.amount,
+        "runes": runes,
+        "submitted_at": notice.submitted_at,
+    }))
+    .expect("should serialize")
+}
+
+fn outcall_cycles(request_bytes: u64) -> u128 {
+    (3_000_000 + 60_000 * SUBNET_SIZE) * SUBNET_SIZE
+        + 400 * SUBNET_SIZE * request_bytes as u128
+        + 800 * SUBNET_SIZE * MAX_RESPONSE_BYTES as u128
+}
+
+async fn post(url: &str, body: Vec<u8>, idempotency_key: String) -> Result<(), String> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        method: HttpMethod::POST,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name(
+            "webhook_transform".to_string(),
+            vec![],
+        )),
+        headers: vec![
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+            // every replica sends the request, receivers dedupe on the key
+            HttpHeader {
+                name: "Idempotency-Key".to_string(),
+                value: idempotency_key,
+            },
+        ],
+        body: Some(body),
+    };
+    let cycles =
+        outcall_cycles(url.len() as u64 + request.body.as_ref().map_or(0, Vec::len) as u64);
+    let (response,) = http_request(request, cycles)
+        .await
+        .map_err(|(code, msg)| format!("{:?}: {}", code, msg))?;
+    if response.status >= Nat::from(200u32) && response.status < Nat::from(300u32) {
+        Ok(())
+    } else {
+        Err(format!("endpoint answered {}", response.status))
+    }
+}
+
+// replicas only have to agree on the status, headers and bodies tend to differ
+pub fn transform(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: vec![],
+    }
+}
+
+/*
+ * posts the due notices, an entry only leaves the queue once the endpoint answered
+ * with a 2xx. delivery is at least once, the txid doubles as the idempotency key
+ */
+pub async fn deliver() {
+    let Some(_guard) = DeliveryGuard::acquire() else {
+        return;
+    };
+    let Some(url) = read_config(|config| config.webhook_url.clone()) else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let due: Vec<WebhookDelivery> = read_webhook_queue(|queue| {
+        queue
+            .iter()
+            .map(|(_, delivery)| delivery)
+            .filter(|delivery| delivery.status.is_due(delivery.next_attempt_at, now))
+            .collect()
+    });
+    for mut delivery in due {
+        let txid = delivery.notice.txid.clone();
+        match post(&url, body(&delivery.notice), txid.clone()).await {
+            Ok(()) => {
+                write_webhook_queue(|queue| queue.remove(&txid));
+            }
+            Err(err) => {
+                let now = ic_cdk::api::time();
+                delivery.attempts += 1;
+                delivery.last_error = Some(err);
+                if delivery.attempts >= MAX_ATTEMPTS {
+                    delivery.status = OutboxStatus::DeadLettered { at: now };
+                    record_event(EventKind::WebhookDeadLettered {
+                        txid: txid.clone(),
+                        attempts: delivery.attempts,
+                    });
+                } else {
+                    delivery.next_attempt_at = now + backoff(delivery.attempts).as_nanos() as u64;
+                }
+                write_webhook_queue(|queue| queue.insert(txid, delivery));
+            }
+        }
+    }
+}
+
+/*
+ * posts a change of the cycle level once, without the queue's retries. a failed post
+ * is only logged, the event log keeps the change either way
+ */
+pub async fn post_cycle_alert(previous: CycleLevel, level: CycleLevel, balance: u128) {
+    let Some(url) = read_config(|config| config.webhook_url.clone()) else {
+        return;
+    };
+    let at = ic_cdk::api::time();
+    let body = serde_json::to_vec(&serde_json::json!({
+        "type": "CycleLevelChanged",
+        "previous": format!("{:?}", previous),
+        "level": format!("{:?}", level),
+        "balance": balance.to_string(),
+        "at": at,
+    }))
+    .expect("should serialize");
+    if let Err(err) = post(&url, body, format!("cycles-{}", at)).await {
+        ic_cdk::println!("failed to post the cycle alert: {}", err);
+    }
+}
+
+// puts dead-lettered posts back in the queue, `None` retries all of them
+pub fn retry_dead_lettered(txids: Option<Vec<String>>) -> u64 {
+    let now = ic_cdk::api::time();
+    write_webhook_queue(|queue| {
+        let deliveries: Vec<WebhookDelivery> = queue
+            .iter()
+            .map(|(_, delivery)| delivery)
+            .filter(|delivery| delivery.status.is_dead_lettered())
+            .filter(|delivery| match txids {
+                Some(ref txids) => txids.contains(&delivery.notice.txid),
+                None => true,
+            })
+            .collect();
+        let requeued = deliveries.len() as u64;
+        for mut delivery in deliveries {
+            delivery.attempts = 0;
+            delivery.next_attempt_at = now;
+            delivery.status = OutboxStatus::Pending;
+            queue.insert(delivery.notice.txid.clone(), delivery);
+        }
+        requeued
+    })
+}
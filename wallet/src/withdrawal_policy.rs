@@ -0,0 +1,307 @@
+use candid::Principal;
+
+use crate::{
+    bitcoin,
+    state::{
+        read_config, read_withdrawal_allowances, write_withdrawal_allowances, DailyLimits,
+        DailyUsage,
+    },
+    types::{TokenType, WalletError},
+};
+
+const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+fn today() -> u64 {
+    ic_cdk::api::time() / DAY_NANOS
+}
+
+// the address as the lists hold it, so differently cased forms of an address match
+pub fn canonical_address(address: &str) -> Result<String, WalletError> {
+    bitcoin::address_validation(address)
+        .map(|address| address.to_string())
+        .map_err(WalletError::InvalidAddress)
+}
+
+pub fn ensure_destination_allowed(to: &str) -> Result<(), WalletError> {
+    let to = canonical_address(to)?;
+    if read_config(|config| config.destination_policy().is_allowed(&to)) {
+        Ok(())
+    } else {
+        Err(WalletError::DestinationNotAllowed(to))
+    }
+}
+
+// the principal's own limits, the configured defaults otherwise
+pub fn limits_of(principal: &Principal) -> DailyLimits {
+    read_withdrawal_allowances(|allowances| allowances.get(principal))
+        .and_then(|allowance| allowance.limits)
+        .or_else(|| read_config(|config| config.daily_limits.clone()))
+        .unwrap_or_default()
+}
+
+pub fn usage_of(principal: &Principal) -> DailyUsage {
+    let usage = read_withdrawal_allowances(|allowances| allowances.get(principal))
+        .map(|allowance| allowance.usage)
+        .unwrap_or_default();
+    usage_on(usage, today())
+}
+
+// usage recorded on an earlier day counts as nothing withdrawn on `day`
+fn usage_on(usage: DailyUsage, day: u64) -> DailyUsage {
+    if usage.day == day {
+        usage
+    } else {
+        DailyUsage {
+            day,
+            ..Default::default()
+        }
+    }
+}
+
+fn limit_for(limits: &DailyLimits, token: &TokenType) -> Option<u128> {
+    match token {
+        TokenType::Runestone(runeid) => limits
+            .runes
+            .iter()
+            .find(|(id, _)| id == runeid)
+            .map(|(_, limit)| *limit),
+        _ => limits.sats.map(u128::from),
+    }
+}
+
+fn used(usage: &DailyUsage, token: &TokenType) -> u128 {
+    match token {
+        TokenType::Runestone(runeid) => usage
+            .runes
+            .iter()
+            .find(|(id, _)| id == runeid)
+            .map_or(0, |(_, used)| *used),
+        _ => usage.sats as u128,
+    }
+}
+
+fn set_used(usage: &mut DailyUsage, token: &TokenType, amount: u128) {
+    match token {
+        TokenType::Runestone(runeid) => {
+            usage.runes.retain(|(id, _)| id != runeid);
+            if amount > 0 {
+                usage.runes.push((runeid.clone(), amount));
+            }
+        }
+        _ => usage.sats = u64::try_from(amount).unwrap_or(u64::MAX),
+    }
+}
+
+// usage after adding `amount`, unless that goes over `limit`
+fn add_within(limit: Option<u128>, used: u128, amount: u128) -> Result<u128, WalletError> {
+    let total = used.saturating_add(amount);
+    match limit {
+        Some(limit) if total > limit => Err(WalletError::DailyLimitExceeded(format!(
+            "{} of the daily limit of {} left",
+            limit.saturating_sub(used),
+            limit
+        ))),
+        _ => Ok(total),
+    }
+}
+
+/*
+ * part of the principal's daily allowance taken by a withdrawal in flight. whatever
+ * isn't settled goes back to the allowance once the charge is dropped, so an early
+ * return before the broadcast leaves the allowance as it was
+*/
+pub struct DailyCharge {
+    principal: Principal,
+    token: TokenType,
+    amount: u128,
+    day: u64,
+}
+
+impl DailyCharge {
+    // keeps `used` of the charged amount
+    pub fn settle(mut self, used: u128) {
+        self.amount = self.amount.saturating_sub(used);
+    }
+
+    // keeps the whole amount when the withdrawal went through
+    pub fn settle_if<T, E>(self, result: &Result<T, E>) {
+        if result.is_ok() {
+            let amount = self.amount;
+            self.settle(amount);
+        }
+    }
+}
+
+impl Drop for DailyCharge {
+    fn drop(&mut self) {
+        // usage of a past day got reset already
+        if self.amount == 0 || self.day != today() {
+            return;
+        }
+        write_withdrawal_allowances(|allowances| {
+            let Some(mut allowance) = allowances.get(&self.principal) else {
+                return;
+            };
+            let remaining = used(&allowance.usage, &self.token).saturating_sub(self.amount);
+            set_used(&mut allowance.usage, &self.token, remaining);
+            allowances.insert(self.principal, allowance);
+        });
+    }
+}
+
+// takes `amount` out of the principal's allowance for today, usage is tracked even without a limit
+pub fn charge(
+    principal: Principal,
+    token: TokenType,
+    amount: u128,
+) -> Result<DailyCharge, WalletError> {
+    let limit = limit_for(&limits_of(&principal), &token);
+    let day = today();
+    write_withdrawal_allowances(|allowances| {
+        let mut allowance = allowances.get(&principal).unwrap_or_default();
+        allowance.usage = usage_on(allowance.usage, day);
+        let used = add_within(limit, used(&allowance.usage, &token), amount)?;
+        set_used(&mut allowance.usage, &token, used);
+        allowances.insert(principal, allowance);
+        Ok(())
+    })?;
+    Ok(DailyCharge {
+        principal,
+        token,
+        amount,
+        day,
+    })
+}
+
+pub fn set_limits(principal: Principal, limits: Option<DailyLimits>) {
+    write_withdrawal_allowances(|allowances| {
+        let mut allowance = allowances.get(&principal).unwrap_or_default();
+        allowance.limits = limits;
+        allowances.insert(principal, allowance);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{write_config, Chain},
+        types::RuneId,
+    };
+
+    fn set_chain(chain: Chain) {
+        write_config(|config| {
+            let mut temp = config.get().clone();
+            temp.chain = Some(chain);
+            let _ = config.set(temp);
+        });
+    }
+
+    fn rune(tx: u32) -> TokenType {
+        TokenType::Runestone(RuneId { block: 840_000, tx })
+    }
+
+    #[test]
+    fn canonical_address_lowercases_bech32() {
+        set_chain(Chain::Mainnet);
+        let canonical = canonical_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        assert_eq!(
+            canonical_address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap(),
+            canonical
+        );
+        assert_eq!(canonical, "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+    }
+
+    #[test]
+    fn canonical_address_keeps_base58_case() {
+        set_chain(Chain::Mainnet);
+        assert_eq!(
+            canonical_address("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap(),
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2"
+        );
+        assert!(canonical_address("1bvbmseystwetqtfn5au4m4gfg7xjanvn2").is_err());
+    }
+
+    #[test]
+    fn canonical_address_follows_the_network() {
+        let testnet = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        set_chain(Chain::Mainnet);
+        assert!(canonical_address(testnet).is_err());
+        set_chain(Chain::Testnet);
+        assert_eq!(canonical_address(testnet).unwrap(), testnet);
+        assert!(canonical_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").is_err());
+    }
+
+    #[test]
+    fn limit_for_picks_the_token() {
+        let limits = DailyLimits {
+            sats: Some(50_000),
+            runes: vec![(
+                RuneId {
+                    block: 840_000,
+                    tx: 1,
+                },
+                700,
+            )],
+        };
+        assert_eq!(limit_for(&limits, &TokenType::Bitcoin), Some(50_000));
+        assert_eq!(limit_for(&limits, &rune(1)), Some(700));
+        assert_eq!(limit_for(&limits, &rune(2)), None);
+        assert_eq!(
+            limit_for(&DailyLimits::default(), &TokenType::Bitcoin),
+            None
+        );
+    }
+
+    #[test]
+    fn set_used_tracks_each_token_apart() {
+        let mut usage = DailyUsage::default();
+        set_used(&mut usage, &TokenType::Bitcoin, 1_000);
+        set_used(&mut usage, &rune(1), 30);
+        set_used(&mut usage, &rune(2), 40);
+        set_used(&mut usage, &rune(1), 35);
+        assert_eq!(used(&usage, &TokenType::Bitcoin), 1_000);
+        assert_eq!(used(&usage, &rune(1)), 35);
+        assert_eq!(used(&usage, &rune(2)), 40);
+        assert_eq!(usage.runes.len(), 2);
+        // a refunded rune leaves no entry behind
+        set_used(&mut usage, &rune(2), 0);
+        assert_eq!(used(&usage, &rune(2)), 0);
+        assert_eq!(usage.runes.len(), 1);
+    }
+
+    #[test]
+    fn set_used_saturates_sats() {
+        let mut usage = DailyUsage::default();
+        set_used(&mut usage, &TokenType::Bitcoin, u128::MAX);
+        assert_eq!(usage.sats, u64::MAX);
+    }
+
+    #[test]
+    fn add_within_stops_at_the_limit() {
+        assert_eq!(add_within(Some(1_000), 400, 600).unwrap(), 1_000);
+        assert!(matches!(
+            add_within(Some(1_000), 400, 601),
+            Err(WalletError::DailyLimitExceeded(left)) if left == "600 of the daily limit of 1000 left"
+        ));
+        assert!(add_within(Some(1_000), 1_200, 0).is_err());
+        assert_eq!(add_within(None, u128::MAX, 1).unwrap(), u128::MAX);
+    }
+
+    #[test]
+    fn usage_rolls_over_with_the_day() {
+        let mut usage = DailyUsage {
+            day: 20_000,
+            ..Default::default()
+        };
+        set_used(&mut usage, &TokenType::Bitcoin, 1_000);
+        set_used(&mut usage, &rune(1), 30);
+        let same_day = usage_on(usage.clone(), 20_000);
+        assert_eq!(used(&same_day, &TokenType::Bitcoin), 1_000);
+        assert_eq!(used(&same_day, &rune(1)), 30);
+        let next_day = usage_on(usage, 20_001);
+        assert_eq!(next_day.day, 20_001);
+        assert_eq!(used(&next_day, &TokenType::Bitcoin), 0);
+        assert_eq!(used(&next_day, &rune(1)), 0);
+    }
+}
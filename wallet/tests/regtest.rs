@@ -0,0 +1,364 @@
+/*
+ * end to end flows against the wallet built with the `regtest-mock` feature, which swaps
+ * the bitcoin canister and the ord backends for an in-canister chain. signing goes
+ * through pocket-ic's threshold keys. build the canister first:
+ *
+ *   cargo build --release --target wasm32-unknown-unknown -p wallet --features regtest-mock
+ *
+ * `WALLET_WASM` points at another build, `POCKET_IC_BIN` at the pocket-ic server
+ */
+use std::collections::HashSet;
+
+use bitcoin::{consensus, Address, CompressedPublicKey, Network, Transaction};
+use candid::{decode_one, encode_args, encode_one, CandidType, Deserialize, IDLValue, Principal};
+use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint};
+use ordinals::{Artifact, Runestone};
+use pocket_ic::{PocketIc, PocketIcBuilder, WasmResult};
+
+const USER: [u8; 29] = [1; 29];
+
+// 2 sat/vbyte
+const FEE_PER_VBYTES: u64 = 2_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct RuneId {
+    block: u64,
+    tx: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Addresses {
+    bitcoin: String,
+}
+
+#[derive(CandidType)]
+enum Destination {
+    Address(String),
+}
+
+#[derive(CandidType)]
+enum FeePayer {
+    Sender,
+}
+
+#[derive(CandidType)]
+enum CoinSelection {
+    SmallestFirst,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum OutputRole {
+    Recipient,
+    Change,
+    Postage,
+    OpReturn,
+    Anchor,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct SummaryOutput {
+    address: Option<String>,
+    value: u64,
+    role: OutputRole,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct TransactionSummary {
+    outputs: Vec<SummaryOutput>,
+    fee: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum Receipt {
+    Bitcoin {
+        txid: String,
+        raw_transaction: Vec<u8>,
+        vsize: u64,
+        fee: u64,
+        summary: Option<TransactionSummary>,
+    },
+}
+
+impl Receipt {
+    fn transaction(&self) -> Transaction {
+        let Receipt::Bitcoin {
+            raw_transaction, ..
+        } = self;
+        consensus::deserialize(raw_transaction).expect("receipt should hold the transaction")
+    }
+
+    fn fee(&self) -> u64 {
+        let Receipt::Bitcoin { fee, .. } = self;
+        *fee
+    }
+}
+
+struct Wallet {
+    pic: PocketIc,
+    canister: Principal,
+    user: Principal,
+}
+
+impl Wallet {
+    fn install() -> Self {
+        let wasm_path = std::env::var("WALLET_WASM").unwrap_or_else(|_| {
+            format!(
+                "{}/../target/wasm32-unknown-unknown/release/wallet.wasm",
+                env!("CARGO_MANIFEST_DIR")
+            )
+        });
+        let wasm = std::fs::read(&wasm_path).unwrap_or_else(|_| {
+            panic!(
+                "no wallet wasm at {}, build it with the regtest-mock feature",
+                wasm_path
+            )
+        });
+        // the ii subnet holds the threshold keys, `dfx_test_key` among them
+        let pic = PocketIcBuilder::new()
+            .with_ii_subnet()
+            .with_application_subnet()
+            .build();
+        let user = Principal::from_slice(&USER);
+        let canister = pic.create_canister_with_settings(Some(user), None);
+        pic.add_cycles(canister, 100_000_000_000_000);
+        pic.install_canister(
+            canister,
+            wasm,
+            encode_one(BitcoinNetwork::Regtest).unwrap(),
+            Some(user),
+        );
+        // lets the key setup timers fetch the public keys
+        for _ in 0..10 {
+            pic.tick();
+        }
+        Self {
+            pic,
+            canister,
+            user,
+        }
+    }
+
+    fn call(&self, method: &str, args: Vec<u8>) -> Vec<u8> {
+        match self
+            .pic
+            .update_call(self.canister, self.user, method, args)
+            .unwrap_or_else(|err| panic!("{} failed: {:?}", method, err))
+        {
+            WasmResult::Reply(reply) => reply,
+            WasmResult::Reject(reject) => panic!("{} rejected: {}", method, reject),
+        }
+    }
+
+    fn update<T: for<'a> Deserialize<'a> + CandidType>(&self, method: &str, args: Vec<u8>) -> T {
+        decode_one(&self.call(method, args)).unwrap()
+    }
+
+    fn query<T: for<'a> Deserialize<'a> + CandidType>(&self, method: &str, args: Vec<u8>) -> T {
+        match self
+            .pic
+            .query_call(self.canister, self.user, method, args)
+            .unwrap_or_else(|err| panic!("{} failed: {:?}", method, err))
+        {
+            WasmResult::Reply(reply) => decode_one(&reply).unwrap(),
+            WasmResult::Reject(reject) => panic!("{} rejected: {}", method, reject),
+        }
+    }
+
+    fn deposit_address(&self) -> String {
+        self.update::<Addresses>("get_deposit_addresses", encode_args(()).unwrap())
+            .bitcoin
+    }
+
+    fn deposit(&self, address: &str, value: u64, runes: Vec<(RuneId, u128)>) -> Outpoint {
+        self.update(
+            "mock_deposit",
+            encode_args((address, value, runes)).unwrap(),
+        )
+    }
+
+    fn mine_blocks(&self, blocks: u32) {
+        self.call("mock_mine_blocks", encode_one(blocks).unwrap());
+    }
+
+    fn sent_transactions(&self) -> Vec<Transaction> {
+        self.query::<Vec<Vec<u8>>>("mock_sent_transactions", encode_args(()).unwrap())
+            .iter()
+            .map(|raw| consensus::deserialize(raw).unwrap())
+            .collect()
+    }
+
+    fn withdraw_bitcoin_args(to: &str, amount: u64) -> Vec<u8> {
+        encode_args((
+            Destination::Address(to.to_string()),
+            amount,
+            Some(FEE_PER_VBYTES),
+            FeePayer::Sender,
+            Some(CoinSelection::SmallestFirst),
+            None::<Vec<u8>>,
+            None::<String>,
+        ))
+        .unwrap()
+    }
+
+    fn withdraw_bitcoin(&self, to: &str, amount: u64) -> Receipt {
+        let result: Result<Receipt, IDLValue> =
+            self.update("withdraw_bitcoin", Self::withdraw_bitcoin_args(to, amount));
+        result.unwrap_or_else(|err| panic!("withdrawal failed: {:?}", err))
+    }
+
+    fn rune_balances(&self, address: &str) -> Vec<(RuneId, u128)> {
+        let result: Result<Vec<(RuneId, u128)>, IDLValue> =
+            self.update("get_runestone_balance_of", encode_one(address).unwrap());
+        result.unwrap_or_else(|err| panic!("balance query failed: {:?}", err))
+    }
+}
+
+// an address outside the wallet, paying to the generator point's key
+fn recipient() -> Address {
+    let pubkey = CompressedPublicKey::from_slice(
+        &hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap(),
+    )
+    .unwrap();
+    Address::p2wpkh(&pubkey, Network::Regtest)
+}
+
+#[test]
+fn bitcoin_deposit_then_withdraw() {
+    let wallet = Wallet::install();
+    let deposit_address = wallet.deposit_address();
+    wallet.deposit(&deposit_address, 100_000, vec![]);
+
+    let to = recipient();
+    let receipt = wallet.withdraw_bitcoin(&to.to_string(), 30_000);
+    let txn = receipt.transaction();
+    assert_eq!(wallet.sent_transactions(), vec![txn.clone()]);
+    assert_eq!(txn.input.len(), 1);
+    assert_eq!(txn.output[0].script_pubkey, to.script_pubkey());
+    assert_eq!(txn.output[0].value.to_sat(), 30_000);
+    let total_output: u64 = txn.output.iter().map(|output| output.value.to_sat()).sum();
+    assert_eq!(receipt.fee(), 100_000 - total_output);
+
+    let Receipt::Bitcoin { summary, .. } = receipt;
+    let summary = summary.expect("fresh withdrawals carry a summary");
+    assert_eq!(summary.fee, 100_000 - total_output);
+    assert_eq!(summary.outputs[0].role, OutputRole::Recipient);
+    assert_eq!(summary.outputs[0].address, Some(to.to_string()));
+    assert!(summary.outputs[1..]
+        .iter()
+        .all(|output| output.role == OutputRole::Change));
+}
+
+// the builder re-estimates the fee until it matches the size of the transaction it pays for
+#[test]
+fn fee_settles_on_the_transaction_size() {
+    let wallet = Wallet::install();
+    let deposit_address = wallet.deposit_address();
+    for _ in 0..5 {
+        wallet.deposit(&deposit_address, 10_000, vec![]);
+    }
+
+    let receipt = wallet.withdraw_bitcoin(&recipient().to_string(), 45_000);
+    let txn = receipt.transaction();
+    assert_eq!(txn.input.len(), 5);
+    // der signatures vary by a byte or two, the estimate is made before signing
+    let inputs = txn.input.len() as u64;
+    let vsize = txn.vsize() as u64;
+    let fee = receipt.fee() * 1_000;
+    assert!(fee >= (vsize - 2 * inputs) * FEE_PER_VBYTES);
+    assert!(fee <= (vsize + 2 * inputs) * FEE_PER_VBYTES);
+}
+
+#[test]
+fn rune_deposit_then_withdraw() {
+    let wallet = Wallet::install();
+    let deposit_address = wallet.deposit_address();
+    let runeid = RuneId {
+        block: 840_000,
+        tx: 1,
+    };
+    wallet.deposit(&deposit_address, 10_000, vec![(runeid.clone(), 1_000)]);
+    wallet.deposit(&deposit_address, 100_000, vec![]);
+    assert_eq!(
+        wallet.rune_balances(&deposit_address),
+        vec![(runeid.clone(), 1_000)]
+    );
+
+    let to = recipient();
+    let result: Result<Receipt, IDLValue> = wallet.update(
+        "withdraw_runestone",
+        encode_args((
+            runeid.clone(),
+            candid::Nat::from(400u64),
+            Destination::Address(to.to_string()),
+            Some(FEE_PER_VBYTES),
+            None::<bool>,
+        ))
+        .unwrap(),
+    );
+    let receipt = result.unwrap_or_else(|err| panic!("withdrawal failed: {:?}", err));
+    let txn = receipt.transaction();
+    let Some(Artifact::Runestone(runestone)) = Runestone::decipher(&txn) else {
+        panic!("rune transfers carry a runestone");
+    };
+    let [edict] = runestone.edicts.as_slice() else {
+        panic!("a single rune moves with a single edict");
+    };
+    assert_eq!((edict.id.block, edict.id.tx), (runeid.block, runeid.tx));
+    assert_eq!(edict.amount, 400);
+    assert_eq!(
+        txn.output[edict.output as usize].script_pubkey,
+        to.script_pubkey()
+    );
+
+    // the rune change went back to the deposit address
+    assert_eq!(wallet.rune_balances(&deposit_address), vec![(runeid, 600)]);
+}
+
+#[test]
+fn concurrent_withdrawals_spend_distinct_utxos() {
+    let wallet = Wallet::install();
+    let deposit_address = wallet.deposit_address();
+    for _ in 0..3 {
+        wallet.deposit(&deposit_address, 50_000, vec![]);
+    }
+    // confirmed below the reorg window, so syncs don't walk them again
+    wallet.mine_blocks(10);
+    wallet.rune_balances(&deposit_address);
+
+    let to = recipient().to_string();
+    let calls: Vec<_> = (0..3)
+        .map(|_| {
+            wallet
+                .pic
+                .submit_call(
+                    wallet.canister,
+                    wallet.user,
+                    "withdraw_bitcoin",
+                    Wallet::withdraw_bitcoin_args(&to, 20_000),
+                )
+                .unwrap()
+        })
+        .collect();
+    for call in calls {
+        match wallet.pic.await_call(call).unwrap() {
+            WasmResult::Reply(reply) => {
+                let result: Result<Receipt, IDLValue> = decode_one(&reply).unwrap();
+                result.unwrap_or_else(|err| panic!("withdrawal failed: {:?}", err));
+            }
+            WasmResult::Reject(reject) => panic!("withdrawal rejected: {}", reject),
+        }
+    }
+
+    let sent = wallet.sent_transactions();
+    assert_eq!(sent.len(), 3);
+    let mut spent = HashSet::new();
+    for txn in sent.iter() {
+        for input in txn.input.iter() {
+            assert!(
+                spent.insert(input.previous_output),
+                "{} spent twice",
+                input.previous_output
+            );
+        }
+    }
+}